@@ -0,0 +1,59 @@
+use std::{hash::{BuildHasher, Hasher, RandomState}, time::Duration};
+
+use tracing::warn;
+
+use crate::config;
+
+/// Returns a random number from 0 to 1. Every `RandomState` is seeded differently, which is
+/// random enough to inject faults.
+fn random() -> f64 {
+    RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64
+}
+
+/// Returns `true` with the given probability.
+fn roll(probability: f64) -> bool {
+    random() < probability
+}
+
+/// Delays an incoming packet, if `chaos.delay_probability` says so.
+pub async fn delay() {
+    let Ok(config) = config::get() else {
+        return;
+    };
+
+    if roll(config.chaos.delay_probability) {
+        let delay = Duration::from_millis((random() * config.chaos.max_delay_ms as f64) as u64);
+
+        warn!("Chaos: delaying packet by {}ms", delay.as_millis());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Completes at a random point in time, as configured by `chaos.drop_probability`, after which the
+/// connection to the server is dropped.
+pub async fn drop_connection() {
+    let probability = config::get().map(|config| config.chaos.drop_probability).unwrap_or_default();
+
+    if probability > 0.0 {
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            if roll(probability) {
+                warn!("Chaos: dropping connection");
+                return;
+            }
+        }
+    }
+
+    std::future::pending::<()>().await
+}
+
+/// Fails a Docker call, if `chaos.docker_failure_probability` says so.
+pub fn docker() -> Result<(), String> {
+    if roll(config::get()?.chaos.docker_failure_probability) {
+        warn!("Chaos: failing Docker call");
+        return Err("Chaos: injected Docker failure".to_string());
+    }
+
+    Ok(())
+}
@@ -1,5 +1,6 @@
-use std::sync::OnceLock;
+use std::{net::{SocketAddr, ToSocketAddrs}, path::Path, sync::OnceLock};
 
+use packet::events::CpuConvention;
 use tracing::warn;
 
 use crate::Cli;
@@ -10,6 +11,7 @@ trait ConfigOverride {
 
 /// Configuration file for the daemon
 #[derive(Debug, serde::Serialize, serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     /// Daemon configuration
     #[serde(default)]
@@ -20,6 +22,39 @@ pub struct Config {
     /// Logging configuration
     #[serde(default)]
     pub logging: Logging,
+    /// Port assignment configuration
+    #[serde(default)]
+    pub ports: Ports,
+    /// Container logs configuration
+    #[serde(default)]
+    pub logs: Logs,
+    /// Storage configuration
+    #[serde(default)]
+    pub storage: Storage,
+    /// Resource watchdog configuration
+    #[serde(default)]
+    pub watchdog: Watchdog,
+    /// Capacity configuration
+    #[serde(default)]
+    pub capacity: Capacity,
+    /// Branding configuration
+    #[serde(default)]
+    pub branding: Branding,
+    /// Docker connection configuration
+    #[serde(default)]
+    pub docker: Docker,
+    /// Server stats configuration
+    #[serde(default)]
+    pub stats: Stats,
+    /// Metrics endpoint configuration
+    #[serde(default)]
+    pub metrics: Metrics,
+    /// Debug endpoint configuration
+    #[serde(default)]
+    pub debug: DebugEndpoint,
+    /// Fault injection configuration
+    #[serde(default)]
+    pub chaos: Chaos,
 }
 
 impl ConfigOverride for Config {
@@ -28,12 +63,24 @@ impl ConfigOverride for Config {
             daemon: self.daemon.override_with(args),
             server: self.server.override_with(args),
             logging: self.logging.override_with(args),
+            ports: self.ports,
+            logs: self.logs,
+            storage: self.storage.override_with(args),
+            watchdog: self.watchdog,
+            capacity: self.capacity,
+            branding: self.branding,
+            docker: self.docker,
+            stats: self.stats,
+            metrics: self.metrics,
+            debug: self.debug,
+            chaos: self.chaos,
         }
     }
 }
 
 /// Daemon configuration
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Daemon {
     /// Daemon ID
     pub uuid: String,
@@ -41,8 +88,9 @@ pub struct Daemon {
     pub public_key: String,
     /// Path to the daemon's private key
     pub private_key: String,
-    /// Path to the daemon's data folder
-    pub data_folder: String,
+    /// Deprecated, moved to `storage.data_folder`
+    #[serde(default, skip_serializing)]
+    pub data_folder: Option<String>,
 }
 
 impl Default for Daemon {
@@ -51,7 +99,7 @@ impl Default for Daemon {
             uuid: "".to_string(),
             public_key: "daemon.pub".to_string(),
             private_key: "daemon.pem".to_string(),
-            data_folder: "/var/aesterisk/data".to_string(),
+            data_folder: None,
         }
     }
 }
@@ -62,18 +110,45 @@ impl ConfigOverride for Daemon {
             uuid: args.daemon_uuid.take().unwrap_or(self.uuid),
             public_key: args.daemon_public_key.take().unwrap_or(self.public_key),
             private_key: args.daemon_private_key.take().unwrap_or(self.private_key),
-            data_folder: args.daemon_data_folder.take().unwrap_or(self.data_folder),
+            data_folder: self.data_folder,
         }
     }
 }
 
 /// Server configuration
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Server {
     /// Server URL
     pub url: String,
     /// Path to the server's public key
     pub public_key: String,
+    /// Issuers accepted on packets of the server
+    #[serde(default = "default_issuers")]
+    pub issuers: Vec<String>,
+    /// Most status events buffered while disconnected from the server, which are replayed once
+    /// connected again (0 to disable buffering)
+    #[serde(default = "default_offline_buffer")]
+    pub offline_buffer: usize,
+    /// Path to a PEM certificate trusted for `wss://` servers in addition to the system roots, e.g.
+    /// of a self-signed server (empty to only trust the system roots)
+    #[serde(default)]
+    pub ca_cert: String,
+    /// Accept any certificate of `wss://` servers, even expired or self-signed ones. Only meant for
+    /// testing, as it allows anyone to impersonate the server
+    #[serde(default)]
+    pub accept_invalid_certs: bool,
+    /// Accept certificates of `wss://` servers issued for other hostnames
+    #[serde(default)]
+    pub accept_invalid_hostnames: bool,
+}
+
+fn default_issuers() -> Vec<String> {
+    vec!["aesterisk/server".to_string()]
+}
+
+fn default_offline_buffer() -> usize {
+    10_000
 }
 
 impl Default for Server {
@@ -81,6 +156,11 @@ impl Default for Server {
         Self {
             url: "wss://daemon.server.aesterisk.io".to_string(),
             public_key: "server.pub".to_string(),
+            issuers: default_issuers(),
+            offline_buffer: default_offline_buffer(),
+            ca_cert: String::new(),
+            accept_invalid_certs: false,
+            accept_invalid_hostnames: false,
         }
     }
 }
@@ -90,12 +170,18 @@ impl ConfigOverride for Server {
         Self {
             url: args.server_url.take().unwrap_or(self.url),
             public_key: args.server_public_key.take().unwrap_or(self.public_key),
+            issuers: self.issuers,
+            offline_buffer: self.offline_buffer,
+            ca_cert: self.ca_cert,
+            accept_invalid_certs: self.accept_invalid_certs,
+            accept_invalid_hostnames: self.accept_invalid_hostnames,
         }
     }
 }
 
 /// Logging configuration
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Logging {
     /// Path to the logs folder
     pub folder: String,
@@ -117,6 +203,461 @@ impl ConfigOverride for Logging {
     }
 }
 
+/// Port assignment configuration
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Ports {
+    /// First host port (inclusive) that can be automatically assigned to servers
+    pub range_start: u16,
+    /// Last host port (inclusive) that can be automatically assigned to servers
+    pub range_end: u16,
+}
+
+impl Default for Ports {
+    fn default() -> Self {
+        Self {
+            range_start: 30000,
+            range_end: 39999,
+        }
+    }
+}
+
+/// Container logs configuration
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Logs {
+    /// Amount of recent log lines kept in memory per server
+    pub buffer_lines: usize,
+    /// External sinks that container logs are forwarded to
+    #[serde(default)]
+    pub sinks: Vec<LogSink>,
+}
+
+impl Default for Logs {
+    fn default() -> Self {
+        Self {
+            buffer_lines: 1000,
+            sinks: Vec::new(),
+        }
+    }
+}
+
+/// Storage configuration
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Storage {
+    /// Path to the daemon's data folder
+    pub data_folder: String,
+    /// Disk space (in MiB) that is kept free on the data folder's disk, servers are not created
+    /// when less space is available
+    pub reserved_mb: u64,
+    /// Disk space (in MiB) set aside for every new server, which has to be available in addition
+    /// to the reserved space when creating it
+    pub server_quota_mb: u64,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self {
+            data_folder: "/var/aesterisk/data".to_string(),
+            reserved_mb: 1024,
+            server_quota_mb: 0,
+        }
+    }
+}
+
+impl ConfigOverride for Storage {
+    fn override_with(self, args: &mut Cli) -> Self {
+        Self {
+            data_folder: args.storage_data_folder.take().unwrap_or(self.data_folder),
+            ..self
+        }
+    }
+}
+
+/// Resource watchdog configuration
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Watchdog {
+    /// Interval (in seconds) between checks of the node's resources
+    pub interval: u64,
+    /// Disk usage of the data folder's filesystem (in percent) above which a warning is sent, or 0
+    /// to disable the warning
+    pub disk_percent: f64,
+    /// Memory usage (in percent) above which a warning is sent, or 0 to disable the warning
+    pub memory_percent: f64,
+    /// Inode usage of the data folder's filesystem (in percent) above which a warning is sent, or
+    /// 0 to disable the warning
+    pub inode_percent: f64,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            interval: 30,
+            disk_percent: 90.0,
+            memory_percent: 90.0,
+            inode_percent: 90.0,
+        }
+    }
+}
+
+/// Capacity configuration
+#[derive(Debug, serde::Serialize, serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Capacity {
+    /// Maximum amount of servers on this node, reported to the server for placing new servers, or
+    /// 0 if unlimited
+    pub max_servers: u32,
+}
+
+/// Docker connection configuration
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Docker {
+    /// Docker endpoint, either a Unix socket (e.g. `unix:///run/user/1000/docker.sock` for rootless
+    /// Docker) or a TCP endpoint (e.g. `tcp://127.0.0.1:2375`). If empty, `DOCKER_HOST` or the
+    /// default socket is used, falling back to the rootless socket in `XDG_RUNTIME_DIR`
+    pub host: String,
+    /// Seconds after which requests to Docker time out
+    pub timeout: u64,
+    /// Docker API version to use (e.g. `1.43`), or empty for the version of the Docker library
+    pub api_version: String,
+}
+
+impl Default for Docker {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            timeout: 120,
+            api_version: String::new(),
+        }
+    }
+}
+
+impl Docker {
+    /// Returns the endpoint to connect to, see `host`.
+    pub fn host(&self) -> String {
+        if !self.host.is_empty() {
+            return self.host.clone();
+        }
+
+        if let Ok(host) = std::env::var("DOCKER_HOST") {
+            return host;
+        }
+
+        // rootless Docker listens on a socket in the runtime directory of its user instead
+        if !Path::new("/var/run/docker.sock").exists()
+            && let Ok(runtime_dir) = std::env::var("XDG_RUNTIME_DIR")
+            && Path::new(&runtime_dir).join("docker.sock").exists() {
+            return format!("unix://{}/docker.sock", runtime_dir);
+        }
+
+        "unix:///var/run/docker.sock".to_string()
+    }
+
+    /// Returns the major and minor version of `api_version`, or `None` if it is empty.
+    pub fn api_version(&self) -> Result<Option<(usize, usize)>, String> {
+        if self.api_version.is_empty() {
+            return Ok(None);
+        }
+
+        let (major, minor) = self.api_version.split_once('.').ok_or_else(|| format!("invalid API version \"{}\", expected e.g. 1.43", self.api_version))?;
+
+        match (major.parse(), minor.parse()) {
+            (Ok(major), Ok(minor)) => Ok(Some((major, minor))),
+            _ => Err(format!("invalid API version \"{}\", expected e.g. 1.43", self.api_version)),
+        }
+    }
+}
+
+/// Server stats configuration
+#[derive(Debug, serde::Serialize, serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Stats {
+    /// How CPU usage of servers is reported, either `per_core` (out of 100% per core, like `docker
+    /// stats` and htop) or `all_cores` (out of 100% for all cores together)
+    pub cpu: CpuConvention,
+}
+
+/// Metrics endpoint configuration
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Metrics {
+    /// Address to serve metrics in the Prometheus text format on (e.g. `127.0.0.1:9464`), at
+    /// `/metrics`, or empty to disable the endpoint. A socket named `metrics` (or bound to this
+    /// address) passed by systemd socket activation is used instead.
+    pub bind: String,
+    /// Whether to export the CPU, memory and storage usage of the node, collected even if no web
+    /// client listens for the node status
+    #[serde(default = "default_true")]
+    pub node: bool,
+    /// Whether to export the status and usage of every server, labeled by server ID
+    #[serde(default = "default_true")]
+    pub containers: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            bind: String::new(),
+            node: true,
+            containers: true,
+        }
+    }
+}
+
+impl Metrics {
+    /// Returns whether the metrics endpoint is enabled.
+    pub fn enabled(&self) -> bool {
+        !self.bind.is_empty()
+    }
+}
+
+/// Debug endpoint configuration, only served if the daemon is built with the `debug_endpoint`
+/// feature
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DebugEndpoint {
+    /// Loopback address to serve the debug endpoint on
+    pub bind: String,
+}
+
+impl Default for DebugEndpoint {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:31310".to_string(),
+        }
+    }
+}
+
+/// Fault injection configuration, to test how the daemon deals with an unreliable connection and
+/// Docker. Faults are only injected if the daemon is built with the `chaos` feature
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Chaos {
+    /// Probability (from 0 to 1) of the connection to the server being dropped, checked every second
+    pub drop_probability: f64,
+    /// Probability (from 0 to 1) of an incoming packet being delayed
+    pub delay_probability: f64,
+    /// Most milliseconds an incoming packet is delayed by
+    pub max_delay_ms: u64,
+    /// Probability (from 0 to 1) of a Docker call failing
+    pub docker_failure_probability: f64,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self {
+            drop_probability: 0.0,
+            delay_probability: 0.0,
+            max_delay_ms: 1000,
+            docker_failure_probability: 0.0,
+        }
+    }
+}
+
+/// Branding configuration, for distributions of the daemon under another name
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Branding {
+    /// Product name, shown when starting up
+    pub product_name: String,
+    /// URL shown when the daemon exits because of an error, e.g. a support or documentation page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub support_url: Option<String>,
+    /// When the logo is printed on startup
+    pub logo: Logo,
+}
+
+impl Default for Branding {
+    fn default() -> Self {
+        Self {
+            product_name: "Aesterisk Daemon".to_string(),
+            support_url: None,
+            logo: Logo::Always,
+        }
+    }
+}
+
+/// When the logo is printed on startup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Logo {
+    Always,
+    /// Only if stdout is a terminal, which keeps it out of e.g. systemd logs
+    Tty,
+    Never,
+}
+
+/// External sink that container logs are forwarded to
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+pub struct LogSink {
+    /// Where the logs are sent
+    #[serde(flatten)]
+    pub target: LogTarget,
+    /// Servers whose logs are forwarded to this sink, or all servers if empty
+    #[serde(default)]
+    pub servers: Vec<u32>,
+}
+
+/// Destination of a log sink
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum LogTarget {
+    /// Syslog (RFC 5424) over UDP, e.g. `127.0.0.1:514`
+    Syslog { address: String },
+    /// Loki push API, e.g. `http://127.0.0.1:3100/loki/api/v1/push`
+    Loki { url: String },
+    /// Plain text files in the `logs` directory of the data folder
+    File,
+}
+
+impl Config {
+    /// Moves settings of older config files to their current place.
+    fn migrate(&mut self) {
+        if let Some(data_folder) = self.daemon.data_folder.take() {
+            warn!("daemon.data_folder has moved to storage.data_folder");
+            self.storage.data_folder = data_folder;
+        }
+    }
+
+    /// Checks the values of the configuration, returning a description of every problem found.
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let mut check = |field: &str, res: Result<(), String>| {
+            if let Err(e) = res {
+                problems.push(format!("{}: {}", field, e));
+            }
+        };
+
+        // an empty ID is allowed until the setup process is completed
+        if !self.daemon.uuid.is_empty() {
+            check("daemon.uuid", uuid::Uuid::parse_str(&self.daemon.uuid).map(|_| ()).map_err(|e| format!("invalid UUID \"{}\": {}", self.daemon.uuid, e)));
+        }
+
+        // both keys are generated by `encryption::init` if the private key doesn't exist yet
+        if Path::new(&self.daemon.private_key).exists() {
+            check("daemon.public_key", check_readable(&self.daemon.public_key));
+            check("daemon.private_key", check_readable(&self.daemon.private_key));
+        } else {
+            check("daemon.public_key", check_creatable(&self.daemon.public_key));
+            check("daemon.private_key", check_creatable(&self.daemon.private_key));
+        }
+        check("storage.data_folder", check_folder(&self.storage.data_folder, true));
+        check("server.url", check_url(&self.server.url, &["ws", "wss"]));
+        check("server.public_key", check_readable(&self.server.public_key));
+
+        if !self.server.ca_cert.is_empty() {
+            check("server.ca_cert", check_readable(&self.server.ca_cert));
+        }
+        check("logging.folder", check_folder(&self.logging.folder, false));
+
+        // the socket itself might not exist yet if Docker is still starting
+        let host = self.docker.host();
+        if !matches!(host.split_once("://"), Some(("unix" | "tcp" | "http", _))) {
+            check("docker.host", Err(format!("invalid endpoint \"{}\", expected unix:// or tcp://", host)));
+        }
+
+        if self.docker.timeout == 0 {
+            check("docker.timeout", Err("should be greater than 0".to_string()));
+        }
+
+        check("docker.api_version", self.docker.api_version().map(|_| ()));
+
+        if let Some(support_url) = self.branding.support_url.as_ref() {
+            check("branding.support_url", check_url(support_url, &["http", "https"]));
+        }
+
+        if self.server.issuers.is_empty() {
+            check("server.issuers", Err("should contain at least one issuer".to_string()));
+        }
+
+        if self.ports.range_start > self.ports.range_end {
+            check("ports", Err(format!("range_start ({}) is greater than range_end ({})", self.ports.range_start, self.ports.range_end)));
+        }
+
+        if self.watchdog.interval == 0 {
+            check("watchdog.interval", Err("should be greater than 0".to_string()));
+        }
+
+        for (field, percent) in [("watchdog.disk_percent", self.watchdog.disk_percent), ("watchdog.memory_percent", self.watchdog.memory_percent), ("watchdog.inode_percent", self.watchdog.inode_percent)] {
+            if !(0.0..=100.0).contains(&percent) {
+                check(field, Err(format!("{} is not a percentage between 0 and 100", percent)));
+            }
+        }
+
+        if !self.metrics.bind.is_empty() {
+            check("metrics.bind", self.metrics.bind.to_socket_addrs().map(|_| ()).map_err(|e| format!("invalid address \"{}\": {}", self.metrics.bind, e)));
+        }
+
+        match self.debug.bind.parse::<SocketAddr>() {
+            Ok(addr) if !addr.ip().is_loopback() => check("debug.bind", Err(format!("\"{}\" should be a loopback address", self.debug.bind))),
+            Ok(_) => (),
+            Err(e) => check("debug.bind", Err(format!("invalid address \"{}\": {}", self.debug.bind, e))),
+        }
+
+        for (field, probability) in [("chaos.drop_probability", self.chaos.drop_probability), ("chaos.delay_probability", self.chaos.delay_probability), ("chaos.docker_failure_probability", self.chaos.docker_failure_probability)] {
+            if !(0.0..=1.0).contains(&probability) {
+                check(field, Err("should be between 0 and 1".to_string()));
+            }
+        }
+
+        for (i, sink) in self.logs.sinks.iter().enumerate() {
+            let field = format!("logs.sinks[{}]", i);
+
+            match &sink.target {
+                LogTarget::Syslog { address } => check(&format!("{}.address", field), address.to_socket_addrs().map(|_| ()).map_err(|e| format!("invalid address \"{}\": {}", address, e))),
+                LogTarget::Loki { url } => check(&format!("{}.url", field), check_url(url, &["http", "https"])),
+                LogTarget::File => (),
+            }
+        }
+
+        problems
+    }
+}
+
+pub fn check_url(url: &str, schemes: &[&str]) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid URL \"{}\": {}", url, e))?;
+
+    if !schemes.contains(&parsed.scheme()) {
+        return Err(format!("URL \"{}\" should use one of the schemes {}", url, schemes.join(", ")));
+    }
+
+    Ok(())
+}
+
+fn check_readable(path: &str) -> Result<(), String> {
+    std::fs::File::open(path).map(|_| ()).map_err(|e| format!("could not read \"{}\": {}", path, e))
+}
+
+/// Checks that a file that doesn't exist yet can be created, as its folder exists.
+fn check_creatable(path: &str) -> Result<(), String> {
+    match Path::new(path).parent() {
+        Some(folder) if !folder.as_os_str().is_empty() && !folder.is_dir() => Err(format!("folder \"{}\" of \"{}\" does not exist", folder.display(), path)),
+        _ => Ok(()),
+    }
+}
+
+fn check_folder(path: &str, absolute: bool) -> Result<(), String> {
+    let path = Path::new(path);
+
+    if absolute && !path.is_absolute() {
+        return Err(format!("\"{}\" should be an absolute path", path.display()));
+    }
+
+    if path.exists() && !path.is_dir() {
+        return Err(format!("\"{}\" is not a folder", path.display()));
+    }
+
+    Ok(())
+}
+
 static CONFIG: OnceLock<Config> = OnceLock::new();
 
 fn save(config: &Config, file: &str) -> Result<(), String> {
@@ -124,31 +665,50 @@ fn save(config: &Config, file: &str) -> Result<(), String> {
     Ok(())
 }
 
-fn load(file: &str) -> Result<Config, String> {
+/// Loads the config file, returning whether environment variables were substituted in it.
+fn load(file: &str) -> Result<(Config, bool), String> {
     match std::fs::read_to_string(file) {
-        Ok(contents) => Ok(toml::from_str(&contents).map_err(|_| "could not parse config file")?),
+        Ok(contents) => {
+            let (contents, substituted) = packet::env::substitute(&contents, |name| std::env::var(name).ok()).map_err(|e| format!("could not load config file {}: {}", file, e))?;
+            Ok((toml::from_str(&contents).map_err(|e| format!("could not parse config file {}: {}", file, e))?, substituted))
+        },
         Err(_) => {
             warn!("Could not read config file, generating default configuration");
-            Ok(Config::default())
+            Ok((Config::default(), false))
         }
     }
 }
 
-fn load_or_create(file: &str) -> Result<Config, String> {
-    let config = load(file)?;
-    save(&config, file)?;
+fn load_or_create(file: &str, create: bool) -> Result<Config, String> {
+    let (mut config, substituted) = load(file)?;
+
+    config.migrate();
+
+    // rewriting the file would replace the variables with their current values
+    if create && !substituted {
+        save(&config, file)?;
+    }
+
     Ok(config)
 }
 
-/// Initializes the configuration with a default config file path and CLI arguments
+/// Initializes the configuration with a default config file path and CLI arguments. When only
+/// validating the configuration, the config file is not created or rewritten.
 pub fn init(default_file: &str, mut override_args: Cli) -> Result<&'static Config, String> {
     if CONFIG.get().is_some() {
         return Err("config already initialized".to_string());
     }
 
-    let config = load_or_create(override_args.config.as_deref().unwrap_or(default_file))?;
+    let file = override_args.config.clone().unwrap_or(default_file.to_string());
+    let config = load_or_create(&file, !override_args.validate_config)?.override_with(&mut override_args);
+
+    let problems = config.validate();
+
+    if !problems.is_empty() {
+        return Err(format!("invalid values in config file {}:\n  {}", file, problems.join("\n  ")));
+    }
 
-    Ok(CONFIG.get_or_init(|| config.override_with(&mut override_args)))
+    Ok(CONFIG.get_or_init(|| config))
 }
 
 /// Gets the configuration. The configuration must be initialized first (by calling `config::init()`)
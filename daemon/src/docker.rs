@@ -1,17 +1,55 @@
-use bollard::Docker;
+use std::{future::Future, time::Instant};
+
+use bollard::{ClientVersion, Docker, API_DEFAULT_VERSION};
 use tokio::sync::OnceCell;
 
+use crate::{config, telemetry};
+
+pub mod build;
+pub mod firewall;
 pub mod network;
+pub mod ports;
+pub mod rootless;
 pub mod server;
+pub mod stats;
+pub mod update;
 
 static DOCKER: OnceCell<Docker> = OnceCell::const_new();
 
+/// Connects to Docker as configured in `docker`. The configuration must be loaded before calling
+/// this function.
 pub fn init() -> Result<(), String> {
-    let docker = Docker::connect_with_local_defaults().map_err(|e| format!("Could not connect to socket: {}", e))?;
+    let config = &config::get()?.docker;
+    let host = config.host();
+
+    let version = config.api_version()?.map(|(major_version, minor_version)| ClientVersion {
+        major_version,
+        minor_version,
+    });
+    let version = version.as_ref().unwrap_or(API_DEFAULT_VERSION);
+
+    let docker = match host.split_once("://") {
+        Some(("unix", path)) => Docker::connect_with_unix(path, config.timeout, version),
+        Some(("tcp" | "http", _)) => Docker::connect_with_http(&host, config.timeout, version),
+        _ => return Err(format!("Invalid Docker endpoint {}", host)),
+    }.map_err(|e| format!("Could not connect to {}: {}", host, e))?;
+
     DOCKER.set(docker).map_err(|_| "Docker has already been initialised")?;
     Ok(())
 }
 
 pub fn get() -> Result<&'static Docker, String> {
+    #[cfg(feature = "chaos")]
+    crate::chaos::docker()?;
+
     Ok(DOCKER.get().ok_or("Docker has not been initialised")?)
 }
+
+/// Awaits a Docker API call, recording how long it took under `name`.
+pub async fn timed<T>(name: &'static str, call: impl Future<Output = T>) -> T {
+    let start = Instant::now();
+    let res = call.await;
+
+    telemetry::observe_docker(name, start.elapsed());
+    res
+}
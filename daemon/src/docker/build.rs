@@ -0,0 +1,193 @@
+use std::{collections::HashMap, time::Duration};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bollard::image::BuildImageOptions;
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use packet::{daemon_server::fetch_build_context::DSFetchBuildContextPacket, events::{BuildOutputEvent, EventData, EventType}, server_daemon::sync::{Build, BuildContext}, Packet};
+use sha2::{Digest, Sha256};
+use tokio::sync::{oneshot, Mutex};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, instrument, warn};
+
+use crate::{config, encryption, flow, sequence, LISTENS, SENDER};
+
+/// How long to wait for the server to send a requested build context
+const FETCH_TIMEOUT: Duration = Duration::from_secs(120);
+
+/// Receives a requested build context, or `None` if the server doesn't have it
+type Waiter = oneshot::Sender<Option<Vec<u8>>>;
+
+lazy_static! {
+    /// Build contexts requested from the server, by hash
+    static ref PENDING: Mutex<HashMap<String, Vec<Waiter>>> = Mutex::new(HashMap::new());
+}
+
+async fn send(packet: Packet) -> Result<(), String> {
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(packet)?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))
+}
+
+/// Sends a line of build output to the server, if anyone is listening for it.
+async fn output(server: u32, image: &str, line: String, error: bool) {
+    debug!("[build {}] {}", image, line);
+
+    if !LISTENS.read().await.contains(&EventType::BuildOutput) {
+        return;
+    }
+
+    // lines are left out if the server can't keep up, which is reported with the next line, but
+    // the error ending the build is always sent
+    let skipped = if error {
+        flow::force(EventType::BuildOutput).await
+    } else {
+        match flow::take(EventType::BuildOutput).await {
+            Some(skipped) => skipped,
+            None => return,
+        }
+    };
+
+    let res = sequence::send(EventData::BuildOutput(BuildOutputEvent {
+        server,
+        image: image.to_string(),
+        line,
+        error,
+        skipped,
+    }), None).await;
+
+    if let Err(e) = res {
+        warn!("Could not send build output: {}", e);
+    }
+}
+
+/// Builds the image of a server and tags it as `image:tag`, which has to be done before creating
+/// it (instead of pulling the image). The output of the build is sent as `BuildOutput` events.
+#[instrument(skip_all, fields(server = server, image = %image, tag = %tag))]
+pub async fn build_image(server: u32, image: &str, tag: &str, build: &Build) -> Result<(), String> {
+    let name = format!("{}:{}", image, tag);
+
+    let (remote, tar) = match &build.context {
+        BuildContext::Git(url) => (url.clone(), None),
+        BuildContext::Archive(hash) => (String::new(), Some(archive(hash).await?)),
+    };
+
+    let mut stream = super::get()?.build_image(BuildImageOptions {
+        dockerfile: build.dockerfile.clone(),
+        t: name.clone(),
+        remote,
+        rm: true,
+        forcerm: true,
+        pull: true,
+        ..Default::default()
+    }, None, tar.map(Into::into));
+
+    while let Some(info) = stream.next().await {
+        let error = match info {
+            Ok(info) => {
+                for line in info.stream.iter().flat_map(|stream| stream.lines()).filter(|line| !line.trim().is_empty()) {
+                    output(server, &name, line.to_string(), false).await;
+                }
+
+                info.error
+            },
+            Err(e) => Some(e.to_string()),
+        };
+
+        if let Some(error) = error {
+            output(server, &name, error.clone(), true).await;
+            return Err(format!("Could not build Docker image: {}", error));
+        }
+    }
+
+    Ok(())
+}
+
+fn verify(hash: &str, data: &[u8]) -> bool {
+    Sha256::digest(data).iter().map(|byte| format!("{:02x}", byte)).collect::<String>() == hash.to_lowercase()
+}
+
+/// Returns a build context archive, from the data folder if it has been fetched before, or from
+/// the server otherwise.
+async fn archive(hash: &str) -> Result<Vec<u8>, String> {
+    // the hash is used as file name
+    if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("Invalid build context hash: {}", hash));
+    }
+
+    let folder = format!("{}/builds", config::get()?.storage.data_folder);
+    let path = format!("{}/{}.tar", folder, hash.to_lowercase());
+
+    if let Ok(data) = tokio::fs::read(&path).await
+        && verify(hash, &data) {
+        debug!("Using cached build context {}", hash);
+        return Ok(data);
+    }
+
+    debug!("Fetching build context {} from server", hash);
+    let data = fetch(hash).await?;
+
+    if !verify(hash, &data) {
+        return Err(format!("Build context {} does not match its hash", hash));
+    }
+
+    if let Err(e) = tokio::fs::create_dir_all(&folder).await {
+        warn!("Could not create build context folder: {}", e);
+    } else if let Err(e) = tokio::fs::write(&path, &data).await {
+        warn!("Could not store build context {}: {}", hash, e);
+    }
+
+    Ok(data)
+}
+
+/// Requests a build context archive from the server and waits for it to arrive. Concurrent
+/// requests for the same archive share a single request.
+async fn fetch(hash: &str) -> Result<Vec<u8>, String> {
+    let (tx, rx) = oneshot::channel();
+
+    let first = {
+        let mut pending = PENDING.lock().await;
+        let waiting = pending.entry(hash.to_string()).or_default();
+        waiting.push(tx);
+        waiting.len() == 1
+    };
+
+    if first {
+        let res = async {
+            send(DSFetchBuildContextPacket {
+                hash: hash.to_string(),
+            }.to_packet()?).await
+        }.await;
+
+        if let Err(e) = res {
+            PENDING.lock().await.remove(hash);
+            return Err(e);
+        }
+    }
+
+    match tokio::time::timeout(FETCH_TIMEOUT, rx).await {
+        Ok(Ok(Some(data))) => Ok(data),
+        Ok(Ok(None)) => Err(format!("Build context {} is not available", hash)),
+        Ok(Err(_)) => Err(format!("Could not receive build context {}", hash)),
+        Err(_) => {
+            PENDING.lock().await.remove(hash);
+            Err(format!("Timed out waiting for build context {}", hash))
+        }
+    }
+}
+
+/// Passes a build context received from the server on to the builds waiting for it.
+pub async fn receive(hash: String, data: Option<String>) -> Result<(), String> {
+    let waiting = PENDING.lock().await.remove(&hash).ok_or_else(|| format!("Received build context {} that was not requested", hash))?;
+
+    let data = data.map(|data| BASE64.decode(data)).transpose().map_err(|e| format!("Could not decode build context: {}", e))?;
+
+    for tx in waiting {
+        // the build might have timed out already
+        let _ = tx.send(data.clone());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,126 @@
+use std::net::Ipv4Addr;
+
+use packet::server_daemon::sync::{Isolation, IsolationPolicy};
+use tokio::{process::Command, sync::OnceCell};
+use tracing::{debug, instrument};
+
+/// Chain containing all egress rules managed by the daemon, jumped to from Docker's `DOCKER-USER`
+/// chain.
+const CHAIN: &str = "AE-EGRESS";
+/// Subnet containing all Aesterisk networks (see `network::create_network`), which is always
+/// reachable regardless of the isolation policy.
+const NETWORKS_CIDR: &str = "10.133.0.0/16";
+
+static CHAIN_READY: OnceCell<()> = OnceCell::const_new();
+
+async fn iptables(args: &[&str]) -> Result<(bool, String), String> {
+    let output = Command::new("iptables").args(args).output().await.map_err(|e| format!("Could not run iptables: {}", e))?;
+    Ok((output.status.success(), String::from_utf8_lossy(&output.stdout).into_owned()))
+}
+
+async fn iptables_ok(args: &[&str]) -> Result<(), String> {
+    match iptables(args).await? {
+        (true, _) => Ok(()),
+        (false, _) => Err(format!("iptables {} failed", args.join(" "))),
+    }
+}
+
+async fn ensure_chain() -> Result<(), String> {
+    CHAIN_READY.get_or_try_init(|| async {
+        if !iptables(&["-n", "-L", CHAIN]).await?.0 {
+            debug!("Creating {} chain", CHAIN);
+            iptables_ok(&["-N", CHAIN]).await?;
+            // replies to inbound connections (e.g. mapped ports) should never be dropped
+            iptables_ok(&["-A", CHAIN, "-m", "conntrack", "--ctstate", "RELATED,ESTABLISHED", "-j", "RETURN"]).await?;
+        }
+
+        if !iptables(&["-C", "DOCKER-USER", "-j", CHAIN]).await?.0 {
+            iptables_ok(&["-I", "DOCKER-USER", "-j", CHAIN]).await?;
+        }
+
+        Ok::<(), String>(())
+    }).await?;
+
+    Ok(())
+}
+
+fn validate_cidr(cidr: &str) -> Result<(), String> {
+    let (addr, prefix) = cidr.split_once('/').unwrap_or((cidr, "32"));
+
+    addr.parse::<Ipv4Addr>().map_err(|_| format!("Invalid CIDR '{}': invalid address", cidr))?;
+
+    match prefix.parse::<u8>() {
+        Ok(prefix) if prefix <= 32 => Ok(()),
+        _ => Err(format!("Invalid CIDR '{}': invalid prefix length", cidr)),
+    }
+}
+
+/// Lists the rules currently applied for the given server, as arguments to `iptables`.
+async fn rules(id: u32) -> Result<Vec<Vec<String>>, String> {
+    let (exists, rules) = iptables(&["-S", CHAIN]).await?;
+
+    if !exists {
+        return Ok(Vec::new());
+    }
+
+    let comment = format!("ae_sv_{}", id);
+
+    Ok(rules.lines()
+        .map(|rule| rule.split_whitespace().map(str::to_string).collect::<Vec<_>>())
+        .filter(|args| args.first().map(String::as_str) == Some("-A") && args.windows(2).any(|w| w[0] == "--comment" && w[1] == comment))
+        .collect())
+}
+
+async fn delete(rules: Vec<Vec<String>>) -> Result<(), String> {
+    for mut rule in rules {
+        rule[0] = "-D".to_string();
+        iptables_ok(&rule.iter().map(String::as_str).collect::<Vec<_>>()).await?;
+    }
+
+    Ok(())
+}
+
+/// Removes all egress rules for the given server.
+#[instrument("clear_firewall", skip_all, fields(server = id))]
+pub async fn clear(id: u32) -> Result<(), String> {
+    delete(rules(id).await?).await
+}
+
+/// Applies the isolation policy of a server to the given container addresses, replacing any
+/// previously applied rules for that server.
+///
+/// The new rules are appended before the old ones are deleted, so the container is never left
+/// without a policy, and an invalid allowlist keeps the previous rules in place.
+#[instrument("apply_firewall", skip_all, fields(server = id))]
+pub async fn apply(id: u32, addresses: &[String], isolation: &Isolation) -> Result<(), String> {
+    let mut allowed = vec![NETWORKS_CIDR];
+
+    if isolation.policy == IsolationPolicy::InternalOnly {
+        for cidr in isolation.allowlist.iter() {
+            validate_cidr(cidr)?;
+            allowed.push(cidr);
+        }
+    }
+
+    if isolation.policy == IsolationPolicy::FullEgress {
+        return clear(id).await;
+    }
+
+    ensure_chain().await?;
+
+    let previous = rules(id).await?;
+    let comment = format!("ae_sv_{}", id);
+
+    for addr in addresses {
+        debug!("Restricting egress of {} ({:?})", addr, isolation.policy);
+
+        for cidr in allowed.iter() {
+            iptables_ok(&["-A", CHAIN, "-s", addr, "-d", cidr, "-m", "comment", "--comment", &comment, "-j", "RETURN"]).await?;
+        }
+
+        iptables_ok(&["-A", CHAIN, "-s", addr, "-m", "comment", "--comment", &comment, "-j", "DROP"]).await?;
+    }
+
+    // `-D` removes the first matching rule, which is the old one if a rule is unchanged
+    delete(previous).await
+}
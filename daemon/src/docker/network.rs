@@ -2,8 +2,9 @@ use std::collections::HashMap;
 
 use bollard::{network::{CreateNetworkOptions, ListNetworksOptions}, secret::{Ipam, IpamConfig}};
 use packet::server_daemon::sync::Network;
-use tracing::debug;
+use tracing::{debug, instrument};
 
+#[instrument(skip_all, fields(network = id))]
 pub async fn create_network(id: u32, subnet: u8) -> Result<String, String> {
     let ipam_config = IpamConfig {
         subnet: Some(format!("10.133.{}.0/24", subnet)),
@@ -26,7 +27,7 @@ pub async fn create_network(id: u32, subnet: u8) -> Result<String, String> {
         ..Default::default()
     };
 
-    Ok(super::get()?.create_network(create_network_options).await.map_err(|e| format!("Could not create docker network: {}", e))?.id)
+    Ok(super::timed("create_network", super::get()?.create_network(create_network_options)).await.map_err(|e| format!("Could not create docker network: {}", e))?.id)
 }
 
 pub async fn get_networks() -> Result<Vec<Network>, String> {
@@ -39,7 +40,7 @@ pub async fn get_networks() -> Result<Vec<Network>, String> {
         ]),
     };
 
-    let networks = super::get()?.list_networks(Some(list_networks_options)).await.map_err(|e| format!("Could not get networks from Docker: {}", e))?;
+    let networks = super::timed("list_networks", super::get()?.list_networks(Some(list_networks_options))).await.map_err(|e| format!("Could not get networks from Docker: {}", e))?;
 
     networks.into_iter().map(|nw| Ok(Network {
         id: nw.labels.ok_or("no labels")?.get("io.aesterisk.network.id").ok_or("no id")?.parse().map_err(|e| format!("Could not parse network ID: {}", e))?,
@@ -57,13 +58,14 @@ async fn get_docker_network(id: u32) -> Result<Option<bollard::secret::Network>,
         ]),
     };
 
-    Ok(super::get()?.list_networks(Some(list_networks_options)).await.map_err(|e| format!("Could not get networks from Docker: {}", e))?.into_iter().next())
+    Ok(super::timed("list_networks", super::get()?.list_networks(Some(list_networks_options))).await.map_err(|e| format!("Could not get networks from Docker: {}", e))?.into_iter().next())
 }
 
 pub async fn network_exists(id: u32) -> Result<bool, String> {
     Ok(get_docker_network(id).await?.is_some())
 }
 
+#[instrument(skip_all, fields(network = id))]
 pub async fn delete_network(id: u32) -> Result<String, String> {
     let network = get_docker_network(id).await?;
 
@@ -74,12 +76,22 @@ pub async fn delete_network(id: u32) -> Result<String, String> {
     let network = network.unwrap();
     let id = network.id.ok_or("Found network has no ID")?;
 
-    super::get()?.remove_network(&id).await.map_err(|e| format!("Could not remove Docker network: {}", e))?;
+    super::timed("remove_network", super::get()?.remove_network(&id)).await.map_err(|e| format!("Could not remove Docker network: {}", e))?;
 
     Ok(id)
 }
 
-pub async fn get_nicc() -> Result<String, String> {
+fn nicc_name(internal: bool) -> &'static str {
+    if internal {
+        "ae_nicc_internal"
+    } else {
+        "ae_nicc"
+    }
+}
+
+/// Gets (or creates) the NICC network. An internal NICC network has no route outside of the host,
+/// and is used for servers with the `NoInternet` isolation policy.
+pub async fn get_nicc(internal: bool) -> Result<String, String> {
     let list_networks_options = ListNetworksOptions {
         filters: HashMap::from([
             ("label".to_string(), vec![
@@ -89,17 +101,20 @@ pub async fn get_nicc() -> Result<String, String> {
         ]),
     };
 
-    match super::get()?.list_networks(Some(list_networks_options)).await.map_err(|e| format!("Could not get networks from Docker: {}", e))?.into_iter().next() {
+    let nicc = super::timed("list_networks", super::get()?.list_networks(Some(list_networks_options))).await.map_err(|e| format!("Could not get networks from Docker: {}", e))?.into_iter().find(|nw| nw.name.as_deref() == Some(nicc_name(internal)));
+
+    match nicc {
         Some(nicc) => Ok(nicc.id.ok_or("NICC has no ID")?),
-        None => Ok(create_nicc().await?),
+        None => Ok(create_nicc(internal).await?),
     }
 }
 
-async fn create_nicc() -> Result<String, String> {
+async fn create_nicc(internal: bool) -> Result<String, String> {
     let create_network_options = CreateNetworkOptions {
-        name: "ae_nicc".to_string(),
+        name: nicc_name(internal).to_string(),
         check_duplicate: true,
         driver: "bridge".to_string(),
+        internal,
         labels: HashMap::from([
             ("io.aesterisk.network.version".to_string(), "0".to_string()),
             ("io.aesterisk.network.nicc".to_string(), "1".to_string()),
@@ -110,7 +125,7 @@ async fn create_nicc() -> Result<String, String> {
         ..Default::default()
     };
 
-    debug!("Creating NICC network...");
+    debug!("Creating NICC network ({})...", nicc_name(internal));
 
-    Ok(super::get()?.create_network(create_network_options).await.map_err(|e| format!("Could not create NICC network: {}", e))?.id)
+    Ok(super::timed("create_network", super::get()?.create_network(create_network_options)).await.map_err(|e| format!("Could not create NICC network: {}", e))?.id)
 }
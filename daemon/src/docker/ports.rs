@@ -0,0 +1,56 @@
+use std::{collections::HashSet, net::{TcpListener, UdpSocket}};
+
+use packet::server_daemon::sync::{Port, Protocol};
+use tracing::debug;
+
+use crate::config;
+
+const LABEL_PREFIX: &str = "io.aesterisk.server.port.";
+
+/// Returns the container label recording the host port a container port is mapped to.
+pub fn label(port: &Port) -> (String, String) {
+    (format!("{}{}/{}", LABEL_PREFIX, port.port, port.protocol), format!("{}", port.mapped))
+}
+
+async fn reserved() -> Result<HashSet<u16>, String> {
+    Ok(super::server::get_servers().await?.into_iter()
+        .flat_map(|container| container.labels.unwrap_or_default().into_iter())
+        .filter(|(key, _)| key.starts_with(LABEL_PREFIX))
+        .filter_map(|(_, value)| value.parse().ok())
+        .collect())
+}
+
+fn is_free(port: u16, protocol: Protocol) -> bool {
+    match protocol {
+        Protocol::Tcp => TcpListener::bind(("0.0.0.0", port)).is_ok(),
+        Protocol::Udp => UdpSocket::bind(("0.0.0.0", port)).is_ok(),
+    }
+}
+
+/// Assigns a free host port from the configured range to every port with an automatic (`0`)
+/// mapping, and returns the newly assigned ports.
+pub async fn assign(ports: &mut [Port]) -> Result<Vec<Port>, String> {
+    if !ports.iter().any(|port| port.mapped == 0) {
+        return Ok(Vec::new());
+    }
+
+    let range = &config::get()?.ports;
+    let mut reserved = reserved().await?;
+    reserved.extend(ports.iter().map(|port| port.mapped).filter(|mapped| *mapped != 0));
+
+    let mut assigned = Vec::new();
+
+    for port in ports.iter_mut().filter(|port| port.mapped == 0) {
+        let mapped = (range.range_start..=range.range_end)
+            .find(|candidate| !reserved.contains(candidate) && is_free(*candidate, port.protocol))
+            .ok_or(format!("No free ports left in range {}-{}", range.range_start, range.range_end))?;
+
+        debug!("Assigned host port {} to {}/{}", mapped, port.port, port.protocol);
+
+        reserved.insert(mapped);
+        port.mapped = mapped;
+        assigned.push(port.clone());
+    }
+
+    Ok(assigned)
+}
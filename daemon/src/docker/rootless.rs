@@ -0,0 +1,53 @@
+use bollard::secret::SystemInfoCgroupVersionEnum;
+use packet::events::DaemonInfo;
+use tokio::sync::OnceCell;
+
+/// Lowest port unprivileged processes can bind to, unless configured otherwise by the kernel.
+const DEFAULT_UNPRIVILEGED_PORT_START: u16 = 1024;
+
+static INFO: OnceCell<DaemonInfo> = OnceCell::const_new();
+
+fn unprivileged_port_start() -> u16 {
+    std::fs::read_to_string("/proc/sys/net/ipv4/ip_unprivileged_port_start").ok()
+        .and_then(|start| start.trim().parse().ok())
+        .unwrap_or(DEFAULT_UNPRIVILEGED_PORT_START)
+}
+
+/// Detects whether Docker runs in rootless mode, and which features are limited because of it.
+/// Docker must be initialised before calling this function.
+pub async fn detect() -> Result<&'static DaemonInfo, String> {
+    let system = super::timed("info", super::get()?.info()).await.map_err(|e| format!("Could not get Docker info: {}", e))?;
+
+    let rootless = system.security_options.unwrap_or_default().iter().any(|option| option.split(',').any(|part| part == "name=rootless"));
+
+    let info = if rootless {
+        DaemonInfo {
+            rootless,
+            min_host_port: unprivileged_port_start(),
+            // rootless Docker can only delegate cgroups on cgroup v2
+            server_stats: !matches!(system.cgroup_version, Some(SystemInfoCgroupVersionEnum::_1)),
+        }
+    } else {
+        DaemonInfo::default()
+    };
+
+    INFO.set(info).map_err(|_| "Docker info has already been detected")?;
+    INFO.get().ok_or("Docker info has not been detected".to_string())
+}
+
+/// Returns the detected info, or the defaults of a rootful Docker if it hasn't been detected.
+pub fn info() -> DaemonInfo {
+    INFO.get().cloned().unwrap_or_default()
+}
+
+/// Returns an error if a host port can't be mapped, because rootless Docker can't bind to
+/// privileged ports.
+pub fn check_port(port: u16) -> Result<(), String> {
+    let info = info();
+
+    if info.rootless && port < info.min_host_port {
+        return Err(format!("Host port {} is privileged, and can't be mapped with rootless Docker (lowest allowed port is {}, see net.ipv4.ip_unprivileged_port_start)", port, info.min_host_port));
+    }
+
+    Ok(())
+}
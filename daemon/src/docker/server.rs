@@ -1,12 +1,15 @@
-use std::{collections::HashMap, fs::create_dir_all};
-use bollard::{container::{Config, CreateContainerOptions, ListContainersOptions, NetworkingConfig, RemoveContainerOptions, RestartContainerOptions, StartContainerOptions, StopContainerOptions}, image::CreateImageOptions, secret::{ContainerSummary, EndpointIpamConfig, EndpointSettings, HealthConfig, HostConfig, MountBindOptions, MountTypeEnum, PortBinding, RestartPolicy, RestartPolicyNameEnum}};
+use std::{collections::{HashMap, HashSet}, fs::create_dir_all, time::{Duration, Instant}};
+use bollard::{container::{Config, CreateContainerOptions, InspectContainerOptions, KillContainerOptions, ListContainersOptions, NetworkingConfig, RemoveContainerOptions, RenameContainerOptions, RestartContainerOptions, StartContainerOptions, StopContainerOptions, TopOptions}, image::CreateImageOptions, network::{ConnectNetworkOptions, DisconnectNetworkOptions}, secret::{ContainerSummary, EndpointIpamConfig, EndpointSettings, HealthConfig, HealthStatusEnum, HostConfig, MountBindOptions, MountTypeEnum, PortBinding, RestartPolicy, RestartPolicyNameEnum}};
 use camino::{Utf8Component, Utf8Path, Utf8PathBuf};
 use futures_util::StreamExt;
-use packet::server_daemon::sync::{Env, EnvDef, EnvType, Mount, Server, ServerNetwork};
+use packet::{events::ProcessTable, server_daemon::sync::{Env, EnvDef, EnvType, Isolation, IsolationPolicy, Mount, Port, Server, ServerMetadata, ServerNetwork}};
 use regex::Regex;
-use tracing::debug;
+use tracing::{debug, instrument, warn};
 
-use crate::{config, docker::{self, network}};
+use crate::{config, docker::{self, firewall, network, ports}, maintenance, storage, sync_state, tasks};
+
+/// How long to wait for a dependency to become healthy before starting its dependents anyway
+pub const HEALTHY_TIMEOUT: Duration = Duration::from_secs(300);
 
 fn validate_env_defs(envs: &HashMap<String, Env>, env_defs: Vec<EnvDef>) -> Result<(), String> {
     for env_def in env_defs.into_iter() {
@@ -90,7 +93,7 @@ fn validate_mounts(server_id: u32, mounts: Vec<Mount>) -> Result<Option<Vec<boll
     if !mounts.is_empty() {
         debug!("Validating mounts...");
 
-        let server_data = format!("{}/{}/", config::get()?.daemon.data_folder, server_id);
+        let server_data = format!("{}/{}/", config::get()?.storage.data_folder, server_id);
         let data_path = Utf8Path::new(&server_data);
 
         create_dir_all(data_path).map_err(|e| format!("Could not create data directory: {}", e))?;
@@ -145,25 +148,47 @@ fn validate_mounts(server_id: u32, mounts: Vec<Mount>) -> Result<Option<Vec<boll
     }
 }
 
-async fn pull_image(image: &str, tag: &str) -> Result<(), String> {
-    match super::get()?.create_image(Some(CreateImageOptions {
+/// Pulls the image of a server, which has to be done before creating it. A checkpoint of the task
+/// pulling the image is recorded whenever a layer has been pulled.
+#[instrument(skip_all, fields(image = %image, tag = %tag))]
+pub async fn pull_image(image: &str, tag: &str, progress: &tasks::Progress) -> Result<(), String> {
+    let mut stream = super::get()?.create_image(Some(CreateImageOptions {
         from_image: image,
         tag,
         ..Default::default()
-    }), None, None).collect::<Vec<_>>().await.into_iter().reduce(|a, b| a.and(b)) {
-        None => (),
-        Some(res) => {
-            res.map_err(|e| format!("Could not create Docker image: {}", e))?;
+    }), None, None);
+
+    let mut layers = HashSet::new();
+    let mut pulled = HashSet::new();
+
+    while let Some(info) = stream.next().await {
+        let info = info.map_err(|e| format!("Could not create Docker image: {}", e))?;
+
+        // the first message has the tag as id
+        let (Some(layer), Some(status)) = (info.id, info.status) else {
+            continue;
+        };
+
+        if status.starts_with("Pulling from") {
+            continue;
+        }
+
+        layers.insert(layer.clone());
+
+        if (status == "Pull complete" || status == "Already exists") && pulled.insert(layer) {
+            progress.checkpoint(Some(pulled.len() as f64 / layers.len() as f64), format!("{}/{} layers", pulled.len(), layers.len())).await;
         }
     }
 
     Ok(())
 }
 
-async fn get_endpoint_config(networks: Vec<ServerNetwork>) -> Result<HashMap<String, EndpointSettings>, String> {
+/// Returns the endpoint settings of the networks of a server. Without `fixed`, the container is
+/// assigned a temporary IP on its networks instead of the server's own.
+async fn get_endpoint_config(networks: Vec<ServerNetwork>, policy: IsolationPolicy, fixed: bool) -> Result<HashMap<String, EndpointSettings>, String> {
     let nicc = if networks.is_empty() {
         debug!("Obtaining or creating NICC network");
-        Some(network::get_nicc().await?)
+        Some(network::get_nicc(policy == IsolationPolicy::NoInternet).await?)
     } else {
         None
     };
@@ -175,35 +200,77 @@ async fn get_endpoint_config(networks: Vec<ServerNetwork>) -> Result<HashMap<Str
     } else {
         let subnets = docker::network::get_networks().await?.into_iter().map(|nw| (nw.id, nw.subnet)).collect::<HashMap<_, _>>();
 
-        let networks = networks.into_iter().map(|nw| Ok((format!("ae_nw_{}", nw.network), EndpointSettings {
-            ipam_config: Some(EndpointIpamConfig {
-                ipv4_address: Some(format!("10.133.{}.{}", subnets.get(&nw.network).ok_or("network not found")?, nw.ip)),
-                ..Default::default()
-            }),
-            ..Default::default()
+        let networks = networks.into_iter().map(|nw| Ok((format!("ae_nw_{}", nw.network), if fixed {
+            endpoint_settings(subnets.get(&nw.network).ok_or("network not found")?, &nw)
+        } else {
+            EndpointSettings::default()
         }))).collect::<Result<Vec<_>, String>>()?;
 
         Ok(networks.into_iter().collect::<HashMap<_, _>>())
     }
 }
 
-pub async fn create_server(server: Server) -> Result<String, String> {
+fn endpoint_settings(subnet: &u8, network: &ServerNetwork) -> EndpointSettings {
+    EndpointSettings {
+        ipam_config: Some(EndpointIpamConfig {
+            ipv4_address: Some(format!("10.133.{}.{}", subnet, network.ip)),
+            ..Default::default()
+        }),
+        ..Default::default()
+    }
+}
+
+/// Creates and starts a server, returning the container ID and any automatically assigned ports.
+/// The image of the server has to be pulled (or built) first, see `pull_image`.
+#[instrument(skip_all, fields(server = server.id))]
+pub async fn create_server(server: Server, metadata: Option<&ServerMetadata>) -> Result<(String, Vec<Port>), String> {
+    let name = format!("ae_sv_{}", server.id);
+    create_container(server, &name, true, metadata).await
+}
+
+/// Creates and starts the container of a server that is going to replace its current container
+/// (see `docker::update`). The candidate does not publish any ports and uses temporary IPs, so it
+/// can run next to the current container.
+#[instrument(skip_all, fields(server = server.id, name = %name))]
+pub async fn create_candidate(server: Server, name: &str, metadata: Option<&ServerMetadata>) -> Result<String, String> {
+    Ok(create_container(server, name, false, metadata).await?.0)
+}
+
+/// Returns the container labels of the display metadata of a server. Labels can't be changed
+/// once a container is created, see `update::apply_metadata`.
+pub fn metadata_labels(metadata: &ServerMetadata) -> Vec<(String, String)> {
+    std::iter::once(("io.aesterisk.server.name".to_string(), metadata.name.clone()))
+        .chain(metadata.labels.iter().map(|(key, value)| (format!("io.aesterisk.label.{}", key), value.clone())))
+        .collect()
+}
+
+async fn create_container(mut server: Server, name: &str, fixed: bool, metadata: Option<&ServerMetadata>) -> Result<(String, Vec<Port>), String> {
     let envs = server.envs.into_iter().map(|e| (e.key.clone(), e)).collect::<HashMap<_, _>>();
 
     validate_env_defs(&envs, server.tag.env_defs).map_err(|e| format!("Failed to validate env defs: {}", e))?;
 
+    storage::check_server_space().map_err(|e| format!("Refusing to create server: {}", e))?;
+
     let create_container_options = CreateContainerOptions {
-        name: format!("ae_sv_{}", server.id),
+        name: name.to_string(),
         ..Default::default()
     };
 
     let mounts = validate_mounts(server.id, server.tag.mounts).map_err(|e| format!("Failed to validate mounts: {}", e))?;
 
-    pull_image(&server.tag.image, &server.tag.docker_tag).await.map_err(|e| format!("Failed to pull image: {}", e))?;
+    let assigned_ports = if fixed {
+        ports::assign(&mut server.ports).await.map_err(|e| format!("Failed to assign ports: {}", e))?
+    } else {
+        Vec::new()
+    };
+
+    for port in server.ports.iter().filter(|_| fixed) {
+        super::rootless::check_port(port.mapped).map_err(|e| format!("Refusing to create server: {}", e))?;
+    }
 
     debug!("Creating container...");
 
-    let endpoints_config = get_endpoint_config(server.networks).await.map_err(|e| format!("Failed to get endpoint config: {}", e))?;
+    let endpoints_config = get_endpoint_config(server.networks, server.isolation.policy, fixed).await.map_err(|e| format!("Failed to get endpoint config: {}", e))?;
 
     let container_config = Config {
         hostname: Some(format!("ae_sv_{}", server.id)),
@@ -213,7 +280,7 @@ pub async fn create_server(server: Server) -> Result<String, String> {
         labels: Some(HashMap::from([
             ("io.aesterisk.server.version".to_string(), "0".to_string()),
             ("io.aesterisk.server.id".to_string(), format!("{}", server.id)),
-        ])),
+        ]).into_iter().chain(server.ports.iter().map(ports::label)).chain(metadata.map(metadata_labels).unwrap_or_default()).collect()),
         healthcheck: Some(HealthConfig {
             test: Some(server.tag.healthcheck.test),
             timeout: Some(server.tag.healthcheck.timeout as i64 * 1_000_000),
@@ -230,7 +297,7 @@ pub async fn create_server(server: Server) -> Result<String, String> {
                 name: Some(RestartPolicyNameEnum::UNLESS_STOPPED),
                 ..Default::default()
             }),
-            port_bindings: Some(server.ports.into_iter().map(|port| (format!("{}/{}", port.port, port.protocol), Some(vec![PortBinding {
+            port_bindings: Some(server.ports.into_iter().filter(|_| fixed).map(|port| (format!("{}/{}", port.port, port.protocol), Some(vec![PortBinding {
                 host_ip: Some("".to_string()),
                 host_port: Some(format!("{}", port.mapped)),
             }]))).collect::<HashMap<_, _>>()),
@@ -240,17 +307,17 @@ pub async fn create_server(server: Server) -> Result<String, String> {
         ..Default::default()
     };
 
-    let id = super::get()?.create_container(Some(create_container_options), container_config).await.map_err(|e| format!("Could not create Docker container: {}", e))?.id;
+    let id = super::timed("create_container", super::get()?.create_container(Some(create_container_options), container_config)).await.map_err(|e| format!("Could not create Docker container: {}", e))?.id;
 
     debug!("Created container: '{}'", id);
 
     debug!("Starting container...");
 
-    super::get()?.start_container(&id, None::<StartContainerOptions<String>>).await.map_err(|e| format!("Could not start Docker container: {}", e))?;
+    super::timed("start_container", super::get()?.start_container(&id, None::<StartContainerOptions<String>>)).await.map_err(|e| format!("Could not start Docker container: {}", e))?;
 
     debug!("Started container");
 
-    Ok(id)
+    Ok((id, assigned_ports))
 }
 
 pub async fn get_servers() -> Result<Vec<ContainerSummary>, String> {
@@ -264,7 +331,11 @@ pub async fn get_servers() -> Result<Vec<ContainerSummary>, String> {
         ..Default::default()
     };
 
-    super::get()?.list_containers(Some(list_containers_options)).await.map_err(|e| format!("Could not get containers from Docker: {}", e))
+    // candidates and retired containers of blue/green updates have the same labels as the server
+    Ok(super::timed("list_containers", super::get()?.list_containers(Some(list_containers_options))).await.map_err(|e| format!("Could not get containers from Docker: {}", e))?
+        .into_iter()
+        .filter(|container| !container.names.iter().flatten().any(|name| name.ends_with(docker::update::CANDIDATE_SUFFIX) || name.ends_with(docker::update::PREVIOUS_SUFFIX)))
+        .collect())
 }
 
 pub async fn get_server(id: u32) -> Result<Option<ContainerSummary>, String> {
@@ -275,30 +346,241 @@ pub async fn get_server(id: u32) -> Result<Option<ContainerSummary>, String> {
                 format!("io.aesterisk.server.id={}", id),
                 "io.aesterisk.server.version=0".to_string()
             ]),
+            ("name".to_string(), vec![
+                format!("^/ae_sv_{}$", id),
+            ]),
         ]),
         ..Default::default()
     };
 
-    Ok(super::get()?.list_containers(Some(list_containers_options)).await.map_err(|e| format!("Could not get containers from Docker: {}", e))?.into_iter().next())
+    Ok(super::timed("list_containers", super::get()?.list_containers(Some(list_containers_options))).await.map_err(|e| format!("Could not get containers from Docker: {}", e))?.into_iter().next())
 }
 
 pub async fn server_exists(id: u32) -> Result<bool, String> {
     Ok(get_server(id).await?.is_some())
 }
 
+#[instrument(skip_all, fields(server = id))]
 pub async fn stop_server(id: u32) -> Result<bool, String> {
     let container = get_server(id).await?.ok_or("Server does not exist")?;
-    Ok(super::get()?.stop_container(container.id.as_ref().ok_or("Container should have an ID")?, None::<StopContainerOptions>).await.is_ok()
-        && super::get()?.remove_container(container.id.as_ref().ok_or("Container should have an ID")?, None::<RemoveContainerOptions>).await.is_ok())
+    Ok(super::timed("stop_container", super::get()?.stop_container(container.id.as_ref().ok_or("Container should have an ID")?, None::<StopContainerOptions>)).await.is_ok()
+        && super::timed("remove_container", super::get()?.remove_container(container.id.as_ref().ok_or("Container should have an ID")?, None::<RemoveContainerOptions>)).await.is_ok())
 }
 
+/// Restarts a server, followed by all servers that (indirectly) depend on it, in start order. If
+/// the server is in a maintenance window, the restart is deferred until the window has ended and
+/// `false` is returned right away.
+#[instrument(skip_all, fields(server = id))]
 pub async fn restart_server(id: u32) -> Result<bool, String> {
+    if !maintenance::in_maintenance(Some(id)).await {
+        return restart_server_now(id).await.map(|()| true);
+    }
+
+    maintenance::defer(Some(id), async move {
+        if let Err(e) = restart_server_now(id).await {
+            warn!("Could not restart server {} after maintenance: {}", id, e);
+        }
+    });
+
+    Ok(false)
+}
+
+async fn restart_container(id: u32, container: &ContainerSummary) -> Result<(), String> {
+    super::timed("restart_container", super::get()?.restart_container(container.id.as_ref().ok_or("Container should have an ID")?, None::<RestartContainerOptions>)).await.map_err(|e| format!("Could not restart server {}: {}", id, e))
+}
+
+/// Restarts a server and its dependents like `restart_server`, without deferring until its
+/// maintenance windows have ended, for restarts requested by a user. Dependents in a maintenance
+/// window are still restarted once it has ended.
+#[instrument(skip_all, fields(server = id))]
+pub async fn restart_server_now(id: u32) -> Result<(), String> {
     // TODO: change restart_container to stop_container followed by start_container, where
     // start_container (or this function in between) somehow needs to know if there are changes to
     // the server that should be used for the start_container call.
 
     let container = get_server(id).await?.ok_or("Server does not exist")?;
-    Ok(super::get()?.restart_container(container.id.as_ref().ok_or("Container should have an ID")?, None::<RestartContainerOptions>).await.is_ok())
+    restart_container(id, &container).await?;
+
+    let Some(spec) = sync_state::load().await?.spec else {
+        return Ok(());
+    };
+
+    for dependent in spec.dependents(id) {
+        let Some(container) = get_server(dependent.id).await? else {
+            continue;
+        };
+
+        if maintenance::in_maintenance(Some(dependent.id)).await {
+            let dependent = dependent.id;
+
+            maintenance::defer(Some(dependent), async move {
+                debug!("Restarting dependent server {} after maintenance", dependent);
+                if let Err(e) = restart_container(dependent, &container).await {
+                    warn!("Could not restart dependent server after maintenance: {}", e);
+                }
+            });
+
+            continue;
+        }
+
+        wait_for_dependencies(&dependent.depends_on.iter().filter(|dependency| dependency.healthy).map(|dependency| dependency.server).collect::<Vec<_>>()).await?;
+
+        debug!("Restarting dependent server {}", dependent.id);
+        restart_container(dependent.id, &container).await?;
+    }
+
+    Ok(())
+}
+
+/// Starts the container of a stopped server.
+#[instrument(skip_all, fields(server = id))]
+pub async fn start_server(id: u32) -> Result<(), String> {
+    let container = get_server(id).await?.ok_or("Server does not exist")?;
+    super::timed("start_container", super::get()?.start_container(container.id.as_ref().ok_or("Container should have an ID")?, None::<StartContainerOptions<String>>)).await.map_err(|e| format!("Could not start server: {}", e))
+}
+
+/// Stops the container of a server, unlike `stop_server` keeping it so it can be started again.
+/// As the container was stopped manually, Docker doesn't restart it until it is started again.
+#[instrument(skip_all, fields(server = id))]
+pub async fn halt_server(id: u32) -> Result<(), String> {
+    let container = get_server(id).await?.ok_or("Server does not exist")?;
+    super::timed("stop_container", super::get()?.stop_container(container.id.as_ref().ok_or("Container should have an ID")?, None::<StopContainerOptions>)).await.map_err(|e| format!("Could not stop server: {}", e))
+}
+
+/// Kills the container of a server, without giving it time to shut down.
+#[instrument(skip_all, fields(server = id))]
+pub async fn kill_server(id: u32) -> Result<(), String> {
+    let container = get_server(id).await?.ok_or("Server does not exist")?;
+    super::timed("kill_container", super::get()?.kill_container(container.id.as_ref().ok_or("Container should have an ID")?, None::<KillContainerOptions<String>>)).await.map_err(|e| format!("Could not kill server: {}", e))
+}
+
+/// Returns the health of a container, or `HEALTHY` if it is running and its tag has no
+/// healthcheck, or `UNHEALTHY` if it has stopped.
+pub async fn health(name: &str) -> Result<HealthStatusEnum, String> {
+    let container = super::timed("inspect_container", super::get()?.inspect_container(name, None::<InspectContainerOptions>)).await.map_err(|e| format!("could not inspect container: {}", e))?;
+    let state = container.state.ok_or("Container should have a state")?;
+
+    Ok(match state.health.and_then(|health| health.status) {
+        Some(HealthStatusEnum::NONE) | Some(HealthStatusEnum::EMPTY) | None if state.running.unwrap_or(false) => HealthStatusEnum::HEALTHY,
+        Some(HealthStatusEnum::NONE) | Some(HealthStatusEnum::EMPTY) | None => HealthStatusEnum::UNHEALTHY,
+        Some(status) => status,
+    })
+}
+
+/// Returns whether a server is healthy, or running if its tag has no healthcheck.
+pub async fn is_healthy(id: u32) -> Result<bool, String> {
+    Ok(health(&format!("ae_sv_{}", id)).await? == HealthStatusEnum::HEALTHY)
+}
+
+/// Waits until all given servers are healthy, giving up (with a warning) after `HEALTHY_TIMEOUT`.
+pub async fn wait_for_dependencies(servers: &[u32]) -> Result<(), String> {
+    let start = Instant::now();
+
+    for &server in servers.iter() {
+        debug!("Waiting for server {} to become healthy", server);
+
+        while !is_healthy(server).await? {
+            if start.elapsed() > HEALTHY_TIMEOUT {
+                warn!("Server {} did not become healthy in time, continuing anyway", server);
+                return Ok(());
+            }
+
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Turns a candidate container into the container of a server, after the server's previous
+/// container has been removed. Only possible for servers without published ports, as those can't
+/// be added to an existing container.
+#[instrument(skip_all, fields(server = server.id, name = %name))]
+pub async fn promote_candidate(server: &Server, name: &str) -> Result<(), String> {
+    let subnets = docker::network::get_networks().await?.into_iter().map(|nw| (nw.id, nw.subnet)).collect::<HashMap<_, _>>();
+
+    for nw in server.networks.iter() {
+        let network = format!("ae_nw_{}", nw.network);
+
+        super::timed("disconnect_network", super::get()?.disconnect_network(&network, DisconnectNetworkOptions {
+            container: name,
+            force: true,
+        })).await.map_err(|e| format!("Could not disconnect candidate from network {}: {}", nw.network, e))?;
+
+        super::timed("connect_network", super::get()?.connect_network(&network, ConnectNetworkOptions {
+            container: name,
+            endpoint_config: endpoint_settings(subnets.get(&nw.network).ok_or("network not found")?, nw),
+        })).await.map_err(|e| format!("Could not connect candidate to network {}: {}", nw.network, e))?;
+    }
+
+    super::timed("rename_container", super::get()?.rename_container(name, RenameContainerOptions {
+        name: format!("ae_sv_{}", server.id),
+    })).await.map_err(|e| format!("Could not rename candidate: {}", e))
+}
+
+/// Stops the container of a server and renames it to `name`, so a new container can take its
+/// place while the old one can still be restored with `restore_container`.
+#[instrument(skip_all, fields(server = id, name = %name))]
+pub async fn retire_server(id: u32, name: &str) -> Result<(), String> {
+    halt_server(id).await?;
+
+    super::timed("rename_container", super::get()?.rename_container(&format!("ae_sv_{}", id), RenameContainerOptions {
+        name,
+    })).await.map_err(|e| format!("Could not rename container: {}", e))
+}
+
+/// Replaces the container of a server, if any, with the container retired as `name` by
+/// `retire_server`, and starts it again.
+#[instrument(skip_all, fields(server = id, name = %name))]
+pub async fn restore_server(id: u32, name: &str) -> Result<(), String> {
+    remove_container(&format!("ae_sv_{}", id)).await?;
+
+    super::timed("rename_container", super::get()?.rename_container(name, RenameContainerOptions {
+        name: format!("ae_sv_{}", id),
+    })).await.map_err(|e| format!("Could not rename container: {}", e))?;
+
+    start_server(id).await
+}
+
+/// Stops and removes a container by name, ignoring containers that don't exist.
+#[instrument(skip_all, fields(name = %name))]
+pub async fn remove_container(name: &str) -> Result<(), String> {
+    super::timed("remove_container", super::get()?.remove_container(name, Some(RemoveContainerOptions {
+        force: true,
+        ..Default::default()
+    }))).await.or_else(|e| match e {
+        bollard::errors::Error::DockerResponseServerError { status_code: 404, .. } => Ok(()),
+        e => Err(format!("Could not remove container {}: {}", name, e)),
+    })
+}
+
+/// Applies the isolation policy of a server to all of its container addresses.
+#[instrument(skip_all, fields(server = id))]
+pub async fn apply_isolation(id: u32, isolation: &Isolation) -> Result<(), String> {
+    let container = super::timed("inspect_container", super::get()?.inspect_container(&format!("ae_sv_{}", id), None::<InspectContainerOptions>)).await.map_err(|e| format!("could not inspect container: {}", e))?;
+
+    let addresses = container.network_settings
+        .and_then(|settings| settings.networks)
+        .unwrap_or_default()
+        .into_values()
+        .filter_map(|endpoint| endpoint.ip_address)
+        .filter(|addr| !addr.is_empty())
+        .collect::<Vec<_>>();
+
+    firewall::apply(id, &addresses, isolation).await
+}
+
+/// Lists the processes running inside the container of a server.
+#[instrument(skip_all, fields(server = id))]
+pub async fn top(id: u32) -> Result<ProcessTable, String> {
+    let top = super::timed("top_processes", super::get()?.top_processes(&format!("ae_sv_{}", id), Some(TopOptions {
+        ps_args: "aux",
+    }))).await.map_err(|e| format!("could not list processes: {}", e))?;
+
+    Ok(ProcessTable {
+        titles: top.titles.unwrap_or_default(),
+        processes: top.processes.unwrap_or_default(),
+    })
 }
 
 pub async fn is_running(id: u32) -> Result<bool, String> {
@@ -0,0 +1,113 @@
+use bollard::container::{self, MemoryStatsStats};
+use packet::events::{CpuConvention, Stats};
+
+const GB: f64 = 1_073_741_824.0;
+
+/// Returns the amount of CPUs available to a container. `online_cpus` is missing on older API
+/// versions, where every CPU is listed in `percpu_usage` instead.
+pub fn cores(stat: &container::Stats) -> Option<u64> {
+    match stat.cpu_stats.online_cpus {
+        Some(cpus) if cpus > 0 => Some(cpus),
+        _ => stat.cpu_stats.cpu_usage.percpu_usage.as_ref().map(|percpu| percpu.len() as u64).filter(|cpus| *cpus > 0),
+    }
+}
+
+/// Returns the CPU usage of a container following `convention`, or `None` if the stats don't
+/// contain the fields needed to calculate it. This is the case for the first sample of a container
+/// (`precpu_stats` are empty), and on hosts without CPU accounting, e.g. rootless Docker on cgroup
+/// v1.
+pub fn cpu(stat: &container::Stats, convention: CpuConvention) -> Option<Stats> {
+    let usage = stat.cpu_stats.cpu_usage.total_usage;
+    let system = stat.cpu_stats.system_cpu_usage?;
+    let pre_usage = stat.precpu_stats.cpu_usage.total_usage;
+    let pre_system = stat.precpu_stats.system_cpu_usage?;
+
+    // a container that is running always used some CPU time, so a usage of zero means that the
+    // host doesn't account for it
+    if usage == 0 || system <= pre_system {
+        return None;
+    }
+
+    let total = match convention {
+        CpuConvention::PerCore => (cores(stat)? * 100) as f64,
+        CpuConvention::AllCores => 100.0,
+    };
+
+    Some(Stats {
+        used: usage.saturating_sub(pre_usage) as f64 / (system - pre_system) as f64 * total,
+        total,
+    })
+}
+
+/// Returns the memory usage of a container in GB, or `None` if the stats don't contain it, e.g. on
+/// rootless Docker on cgroup v1.
+///
+/// Like `docker stats`, inactive file-backed memory is not counted as used, as the kernel reclaims
+/// it before running out of memory. It is reported as `total_inactive_file` on cgroup v1 and as
+/// `inactive_file` on cgroup v2, and missing on some runtimes (e.g. the containerd snapshotter), in
+/// which case the raw usage is returned.
+pub fn memory(stat: &container::Stats) -> Option<Stats> {
+    let usage = stat.memory_stats.usage?;
+    let limit = stat.memory_stats.limit.filter(|limit| *limit > 0)?;
+
+    let inactive = match &stat.memory_stats.stats {
+        Some(MemoryStatsStats::V1(v1)) => v1.total_inactive_file,
+        Some(MemoryStatsStats::V2(v2)) => v2.inactive_file,
+        None => 0,
+    };
+
+    Some(Stats {
+        used: usage.saturating_sub(inactive) as f64 / GB,
+        total: limit as f64 / GB,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(json: &str) -> container::Stats {
+        serde_json::from_str(json).expect("could not parse fixture")
+    }
+
+    fn assert_stats(stats: Option<Stats>, used: f64, total: f64) {
+        let stats = stats.expect("stats should be available");
+        assert!((stats.used - used).abs() < 1e-9, "used is {}, expected {}", stats.used, used);
+        assert!((stats.total - total).abs() < 1e-9, "total is {}, expected {}", stats.total, total);
+    }
+
+    #[test]
+    fn cgroup_v2() {
+        let stat = fixture(include_str!("../../fixtures/stats/cgroup-v2.json"));
+
+        assert_stats(cpu(&stat, CpuConvention::PerCore), 5.0, 400.0);
+        assert_stats(cpu(&stat, CpuConvention::AllCores), 1.25, 100.0);
+        assert_stats(memory(&stat), 100.0 / 1024.0, 2.0);
+    }
+
+    #[test]
+    fn cgroup_v1() {
+        let stat = fixture(include_str!("../../fixtures/stats/cgroup-v1-no-online-cpus.json"));
+
+        // no `online_cpus`, so the CPUs are counted from `percpu_usage`
+        assert_eq!(cores(&stat), Some(2));
+        assert_stats(cpu(&stat, CpuConvention::PerCore), 2.0, 200.0);
+        assert_stats(memory(&stat), 192.0 / 1024.0, 1.0);
+    }
+
+    #[test]
+    fn containerd_first_sample() {
+        let stat = fixture(include_str!("../../fixtures/stats/containerd-first-sample.json"));
+
+        assert!(cpu(&stat, CpuConvention::PerCore).is_none());
+        assert_stats(memory(&stat), 50.0 / 1024.0, 4.0);
+    }
+
+    #[test]
+    fn rootless_cgroup_v1() {
+        let stat = fixture(include_str!("../../fixtures/stats/rootless-cgroup-v1.json"));
+
+        assert!(cpu(&stat, CpuConvention::PerCore).is_none());
+        assert!(memory(&stat).is_none());
+    }
+}
@@ -0,0 +1,170 @@
+use std::{collections::HashMap, time::{Duration, Instant}};
+
+use bollard::secret::HealthStatusEnum;
+use packet::{events::{EventData, EventType, UpdatePhase, UpdatePhaseEvent}, server_daemon::sync::{Port, Server, ServerMetadata, UpdateStrategy}};
+use tracing::{debug, info, instrument, warn};
+
+use crate::{docker::server, maintenance, sequence, sync_state, LISTENS};
+
+/// Suffix of the container name of a server's candidate, see `blue_green`
+pub const CANDIDATE_SUFFIX: &str = "_next";
+/// Suffix of the container name of a server's previous container while it is being replaced, see
+/// `blue_green`
+pub const PREVIOUS_SUFFIX: &str = "_prev";
+
+/// Reports a phase of an update to the server, if anyone is listening for it.
+async fn phase(server: u32, phase: UpdatePhase) {
+    debug!("[update {}] {:?}", server, phase);
+
+    if !LISTENS.read().await.contains(&EventType::UpdatePhase) {
+        return;
+    }
+
+    let res = sequence::send(EventData::UpdatePhase(UpdatePhaseEvent {
+        server,
+        phase,
+    }), None).await;
+
+    if let Err(e) = res {
+        warn!("Could not send update phase: {}", e);
+    }
+}
+
+/// Waits until a candidate is healthy, failing as soon as it turns unhealthy or after
+/// `HEALTHY_TIMEOUT`.
+async fn wait_until_healthy(name: &str) -> Result<(), String> {
+    let start = Instant::now();
+
+    loop {
+        match server::health(name).await? {
+            HealthStatusEnum::HEALTHY => return Ok(()),
+            HealthStatusEnum::UNHEALTHY => return Err("New container is unhealthy".to_string()),
+            _ if start.elapsed() > server::HEALTHY_TIMEOUT => return Err("New container did not become healthy in time".to_string()),
+            _ => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+/// Replaces the running container of a server with minimal downtime: a candidate container is
+/// started next to it first, and the old container is only replaced once the candidate is healthy.
+/// If the candidate does not become healthy, it is removed and the old container keeps running.
+///
+/// Servers without published ports keep running the candidate. Published ports can't be added to a
+/// running container, so for servers with ports the container is recreated once the candidate
+/// has proven healthy, and the candidate is removed afterwards. Until the new container runs, the
+/// old one is only stopped and renamed, and it is restored if the swap fails.
+///
+/// Returns the container ID and any automatically assigned ports, like `server::create_server`.
+#[instrument(skip_all, fields(server = server.id))]
+pub async fn blue_green(server: Server, metadata: Option<&ServerMetadata>) -> Result<(String, Vec<Port>), String> {
+    let id = server.id;
+    let name = format!("ae_sv_{}{}", id, CANDIDATE_SUFFIX);
+    let previous = format!("ae_sv_{}{}", id, PREVIOUS_SUFFIX);
+
+    // a previous update might have been interrupted
+    server::remove_container(&name).await?;
+    server::remove_container(&previous).await?;
+
+    phase(id, UpdatePhase::StartingCandidate).await;
+    let candidate = server::create_candidate(server.clone(), &name, metadata).await?;
+
+    phase(id, UpdatePhase::WaitingForHealth).await;
+    if let Err(e) = wait_until_healthy(&name).await {
+        return Err(roll_back(id, &name, None, e).await);
+    }
+
+    phase(id, UpdatePhase::Swapping).await;
+    let retired = server::server_exists(id).await?;
+    if retired && let Err(e) = server::retire_server(id, &previous).await {
+        return Err(roll_back(id, &name, None, e).await);
+    }
+
+    // the isolation policy is applied to the new container by the sync afterwards
+    let res = if server.ports.is_empty() {
+        server::promote_candidate(&server, &name).await.map(|_| (candidate, Vec::new()))
+    } else {
+        let res = server::create_server(server, metadata).await;
+
+        if res.is_ok() && let Err(e) = server::remove_container(&name).await {
+            warn!("Could not remove candidate of server {}: {}", id, e);
+        }
+
+        res
+    };
+
+    match res {
+        Ok(res) => {
+            if retired && let Err(e) = server::remove_container(&previous).await {
+                warn!("Could not remove previous container of server {}: {}", id, e);
+            }
+
+            phase(id, UpdatePhase::Done).await;
+            Ok(res)
+        },
+        Err(e) => Err(roll_back(id, &name, retired.then_some(previous.as_str()), e).await),
+    }
+}
+
+/// Removes the candidate of a failed update and restores the previous container of the server if
+/// it was already retired, returning the error to fail the update with.
+async fn roll_back(id: u32, candidate: &str, previous: Option<&str>, error: String) -> String {
+    if let Err(e) = server::remove_container(candidate).await {
+        warn!("Could not remove candidate of server {}: {}", id, e);
+    }
+
+    if let Some(previous) = previous && let Err(e) = server::restore_server(id, previous).await {
+        warn!("Could not restore previous container of server {}: {}", id, e);
+    }
+
+    phase(id, UpdatePhase::RolledBack {
+        error: error.clone(),
+    }).await;
+
+    format!("Could not update server {}: {}", id, error)
+}
+
+/// Replaces the container of a server if its labels don't match its display metadata, as Docker
+/// can't change the labels of an existing container. The server's update strategy is used, so
+/// blue/green servers keep running until the new container is healthy. Servers in a maintenance
+/// window are left alone, and get the labels the next time their container is replaced.
+#[instrument(skip_all, fields(server = metadata.server))]
+pub async fn apply_metadata(metadata: ServerMetadata) -> Result<(), String> {
+    let id = metadata.server;
+
+    let Some(container) = server::get_server(id).await? else {
+        return Ok(());
+    };
+
+    let current = container.labels.unwrap_or_default().into_iter()
+        .filter(|(key, _)| key == "io.aesterisk.server.name" || key.starts_with("io.aesterisk.label."))
+        .collect::<HashMap<_, _>>();
+
+    if current == server::metadata_labels(&metadata).into_iter().collect::<HashMap<_, _>>() {
+        return Ok(());
+    }
+
+    if maintenance::in_maintenance(Some(id)).await {
+        info!("Server {} is in maintenance, its labels are updated when its container is next replaced", id);
+        return Ok(());
+    }
+
+    let Some(server) = sync_state::load().await?.spec.and_then(|spec| spec.servers.into_iter().find(|server| server.id == id)) else {
+        return Ok(());
+    };
+
+    debug!("Replacing container of server {} to update its labels", id);
+
+    let isolation = server.isolation.clone();
+
+    match server.update {
+        UpdateStrategy::BlueGreen => {
+            blue_green(server, Some(&metadata)).await?;
+        },
+        UpdateStrategy::Recreate => {
+            server::stop_server(id).await?;
+            server::create_server(server, Some(&metadata)).await?;
+        },
+    }
+
+    server::apply_isolation(id, &isolation).await
+}
@@ -1,13 +1,15 @@
-use std::{fs, sync::OnceLock, time::{Duration, SystemTime}};
+use std::{fs, sync::{OnceLock, RwLock}, time::SystemTime};
 
 use josekit::{jwe::{self, alg::rsaes::{RsaesJweDecrypter, RsaesJweEncrypter}, JweHeader}, jwk::alg::rsa::RsaKeyPair, jwt::{self, JwtPayload, JwtPayloadValidator}, Map, Value};
-use packet::Packet;
+use packet::{envelope::{self, Envelope}, subprotocol::Encoding, Packet};
 use tracing::info;
 
 use crate::config::{self, Config};
 
 static DECRYPTER: OnceLock<RsaesJweDecrypter> = OnceLock::new();
 static ENCRYPTER: OnceLock<RsaesJweEncrypter> = OnceLock::new();
+/// The encoding negotiated with the subprotocol of the current connection
+static ENCODING: RwLock<Encoding> = RwLock::new(Encoding::Json);
 
 fn decrypter() -> Result<&'static RsaesJweDecrypter, String> {
     DECRYPTER.get().ok_or("decrypter not initialized".to_string())
@@ -45,35 +47,64 @@ fn make_encrypter(config: &Config) -> Result<RsaesJweEncrypter, String> {
     }
 }
 
-/// Encrypt a packet
+/// Sets the encoding outgoing packets are serialized in, after a connection negotiated it.
+pub fn set_encoding(encoding: Encoding) {
+    *ENCODING.write().unwrap_or_else(|e| e.into_inner()) = encoding;
+}
+
+/// Encrypt a packet, serialized in the encoding of the current connection
 pub fn encrypt_packet(packet: Packet) -> Result<String, String> {
     let mut header = JweHeader::new();
-    header.set_token_type("JWT");
     header.set_algorithm("RSA-OAEP");
     header.set_content_encryption("A256GCM");
 
-    let mut payload = JwtPayload::new();
-    payload.set_claim("p", Some(serde_json::to_value(packet).map_err(|_| "Packet should be serializable")?)).map_err(|_| "Could not set payload claim")?;
-    payload.set_issuer("aesterisk/daemon");
-    payload.set_issued_at(&SystemTime::now());
-    payload.set_expires_at(&SystemTime::now().checked_add(Duration::from_secs(60)).ok_or("Duration overflow")?);
+    match *ENCODING.read().unwrap_or_else(|e| e.into_inner()) {
+        Encoding::Json => {
+            header.set_token_type("JWT");
+
+            let mut payload = JwtPayload::new();
+            payload.set_claim("p", Some(serde_json::to_value(packet).map_err(|_| "Packet should be serializable")?)).map_err(|_| "Could not set payload claim")?;
+            payload.set_issuer("aesterisk/daemon");
+            payload.set_issued_at(&SystemTime::now());
+            payload.set_expires_at(&SystemTime::now().checked_add(envelope::LIFETIME).ok_or("Duration overflow")?);
+
+            Ok(jwt::encode_with_encrypter(&payload, &header, encrypter()?).map_err(|_| "Could not encrypt packet")?)
+        },
+        Encoding::MessagePack => {
+            header.set_content_type(envelope::CONTENT_TYPE);
 
-    Ok(jwt::encode_with_encrypter(&payload, &header, encrypter()?).map_err(|_| "Could not encrypt packet")?)
+            let envelope = Envelope::new(packet, "aesterisk/daemon")?;
+
+            Ok(jwe::serialize_compact(&envelope.to_bytes()?, &header, encrypter()?).map_err(|_| "Could not encrypt packet")?)
+        },
+    }
 }
 
-/// Decrypt a packet
+/// Decrypt a packet, decoded according to the content type of its JWE header
 pub async fn decrypt_packet(msg: &str) -> Result<Packet, String> {
-    let (payload, _) = jwt::decode_with_decrypter(msg, decrypter()?).map_err(|_| "Could not decrypt message")?;
+    let (plaintext, header) = jwe::deserialize_compact(msg, decrypter()?).map_err(|_| "Could not decrypt message")?;
+    let issuers = &config::get()?.server.issuers;
+
+    if header.content_type() == Some(envelope::CONTENT_TYPE) {
+        let envelope = Envelope::from_bytes(&plaintext)?;
+        envelope.validate(issuers).map_err(|e| format!("Invalid token: {}", e))?;
+
+        return Ok(envelope.packet);
+    }
+
+    let payload = serde_json::from_slice::<Map<String, Value>>(&plaintext).ok().and_then(|payload| JwtPayload::from_map(payload).ok()).ok_or("Could not parse message")?;
 
     let mut validator = JwtPayloadValidator::new();
-    validator.set_issuer("aesterisk/server");
     validator.set_base_time(SystemTime::now());
-    validator.set_min_issued_time(SystemTime::now() - Duration::from_secs(60));
+    validator.set_min_issued_time(SystemTime::now() - envelope::LIFETIME);
     validator.set_max_issued_time(SystemTime::now());
 
-    match validator.validate(&payload) {
-        Ok(()) => (),
-        Err(e) => return Err(format!("Invalid token: {}", e)),
+    validator.validate(&payload).map_err(|e| format!("Invalid token: {}", e))?;
+
+    match payload.issuer() {
+        Some(issuer) if issuers.iter().any(|accepted| accepted == issuer) => (),
+        Some(issuer) => return Err(format!("Invalid token: issuer {} is not accepted", issuer)),
+        None => return Err("Invalid token: missing issuer".to_string()),
     }
 
     let payload: Map<String, Value> = payload.into();
@@ -101,3 +132,25 @@ pub fn init() -> Result<(), String> {
 
     Ok(())
 }
+
+/// Sets up the encrypter with a generated key of the server, returning the decrypter of the server
+/// for reading the packets the daemon sent.
+#[cfg(test)]
+pub fn test_server() -> &'static RsaesJweDecrypter {
+    static SERVER: OnceLock<RsaesJweDecrypter> = OnceLock::new();
+
+    SERVER.get_or_init(|| {
+        let key = RsaKeyPair::generate(2048).expect("could not create keys");
+        ENCRYPTER.set(jwe::RSA_OAEP.encrypter_from_pem(key.to_pem_public_key()).expect("could not create encrypter")).expect("encrypter already initialized");
+        jwe::RSA_OAEP.decrypter_from_pem(key.to_pem_private_key()).expect("could not create decrypter")
+    })
+}
+
+/// Decrypts a packet the daemon sent, see `test_server`.
+#[cfg(test)]
+pub fn decrypt_sent(msg: &str) -> Packet {
+    let (plaintext, _) = jwe::deserialize_compact(msg, test_server()).expect("could not decrypt message");
+    let mut payload = serde_json::from_slice::<Map<String, Value>>(&plaintext).expect("could not parse message");
+
+    Packet::from_value(payload.remove("p").expect("no payload found in packet")).expect("could not parse packet")
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+
+use lazy_static::lazy_static;
+use packet::{events::EventType, features::Feature, flow::Window};
+use tokio::sync::Mutex;
+use tracing::debug;
+
+use crate::FEATURES;
+
+/// The window of a streamed event type, and the events left out since the last one was sent
+#[derive(Default)]
+struct Stream {
+    window: Window,
+    skipped: u32,
+}
+
+lazy_static! {
+    /// Windows of the streamed event types, granted by the server, see `packet::flow`
+    static ref STREAMS: Mutex<HashMap<EventType, Stream>> = Mutex::new(HashMap::new());
+}
+
+/// Resets all windows to their initial credits, as every connection starts with fresh windows.
+pub async fn reset() {
+    STREAMS.lock().await.clear();
+}
+
+/// Adds credits granted by the server to the window of a streamed event type.
+pub async fn grant(event: EventType, credits: u32) {
+    STREAMS.lock().await.entry(event).or_default().window.grant(credits);
+}
+
+/// Takes a credit for sending a streamed event, returning the amount of events left out since the
+/// last one was sent, or `None` if the event has to be left out as well. Always succeeds if flow
+/// control wasn't negotiated.
+pub async fn take(event: EventType) -> Option<u32> {
+    if !FEATURES.read().await.has(Feature::FlowControl) {
+        return Some(0);
+    }
+
+    let mut streams = STREAMS.lock().await;
+    let stream = streams.entry(event).or_default();
+
+    if stream.window.take() {
+        Some(std::mem::take(&mut stream.skipped))
+    } else {
+        if stream.skipped == 0 {
+            debug!("Window of {:?} is exhausted, leaving out events until the server grants more credits", event);
+        }
+
+        stream.skipped = stream.skipped.saturating_add(1);
+        None
+    }
+}
+
+/// Like `take`, but for events that are sent even if there are no credits left, e.g. the error
+/// ending a build.
+pub async fn force(event: EventType) -> u32 {
+    let mut streams = STREAMS.lock().await;
+    let stream = streams.entry(event).or_default();
+
+    stream.window.take();
+    std::mem::take(&mut stream.skipped)
+}
@@ -0,0 +1,124 @@
+use std::{collections::{HashMap, VecDeque}, sync::Arc, time::{SystemTime, UNIX_EPOCH}};
+
+use lazy_static::lazy_static;
+use packet::events::UsageSample;
+use tokio::sync::Mutex;
+use tracing::warn;
+
+use crate::config;
+
+/// How long samples are kept, in seconds
+const RETENTION: u64 = 24 * 60 * 60;
+/// Length of the interval each sample covers, in seconds
+const RESOLUTION: u64 = 60;
+
+#[derive(Default)]
+struct History {
+    samples: VecDeque<UsageSample>,
+    /// Start of the interval currently being accumulated
+    interval: u64,
+    cpu: f64,
+    memory: f64,
+    count: u32,
+}
+
+lazy_static! {
+    static ref HISTORIES: Arc<Mutex<HashMap<u32, History>>> = Arc::new(Mutex::new(HashMap::new()));
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+fn path(id: u32) -> Result<String, String> {
+    Ok(format!("{}/usage/{}.json", config::get()?.storage.data_folder, id))
+}
+
+async fn load(id: u32) -> History {
+    let samples = match path(id) {
+        Ok(path) => tokio::fs::read_to_string(path).await.ok().and_then(|contents| serde_json::from_str::<VecDeque<UsageSample>>(&contents).ok()),
+        Err(_) => None,
+    }.unwrap_or_default();
+
+    History {
+        samples,
+        ..Default::default()
+    }
+}
+
+async fn save(id: u32, samples: &VecDeque<UsageSample>) -> Result<(), String> {
+    let path = path(id)?;
+
+    if let Some(folder) = std::path::Path::new(&path).parent() {
+        tokio::fs::create_dir_all(folder).await.map_err(|e| format!("could not create usage folder: {}", e))?;
+    }
+
+    tokio::fs::write(path, serde_json::to_string(samples).map_err(|e| format!("could not serialize usage history: {}", e))?).await.map_err(|e| format!("could not write usage history: {}", e))
+}
+
+/// Loads the history of a server from the data folder if it isn't in memory yet, without holding
+/// the lock while reading.
+async fn load_missing(id: u32) -> Option<History> {
+    if HISTORIES.lock().await.contains_key(&id) {
+        return None;
+    }
+
+    Some(load(id).await)
+}
+
+/// Records a CPU and memory measurement of a server. Measurements are averaged per minute, and
+/// every finished minute is written to the data folder.
+pub async fn record(id: u32, cpu: f64, memory: f64) {
+    let now = now();
+    let interval = now - now % RESOLUTION;
+
+    let loaded = load_missing(id).await;
+
+    let finished = {
+        let mut histories = HISTORIES.lock().await;
+        let history = histories.entry(id).or_insert_with(|| loaded.unwrap_or_default());
+
+        let finished = if history.count > 0 && history.interval != interval {
+            history.samples.push_back(UsageSample {
+                timestamp: history.interval,
+                cpu: history.cpu / history.count as f64,
+                memory: history.memory / history.count as f64,
+            });
+
+            while history.samples.front().is_some_and(|sample| sample.timestamp + RETENTION < now) {
+                history.samples.pop_front();
+            }
+
+            history.cpu = 0.0;
+            history.memory = 0.0;
+            history.count = 0;
+
+            Some(history.samples.clone())
+        } else {
+            None
+        };
+
+        history.interval = interval;
+        history.cpu += cpu;
+        history.memory += memory;
+        history.count += 1;
+
+        finished
+    };
+
+    // written without holding the lock, samples are only recorded by the status service of the
+    // server, so writes of the same server don't overlap
+    if let Some(samples) = finished && let Err(e) = save(id, &samples).await {
+        warn!("Could not save usage history of server {}: {}", id, e);
+    }
+}
+
+/// Returns the samples of a server within the given range (inclusive), oldest first.
+pub async fn range(id: u32, from: u64, to: u64) -> Vec<UsageSample> {
+    let loaded = load_missing(id).await;
+
+    let mut histories = HISTORIES.lock().await;
+    let history = histories.entry(id).or_insert_with(|| loaded.unwrap_or_default());
+
+    history.samples.iter().filter(|sample| sample.timestamp >= from && sample.timestamp <= to).cloned().collect()
+}
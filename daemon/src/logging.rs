@@ -1,8 +1,9 @@
 use std::{io, sync::Mutex};
 
-use tracing::{subscriber::DefaultGuard, Level};
+use packet::server_daemon::config::LogLevel;
+use tracing::{level_filters::LevelFilter, subscriber::DefaultGuard, Level};
 use tracing_appender::{non_blocking::WorkerGuard, rolling::Rotation};
-use tracing_subscriber::{fmt::writer::MakeWriterExt, layer::SubscriberExt, Layer};
+use tracing_subscriber::{fmt::writer::MakeWriterExt, layer::SubscriberExt, reload, Layer, Registry};
 
 use crate::config;
 
@@ -10,9 +11,11 @@ static FILE_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
 static STDERR_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
 static STDOUT_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
 static SUBSCRIBER_GUARD: Mutex<Option<DefaultGuard>> = Mutex::new(None);
+static LEVEL_HANDLE: Mutex<Option<reload::Handle<LevelFilter, Registry>>> = Mutex::new(None);
 
 /// Initialize the logging system. The configuration must be loaded before calling this function.
-pub fn init() {
+/// If `quiet`, only warnings and errors are logged to the console.
+pub fn init(quiet: bool) {
     let config = config::get().expect("config is not initialized");
 
     let logs_rotation = tracing_appender::rolling::Builder::new().filename_suffix("daemon.aesterisk.log").rotation(Rotation::DAILY).build(&config.logging.folder).expect("could not initialize file logger");
@@ -24,27 +27,58 @@ pub fn init() {
     STDERR_GUARD.lock().expect("stderr_guard poisoned").replace(logs_stderr_guard);
     let (logs_stdout, logs_stdout_guard) = tracing_appender::non_blocking(io::stdout());
     STDOUT_GUARD.lock().expect("stdout_guard poisoned").replace(logs_stdout_guard);
-    let logs_stdio_layer = tracing_subscriber::fmt::layer().with_writer(logs_stderr.with_max_level(Level::WARN).or_else(logs_stdout.with_max_level(Level::DEBUG))).with_ansi(true).boxed();
+    let logs_stdio_layer = tracing_subscriber::fmt::layer().with_writer(logs_stderr.with_max_level(Level::WARN).or_else(logs_stdout.with_max_level(stdout_level(quiet)))).with_ansi(true).boxed();
+
+    // lets the server limit the level at runtime, see `set_level`
+    let (level_layer, level_handle) = reload::Layer::new(LevelFilter::TRACE);
+    LEVEL_HANDLE.lock().expect("level_handle poisoned").replace(level_handle);
 
     drop(SUBSCRIBER_GUARD.lock().expect("subscriber_guard poisoned").take()); // skipcq: RS-E1021
 
-    let subscriber = tracing_subscriber::registry().with(logs_file_layer).with(logs_stdio_layer);
+    let subscriber = tracing_subscriber::registry().with(level_layer).with(logs_file_layer).with(logs_stdio_layer);
     tracing::subscriber::set_global_default(subscriber).expect("could not set global default subscriber");
 }
 
 /// Initialize the logging system before the configuration is loaded. Useful for errors during
 /// config parsing.
-pub fn pre_init() {
+pub fn pre_init(quiet: bool) {
     let (logs_stderr, logs_stderr_guard) = tracing_appender::non_blocking(io::stderr());
     STDERR_GUARD.lock().expect("stderr_guard poisoned").replace(logs_stderr_guard);
     let (logs_stdout, logs_stdout_guard) = tracing_appender::non_blocking(io::stdout());
     STDOUT_GUARD.lock().expect("stdout_guard poisoned").replace(logs_stdout_guard);
 
-    let layer = tracing_subscriber::fmt::layer().with_writer(logs_stderr.with_max_level(Level::WARN).or_else(logs_stdout.with_max_level(Level::DEBUG))).with_ansi(true).boxed();
+    let layer = tracing_subscriber::fmt::layer().with_writer(logs_stderr.with_max_level(Level::WARN).or_else(logs_stdout.with_max_level(stdout_level(quiet)))).with_ansi(true).boxed();
     let subscriber = tracing_subscriber::registry().with(layer);
     SUBSCRIBER_GUARD.lock().expect("subscriber_guard poisoned").replace(tracing::subscriber::set_default(subscriber));
 }
 
+/// Limits the most verbose level logged, on top of the levels of the file and the console. `None`
+/// removes the limit.
+pub fn set_level(level: Option<LogLevel>) -> Result<(), String> {
+    let filter = match level {
+        Some(LogLevel::Error) => LevelFilter::ERROR,
+        Some(LogLevel::Warn) => LevelFilter::WARN,
+        Some(LogLevel::Info) => LevelFilter::INFO,
+        Some(LogLevel::Debug) => LevelFilter::DEBUG,
+        Some(LogLevel::Trace) | None => LevelFilter::TRACE,
+    };
+
+    match LEVEL_HANDLE.lock().expect("level_handle poisoned").as_ref() {
+        Some(handle) => handle.reload(filter).map_err(|e| format!("could not set log level: {}", e)),
+        None => Ok(()),
+    }
+}
+
+/// Returns the most verbose level logged to stdout. Warnings and errors are logged to stderr, so
+/// nothing is logged to stdout if `quiet`.
+fn stdout_level(quiet: bool) -> Level {
+    if quiet {
+        Level::WARN
+    } else {
+        Level::DEBUG
+    }
+}
+
 /// Flush the logs before the program exits.
 pub fn flush() {
     drop(FILE_GUARD.lock().expect("file_guard poisoned").take()); // skipcq: RS-E1021
@@ -1,22 +1,36 @@
-use std::{process, sync::Arc};
+use std::{io::{self, IsTerminal}, process, sync::Arc};
 
-use clap::Parser;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
 use futures_channel::mpsc;
 use futures_util::future::join_all;
 use lazy_static::lazy_static;
-use packet::events::EventType;
+use packet::{events::EventType, features::Features};
 use tokio::{signal, sync::{Mutex, RwLock}};
 use tokio_tungstenite::tungstenite::Message;
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
-use uuid::Uuid;
 
+use config::Logo;
+
+#[cfg(feature = "chaos")]
+mod chaos;
 mod config;
 mod docker;
 mod encryption;
+mod flow;
+mod history;
 mod logging;
+mod maintenance;
 mod packets;
+mod remote_config;
+mod sequence;
 mod services;
+mod storage;
+mod sync_state;
+mod tasks;
+mod telemetry;
+mod terminal;
 
 type Rx = mpsc::UnboundedReceiver<Message>;
 type Tx = mpsc::UnboundedSender<Message>;
@@ -24,8 +38,17 @@ type Tx = mpsc::UnboundedSender<Message>;
 lazy_static! {
     static ref LISTENS: Arc<RwLock<Vec<EventType>>> = Arc::new(RwLock::new(Vec::new()));
     static ref SENDER: Arc<Mutex<Option<Tx>>> = Arc::new(Mutex::new(None));
+    /// Features negotiated with the server, empty until authenticated
+    static ref FEATURES: Arc<RwLock<Features>> = Arc::new(RwLock::new(Features::default()));
+    /// Server URL to use for the next connection instead of the configured one, set by the server
+    /// when it shuts down
+    static ref RECONNECT_TO: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
 }
 
+/// Held by tests that use the connection to the server, i.e. `SENDER` and `FEATURES`
+#[cfg(test)]
+static TEST_CONNECTION: Mutex<()> = Mutex::const_new(());
+
 #[repr(i32)]
 enum ExitCode {
     Success = 0,
@@ -35,6 +58,7 @@ enum ExitCode {
     SignalError = 4,
     DockerError = 5,
     ServiceError = 6,
+    IoError = 7,
 }
 
 impl From<ExitCode> for i32 {
@@ -76,8 +100,8 @@ pub struct Cli {
     #[clap(short = 'p', long)]
     daemon_private_key: Option<String>,
 
-    #[clap(short = 'd', long)]
-    daemon_data_folder: Option<String>,
+    #[clap(short = 'd', long, alias = "daemon-data-folder")]
+    storage_data_folder: Option<String>,
 
     #[clap(short = 's', long)]
     server_url: Option<String>,
@@ -87,29 +111,125 @@ pub struct Cli {
 
     #[clap(short = 'l', long)]
     logging_folder: Option<String>,
+
+    /// Only check the configuration, and exit
+    #[clap(long)]
+    validate_config: bool,
+
+    /// Don't print the logo, and only log warnings and errors to the console
+    #[clap(short = 'q', long, global = true)]
+    quiet: bool,
+
+    /// Format of results, like the one of --validate-config. JSON implies --quiet
+    #[clap(short = 'o', long, value_enum, default_value_t = Output::Text, global = true)]
+    output: Output,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+/// Format of results printed to stdout
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Output {
+    /// Human-readable log lines
+    Text,
+    /// A single JSON object, for provisioning scripts
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Prints a completion script for the given shell
+    Completions {
+        shell: Shell,
+    },
+    /// Prints the man page
+    Man,
+}
+
+/// Returns the command line interface definition, as it should appear in generated files.
+fn cli_command() -> clap::Command {
+    Cli::command()
+        .name(env!("CARGO_BIN_NAME"))
+        .about("Aesterisk Daemon")
+}
+
+/// Runs a subcommand, which prints generated files instead of starting the daemon.
+fn run_command(command: Command) -> io::Result<()> {
+    match command {
+        Command::Completions { shell } => {
+            clap_complete::generate(shell, &mut cli_command(), env!("CARGO_BIN_NAME"), &mut io::stdout());
+            Ok(())
+        },
+        Command::Man => clap_mangen::Man::new(cli_command()).render(&mut io::stdout()),
+    }
 }
 
 #[tokio::main]
 async fn main() {
-    let cli = Cli::parse();
+    let mut cli = Cli::parse();
+
+    if let Some(command) = cli.command.take() {
+        if let Err(e) = run_command(command) {
+            eprintln!("Could not write output: {}", e);
+            process::exit(ExitCode::IoError.into());
+        }
 
-    println!("{}\n", AESTERISK_LOGO);
+        process::exit(ExitCode::Success.into());
+    }
 
     let mut exit_code = ExitCode::Success;
 
-    logging::pre_init();
+    // machine-readable results are printed to stdout, which would be mixed up with logs otherwise
+    let output = cli.output;
+    let quiet = cli.quiet || output == Output::Json;
+
+    logging::pre_init(quiet);
+
+    let validate_config = cli.validate_config;
 
     let config = match config::init("config.toml", cli) {
         Ok(config) => config,
         Err(e) => {
-            error!("Configuration error, please check your config file: {}", e);
+            if validate_config && output == Output::Json {
+                println!("{}", serde_json::json!({ "valid": false, "error": e }));
+            } else {
+                error!("Configuration error, please check your config file: {}", e);
+            }
+
             exit(ExitCode::ConfigError)
         }
     };
 
-    logging::init();
+    let print_logo = match config.branding.logo {
+        _ if quiet => false,
+        Logo::Always => true,
+        Logo::Tty => io::stdout().is_terminal(),
+        Logo::Never => false,
+    };
+
+    if print_logo {
+        println!("{}\n", AESTERISK_LOGO);
+    }
+
+    if validate_config {
+        match output {
+            Output::Text => info!("Configuration is valid"),
+            Output::Json => println!("{}", serde_json::json!({ "valid": true })),
+        }
+
+        exit(ExitCode::Success);
+    }
+
+    logging::init(quiet);
 
-    info!("Starting Aesterisk Daemon v{}", env!("CARGO_PKG_VERSION"));
+    info!("Starting {} v{}", config.branding.product_name, env!("CARGO_PKG_VERSION"));
+
+    // takes the sockets passed by socket activation, before any tasks read the environment
+    packet::activation::init();
+
+    #[cfg(feature = "chaos")]
+    warn!("Built with the chaos feature, faults are injected as configured in [chaos]");
 
     match encryption::init() {
         Ok(()) => (),
@@ -124,11 +244,6 @@ async fn main() {
         exit(ExitCode::ConfigError)
     }
 
-    if Uuid::parse_str(&config.daemon.uuid).is_err() {
-        error!("Daemon ID is incorrectly set! Please check your config file.");
-        exit(ExitCode::ConfigError)
-    }
-
     match docker::init() {
         Ok(()) => info!("Docker connection established"),
         Err(e) => {
@@ -137,6 +252,38 @@ async fn main() {
         }
     }
 
+    match docker::rootless::detect().await {
+        Ok(info) if info.rootless => {
+            warn!("Docker is running in rootless mode, host ports below {} can't be mapped", info.min_host_port);
+            if !info.server_stats {
+                warn!("Docker is running on cgroup v1 in rootless mode, CPU and memory stats of servers are unavailable");
+            }
+        },
+        Ok(_) => (),
+        Err(e) => warn!("Could not detect whether Docker is running in rootless mode: {}", e),
+    }
+
+    match storage::check_reserve() {
+        Ok(available) => info!("{} MiB of disk space available for servers", available),
+        Err(e) => warn!("{}", e),
+    }
+
+    match sync_state::load().await {
+        Ok(state) if !state.applied && !state.hash.is_empty() => warn!("The last sync ({}) was not fully applied, waiting for the server to resync", state.hash),
+        Ok(_) => (),
+        Err(e) => warn!("Could not read sync state: {}", e),
+    }
+
+    match tasks::load().await {
+        Ok(resumed) if resumed > 0 => info!("Resuming {} unfinished tasks", resumed),
+        Ok(_) => (),
+        Err(e) => warn!("Could not read tasks: {}", e),
+    }
+
+    if let Err(e) = remote_config::load().await {
+        warn!("Could not load remote config: {}", e);
+    }
+
     let token = CancellationToken::new();
 
     let handles = match services::start(token.clone()) {
@@ -173,6 +320,12 @@ async fn main() {
 }
 
 fn exit(code: ExitCode) -> ! {
+    if !matches!(code, ExitCode::Success)
+        && let Ok(config) = config::get()
+        && let Some(support_url) = config.branding.support_url.as_ref() {
+        error!("For help, see {}", support_url);
+    }
+
     logging::flush();
     process::exit(code.into())
 }
@@ -0,0 +1,61 @@
+use std::{collections::HashMap, sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use lazy_static::lazy_static;
+use packet::maintenance::MaintenanceWindow;
+use tokio::sync::RwLock;
+use tracing::{debug, warn};
+
+#[derive(Default)]
+struct Windows {
+    node: Vec<MaintenanceWindow>,
+    servers: HashMap<u32, Vec<MaintenanceWindow>>,
+}
+
+lazy_static! {
+    static ref WINDOWS: Arc<RwLock<Windows>> = Arc::new(RwLock::new(Windows::default()));
+}
+
+/// How often `defer` checks whether a maintenance window has ended.
+const POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Replaces the maintenance windows with the ones from a sync.
+pub async fn set(node: Vec<MaintenanceWindow>, servers: HashMap<u32, Vec<MaintenanceWindow>>) {
+    for window in node.iter().chain(servers.values().flatten()) {
+        if !window.is_valid() {
+            warn!("Ignoring maintenance window with invalid cron expression '{}'", window.cron);
+        }
+    }
+
+    *WINDOWS.write().await = Windows {
+        node,
+        servers,
+    };
+}
+
+/// Returns whether the node (if `server` is `None`) or a server is in a maintenance window. Servers
+/// are also in maintenance during the windows of the node.
+pub async fn in_maintenance(server: Option<u32>) -> bool {
+    let now = now();
+    let windows = WINDOWS.read().await;
+
+    windows.node.iter().any(|window| window.is_active(now))
+        || server.and_then(|server| windows.servers.get(&server)).is_some_and(|windows| windows.iter().any(|window| window.is_active(now)))
+}
+
+/// Runs `task` in the background once the node or server is not in a maintenance window anymore.
+/// Automated restarts and updates should be deferred with this when `in_maintenance` is true.
+pub fn defer<F: Future<Output = ()> + Send + 'static>(server: Option<u32>, task: F) {
+    debug!("Deferring until maintenance of {:?} has ended", server);
+
+    tokio::spawn(async move {
+        while in_maintenance(server).await {
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+
+        task.await;
+    });
+}
@@ -1,31 +1,207 @@
-use packet::{server_daemon::{auth_response::SDAuthResponsePacket, handshake_request::SDHandshakeRequestPacket, sync::SDSyncPacket, listen::SDListenPacket}, ID};
-use tracing::debug;
+use std::sync::{atomic::{AtomicU64, Ordering}, Arc};
 
-use crate::encryption;
+use lazy_static::lazy_static;
+use packet::{chunk::Reassembler, ID, Packet};
+use tokio::sync::{Mutex, MutexGuard, Semaphore, SemaphorePermit};
+use tracing::{debug, instrument, warn};
 
+use crate::{encryption, telemetry};
+
+pub mod ack;
 mod auth;
+mod build_context;
+mod cancel_task;
+mod catalog;
+mod command;
+mod config;
 mod handshake;
 mod listen;
+mod ping;
+mod query_logs;
+mod query_stats;
+mod query_tasks;
+mod query_top;
+mod query_usage;
+mod reconnect_to;
+mod server_metadata;
 mod sync;
+mod terminal_close;
+mod terminal_input;
+mod terminal_open;
+mod window_update;
+
+/// Most packets handled at the same time, see `Concurrency`
+const MAX_CONCURRENT: usize = 8;
+
+/// Identifies the handling of a packet in the logs, also of packets without a request id.
+static NEXT_CORRELATION_ID: AtomicU64 = AtomicU64::new(1);
+
+lazy_static! {
+    static ref PERMITS: Semaphore = Semaphore::new(MAX_CONCURRENT);
+    static ref EXCLUSIVE: Mutex<()> = Mutex::new(());
+}
+
+/// How a packet is handled relative to other packets.
+enum Concurrency {
+    /// Handled right away, as other packets may wait for it (e.g. a sync waiting for a build
+    /// context) or it has to take effect immediately (e.g. cancelling a task)
+    Immediate,
+    /// Handled one at a time, in addition to the limit of `Parallel`, as it changes the containers
+    /// or the state of the daemon
+    Exclusive,
+    /// Handled in parallel with other packets, up to `MAX_CONCURRENT` at a time
+    Parallel,
+}
+
+impl Concurrency {
+    fn of(id: &ID) -> Self {
+        match id {
+            ID::SDAuthResponse
+            | ID::SDHandshakeRequest
+            | ID::SDListen
+            | ID::SDBuildContext
+            | ID::SDReconnectTo
+            | ID::SDCancelTask
+            | ID::SDWindowUpdate
+            | ID::SDTerminalInput
+            | ID::SDTerminalClose
+            | ID::Ack
+            | ID::Nack
+            | ID::Ping => Self::Immediate,
+            ID::SDSync | ID::SDServerMetadata | ID::SDCommand | ID::SDConfig => Self::Exclusive,
+            _ => Self::Parallel,
+        }
+    }
+}
+
+/// Waits until a packet may be handled, returning the guards that have to be held while handling it.
+async fn acquire(id: &ID) -> Result<(Option<MutexGuard<'static, ()>>, Option<SemaphorePermit<'static>>), String> {
+    let concurrency = Concurrency::of(id);
+
+    if matches!(concurrency, Concurrency::Immediate) {
+        return Ok((None, None));
+    }
+
+    let waiting = telemetry::PACKETS_WAITING.fetch_add(1, Ordering::Relaxed) + 1;
+    telemetry::PACKETS_WAITING_PEAK.fetch_max(waiting, Ordering::Relaxed);
+
+    let exclusive = match concurrency {
+        Concurrency::Exclusive => Some(EXCLUSIVE.lock().await),
+        _ => None,
+    };
+
+    let permit = PERMITS.acquire().await.map_err(|e| format!("Could not acquire packet permit: {}", e));
+
+    telemetry::PACKETS_WAITING.fetch_sub(1, Ordering::Relaxed);
+
+    Ok((exclusive, Some(permit?)))
+}
+
+/// Decrypts, parses and handles an incoming packet. Chunked packets are reassembled with the
+/// reassembler of the connection they were received on.
+pub async fn handle(msg: String, reassembler: Arc<Mutex<Reassembler>>) -> Result<(), String> {
+    let mut packet = encryption::decrypt_packet(&msg).await?;
+
+    if packet.id == ID::Chunk {
+        let chunk = packet.payload()?;
+
+        packet = match reassembler.lock().await.push(chunk)? {
+            Some(packet) => packet,
+            None => return Ok(()),
+        };
+    }
+
+    // acknowledgements carry the request id of the packet they answer, and are not answered
+    let request_id = packet.request_id.filter(|_| !matches!(packet.id, ID::Ack | ID::Nack));
 
-/// Decrypts, parses and handles an incoming packet
-pub async fn handle(msg: String) -> Result<(), String> {
-    let packet = encryption::decrypt_packet(&msg).await?;
+    let res = dispatch(packet).await;
 
+    if let Some(request_id) = request_id
+        && let Err(e) = ack::reply(request_id, &res).await {
+        warn!("Could not acknowledge packet: {}", e);
+    }
+
+    res
+}
+
+/// Handles a decrypted and reassembled packet. The span carries a correlation id to tell the log
+/// lines of concurrently handled packets apart, and the request id, which matches the logs of the
+/// server for packets it awaits an acknowledgement for.
+#[instrument("packet", skip_all, fields(id = ?packet.id, correlation_id = NEXT_CORRELATION_ID.fetch_add(1, Ordering::Relaxed), request_id = ?packet.request_id))]
+async fn dispatch(packet: Packet) -> Result<(), String> {
     debug!("Received Packet {:?}", packet.id);
 
+    #[cfg(feature = "chaos")]
+    crate::chaos::delay().await;
+
+    let _guards = acquire(&packet.id).await?;
+
     match packet.id {
         ID::SDAuthResponse => {
-            auth::handle(SDAuthResponsePacket::parse(packet).ok_or("Could not parse SDAuthResponsePacket")?).await
+            auth::handle(packet.payload()?).await
         },
         ID::SDHandshakeRequest => {
-            handshake::handle(SDHandshakeRequestPacket::parse(packet).ok_or("Could not parse SDHandshakeRequestPacket")?).await
+            handshake::handle(packet.payload()?).await
         },
         ID::SDListen => {
-            listen::handle(SDListenPacket::parse(packet).ok_or("Could not parse SDListenPacket")?).await
+            listen::handle(packet.payload()?).await
         },
         ID::SDSync => {
-            sync::handle(SDSyncPacket::parse(packet).ok_or("Could not parse SDSyncPacket")?).await
+            sync::handle(packet.payload()?).await
+        },
+        ID::SDServerMetadata => {
+            server_metadata::handle(packet.payload()?).await
+        },
+        ID::SDBuildContext => {
+            build_context::handle(packet.payload()?).await
+        },
+        ID::SDReconnectTo => {
+            reconnect_to::handle(packet.payload()?).await
+        },
+        ID::SDCatalog => {
+            catalog::handle(packet.payload()?).await
+        },
+        ID::SDConfig => {
+            config::handle(packet.payload()?).await
+        },
+        ID::SDQueryLogs => {
+            query_logs::handle(packet.payload()?).await
+        },
+        ID::SDQueryStats => {
+            query_stats::handle(packet.payload()?).await
+        },
+        ID::SDQueryTop => {
+            query_top::handle(packet.payload()?).await
+        },
+        ID::SDQueryTasks => {
+            query_tasks::handle(packet.payload()?).await
+        },
+        ID::SDCancelTask => {
+            cancel_task::handle(packet.payload()?).await
+        },
+        ID::SDQueryUsage => {
+            query_usage::handle(packet.payload()?).await
+        },
+        ID::SDWindowUpdate => {
+            window_update::handle(packet.payload()?).await
+        },
+        ID::SDTerminalOpen => {
+            terminal_open::handle(packet.payload()?).await
+        },
+        ID::SDTerminalInput => {
+            terminal_input::handle(packet.payload()?).await
+        },
+        ID::SDTerminalClose => {
+            terminal_close::handle(packet.payload()?).await
+        },
+        ID::SDCommand => {
+            command::handle(packet.payload()?).await
+        },
+        ID::Ack | ID::Nack => {
+            ack::handle(packet).await
+        },
+        ID::Ping => {
+            ping::handle(packet.payload()?).await
         },
         _ => {
             Err(format!("Should not receive [A*|D*|SA] packet: {:?}", packet.id))
@@ -0,0 +1,133 @@
+use std::{collections::HashMap, sync::atomic::{AtomicU64, Ordering}, time::Duration};
+
+use lazy_static::lazy_static;
+use packet::{ack, features::Feature, Packet};
+use tokio::sync::{oneshot, Mutex};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{encryption, FEATURES, SENDER};
+
+/// How long to wait for the server to acknowledge a packet
+const ACK_TIMEOUT: Duration = Duration::from_secs(30);
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    /// Packets sent with a request id, waiting for the server to acknowledge them
+    static ref PENDING: Mutex<HashMap<u64, oneshot::Sender<Result<(), String>>>> = Mutex::new(HashMap::new());
+}
+
+async fn send(packet: Packet) -> Result<(), String> {
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(packet)?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))
+}
+
+/// Fails all packets waiting for an acknowledgement, as the connection they were sent on is gone.
+pub async fn reset() {
+    PENDING.lock().await.clear();
+}
+
+/// Sends a packet to the server and waits until the server acknowledges it, returning the reason
+/// if the server could not handle it. If the server doesn't support acknowledgements, the packet is
+/// only sent.
+pub async fn send_acked(packet: Packet) -> Result<(), String> {
+    if !FEATURES.read().await.has(Feature::Acks) {
+        return send(packet).await;
+    }
+
+    let request_id = NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed);
+    let (tx, rx) = oneshot::channel();
+    PENDING.lock().await.insert(request_id, tx);
+
+    if let Err(e) = send(packet.with_request_id(request_id)).await {
+        PENDING.lock().await.remove(&request_id);
+        return Err(e);
+    }
+
+    match tokio::time::timeout(ACK_TIMEOUT, rx).await {
+        Ok(Ok(res)) => res.map_err(|e| format!("Server could not handle packet: {}", e)),
+        Ok(Err(_)) => Err("Disconnected before the server acknowledged packet".to_string()),
+        Err(_) => {
+            PENDING.lock().await.remove(&request_id);
+            Err(format!("Server did not acknowledge packet within {}s", ACK_TIMEOUT.as_secs()))
+        },
+    }
+}
+
+/// Answers a packet of the server that was sent with a request id.
+pub async fn reply(request_id: u64, res: &Result<(), String>) -> Result<(), String> {
+    send(ack::reply(request_id, res)?).await
+}
+
+/// Handles the AckPacket and NackPacket
+pub async fn handle(packet: Packet) -> Result<(), String> {
+    let (request_id, res) = ack::outcome(packet)?;
+    let tx = PENDING.lock().await.remove(&request_id).ok_or("Unknown request id")?;
+
+    // the packet might have stopped waiting for the acknowledgement just now
+    let _ = tx.send(res);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_channel::mpsc;
+    use futures_util::StreamExt;
+    use packet::{features::Features, heartbeat::PongPacket};
+
+    use crate::Rx;
+
+    use super::*;
+
+    fn pong() -> Packet {
+        PongPacket {
+            nonce: 1,
+        }.to_packet().expect("could not build packet")
+    }
+
+    async fn next_request_id(rx: &mut Rx) -> Option<u64> {
+        let msg = rx.next().await.expect("could not get message").into_text().expect("message is not text");
+        encryption::decrypt_sent(&msg).request_id
+    }
+
+    #[tokio::test]
+    async fn acknowledgements_resolve_packets() {
+        let _connection = crate::TEST_CONNECTION.lock().await;
+        encryption::test_server();
+
+        let (tx, mut rx) = mpsc::unbounded();
+        *SENDER.lock().await = Some(tx);
+        *FEATURES.write().await = Features::from([Feature::Acks]);
+
+        let sent = tokio::spawn(send_acked(pong()));
+        let request_id = next_request_id(&mut rx).await.expect("packet has no request id");
+
+        handle(ack::reply(request_id, &Ok(())).expect("could not build ack")).await.expect("could not handle ack");
+        sent.await.expect("sending panicked").expect("packet was not acknowledged");
+
+        // every packet is only acknowledged once
+        assert!(handle(ack::reply(request_id, &Ok(())).expect("could not build ack")).await.is_err());
+
+        let sent = tokio::spawn(send_acked(pong()));
+        let request_id = next_request_id(&mut rx).await.expect("packet has no request id");
+
+        handle(ack::reply(request_id, &Err("unknown nonce".to_string())).expect("could not build nack")).await.expect("could not handle nack");
+        let e = sent.await.expect("sending panicked").expect_err("packet was acknowledged");
+        assert!(e.contains("unknown nonce"));
+
+        // packets sent on a connection that is gone are failed
+        let sent = tokio::spawn(send_acked(pong()));
+        next_request_id(&mut rx).await.expect("packet has no request id");
+
+        reset().await;
+        assert!(sent.await.expect("sending panicked").is_err());
+
+        *FEATURES.write().await = Features::default();
+        send_acked(pong()).await.expect("could not send packet");
+        assert_eq!(next_request_id(&mut rx).await, None);
+    }
+}
@@ -1,13 +1,26 @@
+use std::sync::atomic::Ordering;
+
 use packet::server_daemon::auth_response::SDAuthResponsePacket;
-use tracing::info;
+use tracing::{debug, info};
+
+use crate::{remote_config, services::buffer, telemetry, FEATURES};
 
 /// Handles the SDAuthResponsePacket
 pub async fn handle(auth_response_packet: SDAuthResponsePacket) -> Result<(), String> {
     if !auth_response_packet.success {
-        return Err("Unsuccessful auth response".to_string());
+        return Err(match auth_response_packet.error {
+            Some(error) => format!("Unsuccessful auth response: {}", error),
+            None => "Unsuccessful auth response".to_string(),
+        });
     }
 
     info!("Authenticated");
+    let features = remote_config::features().await.negotiate(&auth_response_packet.features);
+    debug!("Negotiated features: {:?}", features);
+
+    *FEATURES.write().await = features;
+    telemetry::AUTHENTICATED.store(true, Ordering::Relaxed);
+    buffer::replay().await;
 
     Ok(())
 }
@@ -0,0 +1,12 @@
+use packet::server_daemon::build_context::SDBuildContextPacket;
+use tracing::{debug, instrument};
+
+use crate::docker;
+
+/// Handles the SDBuildContextPacket
+#[instrument("build_context", skip_all, fields(hash = %build_context_packet.hash))]
+pub async fn handle(build_context_packet: SDBuildContextPacket) -> Result<(), String> {
+    debug!("Received build context {}", build_context_packet.hash);
+
+    docker::build::receive(build_context_packet.hash, build_context_packet.data).await
+}
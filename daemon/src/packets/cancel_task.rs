@@ -0,0 +1,12 @@
+use packet::server_daemon::cancel_task::SDCancelTaskPacket;
+use tracing::{info, instrument};
+
+use crate::tasks;
+
+/// Handles the SDCancelTaskPacket
+#[instrument("cancel_task", skip_all, fields(task = cancel_task_packet.task))]
+pub async fn handle(cancel_task_packet: SDCancelTaskPacket) -> Result<(), String> {
+    info!("Cancelling task {}", cancel_task_packet.task);
+
+    tasks::cancel(cancel_task_packet.task).await
+}
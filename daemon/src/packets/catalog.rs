@@ -0,0 +1,13 @@
+use packet::server_daemon::catalog::SDCatalogPacket;
+use tracing::debug;
+
+use crate::services::prepull;
+
+/// Handles the SDCatalogPacket
+pub async fn handle(catalog_packet: SDCatalogPacket) -> Result<(), String> {
+    debug!("Received catalog of {} images", catalog_packet.images.len());
+
+    prepull::set_catalog(catalog_packet.images, catalog_packet.windows).await;
+
+    Ok(())
+}
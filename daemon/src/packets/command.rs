@@ -0,0 +1,38 @@
+use packet::{command::ServerCommand, daemon_server::command_result::DSCommandResultPacket, server_daemon::command::SDCommandPacket};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{info, instrument};
+
+use crate::{docker, encryption, SENDER};
+
+async fn run(server: u32, command: ServerCommand) -> Result<(), String> {
+    match command {
+        ServerCommand::Start => docker::server::start_server(server).await,
+        ServerCommand::Stop => docker::server::halt_server(server).await,
+        ServerCommand::Restart => docker::server::restart_server_now(server).await,
+        ServerCommand::Kill => docker::server::kill_server(server).await,
+    }
+}
+
+/// Handles the SDCommandPacket
+#[instrument("command", skip_all, fields(request = command_packet.request, server = command_packet.server, command = ?command_packet.command))]
+pub async fn handle(command_packet: SDCommandPacket) -> Result<(), String> {
+    info!("Running {:?} on server {}", command_packet.command, command_packet.server);
+
+    // failures are reported back, so the web client can show them
+    let error = run(command_packet.server, command_packet.command).await.err();
+
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(
+                DSCommandResultPacket {
+                    request: command_packet.request,
+                    server: command_packet.server,
+                    command: command_packet.command,
+                    error,
+                }.to_packet()?,
+            )?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))?;
+
+    Ok(())
+}
@@ -0,0 +1,15 @@
+use packet::server_daemon::config::SDConfigPacket;
+use tracing::{debug, info};
+
+use crate::remote_config;
+
+/// Handles the SDConfigPacket
+pub async fn handle(config_packet: SDConfigPacket) -> Result<(), String> {
+    debug!("Received remote config: {:?}", config_packet);
+
+    remote_config::apply(config_packet).await?;
+
+    info!("Applied remote config");
+
+    Ok(())
+}
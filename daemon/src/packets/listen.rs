@@ -1,10 +1,20 @@
-use packet::server_daemon::listen::SDListenPacket;
+use packet::{events::EventType, server_daemon::listen::SDListenPacket};
 
-use crate::LISTENS;
+use crate::{services::{server_logs, server_status}, tasks, LISTENS};
 
 /// Handles the SDListenPacket
 pub async fn handle(listen_packet: SDListenPacket) -> Result<(), String> {
+    let listened = LISTENS.read().await.contains(&EventType::Task);
+    let listens = listen_packet.events.contains(&EventType::Task);
+
     *LISTENS.write().await = listen_packet.events;
+    server_logs::follow(listen_packet.log_servers).await;
+    server_status::filter(listen_packet.status_servers).await;
+
+    // changes are missed while nobody listens, e.g. while disconnected
+    if listens && !listened {
+        tasks::report_all().await;
+    }
 
     Ok(())
 }
@@ -0,0 +1,19 @@
+use packet::heartbeat::{PingPacket, PongPacket};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{encryption, SENDER};
+
+/// Handles the PingPacket
+pub async fn handle(ping_packet: PingPacket) -> Result<(), String> {
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(
+                PongPacket {
+                    nonce: ping_packet.nonce,
+                }.to_packet()?,
+            )?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))?;
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use packet::{daemon_server::query_logs_response::DSQueryLogsResponsePacket, server_daemon::query_logs::SDQueryLogsPacket};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::instrument;
+
+use crate::{encryption, services::server_logs, SENDER};
+
+/// Handles the SDQueryLogsPacket
+#[instrument("query_logs", skip_all, fields(request = query_logs_packet.request, server = query_logs_packet.server))]
+pub async fn handle(query_logs_packet: SDQueryLogsPacket) -> Result<(), String> {
+    let lines = server_logs::recent(query_logs_packet.server, query_logs_packet.lines as usize).await;
+
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(
+                DSQueryLogsResponsePacket {
+                    request: query_logs_packet.request,
+                    server: query_logs_packet.server,
+                    lines,
+                }.to_packet()?,
+            )?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))?;
+
+    Ok(())
+}
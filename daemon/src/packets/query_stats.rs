@@ -0,0 +1,33 @@
+use packet::{daemon_server::query_stats_response::DSQueryStatsResponsePacket, events::EventData, server_daemon::query_stats::SDQueryStatsPacket};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::instrument;
+
+use crate::{encryption, services::{node_status, server_status}, SENDER};
+
+/// Handles the SDQueryStatsPacket
+#[instrument("query_stats", skip_all, fields(request = query_stats_packet.request, server = query_stats_packet.server))]
+pub async fn handle(query_stats_packet: SDQueryStatsPacket) -> Result<(), String> {
+    // failures are reported back, as the web client is waiting for an answer
+    let (stats, error) = match query_stats_packet.server {
+        Some(server) => match server_status::sample(server).await {
+            Ok(status) => (Some(EventData::ServerStatus(status)), None),
+            Err(e) => (None, Some(e)),
+        },
+        None => (Some(EventData::NodeStatus(node_status::sample().await)), None),
+    };
+
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(
+                DSQueryStatsResponsePacket {
+                    request: query_stats_packet.request,
+                    server: query_stats_packet.server,
+                    stats,
+                    error,
+                }.to_packet()?,
+            )?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))?;
+
+    Ok(())
+}
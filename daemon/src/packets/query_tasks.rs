@@ -0,0 +1,22 @@
+use packet::{daemon_server::query_tasks_response::DSQueryTasksResponsePacket, server_daemon::query_tasks::SDQueryTasksPacket};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::instrument;
+
+use crate::{encryption, tasks, SENDER};
+
+/// Handles the SDQueryTasksPacket
+#[instrument("query_tasks", skip_all, fields(request = query_tasks_packet.request))]
+pub async fn handle(query_tasks_packet: SDQueryTasksPacket) -> Result<(), String> {
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(
+                DSQueryTasksResponsePacket {
+                    request: query_tasks_packet.request,
+                    tasks: tasks::list().await,
+                }.to_packet()?,
+            )?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))?;
+
+    Ok(())
+}
@@ -0,0 +1,30 @@
+use packet::{daemon_server::query_top_response::DSQueryTopResponsePacket, server_daemon::query_top::SDQueryTopPacket};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::instrument;
+
+use crate::{docker, encryption, SENDER};
+
+/// Handles the SDQueryTopPacket
+#[instrument("query_top", skip_all, fields(request = query_top_packet.request, server = query_top_packet.server))]
+pub async fn handle(query_top_packet: SDQueryTopPacket) -> Result<(), String> {
+    // failures are reported back, as the web client is waiting for an answer
+    let (table, error) = match docker::server::top(query_top_packet.server).await {
+        Ok(table) => (Some(table), None),
+        Err(e) => (None, Some(e)),
+    };
+
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(
+                DSQueryTopResponsePacket {
+                    request: query_top_packet.request,
+                    server: query_top_packet.server,
+                    table,
+                    error,
+                }.to_packet()?,
+            )?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))?;
+
+    Ok(())
+}
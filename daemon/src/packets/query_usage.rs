@@ -0,0 +1,25 @@
+use packet::{daemon_server::query_usage_response::DSQueryUsageResponsePacket, server_daemon::query_usage::SDQueryUsagePacket};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::instrument;
+
+use crate::{encryption, history, SENDER};
+
+/// Handles the SDQueryUsagePacket
+#[instrument("query_usage", skip_all, fields(request = query_usage_packet.request, server = query_usage_packet.server))]
+pub async fn handle(query_usage_packet: SDQueryUsagePacket) -> Result<(), String> {
+    let samples = history::range(query_usage_packet.server, query_usage_packet.from, query_usage_packet.to).await;
+
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(
+                DSQueryUsageResponsePacket {
+                    request: query_usage_packet.request,
+                    server: query_usage_packet.server,
+                    samples,
+                }.to_packet()?,
+            )?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))?;
+
+    Ok(())
+}
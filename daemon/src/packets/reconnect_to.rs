@@ -0,0 +1,15 @@
+use packet::server_daemon::reconnect_to::SDReconnectToPacket;
+use tracing::info;
+
+use crate::{config, RECONNECT_TO};
+
+/// Handles the SDReconnectToPacket
+pub async fn handle(reconnect_to_packet: SDReconnectToPacket) -> Result<(), String> {
+    config::check_url(&reconnect_to_packet.url, &["ws", "wss"])?;
+
+    info!("Server is shutting down, reconnecting to {}", reconnect_to_packet.url);
+
+    RECONNECT_TO.lock().await.replace(reconnect_to_packet.url);
+
+    Ok(())
+}
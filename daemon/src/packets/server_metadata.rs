@@ -0,0 +1,13 @@
+use packet::server_daemon::server_metadata::SDServerMetadataPacket;
+use tracing::{debug, instrument};
+
+use crate::{docker, sync_state};
+
+/// Handles the SDServerMetadataPacket
+#[instrument("server_metadata", skip_all, fields(server = server_metadata_packet.metadata.server))]
+pub async fn handle(server_metadata_packet: SDServerMetadataPacket) -> Result<(), String> {
+    debug!("Received metadata of server {}", server_metadata_packet.metadata.server);
+
+    sync_state::set_metadata(server_metadata_packet.metadata.clone()).await?;
+    docker::update::apply_metadata(server_metadata_packet.metadata).await
+}
@@ -1,35 +1,256 @@
-use packet::server_daemon::sync::SDSyncPacket;
-use tracing::{debug, error, info};
+use std::collections::{HashMap, HashSet};
 
-use crate::{docker, services::{self, server_status}};
+use packet::{daemon_server::{error::DSErrorPacket, sync_progress::DSSyncProgressPacket, sync_result::{DSSyncResultPacket, SyncResultServer}}, events::SyncStep, features::Feature, server_daemon::sync::{SDSyncPacket, UpdateStrategy}, server_web::error::ErrorCode, Packet};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, info, instrument, warn};
 
+use crate::{docker, encryption, maintenance, services::{server_logs, server_status}, sync_state, tasks::{self, Job}, FEATURES, SENDER};
+
+async fn send(packet: Packet) -> Result<(), String> {
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(packet)?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))
+}
+
+async fn request_full_sync(request: Option<u64>) -> Result<bool, String> {
+    send(DSSyncResultPacket {
+        servers: Vec::new(),
+        resync: true,
+        request,
+    }.to_packet()?).await?;
+
+    Ok(false)
+}
+
+/// Reports a step of applying a sync to the web client waiting for it, if any. Progress is only
+/// informational, so failing to report it is logged and doesn't stop the sync.
+async fn progress(request: Option<u64>, step: SyncStep) {
+    let Some(request) = request else {
+        return;
+    };
+
+    let res = async {
+        send(DSSyncProgressPacket {
+            request,
+            step,
+        }.to_packet()?).await
+    }.await;
+
+    if let Err(e) = res {
+        warn!("Could not report progress of sync {}: {}", request, e);
+    }
+}
+
+/// Reports a failed sync to the server, so web clients learn about it even if they aren't waiting
+/// for its progress.
+async fn report_error(request: Option<u64>, error: &str) -> Result<(), String> {
+    if !FEATURES.read().await.has(Feature::DaemonErrors) {
+        return Ok(());
+    }
+
+    send(DSErrorPacket {
+        code: ErrorCode::SyncFailed,
+        message: error.to_string(),
+        request,
+    }.to_packet()?).await
+}
+
+#[instrument("sync", skip_all, fields(request = sync_packet.request, hash = %sync_packet.hash))]
 pub async fn handle(sync_packet: SDSyncPacket) -> Result<(), String> {
-    info!("Syncing data from server with Docker");
+    let request = sync_packet.request;
+
+    match apply_sync(sync_packet, request).await {
+        Ok(true) => {
+            progress(request, SyncStep::Done).await;
+            Ok(())
+        },
+        // the full sync reports the progress instead
+        Ok(false) => Ok(()),
+        Err(e) => {
+            // the failed step is what the web clients waiting for the sync act on
+            if let Err(report) = report_error(request, &e).await {
+                warn!("Could not report failed sync: {}", report);
+            }
+
+            progress(request, SyncStep::Failed {
+                error: e.clone(),
+            }).await;
+
+            Err(e)
+        }
+    }
+}
+
+/// Applies a sync, returning `false` if a full sync was requested from the server instead.
+async fn apply_sync(sync_packet: SDSyncPacket, request: Option<u64>) -> Result<bool, String> {
+    // changed servers that replace their running container themselves, instead of being removed
+    let mut updates = HashSet::new();
+
+    let mut spec = if sync_packet.is_delta() {
+        info!("Syncing changes from server with Docker");
+
+        let state = sync_state::load().await?;
+
+        let mut spec = match state.spec {
+            Some(spec) if state.applied => spec,
+            _ => {
+                warn!("No applied sync to apply delta sync to, requesting a full sync");
+                return request_full_sync(request).await;
+            }
+        };
+
+        let stale = sync_packet.servers.iter().map(|server| server.id).chain(sync_packet.removed_servers.iter().copied()).collect::<Vec<_>>();
+        let removed_networks = sync_packet.removed_networks.clone();
+        updates.extend(sync_packet.servers.iter().filter(|server| server.update == UpdateStrategy::BlueGreen).map(|server| server.id));
+
+        if let Err(e) = spec.apply(sync_packet) {
+            warn!("Could not apply delta sync ({}), requesting a full sync", e);
+            return request_full_sync(request).await;
+        }
+
+        sync_state::begin(&spec.hash).await?;
+
+        debug!("Removing changed and removed servers...");
+        for id in stale {
+            if !updates.contains(&id) && docker::server::server_exists(id).await? {
+                debug!("  Removing server {}", id);
+                progress(request, SyncStep::RemovingServer {
+                    server: id,
+                }).await;
+                docker::server::stop_server(id).await?;
+                docker::firewall::clear(id).await?;
+            }
+        }
+
+        debug!("Removing networks...");
+        for id in removed_networks {
+            if docker::network::network_exists(id).await? {
+                debug!("  Removing network {}", id);
+                progress(request, SyncStep::RemovingNetwork {
+                    network: id,
+                }).await;
+                docker::network::delete_network(id).await?;
+            }
+        }
+
+        spec
+    } else {
+        info!("Syncing data from server with Docker");
+
+        if !sync_packet.hash.is_empty() {
+            let hash = sync_packet.spec_hash()?;
+
+            if hash != sync_packet.hash {
+                return Err(format!("Sync hash mismatch (expected {}, got {}), refusing to apply", sync_packet.hash, hash));
+            }
+        }
+
+        sync_state::begin(&sync_packet.hash).await?;
+
+        sync_packet
+    };
 
     debug!("Syncing networks...");
-    for nw in sync_packet.networks {
+    for nw in spec.networks.iter() {
         debug!("  Checking network {}", nw.id);
         if !docker::network::network_exists(nw.id).await? {
             debug!("    Creating network {}", nw.id);
+            progress(request, SyncStep::CreatingNetwork {
+                network: nw.id,
+            }).await;
             let id = docker::network::create_network(nw.id, nw.subnet).await?;
             debug!("    Created network ({})", id);
         }
     }
 
-    debug!("Stopping running stats services...");
+    maintenance::set(spec.maintenance.clone(), spec.servers.iter().map(|server| (server.id, server.maintenance.clone())).collect::<HashMap<_, _>>()).await;
+
+    debug!("Stopping running stats and logs services...");
     server_status::stop_services().await?;
+    server_logs::stop_services().await?;
+    server_logs::retain(&spec.servers.iter().map(|server| server.id).collect()).await;
+
+    let mut results = Vec::new();
+
+    let (order, cyclic) = spec.start_order();
+
+    // the other servers are still synced, the cycle has to be fixed on the server
+    if !cyclic.is_empty() {
+        let error = format!("Servers {:?} have cyclic dependencies and were not synced", cyclic);
+        warn!("{}", error);
+
+        if let Err(e) = report_error(request, &error).await {
+            warn!("Could not report cyclic dependencies: {}", e);
+        }
+    }
 
     debug!("Syncing servers...");
-    for server in sync_packet.servers {
+    for server in order {
         let id = server.id;
 
         debug!("  Checking server {}", id);
-        if !docker::server::server_exists(id).await? {
-            debug!("    Creating server {}", id);
-            let docker_id = docker::server::create_server(server).await?;
+        let exists = docker::server::server_exists(id).await?;
+        if !exists || updates.contains(&id) {
+            let healthy = server.depends_on.iter().filter(|dependency| dependency.healthy).map(|dependency| dependency.server).collect::<Vec<_>>();
+            if !healthy.is_empty() {
+                debug!("    Waiting for dependencies of server {}", id);
+                docker::server::wait_for_dependencies(&healthy).await?;
+            }
+
+            let image = format!("{}:{}", server.tag.image, server.tag.docker_tag);
+
+            if let Some(build) = &server.tag.build {
+                debug!("    Building image {}", image);
+                progress(request, SyncStep::BuildingImage {
+                    server: id,
+                    image,
+                }).await;
+                tasks::run(Job::Build {
+                    server: id,
+                    image: server.tag.image.clone(),
+                    tag: server.tag.docker_tag.clone(),
+                    build: build.clone(),
+                }).await?;
+            } else {
+                debug!("    Pulling image {}", image);
+                progress(request, SyncStep::PullingImage {
+                    server: id,
+                    image,
+                }).await;
+                tasks::run(Job::Pull {
+                    image: server.tag.image.clone(),
+                    tag: server.tag.docker_tag.clone(),
+                }).await?;
+            }
+
+            let (docker_id, ports) = if exists {
+                debug!("    Updating server {}", id);
+                progress(request, SyncStep::UpdatingServer {
+                    server: id,
+                }).await;
+                docker::update::blue_green(server.clone(), spec.server_metadata(id)).await?
+            } else {
+                debug!("    Creating server {}", id);
+                progress(request, SyncStep::CreatingServer {
+                    server: id,
+                }).await;
+                docker::server::create_server(server.clone(), spec.server_metadata(id)).await?
+            };
             debug!("    Created server ({})", docker_id);
+
+            if !ports.is_empty() {
+                results.push(SyncResultServer {
+                    id,
+                    ports,
+                });
+            }
         }
 
+        debug!("  Applying isolation policy");
+        docker::server::apply_isolation(id, &server.isolation).await?;
+
         debug!("  Starting stats service");
         tokio::spawn(async move {
             match server_status::start(id).await {
@@ -39,7 +260,37 @@ pub async fn handle(sync_packet: SDSyncPacket) -> Result<(), String> {
 
             debug!("Stats service for server {} has stopped", id);
         });
+
+        debug!("  Starting logs service");
+        tokio::spawn(async move {
+            match server_logs::start(id).await {
+                Ok(_) => (),
+                Err(e) => error!("Error in server logs service: {}", e),
+            };
+
+            debug!("Logs service for server {} has stopped", id);
+        });
+    }
+
+    // the server stores the assigned ports as well, so the next delta sync is based on the same spec
+    for result in results.iter() {
+        spec.assign_ports(result.id, &result.ports)?;
+    }
+
+    sync_state::finish(spec).await?;
+
+    if !results.is_empty() {
+        debug!("Reporting assigned ports to server");
+
+        // the sync was applied either way, so it doesn't fail if the ports could not be stored
+        if let Err(e) = super::ack::send_acked(DSSyncResultPacket {
+            servers: results,
+            resync: false,
+            request: None,
+        }.to_packet()?).await {
+            warn!("Could not report assigned ports: {}", e);
+        }
     }
 
-    Ok(())
+    Ok(true)
 }
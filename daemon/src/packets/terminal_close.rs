@@ -0,0 +1,14 @@
+use packet::server_daemon::terminal_close::SDTerminalClosePacket;
+use tracing::{info, instrument};
+
+use crate::terminal;
+
+/// Handles the SDTerminalClosePacket
+#[instrument("terminal_close", skip_all, fields(session = %terminal_close_packet.session))]
+pub async fn handle(terminal_close_packet: SDTerminalClosePacket) -> Result<(), String> {
+    info!("Closing terminal {}", terminal_close_packet.session);
+
+    terminal::close(terminal_close_packet.session).await;
+
+    Ok(())
+}
@@ -0,0 +1,8 @@
+use packet::server_daemon::terminal_input::SDTerminalInputPacket;
+
+use crate::terminal;
+
+/// Handles the SDTerminalInputPacket
+pub async fn handle(terminal_input_packet: SDTerminalInputPacket) -> Result<(), String> {
+    terminal::input(terminal_input_packet).await
+}
@@ -0,0 +1,10 @@
+use packet::server_daemon::terminal_open::SDTerminalOpenPacket;
+use tracing::instrument;
+
+use crate::terminal;
+
+/// Handles the SDTerminalOpenPacket
+#[instrument("terminal_open", skip_all, fields(session = %terminal_open_packet.session, server = terminal_open_packet.server))]
+pub async fn handle(terminal_open_packet: SDTerminalOpenPacket) -> Result<(), String> {
+    terminal::open(terminal_open_packet.session, terminal_open_packet.server, terminal_open_packet.size, terminal_open_packet.command).await
+}
@@ -0,0 +1,17 @@
+use packet::server_daemon::window_update::SDWindowUpdatePacket;
+use tracing::debug;
+
+use crate::flow;
+
+/// Handles the SDWindowUpdatePacket
+pub async fn handle(window_update_packet: SDWindowUpdatePacket) -> Result<(), String> {
+    debug!("Server granted {} credits for {:?}", window_update_packet.credits, window_update_packet.event);
+
+    if !window_update_packet.event.is_streamed() {
+        return Err(format!("{:?} events are not subject to flow control", window_update_packet.event));
+    }
+
+    flow::grant(window_update_packet.event, window_update_packet.credits).await;
+
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::{sync::Arc, time::Duration};
+
+use lazy_static::lazy_static;
+use packet::{features::Features, server_daemon::config::SDConfigPacket};
+use tokio::sync::RwLock;
+
+use crate::{config, logging, FEATURES};
+
+/// Seconds between node status events, unless set by the server
+const DEFAULT_NODE_STATUS_INTERVAL: u64 = 1;
+
+lazy_static! {
+    /// Settings last sent by the server
+    static ref REMOTE_CONFIG: Arc<RwLock<SDConfigPacket>> = Arc::new(RwLock::new(SDConfigPacket::default()));
+}
+
+fn path() -> Result<String, String> {
+    Ok(format!("{}/remote_config.json", config::get()?.storage.data_folder))
+}
+
+/// Reads the settings last sent by the server from the data folder, and applies them.
+pub async fn load() -> Result<(), String> {
+    let remote_config = match tokio::fs::read_to_string(path()?).await {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("could not parse remote config: {}", e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("could not read remote config: {}", e)),
+    };
+
+    set(remote_config).await
+}
+
+async fn save(remote_config: &SDConfigPacket) -> Result<(), String> {
+    let path = path()?;
+
+    if let Some(folder) = std::path::Path::new(&path).parent() {
+        tokio::fs::create_dir_all(folder).await.map_err(|e| format!("could not create data folder: {}", e))?;
+    }
+
+    tokio::fs::write(path, serde_json::to_string(remote_config).map_err(|e| format!("could not serialize remote config: {}", e))?).await.map_err(|e| format!("could not write remote config: {}", e))
+}
+
+async fn set(remote_config: SDConfigPacket) -> Result<(), String> {
+    logging::set_level(remote_config.log_level)?;
+
+    // features that were disabled are enabled again by the next negotiation
+    let features = FEATURES.read().await.without(&remote_config.disabled_features);
+    *FEATURES.write().await = features;
+
+    *REMOTE_CONFIG.write().await = remote_config;
+
+    Ok(())
+}
+
+/// Applies settings sent by the server, and persists them so they apply after a restart.
+pub async fn apply(remote_config: SDConfigPacket) -> Result<(), String> {
+    if *REMOTE_CONFIG.read().await == remote_config {
+        return Ok(());
+    }
+
+    save(&remote_config).await?;
+    set(remote_config).await
+}
+
+/// Returns the interval between node status events.
+pub async fn node_status_interval() -> Duration {
+    Duration::from_secs(REMOTE_CONFIG.read().await.node_status_interval.unwrap_or(DEFAULT_NODE_STATUS_INTERVAL).max(1))
+}
+
+/// Returns the minimum interval between status events of a server, or `None` if every stat Docker
+/// reports is sent.
+pub async fn server_status_interval() -> Option<Duration> {
+    REMOTE_CONFIG.read().await.server_status_interval.map(Duration::from_secs)
+}
+
+/// Returns the features offered to the server when authenticating.
+pub async fn features() -> Features {
+    Features::supported().without(&REMOTE_CONFIG.read().await.disabled_features)
+}
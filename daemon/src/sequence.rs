@@ -0,0 +1,91 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use packet::{daemon_server::event::DSEventPacket, events::EventData};
+use tokio::sync::Mutex;
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::{encryption, SENDER};
+
+lazy_static! {
+    /// Sequence number of the last event sent, see `DSEventPacket::seq`
+    static ref LAST_SEQ: Mutex<u64> = Mutex::new(0);
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Numbers the events sent from 1 again, must be called whenever the daemon connects to the server.
+pub async fn reset() {
+    *LAST_SEQ.lock().await = 0;
+}
+
+/// Sends an event to the server, stamped with the next sequence number of the daemon and the Unix
+/// timestamp it happened at, or the current time if it is `None`, in which case it is sent live
+/// rather than replayed. The sequence number is only used up if the event was sent, so the events
+/// the server receives are numbered without gaps.
+pub async fn send(data: EventData, timestamp: Option<u64>) -> Result<(), String> {
+    // held until the event was sent, so events are sent in the order they are numbered
+    let mut last = LAST_SEQ.lock().await;
+
+    let msg = encryption::encrypt_packet(DSEventPacket {
+        data,
+        seq: Some(*last + 1),
+        timestamp: Some(timestamp.unwrap_or_else(now)),
+        replayed: timestamp.is_some(),
+    }.to_packet()?)?;
+
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(msg)
+    ).map_err(|e| format!("Could not send packet: {}", e))?;
+
+    *last += 1;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_channel::mpsc;
+    use futures_util::StreamExt;
+    use packet::events::ServerLogEvent;
+
+    use super::*;
+
+    fn log() -> EventData {
+        EventData::ServerLog(ServerLogEvent {
+            server: 1,
+            lines: Vec::new(),
+            skipped: 0,
+        })
+    }
+
+    #[tokio::test]
+    async fn failed_sends_keep_sequence_number() {
+        let _connection = crate::TEST_CONNECTION.lock().await;
+        encryption::test_server();
+
+        *SENDER.lock().await = None;
+        let last = *LAST_SEQ.lock().await;
+
+        assert!(send(log(), None).await.is_err());
+        assert_eq!(*LAST_SEQ.lock().await, last);
+
+        let (tx, mut rx) = mpsc::unbounded();
+        *SENDER.lock().await = Some(tx);
+
+        send(log(), Some(10)).await.expect("could not send event");
+        send(log(), None).await.expect("could not send event");
+
+        let mut events = Vec::new();
+        for _ in 0..2 {
+            let msg = rx.next().await.expect("could not get message").into_text().expect("message is not text");
+            events.push(DSEventPacket::parse(encryption::decrypt_sent(&msg)).expect("could not parse event packet"));
+        }
+
+        assert_eq!(events[0].seq, Some(last + 1));
+        assert_eq!(events[0].timestamp, Some(10));
+        assert_eq!(events[1].seq, Some(last + 2));
+    }
+}
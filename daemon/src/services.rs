@@ -1,11 +1,23 @@
-use std::sync::OnceLock;
+use std::{future::Future, sync::OnceLock};
 
 use tokio::task::JoinHandle;
 use tokio_util::sync::CancellationToken;
 
+use crate::{config, telemetry};
+
+pub mod buffer;
 mod client;
-mod node_status;
+mod container_events;
+#[cfg(feature = "debug_endpoint")]
+mod debug;
+mod log_shipping;
+mod metrics;
+pub mod node_status;
+pub mod prepull;
+pub mod server_logs;
 pub mod server_status;
+mod task_worker;
+mod watchdog;
 
 static CANCELLATION_TOKEN: OnceLock<CancellationToken> = OnceLock::new();
 
@@ -13,13 +25,37 @@ pub fn get_cancellation_token() -> Option<CancellationToken> {
     CANCELLATION_TOKEN.get().cloned()
 }
 
+/// Runs a service, recording whether it is running for the metrics endpoint.
+async fn track(name: &'static str, service: impl Future<Output = Result<(), String>>) -> Result<(), String> {
+    telemetry::set_service_up(name, true);
+    let res = service.await;
+    telemetry::set_service_up(name, false);
+
+    res
+}
+
 /// Starts the services and returns their join handles.
 /// Should only be called **once**.
 pub fn start(token: CancellationToken) -> Result<Vec<JoinHandle<Result<(), String>>>, String> {
     CANCELLATION_TOKEN.set(token).map_err(|_| "cancellation token already set")?;
 
-    Ok(vec![
-        tokio::spawn(client::run(get_cancellation_token().ok_or("cancellation token should already be set")?)),
-        tokio::spawn(node_status::run(get_cancellation_token().ok_or("cancellation token should already be set")?)),
-    ])
+    let mut handles = vec![
+        tokio::spawn(track("client", client::run(get_cancellation_token().ok_or("cancellation token should already be set")?))),
+        tokio::spawn(track("node_status", node_status::run(get_cancellation_token().ok_or("cancellation token should already be set")?))),
+        tokio::spawn(track("container_events", container_events::run(get_cancellation_token().ok_or("cancellation token should already be set")?))),
+        tokio::spawn(track("prepull", prepull::run(get_cancellation_token().ok_or("cancellation token should already be set")?))),
+        tokio::spawn(track("log_shipping", log_shipping::run(get_cancellation_token().ok_or("cancellation token should already be set")?))),
+        tokio::spawn(track("task_worker", task_worker::run(get_cancellation_token().ok_or("cancellation token should already be set")?))),
+        tokio::spawn(track("telemetry", telemetry::run(get_cancellation_token().ok_or("cancellation token should already be set")?))),
+        tokio::spawn(track("watchdog", watchdog::run(get_cancellation_token().ok_or("cancellation token should already be set")?))),
+    ];
+
+    if config::get()?.metrics.enabled() {
+        handles.push(tokio::spawn(track("metrics", metrics::run(get_cancellation_token().ok_or("cancellation token should already be set")?))));
+    }
+
+    #[cfg(feature = "debug_endpoint")]
+    handles.push(tokio::spawn(track("debug", debug::run(get_cancellation_token().ok_or("cancellation token should already be set")?))));
+
+    Ok(handles)
 }
@@ -0,0 +1,196 @@
+use std::{collections::VecDeque, time::{SystemTime, UNIX_EPOCH}};
+
+use lazy_static::lazy_static;
+use packet::events::EventData;
+use tokio::sync::Mutex;
+use tracing::{info, warn};
+
+use crate::{config, sequence};
+
+/// Status events of the daemon, buffered while it isn't authenticated with the server.
+struct Buffer {
+    /// Whether the daemon is authenticated, in which case events are sent right away
+    online: bool,
+    /// Buffered events, oldest first, with the Unix timestamp they happened at
+    events: VecDeque<(u64, EventData)>,
+    /// Amount of events dropped since the daemon was last authenticated, as the buffer was full
+    dropped: u64,
+}
+
+lazy_static! {
+    static ref BUFFER: Mutex<Buffer> = Mutex::new(Buffer {
+        online: false,
+        events: VecDeque::new(),
+        dropped: 0,
+    });
+}
+
+impl Buffer {
+    /// Buffers an event, dropping the oldest events once `capacity` events are buffered.
+    fn push(&mut self, capacity: usize, timestamp: u64, data: EventData) {
+        if capacity == 0 {
+            return;
+        }
+
+        while self.events.len() >= capacity {
+            self.events.pop_front();
+            self.dropped += 1;
+        }
+
+        self.events.push_back((timestamp, data));
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Returns whether events are sent right away, rather than buffered.
+pub async fn online() -> bool {
+    BUFFER.lock().await.online
+}
+
+/// Sends an event to the server. While the daemon isn't authenticated, the event is buffered
+/// instead, dropping the oldest events once `server.offline_buffer` events are buffered. Events
+/// that can't be sent, as the connection was lost before the daemon noticed, are buffered as well.
+pub async fn send(data: EventData) -> Result<(), String> {
+    let timestamp = now();
+    let capacity = config::get()?.server.offline_buffer;
+
+    let mut buffer = BUFFER.lock().await;
+
+    if buffer.online {
+        // events are only sent once the buffered ones are replayed, see `replay`
+        drop(buffer);
+
+        let Err(e) = sequence::send(data.clone(), None).await else {
+            return Ok(());
+        };
+
+        buffer = BUFFER.lock().await;
+
+        // the daemon reconnected in the meantime, so the event isn't replayed anymore
+        if buffer.online {
+            return Err(e);
+        }
+    }
+
+    buffer.push(capacity, timestamp, data);
+
+    Ok(())
+}
+
+/// Replays the events buffered while the daemon wasn't authenticated, and sends events right away
+/// from then on. Replayed events are stamped with the time they happened at, and with sequence
+/// numbers as they are sent, so they stay in order with the events sent afterwards.
+pub async fn replay() {
+    let mut buffer = BUFFER.lock().await;
+    buffer.online = true;
+
+    if buffer.dropped > 0 {
+        warn!("Dropped {} events while disconnected, as more than server.offline_buffer events were buffered", buffer.dropped);
+        buffer.dropped = 0;
+    }
+
+    if buffer.events.is_empty() {
+        return;
+    }
+
+    info!("Replaying {} events buffered while disconnected", buffer.events.len());
+
+    for (timestamp, data) in buffer.events.drain(..) {
+        if let Err(e) = sequence::send(data, Some(timestamp)).await {
+            warn!("Could not replay buffered event: {}", e);
+        }
+    }
+}
+
+/// Buffers events from now on, as the daemon lost its connection to the server.
+pub async fn pause() {
+    BUFFER.lock().await.online = false;
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_channel::mpsc;
+    use futures_util::StreamExt;
+    use packet::{daemon_server::event::DSEventPacket, events::ServerLogEvent};
+
+    use super::*;
+    use crate::{encryption, SENDER};
+
+    fn log(server: u32) -> EventData {
+        EventData::ServerLog(ServerLogEvent {
+            server,
+            lines: Vec::new(),
+            skipped: 0,
+        })
+    }
+
+    fn server(event: &EventData) -> u32 {
+        match event {
+            EventData::ServerLog(log) => log.server,
+            _ => panic!("unexpected event {:?}", event),
+        }
+    }
+
+    fn buffer() -> Buffer {
+        Buffer {
+            online: false,
+            events: VecDeque::new(),
+            dropped: 0,
+        }
+    }
+
+    #[test]
+    fn oldest_events_dropped_at_capacity() {
+        let mut buffer = buffer();
+
+        for i in 0..5 {
+            buffer.push(3, i as u64, log(i));
+        }
+
+        assert_eq!(buffer.events.iter().map(|(timestamp, event)| (*timestamp, server(event))).collect::<Vec<_>>(), vec![(2, 2), (3, 3), (4, 4)]);
+        assert_eq!(buffer.dropped, 2);
+    }
+
+    #[test]
+    fn nothing_buffered_without_capacity() {
+        let mut buffer = buffer();
+        buffer.push(0, 0, log(0));
+
+        assert!(buffer.events.is_empty());
+        assert_eq!(buffer.dropped, 0);
+    }
+
+    #[tokio::test]
+    async fn buffered_events_replayed_in_order() {
+        let _connection = crate::TEST_CONNECTION.lock().await;
+        encryption::test_server();
+
+        let (tx, mut rx) = mpsc::unbounded();
+        *SENDER.lock().await = Some(tx);
+
+        {
+            let mut buffer = BUFFER.lock().await;
+            buffer.online = false;
+
+            for i in 0..3 {
+                buffer.push(10, 100 + i as u64, log(i));
+            }
+        }
+
+        replay().await;
+        assert!(online().await);
+
+        for i in 0..3 {
+            let msg = rx.next().await.expect("could not get message").into_text().expect("message is not text");
+            let event = DSEventPacket::parse(encryption::decrypt_sent(&msg)).expect("could not parse event packet");
+
+            assert_eq!((server(&event.data), event.timestamp, event.replayed), (i, Some(100 + i as u64), true));
+        }
+
+        pause().await;
+        *SENDER.lock().await = None;
+    }
+}
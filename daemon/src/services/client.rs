@@ -1,14 +1,20 @@
-use std::time::Duration;
+use std::{sync::{atomic::Ordering, Arc, Once}, time::Duration};
 
 use futures_channel::mpsc::unbounded;
 use futures_util::{future, pin_mut, FutureExt, StreamExt, TryStreamExt};
-use packet::daemon_server::auth::DSAuthPacket;
-use tokio::select;
-use tokio_tungstenite::tungstenite::{self, Message};
+use packet::{chunk::Reassembler, close::{CloseReason, Reconnect}, daemon_server::auth::DSAuthPacket, features::Features, subprotocol::{self, Subprotocol}};
+use tokio::{select, sync::Mutex};
+use tokio_tungstenite::{tungstenite::{self, client::IntoClientRequest, http::HeaderValue, Message}, Connector};
 use tokio_util::sync::CancellationToken;
 use tracing::{error, info, warn};
 
-use crate::{config, encryption, packets, Rx, LISTENS, SENDER};
+use crate::{config, encryption, flow, packets, remote_config, sequence, sync_state, telemetry, terminal, Rx, FEATURES, LISTENS, RECONNECT_TO, SENDER};
+
+use super::buffer;
+
+/// How long to wait before reconnecting, if the server closed the connection with a reason that
+/// calls for backing off.
+const BACK_OFF: Duration = Duration::from_secs(60);
 
 /// Runs the client service, connecting to the Aesterisk Server
 pub async fn run(token: CancellationToken) -> Result<(), String> {
@@ -24,12 +30,41 @@ pub async fn run(token: CancellationToken) -> Result<(), String> {
         SENDER.lock().await.replace(tx);
 
         *LISTENS.write().await = Vec::new();
+        *FEATURES.write().await = Features::default();
+        flow::reset().await;
+        sequence::reset().await;
+        packets::ack::reset().await;
+        terminal::close_all().await;
         select!(
             res = tokio::spawn(connect_to_server(rx)) => {
+                telemetry::CONNECTED.store(false, Ordering::Relaxed);
+                telemetry::AUTHENTICATED.store(false, Ordering::Relaxed);
+                buffer::pause().await;
+
                 match res {
-                    Ok(Ok(())) => {
+                    Ok(Ok(None)) => {
                         attempts = 1;
                     },
+                    Ok(Ok(Some(reason))) => {
+                        attempts = 1;
+
+                        match reason.reconnect() {
+                            Reconnect::Retry => warn!("Server closed the connection: {}", reason),
+                            Reconnect::BackOff => {
+                                warn!("Server closed the connection: {}, reconnecting in {} seconds", reason, BACK_OFF.as_secs());
+
+                                select!(
+                                    _ = tokio::time::sleep(BACK_OFF) => (),
+                                    _ = token.cancelled() => break,
+                                );
+                            },
+                            Reconnect::Stop => {
+                                SENDER.lock().await.take();
+
+                                return Err(format!("Server closed the connection: {}, not reconnecting until the daemon is restarted", reason));
+                            },
+                        }
+                    },
                     Ok(Err(e)) => if attempts <= 5 || attempts % 1800 == 0 {
                         error!("{}", e);
                     },
@@ -38,6 +73,8 @@ pub async fn run(token: CancellationToken) -> Result<(), String> {
                     },
                 }
 
+                telemetry::RECONNECTS.fetch_add(1, Ordering::Relaxed);
+
                 attempts += 1;
                 
                 // TODO: Implement exponential backoff
@@ -71,7 +108,7 @@ fn error_to_string(e: tungstenite::Error) -> String {
     match e {
         tungstenite::Error::Utf8 => "Error in UTF-8 encoding".to_string(),
         tungstenite::Error::Io(e) => format!("IO error ({})", e.kind()),
-        tungstenite::Error::Tls(_) => "TLS error".to_string(),
+        tungstenite::Error::Tls(e) => format!("TLS error ({})", e),
         tungstenite::Error::Url(_) => "Invalid URL".to_string(),
         tungstenite::Error::Http(_) => "HTTP error".to_string(),
         tungstenite::Error::HttpFormat(_) => "HTTP format error".to_string(),
@@ -84,12 +121,57 @@ fn error_to_string(e: tungstenite::Error) -> String {
     }
 }
 
-async fn connect_to_server(rx: Rx) -> Result<(), String> {
+/// Builds the TLS connector used for `wss://` servers, with the certificate verification options of
+/// `server`.
+fn tls_connector(config: &config::Server) -> Result<native_tls::TlsConnector, String> {
+    static INSECURE_WARNING: Once = Once::new();
+
+    let mut builder = native_tls::TlsConnector::builder();
+
+    if !config.ca_cert.is_empty() {
+        let pem = std::fs::read(&config.ca_cert).map_err(|e| format!("Could not read {}: {}", config.ca_cert, e))?;
+        builder.add_root_certificate(native_tls::Certificate::from_pem(&pem).map_err(|e| format!("Invalid certificate {}: {}", config.ca_cert, e))?);
+    }
+
+    if config.accept_invalid_certs || config.accept_invalid_hostnames {
+        INSECURE_WARNING.call_once(|| warn!("Server certificates are not fully verified, anyone could impersonate the server"));
+    }
+
+    builder.danger_accept_invalid_certs(config.accept_invalid_certs);
+    builder.danger_accept_invalid_hostnames(config.accept_invalid_hostnames);
+
+    builder.build().map_err(|e| format!("Could not configure TLS: {}", e))
+}
+
+/// Connects to the server and handles the connection until it ends. Returns why the server closed
+/// the connection, if it said so.
+async fn connect_to_server(rx: Rx) -> Result<Option<CloseReason>, String> {
     let config = config::get()?;
 
-    let (stream, _) = tokio_tungstenite::connect_async(&config.server.url).await.map_err(|e| format!("Could not connect to server: {}", error_to_string(e)))?;
+    // a redirect only applies to a single connection, afterwards the configured server is used again
+    let url = match RECONNECT_TO.lock().await.take() {
+        Some(url) => {
+            info!("Connecting to {} as requested by the previous server", url);
+            url
+        },
+        None => config.server.url.clone(),
+    };
+
+    let mut request = url.as_str().into_client_request().map_err(|e| format!("Invalid server URL: {}", error_to_string(e)))?;
+    request.headers_mut().insert(subprotocol::HEADER, HeaderValue::from_str(&Subprotocol::offer()).map_err(|_| "invalid subprotocol header")?);
+
+    let connector = Connector::NativeTls(tls_connector(&config.server)?);
+    let (stream, response) = tokio_tungstenite::connect_async_tls_with_config(request, None, false, Some(connector)).await.map_err(|e| format!("Could not connect to server: {}", error_to_string(e)))?;
+
+    // servers that don't support subprotocol negotiation yet use the legacy one
+    let protocol = match response.headers().get(subprotocol::HEADER) {
+        Some(selected) => selected.to_str().ok().and_then(Subprotocol::parse).ok_or("Server selected an unsupported subprotocol")?,
+        None => Subprotocol::LEGACY,
+    };
 
-    info!("Connected to server");
+    info!("Connected to server using {}", protocol);
+    encryption::set_encoding(protocol.encoding());
+    telemetry::CONNECTED.store(true, Ordering::Relaxed);
     let (write, read) = stream.split();
 
     info!("Authenticating...");
@@ -101,7 +183,16 @@ async fn connect_to_server(rx: Rx) -> Result<(), String> {
         }
     }));
 
-    let incoming = read.try_filter(|msg| future::ready(msg.is_text())).for_each(|msg| async {
+    let closed = std::sync::Mutex::new(None);
+    // transfers of a previous connection can't be completed anymore
+    let reassembler = Arc::new(Mutex::new(Reassembler::new()));
+
+    let incoming = read.inspect_ok(|msg| {
+        if let Message::Close(Some(frame)) = msg
+            && let Ok(mut closed) = closed.lock() {
+            *closed = CloseReason::from_code(frame.code.into());
+        }
+    }).try_filter(|msg| future::ready(msg.is_text() || msg.is_binary())).for_each(|msg| async {
         let msg = match msg {
             Ok(msg) => msg,
             Err(e) => {
@@ -110,15 +201,17 @@ async fn connect_to_server(rx: Rx) -> Result<(), String> {
             }
         };
 
-        let text = match msg.into_text() {
+        telemetry::PACKETS_RECEIVED.fetch_add(1, Ordering::Relaxed);
+
+        let text = match subprotocol::unframe(protocol, msg) {
             Ok(text) => text,
             Err(e) => {
-                error!("{}", error_to_string(e));
+                error!("Error reading message: {}", e);
                 return;
             }
         };
 
-        tokio::spawn(packets::handle(text).then(|res| match res {
+        tokio::spawn(packets::handle(text, reassembler.clone()).then(|res| match res {
             Ok(()) => future::ready(()),
             Err(e) => {
                 error!("Error handling packet: {}", e);
@@ -127,22 +220,35 @@ async fn connect_to_server(rx: Rx) -> Result<(), String> {
         }));
     });
 
-    let outgoing = rx.map(Ok).forward(write);
+    let outgoing = rx.map(|msg| {
+        telemetry::PACKETS_SENT.fetch_add(1, Ordering::Relaxed);
+        subprotocol::frame(protocol, msg)
+    }).map(Ok).forward(write);
 
     pin_mut!(incoming, outgoing);
+
+    #[cfg(feature = "chaos")]
+    future::select(future::select(incoming, outgoing), Box::pin(crate::chaos::drop_connection())).await;
+    #[cfg(not(feature = "chaos"))]
     future::select(incoming, outgoing).await;
 
-    Ok(())
+    Ok(closed.lock().ok().and_then(|mut closed| closed.take()))
 }
 
 async fn handle_connection() -> Result<(), String> {
     let config = config::get()?;
 
+    // lets the server send a delta sync if it still knows the spec we last applied
+    let sync_hash = sync_state::load().await.map(|state| state.applied_hash().to_string()).unwrap_or_default();
+
     SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
         Message::Text(
             encryption::encrypt_packet(
                 DSAuthPacket {
-                    daemon_uuid: config.daemon.uuid.clone()
+                    daemon_uuid: config.daemon.uuid.clone(),
+                    sync_hash,
+                    features: remote_config::features().await,
+                    version: env!("CARGO_PKG_VERSION").to_string(),
                 }.to_packet()?,
             )?
         )
@@ -0,0 +1,55 @@
+use std::{collections::HashMap, time::Duration};
+
+use bollard::system::EventsOptions;
+use futures_util::StreamExt;
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, warn};
+
+use crate::docker;
+
+use super::server_status;
+
+/// Runs the container events service, which drops cached container information whenever Docker
+/// reports a change to a server's container
+pub async fn run(token: CancellationToken) -> Result<(), String> {
+    loop {
+        select! {
+            _ = token.cancelled() => {
+                warn!("Stopping container events service");
+                return Ok(());
+            },
+            res = listen() => {
+                if let Err(e) = res {
+                    error!("Error in container events service: {}", e);
+                }
+
+                // events may have been missed while not listening
+                server_status::invalidate_all().await;
+                tokio::time::sleep(Duration::from_secs(5)).await;
+            }
+        }
+    }
+}
+
+async fn listen() -> Result<(), String> {
+    let mut stream = docker::get()?.events(Some(EventsOptions::<String> {
+        filters: HashMap::from([("type".to_string(), vec!["container".to_string()])]),
+        ..Default::default()
+    }));
+
+    while let Some(event) = stream.next().await {
+        let event = event.map_err(|e| format!("could not get event: {}", e))?;
+
+        let id = event.actor
+            .and_then(|actor| actor.attributes)
+            .and_then(|attributes| attributes.get("name").and_then(|name| name.strip_prefix("ae_sv_")?.parse::<u32>().ok()));
+
+        if let Some(id) = id {
+            debug!("Container of server {} changed ({})", id, event.action.unwrap_or_default());
+            server_status::invalidate(id).await;
+        }
+    }
+
+    Err("event stream ended".to_string())
+}
@@ -0,0 +1,65 @@
+use std::sync::atomic::Ordering;
+
+use packet::{debug::{self, Source}, events::TaskState};
+use tokio::{net::TcpListener, select};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::{config, tasks, telemetry, FEATURES, LISTENS};
+
+/// jemalloc with sampled heap profiles, served as `/debug/pprof/heap`
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[unsafe(export_name = "_rjem_malloc_conf")]
+static MALLOC_CONF: &[u8; 45] = b"prof:true,prof_active:true,lg_prof_sample:19\0";
+
+struct Daemon;
+
+impl Source for Daemon {
+    async fn state(&self) -> serde_json::Value {
+        let pending = tasks::list().await.into_iter().filter(|task| matches!(task.state, TaskState::Queued | TaskState::Running)).collect::<Vec<_>>();
+
+        serde_json::json!({
+            "connected": telemetry::CONNECTED.load(Ordering::Relaxed),
+            "authenticated": telemetry::AUTHENTICATED.load(Ordering::Relaxed),
+            "listens": *LISTENS.read().await,
+            "features": *FEATURES.read().await,
+            "packets_waiting": telemetry::PACKETS_WAITING.load(Ordering::Relaxed),
+            "pending_tasks": pending,
+        })
+    }
+
+    fn config(&self) -> Result<serde_json::Value, String> {
+        serde_json::to_value(config::get()?).map_err(|e| format!("could not serialize config: {}", e))
+    }
+}
+
+/// Runs the debug service, which dumps the connection state, listens, features, pending tasks and
+/// configuration of the daemon, and records CPU and heap profiles on the configured loopback
+/// address, see `packet::debug::respond`
+pub async fn run(token: CancellationToken) -> Result<(), String> {
+    let bind = &config::get()?.debug.bind;
+    let listener = TcpListener::bind(bind).await.map_err(|e| format!("Could not bind debug endpoint to {}: {}", bind, e))?;
+
+    warn!("Serving debug endpoint on http://{}/debug", bind);
+
+    loop {
+        select! {
+            _ = token.cancelled() => {
+                warn!("Stopping debug service");
+                return Ok(());
+            },
+            res = listener.accept() => match res {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = debug::respond(stream, &Daemon).await {
+                            debug!("Could not serve debug request: {}", e);
+                        }
+                    });
+                },
+                Err(e) => warn!("Could not accept debug connection: {}", e),
+            },
+        }
+    }
+}
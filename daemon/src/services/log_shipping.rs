@@ -0,0 +1,168 @@
+use std::{sync::atomic::{AtomicU64, Ordering}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use lazy_static::lazy_static;
+use packet::events::{LogLine, LogStream};
+use serde_json::json;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt, net::UdpSocket, select, sync::{mpsc, Mutex, OnceCell}};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::config::{self, LogTarget};
+
+/// How long a request to a Loki sink may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// How many batches of log lines may wait to be shipped before new ones are dropped, so a slow sink
+/// never holds up reading the logs of a server.
+const QUEUE_CAPACITY: usize = 1024;
+
+type Batch = (u32, Vec<LogLine>);
+
+lazy_static! {
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap_or_default();
+    static ref QUEUE: (mpsc::Sender<Batch>, Mutex<Option<mpsc::Receiver<Batch>>>) = {
+        let (tx, rx) = mpsc::channel(QUEUE_CAPACITY);
+        (tx, Mutex::new(Some(rx)))
+    };
+}
+
+/// Lines dropped because the queue was full, since the last time this was logged.
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+static SYSLOG_SOCKET: OnceCell<UdpSocket> = OnceCell::const_new();
+
+fn stream_name(stream: LogStream) -> &'static str {
+    match stream {
+        LogStream::Stdout => "stdout",
+        LogStream::Stderr => "stderr",
+    }
+}
+
+async fn ship_syslog(id: u32, address: &str, lines: &[LogLine]) -> Result<(), String> {
+    let socket = SYSLOG_SOCKET.get_or_try_init(|| UdpSocket::bind("0.0.0.0:0")).await.map_err(|e| format!("could not bind syslog socket: {}", e))?;
+    let hostname = &config::get()?.daemon.uuid;
+
+    for line in lines {
+        // facility user (1), severity error (3) for stderr and informational (6) for stdout
+        let priority = match line.stream {
+            LogStream::Stdout => 8 + 6,
+            LogStream::Stderr => 8 + 3,
+        };
+
+        let message = format!("<{}>1 {} {} ae_sv_{} - - - {}", priority, line.timestamp, hostname, id, line.line);
+        socket.send_to(message.as_bytes(), address).await.map_err(|e| format!("could not send to syslog: {}", e))?;
+    }
+
+    Ok(())
+}
+
+async fn ship_loki(id: u32, url: &str, lines: &[LogLine]) -> Result<(), String> {
+    // Loki wants nanosecond timestamps, and the lines are shipped as soon as they are read, so the
+    // current time is close enough and keeps entries ordered within a stream
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|_| "system time is before the unix epoch")?.as_nanos();
+    let daemon = &config::get()?.daemon.uuid;
+
+    let streams = [LogStream::Stdout, LogStream::Stderr].into_iter().filter_map(|stream| {
+        let values = lines.iter().filter(|line| line.stream == stream).map(|line| json!([now.to_string(), line.line])).collect::<Vec<_>>();
+
+        if values.is_empty() {
+            return None;
+        }
+
+        Some(json!({
+            "stream": {
+                "job": "aesterisk",
+                "daemon": daemon,
+                "server": id.to_string(),
+                "stream": stream_name(stream),
+            },
+            "values": values,
+        }))
+    }).collect::<Vec<_>>();
+
+    let res = HTTP_CLIENT.post(url)
+        .header("Content-Type", "application/json")
+        .body(json!({ "streams": streams }).to_string())
+        .send()
+        .await
+        .map_err(|e| format!("could not push to loki: {}", e))?;
+
+    if !res.status().is_success() {
+        return Err(format!("loki responded with {}", res.status()));
+    }
+
+    Ok(())
+}
+
+async fn ship_file(id: u32, lines: &[LogLine]) -> Result<(), String> {
+    let folder = format!("{}/logs", config::get()?.storage.data_folder);
+    tokio::fs::create_dir_all(&folder).await.map_err(|e| format!("could not create logs folder: {}", e))?;
+
+    let mut file = OpenOptions::new().create(true).append(true).open(format!("{}/{}.log", folder, id)).await.map_err(|e| format!("could not open log file: {}", e))?;
+
+    let contents = lines.iter().map(|line| format!("{} {} {}\n", line.timestamp, stream_name(line.stream), line.line)).collect::<String>();
+    file.write_all(contents.as_bytes()).await.map_err(|e| format!("could not write log file: {}", e))?;
+
+    Ok(())
+}
+
+/// Queues log lines of a server to be forwarded to all configured sinks that include it, see
+/// `run`. If the sinks can't keep up, the lines are dropped instead.
+pub fn ship(id: u32, lines: &[LogLine]) {
+    if lines.is_empty() {
+        return;
+    }
+
+    let sinks = match config::get() {
+        Ok(config) => &config.logs.sinks,
+        Err(_) => return,
+    };
+
+    if !sinks.iter().any(|sink| sink.servers.is_empty() || sink.servers.contains(&id)) {
+        return;
+    }
+
+    if QUEUE.0.try_send((id, lines.to_vec())).is_err() {
+        DROPPED.fetch_add(lines.len() as u64, Ordering::Relaxed);
+    }
+}
+
+async fn ship_batch(id: u32, lines: &[LogLine]) -> Result<(), String> {
+    for sink in config::get()?.logs.sinks.iter().filter(|sink| sink.servers.is_empty() || sink.servers.contains(&id)) {
+        let res = match &sink.target {
+            LogTarget::Syslog { address } => ship_syslog(id, address, lines).await,
+            LogTarget::Loki { url } => ship_loki(id, url, lines).await,
+            LogTarget::File => ship_file(id, lines).await,
+        };
+
+        if let Err(e) = res {
+            warn!("Could not ship logs of server {} to {:?}: {}", id, sink.target, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Forwards the queued log lines to the sinks, one batch at a time. Failures are logged and don't
+/// affect the other sinks.
+pub async fn run(token: CancellationToken) -> Result<(), String> {
+    let mut queue = QUEUE.1.lock().await.take().ok_or("Log shipping is already running")?;
+
+    loop {
+        let (id, lines) = select! {
+            _ = token.cancelled() => break,
+            batch = queue.recv() => match batch {
+                Some(batch) => batch,
+                None => break,
+            },
+        };
+
+        ship_batch(id, &lines).await?;
+
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("Dropped {} log lines because the log sinks could not keep up", dropped);
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use packet::activation;
+use tokio::{io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader}, net::TcpStream, select};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::{config, telemetry};
+
+/// How long a client may take to send its request line.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request line that is read, longer ones are answered with a 404.
+const MAX_REQUEST_LINE: u64 = 8 * 1024;
+
+/// Runs the metrics service, which serves the daemon's metrics, and the usage of the node and its
+/// servers, in the Prometheus text format on the configured address
+pub async fn run(token: CancellationToken) -> Result<(), String> {
+    let bind = &config::get()?.metrics.bind;
+    let listener = activation::bind("metrics", bind).await.map_err(|e| format!("Could not bind metrics endpoint to {}: {}", bind, e))?;
+
+    info!("Serving metrics on http://{}/metrics", bind);
+
+    loop {
+        select! {
+            _ = token.cancelled() => {
+                warn!("Stopping metrics service");
+                return Ok(());
+            },
+            res = listener.accept() => match res {
+                Ok((stream, _)) => {
+                    tokio::spawn(async move {
+                        if let Err(e) = respond(stream).await {
+                            debug!("Could not serve metrics: {}", e);
+                        }
+                    });
+                },
+                Err(e) => warn!("Could not accept metrics connection: {}", e),
+            },
+        }
+    }
+}
+
+/// Answers a single HTTP request and closes the connection.
+async fn respond(stream: TcpStream) -> Result<(), String> {
+    let mut stream = BufReader::new(stream);
+
+    let mut request = String::new();
+    tokio::time::timeout(REQUEST_TIMEOUT, (&mut stream).take(MAX_REQUEST_LINE).read_line(&mut request)).await
+        .map_err(|_| "timed out reading request".to_string())?
+        .map_err(|e| format!("could not read request: {}", e))?;
+
+    let (status, body) = match request.split_whitespace().take(2).collect::<Vec<_>>().as_slice() {
+        ["GET", "/metrics"] => ("200 OK", telemetry::render()),
+        _ => ("404 Not Found", String::new()),
+    };
+
+    let response = format!("HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+
+    stream.get_mut().write_all(response.as_bytes()).await.map_err(|e| format!("could not write response: {}", e))
+}
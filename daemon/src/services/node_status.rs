@@ -1,13 +1,14 @@
-use std::{collections::HashSet, time::Duration};
+use std::collections::HashSet;
 
-use packet::{daemon_server::event::DSEventPacket, events::{EventData, EventType, NodeStats, NodeStatusEvent}};
+use packet::{events::{EventData, EventType, NodeStats, NodeStatusEvent}};
 use sysinfo::{CpuRefreshKind, DiskRefreshKind, Disks, MemoryRefreshKind, RefreshKind, System};
 use tokio::select;
-use tokio_tungstenite::tungstenite::Message;
 use tokio_util::sync::CancellationToken;
 use tracing::{error, warn};
 
-use crate::{encryption, LISTENS, SENDER};
+use crate::{config, docker, maintenance, remote_config, telemetry, LISTENS};
+
+use super::{buffer, server_status};
 
 /// Runs the node status service, sending status information to the clients
 pub async fn run(token: CancellationToken) -> Result<(), String> {
@@ -22,72 +23,97 @@ pub async fn run(token: CancellationToken) -> Result<(), String> {
     }
 }
 
-async fn send_loop() -> Result<(), String> {
-    // TODO: make this configurable
-    let mut interval = tokio::time::interval(Duration::from_secs(1));
+fn refresh(system: &mut System, disks: &mut Disks) {
+    system.refresh_specifics(RefreshKind::nothing().with_memory(MemoryRefreshKind::nothing().with_ram()).with_cpu(CpuRefreshKind::nothing().with_cpu_usage()));
+    disks.refresh_specifics(true, DiskRefreshKind::nothing().with_storage());
+}
+
+fn node_stats(system: &System, disks: &Disks) -> NodeStats {
+    const GB: f64 = 1_073_741_824.0;
+
+    let mut counted = HashSet::new();
+
+    let (used, total) = disks.iter()
+        .filter(|disk| counted.insert(disk.name().to_string_lossy()))
+        .filter(|disk| !disk.is_removable())
+        .map(|disk| (disk.available_space(), disk.total_space()))
+        .map(|(available, total)| (total - available, total))
+        .fold((0, 0), |(used, total), (used2, total2)| (used + used2, total + total2));
+
+    NodeStats {
+        used_memory: system.used_memory() as f64 / GB,
+        total_memory: system.total_memory() as f64 / GB,
+        cpu: system.global_cpu_usage() as f64,
+        used_storage: used as f64 / GB,
+        total_storage: total as f64 / GB,
+    }
+}
+
+/// Builds the status of the node from refreshed system information.
+async fn node_status(system: &System, disks: &Disks) -> NodeStatusEvent {
+    let max_servers = config::get().map(|config| config.capacity.max_servers).unwrap_or_default();
+
+    let servers = match server_status::counts().await {
+        Ok(counts) => Some(counts),
+        Err(e) => {
+            warn!("Could not count servers: {}", e);
+            None
+        }
+    };
+
+    NodeStatusEvent {
+        online: true,
+        stats: Some(node_stats(system, disks)),
+        in_maintenance: maintenance::in_maintenance(None).await,
+        servers,
+        max_servers: (max_servers > 0).then_some(max_servers),
+        info: Some(docker::rootless::info()),
+    }
+}
+
+/// Takes a single stats sample of the node, independent of the node status service.
+pub async fn sample() -> NodeStatusEvent {
     let mut system = System::new();
     let mut disks = Disks::new();
 
-    const GB: f64 = 1_073_741_824.0;
+    // the CPU usage is calculated from the difference between two refreshes
+    refresh(&mut system, &mut disks);
+    tokio::time::sleep(sysinfo::MINIMUM_CPU_UPDATE_INTERVAL).await;
+    refresh(&mut system, &mut disks);
+
+    node_status(&system, &disks).await
+}
+
+async fn send_loop() -> Result<(), String> {
+    let mut system = System::new();
+    let mut disks = Disks::new();
 
     loop {
-        interval.tick().await;
+        // the interval can be changed by the server at any time
+        tokio::time::sleep(remote_config::node_status_interval().await).await;
+
+        let metrics = config::get().is_ok_and(|config| config.metrics.enabled() && config.metrics.node);
+
+        // while disconnected, the status is buffered regardless, as the listens are only known
+        // once connected
+        let send = LISTENS.read().await.contains(&EventType::NodeStatus) || !buffer::online().await;
+
+        if !send && !metrics {
+            continue;
+        }
+
+        refresh(&mut system, &mut disks);
+
+        if metrics {
+            telemetry::set_node(node_stats(&system, &disks));
+        }
 
-        if !LISTENS.read().await.contains(&EventType::NodeStatus) {
+        if !send {
             continue;
         }
 
-        if SENDER.lock().await.is_some() {
-            system.refresh_specifics(RefreshKind::nothing().with_memory(MemoryRefreshKind::nothing().with_ram()).with_cpu(CpuRefreshKind::nothing().with_cpu_usage()));
-            disks.refresh_specifics(true, DiskRefreshKind::nothing().with_storage());
-
-            let mut counted = HashSet::new();
-
-            let (used, total) = disks.iter()
-                .filter(|disk| counted.insert(disk.name().to_string_lossy()))
-                .filter(|disk| !disk.is_removable())
-                .map(|disk| (disk.available_space(), disk.total_space()))
-                .map(|(available, total)| (total - available, total))
-                .fold((0, 0), |(used, total), (used2, total2)| (used + used2, total + total2));
-
-            let packet = DSEventPacket {
-                data: EventData::NodeStatus(NodeStatusEvent {
-                    online: true,
-                    stats: Some(NodeStats {
-                        used_memory: system.used_memory() as f64 / GB,
-                        total_memory: system.total_memory() as f64 / GB,
-                        cpu: system.global_cpu_usage() as f64,
-                        used_storage: used as f64 / GB,
-                        total_storage: total as f64 / GB,
-                    }),
-                }),
-            };
-
-            let packet = match packet.to_packet() {
-                Ok(packet) => packet,
-                Err(e) => {
-                    error!("Error creating packet: {}", e);
-                    continue;
-                }
-            };
-
-            let packet = match encryption::encrypt_packet(packet) {
-                Ok(packet) => packet,
-                Err(e) => {
-                    error!("Error encrypting packet: {}", e);
-                    continue;
-                }
-            };
-
-            if let Some(tx) = SENDER.lock().await.as_ref() {
-                match tx.unbounded_send(Message::Text(packet)) {
-                    Ok(_) => (),
-                    Err(e) => {
-                        error!("Could not send packet: {}", e);
-                        continue;
-                    }
-                }
-            }
+        if let Err(e) = buffer::send(EventData::NodeStatus(node_status(&system, &disks).await)).await {
+            error!("Could not send node status: {}", e);
         }
     }
 }
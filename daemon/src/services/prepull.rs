@@ -0,0 +1,102 @@
+use std::{collections::HashSet, sync::Arc, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use lazy_static::lazy_static;
+use packet::{maintenance::MaintenanceWindow, server_daemon::catalog::CatalogImage};
+use tokio::{select, sync::RwLock};
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+
+use crate::{docker, storage, tasks::{self, Job}};
+
+#[derive(Default)]
+struct Catalog {
+    images: Vec<CatalogImage>,
+    windows: Vec<MaintenanceWindow>,
+}
+
+lazy_static! {
+    /// The catalog last sent by the server.
+    static ref CATALOG: Arc<RwLock<Catalog>> = Arc::new(RwLock::new(Catalog::default()));
+}
+
+/// How often the service checks whether images should be pulled.
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Replaces the catalog with the one sent by the server.
+pub async fn set_catalog(images: Vec<CatalogImage>, windows: Vec<MaintenanceWindow>) {
+    for window in windows.iter().filter(|window| !window.is_valid()) {
+        warn!("Ignoring catalog window with invalid cron expression '{}'", window.cron);
+    }
+
+    *CATALOG.write().await = Catalog {
+        images,
+        windows,
+    };
+}
+
+/// Runs the pre-pull service, which queues pulls of the images of the catalog while a window of the
+/// catalog is active
+pub async fn run(token: CancellationToken) -> Result<(), String> {
+    select! {
+        _ = token.cancelled() => {
+            warn!("Stopping pre-pull service");
+            Ok(())
+        },
+        res = pull_loop() => {
+            res
+        }
+    }
+}
+
+async fn pull_loop() -> Result<(), String> {
+    let mut interval = tokio::time::interval(CHECK_INTERVAL);
+
+    // images are only attempted once per window
+    let mut attempted = HashSet::new();
+
+    loop {
+        interval.tick().await;
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+
+        let images = {
+            let catalog = CATALOG.read().await;
+
+            if !catalog.windows.iter().any(|window| window.is_active(now)) {
+                attempted.clear();
+                continue;
+            }
+
+            catalog.images.iter().filter(|image| !attempted.contains(*image)).cloned().collect::<Vec<_>>()
+        };
+
+        if images.is_empty() {
+            continue;
+        }
+
+        if let Err(e) = storage::check_reserve() {
+            warn!("Not pre-pulling images: {}", e);
+            attempted.extend(images);
+            continue;
+        }
+
+        for image in images {
+            let name = format!("{}:{}", image.image, image.docker_tag);
+
+            if docker::timed("inspect_image", docker::get()?.inspect_image(&name)).await.is_ok() {
+                attempted.insert(image);
+                continue;
+            }
+
+            match tasks::submit(Job::Pull {
+                image: image.image.clone(),
+                tag: image.docker_tag.clone(),
+            }).await {
+                Ok(id) => info!("Pre-pulling image {} as task {}", name, id),
+                Err(e) => warn!("Could not pre-pull image {}: {}", name, e),
+            }
+
+            attempted.insert(image);
+        }
+    }
+}
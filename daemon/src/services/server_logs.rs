@@ -0,0 +1,172 @@
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::Arc, time::Duration};
+
+use bollard::container::{LogOutput, LogsOptions};
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use packet::events::{EventData, EventType, LogLine, LogStream, ServerLogEvent};
+use tokio::{select, sync::{Mutex, RwLock}};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, warn};
+
+use crate::{config, docker, flow, sequence, LISTENS};
+
+const REATTACH_DELAY: Duration = Duration::from_secs(1);
+
+lazy_static! {
+    static ref CANCELLATION_TOKEN: Arc<Mutex<Option<CancellationToken>>> = Arc::new(Mutex::new(None));
+    static ref BUFFERS: Arc<RwLock<HashMap<u32, VecDeque<LogLine>>>> = Arc::new(RwLock::new(HashMap::new()));
+    /// Servers whose logs are followed by web clients, see `SDListenPacket::log_servers`
+    static ref FOLLOWED: RwLock<HashSet<u32>> = RwLock::new(HashSet::new());
+}
+
+pub async fn get_cancellation_token() -> Result<CancellationToken, String> {
+    let mut guard = CANCELLATION_TOKEN.lock().await;
+
+    if guard.is_none() {
+        guard.replace(super::get_cancellation_token().ok_or("no parent cancellation token provided")?.child_token());
+    }
+
+    Ok(guard.as_ref().expect("should NOT be None after Option::replace() call").clone())
+}
+
+pub async fn stop_services() -> Result<(), String> {
+    get_cancellation_token().await?.cancel();
+
+    let token = CANCELLATION_TOKEN.lock().await.take();
+    drop(token);
+
+    Ok(())
+}
+
+/// Returns the last `lines` buffered log lines of a server, oldest first.
+pub async fn recent(id: u32, lines: usize) -> Vec<LogLine> {
+    match BUFFERS.read().await.get(&id) {
+        Some(buffer) => buffer.iter().skip(buffer.len().saturating_sub(lines)).cloned().collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Drops the buffered lines of servers that aren't in `servers`, which were removed by a sync.
+pub async fn retain(servers: &HashSet<u32>) {
+    BUFFERS.write().await.retain(|id, _| servers.contains(id));
+}
+
+/// Sets the servers whose new lines are sent to the server as `ServerLog` events.
+pub async fn follow(servers: Vec<u32>) {
+    *FOLLOWED.write().await = servers.into_iter().collect();
+}
+
+/// Sends new lines of a server to the server, if web clients follow its logs.
+async fn stream(id: u32, lines: &[LogLine]) {
+    if lines.is_empty() || !LISTENS.read().await.contains(&EventType::ServerLog) || !FOLLOWED.read().await.contains(&id) {
+        return;
+    }
+
+    // lines are left out if the server can't keep up, which is reported with the next lines
+    let Some(skipped) = flow::take(EventType::ServerLog).await else {
+        return;
+    };
+
+    let res = sequence::send(EventData::ServerLog(ServerLogEvent {
+        server: id,
+        lines: lines.to_vec(),
+        skipped,
+    }), None).await;
+
+    if let Err(e) = res {
+        warn!("Could not send logs of server {}: {}", id, e);
+    }
+}
+
+fn parse_output(output: LogOutput) -> Vec<LogLine> {
+    let (stream, message) = match output {
+        LogOutput::StdErr { message } => (LogStream::Stderr, message),
+        LogOutput::StdOut { message } | LogOutput::Console { message } => (LogStream::Stdout, message),
+        LogOutput::StdIn { .. } => return Vec::new(),
+    };
+
+    String::from_utf8_lossy(&message).lines().filter_map(|line| {
+        let (timestamp, line) = line.split_once(' ')?;
+
+        Some(LogLine {
+            timestamp: timestamp.to_string(),
+            stream,
+            line: line.trim_end_matches('\r').to_string(),
+        })
+    }).collect()
+}
+
+async fn push(id: u32, lines: Vec<LogLine>) -> Result<(), String> {
+    let capacity = config::get()?.logs.buffer_lines;
+
+    let mut buffers = BUFFERS.write().await;
+    let buffer = buffers.entry(id).or_insert_with(|| VecDeque::with_capacity(capacity));
+
+    for line in lines {
+        if buffer.len() >= capacity {
+            buffer.pop_front();
+        }
+
+        buffer.push_back(line);
+    }
+
+    Ok(())
+}
+
+async fn run(token: CancellationToken, id: u32) -> Result<(), String> {
+    // the stream backfills the last lines when attaching, so start with an empty buffer to avoid
+    // duplicates after a reattach, and only stream lines that weren't buffered yet
+    let buffered = BUFFERS.write().await.remove(&id).map(|buffer| buffer.into_iter().map(|line| line.timestamp).collect::<HashSet<_>>()).unwrap_or_default();
+
+    let mut logs = docker::get()?.logs(&format!("ae_sv_{}", id), Some(LogsOptions {
+        follow: true,
+        stdout: true,
+        stderr: true,
+        timestamps: true,
+        tail: format!("{}", config::get()?.logs.buffer_lines),
+        ..Default::default()
+    }));
+
+    while let Some(output) = logs.next().await {
+        if token.is_cancelled() {
+            break;
+        }
+
+        match output {
+            Ok(output) => {
+                let lines = parse_output(output);
+                super::log_shipping::ship(id, &lines);
+                let new = lines.iter().filter(|line| !buffered.contains(&line.timestamp)).cloned().collect::<Vec<_>>();
+                stream(id, &new).await;
+                push(id, lines).await?;
+            }
+            Err(e) => return Err(format!("could not get logs: {}", e)),
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn start(id: u32) -> Result<(), String> {
+    let token = get_cancellation_token().await?;
+
+    loop {
+        select! {
+            _ = token.cancelled() => {
+                break;
+            }
+            res = run(token.clone(), id) => {
+                if let Err(e) = res {
+                    error!("Error in server logs: {}", e);
+                }
+
+                // the stream ends when the container stops, wait a bit before reattaching
+                tokio::time::sleep(REATTACH_DELAY).await;
+            }
+        }
+    }
+
+    debug!("Exiting server logs service for server {}", id);
+
+    Ok(())
+}
@@ -1,20 +1,38 @@
-use std::sync::Arc;
+use std::{collections::{HashMap, HashSet}, sync::Arc, time::{Duration, Instant}};
 
-use bollard::{container::{InspectContainerOptions, MemoryStatsStats, StatsOptions}, secret::{ContainerInspectResponse, ContainerStateStatusEnum, HealthStatusEnum}};
+use bollard::{container::{InspectContainerOptions, StatsOptions}, secret::{ContainerInspectResponse, ContainerStateStatusEnum, ContainerSummary, HealthStatusEnum}};
 use futures_util::StreamExt;
 use lazy_static::lazy_static;
-use packet::{daemon_server::event::DSEventPacket, events::{EventData, ServerStatusEvent, ServerStatusType, Stats}};
-use tokio::{select, sync::Mutex};
-use tokio_tungstenite::tungstenite::Message;
+use packet::{events::{CpuConvention, EventData, ServerCounts, ServerStatusEvent, ServerStatusType, Stats, StatusReason}};
+use tokio::{select, sync::{Mutex, RwLock}};
 use tokio_util::sync::CancellationToken;
 use tracing::{debug, error};
 
-use crate::{docker, encryption, SENDER};
+use crate::{config, docker, history, maintenance, remote_config, telemetry};
+
+use super::buffer;
+
+/// A status of a server, together with the reason gathered when it entered that status.
+type StatusWithReason = (ServerStatusType, Option<StatusReason>);
 
 lazy_static! {
     static ref CANCELLATION_TOKEN: Arc<Mutex<Option<CancellationToken>>> = Arc::new(Mutex::new(None));
+    /// Last status of every server, together with the reason gathered when it entered that status.
+    static ref LAST_STATUS: Arc<Mutex<HashMap<u32, StatusWithReason>>> = Arc::new(Mutex::new(HashMap::new()));
+    /// Inspected containers, dropped when Docker reports a change to them (see
+    /// `services::container_events`) or once they are older than `INSPECT_MAX_AGE`.
+    static ref INSPECT_CACHE: Arc<Mutex<HashMap<u32, (Instant, ContainerInspectResponse)>>> = Arc::new(Mutex::new(HashMap::new()));
+    /// Amount of servers by status, dropped together with the inspected containers.
+    static ref COUNTS_CACHE: Arc<Mutex<Option<(Instant, ServerCounts)>>> = Arc::new(Mutex::new(None));
+    /// Servers whose status is sent to the server, or `None` for all of them, see
+    /// `SDListenPacket::status_servers`
+    static ref STREAMED: RwLock<Option<HashSet<u32>>> = RwLock::new(None);
 }
 
+/// Maximum age of an inspected container, after which it is inspected again to update its storage
+/// usage.
+const INSPECT_MAX_AGE: Duration = Duration::from_secs(60);
+
 pub async fn get_cancellation_token() -> Result<CancellationToken, String> {
     let mut guard = CANCELLATION_TOKEN.lock().await;
 
@@ -34,6 +52,39 @@ pub async fn stop_services() -> Result<(), String> {
     Ok(())
 }
 
+/// Sets the servers whose status is sent to the server, or `None` to send the status of all of
+/// them.
+pub async fn filter(servers: Option<Vec<u32>>) {
+    *STREAMED.write().await = servers.map(|servers| servers.into_iter().collect());
+}
+
+/// Drops the cached inspection of a server's container.
+pub async fn invalidate(id: u32) {
+    INSPECT_CACHE.lock().await.remove(&id);
+    COUNTS_CACHE.lock().await.take();
+}
+
+/// Drops the cached inspections of all containers.
+pub async fn invalidate_all() {
+    INSPECT_CACHE.lock().await.clear();
+    COUNTS_CACHE.lock().await.take();
+}
+
+async fn inspect(id: u32) -> Result<ContainerInspectResponse, String> {
+    if let Some((inspected, server)) = INSPECT_CACHE.lock().await.get(&id)
+        && inspected.elapsed() < INSPECT_MAX_AGE {
+        return Ok(server.clone());
+    }
+
+    let server = docker::timed("inspect_container", docker::get()?.inspect_container(&format!("ae_sv_{}", id), Some(InspectContainerOptions {
+        size: true,
+    }))).await.map_err(|e| format!("could not inspect container: {}", e))?;
+
+    INSPECT_CACHE.lock().await.insert(id, (Instant::now(), server.clone()));
+
+    Ok(server)
+}
+
 fn get_status_type(server: &ContainerInspectResponse) -> Result<ServerStatusType, String> {
     Ok(match server.state.as_ref().ok_or("no state")?.status.ok_or("no status")? {
         ContainerStateStatusEnum::PAUSED => ServerStatusType::Starting,
@@ -50,80 +101,164 @@ fn get_status_type(server: &ContainerInspectResponse) -> Result<ServerStatusType
     })
 }
 
-async fn send_to_server(server_status: ServerStatusEvent) -> Result<(), String> {
-    if SENDER.lock().await.is_some() {
-        let packet = DSEventPacket {
-            data: EventData::ServerStatus(server_status),
-        };
+/// Gets the status of a server from the summary of its container, like `get_status_type`.
+fn get_summary_status_type(server: &ContainerSummary) -> ServerStatusType {
+    let health = server.status.as_deref().unwrap_or_default();
 
-        let packet = match packet.to_packet() {
-            Ok(packet) => packet,
-            Err(e) => {
-                return Err(format!("Error creating packet: {}", e));
-            }
-        };
+    match server.state.as_deref().unwrap_or_default() {
+        "paused" => ServerStatusType::Starting,
+        "restarting" => ServerStatusType::Restarting,
+        "removing" => ServerStatusType::Stopping,
+        "created" | "running" if health.contains("(unhealthy)") => ServerStatusType::Unhealthy,
+        "created" | "running" if health.contains("(health: starting)") => ServerStatusType::Starting,
+        "created" | "running" => ServerStatusType::Healthy,
+        _ => ServerStatusType::Stopped,
+    }
+}
 
-        let packet = match encryption::encrypt_packet(packet) {
-            Ok(packet) => packet,
-            Err(e) => {
-                return Err(format!("Error encrypting packet: {}", e));
-            }
-        };
+/// Returns the amount of servers on this node by status.
+pub async fn counts() -> Result<ServerCounts, String> {
+    if let Some((counted, counts)) = COUNTS_CACHE.lock().await.as_ref()
+        && counted.elapsed() < INSPECT_MAX_AGE {
+        return Ok(counts.clone());
+    }
 
-        if let Some(tx) = SENDER.lock().await.as_ref() {
-            match tx.unbounded_send(Message::Text(packet)) {
-                Ok(_) => (),
-                Err(e) => {
-                    return Err(format!("Could not send packet: {}", e));
-                }
-            }
-        }
+    let mut counts = ServerCounts::default();
+
+    for server in docker::server::get_servers().await? {
+        counts.add(&get_summary_status_type(&server));
     }
 
-    Ok(())
+    COUNTS_CACHE.lock().await.replace((Instant::now(), counts.clone()));
+
+    Ok(counts)
 }
 
-async fn send_stat(id: u32, stat: bollard::container::Stats) -> Result<(), String> {
-    if stat.precpu_stats.system_cpu_usage.is_none() {
-        debug!("Skipping sending stats for server {}: precpu_stats.system_cpu_usage is not populated yet (should only take a cycle)", id);
-        return Ok(());
+/// Longest healthcheck output included in a status reason, in bytes.
+const MAX_HEALTH_OUTPUT: usize = 1024;
+
+fn get_status_reason(server: &ContainerInspectResponse, status: &ServerStatusType) -> Option<StatusReason> {
+    if !matches!(status, ServerStatusType::Stopped | ServerStatusType::Restarting | ServerStatusType::Unhealthy) {
+        return None;
     }
 
-    let server = docker::get()?.inspect_container(&format!("ae_sv_{}", id), Some(InspectContainerOptions {
-        size: true,
-    })).await.map_err(|e| format!("could not inspect container: {}", e))?;
+    let state = server.state.as_ref()?;
 
-    let status = get_status_type(&server).map_err(|e| format!("could not get status type: {}", e))?;
+    let health_output = match status {
+        ServerStatusType::Unhealthy => state.health.as_ref()
+            .and_then(|health| health.log.as_ref())
+            .and_then(|log| log.last())
+            .and_then(|result| result.output.as_ref())
+            .map(|output| {
+                let output = output.trim();
+                let mut end = output.len().min(MAX_HEALTH_OUTPUT);
 
-    const GB: f64 = 1_073_741_824.0;
+                while !output.is_char_boundary(end) {
+                    end -= 1;
+                }
 
-    let server_status = ServerStatusEvent {
-        server: id,
-        cpu: match status {
-            ServerStatusType::Healthy | ServerStatusType::Starting | ServerStatusType::Stopping => Some(Stats {
-                used: (stat.cpu_stats.cpu_usage.total_usage as f64 - stat.precpu_stats.cpu_usage.total_usage as f64) / (stat.cpu_stats.system_cpu_usage.ok_or("no cpu_stats.system_cpu_usage")? as f64 - stat.precpu_stats.system_cpu_usage.ok_or("no precpu_stats.system_cpu_usage")? as f64) * (stat.cpu_stats.online_cpus.ok_or("no cpu_stats.online_cpus")? * 100) as f64,
-                total: (stat.cpu_stats.online_cpus.ok_or("no cpu_stats.online_cpus")? * 100) as f64,
-            }),
-            _ => None,
-        },
-        memory: match status {
-            ServerStatusType::Healthy | ServerStatusType::Starting | ServerStatusType::Stopping => Some(Stats {
-                used: (stat.memory_stats.usage.ok_or("no memory_stats.usage")? - match stat.memory_stats.stats.ok_or("no memory_stats.stats")? {
-                    MemoryStatsStats::V1(v1) => v1.cache,
-                    MemoryStatsStats::V2(v2) => v2.file,
-                }) as f64 / GB,
-                total: stat.memory_stats.limit.ok_or("no memory_stats.limit")? as f64 / GB,
+                output[..end].to_string()
             }),
-            _ => None,
+        _ => None,
+    };
+
+    Some(StatusReason {
+        exit_code: state.exit_code,
+        oom_killed: state.oom_killed.unwrap_or(false),
+        error: state.error.clone().filter(|error| !error.is_empty()),
+        health_output,
+    })
+}
+
+/// Returns the reason for the status of a server, which is only gathered when the status changes.
+async fn status_reason(id: u32, server: &ContainerInspectResponse, status: &ServerStatusType) -> Option<StatusReason> {
+    let mut last_status = LAST_STATUS.lock().await;
+
+    match last_status.get(&id) {
+        Some((last, reason)) if last == status => reason.clone(),
+        _ => {
+            let reason = get_status_reason(server, status);
+            last_status.insert(id, (status.clone(), reason.clone()));
+            reason
         },
-        storage: Some(Stats {
-            used: server.size_root_fs.ok_or("no size_root_fs")? as f64 / GB,
+    }
+}
+
+/// Maps a stats sample and the inspected container of a server to its status. `in_maintenance` and
+/// `reason` depend on the state of the daemon, and are left for `build_status` to fill in.
+fn map_status(id: u32, stat: &bollard::container::Stats, server: &ContainerInspectResponse, server_stats: bool, convention: CpuConvention) -> Result<ServerStatusEvent, String> {
+    let status = get_status_type(server).map_err(|e| format!("could not get status type: {}", e))?;
+
+    const GB: f64 = 1_073_741_824.0;
+
+    // rootless Docker on cgroup v1 can't report CPU and memory stats
+    let stats = server_stats && matches!(status, ServerStatusType::Healthy | ServerStatusType::Starting | ServerStatusType::Stopping);
+
+    Ok(ServerStatusEvent {
+        server: id,
+        cpu: stats.then(|| docker::stats::cpu(stat, convention)).flatten(),
+        memory: stats.then(|| docker::stats::memory(stat)).flatten(),
+        // the containerd snapshotter doesn't report the size of the root filesystem
+        storage: server.size_root_fs.map(|size| Stats {
+            used: size as f64 / GB,
             total: 100.0, // TODO: make max storage configurable
         }),
         status,
-    };
+        in_maintenance: false,
+        reason: None,
+        started_at: server.state.as_ref()
+            .filter(|state| state.running == Some(true))
+            .and_then(|state| state.started_at.clone()),
+        restart_count: server.restart_count.unwrap_or(0).max(0) as u64,
+        cpu_convention: convention,
+        cores: docker::stats::cores(stat).map(|cores| cores as u32),
+    })
+}
+
+async fn build_status(id: u32, stat: bollard::container::Stats) -> Result<ServerStatusEvent, String> {
+    let server = inspect(id).await?;
+
+    let mut server_status = map_status(id, &stat, &server, docker::rootless::info().server_stats, config::get()?.stats.cpu)?;
+    server_status.reason = status_reason(id, &server, &server_status.status).await;
+    server_status.in_maintenance = maintenance::in_maintenance(Some(id)).await;
 
-    send_to_server(server_status).await
+    Ok(server_status)
+}
+
+/// Takes a single stats sample of a server, independent of its stats service.
+pub async fn sample(id: u32) -> Result<ServerStatusEvent, String> {
+    // without `one_shot`, Docker takes a second sample to populate `precpu_stats`, which are needed
+    // to calculate the CPU usage
+    let stat = docker::get()?.stats(&format!("ae_sv_{}", id), Some(StatsOptions {
+        stream: false,
+        one_shot: false,
+    })).next().await.ok_or("no stat returned")?.map_err(|e| format!("could not get stat: {}", e))?;
+
+    build_status(id, stat).await
+}
+
+async fn send_stat(id: u32, stat: bollard::container::Stats) -> Result<(), String> {
+    if stat.cpu_stats.system_cpu_usage.is_some() && stat.precpu_stats.system_cpu_usage.is_none() {
+        debug!("Skipping sending stats for server {}: precpu_stats.system_cpu_usage is not populated yet (should only take a cycle)", id);
+        return Ok(());
+    }
+
+    let server_status = build_status(id, stat).await?;
+
+    if let (Some(cpu), Some(memory)) = (server_status.cpu_per_core(), &server_status.memory) {
+        history::record(id, cpu, memory.used).await;
+    }
+
+    if config::get().is_ok_and(|config| config.metrics.enabled() && config.metrics.containers) {
+        telemetry::set_server(&server_status);
+    }
+
+    // the history and metrics are recorded regardless, as they don't depend on listens
+    if STREAMED.read().await.as_ref().is_some_and(|servers| !servers.contains(&id)) {
+        return Ok(());
+    }
+
+    buffer::send(EventData::ServerStatus(server_status)).await
 }
 
 async fn run(token: CancellationToken, id: u32) -> Result<(), String> {
@@ -132,14 +267,22 @@ async fn run(token: CancellationToken, id: u32) -> Result<(), String> {
         one_shot: false,
     }));
 
+    let mut sent: Option<Instant> = None;
+
     while let Some(stat) = stream.next().await {
         if token.is_cancelled() {
             break;
         }
 
+        if let Some(interval) = remote_config::server_status_interval().await
+            && sent.is_some_and(|sent| sent.elapsed() < interval) {
+            continue;
+        }
+
         match stat {
             Ok(stat) => {
                 send_stat(id, stat).await?;
+                sent = Some(Instant::now());
             },
             Err(e) => return Err(format!("could not get stat: {}", e))
         }
@@ -168,7 +311,60 @@ pub async fn start(id: u32) -> Result<(), String> {
         }
     }
 
+    telemetry::remove_server(id);
+
     debug!("Exiting server status service for server {}", id);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Maps the recorded stats and inspected container of a scenario, and compares the result to its
+    /// recorded event. Run with `UPDATE_GOLDEN=1` to record the events again after an intended change.
+    fn golden(scenario: &str) {
+        let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/status").join(scenario);
+        let read = |file: &str| std::fs::read_to_string(dir.join(file)).expect("could not read fixture");
+
+        let stat = serde_json::from_str(&read("stats.json")).expect("could not parse stats");
+        let server = serde_json::from_str(&read("inspect.json")).expect("could not parse inspected container");
+
+        let mut event = map_status(1, &stat, &server, true, CpuConvention::PerCore).expect("could not map status");
+        event.reason = get_status_reason(&server, &event.status);
+
+        let event = serde_json::to_value(event).expect("could not serialize event");
+
+        if std::env::var("UPDATE_GOLDEN").is_ok() {
+            let json = serde_json::to_string_pretty(&event).expect("could not serialize event");
+            std::fs::write(dir.join("event.json"), json + "\n").expect("could not write golden event");
+            return;
+        }
+
+        let expected: serde_json::Value = serde_json::from_str(&read("event.json")).expect("could not parse golden event");
+        assert_eq!(event, expected, "event of scenario {} changed", scenario);
+    }
+
+    #[test]
+    fn idle() {
+        golden("idle");
+    }
+
+    #[test]
+    fn busy() {
+        golden("busy");
+    }
+
+    #[test]
+    fn paused() {
+        golden("paused");
+    }
+
+    #[test]
+    fn oom() {
+        golden("oom");
+    }
+}
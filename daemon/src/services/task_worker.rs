@@ -0,0 +1,34 @@
+use std::time::Duration;
+
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+use crate::tasks;
+
+/// How long to wait before attempting a failed task again
+const RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// Runs the task worker service, which runs queued tasks one after another
+pub async fn run(token: CancellationToken) -> Result<(), String> {
+    select! {
+        _ = token.cancelled() => {
+            warn!("Stopping task worker service");
+            Ok(())
+        },
+        res = work_loop() => {
+            res
+        }
+    }
+}
+
+async fn work_loop() -> Result<(), String> {
+    loop {
+        let (id, job) = tasks::next().await;
+
+        if let Err(e) = tasks::execute(id, job, true).await {
+            warn!("Task {} failed: {}", id, e);
+            tokio::time::sleep(RETRY_DELAY).await;
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use std::{collections::HashSet, time::Duration};
+
+use packet::{events::{EventData, Resource, ResourceWarningEvent}, features::Feature};
+use sysinfo::{MemoryRefreshKind, System};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
+
+use crate::{config, sequence, storage, FEATURES};
+
+/// Runs the resource watchdog service, which warns the server whenever the usage of the node's
+/// disk, memory or inodes crosses the configured thresholds
+pub async fn run(token: CancellationToken) -> Result<(), String> {
+    select! {
+        _ = token.cancelled() => {
+            warn!("Stopping resource watchdog service");
+            Ok(())
+        },
+        res = check_loop() => {
+            res
+        }
+    }
+}
+
+/// Sends a resource warning to the server, returning `false` if the server doesn't support them
+/// (or the daemon is not authenticated yet).
+async fn send(warning: ResourceWarningEvent) -> Result<bool, String> {
+    if !FEATURES.read().await.has(Feature::ResourceWarnings) {
+        return Ok(false);
+    }
+
+    sequence::send(EventData::ResourceWarning(warning), None).await?;
+
+    Ok(true)
+}
+
+async fn check_loop() -> Result<(), String> {
+    let config = &config::get()?.watchdog;
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+    let mut system = System::new();
+
+    // resources above their threshold, and those the server has been warned about
+    let mut firing = HashSet::new();
+    let mut reported = HashSet::new();
+
+    loop {
+        interval.tick().await;
+
+        let usage = match storage::usage() {
+            Ok(usage) => Some(usage),
+            Err(e) => {
+                warn!("Could not check disk usage: {}", e);
+                None
+            }
+        };
+
+        system.refresh_memory_specifics(MemoryRefreshKind::nothing().with_ram());
+
+        let memory = (system.total_memory() > 0).then(|| system.used_memory() as f64 / system.total_memory() as f64 * 100.0);
+
+        let checks = [
+            (Resource::Disk, usage.as_ref().map(|usage| usage.disk_percent), config.disk_percent),
+            (Resource::Memory, memory, config.memory_percent),
+            (Resource::Inodes, usage.as_ref().map(|usage| usage.inode_percent), config.inode_percent),
+        ];
+
+        for (resource, value, threshold) in checks {
+            let Some(value) = value else {
+                continue;
+            };
+
+            if threshold > 0.0 && value > threshold {
+                if firing.insert(resource) {
+                    warn!("{:?} usage ({:.1}%) is above {}%", resource, value, threshold);
+                }
+            } else if firing.remove(&resource) {
+                info!("{:?} usage ({:.1}%) has recovered", resource, value);
+            }
+
+            let is_firing = firing.contains(&resource);
+
+            // unreported changes are retried on the next check, e.g. after reconnecting
+            if is_firing == reported.contains(&resource) {
+                continue;
+            }
+
+            match send(ResourceWarningEvent {
+                resource,
+                value,
+                threshold,
+                firing: is_firing,
+            }).await {
+                Ok(true) if is_firing => {
+                    reported.insert(resource);
+                },
+                Ok(true) => {
+                    reported.remove(&resource);
+                },
+                Ok(false) => (),
+                Err(e) => error!("Could not send resource warning: {}", e),
+            }
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use std::{ffi::CString, io, mem::MaybeUninit, os::unix::ffi::OsStrExt, path::Path};
+
+use crate::config;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Usage of the filesystem the data folder is on.
+pub struct Usage {
+    /// Space available to unprivileged users, in MiB
+    pub available_mb: u64,
+    /// Used disk space, in percent
+    pub disk_percent: f64,
+    /// Used inodes, in percent
+    pub inode_percent: f64,
+}
+
+fn percent(used: u64, total: u64) -> f64 {
+    if total == 0 {
+        return 0.0;
+    }
+
+    used as f64 / total as f64 * 100.0
+}
+
+/// Returns the usage of the filesystem the data folder is on.
+// the field types of statvfs differ between platforms
+#[allow(clippy::unnecessary_cast)]
+pub fn usage() -> Result<Usage, String> {
+    let data_folder = &config::get()?.storage.data_folder;
+
+    // the data folder might not have been created yet
+    let path = Path::new(data_folder).ancestors().find(|path| path.exists()).ok_or_else(|| format!("data folder {} does not exist", data_folder))?;
+    let path = CString::new(path.as_os_str().as_bytes()).map_err(|_| format!("invalid data folder {}", data_folder))?;
+
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+
+    // SAFETY: `path` is a valid C string, and `stat` is only read if statvfs succeeded
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(format!("could not get usage of data folder {}: {}", data_folder, io::Error::last_os_error()));
+    }
+
+    let stat = unsafe { stat.assume_init() };
+
+    let used_blocks = stat.f_blocks.saturating_sub(stat.f_bfree) as u64;
+
+    Ok(Usage {
+        available_mb: stat.f_bavail as u64 * stat.f_frsize as u64 / MIB,
+        // like df, the space reserved for root counts as neither used nor available
+        disk_percent: percent(used_blocks, used_blocks + stat.f_bavail as u64),
+        // some filesystems (e.g. btrfs) don't have a fixed amount of inodes, and report 0
+        inode_percent: percent(stat.f_files.saturating_sub(stat.f_ffree) as u64, stat.f_files as u64),
+    })
+}
+
+/// Checks that more disk space than the reserve is available, returning the space (in MiB) that
+/// can be used by servers.
+pub fn check_reserve() -> Result<u64, String> {
+    let reserved = config::get()?.storage.reserved_mb;
+    let available = usage()?.available_mb;
+
+    available.checked_sub(reserved).filter(|usable| *usable > 0).ok_or_else(|| format!("free disk space ({} MiB) is below the reserve of {} MiB", available, reserved))
+}
+
+/// Checks that enough disk space is available to create a new server.
+pub fn check_server_space() -> Result<(), String> {
+    let quota = config::get()?.storage.server_quota_mb;
+    let usable = check_reserve()?;
+
+    if usable < quota {
+        return Err(format!("only {} MiB of disk space are available, but {} MiB are set aside for every server", usable, quota));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,83 @@
+use packet::server_daemon::sync::{SDSyncPacket, ServerMetadata};
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+/// The hash of the last sync, and whether applying it finished. A state with `applied` set to
+/// `false` means the daemon stopped in the middle of applying a sync.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct SyncState {
+    pub hash: String,
+    pub applied: bool,
+    /// The last fully applied spec, which delta syncs are applied to
+    #[serde(default)]
+    pub spec: Option<SDSyncPacket>,
+}
+
+impl SyncState {
+    /// Returns the hash of the last sync if it was fully applied, or an empty string otherwise.
+    pub fn applied_hash(&self) -> &str {
+        if self.applied {
+            &self.hash
+        } else {
+            ""
+        }
+    }
+}
+
+fn path() -> Result<String, String> {
+    Ok(format!("{}/sync.json", config::get()?.storage.data_folder))
+}
+
+/// Reads the state of the last sync from the data folder.
+pub async fn load() -> Result<SyncState, String> {
+    match tokio::fs::read_to_string(path()?).await {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("could not parse sync state: {}", e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SyncState::default()),
+        Err(e) => Err(format!("could not read sync state: {}", e)),
+    }
+}
+
+async fn save(state: &SyncState) -> Result<(), String> {
+    let path = path()?;
+
+    if let Some(folder) = std::path::Path::new(&path).parent() {
+        tokio::fs::create_dir_all(folder).await.map_err(|e| format!("could not create data folder: {}", e))?;
+    }
+
+    tokio::fs::write(path, serde_json::to_string(state).map_err(|e| format!("could not serialize sync state: {}", e))?).await.map_err(|e| format!("could not write sync state: {}", e))
+}
+
+/// Marks a sync as being applied.
+pub async fn begin(hash: &str) -> Result<(), String> {
+    save(&SyncState {
+        hash: hash.to_string(),
+        applied: false,
+        spec: None,
+    }).await
+}
+
+/// Marks a sync as fully applied, storing the resulting spec.
+pub async fn finish(spec: SDSyncPacket) -> Result<(), String> {
+    save(&SyncState {
+        hash: spec.hash.clone(),
+        applied: true,
+        spec: Some(spec),
+    }).await
+}
+
+/// Replaces the display metadata of a server in the last applied spec, so it is used the next time
+/// the server's container is created. Metadata isn't part of the spec hash, so the hash stays valid.
+pub async fn set_metadata(metadata: ServerMetadata) -> Result<(), String> {
+    let mut state = load().await?;
+
+    let Some(spec) = state.spec.as_mut() else {
+        // the next sync contains the metadata
+        return Ok(());
+    };
+
+    spec.metadata.retain(|existing| existing.server != metadata.server);
+    spec.metadata.push(metadata);
+
+    save(&state).await
+}
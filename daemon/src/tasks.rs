@@ -0,0 +1,354 @@
+use std::{collections::HashMap, sync::Arc};
+
+use lazy_static::lazy_static;
+use packet::{events::{EventData, EventType, TaskEvent, TaskKind, TaskState}, server_daemon::sync::Build};
+use serde::{Deserialize, Serialize};
+use tokio::{select, sync::{Mutex, Notify}};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::{config, docker, sequence, LISTENS};
+
+/// How often a queued task is started before it fails
+const MAX_ATTEMPTS: u32 = 3;
+/// How many finished tasks are kept, so their state can still be reported
+const MAX_FINISHED: usize = 100;
+
+/// An operation that can outlive the connection to the server, or the daemon itself.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Job {
+    Pull { image: String, tag: String },
+    Build { server: u32, image: String, tag: String, build: Build },
+}
+
+impl Job {
+    fn kind(&self) -> TaskKind {
+        match self {
+            Job::Pull { image, tag } => TaskKind::PullImage {
+                image: format!("{}:{}", image, tag),
+            },
+            Job::Build { server, image, tag, .. } => TaskKind::BuildImage {
+                server: *server,
+                image: format!("{}:{}", image, tag),
+            },
+        }
+    }
+
+    /// Runs the job from the beginning. Work done by an interrupted attempt is skipped by Docker,
+    /// e.g. layers that were pulled already, or cached build steps.
+    async fn run(&self, progress: &Progress) -> Result<(), String> {
+        match self {
+            Job::Pull { image, tag } => docker::server::pull_image(image, tag, progress).await.map_err(|e| format!("Failed to pull image: {}", e)),
+            Job::Build { server, image, tag, build } => docker::build::build_image(*server, image, tag, build).await.map_err(|e| format!("Failed to build image: {}", e)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Task {
+    id: u64,
+    job: Job,
+    state: TaskState,
+    progress: Option<f64>,
+    checkpoint: Option<String>,
+    attempts: u32,
+}
+
+impl Task {
+    fn is_finished(&self) -> bool {
+        matches!(self.state, TaskState::Done | TaskState::Failed { .. } | TaskState::Cancelled)
+    }
+
+    fn event(&self) -> TaskEvent {
+        TaskEvent {
+            id: self.id,
+            kind: self.job.kind(),
+            state: self.state.clone(),
+            progress: self.progress,
+            checkpoint: self.checkpoint.clone(),
+            attempts: self.attempts,
+        }
+    }
+}
+
+/// The tasks of the daemon, which are persisted in the data folder after every change.
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct Queue {
+    next_id: u64,
+    tasks: Vec<Task>,
+}
+
+lazy_static! {
+    static ref QUEUE: Arc<Mutex<Queue>> = Arc::new(Mutex::new(Queue::default()));
+    /// Wakes up the task worker service when a task is queued
+    static ref QUEUED: Arc<Notify> = Arc::new(Notify::new());
+    /// Cancellation tokens of the running tasks, by id
+    static ref RUNNING: Arc<Mutex<HashMap<u64, CancellationToken>>> = Arc::new(Mutex::new(HashMap::new()));
+}
+
+/// Lets a running task record its progress.
+pub struct Progress {
+    id: u64,
+}
+
+impl Progress {
+    /// Records a checkpoint the task reached, and its progress from 0 to 1 if known.
+    pub async fn checkpoint(&self, progress: Option<f64>, checkpoint: String) {
+        let res = update(self.id, |task| {
+            task.progress = progress;
+            task.checkpoint = Some(checkpoint);
+            true
+        }).await;
+
+        if let Err(e) = res {
+            warn!("Could not record checkpoint of task {}: {}", self.id, e);
+        }
+    }
+}
+
+fn path() -> Result<String, String> {
+    Ok(format!("{}/tasks.json", config::get()?.storage.data_folder))
+}
+
+async fn save(queue: &Queue) -> Result<(), String> {
+    let path = path()?;
+
+    if let Some(folder) = std::path::Path::new(&path).parent() {
+        tokio::fs::create_dir_all(folder).await.map_err(|e| format!("could not create data folder: {}", e))?;
+    }
+
+    tokio::fs::write(path, serde_json::to_string(queue).map_err(|e| format!("could not serialize tasks: {}", e))?).await.map_err(|e| format!("could not write tasks: {}", e))
+}
+
+/// Reads the tasks from the data folder, queueing the tasks that were interrupted by a restart
+/// again. Returns the amount of tasks to resume.
+pub async fn load() -> Result<usize, String> {
+    let mut queue: Queue = match tokio::fs::read_to_string(path()?).await {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| format!("could not parse tasks: {}", e))?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+        Err(e) => return Err(format!("could not read tasks: {}", e)),
+    };
+
+    for task in queue.tasks.iter_mut().filter(|task| task.state == TaskState::Running) {
+        task.state = TaskState::Queued;
+    }
+
+    let resumed = queue.tasks.iter().filter(|task| task.state == TaskState::Queued).count();
+
+    save(&queue).await?;
+    *QUEUE.lock().await = queue;
+
+    if resumed > 0 {
+        QUEUED.notify_one();
+    }
+
+    Ok(resumed)
+}
+
+/// Sends the state of a task to the server, if anyone is listening for it.
+async fn report(event: TaskEvent) {
+    debug!("[task {}] {:?}", event.id, event.state);
+
+    if !LISTENS.read().await.contains(&EventType::Task) {
+        return;
+    }
+
+    let res = sequence::send(EventData::Task(event), None).await;
+
+    if let Err(e) = res {
+        warn!("Could not send task state: {}", e);
+    }
+}
+
+/// Sends the state of all tasks to the server, e.g. after reconnecting, so it doesn't miss changes
+/// while it wasn't connected.
+pub async fn report_all() {
+    for event in list().await {
+        report(event).await;
+    }
+}
+
+/// Changes a task, persists it and reports its new state. `f` returns whether it changed the task,
+/// which is returned as well.
+async fn update(id: u64, f: impl FnOnce(&mut Task) -> bool) -> Result<bool, String> {
+    let event = {
+        let mut queue = QUEUE.lock().await;
+        let task = queue.tasks.iter_mut().find(|task| task.id == id).ok_or_else(|| format!("Task {} does not exist", id))?;
+
+        if !f(task) {
+            return Ok(false);
+        }
+
+        let event = task.event();
+
+        if task.is_finished() {
+            let finished = queue.tasks.iter().filter(|task| task.is_finished()).count();
+            let mut excess = finished.saturating_sub(MAX_FINISHED);
+
+            queue.tasks.retain(|task| {
+                let drop = excess > 0 && task.is_finished();
+                excess -= drop as usize;
+                !drop
+            });
+        }
+
+        save(&queue).await?;
+        event
+    };
+
+    report(event).await;
+
+    Ok(true)
+}
+
+/// Adds a task, persists it and reports it.
+async fn add(job: Job, state: TaskState) -> Result<u64, String> {
+    let (id, event) = {
+        let mut queue = QUEUE.lock().await;
+
+        let id = queue.next_id;
+        queue.next_id += 1;
+
+        let task = Task {
+            id,
+            job,
+            attempts: (state == TaskState::Running) as u32,
+            state,
+            progress: None,
+            checkpoint: None,
+        };
+
+        let event = task.event();
+        queue.tasks.push(task);
+
+        save(&queue).await?;
+        (id, event)
+    };
+
+    report(event).await;
+
+    Ok(id)
+}
+
+/// Queues a job for the task worker service, returning the id of its task. If the same job is
+/// queued or running already, the id of that task is returned instead.
+pub async fn submit(job: Job) -> Result<u64, String> {
+    if let Some(task) = QUEUE.lock().await.tasks.iter().find(|task| !task.is_finished() && task.job == job) {
+        return Ok(task.id);
+    }
+
+    let id = add(job, TaskState::Queued).await?;
+    QUEUED.notify_one();
+
+    Ok(id)
+}
+
+/// Runs a job right away and waits for it to finish, as a task that is resumed by the task worker
+/// service if the daemon stops before it finished. Unlike queued tasks, it fails on the first error.
+pub async fn run(job: Job) -> Result<(), String> {
+    let id = add(job.clone(), TaskState::Running).await?;
+
+    execute(id, job, false).await
+}
+
+/// Marks the next queued task as running and returns it, or waits until a task is queued.
+pub async fn next() -> (u64, Job) {
+    loop {
+        let next = {
+            let queue = QUEUE.lock().await;
+
+            queue.tasks.iter().find(|task| task.state == TaskState::Queued).map(|task| (task.id, task.job.clone()))
+        };
+
+        let Some((id, job)) = next else {
+            QUEUED.notified().await;
+            continue;
+        };
+
+        // the task might have been cancelled in the meantime
+        let started = update(id, |task| {
+            if task.state != TaskState::Queued {
+                return false;
+            }
+
+            task.state = TaskState::Running;
+            task.progress = None;
+            task.attempts += 1;
+            true
+        }).await;
+
+        match started {
+            Ok(true) => return (id, job),
+            Ok(false) => continue,
+            Err(e) => {
+                warn!("Could not start task {}: {}", id, e);
+                return (id, job);
+            },
+        }
+    }
+}
+
+/// Cancels a queued or running task.
+pub async fn cancel(id: u64) -> Result<(), String> {
+    if let Some(token) = RUNNING.lock().await.get(&id) {
+        token.cancel();
+        return Ok(());
+    }
+
+    let cancelled = update(id, |task| {
+        if task.state != TaskState::Queued {
+            return false;
+        }
+
+        task.state = TaskState::Cancelled;
+        true
+    }).await?;
+
+    if !cancelled {
+        return Err(format!("Task {} is not queued or running", id));
+    }
+
+    Ok(())
+}
+
+/// Returns the state of all tasks.
+pub async fn list() -> Vec<TaskEvent> {
+    QUEUE.lock().await.tasks.iter().map(Task::event).collect()
+}
+
+/// Runs the job of a running task and records the result. With `retry`, a failed task is queued
+/// again until it has been attempted `MAX_ATTEMPTS` times.
+pub async fn execute(id: u64, job: Job, retry: bool) -> Result<(), String> {
+    let token = CancellationToken::new();
+    RUNNING.lock().await.insert(id, token.clone());
+
+    let progress = Progress {
+        id,
+    };
+
+    let res = select! {
+        _ = token.cancelled() => Err(format!("Task {} was cancelled", id)),
+        res = job.run(&progress) => res,
+    };
+
+    RUNNING.lock().await.remove(&id);
+
+    let recorded = update(id, |task| {
+        task.state = match &res {
+            _ if token.is_cancelled() => TaskState::Cancelled,
+            Ok(()) => TaskState::Done,
+            Err(_) if retry && task.attempts < MAX_ATTEMPTS => TaskState::Queued,
+            Err(e) => TaskState::Failed {
+                error: e.clone(),
+            },
+        };
+        true
+    }).await;
+
+    if let Err(e) = recorded {
+        warn!("Could not record result of task {}: {}", id, e);
+    }
+
+    res
+}
@@ -0,0 +1,313 @@
+use std::{collections::BTreeMap, fmt::Write, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, Mutex}, time::Duration};
+
+use packet::events::{NodeStats, ServerStatusEvent, ServerStatusType};
+use tokio::select;
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+/// Whether the daemon is connected to the server.
+pub static CONNECTED: AtomicBool = AtomicBool::new(false);
+
+/// Whether the daemon is authenticated with the server.
+pub static AUTHENTICATED: AtomicBool = AtomicBool::new(false);
+
+/// The amount of times the daemon reconnected to the server, after a connection ended or could
+/// not be established.
+pub static RECONNECTS: AtomicU64 = AtomicU64::new(0);
+
+/// The amount of messages received from the server.
+pub static PACKETS_RECEIVED: AtomicU64 = AtomicU64::new(0);
+
+/// The amount of messages sent to the server.
+pub static PACKETS_SENT: AtomicU64 = AtomicU64::new(0);
+
+/// The amount of incoming packets waiting to be handled, see `packets::handle`.
+pub static PACKETS_WAITING: AtomicU64 = AtomicU64::new(0);
+
+/// The most incoming packets waiting at the same time, since the counters were last logged.
+pub static PACKETS_WAITING_PEAK: AtomicU64 = AtomicU64::new(0);
+
+/// Upper bounds (in seconds) of the buckets of `DOCKER_LATENCY`.
+const LATENCY_BUCKETS: [f64; 10] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 10.0];
+
+/// Durations of Docker API calls, see `Histogram`.
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        for (bucket, le) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+/// Durations of Docker API calls, by call, see `docker::timed`.
+static DOCKER_LATENCY: Mutex<BTreeMap<&'static str, Histogram>> = Mutex::new(BTreeMap::new());
+
+/// Whether each service is running, by name, see `services::start`.
+static SERVICES: Mutex<BTreeMap<&'static str, bool>> = Mutex::new(BTreeMap::new());
+
+/// Latest stats of the node, see `services::node_status`.
+static NODE: Mutex<Option<NodeStats>> = Mutex::new(None);
+
+/// Latest status of every server, by ID, see `services::server_status`.
+static SERVERS: Mutex<BTreeMap<u32, ServerStatusEvent>> = Mutex::new(BTreeMap::new());
+
+/// Records the duration of a Docker API call.
+pub fn observe_docker(call: &'static str, duration: Duration) {
+    if let Ok(mut latency) = DOCKER_LATENCY.lock() {
+        latency.entry(call).or_default().observe(duration);
+    }
+}
+
+/// Records whether a service is running.
+pub fn set_service_up(service: &'static str, up: bool) {
+    if let Ok(mut services) = SERVICES.lock() {
+        services.insert(service, up);
+    }
+}
+
+/// Records the latest stats of the node.
+pub fn set_node(stats: NodeStats) {
+    if let Ok(mut node) = NODE.lock() {
+        node.replace(stats);
+    }
+}
+
+/// Records the latest status of a server.
+pub fn set_server(status: &ServerStatusEvent) {
+    if let Ok(mut servers) = SERVERS.lock() {
+        servers.insert(status.server, status.clone());
+    }
+}
+
+/// Forgets a server, once its status isn't collected anymore.
+pub fn remove_server(id: u32) {
+    if let Ok(mut servers) = SERVERS.lock() {
+        servers.remove(&id);
+    }
+}
+
+/// Renders the usage of the node and its servers. Usage is reported in gigabytes by the status
+/// services, but exported in bytes as Prometheus expects.
+fn render_usage(out: &mut String) {
+    const GB: f64 = 1_073_741_824.0;
+
+    if let Ok(node) = NODE.lock()
+        && let Some(node) = node.as_ref() {
+        let mut gauge = |name: &str, help: &str, value: f64| {
+            let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} gauge\n{name} {value}\n");
+        };
+
+        gauge("aesterisk_node_cpu_usage_percent", "CPU usage of the node, out of 100% for all cores together", node.cpu);
+        gauge("aesterisk_node_memory_used_bytes", "Memory used on the node", node.used_memory * GB);
+        gauge("aesterisk_node_memory_total_bytes", "Memory of the node", node.total_memory * GB);
+        gauge("aesterisk_node_storage_used_bytes", "Storage used on the non-removable disks of the node", node.used_storage * GB);
+        gauge("aesterisk_node_storage_total_bytes", "Storage of the non-removable disks of the node", node.total_storage * GB);
+    }
+
+    let Ok(servers) = SERVERS.lock() else {
+        return;
+    };
+
+    if servers.is_empty() {
+        return;
+    }
+
+    let statuses = [
+        (ServerStatusType::Healthy, "healthy"),
+        (ServerStatusType::Starting, "starting"),
+        (ServerStatusType::Restarting, "restarting"),
+        (ServerStatusType::Stopping, "stopping"),
+        (ServerStatusType::Stopped, "stopped"),
+        (ServerStatusType::Unhealthy, "unhealthy"),
+    ];
+
+    out.push_str("# HELP aesterisk_server_status Status of a server, 1 for its current status\n# TYPE aesterisk_server_status gauge\n");
+    for (id, server) in servers.iter() {
+        for (status, name) in statuses.iter() {
+            let _ = writeln!(out, "aesterisk_server_status{{server=\"{}\",status=\"{}\"}} {}", id, name, (server.status == *status) as u8);
+        }
+    }
+
+    out.push_str("# HELP aesterisk_server_in_maintenance Whether a server is in a maintenance window\n# TYPE aesterisk_server_in_maintenance gauge\n");
+    for (id, server) in servers.iter() {
+        let _ = writeln!(out, "aesterisk_server_in_maintenance{{server=\"{}\"}} {}", id, server.in_maintenance as u8);
+    }
+
+    out.push_str("# HELP aesterisk_server_restarts_total Restarts of the container of a server\n# TYPE aesterisk_server_restarts_total counter\n");
+    for (id, server) in servers.iter() {
+        let _ = writeln!(out, "aesterisk_server_restarts_total{{server=\"{}\"}} {}", id, server.restart_count);
+    }
+
+    // CPU and memory usage are missing while a server is stopped, or if Docker can't report them.
+    // CPU usage is converted to the per-core convention, so servers can be compared
+    out.push_str("# HELP aesterisk_server_cpu_usage_percent CPU usage of a server, out of 100% per core\n# TYPE aesterisk_server_cpu_usage_percent gauge\n");
+    for (id, server) in servers.iter() {
+        if let Some(cpu) = server.cpu_per_core() {
+            let _ = writeln!(out, "aesterisk_server_cpu_usage_percent{{server=\"{}\"}} {}", id, cpu);
+        }
+    }
+
+    out.push_str("# HELP aesterisk_server_memory_used_bytes Memory used by a server\n# TYPE aesterisk_server_memory_used_bytes gauge\n");
+    for (id, server) in servers.iter() {
+        if let Some(memory) = &server.memory {
+            let _ = writeln!(out, "aesterisk_server_memory_used_bytes{{server=\"{}\"}} {}", id, memory.used * GB);
+        }
+    }
+
+    out.push_str("# HELP aesterisk_server_memory_limit_bytes Memory available to a server\n# TYPE aesterisk_server_memory_limit_bytes gauge\n");
+    for (id, server) in servers.iter() {
+        if let Some(memory) = &server.memory {
+            let _ = writeln!(out, "aesterisk_server_memory_limit_bytes{{server=\"{}\"}} {}", id, memory.total * GB);
+        }
+    }
+
+    out.push_str("# HELP aesterisk_server_storage_used_bytes Size of the root filesystem of a server\n# TYPE aesterisk_server_storage_used_bytes gauge\n");
+    for (id, server) in servers.iter() {
+        if let Some(storage) = &server.storage {
+            let _ = writeln!(out, "aesterisk_server_storage_used_bytes{{server=\"{}\"}} {}", id, storage.used * GB);
+        }
+    }
+}
+
+/// Renders the metrics in the Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+
+    let mut metric = |name: &str, kind: &str, help: &str, value: u64| {
+        let _ = write!(out, "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n");
+    };
+
+    metric("aesterisk_daemon_connected", "gauge", "Whether the daemon is connected to the server", CONNECTED.load(Ordering::Relaxed) as u64);
+    metric("aesterisk_daemon_authenticated", "gauge", "Whether the daemon is authenticated with the server", AUTHENTICATED.load(Ordering::Relaxed) as u64);
+    metric("aesterisk_daemon_reconnects_total", "counter", "Reconnections to the server", RECONNECTS.load(Ordering::Relaxed));
+    metric("aesterisk_daemon_packets_received_total", "counter", "Messages received from the server", PACKETS_RECEIVED.load(Ordering::Relaxed));
+    metric("aesterisk_daemon_packets_sent_total", "counter", "Messages sent to the server", PACKETS_SENT.load(Ordering::Relaxed));
+    metric("aesterisk_daemon_packets_waiting", "gauge", "Incoming packets waiting to be handled", PACKETS_WAITING.load(Ordering::Relaxed));
+
+    out.push_str("# HELP aesterisk_daemon_service_up Whether a service of the daemon is running\n# TYPE aesterisk_daemon_service_up gauge\n");
+    if let Ok(services) = SERVICES.lock() {
+        for (service, up) in services.iter() {
+            let _ = writeln!(out, "aesterisk_daemon_service_up{{service=\"{}\"}} {}", service, *up as u8);
+        }
+    }
+
+    out.push_str("# HELP aesterisk_daemon_docker_request_duration_seconds Duration of Docker API calls\n# TYPE aesterisk_daemon_docker_request_duration_seconds histogram\n");
+    if let Ok(latency) = DOCKER_LATENCY.lock() {
+        for (call, histogram) in latency.iter() {
+            for (le, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                let _ = writeln!(out, "aesterisk_daemon_docker_request_duration_seconds_bucket{{call=\"{}\",le=\"{}\"}} {}", call, le, count);
+            }
+
+            let _ = writeln!(out, "aesterisk_daemon_docker_request_duration_seconds_bucket{{call=\"{}\",le=\"+Inf\"}} {}", call, histogram.count);
+            let _ = writeln!(out, "aesterisk_daemon_docker_request_duration_seconds_sum{{call=\"{}\"}} {}", call, histogram.sum);
+            let _ = writeln!(out, "aesterisk_daemon_docker_request_duration_seconds_count{{call=\"{}\"}} {}", call, histogram.count);
+        }
+    }
+
+    render_usage(&mut out);
+
+    out
+}
+
+/// Periodically logs the counters.
+pub async fn run(token: CancellationToken) -> Result<(), String> {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        select! {
+            _ = token.cancelled() => {
+                warn!("Stopping telemetry service");
+                return Ok(());
+            },
+            _ = interval.tick() => (),
+        }
+
+        debug!("Packets waiting: {} (peak {})", PACKETS_WAITING.load(Ordering::Relaxed), PACKETS_WAITING_PEAK.swap(0, Ordering::Relaxed));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use packet::events::{CpuConvention, Stats};
+
+    use super::*;
+
+    fn server(id: u32, status: ServerStatusType, cpu: Option<f64>, cpu_convention: CpuConvention) -> ServerStatusEvent {
+        ServerStatusEvent {
+            server: id,
+            status,
+            memory: cpu.map(|_| Stats {
+                used: 0.5,
+                total: 2.0,
+            }),
+            cpu: cpu.map(|used| Stats {
+                used,
+                total: 100.0,
+            }),
+            storage: None,
+            in_maintenance: false,
+            reason: None,
+            started_at: None,
+            restart_count: 3,
+            cpu_convention,
+            cores: Some(4),
+        }
+    }
+
+    #[test]
+    fn usage() {
+        set_node(NodeStats {
+            used_memory: 1.0,
+            total_memory: 4.0,
+            cpu: 12.5,
+            used_storage: 10.0,
+            total_storage: 100.0,
+        });
+
+        set_server(&server(1, ServerStatusType::Healthy, Some(150.0), CpuConvention::PerCore));
+        set_server(&server(2, ServerStatusType::Healthy, Some(25.0), CpuConvention::AllCores));
+        set_server(&server(3, ServerStatusType::Stopped, None, CpuConvention::PerCore));
+
+        let mut out = String::new();
+        render_usage(&mut out);
+        let lines = out.lines().collect::<Vec<_>>();
+
+        for line in [
+            "# TYPE aesterisk_node_cpu_usage_percent gauge",
+            "aesterisk_node_cpu_usage_percent 12.5",
+            "aesterisk_node_memory_used_bytes 1073741824",
+            "aesterisk_node_storage_total_bytes 107374182400",
+            "aesterisk_server_status{server=\"1\",status=\"healthy\"} 1",
+            "aesterisk_server_status{server=\"3\",status=\"healthy\"} 0",
+            "aesterisk_server_status{server=\"3\",status=\"stopped\"} 1",
+            "aesterisk_server_restarts_total{server=\"2\"} 3",
+            // converted from the all-cores convention
+            "aesterisk_server_cpu_usage_percent{server=\"1\"} 150",
+            "aesterisk_server_cpu_usage_percent{server=\"2\"} 100",
+            "aesterisk_server_memory_limit_bytes{server=\"1\"} 2147483648",
+        ] {
+            assert!(lines.contains(&line), "missing {:?} in:\n{}", line, out);
+        }
+
+        // stopped servers have no usage
+        assert!(!out.contains("aesterisk_server_cpu_usage_percent{server=\"3\"}"));
+        assert!(!out.contains("aesterisk_server_memory_used_bytes{server=\"3\"}"));
+
+        // every metric has a single HELP and TYPE
+        assert_eq!(lines.iter().filter(|line| line.starts_with("# TYPE aesterisk_server_status ")).count(), 1);
+    }
+}
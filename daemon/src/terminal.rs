@@ -0,0 +1,313 @@
+use std::collections::HashMap;
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use bollard::exec::{CreateExecOptions, ResizeExecOptions, StartExecOptions, StartExecResults};
+use futures_util::StreamExt;
+use lazy_static::lazy_static;
+use packet::{daemon_server::{terminal_close::DSTerminalClosePacket, terminal_output::DSTerminalOutputPacket}, features::Feature, flow::Window, server_daemon::terminal_input::SDTerminalInputPacket, terminal::{Reorder, TerminalSize}, Packet};
+use tokio::{io::AsyncWriteExt, select, sync::{mpsc, Mutex}};
+use tokio_tungstenite::tungstenite::Message;
+use tokio_util::sync::CancellationToken;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{docker, encryption, FEATURES, SENDER};
+
+/// Command run in a terminal if the web client didn't ask for one
+const DEFAULT_COMMAND: &str = "/bin/sh";
+
+/// What the web client sends to a terminal
+#[derive(Debug, PartialEq, Eq)]
+enum Input {
+    /// Bytes written to the TTY
+    Data(Vec<u8>),
+    /// Credits for sending more output, see `packet::flow`
+    Credits(u32),
+    Resize(TerminalSize),
+}
+
+/// A terminal opened by a web client, running an exec in one of the containers once it started.
+struct Session {
+    input: mpsc::UnboundedSender<Input>,
+    /// Input packets waiting for the ones before them
+    reorder: Reorder<SDTerminalInputPacket>,
+    token: CancellationToken,
+}
+
+lazy_static! {
+    static ref SESSIONS: Mutex<HashMap<Uuid, Session>> = Mutex::new(HashMap::new());
+}
+
+async fn send(packet: Packet) -> Result<(), String> {
+    SENDER.lock().await.as_ref().ok_or("sender is not available")?.unbounded_send(
+        Message::Text(
+            encryption::encrypt_packet(packet)?
+        )
+    ).map_err(|e| format!("Could not send packet: {}", e))
+}
+
+async fn resize(exec: &str, size: TerminalSize) -> Result<(), String> {
+    docker::timed("resize_exec", docker::get()?.resize_exec(exec, ResizeExecOptions {
+        height: size.rows,
+        width: size.cols,
+    })).await.map_err(|e| format!("Could not resize terminal: {}", e))
+}
+
+/// Opens a terminal into a server. If it can't be opened, the server is told why.
+pub async fn open(session: Uuid, server: u32, size: TerminalSize, command: Vec<String>) -> Result<(), String> {
+    if let Err(e) = start(session, server, size, command).await {
+        warn!("Could not open terminal {}: {}", session, e);
+
+        send(DSTerminalClosePacket {
+            session,
+            exit_code: None,
+            error: Some(e),
+            seq: None,
+        }.to_packet()?).await?;
+    }
+
+    Ok(())
+}
+
+/// Adds a session for a terminal that is being opened, so its input is queued until it runs.
+/// Fails if the session is already open.
+async fn reserve(session: Uuid) -> Result<(mpsc::UnboundedReceiver<Input>, CancellationToken), String> {
+    let mut sessions = SESSIONS.lock().await;
+
+    if sessions.contains_key(&session) {
+        return Err(format!("Terminal {} is already open", session));
+    }
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let token = CancellationToken::new();
+
+    sessions.insert(session, Session {
+        input: tx,
+        reorder: Reorder::default(),
+        token: token.clone(),
+    });
+
+    Ok((rx, token))
+}
+
+/// Starts `command` in a TTY in the container of a server, and relays its input and output until
+/// it exits or the terminal is closed.
+async fn start(session: Uuid, server: u32, size: TerminalSize, command: Vec<String>) -> Result<(), String> {
+    let (rx, token) = reserve(session).await?;
+
+    let res = relay(session, server, size, command, rx, token).await;
+
+    if res.is_err() {
+        SESSIONS.lock().await.remove(&session);
+    }
+
+    res
+}
+
+/// Starts the exec of a reserved session, and spawns the task relaying its input and output.
+async fn relay(session: Uuid, server: u32, size: TerminalSize, command: Vec<String>, mut rx: mpsc::UnboundedReceiver<Input>, token: CancellationToken) -> Result<(), String> {
+    let command = match command.is_empty() {
+        true => vec![DEFAULT_COMMAND.to_string()],
+        false => command,
+    };
+
+    let exec = docker::timed("create_exec", docker::get()?.create_exec(&format!("ae_sv_{}", server), CreateExecOptions {
+        attach_stdin: Some(true),
+        attach_stdout: Some(true),
+        attach_stderr: Some(true),
+        tty: Some(true),
+        cmd: Some(command),
+        ..Default::default()
+    })).await.map_err(|e| format!("Could not create exec: {}", e))?.id;
+
+    let res = docker::timed("start_exec", docker::get()?.start_exec(&exec, Some(StartExecOptions {
+        detach: false,
+        tty: true,
+        output_capacity: None,
+    }))).await.map_err(|e| format!("Could not start exec: {}", e))?;
+
+    let StartExecResults::Attached { mut output, mut input } = res else {
+        return Err("Exec was started detached".to_string());
+    };
+
+    // the TTY only exists once the exec is running
+    resize(&exec, size).await?;
+
+    // without flow control, output is read and sent as fast as the TTY produces it
+    let mut window = FEATURES.read().await.has(Feature::TerminalFlowControl).then(Window::default);
+
+    // numbers the output and the close packet, which the server puts back into order
+    let mut seq = 0;
+
+    info!("Opened terminal {} into server {}", session, server);
+
+    tokio::spawn(async move {
+        let res = async {
+            loop {
+                select! {
+                    // the TTY isn't read while there are no credits, so the process blocks on writing
+                    // output rather than it queueing up in the server
+                    chunk = output.next(), if window.is_none_or(|window| window.credits() > 0) => match chunk {
+                        Some(Ok(chunk)) => {
+                            if let Some(window) = window.as_mut() {
+                                window.take();
+                            }
+
+                            send(DSTerminalOutputPacket {
+                                session,
+                                data: BASE64.encode(chunk.into_bytes()),
+                                seq: Some(seq),
+                            }.to_packet()?).await?;
+
+                            seq += 1;
+                        },
+                        Some(Err(e)) => return Err(format!("Could not read terminal output: {}", e)),
+                        None => return Ok(()),
+                    },
+                    data = rx.recv() => match data {
+                        Some(Input::Data(data)) => input.write_all(&data).await.map_err(|e| format!("Could not write terminal input: {}", e))?,
+                        Some(Input::Credits(credits)) => if let Some(window) = window.as_mut() {
+                            window.grant(credits);
+                        },
+                        Some(Input::Resize(size)) => if let Err(e) = resize(&exec, size).await {
+                            warn!("Could not resize terminal {}: {}", session, e);
+                        },
+                        None => return Ok(()),
+                    },
+                    // dropping the input closes stdin, which ends shells
+                    _ = token.cancelled() => return Ok(()),
+                }
+            }
+        }.await;
+
+        info!("Closed terminal {}", session);
+
+        // terminals closed by the server are already gone
+        if SESSIONS.lock().await.remove(&session).is_none() {
+            return;
+        }
+
+        let exit_code = match docker::get() {
+            Ok(docker) => docker::timed("inspect_exec", docker.inspect_exec(&exec)).await.ok().and_then(|exec| exec.exit_code),
+            Err(_) => None,
+        };
+
+        let packet = DSTerminalClosePacket {
+            session,
+            exit_code,
+            error: res.err(),
+            seq: Some(seq),
+        }.to_packet();
+
+        if let Err(e) = async { send(packet?).await }.await {
+            warn!("Could not report closed terminal {}: {}", session, e);
+        }
+    });
+
+    Ok(())
+}
+
+/// Writes input of the web client to a terminal, grants it credits for sending more output, and
+/// resizes its TTY if requested, in the order the server sent the input.
+pub async fn input(packet: SDTerminalInputPacket) -> Result<(), String> {
+    let session = packet.session;
+
+    let mut sessions = SESSIONS.lock().await;
+    let terminal = sessions.get_mut(&session).ok_or(format!("Terminal {} is not open", session))?;
+
+    for packet in terminal.reorder.push(packet.seq, packet)? {
+        let mut inputs = Vec::new();
+
+        if !packet.data.is_empty() {
+            inputs.push(Input::Data(BASE64.decode(packet.data).map_err(|e| format!("Invalid terminal input: {}", e))?));
+        }
+
+        if packet.credits > 0 {
+            inputs.push(Input::Credits(packet.credits));
+        }
+
+        if let Some(size) = packet.resize {
+            inputs.push(Input::Resize(size));
+        }
+
+        for input in inputs {
+            terminal.input.send(input).map_err(|_| format!("Terminal {} is closing", session))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Closes a terminal on request of the server.
+pub async fn close(session: Uuid) {
+    if let Some(terminal) = SESSIONS.lock().await.remove(&session) {
+        terminal.token.cancel();
+    }
+}
+
+/// Closes all terminals, as the server forgets about them once the connection is lost.
+pub async fn close_all() {
+    for (_, terminal) in SESSIONS.lock().await.drain() {
+        terminal.token.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn input_packet(session: Uuid, data: &[u8], seq: u64) -> SDTerminalInputPacket {
+        SDTerminalInputPacket {
+            session,
+            data: BASE64.encode(data),
+            resize: None,
+            credits: 0,
+            seq: Some(seq),
+        }
+    }
+
+    #[tokio::test]
+    async fn sessions_reserved_once() {
+        let session = Uuid::from_u128(1);
+
+        let _reserved = reserve(session).await.expect("could not reserve session");
+        assert!(reserve(session).await.is_err());
+
+        close(session).await;
+        assert!(reserve(session).await.is_ok());
+
+        close(session).await;
+    }
+
+    #[tokio::test]
+    async fn input_written_in_order() {
+        let session = Uuid::from_u128(2);
+        let (mut rx, _token) = reserve(session).await.expect("could not reserve session");
+
+        input(input_packet(session, b"b", 1)).await.expect("could not write input");
+        input(SDTerminalInputPacket {
+            resize: Some(TerminalSize {
+                cols: 80,
+                rows: 24,
+            }),
+            credits: 5,
+            ..input_packet(session, b"c", 2)
+        }).await.expect("could not write input");
+        assert!(rx.try_recv().is_err());
+
+        input(input_packet(session, b"a", 0)).await.expect("could not write input");
+
+        assert_eq!(rx.recv().await, Some(Input::Data(b"a".to_vec())));
+        assert_eq!(rx.recv().await, Some(Input::Data(b"b".to_vec())));
+        assert_eq!(rx.recv().await, Some(Input::Data(b"c".to_vec())));
+        assert_eq!(rx.recv().await, Some(Input::Credits(5)));
+        assert_eq!(rx.recv().await, Some(Input::Resize(TerminalSize {
+            cols: 80,
+            rows: 24,
+        })));
+
+        assert!(input(input_packet(session, b"a", 0)).await.is_err());
+
+        close(session).await;
+    }
+}
@@ -0,0 +1,116 @@
+-- The tables of the Postgres schema the server needs to authenticate users and daemons and to
+-- sync nodes, without the schema prefix SQLite doesn't support. UUIDs are stored as hyphenated
+-- text, and the healthcheck test of a tag as a JSON array.
+
+CREATE TABLE teams (
+	team_id INTEGER PRIMARY KEY NOT NULL,
+	team_name TEXT NOT NULL,
+	team_created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE TABLE users (
+	user_id INTEGER PRIMARY KEY NOT NULL,
+	user_team INTEGER NOT NULL,
+	user_joined_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+	user_public_key TEXT NOT NULL,
+	CONSTRAINT fk_teams FOREIGN KEY(user_team) REFERENCES teams(team_id)
+);
+
+CREATE INDEX ix_users_team ON users(user_team);
+
+CREATE TABLE nodes (
+	node_id INTEGER PRIMARY KEY NOT NULL,
+	node_name TEXT NOT NULL,
+	node_public_key TEXT NOT NULL,
+	node_uuid TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE team_nodes (
+	team_id INTEGER NOT NULL,
+	node_id INTEGER NOT NULL UNIQUE,
+	CONSTRAINT fk_teams FOREIGN KEY(team_id) REFERENCES teams(team_id),
+	CONSTRAINT fk_nodes FOREIGN KEY(node_id) REFERENCES nodes(node_id),
+	PRIMARY KEY(team_id, node_id)
+);
+
+CREATE TABLE networks (
+	network_id INTEGER PRIMARY KEY NOT NULL,
+	network_name TEXT NOT NULL,
+	network_local_ip SMALLINT NOT NULL
+);
+
+CREATE TABLE node_networks (
+	node_id INTEGER NOT NULL,
+	network_id INTEGER NOT NULL UNIQUE,
+	CONSTRAINT fk_nodes FOREIGN KEY(node_id) REFERENCES nodes(node_id),
+	CONSTRAINT fk_networks FOREIGN KEY(network_id) REFERENCES networks(network_id),
+	PRIMARY KEY(node_id, network_id)
+);
+
+CREATE TABLE tags (
+	tag_id INTEGER PRIMARY KEY NOT NULL,
+	tag_name TEXT NOT NULL,
+	tag_image TEXT NOT NULL,
+	tag_docker_tags TEXT NOT NULL,
+	tag_healthcheck_test TEXT NOT NULL,
+	tag_healthcheck_interval INTEGER NOT NULL,
+	tag_healthcheck_timeout INTEGER NOT NULL,
+	tag_healthcheck_retries INTEGER NOT NULL,
+	tag_team INTEGER DEFAULT NULL,
+	CONSTRAINT fk_teams FOREIGN KEY(tag_team) REFERENCES teams(team_id)
+);
+
+CREATE TABLE servers (
+	server_id INTEGER PRIMARY KEY NOT NULL,
+	server_name TEXT NOT NULL,
+	server_tag INTEGER NOT NULL,
+	CONSTRAINT fk_tags FOREIGN KEY(server_tag) REFERENCES tags(tag_id)
+);
+
+CREATE TABLE node_servers (
+	node_id INTEGER NOT NULL,
+	server_id INTEGER NOT NULL UNIQUE,
+	CONSTRAINT fk_nodes FOREIGN KEY(node_id) REFERENCES nodes(node_id),
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES servers(server_id),
+	PRIMARY KEY(node_id, server_id)
+);
+
+CREATE TABLE ports (
+	port_id INTEGER PRIMARY KEY NOT NULL,
+	port_port INTEGER NOT NULL,
+	port_protocol SMALLINT NOT NULL,
+	port_mapped INTEGER NOT NULL
+);
+
+CREATE TABLE server_ports (
+	server_id INTEGER NOT NULL,
+	port_id INTEGER NOT NULL UNIQUE,
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES servers(server_id),
+	CONSTRAINT fk_ports FOREIGN KEY(port_id) REFERENCES ports(port_id),
+	PRIMARY KEY(server_id, port_id)
+);
+
+CREATE TABLE envs (
+	env_id INTEGER PRIMARY KEY NOT NULL,
+	env_key TEXT NOT NULL,
+	env_value TEXT NOT NULL
+);
+
+CREATE TABLE server_envs (
+	server_id INTEGER NOT NULL,
+	env_id INTEGER NOT NULL UNIQUE,
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES servers(server_id),
+	CONSTRAINT fk_envs FOREIGN KEY(env_id) REFERENCES envs(env_id),
+	PRIMARY KEY(server_id, env_id)
+);
+
+CREATE TABLE server_networks (
+	server_id INTEGER NOT NULL,
+	network_id INTEGER NOT NULL,
+	local_ip SMALLINT NOT NULL,
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES servers(server_id),
+	CONSTRAINT fk_networks FOREIGN KEY(network_id) REFERENCES networks(network_id),
+	PRIMARY KEY(server_id, network_id)
+);
+
+CREATE INDEX ix_server_networks_network ON server_networks(network_id);
@@ -0,0 +1,213 @@
+-- The tables and columns the Postgres schema gained after 1_init.sql, so SQLite databases store
+-- everything the server syncs to daemons and records about them. Arrays are stored as JSON arrays,
+-- UUIDs as hyphenated text.
+
+ALTER TABLE envs ADD COLUMN env_secret BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE mounts (
+	mount_id INTEGER PRIMARY KEY NOT NULL,
+	mount_container_path TEXT NOT NULL,
+	mount_host_path TEXT NOT NULL
+);
+
+CREATE TABLE tag_mounts (
+	tag_id INTEGER NOT NULL,
+	mount_id INTEGER NOT NULL UNIQUE,
+	CONSTRAINT fk_tags FOREIGN KEY(tag_id) REFERENCES tags(tag_id),
+	CONSTRAINT fk_mounts FOREIGN KEY(mount_id) REFERENCES mounts(mount_id),
+	PRIMARY KEY(tag_id, mount_id)
+);
+
+CREATE TABLE env_defs (
+	env_def_id INTEGER PRIMARY KEY NOT NULL,
+	env_def_name TEXT NOT NULL,
+	env_def_description TEXT NOT NULL,
+	env_def_key TEXT NOT NULL,
+	env_def_secret BOOLEAN NOT NULL,
+	env_def_required BOOLEAN NOT NULL,
+	env_def_type SMALLINT NOT NULL,
+	env_def_default_value TEXT DEFAULT NULL,
+	env_def_regex TEXT DEFAULT NULL,
+	env_def_min INTEGER DEFAULT NULL,
+	env_def_max INTEGER DEFAULT NULL,
+	env_def_trim BOOLEAN NOT NULL
+);
+
+CREATE TABLE tag_env_defs (
+	tag_id INTEGER NOT NULL,
+	env_def_id INTEGER NOT NULL UNIQUE,
+	CONSTRAINT fk_tags FOREIGN KEY(tag_id) REFERENCES tags(tag_id),
+	CONSTRAINT fk_env_defs FOREIGN KEY(env_def_id) REFERENCES env_defs(env_def_id),
+	PRIMARY KEY(tag_id, env_def_id)
+);
+
+-- uploaded build contexts, build_context_hash is the hex encoded SHA-256 hash of the tar archive
+CREATE TABLE build_contexts (
+	build_context_hash TEXT PRIMARY KEY NOT NULL,
+	build_context_data BLOB NOT NULL
+);
+
+ALTER TABLE tags ADD COLUMN tag_build_git_url TEXT DEFAULT NULL;
+ALTER TABLE tags ADD COLUMN tag_build_context_hash TEXT DEFAULT NULL REFERENCES build_contexts(build_context_hash);
+ALTER TABLE tags ADD COLUMN tag_build_dockerfile TEXT NOT NULL DEFAULT 'Dockerfile';
+
+-- server_isolation_allowlist is a JSON array of strings
+ALTER TABLE servers ADD COLUMN server_isolation_policy SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE servers ADD COLUMN server_isolation_allowlist TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE servers ADD COLUMN server_update_strategy SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE servers ADD COLUMN server_memory_reservation INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE servers ADD COLUMN server_storage_reservation INTEGER NOT NULL DEFAULT 0;
+
+CREATE TABLE server_dependencies (
+	server_id INTEGER NOT NULL,
+	dependency_server_id INTEGER NOT NULL,
+	dependency_wait_healthy BOOLEAN NOT NULL DEFAULT FALSE,
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES servers(server_id),
+	CONSTRAINT fk_dependency_servers FOREIGN KEY(dependency_server_id) REFERENCES servers(server_id),
+	CONSTRAINT ck_server_dependency_self CHECK (server_id <> dependency_server_id),
+	PRIMARY KEY(server_id, dependency_server_id)
+);
+
+CREATE INDEX ix_server_dependencies_dependency ON server_dependencies(dependency_server_id);
+
+CREATE TABLE server_labels (
+	server_id INTEGER NOT NULL,
+	server_label_key TEXT NOT NULL,
+	server_label_value TEXT NOT NULL,
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES servers(server_id),
+	PRIMARY KEY(server_id, server_label_key)
+);
+
+CREATE TABLE maintenance_windows (
+	maintenance_window_id INTEGER PRIMARY KEY NOT NULL,
+	node_id INTEGER NOT NULL,
+	server_id INTEGER DEFAULT NULL,
+	maintenance_window_cron TEXT NOT NULL,
+	maintenance_window_duration INTEGER NOT NULL,
+	CONSTRAINT fk_nodes FOREIGN KEY(node_id) REFERENCES nodes(node_id),
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES servers(server_id)
+);
+
+CREATE INDEX ix_maintenance_windows_node ON maintenance_windows(node_id);
+
+CREATE TABLE node_groups (
+	node_group_id INTEGER PRIMARY KEY NOT NULL,
+	node_group_name TEXT NOT NULL,
+	node_group_team INTEGER DEFAULT NULL,
+	CONSTRAINT fk_teams FOREIGN KEY(node_group_team) REFERENCES teams(team_id)
+);
+
+CREATE TABLE node_group_members (
+	node_group_id INTEGER NOT NULL,
+	node_id INTEGER NOT NULL,
+	CONSTRAINT fk_node_groups FOREIGN KEY(node_group_id) REFERENCES node_groups(node_group_id),
+	CONSTRAINT fk_nodes FOREIGN KEY(node_id) REFERENCES nodes(node_id),
+	PRIMARY KEY(node_group_id, node_id)
+);
+
+CREATE INDEX ix_node_group_members_node ON node_group_members(node_id);
+
+CREATE TABLE team_quotas (
+	team_id INTEGER PRIMARY KEY NOT NULL,
+	quota_max_daemons INTEGER DEFAULT NULL,
+	quota_max_servers INTEGER DEFAULT NULL,
+	quota_max_memory INTEGER DEFAULT NULL,
+	quota_max_storage INTEGER DEFAULT NULL,
+	CONSTRAINT fk_teams FOREIGN KEY(team_id) REFERENCES teams(team_id)
+);
+
+CREATE TABLE wasm_extensions (
+	wasm_extension_id INTEGER PRIMARY KEY NOT NULL,
+	team_id INTEGER NOT NULL,
+	wasm_extension_name TEXT NOT NULL,
+	wasm_extension_position INTEGER NOT NULL DEFAULT 0,
+	wasm_extension_module BLOB NOT NULL,
+	CONSTRAINT fk_teams FOREIGN KEY(team_id) REFERENCES teams(team_id)
+);
+
+CREATE INDEX ix_wasm_extensions_team ON wasm_extensions(team_id);
+
+-- server_id is 0 for node metrics, metric_time is in seconds since the unix epoch
+CREATE TABLE metrics (
+	metric_time BIGINT NOT NULL,
+	node_uuid TEXT NOT NULL,
+	server_id INTEGER NOT NULL,
+	metric_cpu DOUBLE PRECISION DEFAULT NULL,
+	metric_memory_used DOUBLE PRECISION DEFAULT NULL,
+	metric_memory_total DOUBLE PRECISION DEFAULT NULL,
+	metric_storage_used DOUBLE PRECISION DEFAULT NULL,
+	metric_storage_total DOUBLE PRECISION DEFAULT NULL
+);
+
+CREATE INDEX ix_metrics_node_server_time ON metrics(node_uuid, server_id, metric_time);
+
+CREATE TABLE metrics_hourly (
+	metric_time BIGINT NOT NULL,
+	node_uuid TEXT NOT NULL,
+	server_id INTEGER NOT NULL,
+	metric_cpu DOUBLE PRECISION DEFAULT NULL,
+	metric_memory_used DOUBLE PRECISION DEFAULT NULL,
+	metric_memory_total DOUBLE PRECISION DEFAULT NULL,
+	metric_storage_used DOUBLE PRECISION DEFAULT NULL,
+	metric_storage_total DOUBLE PRECISION DEFAULT NULL,
+	PRIMARY KEY(node_uuid, server_id, metric_time)
+);
+
+CREATE TABLE alert_rules (
+	alert_rule_id INTEGER PRIMARY KEY NOT NULL,
+	node_id INTEGER NOT NULL,
+	server_id INTEGER DEFAULT NULL,
+	alert_rule_metric SMALLINT NOT NULL,
+	alert_rule_threshold DOUBLE PRECISION NOT NULL,
+	alert_rule_duration INTEGER NOT NULL,
+	alert_rule_webhook TEXT DEFAULT NULL,
+	CONSTRAINT fk_nodes FOREIGN KEY(node_id) REFERENCES nodes(node_id),
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES servers(server_id)
+);
+
+CREATE INDEX ix_alert_rules_node ON alert_rules(node_id);
+
+CREATE TABLE connection_sessions (
+	session_id INTEGER PRIMARY KEY NOT NULL,
+	session_peer_type TEXT NOT NULL,
+	node_uuid TEXT DEFAULT NULL,
+	user_id INTEGER DEFAULT NULL,
+	session_addr TEXT NOT NULL,
+	session_connected_at BIGINT NOT NULL,
+	session_disconnected_at BIGINT NOT NULL,
+	session_reason TEXT NOT NULL,
+	session_bytes_in BIGINT NOT NULL,
+	session_bytes_out BIGINT NOT NULL,
+	session_packets_in BIGINT NOT NULL,
+	session_packets_out BIGINT NOT NULL
+);
+
+CREATE INDEX ix_connection_sessions_node_time ON connection_sessions(node_uuid, session_connected_at);
+CREATE INDEX ix_connection_sessions_disconnected ON connection_sessions(session_disconnected_at);
+
+CREATE TABLE notifications (
+	notification_id INTEGER PRIMARY KEY NOT NULL,
+	user_id INTEGER NOT NULL,
+	notification_kind SMALLINT NOT NULL,
+	node_uuid TEXT DEFAULT NULL,
+	server_id INTEGER DEFAULT NULL,
+	notification_message TEXT NOT NULL,
+	notification_created_at BIGINT NOT NULL,
+	notification_read BOOLEAN NOT NULL DEFAULT FALSE,
+	CONSTRAINT fk_users FOREIGN KEY(user_id) REFERENCES users(user_id)
+);
+
+CREATE INDEX ix_notifications_user ON notifications(user_id, notification_id);
+CREATE INDEX ix_notifications_created ON notifications(notification_created_at);
+
+CREATE TABLE events (
+	event_id INTEGER PRIMARY KEY NOT NULL,
+	node_uuid TEXT NOT NULL,
+	event_type SMALLINT NOT NULL,
+	event_seq BIGINT DEFAULT NULL,
+	event_time BIGINT NOT NULL,
+	event_data TEXT NOT NULL
+);
+
+CREATE INDEX ix_events_node_type ON events(node_uuid, event_type, event_id);
+CREATE INDEX ix_events_time ON events(event_time);
@@ -0,0 +1,289 @@
+ALTER TABLE aesterisk.servers
+	ADD COLUMN server_isolation_policy SMALLINT NOT NULL DEFAULT 0,
+	ADD COLUMN server_isolation_allowlist TEXT[] NOT NULL DEFAULT '{}';
+
+-- metrics are keyed by node uuid rather than node id, as that's what the daemons identify with.
+-- server_id is 0 for node metrics. both tables can be turned into TimescaleDB hypertables with
+-- `SELECT create_hypertable('aesterisk.metrics', by_range('metric_time', 86400));` (and the same
+-- for aesterisk.metrics_hourly with 604800), as metric_time is in seconds since the unix epoch.
+CREATE TABLE aesterisk.metrics (
+	metric_time BIGINT NOT NULL,
+	node_uuid UUID NOT NULL,
+	server_id INTEGER NOT NULL,
+	metric_cpu DOUBLE PRECISION DEFAULT NULL,
+	metric_memory_used DOUBLE PRECISION DEFAULT NULL,
+	metric_memory_total DOUBLE PRECISION DEFAULT NULL,
+	metric_storage_used DOUBLE PRECISION DEFAULT NULL,
+	metric_storage_total DOUBLE PRECISION DEFAULT NULL
+);
+
+CREATE INDEX ix_metrics_node_server_time ON aesterisk.metrics(node_uuid, server_id, metric_time);
+
+CREATE TABLE aesterisk.metrics_hourly (
+	metric_time BIGINT NOT NULL,
+	node_uuid UUID NOT NULL,
+	server_id INTEGER NOT NULL,
+	metric_cpu DOUBLE PRECISION DEFAULT NULL,
+	metric_memory_used DOUBLE PRECISION DEFAULT NULL,
+	metric_memory_total DOUBLE PRECISION DEFAULT NULL,
+	metric_storage_used DOUBLE PRECISION DEFAULT NULL,
+	metric_storage_total DOUBLE PRECISION DEFAULT NULL,
+	PRIMARY KEY(node_uuid, server_id, metric_time)
+);
+
+-- alert_rule_metric: 0 = cpu, 1 = memory, 2 = storage. alert_rule_threshold is in percent and
+-- alert_rule_duration in seconds. rules without a server apply to the node itself.
+CREATE TABLE aesterisk.alert_rules (
+	alert_rule_id SERIAL PRIMARY KEY NOT NULL,
+	node_id INTEGER NOT NULL,
+	server_id INTEGER DEFAULT NULL,
+	alert_rule_metric SMALLINT NOT NULL,
+	alert_rule_threshold DOUBLE PRECISION NOT NULL,
+	alert_rule_duration INTEGER NOT NULL,
+	alert_rule_webhook TEXT DEFAULT NULL,
+	CONSTRAINT fk_nodes FOREIGN KEY(node_id) REFERENCES aesterisk.nodes(node_id),
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES aesterisk.servers(server_id)
+);
+
+CREATE INDEX ix_alert_rules_node ON aesterisk.alert_rules(node_id);
+
+CREATE TABLE aesterisk.node_groups (
+	node_group_id SERIAL PRIMARY KEY NOT NULL,
+	node_group_name TEXT NOT NULL,
+	node_group_team INTEGER DEFAULT NULL,
+	CONSTRAINT fk_teams FOREIGN KEY(node_group_team) REFERENCES aesterisk.teams(team_id)
+);
+
+CREATE TABLE aesterisk.node_group_members (
+	node_group_id INTEGER NOT NULL,
+	node_id INTEGER NOT NULL,
+	CONSTRAINT fk_node_groups FOREIGN KEY(node_group_id) REFERENCES aesterisk.node_groups(node_group_id),
+	CONSTRAINT fk_nodes FOREIGN KEY(node_id) REFERENCES aesterisk.nodes(node_id),
+	PRIMARY KEY(node_group_id, node_id)
+);
+
+CREATE INDEX ix_node_group_members_node ON aesterisk.node_group_members(node_id);
+
+-- maintenance_window_cron is a five field cron expression in UTC, maintenance_window_duration is
+-- in minutes. windows without a server apply to the node and all of its servers.
+CREATE TABLE aesterisk.maintenance_windows (
+	maintenance_window_id SERIAL PRIMARY KEY NOT NULL,
+	node_id INTEGER NOT NULL,
+	server_id INTEGER DEFAULT NULL,
+	maintenance_window_cron TEXT NOT NULL,
+	maintenance_window_duration INTEGER NOT NULL,
+	CONSTRAINT fk_nodes FOREIGN KEY(node_id) REFERENCES aesterisk.nodes(node_id),
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES aesterisk.servers(server_id)
+);
+
+CREATE INDEX ix_maintenance_windows_node ON aesterisk.maintenance_windows(node_id);
+
+-- uploaded build contexts, build_context_hash is the hex encoded SHA-256 hash of the tar archive
+CREATE TABLE aesterisk.build_contexts (
+	build_context_hash TEXT PRIMARY KEY NOT NULL,
+	build_context_data BYTEA NOT NULL
+);
+
+-- tags with a git url or build context are built by the daemon instead of pulled, and tagged as
+-- tag_image:tag_docker_tags locally. tag_build_dockerfile is relative to the root of the context.
+ALTER TABLE aesterisk.tags
+	ADD COLUMN tag_build_git_url TEXT DEFAULT NULL,
+	ADD COLUMN tag_build_context_hash TEXT DEFAULT NULL,
+	ADD COLUMN tag_build_dockerfile TEXT NOT NULL DEFAULT 'Dockerfile',
+	ADD CONSTRAINT fk_build_contexts FOREIGN KEY(tag_build_context_hash) REFERENCES aesterisk.build_contexts(build_context_hash),
+	ADD CONSTRAINT ck_tag_build_source CHECK (tag_build_git_url IS NULL OR tag_build_context_hash IS NULL);
+
+-- tags with a team are owned by it and can only be changed by its spec imports, which never touch
+-- tags without a team.
+ALTER TABLE aesterisk.tags
+	ADD COLUMN tag_team INTEGER DEFAULT NULL,
+	ADD CONSTRAINT fk_teams FOREIGN KEY(tag_team) REFERENCES aesterisk.teams(team_id);
+
+CREATE INDEX ix_tags_team_name ON aesterisk.tags(tag_team, tag_name);
+
+-- servers are started after the servers they depend on, which have to be on the same node. if
+-- dependency_wait_healthy is set, the dependency also has to pass its healthcheck first.
+CREATE TABLE aesterisk.server_dependencies (
+	server_id INTEGER NOT NULL,
+	dependency_server_id INTEGER NOT NULL,
+	dependency_wait_healthy BOOLEAN NOT NULL DEFAULT FALSE,
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES aesterisk.servers(server_id),
+	CONSTRAINT fk_dependency_servers FOREIGN KEY(dependency_server_id) REFERENCES aesterisk.servers(server_id),
+	CONSTRAINT ck_server_dependency_self CHECK (server_id <> dependency_server_id),
+	PRIMARY KEY(server_id, dependency_server_id)
+);
+
+CREATE INDEX ix_server_dependencies_dependency ON aesterisk.server_dependencies(dependency_server_id);
+
+-- server_update_strategy is the `UpdateStrategy` used to replace the container of a server when it
+-- changes (0 = recreate, 1 = blue/green).
+ALTER TABLE aesterisk.servers
+	ADD COLUMN server_update_strategy SMALLINT NOT NULL DEFAULT 0;
+
+-- custom labels of a server, applied to its container as io.aesterisk.label.<server_label_key>
+-- whenever it is created. the friendly name of the server is servers.server_name.
+CREATE TABLE aesterisk.server_labels (
+	server_id INTEGER NOT NULL,
+	server_label_key TEXT NOT NULL,
+	server_label_value TEXT NOT NULL,
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES aesterisk.servers(server_id),
+	PRIMARY KEY(server_id, server_label_key)
+);
+
+-- usage of the servers on the nodes of a team per calendar month (UTC, as YYYY-MM), summed up from
+-- the hourly metric rollups. servers on nodes shared by multiple teams are attributed to the team
+-- with the lowest id. usage_accounted_until is the end of the last accounted hour, in seconds since
+-- the unix epoch.
+CREATE TABLE aesterisk.team_usage (
+	team_id INTEGER NOT NULL,
+	usage_month TEXT NOT NULL,
+	usage_cpu_core_hours DOUBLE PRECISION NOT NULL DEFAULT 0,
+	usage_memory_gb_hours DOUBLE PRECISION NOT NULL DEFAULT 0,
+	usage_storage_gb_hours DOUBLE PRECISION NOT NULL DEFAULT 0,
+	CONSTRAINT fk_teams FOREIGN KEY(team_id) REFERENCES aesterisk.teams(team_id),
+	PRIMARY KEY(team_id, usage_month)
+);
+
+CREATE TABLE aesterisk.usage_accounting (
+	usage_accounting_id BOOLEAN PRIMARY KEY NOT NULL DEFAULT TRUE CHECK (usage_accounting_id),
+	usage_accounted_until BIGINT NOT NULL
+);
+
+INSERT INTO aesterisk.usage_accounting (usage_accounted_until) VALUES (0);
+
+-- quotas of a team, NULL meaning unlimited. quota_max_memory and quota_max_storage are in MB, and
+-- limit the sum of the reservations of the servers on the team's nodes.
+CREATE TABLE aesterisk.team_quotas (
+	team_id INTEGER PRIMARY KEY NOT NULL,
+	quota_max_daemons INTEGER DEFAULT NULL,
+	quota_max_servers INTEGER DEFAULT NULL,
+	quota_max_memory INTEGER DEFAULT NULL,
+	quota_max_storage INTEGER DEFAULT NULL,
+	CONSTRAINT fk_teams FOREIGN KEY(team_id) REFERENCES aesterisk.teams(team_id)
+);
+
+-- WASM extensions uploaded by a team, run on the events of its nodes in order of their position
+CREATE TABLE aesterisk.wasm_extensions (
+	wasm_extension_id SERIAL PRIMARY KEY NOT NULL,
+	team_id INTEGER NOT NULL,
+	wasm_extension_name TEXT NOT NULL,
+	wasm_extension_position INTEGER NOT NULL DEFAULT 0,
+	wasm_extension_module BYTEA NOT NULL,
+	CONSTRAINT fk_teams FOREIGN KEY(team_id) REFERENCES aesterisk.teams(team_id)
+);
+
+CREATE INDEX ix_wasm_extensions_team ON aesterisk.wasm_extensions(team_id);
+
+-- memory and storage reserved by a server (in MB), counted against the quotas of its team
+ALTER TABLE aesterisk.servers
+	ADD COLUMN server_memory_reservation INTEGER NOT NULL DEFAULT 0,
+	ADD COLUMN server_storage_reservation INTEGER NOT NULL DEFAULT 0;
+
+-- connections of daemons and web clients, recorded when they end. node_uuid is set for daemons,
+-- user_id for web clients, times are unix timestamps in seconds.
+CREATE TABLE aesterisk.connection_sessions (
+	session_id SERIAL PRIMARY KEY NOT NULL,
+	session_peer_type TEXT NOT NULL,
+	node_uuid UUID DEFAULT NULL,
+	user_id INTEGER DEFAULT NULL,
+	session_addr TEXT NOT NULL,
+	session_connected_at BIGINT NOT NULL,
+	session_disconnected_at BIGINT NOT NULL,
+	session_reason TEXT NOT NULL,
+	session_bytes_in BIGINT NOT NULL,
+	session_bytes_out BIGINT NOT NULL,
+	session_packets_in BIGINT NOT NULL,
+	session_packets_out BIGINT NOT NULL
+);
+
+CREATE INDEX ix_connection_sessions_node_time ON aesterisk.connection_sessions(node_uuid, session_connected_at);
+CREATE INDEX ix_connection_sessions_disconnected ON aesterisk.connection_sessions(session_disconnected_at);
+
+CREATE TABLE aesterisk.notifications (
+	notification_id BIGSERIAL PRIMARY KEY NOT NULL,
+	user_id INTEGER NOT NULL,
+	notification_kind SMALLINT NOT NULL,
+	node_uuid UUID DEFAULT NULL,
+	server_id INTEGER DEFAULT NULL,
+	notification_message TEXT NOT NULL,
+	notification_created_at BIGINT NOT NULL,
+	notification_read BOOLEAN NOT NULL DEFAULT FALSE,
+	CONSTRAINT fk_users FOREIGN KEY(user_id) REFERENCES aesterisk.users(user_id)
+);
+
+CREATE INDEX ix_notifications_user ON aesterisk.notifications(user_id, notification_id);
+CREATE INDEX ix_notifications_created ON aesterisk.notifications(notification_created_at);
+
+-- recent status events of daemons, sent to web clients that start listening to them. the data is
+-- the JSON of the event.
+CREATE TABLE aesterisk.events (
+	event_id BIGSERIAL PRIMARY KEY NOT NULL,
+	node_uuid UUID NOT NULL,
+	event_type SMALLINT NOT NULL,
+	event_seq BIGINT DEFAULT NULL,
+	event_time BIGINT NOT NULL,
+	event_data TEXT NOT NULL
+);
+
+CREATE INDEX ix_events_node_type ON aesterisk.events(node_uuid, event_type, event_id);
+CREATE INDEX ix_events_time ON aesterisk.events(event_time);
+
+-- rules the server evaluates against the status events of a node: once a server (or any server
+-- of the node if server_id is NULL) entered the trigger status condition_count times within
+-- condition_window seconds, the command is run on it and/or its team is notified.
+CREATE TABLE aesterisk.automation_rules (
+	automation_rule_id SERIAL PRIMARY KEY NOT NULL,
+	node_id INTEGER NOT NULL,
+	server_id INTEGER DEFAULT NULL,
+	automation_rule_name TEXT NOT NULL,
+	automation_rule_enabled BOOLEAN NOT NULL DEFAULT TRUE,
+	automation_rule_trigger_status SMALLINT NOT NULL,
+	automation_rule_condition_count INTEGER NOT NULL DEFAULT 1,
+	automation_rule_condition_window INTEGER NOT NULL DEFAULT 0,
+	automation_rule_skip_maintenance BOOLEAN NOT NULL DEFAULT TRUE,
+	automation_rule_action_command SMALLINT DEFAULT NULL,
+	automation_rule_action_notify BOOLEAN NOT NULL DEFAULT FALSE,
+	automation_rule_cooldown INTEGER NOT NULL DEFAULT 300,
+	CONSTRAINT fk_nodes FOREIGN KEY(node_id) REFERENCES aesterisk.nodes(node_id),
+	CONSTRAINT fk_servers FOREIGN KEY(server_id) REFERENCES aesterisk.servers(server_id)
+);
+
+CREATE INDEX ix_automation_rules_node ON aesterisk.automation_rules(node_id);
+
+-- what happened whenever an automation rule was triggered, to debug rules
+CREATE TABLE aesterisk.automation_logs (
+	automation_log_id BIGSERIAL PRIMARY KEY NOT NULL,
+	automation_rule_id INTEGER NOT NULL,
+	node_uuid UUID NOT NULL,
+	server_id INTEGER NOT NULL,
+	automation_log_outcome SMALLINT NOT NULL,
+	automation_log_message TEXT NOT NULL,
+	automation_log_created_at BIGINT NOT NULL,
+	CONSTRAINT fk_automation_rules FOREIGN KEY(automation_rule_id) REFERENCES aesterisk.automation_rules(automation_rule_id) ON DELETE CASCADE
+);
+
+CREATE INDEX ix_automation_logs_rule ON aesterisk.automation_logs(automation_rule_id, automation_log_id);
+CREATE INDEX ix_automation_logs_created ON aesterisk.automation_logs(automation_log_created_at);
+
+-- notifies the server whenever data that is part of a daemon sync changes, so it can drop its
+-- cached specs. the payload is the name of the changed table.
+CREATE FUNCTION aesterisk.notify_sync() RETURNS TRIGGER AS $$
+BEGIN
+	PERFORM pg_notify('aesterisk_sync', TG_TABLE_NAME);
+	RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+DO $$
+DECLARE
+	sync_table TEXT;
+BEGIN
+	FOREACH sync_table IN ARRAY ARRAY[
+		'nodes', 'networks', 'node_networks', 'tags', 'env_defs', 'tag_env_defs', 'servers', 'ports',
+		'server_ports', 'envs', 'server_envs', 'server_networks', 'node_servers', 'maintenance_windows',
+		'server_dependencies', 'server_labels'
+	] LOOP
+		EXECUTE format('CREATE TRIGGER tr_%s_notify_sync AFTER INSERT OR UPDATE OR DELETE OR TRUNCATE ON aesterisk.%I FOR EACH STATEMENT EXECUTE FUNCTION aesterisk.notify_sync()', sync_table, sync_table);
+	END LOOP;
+END;
+$$;
@@ -0,0 +1,74 @@
+//! Helpers shared by the benchmarks. The server and daemon are binaries, so their encryption is
+//! mirrored here: packets are wrapped in a JWT claim and encrypted as a JWE, like
+//! `server::encryption::encrypt_packet` and `daemon::encryption::encrypt_packet` do.
+
+// not every benchmark uses every helper
+#![allow(dead_code)]
+
+use std::{fs, path::PathBuf, time::{Duration, SystemTime}};
+
+use aesterisk_packet::{server_daemon::sync::SDSyncPacket, Packet};
+use josekit::{jwe::{self, JweDecrypter, JweEncrypter, JweHeader}, jwk::alg::rsa::RsaKeyPair, jwt::{self, JwtPayload}};
+
+/// Returns a packet of the fixtures of the current version, see `tests/compat`.
+pub fn fixture(name: &str) -> Packet {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/compat/fixtures/v0.1.0").join(format!("{}.json", name));
+
+    serde_json::from_str(&fs::read_to_string(path).expect("could not read fixture")).expect("could not parse fixture")
+}
+
+/// Returns the sync fixture with its server repeated `servers` times.
+pub fn sync_packet(servers: u32) -> SDSyncPacket {
+    let mut sync = SDSyncPacket::parse(fixture("SDSync")).expect("could not parse SDSync fixture");
+    let server = sync.servers.first().expect("SDSync fixture should have a server").clone();
+
+    sync.servers = (1..=servers).map(|id| {
+        let mut server = server.clone();
+        server.id = id;
+        server
+    }).collect();
+
+    sync
+}
+
+/// Returns an RSA-OAEP encrypter and decrypter, as used for every connection today.
+pub fn rsa() -> (Box<dyn JweEncrypter>, Box<dyn JweDecrypter>) {
+    let key = RsaKeyPair::generate(2048).expect("could not generate key");
+
+    (
+        Box::new(jwe::RSA_OAEP.encrypter_from_pem(key.to_pem_public_key()).expect("could not create encrypter")),
+        Box::new(jwe::RSA_OAEP.decrypter_from_pem(key.to_pem_private_key()).expect("could not create decrypter")),
+    )
+}
+
+/// Returns an encrypter and decrypter using a symmetric session key, as proposed to replace RSA
+/// after the handshake.
+pub fn session() -> (Box<dyn JweEncrypter>, Box<dyn JweDecrypter>) {
+    let key = (0..32).collect::<Vec<u8>>();
+
+    (
+        Box::new(jwe::Dir.encrypter_from_bytes(&key).expect("could not create encrypter")),
+        Box::new(jwe::Dir.decrypter_from_bytes(&key).expect("could not create decrypter")),
+    )
+}
+
+pub fn encrypt_packet(packet: &Packet, encrypter: &dyn JweEncrypter) -> String {
+    let mut header = JweHeader::new();
+    header.set_token_type("JWT");
+    header.set_algorithm(encrypter.algorithm().name());
+    header.set_content_encryption("A256GCM");
+
+    let mut payload = JwtPayload::new();
+    payload.set_claim("p", Some(serde_json::to_value(packet).expect("packet should be serializable"))).expect("could not set payload claim");
+    payload.set_issuer("aesterisk/server");
+    payload.set_issued_at(&SystemTime::now());
+    payload.set_expires_at(&(SystemTime::now() + Duration::from_secs(60)));
+
+    jwt::encode_with_encrypter(&payload, &header, encrypter).expect("could not encrypt packet")
+}
+
+pub fn decrypt_packet(msg: &str, decrypter: &dyn JweDecrypter) -> Packet {
+    let (payload, _) = jwt::decode_with_decrypter(msg, decrypter).expect("could not decrypt packet");
+
+    serde_json::from_value(payload.claim("p").expect("payload should have a packet").clone()).expect("could not parse packet")
+}
@@ -0,0 +1,38 @@
+//! Encrypting and decrypting packets with RSA-OAEP, as every packet is today, compared to the
+//! proposed symmetric session keys.
+
+use aesterisk_packet::Packet;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+
+mod common;
+
+fn packets() -> Vec<(&'static str, Packet)> {
+    vec![
+        ("event", common::fixture("DSEvent")),
+        ("sync_100", common::sync_packet(100).to_packet().expect("could not build sync packet")),
+    ]
+}
+
+fn encryption(c: &mut Criterion) {
+    let keys = [("rsa", common::rsa()), ("session", common::session())];
+
+    let mut group = c.benchmark_group("encrypt_packet");
+    for (name, packet) in packets() {
+        for (scheme, (encrypter, _)) in keys.iter() {
+            group.bench_with_input(BenchmarkId::new(*scheme, name), &packet, |b, packet| b.iter(|| common::encrypt_packet(black_box(packet), encrypter.as_ref())));
+        }
+    }
+    group.finish();
+
+    let mut group = c.benchmark_group("decrypt_packet");
+    for (name, packet) in packets() {
+        for (scheme, (encrypter, decrypter)) in keys.iter() {
+            let msg = common::encrypt_packet(&packet, encrypter.as_ref());
+            group.bench_with_input(BenchmarkId::new(*scheme, name), &msg, |b, msg| b.iter(|| common::decrypt_packet(black_box(msg), decrypter.as_ref())));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, encryption);
+criterion_main!(benches);
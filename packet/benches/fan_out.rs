@@ -0,0 +1,54 @@
+//! Delivering an event to N web clients, like `State::deliver_event` does: the event packet is
+//! built and encrypted once per user listening, as all web clients of a user share its key, and
+//! sent to each of its clients. `per_client` is the cost of encrypting for every client instead.
+
+use aesterisk_packet::server_web::event::SWEventPacket;
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use josekit::jwe::JweEncrypter;
+
+mod common;
+
+const CLIENTS: [usize; 4] = [1, 10, 100, 1000];
+
+/// Web clients every user has open, e.g. tabs of the dashboard
+const CLIENTS_PER_USER: usize = 10;
+
+fn encrypt_event(event: &SWEventPacket, encrypter: &dyn JweEncrypter) -> String {
+    let packet = SWEventPacket {
+        event: event.event.clone(),
+        daemon: event.daemon,
+        seq: event.seq,
+        timestamp: event.timestamp,
+    }.to_packet().expect("could not build event packet");
+
+    common::encrypt_packet(black_box(&packet), encrypter)
+}
+
+fn fan_out(c: &mut Criterion) {
+    let event = SWEventPacket::parse(common::fixture("SWEvent")).expect("could not parse SWEvent fixture");
+
+    // the cost of encrypting doesn't depend on the key, so all users share one
+    let keys = [("rsa", common::rsa()), ("session", common::session())];
+
+    let mut group = c.benchmark_group("fan_out");
+    for (scheme, (encrypter, _)) in keys.iter() {
+        for clients in CLIENTS {
+            group.throughput(Throughput::Elements(clients as u64));
+            group.bench_with_input(BenchmarkId::new(format!("{}/per_client", scheme), clients), &clients, |b, &clients| b.iter(|| {
+                (0..clients).map(|_| encrypt_event(&event, encrypter.as_ref())).collect::<Vec<_>>()
+            }));
+            group.bench_with_input(BenchmarkId::new(format!("{}/per_user", scheme), clients), &clients, |b, &clients| b.iter(|| {
+                (0..clients.div_ceil(CLIENTS_PER_USER)).flat_map(|user| {
+                    let msg = encrypt_event(&event, encrypter.as_ref());
+                    let count = (clients - user * CLIENTS_PER_USER).min(CLIENTS_PER_USER);
+
+                    std::iter::repeat_n(msg, count)
+                }).collect::<Vec<_>>()
+            }));
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, fan_out);
+criterion_main!(benches);
@@ -0,0 +1,25 @@
+//! Serializing sync packets of a growing amount of servers. Throughput is reported in bytes of
+//! the serialized packet, so the size of a sync shows up next to the time it takes.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+mod common;
+
+const SERVERS: [u32; 4] = [1, 10, 100, 1000];
+
+fn sync(c: &mut Criterion) {
+    let mut group = c.benchmark_group("sync_serialize");
+    for servers in SERVERS {
+        let sync = common::sync_packet(servers);
+        let size = serde_json::to_string(&sync.to_packet().expect("could not build sync packet")).expect("could not serialize sync packet").len();
+
+        group.throughput(Throughput::Bytes(size as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(servers), &sync, |b, sync| b.iter(|| {
+            serde_json::to_string(&black_box(sync).to_packet().expect("could not build sync packet")).expect("could not serialize sync packet")
+        }));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, sync);
+criterion_main!(benches);
@@ -0,0 +1,42 @@
+use crate::{Packet, ID};
+
+/// Tells the sender that the packet with the request id of this packet was handled successfully.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AckPacket {}
+
+impl_packet!(AckPacket, Ack);
+
+/// Tells the sender that the packet with the request id of this packet could not be handled.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct NackPacket {
+    pub reason: String,
+}
+
+impl_packet!(NackPacket, Nack);
+
+/// Returns the `AckPacket` or `NackPacket` answering the packet with `request_id`, depending on
+/// the result of handling it.
+pub fn reply(request_id: u64, res: &Result<(), String>) -> Result<Packet, String> {
+    let packet = match res {
+        Ok(()) => AckPacket {}.to_packet()?,
+        Err(e) => NackPacket {
+            reason: e.clone(),
+        }.to_packet()?,
+    };
+
+    Ok(packet.with_request_id(request_id))
+}
+
+/// Returns the request id answered by an `AckPacket` or `NackPacket`, along with the result it
+/// reports.
+pub fn outcome(packet: Packet) -> Result<(u64, Result<(), String>), String> {
+    let request_id = packet.request_id.ok_or_else(|| format!("{:?}Packet without request id", packet.id))?;
+
+    match packet.id {
+        ID::Ack => Ok((request_id, Ok(()))),
+        ID::Nack => Ok((request_id, Err(packet.payload::<NackPacket>()?.reason))),
+        _ => Err(format!("{:?}Packet is not an acknowledgement", packet.id)),
+    }
+}
@@ -0,0 +1,183 @@
+use std::{io, net::SocketAddr, sync::{LazyLock, Mutex}};
+
+use tokio::net::TcpListener;
+use tracing::{info, warn};
+
+/// First file descriptor passed by systemd, see `sd_listen_fds(3)`.
+#[cfg(unix)]
+const SD_LISTEN_FDS_START: i32 = 3;
+
+/// The variables systemd passes the sockets with.
+#[cfg(unix)]
+const VARS: [&str; 3] = ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"];
+
+/// Sockets passed by systemd, with their `FileDescriptorName=`.
+static PASSED: LazyLock<Mutex<Vec<(String, std::net::TcpListener)>>> = LazyLock::new(|| Mutex::new(passed()));
+
+/// Takes the sockets systemd passed to this process. Must be called at startup, before any other
+/// threads read the environment, as the `LISTEN_*` variables are removed so child processes don't
+/// mistake them for their own.
+pub fn init() {
+    LazyLock::force(&PASSED);
+}
+
+/// Returns the file descriptors and names of the sockets passed through `LISTEN_PID`, `LISTEN_FDS`
+/// and `LISTEN_FDNAMES`, if they were passed to the process with the id `pid`.
+#[cfg(unix)]
+fn listen_fds(vars: [Option<String>; 3], pid: u32) -> Vec<(i32, String)> {
+    let [listen_pid, count, names] = vars;
+
+    // the variables are inherited by children that weren't meant to take the sockets
+    if listen_pid.and_then(|listen_pid| listen_pid.parse::<u32>().ok()) != Some(pid) {
+        return Vec::new();
+    }
+
+    let count = count.and_then(|count| count.parse::<i32>().ok()).unwrap_or(0);
+    let names = names.unwrap_or_default().split(':').map(str::to_string).collect::<Vec<_>>();
+
+    (0..count).map(|i| (SD_LISTEN_FDS_START + i, names.get(i as usize).cloned().unwrap_or_default())).collect()
+}
+
+/// Takes ownership of a passed socket, making sure it is a TCP socket. The socket is duplicated
+/// with `FD_CLOEXEC` set, and the inherited file descriptor closed, so child processes don't keep
+/// it open.
+///
+/// # Safety
+///
+/// `fd` must be an open file descriptor that nothing else in this process owns.
+#[cfg(unix)]
+unsafe fn take(fd: i32) -> io::Result<std::net::TcpListener> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+
+    // SAFETY: guaranteed by the caller
+    let inherited = unsafe { OwnedFd::from_raw_fd(fd) };
+    let socket = socket2::Socket::from(inherited.try_clone()?);
+
+    if socket.r#type()? != socket2::Type::STREAM || socket.local_addr()?.as_socket().is_none() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("file descriptor {} is not a TCP socket", fd)));
+    }
+
+    Ok(socket.into())
+}
+
+#[cfg(unix)]
+fn passed() -> Vec<(String, std::net::TcpListener)> {
+    let fds = listen_fds(VARS.map(|name| std::env::var(name).ok()), std::process::id());
+
+    for name in VARS {
+        // SAFETY: only called by `init` at startup, before other threads read the environment
+        unsafe { std::env::remove_var(name) };
+    }
+
+    if !fds.is_empty() {
+        info!("Received {} sockets by socket activation", fds.len());
+    }
+
+    fds.into_iter().filter_map(|(fd, name)| {
+        // SAFETY: systemd passes `LISTEN_FDS` open sockets starting at `SD_LISTEN_FDS_START`,
+        // which nothing else in this process owns
+        match unsafe { take(fd) } {
+            Ok(listener) => Some((name, listener)),
+            Err(e) => {
+                warn!("Ignoring socket {} passed by socket activation: {}", name, e);
+                None
+            },
+        }
+    }).collect()
+}
+
+#[cfg(not(unix))]
+fn passed() -> Vec<(String, std::net::TcpListener)> {
+    Vec::new()
+}
+
+/// Returns the index of the passed socket for a listener, matched by its name or else by its
+/// address.
+fn find(passed: &[(String, std::net::TcpListener)], name: &str, addr: Option<SocketAddr>) -> Option<usize> {
+    passed.iter().position(|(passed_name, _)| passed_name == name)
+        .or_else(|| passed.iter().position(|(_, listener)| addr.is_some() && listener.local_addr().ok() == addr))
+}
+
+/// Returns the socket systemd passed for a listener, matched by its `FileDescriptorName=` (e.g.
+/// `web` for the web listener of the server, or `metrics` for the metrics endpoint of the daemon)
+/// or else by its address, or binds to `addr` if none was passed. Passed sockets can be bound to
+/// privileged ports without running as root, and stay open while the process restarts, so
+/// connections wait instead of being refused.
+pub async fn bind(name: &str, addr: &str) -> io::Result<TcpListener> {
+    let listener = {
+        let passed = PASSED.lock().expect("passed sockets poisoned");
+
+        find(&passed, name, addr.parse::<SocketAddr>().ok())
+            // cloned, so the socket can be taken again when the listener is restarted
+            .map(|i| passed[i].1.try_clone())
+            .transpose()?
+    };
+
+    let Some(listener) = listener else {
+        return TcpListener::bind(addr).await;
+    };
+
+    if let Ok(local_addr) = listener.local_addr()
+        && addr.parse::<SocketAddr>().is_ok_and(|addr| addr != local_addr) {
+        warn!("Using socket on {} passed for {}, instead of {}", local_addr, name, addr);
+    }
+
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn vars(pid: &str, count: &str, names: &str) -> [Option<String>; 3] {
+        [pid, count, names].map(|var| Some(var.to_string()))
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fds_of_other_processes_ignored() {
+        assert_eq!(listen_fds(vars("41", "1", "web"), 42), Vec::new());
+        assert_eq!(listen_fds([None, None, None], 42), Vec::new());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn fds_named_in_order() {
+        assert_eq!(listen_fds(vars("42", "3", "web:daemon"), 42), vec![
+            (3, "web".to_string()),
+            (4, "daemon".to_string()),
+            (5, String::new()),
+        ]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_tcp_sockets_taken() {
+        use std::os::fd::IntoRawFd;
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").expect("could not bind listener");
+        let addr = listener.local_addr().expect("listener has no address");
+
+        // SAFETY: the file descriptor was just released by the listener
+        let taken = unsafe { take(listener.into_raw_fd()) }.expect("could not take TCP socket");
+        assert_eq!(taken.local_addr().ok(), Some(addr));
+
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").expect("could not bind socket");
+
+        // SAFETY: the file descriptor was just released by the socket
+        assert!(unsafe { take(socket.into_raw_fd()) }.is_err());
+    }
+
+    #[test]
+    fn passed_sockets_found_by_name_then_address() {
+        let passed = ["web", "daemon"].map(|name| (name.to_string(), std::net::TcpListener::bind("127.0.0.1:0").expect("could not bind listener")));
+        let daemon_addr = passed[1].1.local_addr().ok();
+
+        assert_eq!(find(&passed, "daemon", None), Some(1));
+        assert_eq!(find(&passed, "metrics", daemon_addr), Some(1));
+        assert_eq!(find(&passed, "metrics", "127.0.0.1:1".parse().ok()), None);
+        assert_eq!(find(&passed, "metrics", None), None);
+    }
+}
@@ -0,0 +1,141 @@
+//! Prints a description of every packet, generated from the packet types, either as Markdown
+//! (default) or as JSON (`--json`).
+//!
+//! cargo run -p aesterisk-packet --features schema --bin packet-docs > packet/PROTOCOL.md
+
+use std::collections::BTreeMap;
+
+use aesterisk_packet::schema::{packets, PacketDescription};
+use serde_json::Value;
+
+/// Returns a short description of the type of a schema, linking to referenced definitions.
+fn type_of(schema: &Value) -> String {
+    if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+        let name = reference.rsplit('/').next().unwrap_or(reference);
+        return format!("[{}](#{})", name, name.to_lowercase());
+    }
+
+    if let Some(variants) = schema.get("anyOf").or_else(|| schema.get("oneOf")).and_then(Value::as_array) {
+        return variants.iter().map(type_of).collect::<Vec<_>>().join(" or ");
+    }
+
+    if let Some(values) = schema.get("enum").and_then(Value::as_array) {
+        return values.iter().map(|value| format!("`{}`", value)).collect::<Vec<_>>().join(" or ");
+    }
+
+    if let Some(value) = schema.get("const") {
+        return format!("`{}`", value);
+    }
+
+    let types = match schema.get("type") {
+        Some(Value::String(name)) => vec![name.as_str()],
+        Some(Value::Array(names)) => names.iter().filter_map(Value::as_str).collect(),
+        _ => return "any".to_string(),
+    };
+
+    types.into_iter().map(|name| match name {
+        "array" => format!("array of {}", schema.get("items").map(type_of).unwrap_or("any".to_string())),
+        "object" => match schema.get("additionalProperties") {
+            Some(Value::Bool(false)) | None => "object".to_string(),
+            Some(values) => format!("map of {}", type_of(values)),
+        },
+        "integer" | "number" => match schema.get("format").and_then(Value::as_str) {
+            Some(format) => format!("{} ({})", name, format),
+            None => name.to_string(),
+        },
+        name => name.to_string(),
+    }).collect::<Vec<_>>().join(" or ")
+}
+
+fn description(schema: &Value) -> String {
+    schema.get("description").and_then(Value::as_str).unwrap_or_default().replace('\n', " ")
+}
+
+/// Writes the fields of an object schema as a table, or the variants of an enum schema as a list.
+fn write_fields(out: &mut String, schema: &Value) {
+    if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+        let required = schema.get("required").and_then(Value::as_array).cloned().unwrap_or_default();
+
+        out.push_str("| Field | Type | Required | Description |\n");
+        out.push_str("| --- | --- | --- | --- |\n");
+
+        for (name, field) in properties {
+            let required = if required.iter().any(|value| value == name) { "yes" } else { "no" };
+            out.push_str(&format!("| `{}` | {} | {} | {} |\n", name, type_of(field), required, description(field)));
+        }
+    } else if let Some(variants) = schema.get("oneOf").or_else(|| schema.get("anyOf")).and_then(Value::as_array) {
+        for variant in variants {
+            out.push_str(&format!("- {}", type_of(variant)));
+
+            if let Some(properties) = variant.get("properties").and_then(Value::as_object) {
+                let fields = properties.iter().map(|(name, field)| format!("`{}`: {}", name, type_of(field))).collect::<Vec<_>>();
+                out.push_str(&format!(" {{ {} }}", fields.join(", ")));
+            }
+
+            let description = description(variant);
+            if !description.is_empty() {
+                out.push_str(&format!(": {}", description));
+            }
+
+            out.push('\n');
+        }
+    } else {
+        out.push_str(&format!("{}\n", type_of(schema)));
+    }
+}
+
+fn markdown(packets: &[PacketDescription]) -> String {
+    let mut out = String::from("# Protocol\n\nGenerated by `packet-docs` from the packet types, do not edit.\n\n");
+    let mut definitions = BTreeMap::new();
+
+    out.push_str("| ID | Packet | From | To | Version |\n");
+    out.push_str("| --- | --- | --- | --- | --- |\n");
+
+    for packet in packets {
+        out.push_str(&format!("| {} | [{}](#{}) | {} | {} | {} |\n", packet.id, packet.name, packet.name.to_lowercase(), packet.from, packet.to, packet.version));
+    }
+
+    out.push_str("\n## Packets\n");
+
+    for packet in packets {
+        let schema = packet.schema.as_value();
+
+        out.push_str(&format!("\n### {}\n\nID {}, from {} to {}, version {}.\n\n", packet.name, packet.id, packet.from, packet.to, packet.version));
+
+        let description = description(schema);
+        if !description.is_empty() {
+            out.push_str(&format!("{}\n\n", description));
+        }
+
+        write_fields(&mut out, schema);
+
+        if let Some(defs) = schema.get("$defs").and_then(Value::as_object) {
+            definitions.extend(defs.iter().map(|(name, schema)| (name.clone(), schema.clone())));
+        }
+    }
+
+    out.push_str("\n## Types\n");
+
+    for (name, schema) in definitions {
+        out.push_str(&format!("\n### {}\n\n", name));
+
+        let description = description(&schema);
+        if !description.is_empty() {
+            out.push_str(&format!("{}\n\n", description));
+        }
+
+        write_fields(&mut out, &schema);
+    }
+
+    out
+}
+
+fn main() {
+    let packets = packets();
+
+    if std::env::args().any(|arg| arg == "--json") {
+        println!("{}", serde_json::to_string_pretty(&packets).expect("packet descriptions should be serializable"));
+    } else {
+        print!("{}", markdown(&packets));
+    }
+}
@@ -0,0 +1,265 @@
+use std::{collections::HashMap, sync::atomic::{AtomicU64, Ordering}, time::{Duration, Instant}};
+
+use crate::Packet;
+
+/// Serialized packets larger than this are split into chunks before encrypting them.
+pub const MAX_CHUNK_SIZE: usize = 512 * 1024;
+
+/// The maximum number of chunks a single transfer may consist of.
+const MAX_CHUNKS: u32 = 512;
+
+/// The maximum number of transfers a `Reassembler` keeps track of at the same time.
+const MAX_TRANSFERS: usize = 8;
+
+/// The maximum amount of bytes a `Reassembler` buffers over all of its incomplete transfers, which
+/// is enough for a single transfer of `MAX_CHUNKS` chunks.
+const MAX_BUFFERED: usize = MAX_CHUNKS as usize * MAX_CHUNK_SIZE;
+
+/// Incomplete transfers are dropped once they haven't completed for this long.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+
+static NEXT_CHUNK_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A part of a serialized packet that was too large to be sent as a single message. `checksum` is
+/// the FNV-1a hash of the complete serialized packet, and is the same in every chunk.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ChunkPacket {
+    #[serde(rename = "c")]
+    pub chunk: u64,
+    #[serde(rename = "i")]
+    pub index: u32,
+    #[serde(rename = "t")]
+    pub total: u32,
+    #[serde(rename = "s")]
+    pub checksum: u64,
+    #[serde(rename = "d")]
+    pub data: String,
+}
+
+fn checksum(data: &str) -> u64 {
+    data.bytes().fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+impl_packet!(ChunkPacket, Chunk);
+
+/// Splits a packet into chunk packets of at most `MAX_CHUNK_SIZE` bytes of data each. Packets that
+/// are small enough are returned as they are.
+pub fn split(packet: Packet) -> Result<Vec<Packet>, String> {
+    let data = serde_json::to_string(&packet).map_err(|_| "packet could not be serialized")?;
+
+    if data.len() <= MAX_CHUNK_SIZE {
+        return Ok(vec![packet]);
+    }
+
+    let mut parts = Vec::new();
+    let mut rest = data.as_str();
+
+    while !rest.is_empty() {
+        let mut end = rest.len().min(MAX_CHUNK_SIZE);
+
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        let (part, remaining) = rest.split_at(end);
+        parts.push(part);
+        rest = remaining;
+    }
+
+    if parts.len() > MAX_CHUNKS as usize {
+        return Err(format!("packet is too large to be sent ({} bytes)", data.len()));
+    }
+
+    let chunk = NEXT_CHUNK_ID.fetch_add(1, Ordering::Relaxed);
+    let total = parts.len() as u32;
+    let checksum = checksum(&data);
+
+    parts.into_iter().enumerate().map(|(index, part)| ChunkPacket {
+        chunk,
+        index: index as u32,
+        total,
+        checksum,
+        data: part.to_string(),
+    }.to_packet()).collect()
+}
+
+struct Transfer {
+    checksum: u64,
+    parts: Vec<Option<String>>,
+    received: u32,
+    started: Instant,
+}
+
+impl Transfer {
+    fn bytes(&self) -> usize {
+        self.parts.iter().flatten().map(String::len).sum()
+    }
+}
+
+/// Collects chunk packets of a connection until a packet is complete. Chunks may arrive in any
+/// order. Transfers that don't complete within `TRANSFER_TIMEOUT` are dropped, and at most
+/// `MAX_BUFFERED` bytes are buffered at a time.
+pub struct Reassembler {
+    transfers: HashMap<u64, Transfer>,
+    buffered: usize,
+    max_buffered: usize,
+    timeout: Duration,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self {
+            transfers: HashMap::new(),
+            buffered: 0,
+            max_buffered: MAX_BUFFERED,
+            timeout: TRANSFER_TIMEOUT,
+        }
+    }
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Drops a transfer, no longer counting its chunks as buffered.
+    fn drop_transfer(&mut self, chunk: u64) -> Option<Transfer> {
+        let transfer = self.transfers.remove(&chunk)?;
+        self.buffered -= transfer.bytes();
+        Some(transfer)
+    }
+
+    /// Adds a chunk, returning the original packet once all of its chunks have been received.
+    pub fn push(&mut self, chunk: ChunkPacket) -> Result<Option<Packet>, String> {
+        if chunk.total == 0 || chunk.total > MAX_CHUNKS || chunk.index >= chunk.total {
+            return Err(format!("invalid chunk {}/{} of transfer {}", chunk.index, chunk.total, chunk.chunk));
+        }
+
+        let stale = self.transfers.iter().filter(|(_, transfer)| transfer.started.elapsed() >= self.timeout).map(|(id, _)| *id).collect::<Vec<_>>();
+        for id in stale {
+            self.drop_transfer(id);
+        }
+
+        if !self.transfers.contains_key(&chunk.chunk) && self.transfers.len() >= MAX_TRANSFERS {
+            return Err("too many incomplete chunked transfers".into());
+        }
+
+        let transfer = self.transfers.entry(chunk.chunk).or_insert_with(|| Transfer {
+            checksum: chunk.checksum,
+            parts: vec![None; chunk.total as usize],
+            received: 0,
+            started: Instant::now(),
+        });
+
+        if transfer.checksum != chunk.checksum || transfer.parts.len() != chunk.total as usize {
+            self.drop_transfer(chunk.chunk);
+            return Err(format!("chunks of transfer {} do not match", chunk.chunk));
+        }
+
+        let replaced = transfer.parts[chunk.index as usize].as_ref().map_or(0, String::len);
+
+        if self.buffered - replaced + chunk.data.len() > self.max_buffered {
+            self.drop_transfer(chunk.chunk);
+            return Err(format!("too much data buffered for chunked transfers, dropped transfer {}", chunk.chunk));
+        }
+
+        self.buffered = self.buffered - replaced + chunk.data.len();
+
+        let part = &mut transfer.parts[chunk.index as usize];
+
+        if part.is_none() {
+            transfer.received += 1;
+        }
+
+        part.replace(chunk.data);
+
+        if transfer.received < chunk.total {
+            return Ok(None);
+        }
+
+        let transfer = self.drop_transfer(chunk.chunk).expect("transfer should exist after inserting it");
+        let data = transfer.parts.into_iter().flatten().collect::<String>();
+
+        if checksum(&data) != transfer.checksum {
+            return Err(format!("checksum mismatch in transfer {}", chunk.chunk));
+        }
+
+        Ok(Some(serde_json::from_str(&data).map_err(|_| "failed to deserialize reassembled packet")?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::heartbeat::PingPacket;
+
+    use super::*;
+
+    fn chunk(id: u64, index: u32, total: u32, data: &str) -> ChunkPacket {
+        ChunkPacket {
+            chunk: id,
+            index,
+            total,
+            checksum: 0,
+            data: data.to_string(),
+        }
+    }
+
+    #[test]
+    fn split_and_reassemble() {
+        let packet = PingPacket {
+            nonce: 1,
+        }.to_packet().expect("could not create packet");
+        let data = serde_json::to_string(&packet).expect("could not serialize packet");
+
+        let mut reassembler = Reassembler::new();
+        let (first, second) = data.split_at(data.len() / 2);
+        let checksum = checksum(&data);
+
+        assert!(reassembler.push(ChunkPacket { checksum, ..chunk(1, 1, 2, second) }).expect("could not push chunk").is_none());
+        let reassembled = reassembler.push(ChunkPacket { checksum, ..chunk(1, 0, 2, first) }).expect("could not push chunk").expect("packet should be complete");
+
+        assert_eq!(reassembled.id, packet.id);
+        assert_eq!(reassembler.buffered, 0);
+    }
+
+    #[test]
+    fn stale_transfers_evicted() {
+        let mut reassembler = Reassembler {
+            timeout: Duration::from_millis(10),
+            ..Reassembler::new()
+        };
+
+        for id in 0..MAX_TRANSFERS as u64 {
+            reassembler.push(chunk(id, 0, 2, "a")).expect("could not push chunk");
+        }
+        assert!(reassembler.push(chunk(MAX_TRANSFERS as u64, 0, 2, "a")).is_err());
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        reassembler.push(chunk(MAX_TRANSFERS as u64, 0, 2, "a")).expect("stale transfers were not evicted");
+        assert_eq!(reassembler.transfers.len(), 1);
+        assert_eq!(reassembler.buffered, 1);
+    }
+
+    #[test]
+    fn buffered_bytes_limited() {
+        let mut reassembler = Reassembler {
+            max_buffered: 4,
+            ..Reassembler::new()
+        };
+
+        reassembler.push(chunk(1, 0, 3, "ab")).expect("could not push chunk");
+        // resent chunks replace the data they were sent with before
+        reassembler.push(chunk(1, 0, 3, "ab")).expect("could not push chunk");
+        reassembler.push(chunk(2, 0, 2, "cd")).expect("could not push chunk");
+        assert_eq!(reassembler.buffered, 4);
+
+        assert!(reassembler.push(chunk(1, 1, 3, "e")).is_err());
+        assert!(!reassembler.transfers.contains_key(&1));
+        assert_eq!(reassembler.buffered, 2);
+
+        reassembler.push(chunk(2, 1, 2, "e")).expect_err("checksum should not match");
+        assert_eq!(reassembler.buffered, 0);
+    }
+}
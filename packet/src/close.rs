@@ -0,0 +1,106 @@
+use std::fmt::{Display, Formatter};
+
+/// Why the server closed a connection, sent as the code of the WebSocket close frame. Codes are in
+/// the 4000-4999 range, which is reserved for applications.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CloseReason {
+    /// The peer failed the authentication challenge
+    AuthFailed,
+    /// The peer's access was revoked, e.g. its node or user was removed
+    Revoked,
+    /// The server is shutting down, and will be back shortly
+    ServerShutdown,
+    /// The peer didn't send anything for too long
+    IdleTimeout,
+    /// The peer sent messages that could not be decrypted or parsed
+    ProtocolError,
+    /// The peer authenticated, but was rejected anyway, e.g. because its team exceeded a quota
+    Rejected,
+    /// The peer didn't answer the server's pings for too long
+    HeartbeatTimeout,
+}
+
+/// What a peer should do after the server closed its connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Reconnect {
+    /// Reconnect right away.
+    Retry,
+    /// Reconnect, but only after waiting for a while, as the server will likely close the
+    /// connection for the same reason again.
+    BackOff,
+    /// Don't reconnect, as the connection can't succeed without the peer being reconfigured.
+    Stop,
+}
+
+impl CloseReason {
+    pub const ALL: &[CloseReason] = &[
+        CloseReason::AuthFailed,
+        CloseReason::Revoked,
+        CloseReason::ServerShutdown,
+        CloseReason::IdleTimeout,
+        CloseReason::ProtocolError,
+        CloseReason::Rejected,
+        CloseReason::HeartbeatTimeout,
+    ];
+
+    pub fn code(&self) -> u16 {
+        match self {
+            CloseReason::AuthFailed => 4001,
+            CloseReason::Revoked => 4002,
+            CloseReason::ServerShutdown => 4003,
+            CloseReason::IdleTimeout => 4004,
+            CloseReason::ProtocolError => 4005,
+            CloseReason::Rejected => 4006,
+            CloseReason::HeartbeatTimeout => 4007,
+        }
+    }
+
+    pub fn from_code(code: u16) -> Option<Self> {
+        Self::ALL.iter().copied().find(|reason| reason.code() == code)
+    }
+
+    pub fn reconnect(&self) -> Reconnect {
+        match self {
+            // a failed challenge may be caused by a key that was rotated after the server cached
+            // it, which the server evicts, so the next attempt may succeed
+            CloseReason::AuthFailed | CloseReason::ProtocolError | CloseReason::Rejected => Reconnect::BackOff,
+            CloseReason::Revoked => Reconnect::Stop,
+            CloseReason::ServerShutdown | CloseReason::IdleTimeout | CloseReason::HeartbeatTimeout => Reconnect::Retry,
+        }
+    }
+}
+
+impl Display for CloseReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CloseReason::AuthFailed => write!(f, "authentication failed"),
+            CloseReason::Revoked => write!(f, "access revoked"),
+            CloseReason::ServerShutdown => write!(f, "server shutdown"),
+            CloseReason::IdleTimeout => write!(f, "idle timeout"),
+            CloseReason::ProtocolError => write!(f, "protocol error"),
+            CloseReason::Rejected => write!(f, "rejected"),
+            CloseReason::HeartbeatTimeout => write!(f, "heartbeat timeout"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_round_trip() {
+        for reason in CloseReason::ALL {
+            assert_eq!(CloseReason::from_code(reason.code()), Some(*reason));
+        }
+
+        assert_eq!(CloseReason::from_code(1000), None);
+    }
+
+    #[test]
+    fn only_revoked_stops() {
+        for reason in CloseReason::ALL {
+            assert_eq!(reason.reconnect() == Reconnect::Stop, *reason == CloseReason::Revoked, "{}", reason);
+        }
+    }
+}
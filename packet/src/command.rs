@@ -0,0 +1,15 @@
+use serde::{Deserialize, Serialize};
+
+/// A command changing whether a server is running.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ServerCommand {
+    Start,
+    /// Stops the server gracefully, without removing its container
+    Stop,
+    /// Restarts the server, followed by all servers that depend on it
+    Restart,
+    /// Stops the server immediately
+    Kill,
+}
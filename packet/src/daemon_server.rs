@@ -1,3 +1,15 @@
 pub mod auth;
+pub mod command_result;
+pub mod error;
 pub mod event;
+pub mod fetch_build_context;
 pub mod handshake_response;
+pub mod query_logs_response;
+pub mod query_stats_response;
+pub mod query_tasks_response;
+pub mod query_top_response;
+pub mod query_usage_response;
+pub mod sync_progress;
+pub mod sync_result;
+pub mod terminal_close;
+pub mod terminal_output;
@@ -1,36 +1,18 @@
-use crate::{Packet, Version, ID};
+use crate::features::Features;
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DSAuthPacket {
     pub daemon_uuid: String,
+    /// Hash of the last fully applied sync, or empty if there is none
+    #[serde(default)]
+    pub sync_hash: String,
+    /// Features supported by the sender, see `Feature`
+    #[serde(default)]
+    pub features: Features,
+    /// Version of the daemon, e.g. `0.1.0`, or empty for daemons that don't report it
+    #[serde(default)]
+    pub version: String,
 }
 
-impl DSAuthPacket {
-    pub fn parse(packet: Packet) -> Option<Self> {
-        if packet.id != ID::DSAuth {
-            return None;
-        }
-
-        match packet.version {
-            Version::V0_1_0 => {
-                let res = serde_json::from_value(packet.data);
-
-                if res.is_err() {
-                    println!("W (Packet) DSAuthPacket deserializing error: {:#?}", res.as_ref().err().expect("Result::err should return Some when Result::is_err returns true"));
-                }
-
-                res.ok()
-            }
-        }
-    }
-
-    pub fn to_packet(&self) -> Result<Packet, String> {
-        let data = serde_json::to_value(&self).map_err(|_| "packet data should be serializeable")?;
-        Ok(Packet::new(Version::V0_1_0, ID::DSAuth, data))
-    }
-
-    pub fn to_string(&self) -> Result<String, String> {
-        let packet = self.to_packet()?;
-        Ok(serde_json::to_string(&packet).map_err(|_| "packet could not be serialized")?)
-    }
-}
+impl_packet!(DSAuthPacket, DSAuth);
@@ -0,0 +1,15 @@
+use crate::server_web::error::ErrorCode;
+
+/// Reports a failure of the daemon the web clients would otherwise not learn about, e.g. a
+/// container that could not be created while applying a sync.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DSErrorPacket {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Request id of the sync that failed, if a web client is waiting for it
+    #[serde(default)]
+    pub request: Option<u64>,
+}
+
+impl_packet!(DSErrorPacket, DSError);
@@ -1,36 +1,25 @@
-use crate::{events::EventData, Packet, Version, ID};
+use crate::{events::EventData};
 
 #[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct DSEventPacket {
     pub data: EventData,
+    /// Sequence number of the event among all events sent by the daemon, increasing by 1 with
+    /// every event sent, so gaps and reordering can be detected. Starts at 1 on every connection.
+    /// The server handles events in this order, except for snapshots (see
+    /// `EventType::is_snapshot`), which it drops if they aren't newer than the latest one of their
+    /// type and server.
+    #[serde(default)]
+    pub seq: Option<u64>,
+    /// Unix timestamp (in seconds) the event happened at. Events the daemon buffered while it was
+    /// disconnected keep the time they happened at when they are replayed after reconnecting.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    /// Whether the event was buffered while the daemon was disconnected. Replayed events are
+    /// recorded in the history, but not evaluated by alert and automation rules, nor sent to web
+    /// clients, as they are out of date.
+    #[serde(default)]
+    pub replayed: bool,
 }
 
-impl DSEventPacket {
-    pub fn parse(packet: Packet) -> Option<Self> {
-        if packet.id != ID::DSEvent {
-            return None;
-        }
-
-        match packet.version {
-            Version::V0_1_0 => {
-                let res = serde_json::from_value(packet.data);
-
-                if res.is_err() {
-                    println!("W (Packet) DSEvent deserializing error: {:#?}", res.as_ref().err().expect("Result::err should return Some when Result::is_err returns true"));
-                }
-
-                res.ok()
-            }
-        }
-    }
-
-    pub fn to_packet(&self) -> Result<Packet, String> {
-        let data = serde_json::to_value(&self).map_err(|_| "packet data should be serializeable")?;
-        Ok(Packet::new(Version::V0_1_0, ID::DSEvent, data))
-    }
-
-    pub fn to_string(&self) -> Result<String, String> {
-        let packet = self.to_packet()?;
-        Ok(serde_json::to_string(&packet).map_err(|_| "Packet could not be serialized")?)
-    }
-}
+impl_packet!(DSEventPacket, DSEvent);
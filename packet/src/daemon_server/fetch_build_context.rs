@@ -0,0 +1,10 @@
+/// Requests an uploaded build context archive from the server, which responds with an
+/// `SDBuildContextPacket`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DSFetchBuildContextPacket {
+    /// Hex encoded SHA-256 hash of the archive
+    pub hash: String,
+}
+
+impl_packet!(DSFetchBuildContextPacket, DSFetchBuildContext);
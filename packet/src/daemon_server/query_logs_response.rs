@@ -0,0 +1,11 @@
+use crate::{events::LogLine};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DSQueryLogsResponsePacket {
+    pub request: u64,
+    pub server: u32,
+    pub lines: Vec<LogLine>,
+}
+
+impl_packet!(DSQueryLogsResponsePacket, DSQueryLogsResponse);
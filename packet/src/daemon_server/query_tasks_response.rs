@@ -0,0 +1,10 @@
+use crate::{events::TaskEvent};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DSQueryTasksResponsePacket {
+    pub request: u64,
+    pub tasks: Vec<TaskEvent>,
+}
+
+impl_packet!(DSQueryTasksResponsePacket, DSQueryTasksResponse);
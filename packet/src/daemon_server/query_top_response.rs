@@ -0,0 +1,12 @@
+use crate::{events::ProcessTable};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DSQueryTopResponsePacket {
+    pub request: u64,
+    pub server: u32,
+    pub table: Option<ProcessTable>,
+    pub error: Option<String>,
+}
+
+impl_packet!(DSQueryTopResponsePacket, DSQueryTopResponse);
@@ -0,0 +1,11 @@
+use crate::{events::UsageSample};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DSQueryUsageResponsePacket {
+    pub request: u64,
+    pub server: u32,
+    pub samples: Vec<UsageSample>,
+}
+
+impl_packet!(DSQueryUsageResponsePacket, DSQueryUsageResponse);
@@ -0,0 +1,10 @@
+use crate::{events::SyncStep};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DSSyncProgressPacket {
+    pub request: u64,
+    pub step: SyncStep,
+}
+
+impl_packet!(DSSyncProgressPacket, DSSyncProgress);
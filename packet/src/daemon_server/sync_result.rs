@@ -0,0 +1,23 @@
+use crate::{server_daemon::sync::Port};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SyncResultServer {
+    pub id: u32,
+    /// Ports which were automatically assigned a host port by the daemon
+    pub ports: Vec<Port>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DSSyncResultPacket {
+    pub servers: Vec<SyncResultServer>,
+    /// Set when the daemon could not apply a delta sync, and needs a full sync instead
+    #[serde(default)]
+    pub resync: bool,
+    /// Request id of the sync that could not be applied, passed on to the full sync
+    #[serde(default)]
+    pub request: Option<u64>,
+}
+
+impl_packet!(DSSyncResultPacket, DSSyncResult);
@@ -0,0 +1,17 @@
+use uuid::Uuid;
+
+/// Output of the TTY of a terminal.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DSTerminalOutputPacket {
+    pub session: Uuid,
+    /// Base64 encoded bytes read from the TTY
+    pub data: String,
+    /// Number of the packet among the output and close packets of the session, counting from 0, so
+    /// they are handled in order, see `terminal::Reorder`. Packets without one are handled as they
+    /// arrive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+impl_packet!(DSTerminalOutputPacket, DSTerminalOutput);
@@ -0,0 +1,95 @@
+//! Environment variable substitution for the config files of the server and the daemon.
+
+/// Replaces `${NAME}` and `${NAME:-default}` in the contents of a config file with the value of
+/// the variable `NAME` returned by `lookup`, or `default` if it is not set. `$$` is kept as a
+/// literal `$`, so `$${NAME}` becomes `${NAME}`. Returns whether anything was substituted.
+pub fn substitute(contents: &str, lookup: impl Fn(&str) -> Option<String>) -> Result<(String, bool), String> {
+    let mut result = String::with_capacity(contents.len());
+    let mut rest = contents;
+    let mut substituted = false;
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        if let Some(escaped) = rest.strip_prefix("$$") {
+            result.push('$');
+            rest = escaped;
+            continue;
+        }
+
+        let Some(variable) = rest.strip_prefix("${") else {
+            result.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+
+        let line = contents[..contents.len() - rest.len()].matches('\n').count() + 1;
+        let end = variable.find('}').ok_or_else(|| format!("unterminated variable on line {}", line))?;
+
+        let (name, default) = match variable[..end].split_once(":-") {
+            Some((name, default)) => (name, Some(default)),
+            None => (&variable[..end], None),
+        };
+
+        match (lookup(name), default) {
+            (Some(value), _) => result.push_str(&value),
+            (None, Some(default)) => result.push_str(default),
+            (None, None) => return Err(format!("environment variable {} on line {} is not set", name, line)),
+        }
+
+        substituted = true;
+        rest = &variable[end + 1..];
+    }
+
+    result.push_str(rest);
+
+    Ok((result, substituted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "HOST" => Some("example.com".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(substitute("url = \"wss://${HOST}/daemon\"", lookup), Ok(("url = \"wss://example.com/daemon\"".to_string(), true)));
+        assert_eq!(substitute("${HOST}${HOST}", lookup), Ok(("example.comexample.com".to_string(), true)));
+        assert_eq!(substitute("a = \"${EMPTY}\"", lookup), Ok(("a = \"\"".to_string(), true)));
+    }
+
+    #[test]
+    fn defaults() {
+        assert_eq!(substitute("${PORT:-31306}", lookup), Ok(("31306".to_string(), true)));
+        assert_eq!(substitute("${HOST:-localhost}", lookup), Ok(("example.com".to_string(), true)));
+        assert_eq!(substitute("${PORT:-}", lookup), Ok((String::new(), true)));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(substitute("$${HOST}", lookup), Ok(("${HOST}".to_string(), false)));
+        assert_eq!(substitute("price = \"$$5\"", lookup), Ok(("price = \"$5\"".to_string(), false)));
+        assert_eq!(substitute("$$$${HOST}", lookup), Ok(("$${HOST}".to_string(), false)));
+        assert_eq!(substitute("$$${HOST}", lookup), Ok(("$example.com".to_string(), true)));
+    }
+
+    #[test]
+    fn untouched() {
+        assert_eq!(substitute("no variables", lookup), Ok(("no variables".to_string(), false)));
+        assert_eq!(substitute("cost = \"5$\" # $HOST", lookup), Ok(("cost = \"5$\" # $HOST".to_string(), false)));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(substitute("a = 1\nb = \"${PORT\"", lookup), Err("unterminated variable on line 2".to_string()));
+        assert_eq!(substitute("a = 1\n\nb = \"${PORT}\"", lookup), Err("environment variable PORT on line 3 is not set".to_string()));
+    }
+}
@@ -0,0 +1,77 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Packet;
+
+/// How long a packet is valid for after it has been issued.
+pub const LIFETIME: Duration = Duration::from_secs(60);
+
+/// The content type set in the JWE header of packets sent as an `Envelope`.
+pub const CONTENT_TYPE: &str = "msgpack";
+
+/// The MessagePack counterpart of the JWT packets are otherwise sent in, with the same claims.
+/// It is used by `Encoding::MessagePack` and encrypted as the plaintext of a JWE.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope {
+    #[serde(rename = "p")]
+    pub packet: Packet,
+    #[serde(rename = "iss")]
+    pub issuer: String,
+    /// Seconds since the Unix epoch
+    #[serde(rename = "iat")]
+    pub issued_at: u64,
+    /// Seconds since the Unix epoch
+    #[serde(rename = "exp")]
+    pub expires_at: u64,
+}
+
+fn now() -> Result<u64, String> {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|now| now.as_secs()).map_err(|_| "System time is before the Unix epoch".to_string())
+}
+
+impl Envelope {
+    /// Wraps a packet issued now by `issuer`, valid for `LIFETIME`.
+    pub fn new(packet: Packet, issuer: &str) -> Result<Self, String> {
+        let now = now()?;
+
+        Ok(Self {
+            packet,
+            issuer: issuer.to_string(),
+            issued_at: now,
+            expires_at: now + LIFETIME.as_secs(),
+        })
+    }
+
+    pub fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        rmp_serde::to_vec_named(self).map_err(|e| format!("Could not serialize envelope: {}", e))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        rmp_serde::from_slice(bytes).map_err(|e| format!("Could not deserialize envelope: {}", e))
+    }
+
+    /// Checks that the packet was issued within the last `LIFETIME` by one of `issuers` and hasn't
+    /// expired yet, like the claims of a JWT are validated.
+    pub fn validate(&self, issuers: &[String]) -> Result<(), String> {
+        let now = now()?;
+
+        if self.issued_at > now {
+            return Err("issued in the future".to_string());
+        }
+
+        if self.issued_at + LIFETIME.as_secs() < now {
+            return Err("issued too long ago".to_string());
+        }
+
+        if self.expires_at < now {
+            return Err("expired".to_string());
+        }
+
+        if !issuers.contains(&self.issuer) {
+            return Err(format!("issuer {} is not accepted", self.issuer));
+        }
+
+        Ok(())
+    }
+}
@@ -2,18 +2,116 @@ use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 #[derive(Debug, Serialize, Deserialize, Hash, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum EventType {
     NodeStatus,
     ServerStatus,
+    Alert,
+    FleetSummary,
+    ResourceWarning,
+    BuildOutput,
+    UpdatePhase,
+    UpdateRequired,
+    Task,
+    ServerLog,
+}
+
+impl EventType {
+    /// Returns whether events of this type are produced continuously rather than on changes, and
+    /// are therefore subject to flow control, see `flow`.
+    pub fn is_streamed(&self) -> bool {
+        matches!(self, EventType::BuildOutput | EventType::ServerLog)
+    }
+
+    /// Returns whether events of this type are snapshots of a status, of which only the latest
+    /// matters. Servers drop snapshots older than the latest one they handled, while other events
+    /// are handled in the order they were sent.
+    pub fn is_snapshot(&self) -> bool {
+        matches!(self, EventType::NodeStatus | EventType::ServerStatus)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct NodeStatusEvent {
     pub online: bool,
     pub stats: Option<NodeStats>,
+    #[serde(default)]
+    pub in_maintenance: bool,
+    /// Servers managed by the daemon, by status
+    #[serde(default)]
+    pub servers: Option<ServerCounts>,
+    /// Maximum amount of servers the daemon accepts, or `None` if unlimited
+    #[serde(default)]
+    pub max_servers: Option<u32>,
+    /// Environment of the daemon, or `None` if unknown (e.g. the daemon is offline)
+    #[serde(default)]
+    pub info: Option<DaemonInfo>,
 }
 
+impl NodeStatusEvent {
+    /// Returns whether the daemon manages as many servers as it accepts.
+    pub fn is_full(&self) -> bool {
+        match (&self.servers, self.max_servers) {
+            (Some(servers), Some(max_servers)) => servers.total() >= max_servers,
+            _ => false,
+        }
+    }
+}
+
+/// Environment of a daemon, used to warn about features that aren't supported on its node.
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct DaemonInfo {
+    /// Whether Docker runs in rootless mode
+    pub rootless: bool,
+    /// Lowest host port servers can be mapped to
+    pub min_host_port: u16,
+    /// Whether CPU and memory stats of servers are available, which requires cgroup v2 on rootless
+    /// Docker
+    pub server_stats: bool,
+}
+
+impl Default for DaemonInfo {
+    fn default() -> Self {
+        Self {
+            rootless: false,
+            min_host_port: 1,
+            server_stats: true,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ServerCounts {
+    pub healthy: u32,
+    pub starting: u32,
+    pub restarting: u32,
+    pub stopping: u32,
+    pub stopped: u32,
+    pub unhealthy: u32,
+}
+
+impl ServerCounts {
+    pub fn add(&mut self, status: &ServerStatusType) {
+        match status {
+            ServerStatusType::Healthy => self.healthy += 1,
+            ServerStatusType::Starting => self.starting += 1,
+            ServerStatusType::Restarting => self.restarting += 1,
+            ServerStatusType::Stopping => self.stopping += 1,
+            ServerStatusType::Stopped => self.stopped += 1,
+            ServerStatusType::Unhealthy => self.unhealthy += 1,
+        }
+    }
+
+    pub fn total(&self) -> u32 {
+        self.healthy + self.starting + self.restarting + self.stopping + self.stopped + self.unhealthy
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct NodeStats {
     pub used_memory: f64,
     pub total_memory: f64,
@@ -23,15 +121,58 @@ pub struct NodeStats {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ServerStatusEvent {
     pub server: u32,
     pub status: ServerStatusType,
     pub memory: Option<Stats>,
     pub cpu: Option<Stats>,
     pub storage: Option<Stats>,
+    #[serde(default)]
+    pub in_maintenance: bool,
+    /// Why the server is stopped, restarting or unhealthy, gathered when it entered that status
+    #[serde(default)]
+    pub reason: Option<StatusReason>,
+    /// RFC 3339 timestamp of when the server was started, if it is running
+    #[serde(default)]
+    pub started_at: Option<String>,
+    /// Amount of times Docker restarted the server's container
+    #[serde(default)]
+    pub restart_count: u64,
+    /// How `cpu` is normalized
+    #[serde(default)]
+    pub cpu_convention: CpuConvention,
+    /// Amount of CPU cores available to the server, to convert `cpu` between conventions
+    #[serde(default)]
+    pub cores: Option<u32>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl ServerStatusEvent {
+    /// Returns the CPU usage in percent of one core, regardless of `cpu_convention`.
+    pub fn cpu_per_core(&self) -> Option<f64> {
+        let cpu = self.cpu.as_ref()?;
+
+        match self.cpu_convention {
+            CpuConvention::PerCore => Some(cpu.used),
+            CpuConvention::AllCores => Some(cpu.used * self.cores? as f64),
+        }
+    }
+}
+
+/// How CPU usage of servers is reported.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum CpuConvention {
+    /// In percent of one core, out of 100% per core, like `docker stats` and htop
+    #[default]
+    PerCore,
+    /// In percent of all cores, out of 100%
+    AllCores,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 #[serde(rename_all = "lowercase")]
 pub enum ServerStatusType {
     /// Server is running (and healthy if healthcheck exists)
@@ -49,15 +190,281 @@ pub enum ServerStatusType {
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct StatusReason {
+    /// Exit code of the container's main process
+    pub exit_code: Option<i64>,
+    /// Whether the container was killed because it ran out of memory
+    #[serde(default)]
+    pub oom_killed: bool,
+    /// Error reported by Docker, e.g. if the container could not be started
+    pub error: Option<String>,
+    /// Output of the last healthcheck, if the server is unhealthy
+    pub health_output: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Stats {
     pub used: f64,
     pub total: f64,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum LogStream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct LogLine {
+    /// RFC 3339 timestamp of the line, as reported by Docker
+    pub timestamp: String,
+    pub stream: LogStream,
+    pub line: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ProcessTable {
+    /// Column names, as reported by `ps` inside the container
+    pub titles: Vec<String>,
+    /// One row per process, in the same order as `titles`
+    pub processes: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UsageSample {
+    /// Start of the minute this sample covers, in seconds since the unix epoch
+    pub timestamp: u64,
+    /// Average CPU usage over the minute, in percent of a single core
+    pub cpu: f64,
+    /// Average memory usage over the minute, in GB
+    pub memory: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MetricSample {
+    /// Time of the sample (or start of the hour for rollups), in seconds since the unix epoch
+    pub timestamp: u64,
+    pub cpu: Option<f64>,
+    pub memory_used: Option<f64>,
+    pub memory_total: Option<f64>,
+    pub storage_used: Option<f64>,
+    pub storage_total: Option<f64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum AlertMetric {
+    Cpu,
+    Memory,
+    Storage,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct AlertEvent {
+    pub rule: u32,
+    /// Server the rule applies to, or `None` if it applies to the node itself
+    pub server: Option<u32>,
+    pub metric: AlertMetric,
+    /// Value that caused the state change, in percent
+    pub value: f64,
+    /// Threshold of the rule, in percent
+    pub threshold: f64,
+    /// `true` when the threshold has tripped, `false` when it has recovered
+    pub firing: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum Resource {
+    /// Disk space of the filesystem the daemon's data folder is on
+    Disk,
+    Memory,
+    /// Inodes of the filesystem the daemon's data folder is on
+    Inodes,
+}
+
+/// Sent by a daemon whenever the usage of one of its node's resources crosses the threshold
+/// configured on the daemon, regardless of anyone listening.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ResourceWarningEvent {
+    pub resource: Resource,
+    /// Usage of the resource, in percent
+    pub value: f64,
+    /// Threshold configured on the daemon, in percent
+    pub threshold: f64,
+    /// `true` when the usage went above the threshold, `false` when it has recovered
+    pub firing: bool,
+}
+
+/// A line of output of an image being built by a daemon, for a server that is being created.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct BuildOutputEvent {
+    pub server: u32,
+    /// Name of the image being built, as `image:docker_tag`
+    pub image: String,
+    pub line: String,
+    /// `true` if the build failed with this line as the error
+    pub error: bool,
+    /// Lines left out before this one because the receiver had no credits left, see `flow`
+    #[serde(default)]
+    pub skipped: u32,
+}
+
+/// Lines a server's container wrote to stdout or stderr, sent to the web clients following its
+/// logs, see `ListenEvent::servers`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ServerLogEvent {
+    pub server: u32,
+    pub lines: Vec<LogLine>,
+    /// Lines left out before these because the receiver had no credits left, see `flow`
+    #[serde(default)]
+    pub skipped: u32,
+}
+
+/// A phase of a blue/green update of a server, see `UpdateStrategy::BlueGreen`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "phase", rename_all = "snake_case")]
+pub enum UpdatePhase {
+    StartingCandidate,
+    WaitingForHealth,
+    /// The old container is being replaced by the new one
+    Swapping,
+    Done,
+    /// The new container did not become healthy and was removed, the old one keeps running
+    RolledBack { error: String },
+}
+
+/// Sent by a daemon for every phase of a blue/green update of one of its servers.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UpdatePhaseEvent {
+    pub server: u32,
+    pub phase: UpdatePhase,
+}
+
+/// Sent by the server when a daemon connects with a version outside of the supported range.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct UpdateRequiredEvent {
+    /// Version of the daemon, or empty if it didn't report one
+    pub version: String,
+    /// Oldest supported version, or empty if there is none
+    pub min_version: String,
+    /// Newest supported version, or empty if there is none
+    pub max_version: String,
+    /// Whether the daemon was refused, or only allowed to connect with a warning
+    pub refused: bool,
+}
+
+/// What a task of a daemon does.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TaskKind {
+    PullImage { image: String },
+    BuildImage { server: u32, image: String },
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum TaskState {
+    Queued,
+    Running,
+    Done,
+    /// The task failed on its last attempt
+    Failed { error: String },
+    Cancelled,
+}
+
+/// Sent by a daemon whenever one of its tasks changes. Tasks are long-running operations, which
+/// the daemon persists and resumes after restarts, so they are identified by an id that is unique
+/// per daemon. The state of all tasks is sent again whenever the server starts listening.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TaskEvent {
+    pub id: u64,
+    pub kind: TaskKind,
+    pub state: TaskState,
+    /// Progress of the current attempt from 0 to 1, if known
+    #[serde(default)]
+    pub progress: Option<f64>,
+    /// Last checkpoint the task reached, e.g. `3/7 layers`
+    #[serde(default)]
+    pub checkpoint: Option<String>,
+    /// Amount of times the task was started, including resumptions after restarts
+    pub attempts: u32,
+}
+
+/// A step of applying a sync on a daemon, reported to the web client that requested the sync.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum SyncStep {
+    RemovingServer { server: u32 },
+    RemovingNetwork { network: u32 },
+    CreatingNetwork { network: u32 },
+    PullingImage { server: u32, image: String },
+    BuildingImage { server: u32, image: String },
+    CreatingServer { server: u32 },
+    /// The server is being replaced using its update strategy
+    UpdatingServer { server: u32 },
+    Done,
+    Failed { error: String },
+    /// The sync was not sent, because the web client exceeded its sync rate limit
+    Throttled { retry_after: u64 },
+}
+
+impl SyncStep {
+    /// Returns whether this is the last step of a sync.
+    pub fn is_final(&self) -> bool {
+        matches!(self, SyncStep::Done | SyncStep::Failed { .. } | SyncStep::Throttled { .. })
+    }
+}
+
+/// Summary of a set of daemons, computed by the server. Sent with a nil daemon UUID.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct FleetSummaryEvent {
+    pub online_daemons: u32,
+    pub offline_daemons: u32,
+    pub healthy_servers: u32,
+    pub unhealthy_servers: u32,
+    pub stopped_servers: u32,
+    /// Average CPU usage of the online daemons, in percent
+    pub cpu: f64,
+    pub used_memory: f64,
+    pub total_memory: f64,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub enum EventData {
     NodeStatus(NodeStatusEvent),
     ServerStatus(ServerStatusEvent),
+    Alert(AlertEvent),
+    FleetSummary(FleetSummaryEvent),
+    ResourceWarning(ResourceWarningEvent),
+    BuildOutput(BuildOutputEvent),
+    UpdatePhase(UpdatePhaseEvent),
+    UpdateRequired(UpdateRequiredEvent),
+    Task(TaskEvent),
+    ServerLog(ServerLogEvent),
 }
 
 impl EventData {
@@ -65,18 +472,38 @@ impl EventData {
         match self {
             EventData::NodeStatus(_) => EventType::NodeStatus,
             EventData::ServerStatus(_) => EventType::ServerStatus,
+            EventData::Alert(_) => EventType::Alert,
+            EventData::FleetSummary(_) => EventType::FleetSummary,
+            EventData::ResourceWarning(_) => EventType::ResourceWarning,
+            EventData::BuildOutput(_) => EventType::BuildOutput,
+            EventData::UpdatePhase(_) => EventType::UpdatePhase,
+            EventData::UpdateRequired(_) => EventType::UpdateRequired,
+            EventData::Task(_) => EventType::Task,
+            EventData::ServerLog(_) => EventType::ServerLog,
         }
     }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Event {
     pub daemon: Uuid,
     pub event: EventData,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ListenEvent {
     pub event: EventType,
     pub daemons: Vec<Uuid>,
+    /// Daemon groups to listen to, expanded to their members by the server. Subscriptions follow
+    /// membership changes of these groups.
+    #[serde(default)]
+    pub groups: Vec<u32>,
+    /// Servers of the `daemons` to receive events of. With `ServerStatus`, only the status of these
+    /// servers is sent, or of all servers if empty. With `ServerLog`, these are the servers to
+    /// follow the logs of, and following a server sends its recent lines first, as a
+    /// `SWQueryLogsResponsePacket`. Ignored with other events.
+    #[serde(default)]
+    pub servers: Vec<u32>,
 }
@@ -0,0 +1,72 @@
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+/// A protocol feature, that is only used on a connection if both sides support it. Features are
+/// exchanged during authentication, so that new protocol features can be rolled out gradually.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    /// Packets larger than `chunk::MAX_CHUNK_SIZE` are split up into `ChunkPacket`s.
+    Chunking,
+    /// Syncs may only contain the changes to the last applied sync.
+    DeltaSync,
+    /// The progress of applying a sync is reported back to the web client that requested it.
+    SyncProgress,
+    /// Daemons send `ResourceWarning` events when their node runs low on resources.
+    ResourceWarnings,
+    /// Daemons pull the images of the catalog sent by the server ahead of time.
+    Catalog,
+    /// Daemons apply the settings sent by the server in `SDConfigPacket`s.
+    RemoteConfig,
+    /// Streamed events are only sent while the receiver has granted credits for them, see `flow`.
+    FlowControl,
+    /// Packets with a request id are answered with an `AckPacket` or `NackPacket`, see `ack`.
+    Acks,
+    /// Daemons report failures the web clients would otherwise not learn about in `DSErrorPacket`s.
+    DaemonErrors,
+    /// Peers answer the `PingPacket`s of the server, see `heartbeat`.
+    Heartbeats,
+    /// Daemons open terminals into their servers on `SDTerminalOpenPacket`s.
+    Terminals,
+    /// The output of a terminal is only sent while the receiver has granted credits for it, see
+    /// `flow`.
+    TerminalFlowControl,
+    /// A feature added in a later version, which is never negotiated.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A set of `Feature`s, serialized as a list.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(transparent)]
+pub struct Features(BTreeSet<Feature>);
+
+impl Features {
+    /// Returns all features supported by this version.
+    pub fn supported() -> Self {
+        Self::from([Feature::Chunking, Feature::DeltaSync, Feature::SyncProgress, Feature::ResourceWarnings, Feature::Catalog, Feature::RemoteConfig, Feature::FlowControl, Feature::Acks, Feature::DaemonErrors, Feature::Heartbeats, Feature::Terminals, Feature::TerminalFlowControl])
+    }
+
+    /// Returns the features supported by both `self` and `other`.
+    pub fn negotiate(&self, other: &Features) -> Self {
+        Self(self.0.intersection(&other.0).copied().filter(|feature| *feature != Feature::Unknown).collect())
+    }
+
+    /// Returns `self` without the `disabled` features.
+    pub fn without(&self, disabled: &[Feature]) -> Self {
+        Self(self.0.iter().copied().filter(|feature| !disabled.contains(feature)).collect())
+    }
+
+    pub fn has(&self, feature: Feature) -> bool {
+        self.0.contains(&feature)
+    }
+}
+
+impl<const N: usize> From<[Feature; N]> for Features {
+    fn from(features: [Feature; N]) -> Self {
+        Self(BTreeSet::from(features))
+    }
+}
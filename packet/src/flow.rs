@@ -0,0 +1,89 @@
+//! Credit-based flow control of streamed events (see `EventType::is_streamed`) and terminal output,
+//! so that a receiver reading slower than they are produced doesn't make the sender queue them
+//! without bound. It is only used if `Feature::FlowControl` or `Feature::TerminalFlowControl`
+//! respectively was negotiated.
+//!
+//! Every streamed event type has a `Window` of credits on both ends of a connection, starting at
+//! `INITIAL_WINDOW`. Sending an event takes a credit, events are left out while there are none, and
+//! the receiver grants more credits with window update packets once it has processed events.
+//!
+//! Every terminal has a window as well, but as output can't be left out, the daemon stops reading
+//! from the TTY while there are no credits left, until more are granted in the `credits` of terminal
+//! input packets.
+
+/// Credits a window starts out with.
+pub const INITIAL_WINDOW: u32 = 256;
+
+/// The most credits a window can hold, no matter how many are granted.
+pub const MAX_WINDOW: u32 = 4096;
+
+/// The credits left for sending a streamed event type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Window {
+    credits: u32,
+}
+
+impl Default for Window {
+    fn default() -> Self {
+        Self {
+            credits: INITIAL_WINDOW,
+        }
+    }
+}
+
+impl Window {
+    /// Takes a credit for sending an event, returning `false` if there are none left.
+    pub fn take(&mut self) -> bool {
+        if self.credits == 0 {
+            return false;
+        }
+
+        self.credits -= 1;
+        true
+    }
+
+    /// Adds credits granted by the receiver, up to `MAX_WINDOW`.
+    pub fn grant(&mut self, credits: u32) {
+        self.credits = self.credits.saturating_add(credits).min(MAX_WINDOW);
+    }
+
+    pub fn credits(&self) -> u32 {
+        self.credits
+    }
+}
+
+/// Returns whether no credits are granted, to leave them out of packets.
+pub(crate) fn is_zero(credits: &u32) -> bool {
+    *credits == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_until_exhausted() {
+        let mut window = Window::default();
+
+        for _ in 0..INITIAL_WINDOW {
+            assert!(window.take());
+        }
+
+        assert_eq!(window.credits(), 0);
+        assert!(!window.take());
+        assert_eq!(window.credits(), 0);
+    }
+
+    #[test]
+    fn grant_up_to_max() {
+        let mut window = Window::default();
+        while window.take() {}
+
+        window.grant(10);
+        assert_eq!(window.credits(), 10);
+        assert!(window.take());
+
+        window.grant(u32::MAX);
+        assert_eq!(window.credits(), MAX_WINDOW);
+    }
+}
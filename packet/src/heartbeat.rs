@@ -0,0 +1,18 @@
+/// Sent periodically by the server to a peer that negotiated `Feature::Heartbeats`, which answers
+/// it with a `PongPacket`. Peers that don't answer for too long are disconnected.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PingPacket {
+    pub nonce: u64,
+}
+
+impl_packet!(PingPacket, Ping);
+
+/// Answers a `PingPacket`, with its nonce.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PongPacket {
+    pub nonce: u64,
+}
+
+impl_packet!(PongPacket, Pong);
@@ -1,25 +1,75 @@
 use std::{fmt::{Display, Formatter}, str::FromStr};
 
+/// Implements `PacketPayload` for a packet, along with its `parse`, `to_packet` and `to_string`
+/// methods, so they can be called without importing the trait.
+macro_rules! impl_packet {
+    ($packet:ty, $id:ident) => {
+        impl $crate::PacketPayload for $packet {
+            const ID: $crate::ID = $crate::ID::$id;
+        }
+
+        impl $packet {
+            pub fn parse(packet: $crate::Packet) -> Option<Self> {
+                <Self as $crate::PacketPayload>::parse(packet)
+            }
+
+            pub fn to_packet(&self) -> Result<$crate::Packet, String> {
+                <Self as $crate::PacketPayload>::to_packet(self)
+            }
+
+            pub fn to_string(&self) -> Result<String, String> {
+                <Self as $crate::PacketPayload>::to_string(self)
+            }
+        }
+    };
+}
+
+pub mod ack;
+#[cfg(feature = "activation")]
+pub mod activation;
+pub mod chunk;
+pub mod close;
+pub mod command;
+#[cfg(feature = "debug_endpoint")]
+pub mod debug;
+#[cfg(feature = "binary")]
+pub mod envelope;
+pub mod env;
 pub mod events;
+pub mod features;
+pub mod flow;
+pub mod heartbeat;
+pub mod maintenance;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod subprotocol;
+pub mod terminal;
 pub mod web_server;
 pub mod server_web;
 pub mod daemon_server;
 pub mod server_daemon;
 
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Packet {
     pub version: Version,
     pub id: ID,
     pub data: serde_json::Value,
+    /// Set by the sender to be answered with an `AckPacket` or `NackPacket` once the packet was
+    /// handled. Acknowledgements carry the request id of the packet they answer.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u64>,
 }
 
-#[derive(serde_repr::Serialize_repr, serde_repr::Deserialize_repr, Debug, PartialEq)]
+#[derive(serde_repr::Serialize_repr, serde_repr::Deserialize_repr, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema_repr))]
 #[repr(u8)]
 pub enum Version {
     V0_1_0 = 0,
 }
 
-#[derive(serde_repr::Serialize_repr, serde_repr::Deserialize_repr, Debug, PartialEq)]
+#[derive(serde_repr::Serialize_repr, serde_repr::Deserialize_repr, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema_repr))]
 #[repr(u8)]
 pub enum ID {
     WSAuth = 0,
@@ -36,6 +86,81 @@ pub enum ID {
     SWEvent = 11,
     WSSync = 12,
     SDSync = 13,
+    DSSyncResult = 14,
+    WSQueryLogs = 15,
+    SDQueryLogs = 16,
+    DSQueryLogsResponse = 17,
+    SWQueryLogsResponse = 18,
+    WSQueryTop = 19,
+    SDQueryTop = 20,
+    DSQueryTopResponse = 21,
+    SWQueryTopResponse = 22,
+    WSQueryUsage = 23,
+    SDQueryUsage = 24,
+    DSQueryUsageResponse = 25,
+    SWQueryUsageResponse = 26,
+    WSQueryMetrics = 27,
+    SWQueryMetricsResponse = 28,
+    WSSyncGroup = 29,
+    SWSyncGroupResult = 30,
+    Chunk = 31,
+    DSSyncProgress = 32,
+    SWSyncProgress = 33,
+    WSQueryStats = 34,
+    SDQueryStats = 35,
+    DSQueryStatsResponse = 36,
+    SWQueryStatsResponse = 37,
+    SWError = 38,
+    WSPlaceServer = 39,
+    SWPlaceServerResponse = 40,
+    SDCatalog = 41,
+    DSFetchBuildContext = 42,
+    SDBuildContext = 43,
+    WSImportSpec = 44,
+    SWImportSpecResponse = 45,
+    WSExportSpec = 46,
+    SWExportSpecResponse = 47,
+    WSServerMetadata = 48,
+    SWServerMetadataResponse = 49,
+    SDServerMetadata = 50,
+    WSQueryTeamUsage = 51,
+    SWQueryTeamUsageResponse = 52,
+    WSQueryConnections = 53,
+    SWQueryConnectionsResponse = 54,
+    SDReconnectTo = 55,
+    SDConfig = 56,
+    WSQueryTasks = 57,
+    SDQueryTasks = 58,
+    DSQueryTasksResponse = 59,
+    SWQueryTasksResponse = 60,
+    WSCancelTask = 61,
+    SDCancelTask = 62,
+    WSWindowUpdate = 63,
+    SDWindowUpdate = 64,
+    Ack = 65,
+    Nack = 66,
+    WSQuerySnapshot = 67,
+    DSError = 68,
+    Ping = 69,
+    Pong = 70,
+    WSQueryNotifications = 71,
+    SWQueryNotificationsResponse = 72,
+    WSMarkNotificationsRead = 73,
+    WSTerminalOpen = 74,
+    SDTerminalOpen = 75,
+    WSTerminalInput = 76,
+    SDTerminalInput = 77,
+    DSTerminalOutput = 78,
+    SWTerminalOutput = 79,
+    WSTerminalClose = 80,
+    SDTerminalClose = 81,
+    DSTerminalClose = 82,
+    SWTerminalClose = 83,
+    WSCommand = 84,
+    SDCommand = 85,
+    DSCommandResult = 86,
+    SWCommandResult = 87,
+    WSUnlisten = 88,
 }
 
 impl Packet {
@@ -44,9 +169,16 @@ impl Packet {
             version,
             id,
             data,
+            request_id: None,
         }
     }
 
+    /// Sets the request id of the packet, asking the receiver to acknowledge it, see `ack`.
+    pub fn with_request_id(mut self, request_id: u64) -> Self {
+        self.request_id = Some(request_id);
+        self
+    }
+
     pub fn from_value(value: serde_json::Value) -> Option<Self> {
         let res = serde_json::from_value(value);
 
@@ -56,6 +188,46 @@ impl Packet {
 
         res.ok()
     }
+
+    /// Parses the data of the packet as `P`, e.g. after dispatching on its ID.
+    pub fn payload<P: PacketPayload>(self) -> Result<P, String> {
+        P::parse(self).ok_or_else(|| format!("Could not parse {:?}Packet", P::ID))
+    }
+}
+
+/// The data of the packets of a single `ID`, see `impl_packet!`.
+pub trait PacketPayload: serde::Serialize + serde::de::DeserializeOwned {
+    const ID: ID;
+
+    /// Parses the data of a packet, returning `None` if the packet has another ID or its data is
+    /// invalid.
+    fn parse(packet: Packet) -> Option<Self> {
+        if packet.id != Self::ID {
+            return None;
+        }
+
+        match packet.version {
+            Version::V0_1_0 => {
+                let res = serde_json::from_value(packet.data);
+
+                if let Err(e) = res.as_ref() {
+                    println!("W (Packet) {:?}Packet deserializing error: {:#?}", Self::ID, e);
+                }
+
+                res.ok()
+            }
+        }
+    }
+
+    fn to_packet(&self) -> Result<Packet, String> {
+        let data = serde_json::to_value(self).map_err(|_| "packet data should be serializeable")?;
+        Ok(Packet::new(Version::V0_1_0, Self::ID, data))
+    }
+
+    fn to_string(&self) -> Result<String, String> {
+        let packet = self.to_packet()?;
+        Ok(serde_json::to_string(&packet).map_err(|_| "packet could not be serialized")?)
+    }
 }
 
 impl FromStr for Packet {
@@ -8,6 +8,8 @@ fn main() {
         events: vec![ListenEvent {
             event: EventType::NodeStatus,
             daemons: vec![id],
+            groups: Vec::new(),
+            servers: Vec::new(),
         }],
     }.to_packet().unwrap();
 
@@ -22,9 +24,15 @@ fn main() {
                 cpu: 56.0,
                 used_storage: 180.4,
                 total_storage: 256.0,
-            })
+            }),
+            in_maintenance: false,
+            servers: None,
+            max_servers: None,
+            info: None,
         }),
-        daemon: id
+        daemon: id,
+        seq: Some(1),
+        timestamp: None,
     }.to_packet().unwrap();
 
     println!(" Event: {}", packet2.to_string());
@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+
+/// A recurring maintenance window, starting whenever `cron` matches and lasting `duration` minutes.
+/// The cron expression has the usual five fields (minute, hour, day of month, month, day of week)
+/// and is evaluated in UTC.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MaintenanceWindow {
+    #[serde(rename = "c")]
+    pub cron: String,
+    #[serde(rename = "d")]
+    pub duration: u32,
+}
+
+struct Cron {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+fn parse_field(field: &str, min: u32, max: u32) -> Option<u64> {
+    let mut mask = 0;
+
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (range, step.parse::<u32>().ok().filter(|step| *step > 0)?),
+            None => (part, 1),
+        };
+
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            // `5/15` means every 15 starting at 5
+            None if step > 1 => (range.parse().ok()?, max),
+            None => {
+                let value = range.parse().ok()?;
+                (value, value)
+            }
+        };
+
+        if start < min || end > max || start > end {
+            return None;
+        }
+
+        for value in (start..=end).step_by(step as usize) {
+            mask |= 1 << value;
+        }
+    }
+
+    Some(mask)
+}
+
+impl Cron {
+    fn parse(expression: &str) -> Option<Self> {
+        let fields = expression.split_whitespace().collect::<Vec<_>>();
+
+        if fields.len() != 5 {
+            return None;
+        }
+
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+
+        // both 0 and 7 are sunday
+        if weekdays & (1 << 7) != 0 {
+            weekdays |= 1;
+        }
+
+        Some(Self {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    fn matches(&self, time: u64) -> bool {
+        let days = time / 86400;
+        let seconds = time % 86400;
+        let (month, day) = month_day(days);
+        // 1970-01-01 was a thursday
+        let weekday = (days + 4) % 7;
+
+        let day_matches = match (self.any_day, self.any_weekday) {
+            (false, false) => self.days & (1 << day) != 0 || self.weekdays & (1 << weekday) != 0,
+            _ => self.days & (1 << day) != 0 && self.weekdays & (1 << weekday) != 0,
+        };
+
+        self.minutes & (1 << (seconds / 60 % 60)) != 0
+            && self.hours & (1 << (seconds / 3600)) != 0
+            && self.months & (1 << month) != 0
+            && day_matches
+    }
+}
+
+/// Converts days since the unix epoch to a month (1-12) and day of month (1-31).
+fn month_day(days: u64) -> (u64, u64) {
+    // see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719468;
+    let day_of_era = days % 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+
+    (month, day)
+}
+
+impl MaintenanceWindow {
+    /// Returns whether the cron expression is valid.
+    pub fn is_valid(&self) -> bool {
+        Cron::parse(&self.cron).is_some()
+    }
+
+    /// Returns whether the window is active at the given time, in seconds since the unix epoch.
+    /// Windows with an invalid cron expression are never active.
+    pub fn is_active(&self, now: u64) -> bool {
+        let cron = match Cron::parse(&self.cron) {
+            Some(cron) => cron,
+            None => return false,
+        };
+
+        let minute = now - now % 60;
+
+        (0..self.duration as u64).map(|ago| ago * 60).take_while(|ago| *ago <= minute).any(|ago| cron.matches(minute - ago))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-03-15 12:30:00 UTC, a friday.
+    const FRIDAY_NOON: u64 = 1710505800;
+
+    fn window(cron: &str, duration: u32) -> MaintenanceWindow {
+        MaintenanceWindow {
+            cron: cron.to_string(),
+            duration,
+        }
+    }
+
+    #[test]
+    fn parse_fields() {
+        assert_eq!(parse_field("*", 0, 3), Some(0b1111));
+        assert_eq!(parse_field("1,3", 0, 5), Some(0b1010));
+        assert_eq!(parse_field("1-3", 0, 5), Some(0b1110));
+        assert_eq!(parse_field("*/2", 0, 5), Some(0b10101));
+        assert_eq!(parse_field("1-5/2", 0, 5), Some(0b101010));
+        assert_eq!(parse_field("5/15", 0, 59), Some((1 << 5) | (1 << 20) | (1 << 35) | (1 << 50)));
+    }
+
+    #[test]
+    fn reject_invalid() {
+        assert_eq!(parse_field("60", 0, 59), None);
+        assert_eq!(parse_field("0", 1, 31), None);
+        assert_eq!(parse_field("5-1", 0, 59), None);
+        assert_eq!(parse_field("*/0", 0, 59), None);
+        assert_eq!(parse_field("a", 0, 59), None);
+        assert_eq!(parse_field("", 0, 59), None);
+
+        assert!(!window("* * * *", 60).is_valid());
+        assert!(!window("* * * * * *", 60).is_valid());
+        assert!(window("0 2 * * 0", 60).is_valid());
+    }
+
+    #[test]
+    fn month_days() {
+        assert_eq!(month_day(0), (1, 1));
+        assert_eq!(month_day(FRIDAY_NOON / 86400), (3, 15));
+        // 2024-02-29, a leap day
+        assert_eq!(month_day(19782), (2, 29));
+        // 2023-12-31
+        assert_eq!(month_day(19722), (12, 31));
+    }
+
+    #[test]
+    fn matches() {
+        let cron = |expression| Cron::parse(expression).expect("cron expression should be valid");
+
+        assert!(cron("30 12 * * *").matches(FRIDAY_NOON));
+        assert!(!cron("31 12 * * *").matches(FRIDAY_NOON));
+        assert!(cron("30 12 15 3 *").matches(FRIDAY_NOON));
+        assert!(cron("30 12 * * 5").matches(FRIDAY_NOON));
+        assert!(!cron("30 12 * * 0,7").matches(FRIDAY_NOON));
+        // sunday as 7
+        assert!(cron("30 12 * * 7").matches(FRIDAY_NOON + 2 * 86400));
+        // with both day of month and day of week restricted, either may match
+        assert!(cron("30 12 1 * 5").matches(FRIDAY_NOON));
+        assert!(cron("30 12 15 * 1").matches(FRIDAY_NOON));
+        assert!(!cron("30 12 1 * 1").matches(FRIDAY_NOON));
+    }
+
+    #[test]
+    fn active() {
+        let noon = window("0 12 * * *", 60);
+
+        assert!(noon.is_active(FRIDAY_NOON));
+        assert!(!noon.is_active(FRIDAY_NOON - 31 * 60));
+        // the window ends after 60 minutes
+        assert!(noon.is_active(FRIDAY_NOON + 29 * 60));
+        assert!(!noon.is_active(FRIDAY_NOON + 30 * 60));
+        assert!(!window("nope", 60).is_active(FRIDAY_NOON));
+    }
+}
@@ -0,0 +1,142 @@
+use schemars::{schema_for, Schema};
+use serde::Serialize;
+
+use crate::{ack, chunk, daemon_server, heartbeat, server_daemon, server_web, web_server, ID};
+
+/// Description of a packet type, generated from its Rust struct.
+#[derive(Serialize)]
+pub struct PacketDescription {
+    pub id: u8,
+    pub name: &'static str,
+    /// Peer sending the packet, `web`, `server` or `daemon`, or `any` for packets sent in every
+    /// direction
+    pub from: &'static str,
+    /// Peer receiving the packet
+    pub to: &'static str,
+    pub version: &'static str,
+    /// JSON schema of the packet's data
+    pub schema: Schema,
+}
+
+fn direction(name: &str) -> (&'static str, &'static str) {
+    match &name[..2] {
+        "WS" => ("web", "server"),
+        "SW" => ("server", "web"),
+        "DS" => ("daemon", "server"),
+        "SD" => ("server", "daemon"),
+        _ => ("any", "any"),
+    }
+}
+
+macro_rules! describe {
+    ($id:ident, $packet:ty) => {{
+        let (from, to) = direction(stringify!($id));
+
+        PacketDescription {
+            id: ID::$id as u8,
+            name: stringify!($id),
+            from,
+            to,
+            version: "0.1.0",
+            schema: schema_for!($packet),
+        }
+    }};
+}
+
+/// Returns the description of every packet, ordered by ID.
+pub fn packets() -> Vec<PacketDescription> {
+    let mut packets = vec![
+        describe!(WSAuth, web_server::auth::WSAuthPacket),
+        describe!(DSAuth, daemon_server::auth::DSAuthPacket),
+        describe!(SWHandshakeRequest, server_web::handshake_request::SWHandshakeRequestPacket),
+        describe!(SDHandshakeRequest, server_daemon::handshake_request::SDHandshakeRequestPacket),
+        describe!(WSHandshakeResponse, web_server::handshake_response::WSHandshakeResponsePacket),
+        describe!(DSHandshakeResponse, daemon_server::handshake_response::DSHandshakeResponsePacket),
+        describe!(SWAuthResponse, server_web::auth_response::SWAuthResponsePacket),
+        describe!(SDAuthResponse, server_daemon::auth_response::SDAuthResponsePacket),
+        describe!(WSListen, web_server::listen::WSListenPacket),
+        describe!(SDListen, server_daemon::listen::SDListenPacket),
+        describe!(DSEvent, daemon_server::event::DSEventPacket),
+        describe!(SWEvent, server_web::event::SWEventPacket),
+        describe!(WSSync, web_server::sync::WSSyncPacket),
+        describe!(SDSync, server_daemon::sync::SDSyncPacket),
+        describe!(DSSyncResult, daemon_server::sync_result::DSSyncResultPacket),
+        describe!(WSQueryLogs, web_server::query_logs::WSQueryLogsPacket),
+        describe!(SDQueryLogs, server_daemon::query_logs::SDQueryLogsPacket),
+        describe!(DSQueryLogsResponse, daemon_server::query_logs_response::DSQueryLogsResponsePacket),
+        describe!(SWQueryLogsResponse, server_web::query_logs_response::SWQueryLogsResponsePacket),
+        describe!(WSQueryTop, web_server::query_top::WSQueryTopPacket),
+        describe!(SDQueryTop, server_daemon::query_top::SDQueryTopPacket),
+        describe!(DSQueryTopResponse, daemon_server::query_top_response::DSQueryTopResponsePacket),
+        describe!(SWQueryTopResponse, server_web::query_top_response::SWQueryTopResponsePacket),
+        describe!(WSQueryUsage, web_server::query_usage::WSQueryUsagePacket),
+        describe!(SDQueryUsage, server_daemon::query_usage::SDQueryUsagePacket),
+        describe!(DSQueryUsageResponse, daemon_server::query_usage_response::DSQueryUsageResponsePacket),
+        describe!(SWQueryUsageResponse, server_web::query_usage_response::SWQueryUsageResponsePacket),
+        describe!(WSQueryMetrics, web_server::query_metrics::WSQueryMetricsPacket),
+        describe!(SWQueryMetricsResponse, server_web::query_metrics_response::SWQueryMetricsResponsePacket),
+        describe!(WSSyncGroup, web_server::sync_group::WSSyncGroupPacket),
+        describe!(SWSyncGroupResult, server_web::sync_group_result::SWSyncGroupResultPacket),
+        describe!(Chunk, chunk::ChunkPacket),
+        describe!(DSSyncProgress, daemon_server::sync_progress::DSSyncProgressPacket),
+        describe!(SWSyncProgress, server_web::sync_progress::SWSyncProgressPacket),
+        describe!(WSQueryStats, web_server::query_stats::WSQueryStatsPacket),
+        describe!(SDQueryStats, server_daemon::query_stats::SDQueryStatsPacket),
+        describe!(DSQueryStatsResponse, daemon_server::query_stats_response::DSQueryStatsResponsePacket),
+        describe!(SWQueryStatsResponse, server_web::query_stats_response::SWQueryStatsResponsePacket),
+        describe!(SWError, server_web::error::SWErrorPacket),
+        describe!(WSPlaceServer, web_server::place_server::WSPlaceServerPacket),
+        describe!(SWPlaceServerResponse, server_web::place_server_response::SWPlaceServerResponsePacket),
+        describe!(SDCatalog, server_daemon::catalog::SDCatalogPacket),
+        describe!(DSFetchBuildContext, daemon_server::fetch_build_context::DSFetchBuildContextPacket),
+        describe!(SDBuildContext, server_daemon::build_context::SDBuildContextPacket),
+        describe!(WSImportSpec, web_server::import_spec::WSImportSpecPacket),
+        describe!(SWImportSpecResponse, server_web::import_spec_response::SWImportSpecResponsePacket),
+        describe!(WSExportSpec, web_server::export_spec::WSExportSpecPacket),
+        describe!(SWExportSpecResponse, server_web::export_spec_response::SWExportSpecResponsePacket),
+        describe!(WSServerMetadata, web_server::server_metadata::WSServerMetadataPacket),
+        describe!(SWServerMetadataResponse, server_web::server_metadata_response::SWServerMetadataResponsePacket),
+        describe!(SDServerMetadata, server_daemon::server_metadata::SDServerMetadataPacket),
+        describe!(WSQueryTeamUsage, web_server::query_team_usage::WSQueryTeamUsagePacket),
+        describe!(SWQueryTeamUsageResponse, server_web::query_team_usage_response::SWQueryTeamUsageResponsePacket),
+        describe!(WSQueryConnections, web_server::query_connections::WSQueryConnectionsPacket),
+        describe!(SWQueryConnectionsResponse, server_web::query_connections_response::SWQueryConnectionsResponsePacket),
+        describe!(SDReconnectTo, server_daemon::reconnect_to::SDReconnectToPacket),
+        describe!(SDConfig, server_daemon::config::SDConfigPacket),
+        describe!(WSQueryTasks, web_server::query_tasks::WSQueryTasksPacket),
+        describe!(SDQueryTasks, server_daemon::query_tasks::SDQueryTasksPacket),
+        describe!(DSQueryTasksResponse, daemon_server::query_tasks_response::DSQueryTasksResponsePacket),
+        describe!(SWQueryTasksResponse, server_web::query_tasks_response::SWQueryTasksResponsePacket),
+        describe!(WSCancelTask, web_server::cancel_task::WSCancelTaskPacket),
+        describe!(SDCancelTask, server_daemon::cancel_task::SDCancelTaskPacket),
+        describe!(WSWindowUpdate, web_server::window_update::WSWindowUpdatePacket),
+        describe!(SDWindowUpdate, server_daemon::window_update::SDWindowUpdatePacket),
+        describe!(Ack, ack::AckPacket),
+        describe!(Nack, ack::NackPacket),
+        describe!(WSQuerySnapshot, web_server::query_snapshot::WSQuerySnapshotPacket),
+        describe!(DSError, daemon_server::error::DSErrorPacket),
+        describe!(Ping, heartbeat::PingPacket),
+        describe!(Pong, heartbeat::PongPacket),
+        describe!(WSQueryNotifications, web_server::query_notifications::WSQueryNotificationsPacket),
+        describe!(SWQueryNotificationsResponse, server_web::query_notifications_response::SWQueryNotificationsResponsePacket),
+        describe!(WSMarkNotificationsRead, web_server::mark_notifications_read::WSMarkNotificationsReadPacket),
+        describe!(WSTerminalOpen, web_server::terminal_open::WSTerminalOpenPacket),
+        describe!(SDTerminalOpen, server_daemon::terminal_open::SDTerminalOpenPacket),
+        describe!(WSTerminalInput, web_server::terminal_input::WSTerminalInputPacket),
+        describe!(SDTerminalInput, server_daemon::terminal_input::SDTerminalInputPacket),
+        describe!(DSTerminalOutput, daemon_server::terminal_output::DSTerminalOutputPacket),
+        describe!(SWTerminalOutput, server_web::terminal_output::SWTerminalOutputPacket),
+        describe!(WSTerminalClose, web_server::terminal_close::WSTerminalClosePacket),
+        describe!(SDTerminalClose, server_daemon::terminal_close::SDTerminalClosePacket),
+        describe!(DSTerminalClose, daemon_server::terminal_close::DSTerminalClosePacket),
+        describe!(SWTerminalClose, server_web::terminal_close::SWTerminalClosePacket),
+        describe!(WSCommand, web_server::command::WSCommandPacket),
+        describe!(SDCommand, server_daemon::command::SDCommandPacket),
+        describe!(DSCommandResult, daemon_server::command_result::DSCommandResultPacket),
+        describe!(SWCommandResult, server_web::command_result::SWCommandResultPacket),
+        describe!(WSUnlisten, web_server::unlisten::WSUnlistenPacket),
+    ];
+
+    packets.sort_by_key(|packet| packet.id);
+    packets
+}
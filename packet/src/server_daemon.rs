@@ -1,4 +1,20 @@
 pub mod auth_response;
+pub mod build_context;
+pub mod cancel_task;
+pub mod catalog;
+pub mod command;
+pub mod config;
 pub mod handshake_request;
 pub mod listen;
+pub mod query_logs;
+pub mod query_stats;
+pub mod query_tasks;
+pub mod query_top;
+pub mod query_usage;
+pub mod reconnect_to;
+pub mod server_metadata;
 pub mod sync;
+pub mod terminal_close;
+pub mod terminal_input;
+pub mod terminal_open;
+pub mod window_update;
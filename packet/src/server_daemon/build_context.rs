@@ -0,0 +1,12 @@
+/// An uploaded build context archive, sent in response to a `DSFetchBuildContextPacket`. Archives
+/// are usually large, so this packet is split into chunks when chunking is supported.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDBuildContextPacket {
+    /// Hex encoded SHA-256 hash of the archive
+    pub hash: String,
+    /// Base64 encoded tar archive, or `None` if it's not available to the daemon
+    pub data: Option<String>,
+}
+
+impl_packet!(SDBuildContextPacket, SDBuildContext);
@@ -0,0 +1,8 @@
+/// Cancels a queued or running task. The result is reported as a `Task` event.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDCancelTaskPacket {
+    pub task: u64,
+}
+
+impl_packet!(SDCancelTaskPacket, SDCancelTask);
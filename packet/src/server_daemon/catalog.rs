@@ -0,0 +1,20 @@
+use crate::{maintenance::MaintenanceWindow};
+
+/// An image of the catalog, pulled by daemons ahead of time.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct CatalogImage {
+    pub image: String,
+    pub docker_tag: String,
+}
+
+/// The catalog of commonly used images, which daemons pull while one of the `windows` is active,
+/// so servers using them can be created without waiting for a pull.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDCatalogPacket {
+    pub images: Vec<CatalogImage>,
+    pub windows: Vec<MaintenanceWindow>,
+}
+
+impl_packet!(SDCatalogPacket, SDCatalog);
@@ -0,0 +1,12 @@
+use crate::command::ServerCommand;
+
+/// Runs a command on a server, to be answered with a `DSCommandResultPacket`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDCommandPacket {
+    pub request: u64,
+    pub server: u32,
+    pub command: ServerCommand,
+}
+
+impl_packet!(SDCommandPacket, SDCommand);
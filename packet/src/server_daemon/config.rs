@@ -0,0 +1,35 @@
+use crate::{features::Feature};
+
+/// Daemon settings set by the server, which the daemon applies at runtime and persists, so they
+/// also apply before it reconnects after a restart. Settings that are `None` fall back to the
+/// daemon's own defaults, so the full set of settings is sent every time.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDConfigPacket {
+    /// Seconds between `NodeStatus` events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_status_interval: Option<u64>,
+    /// Minimum seconds between `ServerStatus` events of a server
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_status_interval: Option<u64>,
+    /// Most verbose level the daemon logs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<LogLevel>,
+    /// Features the daemon stops using, and doesn't offer when authenticating
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub disabled_features: Vec<Feature>,
+}
+
+/// A log level, from least to most verbose.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl_packet!(SDConfigPacket, SDConfig);
@@ -0,0 +1,10 @@
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDQueryLogsPacket {
+    pub request: u64,
+    pub server: u32,
+    /// Maximum amount of lines to return
+    pub lines: u32,
+}
+
+impl_packet!(SDQueryLogsPacket, SDQueryLogs);
@@ -0,0 +1,9 @@
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDQueryStatsPacket {
+    pub request: u64,
+    /// Server to sample, or `None` to sample the node itself
+    pub server: Option<u32>,
+}
+
+impl_packet!(SDQueryStatsPacket, SDQueryStats);
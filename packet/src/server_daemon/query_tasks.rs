@@ -0,0 +1,7 @@
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDQueryTasksPacket {
+    pub request: u64,
+}
+
+impl_packet!(SDQueryTasksPacket, SDQueryTasks);
@@ -0,0 +1,8 @@
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDQueryTopPacket {
+    pub request: u64,
+    pub server: u32,
+}
+
+impl_packet!(SDQueryTopPacket, SDQueryTop);
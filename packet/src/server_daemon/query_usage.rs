@@ -0,0 +1,10 @@
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDQueryUsagePacket {
+    pub request: u64,
+    pub server: u32,
+    pub from: u64,
+    pub to: u64,
+}
+
+impl_packet!(SDQueryUsagePacket, SDQueryUsage);
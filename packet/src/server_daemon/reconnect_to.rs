@@ -0,0 +1,10 @@
+/// Tells the daemon to connect to another server the next time it reconnects, as this server is
+/// about to shut down.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDReconnectToPacket {
+    /// URL of the server to connect to instead, e.g. `wss://daemon-2.server.aesterisk.io`
+    pub url: String,
+}
+
+impl_packet!(SDReconnectToPacket, SDReconnectTo);
@@ -0,0 +1,11 @@
+use crate::{server_daemon::sync::ServerMetadata};
+
+/// Sent whenever the display metadata of a server changes outside of a sync. The daemon replaces
+/// the container of the server if its labels differ, using the server's update strategy.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDServerMetadataPacket {
+    pub metadata: ServerMetadata,
+}
+
+impl_packet!(SDServerMetadataPacket, SDServerMetadata);
@@ -1,13 +1,15 @@
-use std::fmt::Display;
+use std::{collections::{BTreeMap, HashSet}, fmt::Display};
 
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
+use sha2::{Digest, Sha256};
 
-use crate::{Packet, Version, ID};
+use crate::maintenance::MaintenanceWindow;
 
 // serde(rename = "...") is used to minimise data required to transfer sync packets
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Network {
     #[serde(rename = "i")]
     pub id: u32,
@@ -15,7 +17,8 @@ pub struct Network {
     pub subnet: u8,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Server {
     #[serde(rename = "i")]
     pub id: u32,
@@ -27,9 +30,60 @@ pub struct Server {
     pub networks: Vec<ServerNetwork>,
     #[serde(rename = "p")]
     pub ports: Vec<Port>,
+    #[serde(rename = "o", default)]
+    pub isolation: Isolation,
+    #[serde(rename = "w", default)]
+    pub maintenance: Vec<MaintenanceWindow>,
+    /// Servers of the same daemon that have to be started before this server
+    #[serde(rename = "d", default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<Dependency>,
+    /// How the container is replaced when the server changes
+    #[serde(rename = "u", default, skip_serializing_if = "UpdateStrategy::is_recreate")]
+    pub update: UpdateStrategy,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize_repr, Deserialize_repr, Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema_repr))]
+#[repr(u8)]
+pub enum UpdateStrategy {
+    /// The old container is removed before the new one is created
+    #[default]
+    Recreate = 0,
+    /// The new container is started next to the old one (without published ports and on a
+    /// temporary IP), and only replaces it once it is healthy. Both containers share the server's
+    /// mounts while the update is in progress.
+    BlueGreen = 1,
+}
+
+impl UpdateStrategy {
+    pub fn is_recreate(&self) -> bool {
+        *self == UpdateStrategy::Recreate
+    }
+}
+
+impl From<u8> for UpdateStrategy {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => UpdateStrategy::Recreate,
+            1 => UpdateStrategy::BlueGreen,
+            _ => panic!("Invalid UpdateStrategy value: {}", value),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Dependency {
+    #[serde(rename = "s")]
+    pub server: u32,
+    /// Whether the server has to be healthy (instead of just started) before its dependents are
+    /// started
+    #[serde(rename = "h")]
+    pub healthy: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Tag {
     #[serde(rename = "i")]
     pub image: String,
@@ -41,9 +95,36 @@ pub struct Tag {
     pub mounts: Vec<Mount>,
     #[serde(rename = "e")]
     pub env_defs: Vec<EnvDef>,
+    /// How to build the image, or `None` if it's pulled from a registry. Built images are tagged
+    /// as `image:docker_tag` locally.
+    #[serde(rename = "b", default, skip_serializing_if = "Option::is_none")]
+    pub build: Option<Build>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Build {
+    #[serde(rename = "c")]
+    pub context: BuildContext,
+    /// Path of the Dockerfile, relative to the root of the build context
+    #[serde(rename = "f")]
+    pub dockerfile: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub enum BuildContext {
+    /// A Git repository, cloned by Docker. Supports Docker's `url#ref:dir` syntax.
+    #[serde(rename = "g")]
+    Git(String),
+    /// An uploaded tar archive, identified by the hex encoded SHA-256 hash of its contents. The
+    /// daemon requests it from the server with a `DSFetchBuildContextPacket`.
+    #[serde(rename = "a")]
+    Archive(String),
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Healthcheck {
     #[serde(rename = "t")]
     pub test: Vec<String>,
@@ -55,7 +136,8 @@ pub struct Healthcheck {
     pub retries: u64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Mount {
     #[serde(rename = "c")]
     pub container_path: String,
@@ -63,7 +145,8 @@ pub struct Mount {
     pub host_path: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct EnvDef {
     #[serde(rename = "k")]
     pub key: String,
@@ -83,7 +166,8 @@ pub struct EnvDef {
     pub trim: bool,
 }
 
-#[derive(Serialize_repr, Deserialize_repr, Debug)]
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema_repr))]
 #[repr(u8)]
 pub enum EnvType {
     Boolean = 0,
@@ -102,7 +186,8 @@ impl From<u8> for EnvType {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Env {
     #[serde(rename = "k")]
     pub key: String,
@@ -110,7 +195,8 @@ pub struct Env {
     pub value: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct ServerNetwork {
     #[serde(rename = "n")]
     pub network: u32,
@@ -118,17 +204,20 @@ pub struct ServerNetwork {
     pub ip: u8,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct Port {
     #[serde(rename = "p")]
     pub port: u16,
     #[serde(rename = "r")]
     pub protocol: Protocol,
+    /// Host port to map to, or `0` to let the daemon pick a free port from its configured range
     #[serde(rename = "m")]
     pub mapped: u16,
 }
 
-#[derive(Serialize_repr, Deserialize_repr, Debug)]
+#[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema_repr))]
 #[repr(u8)]
 pub enum Protocol {
     Tcp = 0,
@@ -154,40 +243,294 @@ impl Display for Protocol {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Isolation {
+    #[serde(rename = "p")]
+    pub policy: IsolationPolicy,
+    #[serde(rename = "a", default)]
+    pub allowlist: Vec<String>,
+}
+
+#[derive(Serialize_repr, Deserialize_repr, Debug, Default, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema_repr))]
+#[repr(u8)]
+pub enum IsolationPolicy {
+    /// Server can reach the internet, but not other servers outside of its networks
+    #[default]
+    FullEgress = 0,
+    /// Server cannot reach anything outside of its networks
+    NoInternet = 1,
+    /// Server can only reach its networks and the allowlisted CIDRs
+    InternalOnly = 2,
+}
+
+impl From<u8> for IsolationPolicy {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => IsolationPolicy::FullEgress,
+            1 => IsolationPolicy::NoInternet,
+            2 => IsolationPolicy::InternalOnly,
+            _ => panic!("Invalid IsolationPolicy value: {}", value),
+        }
+    }
+}
+
+/// Display metadata of a server, which is not part of the spec hash, so syncs never recreate a
+/// server because of it. Docker can't change the labels of an existing container, so daemons
+/// replace the container using the server's update strategy when the metadata changes, see
+/// `SDServerMetadataPacket`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ServerMetadata {
+    #[serde(rename = "s")]
+    pub server: u32,
+    #[serde(rename = "n")]
+    pub name: String,
+    #[serde(rename = "l", default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
 pub struct SDSyncPacket {
     #[serde(rename = "n")]
     pub networks: Vec<Network>,
     #[serde(rename = "s")]
     pub servers: Vec<Server>,
+    /// Maintenance windows of the node itself, which apply to all of its servers
+    #[serde(rename = "w", default)]
+    pub maintenance: Vec<MaintenanceWindow>,
+    /// Display metadata of the servers, always complete (even in delta syncs)
+    #[serde(rename = "m", default, skip_serializing_if = "Vec::is_empty")]
+    pub metadata: Vec<ServerMetadata>,
+    /// Hash of the spec, see `SDSyncPacket::spec_hash`
+    #[serde(rename = "h", default)]
+    pub hash: String,
+    /// Hash of the spec this sync is a delta of, or empty for a full sync. A delta only contains
+    /// the added and changed networks and servers, and the IDs of the removed ones.
+    #[serde(rename = "b", default)]
+    pub base: String,
+    /// Networks removed since the base spec, only used by delta syncs
+    #[serde(rename = "rn", default)]
+    pub removed_networks: Vec<u32>,
+    /// Servers removed since the base spec, only used by delta syncs
+    #[serde(rename = "rs", default)]
+    pub removed_servers: Vec<u32>,
+    /// Request id to report the progress of applying this sync with, if a web client is waiting for
+    /// it
+    #[serde(rename = "r", default)]
+    pub request: Option<u64>,
 }
 
 impl SDSyncPacket {
-    pub fn parse(packet: Packet) -> Option<Self> {
-        if packet.id != ID::SDSync {
-            return None;
-        }
+    /// Computes the SHA-256 hash (hex encoded) of the networks, servers and maintenance windows.
+    /// Networks and servers are sorted by their ID first, so the hash doesn't depend on the order
+    /// they were fetched in.
+    pub fn spec_hash(&self) -> Result<String, String> {
+        let mut networks = self.networks.iter().collect::<Vec<_>>();
+        networks.sort_by_key(|network| network.id);
 
-        match packet.version {
-            Version::V0_1_0 => {
-                let res = serde_json::from_value(packet.data);
+        let mut servers = self.servers.iter().collect::<Vec<_>>();
+        servers.sort_by_key(|server| server.id);
+
+        let spec = serde_json::to_string(&(networks, servers, &self.maintenance)).map_err(|_| "spec should be serializable")?;
+
+        Ok(Sha256::digest(spec.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect())
+    }
 
-                if res.is_err() {
-                    println!("W (Packet) SDSync deserializing error: {:#?}", res.as_ref().expect_err("Result::err should return Some when Result::is_err returns true"));
+    /// Returns the servers in the order they should be started in, so that servers are started
+    /// after the servers they depend on, along with the IDs of the servers that can't be ordered,
+    /// as they are part of a dependency cycle or depend on a server that is. Dependencies on
+    /// servers that are not part of the spec are ignored.
+    pub fn start_order(&self) -> (Vec<&Server>, Vec<u32>) {
+        let ids = self.servers.iter().map(|server| server.id).collect::<HashSet<_>>();
+        let mut started = HashSet::new();
+        let mut order = Vec::with_capacity(self.servers.len());
+
+        while order.len() < self.servers.len() {
+            let before = order.len();
+
+            for server in self.servers.iter() {
+                if !started.contains(&server.id) && server.depends_on.iter().all(|dependency| started.contains(&dependency.server) || !ids.contains(&dependency.server)) {
+                    started.insert(server.id);
+                    order.push(server);
                 }
+            }
 
-                res.ok()
+            if order.len() == before {
+                break;
             }
         }
+
+        let cyclic = self.servers.iter().map(|server| server.id).filter(|id| !started.contains(id)).collect();
+
+        (order, cyclic)
     }
 
-    pub fn to_packet(&self) -> Result<Packet, String> {
-        let data = serde_json::to_value(self).map_err(|_| "packet data should be serializeable")?;
-        Ok(Packet::new(Version::V0_1_0, ID::SDSync, data))
+    /// Returns the servers that directly or indirectly depend on a server, in start order. Servers
+    /// with cyclic dependencies are left out.
+    pub fn dependents(&self, id: u32) -> Vec<&Server> {
+        let mut affected = HashSet::from([id]);
+        let mut dependents = Vec::new();
+
+        for server in self.start_order().0 {
+            if server.depends_on.iter().any(|dependency| affected.contains(&dependency.server)) {
+                affected.insert(server.id);
+                dependents.push(server);
+            }
+        }
+
+        dependents
+    }
+
+    /// Returns the display metadata of a server, if any.
+    pub fn server_metadata(&self, id: u32) -> Option<&ServerMetadata> {
+        self.metadata.iter().find(|metadata| metadata.server == id)
+    }
+
+    /// Returns whether this is a delta sync, see `SDSyncPacket::diff`.
+    pub fn is_delta(&self) -> bool {
+        !self.base.is_empty()
+    }
+
+    /// Creates a delta sync which turns the `previous` (full) spec into this (full) spec.
+    pub fn diff(&self, previous: &SDSyncPacket) -> SDSyncPacket {
+        SDSyncPacket {
+            networks: self.networks.iter().filter(|network| !previous.networks.contains(network)).cloned().collect(),
+            servers: self.servers.iter().filter(|server| !previous.servers.contains(server)).cloned().collect(),
+            maintenance: self.maintenance.clone(),
+            metadata: self.metadata.clone(),
+            hash: self.hash.clone(),
+            base: previous.hash.clone(),
+            removed_networks: previous.networks.iter().map(|network| network.id).filter(|id| !self.networks.iter().any(|network| network.id == *id)).collect(),
+            removed_servers: previous.servers.iter().map(|server| server.id).filter(|id| !self.servers.iter().any(|server| server.id == *id)).collect(),
+            request: self.request,
+        }
+    }
+
+    /// Applies a delta sync to this (full) spec, and verifies the result against the hash of the
+    /// delta.
+    pub fn apply(&mut self, delta: SDSyncPacket) -> Result<(), String> {
+        if delta.base != self.hash {
+            return Err(format!("delta sync is based on {}, but the current spec is {}", delta.base, self.hash));
+        }
+
+        self.networks.retain(|network| !delta.removed_networks.contains(&network.id) && !delta.networks.iter().any(|changed| changed.id == network.id));
+        self.networks.extend(delta.networks);
+
+        self.servers.retain(|server| !delta.removed_servers.contains(&server.id) && !delta.servers.iter().any(|changed| changed.id == server.id));
+        self.servers.extend(delta.servers);
+
+        self.maintenance = delta.maintenance;
+        self.metadata = delta.metadata;
+        self.hash = self.spec_hash()?;
+
+        if self.hash != delta.hash {
+            return Err(format!("spec hash after applying delta sync does not match (expected {}, got {})", delta.hash, self.hash));
+        }
+
+        Ok(())
+    }
+
+    /// Sets the host ports the daemon assigned to a server, and updates the hash accordingly.
+    pub fn assign_ports(&mut self, id: u32, ports: &[Port]) -> Result<(), String> {
+        if let Some(server) = self.servers.iter_mut().find(|server| server.id == id) {
+            for port in server.ports.iter_mut() {
+                if let Some(assigned) = ports.iter().find(|assigned| assigned.port == port.port && assigned.protocol == port.protocol) {
+                    port.mapped = assigned.mapped;
+                }
+            }
+        }
+
+        self.hash = self.spec_hash()?;
+
+        Ok(())
+    }
+}
+
+impl_packet!(SDSyncPacket, SDSync);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(id: u32, depends_on: &[u32]) -> Server {
+        Server {
+            id,
+            tag: Tag {
+                image: "nginx".to_string(),
+                docker_tag: "latest".to_string(),
+                healthcheck: Healthcheck {
+                    test: Vec::new(),
+                    interval: 30,
+                    timeout: 5,
+                    retries: 3,
+                },
+                mounts: Vec::new(),
+                env_defs: Vec::new(),
+                build: None,
+            },
+            envs: Vec::new(),
+            networks: Vec::new(),
+            ports: Vec::new(),
+            isolation: Isolation::default(),
+            maintenance: Vec::new(),
+            depends_on: depends_on.iter().map(|server| Dependency {
+                server: *server,
+                healthy: false,
+            }).collect(),
+            update: UpdateStrategy::Recreate,
+        }
     }
 
-    pub fn to_string(&self) -> Result<String, String> {
-        let packet = self.to_packet()?;
-        Ok(serde_json::to_string(&packet).map_err(|_| "Packet could not be serialized")?)
+    fn spec(servers: Vec<Server>) -> SDSyncPacket {
+        SDSyncPacket {
+            networks: Vec::new(),
+            servers,
+            maintenance: Vec::new(),
+            metadata: Vec::new(),
+            hash: String::new(),
+            base: String::new(),
+            removed_networks: Vec::new(),
+            removed_servers: Vec::new(),
+            request: None,
+        }
+    }
+
+    fn ids(servers: Vec<&Server>) -> Vec<u32> {
+        servers.into_iter().map(|server| server.id).collect()
+    }
+
+    #[test]
+    fn start_order() {
+        // 4 depends on a server that isn't part of the spec, which is ignored
+        let spec = spec(vec![server(1, &[2, 3]), server(2, &[3]), server(3, &[]), server(4, &[9])]);
+        let (order, cyclic) = spec.start_order();
+
+        assert_eq!(ids(order), vec![3, 4, 2, 1]);
+        assert!(cyclic.is_empty());
+    }
+
+    #[test]
+    fn start_order_cycles() {
+        // 1 and 2 depend on each other, 3 depends on the cycle, and 4 and 5 are unaffected
+        let spec = spec(vec![server(1, &[2]), server(2, &[1]), server(3, &[1]), server(4, &[]), server(5, &[4])]);
+        let (order, cyclic) = spec.start_order();
+
+        assert_eq!(ids(order), vec![4, 5]);
+        assert_eq!(cyclic, vec![1, 2, 3]);
+
+        let spec = self::spec(vec![server(1, &[1])]);
+        assert_eq!(spec.start_order().1, vec![1]);
+    }
+
+    #[test]
+    fn dependents() {
+        let spec = spec(vec![server(1, &[]), server(2, &[1]), server(3, &[2]), server(4, &[]), server(5, &[6]), server(6, &[5, 1])]);
+
+        assert_eq!(ids(spec.dependents(1)), vec![2, 3]);
+        assert_eq!(ids(spec.dependents(3)), Vec::<u32>::new());
+        assert_eq!(ids(spec.dependents(4)), Vec::<u32>::new());
     }
 }
@@ -0,0 +1,10 @@
+use uuid::Uuid;
+
+/// Closes a terminal, as its web client closed it or disconnected.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDTerminalClosePacket {
+    pub session: Uuid,
+}
+
+impl_packet!(SDTerminalClosePacket, SDTerminalClose);
@@ -0,0 +1,27 @@
+use uuid::Uuid;
+
+use crate::terminal::TerminalSize;
+
+/// Input of a web client for a terminal, and/or credits for its output, see
+/// `WSTerminalInputPacket`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDTerminalInputPacket {
+    pub session: Uuid,
+    /// Base64 encoded bytes written to the TTY
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub data: String,
+    /// New size of the TTY, if the terminal was resized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resize: Option<TerminalSize>,
+    /// Credits granted for sending more output of the terminal, see `flow`. Only sent if
+    /// `Feature::TerminalFlowControl` was negotiated.
+    #[serde(default, skip_serializing_if = "crate::flow::is_zero")]
+    pub credits: u32,
+    /// Number of the packet among the input packets of the session, counting from 0, so they are
+    /// handled in order, see `terminal::Reorder`. Packets without one are handled as they arrive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+impl_packet!(SDTerminalInputPacket, SDTerminalInput);
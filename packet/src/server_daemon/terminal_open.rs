@@ -0,0 +1,17 @@
+use uuid::Uuid;
+
+use crate::terminal::TerminalSize;
+
+/// Opens a terminal into a server, requested by a web client, see `WSTerminalOpenPacket`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDTerminalOpenPacket {
+    pub session: Uuid,
+    pub server: u32,
+    pub size: TerminalSize,
+    /// Command to run in the container. Defaults to `/bin/sh`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+}
+
+impl_packet!(SDTerminalOpenPacket, SDTerminalOpen);
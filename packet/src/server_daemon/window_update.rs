@@ -0,0 +1,12 @@
+use crate::events::EventType;
+
+/// Grants the daemon's window for a streamed event type more credits, see `flow::Window`. Only
+/// sent if `Feature::FlowControl` was negotiated.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SDWindowUpdatePacket {
+    pub event: EventType,
+    pub credits: u32,
+}
+
+impl_packet!(SDWindowUpdatePacket, SDWindowUpdate);
@@ -1,3 +1,22 @@
 pub mod auth_response;
+pub mod command_result;
+pub mod error;
 pub mod event;
+pub mod export_spec_response;
 pub mod handshake_request;
+pub mod import_spec_response;
+pub mod place_server_response;
+pub mod query_connections_response;
+pub mod query_logs_response;
+pub mod query_metrics_response;
+pub mod query_notifications_response;
+pub mod query_stats_response;
+pub mod query_tasks_response;
+pub mod query_team_usage_response;
+pub mod query_top_response;
+pub mod query_usage_response;
+pub mod server_metadata_response;
+pub mod sync_group_result;
+pub mod sync_progress;
+pub mod terminal_close;
+pub mod terminal_output;
@@ -0,0 +1,15 @@
+use uuid::Uuid;
+
+use crate::command::ServerCommand;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWCommandResultPacket {
+    pub daemon: Uuid,
+    pub server: u32,
+    pub command: ServerCommand,
+    /// Why the command failed, or `None` if it succeeded
+    pub error: Option<String>,
+}
+
+impl_packet!(SWCommandResultPacket, SWCommandResult);
@@ -0,0 +1,33 @@
+use uuid::Uuid;
+
+/// Identifies the kind of error, so clients can react to it without parsing the message.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    /// A listen was rejected, as it would exceed the listen quota of the socket or user
+    ListenQuotaExceeded,
+    /// A daemon or server was rejected, as the team would exceed one of its quotas
+    QuotaExceeded,
+    /// A packet was dropped, as the socket exceeded the rate limit of its packet type
+    RateLimited,
+    /// A daemon could not apply a sync, e.g. as a container could not be created
+    SyncFailed,
+}
+
+/// Reports a failure to handle a packet of the web client, or a failure of a daemon it is
+/// interested in.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWErrorPacket {
+    pub code: ErrorCode,
+    pub message: String,
+    /// Daemon that reported the error, if it didn't happen on the server
+    #[serde(default)]
+    pub daemon: Option<Uuid>,
+    /// Request id of the packet of the web client that failed, if it was sent with one
+    #[serde(default)]
+    pub request: Option<u64>,
+}
+
+impl_packet!(SWErrorPacket, SWError);
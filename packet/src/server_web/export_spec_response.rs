@@ -0,0 +1,9 @@
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWExportSpecResponsePacket {
+    /// The spec, in TOML
+    pub spec: Option<String>,
+    pub error: Option<String>,
+}
+
+impl_packet!(SWExportSpecResponsePacket, SWExportSpecResponse);
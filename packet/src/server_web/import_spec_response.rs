@@ -0,0 +1,11 @@
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWImportSpecResponsePacket {
+    /// Descriptions of the changes needed to apply the spec
+    pub changes: Vec<String>,
+    /// Whether the changes were applied
+    pub applied: bool,
+    pub error: Option<String>,
+}
+
+impl_packet!(SWImportSpecResponsePacket, SWImportSpecResponse);
@@ -0,0 +1,25 @@
+use uuid::Uuid;
+
+/// A daemon a new server can be placed on.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct PlacementCandidate {
+    pub daemon: Uuid,
+    /// Average usage of the daemon's CPU, memory and server capacity, between 0 and 1
+    pub load: f64,
+    pub servers: u32,
+    pub max_servers: Option<u32>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWPlaceServerResponsePacket {
+    pub server: Option<u32>,
+    /// Eligible daemons of the user's team, least-loaded first
+    pub candidates: Vec<PlacementCandidate>,
+    /// Daemon the server was assigned to, if it was placed
+    pub assigned: Option<Uuid>,
+    pub error: Option<String>,
+}
+
+impl_packet!(SWPlaceServerResponsePacket, SWPlaceServerResponse);
@@ -0,0 +1,32 @@
+use uuid::Uuid;
+
+/// A connection of a daemon to the server, from the WebSocket upgrade until the disconnect.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct ConnectionSession {
+    /// Address the daemon connected from
+    pub addr: String,
+    /// Unix timestamp (seconds)
+    pub connected_at: u64,
+    /// Unix timestamp (seconds)
+    pub disconnected_at: u64,
+    /// Why the connection ended, e.g. closed by the daemon or a read error
+    pub reason: String,
+    /// Size of the received messages, in bytes
+    pub bytes_in: u64,
+    /// Size of the sent messages, in bytes
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWQueryConnectionsResponsePacket {
+    pub daemon: Uuid,
+    /// Sessions of the daemon, newest first
+    pub sessions: Vec<ConnectionSession>,
+    pub error: Option<String>,
+}
+
+impl_packet!(SWQueryConnectionsResponsePacket, SWQueryConnectionsResponse);
@@ -0,0 +1,13 @@
+use uuid::Uuid;
+
+use crate::{events::LogLine};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWQueryLogsResponsePacket {
+    pub daemon: Uuid,
+    pub server: u32,
+    pub lines: Vec<LogLine>,
+}
+
+impl_packet!(SWQueryLogsResponsePacket, SWQueryLogsResponse);
@@ -0,0 +1,13 @@
+use uuid::Uuid;
+
+use crate::{events::MetricSample};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWQueryMetricsResponsePacket {
+    pub daemon: Uuid,
+    pub server: Option<u32>,
+    pub samples: Vec<MetricSample>,
+}
+
+impl_packet!(SWQueryMetricsResponsePacket, SWQueryMetricsResponse);
@@ -0,0 +1,49 @@
+use uuid::Uuid;
+
+/// What a notification is about.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    /// An alert rule tripped or recovered
+    Alert,
+    /// A daemon could not apply a sync
+    SyncFailed,
+    /// A daemon reported an error that didn't happen during a sync
+    DaemonError,
+    /// A daemon group finished syncing
+    GroupSync,
+    /// An automation rule fired
+    Automation,
+    /// A command that the user ran on a server finished
+    CommandResult,
+}
+
+/// A notification stored in the inbox of a user, so it isn't lost if none of its web clients were
+/// connected when it happened.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Notification {
+    pub id: u64,
+    pub kind: NotificationKind,
+    /// Daemon the notification is about, if any
+    pub daemon: Option<Uuid>,
+    /// Server the notification is about, if any
+    pub server: Option<u32>,
+    pub message: String,
+    /// Unix timestamp (seconds)
+    pub created_at: u64,
+    pub read: bool,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWQueryNotificationsResponsePacket {
+    /// Notifications of the user, newest first
+    pub notifications: Vec<Notification>,
+    /// Amount of unread notifications of the user, including ones that weren't returned
+    pub unread: u64,
+    pub error: Option<String>,
+}
+
+impl_packet!(SWQueryNotificationsResponsePacket, SWQueryNotificationsResponse);
@@ -0,0 +1,15 @@
+use uuid::Uuid;
+
+use crate::{events::EventData};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWQueryStatsResponsePacket {
+    pub daemon: Uuid,
+    pub server: Option<u32>,
+    /// Either a `NodeStatus` or a `ServerStatus` event, as it would be sent to listeners
+    pub stats: Option<EventData>,
+    pub error: Option<String>,
+}
+
+impl_packet!(SWQueryStatsResponsePacket, SWQueryStatsResponse);
@@ -0,0 +1,13 @@
+use uuid::Uuid;
+
+use crate::{events::TaskEvent};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWQueryTasksResponsePacket {
+    pub daemon: Uuid,
+    pub tasks: Vec<TaskEvent>,
+    pub error: Option<String>,
+}
+
+impl_packet!(SWQueryTasksResponsePacket, SWQueryTasksResponse);
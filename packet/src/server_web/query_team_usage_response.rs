@@ -0,0 +1,38 @@
+/// Resource usage over time, summed up from hourly averages.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct Usage {
+    pub cpu_core_hours: f64,
+    pub memory_gb_hours: f64,
+    pub storage_gb_hours: f64,
+}
+
+impl Usage {
+    pub fn add(&mut self, other: &Usage) {
+        self.cpu_core_hours += other.cpu_core_hours;
+        self.memory_gb_hours += other.memory_gb_hours;
+        self.storage_gb_hours += other.storage_gb_hours;
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct MonthlyUsage {
+    /// Calendar month (UTC), as `YYYY-MM`
+    pub month: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWQueryTeamUsageResponsePacket {
+    pub team: u32,
+    /// Usage per month, oldest first. Months without any usage are left out.
+    pub months: Vec<MonthlyUsage>,
+    /// Sum of the usage of all returned months
+    pub total: Usage,
+    pub error: Option<String>,
+}
+
+impl_packet!(SWQueryTeamUsageResponsePacket, SWQueryTeamUsageResponse);
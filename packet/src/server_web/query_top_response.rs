@@ -0,0 +1,14 @@
+use uuid::Uuid;
+
+use crate::{events::ProcessTable};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWQueryTopResponsePacket {
+    pub daemon: Uuid,
+    pub server: u32,
+    pub table: Option<ProcessTable>,
+    pub error: Option<String>,
+}
+
+impl_packet!(SWQueryTopResponsePacket, SWQueryTopResponse);
@@ -0,0 +1,13 @@
+use uuid::Uuid;
+
+use crate::{events::UsageSample};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWQueryUsageResponsePacket {
+    pub daemon: Uuid,
+    pub server: u32,
+    pub samples: Vec<UsageSample>,
+}
+
+impl_packet!(SWQueryUsageResponsePacket, SWQueryUsageResponse);
@@ -0,0 +1,11 @@
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWServerMetadataResponsePacket {
+    pub daemon: Uuid,
+    pub server: u32,
+    pub error: Option<String>,
+}
+
+impl_packet!(SWServerMetadataResponsePacket, SWServerMetadataResponse);
@@ -0,0 +1,19 @@
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct GroupSyncResult {
+    pub daemon: Uuid,
+    /// Whether the daemon was connected, offline daemons are synced when they connect
+    pub online: bool,
+    pub error: Option<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWSyncGroupResultPacket {
+    pub group: u32,
+    pub results: Vec<GroupSyncResult>,
+}
+
+impl_packet!(SWSyncGroupResultPacket, SWSyncGroupResult);
@@ -0,0 +1,12 @@
+use uuid::Uuid;
+
+use crate::{events::SyncStep};
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWSyncProgressPacket {
+    pub daemon: Uuid,
+    pub step: SyncStep,
+}
+
+impl_packet!(SWSyncProgressPacket, SWSyncProgress);
@@ -0,0 +1,19 @@
+use uuid::Uuid;
+
+/// Reports that a terminal of the web client was closed, as its process exited, it could not be
+/// opened, or its daemon disconnected.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct SWTerminalClosePacket {
+    pub session: Uuid,
+    /// Exit code of the process, if it exited
+    pub exit_code: Option<i64>,
+    pub error: Option<String>,
+    /// Number of the packet among the output and close packets of the session, counting from 0, so
+    /// it is handled after the output, see `terminal::Reorder`. Packets without one are handled as
+    /// they arrive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+impl_packet!(SWTerminalClosePacket, SWTerminalClose);
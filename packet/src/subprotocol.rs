@@ -0,0 +1,151 @@
+use std::fmt::{Display, Formatter};
+
+#[cfg(feature = "websocket")]
+use tokio_tungstenite::tungstenite::Message;
+
+/// The name of the `Sec-WebSocket-Protocol` header.
+pub const HEADER: &str = "Sec-WebSocket-Protocol";
+
+/// A WebSocket subprotocol, which selects the protocol revision and the transport encoding of a
+/// connection when it is upgraded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Subprotocol {
+    /// Protocol revision 0, with packets serialized as MessagePack and sent as JWE compact
+    /// serialization in binary frames.
+    #[cfg(feature = "binary")]
+    V0MsgpackBinary,
+    /// Protocol revision 0, with packets serialized as JSON and sent as JWE compact serialization
+    /// in binary frames.
+    V0JweBinary,
+    /// Protocol revision 0, with packets serialized as JSON and sent as JWE compact serialization
+    /// in text frames.
+    V0JweJson,
+}
+
+/// The format packets are serialized in before they are encrypted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    /// Packets are sent as the `p` claim of a JWT.
+    Json,
+    /// Packets are sent in an `Envelope`, see `crate::envelope`.
+    #[cfg(feature = "binary")]
+    MessagePack,
+}
+
+/// The kind of WebSocket frames messages are sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Framing {
+    Text,
+    Binary,
+}
+
+impl Subprotocol {
+    /// All supported subprotocols, in order of preference.
+    pub const SUPPORTED: &[Subprotocol] = &[
+        #[cfg(feature = "binary")]
+        Subprotocol::V0MsgpackBinary,
+        Subprotocol::V0JweBinary,
+        Subprotocol::V0JweJson,
+    ];
+
+    /// The subprotocol of clients that don't offer any subprotocols.
+    pub const LEGACY: Subprotocol = Subprotocol::V0JweJson;
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            #[cfg(feature = "binary")]
+            Subprotocol::V0MsgpackBinary => "aesterisk.v0+msgpack-binary",
+            Subprotocol::V0JweBinary => "aesterisk.v0+jwe-binary",
+            Subprotocol::V0JweJson => "aesterisk.v0+jwe-json",
+        }
+    }
+
+    /// Returns the kind of frames messages are sent in. Text frames are always accepted, to stay
+    /// compatible with legacy peers.
+    pub fn framing(&self) -> Framing {
+        match self {
+            #[cfg(feature = "binary")]
+            Subprotocol::V0MsgpackBinary => Framing::Binary,
+            Subprotocol::V0JweBinary => Framing::Binary,
+            Subprotocol::V0JweJson => Framing::Text,
+        }
+    }
+
+    /// Returns the format packets are serialized in. Incoming packets are decoded according to
+    /// their JWE header instead, so either encoding is accepted.
+    pub fn encoding(&self) -> Encoding {
+        match self {
+            #[cfg(feature = "binary")]
+            Subprotocol::V0MsgpackBinary => Encoding::MessagePack,
+            Subprotocol::V0JweBinary | Subprotocol::V0JweJson => Encoding::Json,
+        }
+    }
+
+    pub fn parse(name: &str) -> Option<Self> {
+        Self::SUPPORTED.iter().copied().find(|protocol| protocol.as_str() == name)
+    }
+
+    /// Picks the most preferred supported subprotocol of the comma-separated list offered in a
+    /// `Sec-WebSocket-Protocol` header.
+    pub fn negotiate(offered: &str) -> Option<Self> {
+        let offered = offered.split(',').map(str::trim).collect::<Vec<_>>();
+
+        Self::SUPPORTED.iter().copied().find(|protocol| offered.contains(&protocol.as_str()))
+    }
+
+    /// Returns the value of the `Sec-WebSocket-Protocol` header offering all supported subprotocols.
+    pub fn offer() -> String {
+        Self::SUPPORTED.iter().map(Subprotocol::as_str).collect::<Vec<_>>().join(", ")
+    }
+}
+
+impl Display for Subprotocol {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Converts an outgoing message to the framing of the connection's subprotocol.
+#[cfg(feature = "websocket")]
+pub fn frame(protocol: Subprotocol, msg: Message) -> Message {
+    match (protocol.framing(), msg) {
+        (Framing::Binary, Message::Text(text)) => Message::Binary(text.into_bytes()),
+        (_, msg) => msg,
+    }
+}
+
+/// Returns the encrypted packet of an incoming text or binary message. Binary messages are only
+/// accepted if the connection's subprotocol frames messages as binary.
+#[cfg(feature = "websocket")]
+pub fn unframe(protocol: Subprotocol, msg: Message) -> Result<String, String> {
+    match (protocol.framing(), msg) {
+        (_, Message::Text(text)) => Ok(text),
+        (Framing::Binary, Message::Binary(data)) => String::from_utf8(data).map_err(|_| "Binary message is not valid UTF-8".to_string()),
+        (Framing::Text, Message::Binary(_)) => Err(format!("Binary messages are not supported by {}", protocol)),
+        _ => Err("Unexpected message type".to_string()),
+    }
+}
+
+#[cfg(all(test, feature = "websocket"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_framed_per_subprotocol() {
+        let text = || Message::Text("packet".to_string());
+
+        assert_eq!(frame(Subprotocol::V0JweBinary, text()), Message::Binary(b"packet".to_vec()));
+        assert_eq!(frame(Subprotocol::V0JweJson, text()), text());
+    }
+
+    #[test]
+    fn binary_messages_only_accepted_if_negotiated() {
+        let binary = || Message::Binary(b"packet".to_vec());
+
+        assert_eq!(unframe(Subprotocol::V0JweBinary, binary()), Ok("packet".to_string()));
+        assert_eq!(unframe(Subprotocol::V0JweJson, Message::Text("packet".to_string())), Ok("packet".to_string()));
+        assert!(unframe(Subprotocol::V0JweJson, binary()).is_err());
+        assert!(unframe(Subprotocol::V0JweBinary, Message::Binary(vec![0xff])).is_err());
+        assert!(unframe(Subprotocol::V0JweBinary, Message::Ping(Vec::new())).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// Most packets of a terminal held back until the ones before them arrive, see `Reorder`.
+pub const MAX_OUT_OF_ORDER: usize = 1024;
+
+/// The size of the TTY of a terminal, in characters.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct TerminalSize {
+    pub cols: u16,
+    pub rows: u16,
+}
+
+/// Puts the packets of a terminal back into the order they were sent in, as both ends handle the
+/// packets of a connection concurrently. Senders number the packets of a session in one direction
+/// from 0, packets without a sequence number are passed on as they arrive. Servers also put the
+/// events of a daemon back into order with it, see `DSEventPacket::seq`.
+#[derive(Debug)]
+pub struct Reorder<T> {
+    next: u64,
+    pending: BTreeMap<u64, T>,
+}
+
+impl<T> Default for Reorder<T> {
+    fn default() -> Self {
+        Self {
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+}
+
+impl<T> Reorder<T> {
+    /// Creates a `Reorder` for senders that number their packets from `next`.
+    pub fn starting_at(next: u64) -> Self {
+        Self {
+            next,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    /// Adds a packet that arrived, returning the packets that are next in order, if any. Fails for
+    /// packets that already arrived, or for packets that would have to be held back once
+    /// `MAX_OUT_OF_ORDER` packets are.
+    pub fn push(&mut self, seq: Option<u64>, packet: T) -> Result<Vec<T>, String> {
+        let Some(seq) = seq else {
+            return Ok(vec![packet]);
+        };
+
+        if seq < self.next || self.pending.contains_key(&seq) {
+            return Err(format!("Packet {} arrived twice", seq));
+        }
+
+        if seq != self.next && self.pending.len() >= MAX_OUT_OF_ORDER {
+            return Err(format!("More than {} packets arrived out of order", MAX_OUT_OF_ORDER));
+        }
+
+        self.pending.insert(seq, packet);
+
+        Ok(self.ready())
+    }
+
+    /// Like `push`, but once `MAX_OUT_OF_ORDER` packets are held back, gives up on the packets
+    /// missing before them instead of failing, for senders whose packets can get lost. Still fails
+    /// for packets that already arrived.
+    pub fn push_lossy(&mut self, seq: Option<u64>, packet: T) -> Result<Vec<T>, String> {
+        let mut ready = Vec::new();
+
+        if let Some(seq) = seq
+            && seq > self.next
+            && self.pending.len() >= MAX_OUT_OF_ORDER {
+            self.next = self.pending.keys().next().map_or(seq, |first| seq.min(*first));
+            ready = self.ready();
+        }
+
+        ready.extend(self.push(seq, packet)?);
+
+        Ok(ready)
+    }
+
+    /// Takes the held back packets that are next in order.
+    fn ready(&mut self) -> Vec<T> {
+        let mut ready = Vec::new();
+        while let Some(packet) = self.pending.remove(&self.next) {
+            ready.push(packet);
+            self.next += 1;
+        }
+
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_reordered() {
+        let mut reorder = Reorder::default();
+
+        assert_eq!(reorder.push(Some(1), 'b'), Ok(Vec::new()));
+        assert_eq!(reorder.push(Some(2), 'c'), Ok(Vec::new()));
+        assert_eq!(reorder.push(Some(0), 'a'), Ok(vec!['a', 'b', 'c']));
+        assert_eq!(reorder.push(Some(3), 'd'), Ok(vec!['d']));
+    }
+
+    #[test]
+    fn unnumbered_packets_passed_on() {
+        let mut reorder = Reorder::default();
+
+        assert_eq!(reorder.push(Some(1), 'b'), Ok(Vec::new()));
+        assert_eq!(reorder.push(None, 'x'), Ok(vec!['x']));
+    }
+
+    #[test]
+    fn duplicates_rejected() {
+        let mut reorder = Reorder::default();
+
+        reorder.push(Some(0), 'a').expect("could not push packet");
+        reorder.push(Some(2), 'c').expect("could not push packet");
+
+        assert!(reorder.push(Some(0), 'a').is_err());
+        assert!(reorder.push(Some(2), 'c').is_err());
+    }
+
+    #[test]
+    fn held_back_packets_limited() {
+        let mut reorder = Reorder::default();
+
+        for seq in 1..=MAX_OUT_OF_ORDER as u64 {
+            reorder.push(Some(seq), seq).expect("could not push packet");
+        }
+
+        assert!(reorder.push(Some(MAX_OUT_OF_ORDER as u64 + 1), 0).is_err());
+
+        // the packet they wait for is still taken
+        assert_eq!(reorder.push(Some(0), 0).map(|ready| ready.len()), Ok(MAX_OUT_OF_ORDER + 1));
+    }
+
+    #[test]
+    fn lost_packets_skipped() {
+        let mut reorder = Reorder::starting_at(1);
+
+        for seq in 3..MAX_OUT_OF_ORDER as u64 + 3 {
+            assert_eq!(reorder.push_lossy(Some(seq), seq), Ok(Vec::new()));
+        }
+
+        // packets 1 and 2 were lost, so the held back packets are passed on once there are too many
+        let ready = reorder.push_lossy(Some(MAX_OUT_OF_ORDER as u64 + 3), 0).expect("could not push packet");
+        assert_eq!(ready.len(), MAX_OUT_OF_ORDER + 1);
+        assert_eq!(ready[0], 3);
+
+        assert!(reorder.push_lossy(Some(1), 1).is_err());
+    }
+}
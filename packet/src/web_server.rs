@@ -1,4 +1,27 @@
 pub mod auth;
+pub mod cancel_task;
+pub mod command;
+pub mod export_spec;
 pub mod handshake_response;
+pub mod import_spec;
 pub mod listen;
+pub mod mark_notifications_read;
+pub mod place_server;
+pub mod query_connections;
+pub mod query_logs;
+pub mod query_metrics;
+pub mod query_notifications;
+pub mod query_snapshot;
+pub mod query_stats;
+pub mod query_tasks;
+pub mod query_team_usage;
+pub mod query_top;
+pub mod query_usage;
+pub mod server_metadata;
 pub mod sync;
+pub mod sync_group;
+pub mod terminal_close;
+pub mod terminal_input;
+pub mod terminal_open;
+pub mod unlisten;
+pub mod window_update;
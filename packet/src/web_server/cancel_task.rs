@@ -0,0 +1,11 @@
+use uuid::Uuid;
+
+/// Cancels a queued or running task of a daemon. The result is reported as a `Task` event.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSCancelTaskPacket {
+    pub daemon: Uuid,
+    pub task: u64,
+}
+
+impl_packet!(WSCancelTaskPacket, WSCancelTask);
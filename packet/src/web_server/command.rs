@@ -0,0 +1,15 @@
+use uuid::Uuid;
+
+use crate::command::ServerCommand;
+
+/// Runs a command on a server of a daemon of the web client's team. The outcome is reported in a
+/// `SWCommandResultPacket`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSCommandPacket {
+    pub daemon: Uuid,
+    pub server: u32,
+    pub command: ServerCommand,
+}
+
+impl_packet!(WSCommandPacket, WSCommand);
@@ -0,0 +1,11 @@
+use uuid::Uuid;
+
+/// Exports the networks and servers of some of the user's team's nodes as a declarative spec.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSExportSpecPacket {
+    /// Nodes to export, or all nodes of the team if empty
+    pub daemons: Vec<Uuid>,
+}
+
+impl_packet!(WSExportSpecPacket, WSExportSpec);
@@ -0,0 +1,16 @@
+/// Imports a declarative spec of the networks and servers of some of the user's team's nodes.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSImportSpecPacket {
+    /// The spec, in TOML unless `yaml` is set
+    pub spec: String,
+    /// Whether the spec is in YAML
+    #[serde(default)]
+    pub yaml: bool,
+    /// Whether networks and servers of the spec's nodes that are not in the spec are deleted
+    pub prune: bool,
+    /// Whether to only report the changes, without applying them
+    pub dry_run: bool,
+}
+
+impl_packet!(WSImportSpecPacket, WSImportSpec);
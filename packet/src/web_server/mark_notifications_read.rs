@@ -0,0 +1,12 @@
+/// Marks notifications of the web client's user as read. Send it with a request id to be told once
+/// they are, see `ack`.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSMarkNotificationsReadPacket {
+    pub ids: Vec<u64>,
+    /// Marks all notifications of the user as read instead of `ids`
+    #[serde(default)]
+    pub all: bool,
+}
+
+impl_packet!(WSMarkNotificationsReadPacket, WSMarkNotificationsRead);
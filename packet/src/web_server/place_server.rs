@@ -0,0 +1,9 @@
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSPlaceServerPacket {
+    /// Server to assign to the least-loaded daemon, or `None` to only get suggestions. Only
+    /// servers that are not assigned to a daemon yet can be placed.
+    pub server: Option<u32>,
+}
+
+impl_packet!(WSPlaceServerPacket, WSPlaceServer);
@@ -0,0 +1,13 @@
+use uuid::Uuid;
+
+/// Queries the connection history of a daemon of the web client's team.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSQueryConnectionsPacket {
+    pub daemon: Uuid,
+    /// Maximum amount of sessions to return, newest first. Defaults to 50, at most 500.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+impl_packet!(WSQueryConnectionsPacket, WSQueryConnections);
@@ -0,0 +1,12 @@
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSQueryLogsPacket {
+    pub daemon: Uuid,
+    pub server: u32,
+    /// Maximum amount of lines to return
+    pub lines: u32,
+}
+
+impl_packet!(WSQueryLogsPacket, WSQueryLogs);
@@ -0,0 +1,15 @@
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSQueryMetricsPacket {
+    pub daemon: Uuid,
+    /// Server to query, or the node itself if `None`
+    pub server: Option<u32>,
+    /// Start of the range, in seconds since the unix epoch (inclusive)
+    pub from: u64,
+    /// End of the range, in seconds since the unix epoch (inclusive)
+    pub to: u64,
+}
+
+impl_packet!(WSQueryMetricsPacket, WSQueryMetrics);
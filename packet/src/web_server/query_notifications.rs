@@ -0,0 +1,16 @@
+/// Queries the notification inbox of the web client's user, newest first.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSQueryNotificationsPacket {
+    /// Only return notifications that weren't marked as read yet
+    #[serde(default)]
+    pub unread_only: bool,
+    /// Only return notifications with an id lower than this, to page through older notifications
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub before: Option<u64>,
+    /// Maximum amount of notifications to return. Defaults to 50, at most 500.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u32>,
+}
+
+impl_packet!(WSQueryNotificationsPacket, WSQueryNotifications);
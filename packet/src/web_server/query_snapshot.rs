@@ -0,0 +1,12 @@
+use uuid::Uuid;
+
+/// Asks for the latest status of a daemon, e.g. after a gap in the sequence numbers of its events.
+/// The server answers with the latest `NodeStatus` and `ServerStatus` events it received from the
+/// daemon, for the event types the web client listens to.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSQuerySnapshotPacket {
+    pub daemon: Uuid,
+}
+
+impl_packet!(WSQuerySnapshotPacket, WSQuerySnapshot);
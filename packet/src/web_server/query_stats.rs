@@ -0,0 +1,11 @@
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSQueryStatsPacket {
+    pub daemon: Uuid,
+    /// Server to sample, or `None` to sample the node itself
+    pub server: Option<u32>,
+}
+
+impl_packet!(WSQueryStatsPacket, WSQueryStats);
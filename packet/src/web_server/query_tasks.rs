@@ -0,0 +1,10 @@
+use uuid::Uuid;
+
+/// Lists the tasks of a daemon, both unfinished ones and recently finished ones.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSQueryTasksPacket {
+    pub daemon: Uuid,
+}
+
+impl_packet!(WSQueryTasksPacket, WSQueryTasks);
@@ -0,0 +1,11 @@
+/// Queries the monthly resource usage of the web client's team.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSQueryTeamUsagePacket {
+    /// First month to return, as `YYYY-MM`
+    pub from: String,
+    /// Last month to return (inclusive), as `YYYY-MM`
+    pub to: String,
+}
+
+impl_packet!(WSQueryTeamUsagePacket, WSQueryTeamUsage);
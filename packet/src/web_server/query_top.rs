@@ -0,0 +1,10 @@
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSQueryTopPacket {
+    pub daemon: Uuid,
+    pub server: u32,
+}
+
+impl_packet!(WSQueryTopPacket, WSQueryTop);
@@ -0,0 +1,14 @@
+use uuid::Uuid;
+
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSQueryUsagePacket {
+    pub daemon: Uuid,
+    pub server: u32,
+    /// Start of the range, in seconds since the unix epoch (inclusive)
+    pub from: u64,
+    /// End of the range, in seconds since the unix epoch (inclusive)
+    pub to: u64,
+}
+
+impl_packet!(WSQueryUsagePacket, WSQueryUsage);
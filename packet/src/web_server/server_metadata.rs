@@ -0,0 +1,18 @@
+use std::collections::BTreeMap;
+
+use uuid::Uuid;
+
+/// Updates the display metadata of a server. The container of the server is replaced using its
+/// update strategy to apply the new labels, as Docker can't change them in place.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSServerMetadataPacket {
+    pub daemon: Uuid,
+    pub server: u32,
+    /// New friendly name of the server, or `None` to keep the current one
+    pub name: Option<String>,
+    /// New custom labels of the server, replacing all current ones, or `None` to keep them
+    pub labels: Option<BTreeMap<String, String>>,
+}
+
+impl_packet!(WSServerMetadataPacket, WSServerMetadata);
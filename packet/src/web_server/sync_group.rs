@@ -0,0 +1,7 @@
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSSyncGroupPacket {
+    pub group: u32,
+}
+
+impl_packet!(WSSyncGroupPacket, WSSyncGroup);
@@ -0,0 +1,10 @@
+use uuid::Uuid;
+
+/// Closes a terminal of the web client, killing the process running in it.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSTerminalClosePacket {
+    pub session: Uuid,
+}
+
+impl_packet!(WSTerminalClosePacket, WSTerminalClose);
@@ -0,0 +1,28 @@
+use uuid::Uuid;
+
+use crate::terminal::TerminalSize;
+
+/// Input typed into a terminal of the web client, a new size of its TTY, and/or credits for its
+/// output.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSTerminalInputPacket {
+    pub session: Uuid,
+    /// Base64 encoded bytes written to the TTY
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub data: String,
+    /// New size of the TTY, if the terminal was resized
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub resize: Option<TerminalSize>,
+    /// Output packets of the terminal the web client has processed, granting the daemon as many more
+    /// credits to send output, see `flow`. Only used if `Feature::TerminalFlowControl` was
+    /// negotiated.
+    #[serde(default, skip_serializing_if = "crate::flow::is_zero")]
+    pub credits: u32,
+    /// Number of the packet among the input packets of the session, counting from 0, so they are
+    /// handled in order, see `terminal::Reorder`. Packets without one are handled as they arrive.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seq: Option<u64>,
+}
+
+impl_packet!(WSTerminalInputPacket, WSTerminalInput);
@@ -0,0 +1,21 @@
+use uuid::Uuid;
+
+use crate::terminal::TerminalSize;
+
+/// Opens a terminal into a running server of a daemon of the web client's team. The output of the
+/// terminal is sent in `SWTerminalOutputPacket`s until it is closed by either side.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSTerminalOpenPacket {
+    /// Id of the terminal session, chosen by the web client. Has to be unique, so a random UUID
+    /// should be used.
+    pub session: Uuid,
+    pub daemon: Uuid,
+    pub server: u32,
+    pub size: TerminalSize,
+    /// Command to run in the container. Defaults to `/bin/sh`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub command: Vec<String>,
+}
+
+impl_packet!(WSTerminalOpenPacket, WSTerminalOpen);
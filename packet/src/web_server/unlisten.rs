@@ -0,0 +1,13 @@
+use crate::events::ListenEvent;
+
+/// Removes subscriptions of the web client, added by `WSListenPacket`s. With `ServerStatus` and
+/// `ServerLog`, only the subscriptions to the `servers` are removed if any are given, and the
+/// daemons are unsubscribed from once no servers are left. A subscription to the status of all
+/// servers of a daemon is only removed as a whole.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSUnlistenPacket {
+    pub events: Vec<ListenEvent>,
+}
+
+impl_packet!(WSUnlistenPacket, WSUnlisten);
@@ -0,0 +1,15 @@
+use uuid::Uuid;
+
+use crate::events::EventType;
+
+/// Grants the web client's window for a streamed event type of a daemon more credits, see
+/// `flow::Window`. Only sent if `Feature::FlowControl` was negotiated.
+#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[cfg_attr(feature = "schema", derive(schemars::JsonSchema))]
+pub struct WSWindowUpdatePacket {
+    pub daemon: Uuid,
+    pub event: EventType,
+    pub credits: u32,
+}
+
+impl_packet!(WSWindowUpdatePacket, WSWindowUpdate);
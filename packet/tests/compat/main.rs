@@ -0,0 +1,194 @@
+//! Wire-compatibility tests, protecting rolling upgrades of daemons and servers.
+//!
+//! `fixtures/<version>/<ID>.json` holds every packet as produced by that version of the protocol.
+//! Fixtures are never changed once committed: all of them have to keep parsing, and the current
+//! version has to serialize every field of its fixtures unchanged. Fields may be added, as peers
+//! ignore fields they don't know. If a change to a packet alters its wire format otherwise, bump
+//! `Version` and add fixtures for the new version instead of editing the old ones.
+
+use std::{fs, path::PathBuf, str::FromStr};
+
+use aesterisk_packet::{ack, chunk, daemon_server, heartbeat, server_daemon, server_web, web_server, Packet, Version, ID};
+use serde_json::Value;
+
+#[cfg(feature = "binary")]
+use aesterisk_packet::envelope::Envelope;
+
+/// Directory name of the fixtures of the current version.
+const CURRENT: &str = "v0.1.0";
+
+fn fixtures_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/compat/fixtures")
+}
+
+/// Returns the path of the first value of `expected` that `actual` doesn't contain unchanged, where
+/// objects in `actual` may contain additional fields.
+fn changed(expected: &Value, actual: &Value, path: String) -> Option<String> {
+    match (expected, actual) {
+        (Value::Object(expected), Value::Object(actual)) => expected.iter().find_map(|(key, value)| match actual.get(key) {
+            Some(actual) => changed(value, actual, format!("{}.{}", path, key)),
+            None => Some(format!("{}.{}", path, key)),
+        }),
+        (Value::Array(expected), Value::Array(actual)) if expected.len() == actual.len() => expected.iter().zip(actual)
+            .enumerate()
+            .find_map(|(i, (expected, actual))| changed(expected, actual, format!("{}[{}]", path, i))),
+        (expected, actual) if expected == actual => None,
+        _ => Some(path),
+    }
+}
+
+/// Returns the fixtures of a packet in every version, as `(version, raw JSON)`.
+fn fixtures(name: &str) -> Vec<(String, String)> {
+    let mut fixtures = fs::read_dir(fixtures_dir()).expect("could not read fixtures")
+        .map(|entry| entry.expect("could not read fixture directory").path())
+        .filter_map(|dir| {
+            let version = dir.file_name()?.to_string_lossy().to_string();
+            let json = fs::read_to_string(dir.join(format!("{}.json", name))).ok()?;
+            Some((version, json))
+        })
+        .collect::<Vec<_>>();
+
+    fixtures.sort();
+    fixtures
+}
+
+macro_rules! compat {
+    ($($test:ident: $id:ident => $packet:ty),* $(,)?) => {
+        $(
+            #[test]
+            fn $test() {
+                let fixtures = fixtures(stringify!($id));
+                assert!(fixtures.iter().any(|(version, _)| version == CURRENT), "{} has no fixture for {}", stringify!($id), CURRENT);
+
+                for (version, json) in fixtures {
+                    let packet = Packet::from_str(&json).unwrap_or_else(|e| panic!("{} {} is no longer a valid packet: {}", version, stringify!($id), e));
+                    assert!(packet.id == ID::$id, "{} {} has the wrong ID", version, stringify!($id));
+
+                    #[cfg(feature = "binary")]
+                    {
+                        let envelope = Envelope::new(Packet::from_str(&json).expect("fixture should parse twice"), "compat").expect("could not create envelope");
+                        let decoded = Envelope::from_bytes(&envelope.to_bytes().expect("could not encode envelope")).expect("could not decode envelope").packet;
+                        assert!(decoded.id == packet.id && decoded.data == packet.data, "{} {} changes when sent as MessagePack", version, stringify!($id));
+                    }
+
+                    let current = packet.version == Version::V0_1_0 && version == CURRENT;
+                    let expected = packet.data.clone();
+
+                    let parsed = <$packet>::parse(packet).unwrap_or_else(|| panic!("{} {} can no longer be parsed", version, stringify!($id)));
+
+                    if current {
+                        let data = serde_json::to_value(&parsed).expect("could not serialize packet");
+
+                        if let Some(path) = changed(&expected, &data, "data".to_string()) {
+                            panic!("{} changed its wire format at {}, bump Version and add fixtures for the new version", stringify!($id), path);
+                        }
+                    }
+                }
+            }
+        )*
+
+        /// Every packet needs to be covered by a test above.
+        #[test]
+        fn all_packets_covered() {
+            let covered = [$(ID::$id as u8),*];
+
+            for id in 0..=u8::MAX {
+                if serde_json::from_value::<ID>(Value::from(id)).is_ok() {
+                    assert!(covered.contains(&id), "packet {} has no compatibility test", id);
+                }
+            }
+        }
+    };
+}
+
+compat! {
+    ws_auth: WSAuth => web_server::auth::WSAuthPacket,
+    ds_auth: DSAuth => daemon_server::auth::DSAuthPacket,
+    sw_handshake_request: SWHandshakeRequest => server_web::handshake_request::SWHandshakeRequestPacket,
+    sd_handshake_request: SDHandshakeRequest => server_daemon::handshake_request::SDHandshakeRequestPacket,
+    ws_handshake_response: WSHandshakeResponse => web_server::handshake_response::WSHandshakeResponsePacket,
+    ds_handshake_response: DSHandshakeResponse => daemon_server::handshake_response::DSHandshakeResponsePacket,
+    sw_auth_response: SWAuthResponse => server_web::auth_response::SWAuthResponsePacket,
+    sd_auth_response: SDAuthResponse => server_daemon::auth_response::SDAuthResponsePacket,
+    ws_listen: WSListen => web_server::listen::WSListenPacket,
+    sd_listen: SDListen => server_daemon::listen::SDListenPacket,
+    ds_event: DSEvent => daemon_server::event::DSEventPacket,
+    sw_event: SWEvent => server_web::event::SWEventPacket,
+    ws_sync: WSSync => web_server::sync::WSSyncPacket,
+    sd_sync: SDSync => server_daemon::sync::SDSyncPacket,
+    ds_sync_result: DSSyncResult => daemon_server::sync_result::DSSyncResultPacket,
+    ws_query_logs: WSQueryLogs => web_server::query_logs::WSQueryLogsPacket,
+    sd_query_logs: SDQueryLogs => server_daemon::query_logs::SDQueryLogsPacket,
+    ds_query_logs_response: DSQueryLogsResponse => daemon_server::query_logs_response::DSQueryLogsResponsePacket,
+    sw_query_logs_response: SWQueryLogsResponse => server_web::query_logs_response::SWQueryLogsResponsePacket,
+    ws_query_top: WSQueryTop => web_server::query_top::WSQueryTopPacket,
+    sd_query_top: SDQueryTop => server_daemon::query_top::SDQueryTopPacket,
+    ds_query_top_response: DSQueryTopResponse => daemon_server::query_top_response::DSQueryTopResponsePacket,
+    sw_query_top_response: SWQueryTopResponse => server_web::query_top_response::SWQueryTopResponsePacket,
+    ws_query_usage: WSQueryUsage => web_server::query_usage::WSQueryUsagePacket,
+    sd_query_usage: SDQueryUsage => server_daemon::query_usage::SDQueryUsagePacket,
+    ds_query_usage_response: DSQueryUsageResponse => daemon_server::query_usage_response::DSQueryUsageResponsePacket,
+    sw_query_usage_response: SWQueryUsageResponse => server_web::query_usage_response::SWQueryUsageResponsePacket,
+    ws_query_metrics: WSQueryMetrics => web_server::query_metrics::WSQueryMetricsPacket,
+    sw_query_metrics_response: SWQueryMetricsResponse => server_web::query_metrics_response::SWQueryMetricsResponsePacket,
+    ws_sync_group: WSSyncGroup => web_server::sync_group::WSSyncGroupPacket,
+    sw_sync_group_result: SWSyncGroupResult => server_web::sync_group_result::SWSyncGroupResultPacket,
+    chunk: Chunk => chunk::ChunkPacket,
+    ds_sync_progress: DSSyncProgress => daemon_server::sync_progress::DSSyncProgressPacket,
+    sw_sync_progress: SWSyncProgress => server_web::sync_progress::SWSyncProgressPacket,
+    ws_query_stats: WSQueryStats => web_server::query_stats::WSQueryStatsPacket,
+    sd_query_stats: SDQueryStats => server_daemon::query_stats::SDQueryStatsPacket,
+    ds_query_stats_response: DSQueryStatsResponse => daemon_server::query_stats_response::DSQueryStatsResponsePacket,
+    sw_query_stats_response: SWQueryStatsResponse => server_web::query_stats_response::SWQueryStatsResponsePacket,
+    sw_error: SWError => server_web::error::SWErrorPacket,
+    ws_place_server: WSPlaceServer => web_server::place_server::WSPlaceServerPacket,
+    sw_place_server_response: SWPlaceServerResponse => server_web::place_server_response::SWPlaceServerResponsePacket,
+    sd_catalog: SDCatalog => server_daemon::catalog::SDCatalogPacket,
+    ds_fetch_build_context: DSFetchBuildContext => daemon_server::fetch_build_context::DSFetchBuildContextPacket,
+    sd_build_context: SDBuildContext => server_daemon::build_context::SDBuildContextPacket,
+    ws_import_spec: WSImportSpec => web_server::import_spec::WSImportSpecPacket,
+    sw_import_spec_response: SWImportSpecResponse => server_web::import_spec_response::SWImportSpecResponsePacket,
+    ws_export_spec: WSExportSpec => web_server::export_spec::WSExportSpecPacket,
+    sw_export_spec_response: SWExportSpecResponse => server_web::export_spec_response::SWExportSpecResponsePacket,
+    ws_server_metadata: WSServerMetadata => web_server::server_metadata::WSServerMetadataPacket,
+    sw_server_metadata_response: SWServerMetadataResponse => server_web::server_metadata_response::SWServerMetadataResponsePacket,
+    sd_server_metadata: SDServerMetadata => server_daemon::server_metadata::SDServerMetadataPacket,
+    ws_query_team_usage: WSQueryTeamUsage => web_server::query_team_usage::WSQueryTeamUsagePacket,
+    sw_query_team_usage_response: SWQueryTeamUsageResponse => server_web::query_team_usage_response::SWQueryTeamUsageResponsePacket,
+    ws_query_connections: WSQueryConnections => web_server::query_connections::WSQueryConnectionsPacket,
+    sw_query_connections_response: SWQueryConnectionsResponse => server_web::query_connections_response::SWQueryConnectionsResponsePacket,
+    sd_reconnect_to: SDReconnectTo => server_daemon::reconnect_to::SDReconnectToPacket,
+    sd_config: SDConfig => server_daemon::config::SDConfigPacket,
+    ws_query_tasks: WSQueryTasks => web_server::query_tasks::WSQueryTasksPacket,
+    sd_query_tasks: SDQueryTasks => server_daemon::query_tasks::SDQueryTasksPacket,
+    ds_query_tasks_response: DSQueryTasksResponse => daemon_server::query_tasks_response::DSQueryTasksResponsePacket,
+    sw_query_tasks_response: SWQueryTasksResponse => server_web::query_tasks_response::SWQueryTasksResponsePacket,
+    ws_cancel_task: WSCancelTask => web_server::cancel_task::WSCancelTaskPacket,
+    sd_cancel_task: SDCancelTask => server_daemon::cancel_task::SDCancelTaskPacket,
+    ws_window_update: WSWindowUpdate => web_server::window_update::WSWindowUpdatePacket,
+    sd_window_update: SDWindowUpdate => server_daemon::window_update::SDWindowUpdatePacket,
+    ack: Ack => ack::AckPacket,
+    nack: Nack => ack::NackPacket,
+    ws_query_snapshot: WSQuerySnapshot => web_server::query_snapshot::WSQuerySnapshotPacket,
+    ds_error: DSError => daemon_server::error::DSErrorPacket,
+    ping: Ping => heartbeat::PingPacket,
+    pong: Pong => heartbeat::PongPacket,
+    ws_query_notifications: WSQueryNotifications => web_server::query_notifications::WSQueryNotificationsPacket,
+    sw_query_notifications_response: SWQueryNotificationsResponse => server_web::query_notifications_response::SWQueryNotificationsResponsePacket,
+    ws_mark_notifications_read: WSMarkNotificationsRead => web_server::mark_notifications_read::WSMarkNotificationsReadPacket,
+    ws_terminal_open: WSTerminalOpen => web_server::terminal_open::WSTerminalOpenPacket,
+    sd_terminal_open: SDTerminalOpen => server_daemon::terminal_open::SDTerminalOpenPacket,
+    ws_terminal_input: WSTerminalInput => web_server::terminal_input::WSTerminalInputPacket,
+    sd_terminal_input: SDTerminalInput => server_daemon::terminal_input::SDTerminalInputPacket,
+    ds_terminal_output: DSTerminalOutput => daemon_server::terminal_output::DSTerminalOutputPacket,
+    sw_terminal_output: SWTerminalOutput => server_web::terminal_output::SWTerminalOutputPacket,
+    ws_terminal_close: WSTerminalClose => web_server::terminal_close::WSTerminalClosePacket,
+    sd_terminal_close: SDTerminalClose => server_daemon::terminal_close::SDTerminalClosePacket,
+    ds_terminal_close: DSTerminalClose => daemon_server::terminal_close::DSTerminalClosePacket,
+    sw_terminal_close: SWTerminalClose => server_web::terminal_close::SWTerminalClosePacket,
+    ws_command: WSCommand => web_server::command::WSCommandPacket,
+    sd_command: SDCommand => server_daemon::command::SDCommandPacket,
+    ds_command_result: DSCommandResult => daemon_server::command_result::DSCommandResultPacket,
+    sw_command_result: SWCommandResult => server_web::command_result::SWCommandResultPacket,
+    ws_unlisten: WSUnlisten => web_server::unlisten::WSUnlistenPacket,
+}
@@ -0,0 +1,111 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use packet::server_web::query_team_usage_response::{MonthlyUsage, Usage};
+use tracing::debug;
+
+use crate::db;
+
+/// Length of the buckets that are accounted, see `metrics::rollup`.
+const BUCKET: i64 = 60 * 60;
+
+fn is_month(month: &str) -> bool {
+    month.len() == 7
+        && month.as_bytes()[4] == b'-'
+        && month[..4].chars().all(|c| c.is_ascii_digit())
+        && matches!(month[5..].parse::<u8>(), Ok(1..=12))
+}
+
+/// Adds the hourly rollups of all servers since the last run to the monthly usage of the teams
+/// owning their nodes. Every hour is accounted exactly once, even with multiple servers sharing the
+/// database.
+pub async fn account() -> Result<(), String> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default() as i64;
+    let until = now - now % BUCKET;
+
+    let mut tx = db::get()?.begin().await.map_err(|e| format!("Could not start transaction: {}", e))?;
+
+    let from = db::timed("lock_usage_accounting", sqlx::query_scalar!(r#"
+        SELECT usage_accounted_until FROM aesterisk.usage_accounting FOR UPDATE;
+    "#).fetch_one(&mut *tx)).await?;
+
+    if from >= until {
+        return Ok(());
+    }
+
+    debug!("Accounting usage from {} until {}", from, until);
+
+    // cpu is in percent of a core, memory and storage are in GB
+    db::timed("account_team_usage", sqlx::query!(r#"
+        WITH samples AS (
+            SELECT DISTINCT ON (metrics_hourly.node_uuid, metrics_hourly.server_id, metrics_hourly.metric_time)
+                team_nodes.team_id,
+                TO_CHAR(TO_TIMESTAMP(metrics_hourly.metric_time) AT TIME ZONE 'UTC', 'YYYY-MM') AS usage_month,
+                COALESCE(metrics_hourly.metric_cpu, 0) / 100 AS cpu,
+                COALESCE(metrics_hourly.metric_memory_used, 0) AS memory,
+                COALESCE(metrics_hourly.metric_storage_used, 0) AS storage
+            FROM aesterisk.metrics_hourly
+            INNER JOIN aesterisk.nodes
+                ON metrics_hourly.node_uuid = nodes.node_uuid
+            INNER JOIN aesterisk.team_nodes
+                ON nodes.node_id = team_nodes.node_id
+            WHERE metrics_hourly.server_id <> 0
+            AND metrics_hourly.metric_time >= $1
+            AND metrics_hourly.metric_time < $2
+            ORDER BY metrics_hourly.node_uuid, metrics_hourly.server_id, metrics_hourly.metric_time, team_nodes.team_id
+        )
+        INSERT INTO aesterisk.team_usage (
+            team_id,
+            usage_month,
+            usage_cpu_core_hours,
+            usage_memory_gb_hours,
+            usage_storage_gb_hours
+        )
+        SELECT team_id, usage_month, SUM(cpu), SUM(memory), SUM(storage)
+        FROM samples
+        GROUP BY team_id, usage_month
+        ON CONFLICT (team_id, usage_month) DO UPDATE SET
+            usage_cpu_core_hours = team_usage.usage_cpu_core_hours + EXCLUDED.usage_cpu_core_hours,
+            usage_memory_gb_hours = team_usage.usage_memory_gb_hours + EXCLUDED.usage_memory_gb_hours,
+            usage_storage_gb_hours = team_usage.usage_storage_gb_hours + EXCLUDED.usage_storage_gb_hours;
+    "#, from, until).execute(&mut *tx)).await?;
+
+    db::timed("update_usage_accounting", sqlx::query!(r#"
+        UPDATE aesterisk.usage_accounting SET usage_accounted_until = $1;
+    "#, until).execute(&mut *tx)).await?;
+
+    tx.commit().await.map_err(|e| format!("Could not commit transaction: {}", e))
+}
+
+/// Returns the team of a user, and the monthly usage of that team from `from` until `to`
+/// (inclusive, both as `YYYY-MM`).
+pub async fn query(user_id: u32, from: &str, to: &str) -> Result<(u32, Vec<MonthlyUsage>), String> {
+    if !is_month(from) || !is_month(to) {
+        return Err("Months have to be formatted as YYYY-MM".to_string());
+    }
+
+    let team = db::timed("fetch_user_team", sqlx::query_scalar!(r#"
+        SELECT user_team FROM aesterisk.users WHERE user_id = $1;
+    "#, user_id as i32).fetch_one(db::get()?)).await?;
+
+    let months = db::timed("fetch_team_usage", sqlx::query!(r#"
+        SELECT
+            team_usage.usage_month,
+            team_usage.usage_cpu_core_hours,
+            team_usage.usage_memory_gb_hours,
+            team_usage.usage_storage_gb_hours
+        FROM aesterisk.team_usage
+        WHERE team_usage.team_id = $1
+        AND team_usage.usage_month >= $2
+        AND team_usage.usage_month <= $3
+        ORDER BY team_usage.usage_month;
+    "#, team, from, to).fetch_all(db::get()?)).await?;
+
+    Ok((team as u32, months.into_iter().map(|month| MonthlyUsage {
+        month: month.usage_month,
+        usage: Usage {
+            cpu_core_hours: month.usage_cpu_core_hours,
+            memory_gb_hours: month.usage_memory_gb_hours,
+            storage_gb_hours: month.usage_storage_gb_hours,
+        },
+    }).collect()))
+}
@@ -0,0 +1,206 @@
+use std::{collections::BTreeSet, time::{SystemTime, UNIX_EPOCH}};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use packet::events::{AlertEvent, AlertMetric, EventData, EventType, ResourceWarningEvent, Stats};
+use sqlx::types::Uuid;
+use tracing::{debug, warn};
+
+use crate::repository;
+
+/// `AlertRule` is a threshold on a metric of a node or server, that trips once the metric has been
+/// above the threshold for `duration` seconds.
+pub struct AlertRule {
+    id: i64,
+    server: Option<u32>,
+    metric: AlertMetric,
+    threshold: f64,
+    duration: u64,
+    webhook: Option<String>,
+}
+
+impl AlertRule {
+    /// Converts a stored alert rule, see `alert_rules` in the schema. Rules with an unknown metric
+    /// are ignored.
+    pub fn from_db(id: i64, server: Option<i64>, metric: i64, threshold: f64, duration: i64, webhook: Option<String>) -> Option<Self> {
+        Some(Self {
+            id,
+            server: server.map(|id| id as u32),
+            metric: match metric {
+                0 => AlertMetric::Cpu,
+                1 => AlertMetric::Memory,
+                2 => AlertMetric::Storage,
+                metric => {
+                    warn!("Ignoring alert rule {} with unknown metric {}", id, metric);
+                    return None;
+                }
+            },
+            threshold,
+            duration: duration.max(0) as u64,
+            webhook,
+        })
+    }
+}
+
+#[derive(Default)]
+struct RuleState {
+    /// When the metric first went above the threshold, if it currently is.
+    breached_since: Option<u64>,
+    firing: bool,
+}
+
+lazy_static! {
+    static ref RULES: DashMap<Uuid, Vec<AlertRule>> = DashMap::new();
+    static ref STATES: DashMap<i64, RuleState> = DashMap::new();
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::new();
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+fn percent(stats: &Option<Stats>) -> Option<f64> {
+    stats.as_ref().filter(|stats| stats.total > 0.0).map(|stats| stats.used / stats.total * 100.0)
+}
+
+/// (Re)loads the alert rules of a daemon from the database.
+pub async fn load(uuid: Uuid) -> Result<(), String> {
+    let rules = repository::get().alert_rules(uuid).await?;
+
+    debug!("Loaded {} alert rules for daemon {}", rules.len(), uuid);
+
+    if let Some((_, old)) = RULES.remove(&uuid) {
+        for rule in old.iter().filter(|old| !rules.iter().any(|rule| rule.id == old.id)) {
+            STATES.remove(&rule.id);
+        }
+    }
+
+    RULES.insert(uuid, rules);
+
+    Ok(())
+}
+
+/// Returns the events a daemon needs to send for its alert rules to be evaluated.
+pub fn required_events(uuid: &Uuid) -> Vec<EventType> {
+    let mut events = Vec::new();
+
+    if let Some(rules) = RULES.get(uuid) {
+        if rules.iter().any(|rule| rule.server.is_none()) {
+            events.push(EventType::NodeStatus);
+        }
+
+        if rules.iter().any(|rule| rule.server.is_some()) {
+            events.push(EventType::ServerStatus);
+        }
+    }
+
+    events
+}
+
+fn send_webhook(url: String, body: serde_json::Value) {
+    tokio::spawn(async move {
+        match HTTP_CLIENT.post(&url).header("Content-Type", "application/json").body(body.to_string()).send().await {
+            Ok(res) if !res.status().is_success() => warn!("Alert webhook {} responded with {}", url, res.status()),
+            Ok(_) => (),
+            Err(e) => warn!("Could not send alert webhook {}: {}", url, e),
+        }
+    });
+}
+
+/// Evaluates the alert rules of a daemon against an event, and returns an `AlertEvent` for every
+/// rule that tripped or recovered. Webhooks of those rules are called in the background.
+pub fn evaluate(uuid: &Uuid, event: &EventData) -> Vec<AlertEvent> {
+    let rules = match RULES.get(uuid) {
+        Some(rules) => rules,
+        None => return Vec::new(),
+    };
+
+    let now = now();
+    let mut alerts = Vec::new();
+
+    for rule in rules.iter() {
+        let value = match (event, rule.server) {
+            (EventData::NodeStatus(status), None) => match (&status.stats, rule.metric) {
+                (Some(stats), AlertMetric::Cpu) => Some(stats.cpu),
+                (Some(stats), AlertMetric::Memory) if stats.total_memory > 0.0 => Some(stats.used_memory / stats.total_memory * 100.0),
+                (Some(stats), AlertMetric::Storage) if stats.total_storage > 0.0 => Some(stats.used_storage / stats.total_storage * 100.0),
+                _ => None,
+            },
+            (EventData::ServerStatus(status), Some(server)) if status.server == server => match rule.metric {
+                AlertMetric::Cpu => percent(&status.cpu),
+                AlertMetric::Memory => percent(&status.memory),
+                AlertMetric::Storage => percent(&status.storage),
+            },
+            _ => continue,
+        };
+
+        let mut state = STATES.entry(rule.id).or_default();
+
+        let in_maintenance = match event {
+            EventData::NodeStatus(status) => status.in_maintenance,
+            EventData::ServerStatus(status) => status.in_maintenance,
+            _ => false,
+        };
+
+        let breached = value.is_some_and(|value| value > rule.threshold);
+
+        // breaches during a maintenance window neither start nor keep an alert, but a firing rule
+        // can still resolve
+        if in_maintenance && breached {
+            state.breached_since = None;
+            continue;
+        }
+
+        let firing = if breached {
+            let since = *state.breached_since.get_or_insert(now);
+            now - since >= rule.duration
+        } else {
+            state.breached_since = None;
+            false
+        };
+
+        if firing == state.firing {
+            continue;
+        }
+
+        state.firing = firing;
+
+        let alert = AlertEvent {
+            rule: rule.id as u32,
+            server: rule.server,
+            metric: rule.metric,
+            value: value.unwrap_or_default(),
+            threshold: rule.threshold,
+            firing,
+        };
+
+        debug!("Alert rule {} of daemon {} is {}", rule.id, uuid, if firing { "firing" } else { "resolved" });
+
+        if let Some(url) = &rule.webhook {
+            send_webhook(url.clone(), serde_json::json!({
+                "daemon": uuid,
+                "alert": alert,
+            }));
+        }
+
+        alerts.push(alert);
+    }
+
+    alerts
+}
+
+/// Forwards a resource warning of a daemon to the webhooks of its node alert rules, as the daemon
+/// evaluates the thresholds of resource warnings itself.
+pub fn notify_resource_warning(uuid: &Uuid, warning: &ResourceWarningEvent) {
+    let webhooks = match RULES.get(uuid) {
+        Some(rules) => rules.iter().filter(|rule| rule.server.is_none()).filter_map(|rule| rule.webhook.clone()).collect::<BTreeSet<_>>(),
+        None => return,
+    };
+
+    for url in webhooks {
+        send_webhook(url, serde_json::json!({
+            "daemon": uuid,
+            "resource_warning": warning,
+        }));
+    }
+}
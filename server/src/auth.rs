@@ -0,0 +1,264 @@
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use sqlx::types::Uuid;
+
+use crate::{config::{AuthBackend, CONFIG}, db, sqlite, standalone};
+
+/// How long requests to the `http` backend may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+lazy_static! {
+    static ref BACKEND: Box<dyn Backend> = match CONFIG.auth.backend {
+        // standalone mode keeps the keys next to the spec files
+        _ if standalone::enabled() => Box::new(File {
+            path: standalone::keys_file(),
+        }),
+        AuthBackend::Postgres if sqlite::enabled() => Box::new(Sqlite),
+        AuthBackend::Postgres => Box::new(Postgres),
+        AuthBackend::File => Box::new(File {
+            path: CONFIG.auth.file.clone(),
+        }),
+        AuthBackend::Http => Box::new(Http::new(&CONFIG.auth.url, &CONFIG.auth.ca_cert)),
+    };
+}
+
+/// `Backend` looks up the PEM-encoded public keys users and daemons authenticate with. Keys are
+/// cached by the `State` once looked up, so a backend is only asked again after they are evicted.
+#[async_trait]
+pub trait Backend: Send + Sync {
+    /// Returns the public key of a user, or `None` if the user doesn't exist (anymore).
+    async fn user_public_key(&self, user_id: u32) -> Result<Option<String>, String>;
+    /// Returns the public key of a daemon, or `None` if its node doesn't exist (anymore).
+    async fn daemon_public_key(&self, daemon: &Uuid) -> Result<Option<String>, String>;
+}
+
+/// Returns the backend selected by `auth.backend`, or the keys file of standalone mode.
+pub fn get() -> &'static dyn Backend {
+    BACKEND.as_ref()
+}
+
+/// Looks up keys in the `users` and `nodes` tables of the database.
+struct Postgres;
+
+struct UserPublicKeyQuery {
+    user_public_key: String,
+}
+
+struct NodePublicKeyQuery {
+    node_public_key: String,
+}
+
+#[async_trait]
+impl Backend for Postgres {
+    async fn user_public_key(&self, user_id: u32) -> Result<Option<String>, String> {
+        let res = db::timed("fetch_user_public_key", sqlx::query_as!(UserPublicKeyQuery, "SELECT user_public_key FROM aesterisk.users WHERE user_id = $1", user_id as i32).fetch_optional(db::get()?)).await?;
+
+        Ok(res.map(|res| res.user_public_key))
+    }
+
+    async fn daemon_public_key(&self, daemon: &Uuid) -> Result<Option<String>, String> {
+        let res = db::timed("fetch_node_public_key", sqlx::query_as!(NodePublicKeyQuery, "SELECT node_public_key FROM aesterisk.nodes WHERE node_uuid = $1", daemon).fetch_optional(db::get()?)).await?;
+
+        Ok(res.map(|res| res.node_public_key))
+    }
+}
+
+/// Looks up keys in the `users` and `nodes` tables of the SQLite database.
+struct Sqlite;
+
+#[async_trait]
+impl Backend for Sqlite {
+    async fn user_public_key(&self, user_id: u32) -> Result<Option<String>, String> {
+        db::timed("fetch_user_public_key", sqlx::query_scalar!("SELECT user_public_key FROM users WHERE user_id = ?1", user_id).fetch_optional(db::sqlite()?)).await
+    }
+
+    async fn daemon_public_key(&self, daemon: &Uuid) -> Result<Option<String>, String> {
+        let node = daemon.to_string();
+
+        db::timed("fetch_node_public_key", sqlx::query_scalar!("SELECT node_public_key FROM nodes WHERE node_uuid = ?1", node).fetch_optional(db::sqlite()?)).await
+    }
+}
+
+/// Looks up keys in a TOML file, which is read again on every lookup, so keys can be added without
+/// restarting the server.
+struct File {
+    path: String,
+}
+
+#[derive(serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+struct KeyFile {
+    #[serde(default)]
+    users: HashMap<String, String>,
+    #[serde(default)]
+    daemons: HashMap<String, String>,
+}
+
+impl File {
+    async fn read(&self) -> Result<KeyFile, String> {
+        let contents = tokio::fs::read_to_string(&self.path).await.map_err(|e| format!("Could not read key file {}: {}", self.path, e))?;
+
+        toml::from_str(&contents).map_err(|e| format!("Could not parse key file {}: {}", self.path, e))
+    }
+}
+
+#[async_trait]
+impl Backend for File {
+    async fn user_public_key(&self, user_id: u32) -> Result<Option<String>, String> {
+        Ok(self.read().await?.users.remove(&user_id.to_string()))
+    }
+
+    async fn daemon_public_key(&self, daemon: &Uuid) -> Result<Option<String>, String> {
+        // UUIDs are compared parsed, as they may be written in upper case
+        Ok(self.read().await?.daemons.into_iter()
+            .find(|(uuid, _)| Uuid::parse_str(uuid).is_ok_and(|uuid| uuid == *daemon))
+            .map(|(_, key)| key))
+    }
+}
+
+/// Looks up keys with an external service, e.g. one reading them from LDAP attributes. The service
+/// is only asked over HTTPS, as anyone who can replace its responses can impersonate any user or
+/// daemon.
+struct Http {
+    url: String,
+    /// The error of building the client, e.g. if `auth.ca_cert` can't be read, which fails every
+    /// lookup
+    client: Result<reqwest::Client, String>,
+}
+
+impl Http {
+    fn new(url: &str, ca_cert: &str) -> Self {
+        Self {
+            url: url.trim_end_matches('/').to_string(),
+            client: Self::client(ca_cert),
+        }
+    }
+
+    /// Builds a client that only uses HTTPS, trusting only the CA in `ca_cert` if it isn't empty.
+    fn client(ca_cert: &str) -> Result<reqwest::Client, String> {
+        let builder = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).https_only(true);
+
+        let builder = match ca_cert.is_empty() {
+            true => builder,
+            false => {
+                let pem = std::fs::read(ca_cert).map_err(|e| format!("Could not read {}: {}", ca_cert, e))?;
+                let cert = reqwest::Certificate::from_pem(&pem).map_err(|e| format!("Invalid certificate {}: {}", ca_cert, e))?;
+
+                builder.tls_built_in_root_certs(false).add_root_certificate(cert)
+            },
+        };
+
+        builder.build().map_err(|e| format!("Could not build HTTP client: {}", e))
+    }
+
+    /// Requests the key at `path`, or `None` if the service doesn't know the principal.
+    async fn fetch(&self, path: &str) -> Result<Option<String>, String> {
+        let client = self.client.as_ref().map_err(String::clone)?;
+        let res = client.get(format!("{}/{}", self.url, path)).send().await.map_err(|e| format!("Could not request public key: {}", e))?;
+
+        Self::key(res).await
+    }
+
+    /// Returns the key of a response, or `None` if it is a 404.
+    async fn key(res: reqwest::Response) -> Result<Option<String>, String> {
+        if res.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let res = res.error_for_status().map_err(|e| format!("Could not request public key: {}", e))?;
+
+        res.text().await.map(Some).map_err(|e| format!("Could not read public key: {}", e))
+    }
+}
+
+#[async_trait]
+impl Backend for Http {
+    async fn user_public_key(&self, user_id: u32) -> Result<Option<String>, String> {
+        self.fetch(&format!("users/{}", user_id)).await
+    }
+
+    async fn daemon_public_key(&self, daemon: &Uuid) -> Result<Option<String>, String> {
+        self.fetch(&format!("daemons/{}", daemon)).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio_tungstenite::tungstenite::http;
+
+    use super::*;
+
+    /// Writes a key file for the `File` backend, removed once the test is done with it.
+    struct TempKeyFile(String);
+
+    impl TempKeyFile {
+        fn new(name: &str, contents: &str) -> Self {
+            let path = std::env::temp_dir().join(format!("aesterisk-{}-{}.toml", name, std::process::id()));
+            std::fs::write(&path, contents).expect("could not write key file");
+
+            Self(path.to_string_lossy().to_string())
+        }
+    }
+
+    impl Drop for TempKeyFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn file_keys_looked_up() {
+        let file = TempKeyFile::new("keys", r#"
+            [users]
+            1 = "user key"
+
+            [daemons]
+            "0AB1C2D3-0000-4000-8000-00000000000A" = "daemon key"
+        "#);
+        let backend = File {
+            path: file.0.clone(),
+        };
+
+        assert_eq!(backend.user_public_key(1).await, Ok(Some("user key".to_string())));
+        assert_eq!(backend.user_public_key(2).await, Ok(None));
+
+        // written in upper case, looked up in lower case
+        let daemon = Uuid::parse_str("0ab1c2d3-0000-4000-8000-00000000000a").expect("invalid UUID");
+        assert_eq!(backend.daemon_public_key(&daemon).await, Ok(Some("daemon key".to_string())));
+        assert_eq!(backend.daemon_public_key(&Uuid::nil()).await, Ok(None));
+    }
+
+    #[tokio::test]
+    async fn invalid_key_files_rejected() {
+        let file = TempKeyFile::new("invalid-keys", "[groups]\n");
+
+        assert!(File { path: file.0.clone() }.user_public_key(1).await.is_err());
+        assert!(File { path: format!("{}.missing", file.0) }.user_public_key(1).await.is_err());
+    }
+
+    fn response(status: u16, body: &str) -> reqwest::Response {
+        http::Response::builder().status(status).body(body.to_string()).expect("could not build response").into()
+    }
+
+    #[tokio::test]
+    async fn http_responses_mapped_to_keys() {
+        assert_eq!(Http::key(response(200, "key")).await, Ok(Some("key".to_string())));
+        assert_eq!(Http::key(response(404, "not found")).await, Ok(None));
+        assert!(Http::key(response(500, "")).await.is_err());
+        assert!(Http::key(response(403, "")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn http_keys_only_fetched_over_https() {
+        let backend = Http::new("http://127.0.0.1:1/", "");
+        assert_eq!(backend.url, "http://127.0.0.1:1");
+
+        let e = backend.user_public_key(1).await.expect_err("key was fetched over plain HTTP");
+        assert!(e.contains("Could not request public key"));
+
+        let backend = Http::new("https://127.0.0.1:1", "/nonexistent/ca.pem");
+        assert!(backend.user_public_key(1).await.is_err_and(|e| e.contains("/nonexistent/ca.pem")));
+    }
+}
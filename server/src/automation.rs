@@ -0,0 +1,502 @@
+use std::{collections::VecDeque, time::Duration};
+
+use dashmap::{mapref::entry::Entry, DashMap};
+use lazy_static::lazy_static;
+use packet::{command::ServerCommand, events::{EventData, ServerStatusType}};
+use sqlx::types::Uuid;
+use tracing::{debug, info, warn};
+
+use crate::{config::CONFIG, db, sessions::now};
+
+/// How often logs past their retention are deleted.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// `AutomationRule` runs actions once a server entered a status often enough: the trigger is the
+/// server entering `status`, the condition is that it happened `count` times within `window`
+/// seconds, and the actions are running `command` on the server and notifying its team.
+struct AutomationRule {
+    id: i32,
+    name: String,
+    /// The server the rule applies to, or `None` for every server of the daemon
+    server: Option<u32>,
+    status: ServerStatusType,
+    count: u32,
+    window: u64,
+    skip_maintenance: bool,
+    command: Option<ServerCommand>,
+    notify: bool,
+    /// Seconds after firing during which the rule doesn't fire again for the same server
+    cooldown: u64,
+}
+
+/// The state of a rule for one server.
+#[derive(Default)]
+struct RuleState {
+    /// When the server entered the trigger status, within the window of the rule.
+    triggered: VecDeque<u64>,
+    fired_at: Option<u64>,
+}
+
+/// What a rule does once its server entered the trigger status, see `RuleState::trigger`.
+#[derive(Debug, PartialEq)]
+enum Decision {
+    /// The server didn't enter the status often enough yet, with how often it did within the window
+    Wait(u32),
+    /// The condition was met, but the rule is held back for the given reason
+    Suppress(String),
+    /// The condition was met, with how often the server entered the status within the window
+    Fire(u32),
+}
+
+impl RuleState {
+    /// Records that the server entered the trigger status of a rule at `at` (in seconds), and
+    /// decides whether the rule fires. Times never go backwards, see `evaluate`.
+    fn trigger(&mut self, rule: &AutomationRule, at: u64, in_maintenance: bool) -> Decision {
+        self.triggered.push_back(at);
+        while self.triggered.front().is_some_and(|triggered| at.saturating_sub(*triggered) > rule.window) {
+            self.triggered.pop_front();
+        }
+
+        let triggered = self.triggered.len() as u32;
+
+        if triggered < rule.count {
+            return Decision::Wait(triggered);
+        }
+
+        if rule.skip_maintenance && in_maintenance {
+            return Decision::Suppress("Server is in maintenance".to_string());
+        }
+
+        if let Some(fired_at) = self.fired_at
+            && at.saturating_sub(fired_at) < rule.cooldown {
+            return Decision::Suppress(format!("Rule fired {}s ago, cooling down for {}s", at.saturating_sub(fired_at), rule.cooldown));
+        }
+
+        // the server has to enter the status often enough again to fire the rule again
+        self.triggered.clear();
+        self.fired_at = Some(at);
+
+        Decision::Fire(triggered)
+    }
+}
+
+/// An automation rule whose condition was met, with the actions to run.
+pub struct Firing {
+    pub rule: i32,
+    pub server: u32,
+    pub command: Option<ServerCommand>,
+    /// The message to notify the team of the daemon with, if the rule notifies
+    pub notification: Option<String>,
+}
+
+/// What happened when a rule was triggered, stored with every log entry.
+#[derive(Debug, Clone, Copy)]
+pub enum Outcome {
+    /// The server entered the trigger status, but not often enough yet
+    Triggered,
+    /// The condition was met, but the rule is cooling down or the server is in maintenance
+    Suppressed,
+    /// The condition was met and the actions were run
+    Fired,
+    /// The command of the rule succeeded
+    CommandSucceeded,
+    /// The command of the rule failed or could not be sent
+    CommandFailed,
+}
+
+impl Outcome {
+    fn to_db(self) -> i16 {
+        match self {
+            Outcome::Triggered => 0,
+            Outcome::Suppressed => 1,
+            Outcome::Fired => 2,
+            Outcome::CommandSucceeded => 3,
+            Outcome::CommandFailed => 4,
+        }
+    }
+}
+
+lazy_static! {
+    static ref RULES: DashMap<Uuid, Vec<AutomationRule>> = DashMap::new();
+    /// The state of every rule, by rule and server.
+    static ref STATES: DashMap<(i32, u32), RuleState> = DashMap::new();
+    /// The last status of every server with rules and when it was reported, to detect when it
+    /// enters a status.
+    static ref LAST_STATUS: DashMap<(Uuid, u32), (ServerStatusType, u64)> = DashMap::new();
+    /// Commands sent by rules that weren't answered yet, by request ID, with the daemon they were
+    /// sent to and the rule that sent them.
+    static ref PENDING: DashMap<u64, (Uuid, i32)> = DashMap::new();
+}
+
+fn status_from_db(status: i16) -> Option<ServerStatusType> {
+    match status {
+        0 => Some(ServerStatusType::Healthy),
+        1 => Some(ServerStatusType::Starting),
+        2 => Some(ServerStatusType::Restarting),
+        3 => Some(ServerStatusType::Stopping),
+        4 => Some(ServerStatusType::Stopped),
+        5 => Some(ServerStatusType::Unhealthy),
+        _ => None,
+    }
+}
+
+fn command_from_db(command: i16) -> Option<ServerCommand> {
+    match command {
+        0 => Some(ServerCommand::Start),
+        1 => Some(ServerCommand::Stop),
+        2 => Some(ServerCommand::Restart),
+        3 => Some(ServerCommand::Kill),
+        _ => None,
+    }
+}
+
+/// (Re)loads the enabled automation rules of a daemon from the database.
+pub async fn load(uuid: Uuid) -> Result<(), String> {
+    // automation rules are only stored in Postgres
+    if !CONFIG.automation.enabled || !db::postgres() {
+        return Ok(());
+    }
+
+    struct DbAutomationRule {
+        automation_rule_id: i32,
+        server_id: Option<i32>,
+        automation_rule_name: String,
+        automation_rule_trigger_status: i16,
+        automation_rule_condition_count: i32,
+        automation_rule_condition_window: i32,
+        automation_rule_skip_maintenance: bool,
+        automation_rule_action_command: Option<i16>,
+        automation_rule_action_notify: bool,
+        automation_rule_cooldown: i32,
+    }
+
+    let rules = db::timed("fetch_automation_rules", sqlx::query_as!(DbAutomationRule, r#"
+        SELECT
+            automation_rules.automation_rule_id,
+            automation_rules.server_id,
+            automation_rules.automation_rule_name,
+            automation_rules.automation_rule_trigger_status,
+            automation_rules.automation_rule_condition_count,
+            automation_rules.automation_rule_condition_window,
+            automation_rules.automation_rule_skip_maintenance,
+            automation_rules.automation_rule_action_command,
+            automation_rules.automation_rule_action_notify,
+            automation_rules.automation_rule_cooldown
+        FROM aesterisk.automation_rules
+        INNER JOIN aesterisk.nodes
+            ON automation_rules.node_id = nodes.node_id
+        WHERE nodes.node_uuid = $1
+        AND automation_rules.automation_rule_enabled;
+    "#, uuid).fetch_all(db::get()?)).await?;
+
+    let rules = rules.into_iter().filter_map(|rule| {
+        let Some(status) = status_from_db(rule.automation_rule_trigger_status) else {
+            warn!("Ignoring automation rule {} with unknown trigger status {}", rule.automation_rule_id, rule.automation_rule_trigger_status);
+            return None;
+        };
+
+        let command = match rule.automation_rule_action_command {
+            Some(command) => match command_from_db(command) {
+                Some(command) => Some(command),
+                None => {
+                    warn!("Ignoring automation rule {} with unknown command {}", rule.automation_rule_id, command);
+                    return None;
+                },
+            },
+            None => None,
+        };
+
+        Some(AutomationRule {
+            id: rule.automation_rule_id,
+            name: rule.automation_rule_name,
+            server: rule.server_id.map(|id| id as u32),
+            status,
+            count: rule.automation_rule_condition_count.max(1) as u32,
+            window: rule.automation_rule_condition_window.max(0) as u64,
+            skip_maintenance: rule.automation_rule_skip_maintenance,
+            command,
+            notify: rule.automation_rule_action_notify,
+            cooldown: rule.automation_rule_cooldown.max(0) as u64,
+        })
+    }).collect::<Vec<_>>();
+
+    debug!("Loaded {} automation rules for daemon {}", rules.len(), uuid);
+
+    if let Some((_, old)) = RULES.remove(&uuid) {
+        for rule in old.iter().filter(|old| !rules.iter().any(|rule| rule.id == old.id)) {
+            STATES.retain(|(id, _), _| *id != rule.id);
+        }
+    }
+
+    if rules.is_empty() {
+        LAST_STATUS.retain(|(daemon, _), _| *daemon != uuid);
+    } else {
+        RULES.insert(uuid, rules);
+    }
+
+    Ok(())
+}
+
+/// Returns whether a daemon has automation rules, which need the status of all of its servers.
+pub fn has_rules(uuid: &Uuid) -> bool {
+    RULES.contains_key(uuid)
+}
+
+/// Remembers the status of a server reported at `at`, and returns the status it had before. Returns
+/// `None` for the first status seen of a server, and for statuses reported before the last one,
+/// which arrived out of order and are ignored.
+fn transition(uuid: &Uuid, server: u32, status: &ServerStatusType, at: u64) -> Option<ServerStatusType> {
+    match LAST_STATUS.entry((*uuid, server)) {
+        Entry::Vacant(entry) => {
+            entry.insert((status.clone(), at));
+            None
+        },
+        Entry::Occupied(entry) if at < entry.get().1 => None,
+        Entry::Occupied(mut entry) => Some(entry.insert((status.clone(), at)).0),
+    }
+}
+
+/// Remembers the status of a server from an event the daemon replayed after reconnecting, without
+/// evaluating any rules. The server entered it while the daemon was disconnected, so commands run
+/// now would act on a status it may have left long ago.
+pub fn replayed(uuid: &Uuid, event: &EventData, timestamp: Option<u64>) {
+    if let EventData::ServerStatus(status) = event
+        && RULES.contains_key(uuid) {
+        transition(uuid, status.server, &status.status, timestamp.unwrap_or_else(now));
+    }
+}
+
+/// Evaluates the automation rules of a daemon against an event that happened at `timestamp` (or
+/// now, if the daemon didn't report it), and returns the rules that fired. A server only triggers a
+/// rule when it enters the trigger status, not while it stays in it, and the first status seen of
+/// a server doesn't trigger anything, so rules don't fire for servers that were already in the
+/// status before the server (re)started.
+pub fn evaluate(uuid: &Uuid, event: &EventData, timestamp: Option<u64>) -> Vec<Firing> {
+    let EventData::ServerStatus(status) = event else {
+        return Vec::new();
+    };
+
+    let Some(rules) = RULES.get(uuid) else {
+        return Vec::new();
+    };
+
+    let at = timestamp.unwrap_or_else(now);
+
+    if transition(uuid, status.server, &status.status, at).is_none_or(|previous| previous == status.status) {
+        return Vec::new();
+    }
+
+    let mut firings = Vec::new();
+
+    for rule in rules.iter() {
+        if rule.status != status.status || rule.server.is_some_and(|server| server != status.server) {
+            continue;
+        }
+
+        let decision = STATES.entry((rule.id, status.server)).or_default().trigger(rule, at, status.in_maintenance);
+
+        let triggered = match decision {
+            Decision::Wait(triggered) => {
+                log(rule.id, *uuid, status.server, Outcome::Triggered, format!("Entered {:?} {} of {} times within {}s", status.status, triggered, rule.count, rule.window));
+                continue;
+            },
+            Decision::Suppress(reason) => {
+                log(rule.id, *uuid, status.server, Outcome::Suppressed, reason);
+                continue;
+            },
+            Decision::Fire(triggered) => triggered,
+        };
+
+        info!("Automation rule {} ({}) fired for server {} of daemon {}", rule.id, rule.name, status.server, uuid);
+        log(rule.id, *uuid, status.server, Outcome::Fired, format!("Entered {:?} {} times within {}s", status.status, triggered, rule.window));
+
+        firings.push(Firing {
+            rule: rule.id,
+            server: status.server,
+            command: rule.command,
+            notification: rule.notify.then(|| match rule.command {
+                Some(command) => format!("Automation rule \"{}\" fired as the server entered {:?} {} times, running {:?}", rule.name, status.status, triggered, command),
+                None => format!("Automation rule \"{}\" fired as the server entered {:?} {} times", rule.name, status.status, triggered),
+            }),
+        });
+    }
+
+    firings
+}
+
+/// Remembers a command sent by a rule, to log its result once the daemon answers it.
+pub fn command_sent(request: u64, uuid: Uuid, rule: i32) {
+    PENDING.insert(request, (uuid, rule));
+}
+
+/// Forgets a command that could not be sent.
+pub fn forget_command(request: u64) {
+    PENDING.remove(&request);
+}
+
+/// Logs the result of a command sent by a rule. Returns whether the request was sent by a rule,
+/// otherwise the result is for a web client.
+pub fn command_result(request: u64, uuid: Uuid, server: u32, error: Option<&str>) -> bool {
+    let Some((_, (daemon, rule))) = PENDING.remove_if(&request, |_, (daemon, _)| *daemon == uuid) else {
+        return false;
+    };
+
+    match error {
+        Some(e) => log(rule, daemon, server, Outcome::CommandFailed, e.to_string()),
+        None => log(rule, daemon, server, Outcome::CommandSucceeded, "Command succeeded".to_string()),
+    }
+
+    true
+}
+
+/// Forgets commands sent to daemons that are no longer connected, as they won't be answered.
+/// Returns the amount of forgotten commands.
+pub fn forget_commands(connected: impl Fn(&Uuid) -> bool) -> usize {
+    let before = PENDING.len();
+    PENDING.retain(|_, (daemon, _)| connected(daemon));
+    before - PENDING.len()
+}
+
+/// Logs what happened when a rule was triggered, and stores it in the background so the rule can
+/// be debugged later.
+pub fn log(rule: i32, uuid: Uuid, server: u32, outcome: Outcome, message: String) {
+    debug!("Automation rule {} for server {} of daemon {}: {:?}: {}", rule, server, uuid, outcome, message);
+
+    if db::read_only() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let res = async {
+            db::timed("insert_automation_log", sqlx::query!(r#"
+                INSERT INTO aesterisk.automation_logs (
+                    automation_rule_id,
+                    node_uuid,
+                    server_id,
+                    automation_log_outcome,
+                    automation_log_message,
+                    automation_log_created_at
+                ) VALUES ($1, $2, $3, $4, $5, $6);
+            "#, rule, uuid, server as i32, outcome.to_db(), message, now() as i64).execute(db::get()?)).await
+        }.await;
+
+        if let Err(e) = res {
+            warn!("Could not store automation log: {}", e);
+        }
+    });
+}
+
+/// Periodically deletes logs past their retention. Does nothing if automation is disabled.
+pub async fn run() {
+    if !CONFIG.automation.enabled {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if db::read_only() {
+            continue;
+        }
+
+        let res = async {
+            db::timed("delete_old_automation_logs", sqlx::query!(
+                "DELETE FROM aesterisk.automation_logs WHERE automation_log_created_at < $1",
+                now().saturating_sub(CONFIG.automation.log_retention_days * 24 * 60 * 60) as i64,
+            ).execute(db::get()?)).await
+        }.await;
+
+        if let Err(e) = res {
+            warn!("Could not delete old automation logs: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use packet::events::ServerStatusEvent;
+
+    use super::*;
+
+    fn rule(id: i32, count: u32, window: u64, cooldown: u64) -> AutomationRule {
+        AutomationRule {
+            id,
+            name: format!("rule {}", id),
+            server: None,
+            status: ServerStatusType::Unhealthy,
+            count,
+            window,
+            skip_maintenance: true,
+            command: Some(ServerCommand::Restart),
+            notify: false,
+            cooldown,
+        }
+    }
+
+    fn status(status: ServerStatusType) -> EventData {
+        EventData::ServerStatus(ServerStatusEvent {
+            server: 1,
+            status,
+            memory: None,
+            cpu: None,
+            storage: None,
+            in_maintenance: false,
+            reason: None,
+            started_at: None,
+            restart_count: 0,
+            cpu_convention: Default::default(),
+            cores: None,
+        })
+    }
+
+    #[test]
+    fn window() {
+        let rule = rule(1, 3, 60, 0);
+        let mut state = RuleState::default();
+
+        assert_eq!(state.trigger(&rule, 0, false), Decision::Wait(1));
+        assert_eq!(state.trigger(&rule, 30, false), Decision::Wait(2));
+        // the first one left the window
+        assert_eq!(state.trigger(&rule, 70, false), Decision::Wait(2));
+        assert_eq!(state.trigger(&rule, 80, false), Decision::Fire(3));
+        // firing starts counting from scratch
+        assert_eq!(state.trigger(&rule, 90, false), Decision::Wait(1));
+    }
+
+    #[test]
+    fn cooldown_and_maintenance() {
+        let rule = rule(1, 1, 0, 300);
+        let mut state = RuleState::default();
+
+        assert!(matches!(state.trigger(&rule, 0, true), Decision::Suppress(_)));
+        assert_eq!(state.trigger(&rule, 10, false), Decision::Fire(1));
+        assert!(matches!(state.trigger(&rule, 309, false), Decision::Suppress(reason) if reason.contains("cooling down")));
+        assert_eq!(state.trigger(&rule, 310, false), Decision::Fire(1));
+    }
+
+    #[tokio::test]
+    async fn old_and_replayed_statuses_dont_fire() {
+        let daemon = Uuid::from_u128(0x4517);
+        RULES.insert(daemon, vec![rule(4517, 1, 0, 0)]);
+
+        // the first status seen doesn't fire
+        assert!(evaluate(&daemon, &status(ServerStatusType::Unhealthy), Some(100)).is_empty());
+        assert!(evaluate(&daemon, &status(ServerStatusType::Healthy), Some(200)).is_empty());
+
+        // arrived out of order, the server was healthy since
+        assert!(evaluate(&daemon, &status(ServerStatusType::Unhealthy), Some(150)).is_empty());
+
+        // happened while the daemon was disconnected
+        replayed(&daemon, &status(ServerStatusType::Unhealthy), Some(300));
+        assert!(evaluate(&daemon, &status(ServerStatusType::Unhealthy), Some(400)).is_empty());
+
+        evaluate(&daemon, &status(ServerStatusType::Healthy), Some(500));
+        let firings = evaluate(&daemon, &status(ServerStatusType::Unhealthy), Some(600));
+        assert_eq!(firings.len(), 1);
+        assert!(matches!(firings[0].command, Some(ServerCommand::Restart)));
+
+        RULES.remove(&daemon);
+    }
+}
@@ -0,0 +1,9 @@
+use sqlx::types::Uuid;
+
+use crate::repository;
+
+/// Returns an uploaded build context archive, or `None` if it doesn't exist or none of the
+/// daemon's servers are built from it.
+pub async fn context(uuid: Uuid, hash: &str) -> Result<Option<Vec<u8>>, String> {
+    repository::get().build_context(uuid, hash).await
+}
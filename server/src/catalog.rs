@@ -0,0 +1,14 @@
+use packet::server_daemon::catalog::SDCatalogPacket;
+
+use crate::{config::CONFIG, repository};
+
+/// Builds the catalog from the images used by the most servers, except for images built by the
+/// daemons themselves.
+pub async fn fetch() -> Result<SDCatalogPacket, String> {
+    let images = repository::get().catalog(CONFIG.catalog.size).await?;
+
+    Ok(SDCatalogPacket {
+        images,
+        windows: vec![CONFIG.catalog.window()],
+    })
+}
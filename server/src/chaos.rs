@@ -0,0 +1,50 @@
+use std::time::Duration;
+
+use openssl::rand::rand_bytes;
+use packet::close::CloseReason;
+use tracing::warn;
+
+use crate::{config::CONFIG, server::close, state::Tx};
+
+/// Returns a random number from 0 to 1.
+fn random() -> f64 {
+    let mut bytes = [0; 8];
+
+    match rand_bytes(&mut bytes) {
+        Ok(()) => u64::from_le_bytes(bytes) as f64 / u64::MAX as f64,
+        Err(_) => 1.0,
+    }
+}
+
+/// Returns `true` with the given probability.
+fn roll(probability: f64) -> bool {
+    random() < probability
+}
+
+/// Delays an incoming packet, if `chaos.delay_probability` says so.
+pub async fn delay() {
+    if roll(CONFIG.chaos.delay_probability) {
+        let delay = Duration::from_millis((random() * CONFIG.chaos.max_delay_ms as f64) as u64);
+
+        warn!("Chaos: delaying packet by {}ms", delay.as_millis());
+        tokio::time::sleep(delay).await;
+    }
+}
+
+/// Closes the connection at a random point in time, as configured by `chaos.drop_probability`.
+/// Never completes, like the idle timeout of a connection.
+pub async fn drop_connection(tx: &Tx) {
+    if CONFIG.chaos.drop_probability > 0.0 {
+        loop {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+
+            if roll(CONFIG.chaos.drop_probability) {
+                warn!("Chaos: dropping connection");
+                close(tx, CloseReason::ServerShutdown);
+                break;
+            }
+        }
+    }
+
+    std::future::pending::<()>().await
+}
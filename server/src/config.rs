@@ -1,11 +1,25 @@
+use std::{net::{SocketAddr, ToSocketAddrs}, path::Path};
+
 use lazy_static::lazy_static;
+use packet::{features::Feature, maintenance::MaintenanceWindow, server_daemon::config::{LogLevel, SDConfigPacket}};
+
+use crate::versions;
 
 lazy_static! {
-    pub static ref CONFIG: Config = load_or_create("config.toml");
+    // tests should neither depend on nor rewrite the config file of the working directory
+    pub static ref CONFIG: Config = if cfg!(test) {
+        Config::default()
+    } else {
+        load_or_create("config.toml").unwrap_or_else(|e| {
+            eprintln!("Configuration error, please check your config file: {}", e);
+            crate::exit(crate::ExitCode::ConfigError)
+        })
+    };
 }
 
 /// The `Config` struct represents the configuration of the server.
 #[derive(Debug, serde::Serialize, serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     /// The server configuration.
     #[serde(default)]
@@ -13,18 +27,100 @@ pub struct Config {
     /// The socket configuration.
     #[serde(default)]
     pub sockets: Sockets,
+    /// The TLS configuration of the WebSocket servers.
+    #[serde(default)]
+    pub tls: Tls,
     /// The logging configuration.
     #[serde(default)]
     pub logging: Logging,
+    /// The metrics history configuration.
+    #[serde(default)]
+    pub metrics: Metrics,
+    /// The event history configuration.
+    #[serde(default)]
+    pub history: History,
+    /// The daemon sync configuration.
+    #[serde(default)]
+    pub sync: Sync,
+    /// The database configuration.
+    #[serde(default)]
+    pub database: Database,
+    /// The authentication backend configuration.
+    #[serde(default)]
+    pub auth: Auth,
+    /// The no-database mode configuration.
+    #[serde(default)]
+    pub standalone: Standalone,
+    /// The listen quota configuration.
+    #[serde(default)]
+    pub listens: Listens,
+    /// The event fan-out worker pool configuration.
+    #[serde(default)]
+    pub fanout: Fanout,
+    /// The web client packet rate limit configuration.
+    #[serde(default)]
+    pub rate_limits: RateLimits,
+    /// The connection session history configuration.
+    #[serde(default)]
+    pub sessions: Sessions,
+    /// The critical packet redelivery configuration.
+    #[serde(default)]
+    pub outbox: Outbox,
+    /// The notification inbox configuration.
+    #[serde(default)]
+    pub inbox: Inbox,
+    /// The terminal configuration.
+    #[serde(default)]
+    pub terminals: Terminals,
+    /// The self-monitoring configuration.
+    #[serde(default)]
+    pub watchdog: Watchdog,
+    /// The packet issuer configuration.
+    #[serde(default)]
+    pub issuers: Issuers,
+    /// The image catalog configuration.
+    #[serde(default)]
+    pub catalog: Catalog,
+    /// The declarative spec import configuration.
+    #[serde(default)]
+    pub gitops: GitOps,
+    /// The supported daemon versions configuration.
+    #[serde(default)]
+    pub daemons: Daemons,
+    /// The daemon settings pushed to daemons.
+    #[serde(default)]
+    pub remote_config: RemoteConfig,
+    /// The debug endpoint configuration.
+    #[serde(default)]
+    pub debug: DebugEndpoint,
+    /// The fault injection configuration.
+    #[serde(default)]
+    pub chaos: Chaos,
+    /// The WASM extension configuration.
+    #[serde(default)]
+    pub wasm: Wasm,
+    /// The automation rule configuration.
+    #[serde(default)]
+    pub automation: Automation,
 }
 
 /// The `Server` struct represents the server configuration.
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Server {
     /// The URL of the web (frontend) server.
     pub web_url: String,
     /// The path to the server private key.
     pub private_key: String,
+    /// Whether the server starts in read-only mode, see `db::read_only`. Can be toggled at runtime
+    /// by sending `SIGUSR1` to the server.
+    #[serde(default)]
+    pub read_only: bool,
+    /// The URL of another server (e.g. `wss://daemon-2.server.aesterisk.io`) daemons are told to
+    /// reconnect to when this server shuts down, for maintenance without downtime in deployments
+    /// with multiple servers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub drain_url: Option<String>,
 }
 
 impl Default for Server {
@@ -32,17 +128,45 @@ impl Default for Server {
         Self {
             web_url: "http://127.0.0.1:3000".to_string(),
             private_key: "private.pem".to_string(),
+            read_only: false,
+            drain_url: None,
         }
     }
 }
 
 /// The `Sockets` struct represents the socket configuration.
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Sockets {
-    /// The address to bind the web server.
+    /// The address to bind the web server. A socket named `web` (or bound to this address) passed
+    /// by systemd socket activation is used instead.
     pub web: String,
-    /// The address to bind the daemon server.
+    /// The address to bind the daemon server. A socket named `daemon` (or bound to this address)
+    /// passed by systemd socket activation is used instead.
     pub daemon: String,
+    /// The origins (e.g. `https://aesterisk.io`) web clients may connect from. If empty, only the
+    /// origin of `server.web_url` is allowed.
+    #[serde(default)]
+    pub allowed_origins: Vec<String>,
+    /// The amount of seconds after which connections that haven't sent anything are closed, or `0`
+    /// to keep idle connections open.
+    #[serde(default)]
+    pub idle_timeout: u64,
+    /// The amount of seconds between the pings sent to connections that support heartbeats, or `0`
+    /// to not send any.
+    #[serde(default = "default_heartbeat_interval")]
+    pub heartbeat_interval: u64,
+    /// The amount of pings in a row a connection may leave unanswered before it is closed.
+    #[serde(default = "default_missed_heartbeats")]
+    pub missed_heartbeats: u32,
+}
+
+fn default_heartbeat_interval() -> u64 {
+    30
+}
+
+fn default_missed_heartbeats() -> u32 {
+    3
 }
 
 impl Default for Sockets {
@@ -50,12 +174,17 @@ impl Default for Sockets {
         Self {
             web: "127.0.0.1:31306".to_string(),
             daemon: "127.0.0.1:31304".to_string(),
+            allowed_origins: Vec::new(),
+            idle_timeout: 0,
+            heartbeat_interval: default_heartbeat_interval(),
+            missed_heartbeats: default_missed_heartbeats(),
         }
     }
 }
 
 /// The `Logging` struct represents the logging configuration.
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct Logging {
     /// The folder to store log files in.
     pub folder: String,
@@ -69,19 +198,1016 @@ impl Default for Logging {
     }
 }
 
-fn save(config: &Config, file: &str) {
-    std::fs::write(file, toml::to_string_pretty(&config).expect("failed to serialize default config")).expect("could not write config file");
+/// The `Tls` struct represents the TLS configuration of the WebSocket servers. Without TLS, the
+/// servers accept plain WebSocket connections, and rely on a proxy in front of them to terminate
+/// TLS.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Tls {
+    /// Whether the daemon server only accepts TLS (`wss://`) connections.
+    pub daemon: bool,
+    /// Whether the web server only accepts TLS (`wss://`) connections.
+    pub web: bool,
+    /// The path to the PEM encoded certificate chain, leaf certificate first.
+    pub cert: String,
+    /// The path to the PEM encoded private key of the certificate.
+    pub key: String,
+    /// The ALPN protocols the servers accept, in order of preference. WebSockets are upgraded from
+    /// HTTP/1.1, so no other protocol should be offered.
+    pub alpn: Vec<String>,
+}
+
+impl Default for Tls {
+    fn default() -> Self {
+        Self {
+            daemon: false,
+            web: false,
+            cert: "cert.pem".to_string(),
+            key: "key.pem".to_string(),
+            alpn: vec!["http/1.1".to_string()],
+        }
+    }
+}
+
+impl Tls {
+    /// Returns whether either server accepts TLS connections.
+    pub fn enabled(&self) -> bool {
+        self.daemon || self.web
+    }
+}
+
+/// The `Metrics` struct represents the metrics history configuration.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Metrics {
+    /// Whether node and server metrics should be stored in the database.
+    pub enabled: bool,
+    /// The minimum amount of seconds between two stored samples of the same node or server.
+    pub sample_interval: u64,
+    /// The amount of hours raw samples are kept for, before only hourly rollups remain.
+    pub raw_retention_hours: u64,
+    /// The amount of days hourly rollups are kept for.
+    pub rollup_retention_days: u64,
+    /// Whether the usage of servers should be attributed to the teams owning their nodes, as
+    /// monthly usage records.
+    pub accounting: bool,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            sample_interval: 60,
+            raw_retention_hours: 48,
+            rollup_retention_days: 90,
+            accounting: false,
+        }
+    }
+}
+
+/// The `Sync` struct represents the daemon sync configuration.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sync {
+    /// The amount of times the reconciliation sync after a daemon connects is attempted.
+    pub reconcile_attempts: u32,
+    /// The amount of seconds to wait before retrying a failed reconciliation sync, multiplied by
+    /// the amount of failed attempts.
+    pub reconcile_retry_delay: u64,
+    /// The minimum amount of seconds between two syncs of the same daemon requested by web
+    /// clients. Requests within this window are coalesced into a single deferred sync.
+    pub debounce: u64,
+    /// The maximum amount of syncs a single web client can request per minute.
+    pub rate_limit: u32,
+    /// The amount of seconds an assembled spec is cached for, or `0` to disable caching. Cached
+    /// specs are dropped early when the database notifies the server of a change.
+    pub spec_cache_ttl: u64,
+    /// The amount of seconds to wait for a daemon to report whether it applied a sync. Includes
+    /// pulling and building images, so it should be generous.
+    pub ack_timeout: u64,
+}
+
+impl Default for Sync {
+    fn default() -> Self {
+        Self {
+            reconcile_attempts: 5,
+            reconcile_retry_delay: 10,
+            debounce: 5,
+            rate_limit: 30,
+            spec_cache_ttl: 300,
+            ack_timeout: 900,
+        }
+    }
+}
+
+/// The `History` struct represents the event history configuration. Recent status events of daemons
+/// are stored in the database, so web clients that start listening to a daemon can catch up on what
+/// happened before.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct History {
+    /// Whether node and server status events should be stored in the database.
+    pub enabled: bool,
+    /// The amount of minutes events are kept for.
+    pub retention_minutes: u64,
+    /// The amount of stored events of each type sent when a web client starts listening to a
+    /// daemon, before its latest status, or `0` to only send the latest status.
+    pub backfill: u32,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            retention_minutes: 60,
+            backfill: 0,
+        }
+    }
+}
+
+/// The `Database` struct represents the database configuration.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Database {
+    /// The database `DATABASE_URL` points to.
+    pub backend: DatabaseBackend,
+    /// The amount of seconds a query can take before it fails.
+    pub query_timeout: u64,
+    /// The amount of milliseconds after which a query is logged as slow.
+    pub slow_query_threshold: u64,
+}
+
+impl Default for Database {
+    fn default() -> Self {
+        Self {
+            backend: DatabaseBackend::Postgres,
+            query_timeout: 30,
+            slow_query_threshold: 500,
+        }
+    }
+}
+
+/// The database the server stores its data in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DatabaseBackend {
+    /// A Postgres database, shared with the web interface
+    Postgres,
+    /// A SQLite database file, created and migrated on startup, for single-binary deployments. It
+    /// stores everything Postgres does except automation rules and usage accounting, which aren't
+    /// available, and as nothing notifies the server of changes made to it, they are picked up
+    /// once cached specs expire
+    Sqlite,
+}
+
+/// The backend the public keys of users and daemons are looked up in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthBackend {
+    /// The `users` and `nodes` tables of the database
+    Postgres,
+    /// A TOML file with a `[users]` table of user IDs and a `[daemons]` table of daemon UUIDs to
+    /// PEM-encoded public keys, e.g. for air-gapped labs
+    File,
+    /// An external service, asked with `GET <url>/users/<id>` and `GET <url>/daemons/<uuid>` for
+    /// PEM-encoded public keys, which responds with 404 to principals that don't exist
+    Http,
+}
+
+/// The `Auth` struct represents the authentication backend configuration, which decides where the
+/// public keys of authenticating users and daemons are looked up.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Auth {
+    /// The backend to look up public keys in.
+    pub backend: AuthBackend,
+    /// The key file of the `file` backend.
+    pub file: String,
+    /// The base URL of the `http` backend, which has to use `https`.
+    pub url: String,
+    /// Path to a PEM certificate of the CA that issued the certificate of the `http` backend, which
+    /// is trusted instead of the system roots (empty to trust the system roots).
+    #[serde(default)]
+    pub ca_cert: String,
+}
+
+impl Default for Auth {
+    fn default() -> Self {
+        Self {
+            backend: AuthBackend::Postgres,
+            file: "keys.toml".to_string(),
+            url: String::new(),
+            ca_cert: String::new(),
+        }
+    }
+}
+
+/// The `Standalone` struct represents the no-database mode for small deployments. Instead of the
+/// database, the nodes and their servers are read from the TOML or YAML spec files (see `Spec`) of
+/// a folder, and the public keys of users and daemons from its `keys.toml` (in the format of the
+/// `file` authentication backend). Users can only access the nodes listing them in `users`, and the
+/// server is read-only, as there is no database to write to.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Standalone {
+    /// Whether the server runs without a database.
+    pub enabled: bool,
+    /// The folder containing `keys.toml` and the spec files.
+    pub folder: String,
+    /// The amount of seconds between two checks for changed files.
+    pub interval: u64,
+}
+
+impl Default for Standalone {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            folder: "./aesterisk".to_string(),
+            interval: 5,
+        }
+    }
+}
+
+/// The `Listens` struct represents the listen quota configuration. A listen is a single event type
+/// of a single daemon, so listening to two event types of three daemons counts as six listens.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Listens {
+    /// The maximum amount of listens of a single web client socket, or `0` for no limit.
+    pub max_per_socket: usize,
+    /// The maximum amount of listens of all sockets of a single user combined, or `0` for no limit.
+    pub max_per_user: usize,
+    /// The amount of recent log lines sent when a web client starts following the logs of a server,
+    /// or `0` to only send new lines.
+    pub log_backfill: u32,
+}
+
+impl Default for Listens {
+    fn default() -> Self {
+        Self {
+            max_per_socket: 1000,
+            max_per_user: 5000,
+            log_backfill: 100,
+        }
+    }
+}
+
+/// The `Fanout` struct represents the event fan-out worker pool configuration. Events are
+/// delivered to web clients by a pool of workers, each owning the daemons of its shard, so the
+/// events of a single daemon are always delivered in order.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Fanout {
+    /// The amount of workers, or `0` for one per CPU.
+    pub shards: usize,
+    /// The most events queued per worker. Daemons sending events to a full worker wait until it
+    /// has caught up.
+    pub queue: usize,
+}
+
+impl Default for Fanout {
+    fn default() -> Self {
+        Self {
+            shards: 0,
+            queue: 1024,
+        }
+    }
+}
+
+impl Fanout {
+    /// Returns the amount of workers to start, see `Fanout::shards`.
+    pub fn shards(&self) -> usize {
+        match self.shards {
+            0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            shards => shards,
+        }
+    }
+}
+
+/// The `RateLimits` struct represents the packet rate limit configuration of web client sockets.
+/// Syncs are limited separately, see `Sync::rate_limit`.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimits {
+    /// The limit of packets changing the events a socket listens to.
+    pub listens: RateLimit,
+    /// The limit of packets querying logs, stats, metrics, usage or the spec.
+    pub queries: RateLimit,
+    /// The limit of packets changing servers or the spec, e.g. placements and imports.
+    pub commands: RateLimit,
+}
+
+impl Default for RateLimits {
+    fn default() -> Self {
+        Self {
+            listens: RateLimit {
+                rate: 30,
+                period: 60,
+                burst: 10,
+            },
+            queries: RateLimit {
+                rate: 10,
+                period: 1,
+                burst: 20,
+            },
+            commands: RateLimit {
+                rate: 10,
+                period: 60,
+                burst: 5,
+            },
+        }
+    }
+}
+
+/// The `RateLimit` struct represents a single packet rate limit. Sockets may send `burst` packets at
+/// once, after which they are limited to `rate` packets per `period`.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RateLimit {
+    /// The amount of packets allowed per period, or `0` for no limit.
+    pub rate: u32,
+    /// The length of the period in seconds.
+    pub period: u64,
+    /// The amount of packets a socket can send at once, after not sending any for a while.
+    pub burst: u32,
+}
+
+/// The `Sessions` struct represents the connection session history configuration.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Sessions {
+    /// Whether connections of daemons and web clients should be recorded in the database when
+    /// they end.
+    pub enabled: bool,
+    /// The amount of days sessions are kept for.
+    pub retention_days: u64,
+}
+
+impl Default for Sessions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: 30,
+        }
+    }
+}
+
+/// The `Outbox` struct represents the redelivery configuration of critical packets, like the
+/// results of syncs. They are redelivered to the next web client of a user that authenticates,
+/// until one of its web clients acknowledges them.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Outbox {
+    /// The amount of seconds critical packets are kept for.
+    pub ttl: u64,
+    /// The most critical packets kept per user. The oldest ones are dropped first.
+    pub capacity: usize,
+}
+
+impl Default for Outbox {
+    fn default() -> Self {
+        Self {
+            ttl: 3600,
+            capacity: 50,
+        }
+    }
+}
+
+/// The `Inbox` struct represents the notification inbox configuration. Notifications, like alerts
+/// and failed syncs, are stored per user, so users can catch up on what happened while they were
+/// offline.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Inbox {
+    /// Whether notifications should be stored in the database.
+    pub enabled: bool,
+    /// The amount of days notifications are kept for, whether they were read or not.
+    pub retention_days: u64,
+}
+
+impl Default for Inbox {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: 90,
+        }
+    }
+}
+
+/// The `Automation` struct represents the automation rule configuration. Automation rules are stored
+/// in the database per node, and run commands on servers or notify their team once a server entered
+/// a status often enough, see `automation::evaluate`.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Automation {
+    /// Whether automation rules should be evaluated.
+    pub enabled: bool,
+    /// The amount of days the logs of rule evaluations are kept for.
+    pub log_retention_days: u64,
+}
+
+impl Default for Automation {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            log_retention_days: 14,
+        }
+    }
+}
+
+/// The `Terminals` struct represents the configuration of terminals web clients open into the
+/// servers of their team.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Terminals {
+    /// Whether web clients may open terminals.
+    pub enabled: bool,
+    /// The maximum amount of terminals open at the same time per user, or `0` for no limit.
+    pub max_per_user: usize,
+}
+
+impl Default for Terminals {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_per_user: 4,
+        }
+    }
+}
+
+/// The `Watchdog` struct represents the self-monitoring configuration. The server checks its
+/// listeners, its database connection and the lag of its event loop, and reports its health to
+/// URLs outside of it, so operators learn about failures the server can't report itself.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Watchdog {
+    /// The amount of seconds between checks.
+    pub interval: u64,
+    /// The amount of milliseconds the event loop may lag behind before the server is unhealthy.
+    pub max_lag_ms: u64,
+    /// The URL requested after every check the server passed, e.g. of a dead man's switch that
+    /// alerts once the requests stop.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub heartbeat_url: Option<String>,
+    /// The URL the problems are posted to as JSON whenever the server becomes unhealthy or
+    /// recovers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alert_url: Option<String>,
+}
+
+impl Default for Watchdog {
+    fn default() -> Self {
+        Self {
+            interval: 30,
+            max_lag_ms: 500,
+            heartbeat_url: None,
+            alert_url: None,
+        }
+    }
+}
+
+/// The `Issuers` struct represents the packet issuer configuration. Every packet carries the issuer
+/// of its sender, and packets of issuers that aren't accepted by a listener are rejected. Accepting
+/// multiple issuers allows renaming them without breaking connected peers.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Issuers {
+    /// The issuer of packets sent by the server.
+    pub server: String,
+    /// The issuers accepted on packets of daemons.
+    pub daemon: Vec<String>,
+    /// The issuers accepted on packets of web clients.
+    pub web: Vec<String>,
+}
+
+impl Default for Issuers {
+    fn default() -> Self {
+        Self {
+            server: "aesterisk/server".to_string(),
+            daemon: vec!["aesterisk/daemon".to_string()],
+            web: vec!["aesterisk/web".to_string()],
+        }
+    }
+}
+
+/// The `Catalog` struct represents the image catalog configuration. The catalog contains the most
+/// used images, which daemons pull ahead of time.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Catalog {
+    /// Whether the catalog should be sent to daemons.
+    pub enabled: bool,
+    /// The amount of most used images in the catalog.
+    pub size: u32,
+    /// The cron expression (in UTC) of when daemons start pulling images, which should be when
+    /// they are mostly idle.
+    pub schedule: String,
+    /// The amount of minutes after `schedule` daemons may pull images for.
+    pub duration: u32,
+}
+
+impl Default for Catalog {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            size: 10,
+            schedule: "0 3 * * *".to_string(),
+            duration: 180,
+        }
+    }
+}
+
+impl Catalog {
+    /// Returns the window daemons may pull images in.
+    pub fn window(&self) -> MaintenanceWindow {
+        MaintenanceWindow {
+            cron: self.schedule.clone(),
+            duration: self.duration,
+        }
+    }
+}
+
+/// The `GitOps` struct represents the declarative spec import configuration. The spec file is
+/// imported from a Git repository whenever a new commit is pushed to the configured branch.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GitOps {
+    /// Whether the spec should be imported from the repository.
+    pub enabled: bool,
+    /// The URL of the repository, cloned with the `git` command.
+    pub repository: String,
+    /// The branch to import the spec from.
+    pub branch: String,
+    /// The path of the spec file within the repository, in YAML if it ends with `.yaml` or `.yml`
+    /// and in TOML otherwise.
+    pub path: String,
+    /// The team the spec is imported for, all of its nodes have to belong to the team, and the
+    /// tags it creates are owned by the team.
+    pub team: u32,
+    /// The folder to clone the repository into.
+    pub folder: String,
+    /// The amount of seconds between two checks for new commits.
+    pub interval: u64,
+    /// Whether networks and servers of the spec's nodes that are not in the spec should be deleted.
+    pub prune: bool,
+}
+
+impl Default for GitOps {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            repository: String::new(),
+            branch: "main".to_string(),
+            path: "aesterisk.toml".to_string(),
+            team: 0,
+            folder: "./gitops".to_string(),
+            interval: 60,
+            prune: false,
+        }
+    }
+}
+
+/// The `Daemons` struct represents the supported daemon versions. Web clients are notified with an
+/// `UpdateRequired` event when a daemon outside of the range connects.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Daemons {
+    /// The oldest supported daemon version (e.g. `0.1.0`), or empty to support all older versions.
+    pub min_version: String,
+    /// The newest supported daemon version, or empty to support all newer versions.
+    pub max_version: String,
+    /// Whether daemons outside of the range are refused, instead of only being reported.
+    pub refuse_unsupported: bool,
+}
+
+/// The `RemoteConfig` struct represents the daemon settings sent to every daemon when it
+/// authenticates, overriding the defaults of the daemons. Unset settings are reset to the defaults.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RemoteConfig {
+    /// The amount of seconds between node status events.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub node_status_interval: Option<u64>,
+    /// The minimum amount of seconds between status events of a server.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_status_interval: Option<u64>,
+    /// The most verbose level daemons log, e.g. `info`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<LogLevel>,
+    /// The features daemons stop using, e.g. `["catalog"]`.
+    #[serde(default)]
+    pub disabled_features: Vec<Feature>,
+}
+
+impl RemoteConfig {
+    /// Returns the packet sent to daemons.
+    pub fn packet(&self) -> SDConfigPacket {
+        SDConfigPacket {
+            node_status_interval: self.node_status_interval,
+            server_status_interval: self.server_status_interval,
+            log_level: self.log_level,
+            disabled_features: self.disabled_features.clone(),
+        }
+    }
+}
+
+/// The `DebugEndpoint` struct represents the debug endpoint configuration. The endpoint is only
+/// served if the server is built with the `debug_endpoint` feature.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DebugEndpoint {
+    /// The address to bind the debug endpoint, which has to be a loopback address.
+    pub bind: String,
+}
+
+impl Default for DebugEndpoint {
+    fn default() -> Self {
+        Self {
+            bind: "127.0.0.1:31309".to_string(),
+        }
+    }
+}
+
+/// The `Chaos` struct represents the fault injection configuration, used to test how daemons and
+/// web clients deal with an unreliable server. Faults are only injected if the server is built with
+/// the `chaos` feature.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Chaos {
+    /// The probability (from 0 to 1) of a connection being closed, checked every second.
+    pub drop_probability: f64,
+    /// The probability (from 0 to 1) of an incoming packet being delayed.
+    pub delay_probability: f64,
+    /// The most milliseconds an incoming packet is delayed by.
+    pub max_delay_ms: u64,
+}
+
+impl Default for Chaos {
+    fn default() -> Self {
+        Self {
+            drop_probability: 0.0,
+            delay_probability: 0.0,
+            max_delay_ms: 1000,
+        }
+    }
+}
+
+/// The `Wasm` struct represents the limits of the WASM extensions teams upload, which filter and
+/// transform the events of the team's nodes before they are delivered, e.g. for custom alert logic.
+/// Extensions are only run if the server is built with the `wasm` feature.
+#[derive(Debug, serde::Serialize, serde::Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Wasm {
+    /// Whether the extensions of teams are run.
+    pub enabled: bool,
+    /// The most fuel (roughly the amount of instructions) an extension may use per event.
+    pub fuel: u64,
+    /// The most bytes of memory an extension may use.
+    pub max_memory: usize,
+    /// The most time (in milliseconds) an extension may take per event.
+    pub timeout_ms: u64,
 }
 
-fn load(file: &str) -> Option<Config> {
-    let contents = std::fs::read_to_string(file).ok()?;
-    toml::from_str(&contents).ok()
+impl Default for Wasm {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            fuel: 1_000_000,
+            max_memory: 16 * 1024 * 1024,
+            timeout_ms: 50,
+        }
+    }
+}
+
+impl Config {
+    /// Returns the origins web clients may connect from, see `Sockets::allowed_origins`. They are
+    /// serialized the way browsers send them in the `Origin` header, e.g. without a trailing slash
+    /// and with a lowercase host.
+    pub fn allowed_origins(&self) -> Vec<String> {
+        if !self.sockets.allowed_origins.is_empty() {
+            return self.sockets.allowed_origins.iter().filter_map(|origin| reqwest::Url::parse(origin).ok()).map(|url| url.origin().ascii_serialization()).collect();
+        }
+
+        reqwest::Url::parse(&self.server.web_url).map(|url| vec![url.origin().ascii_serialization()]).unwrap_or_default()
+    }
+
+    /// Checks the values of the configuration, returning a description of every problem found.
+    fn validate(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+
+        let mut check = |field: &str, res: Result<(), String>| {
+            if let Err(e) = res {
+                problems.push(format!("{}: {}", field, e));
+            }
+        };
+
+        check("server.web_url", check_url(&self.server.web_url, &["http", "https"]));
+
+        if let Some(drain_url) = self.server.drain_url.as_ref() {
+            check("server.drain_url", check_url(drain_url, &["ws", "wss"]));
+        }
+        check("server.private_key", std::fs::File::open(&self.server.private_key).map(|_| ()).map_err(|e| format!("could not read \"{}\": {}", self.server.private_key, e)));
+        check("sockets.web", check_address(&self.sockets.web));
+        check("sockets.daemon", check_address(&self.sockets.daemon));
+
+        for (i, origin) in self.sockets.allowed_origins.iter().enumerate() {
+            check(&format!("sockets.allowed_origins[{}]", i), check_origin(origin));
+        }
+
+        if self.sockets.heartbeat_interval > 0 && self.sockets.missed_heartbeats == 0 {
+            check("sockets.missed_heartbeats", Err("should be greater than 0".to_string()));
+        }
+
+        if Path::new(&self.logging.folder).is_file() {
+            check("logging.folder", Err(format!("\"{}\" is not a folder", self.logging.folder)));
+        }
+
+        if self.fanout.queue == 0 {
+            check("fanout.queue", Err("should be greater than 0".to_string()));
+        }
+
+        if self.metrics.enabled && self.metrics.sample_interval == 0 {
+            check("metrics.sample_interval", Err("should be greater than 0".to_string()));
+        }
+
+        if self.metrics.accounting && !self.metrics.enabled {
+            check("metrics.accounting", Err("requires metrics.enabled".to_string()));
+        }
+
+        if self.catalog.enabled && !self.catalog.window().is_valid() {
+            check("catalog.schedule", Err(format!("invalid cron expression \"{}\"", self.catalog.schedule)));
+        }
+
+        if self.gitops.enabled {
+            if self.gitops.repository.is_empty() {
+                check("gitops.repository", Err("should be set".to_string()));
+            }
+
+            if self.gitops.team == 0 {
+                check("gitops.team", Err("should be set".to_string()));
+            }
+
+            if self.gitops.interval == 0 {
+                check("gitops.interval", Err("should be greater than 0".to_string()));
+            }
+
+            if Path::new(&self.gitops.folder).is_file() {
+                check("gitops.folder", Err(format!("\"{}\" is not a folder", self.gitops.folder)));
+            }
+        }
+
+        for (field, limit) in [("listens", &self.rate_limits.listens), ("queries", &self.rate_limits.queries), ("commands", &self.rate_limits.commands)] {
+            if limit.rate == 0 {
+                continue;
+            }
+
+            if limit.period == 0 {
+                check(&format!("rate_limits.{}.period", field), Err("should be greater than 0".to_string()));
+            }
+
+            if limit.burst == 0 {
+                check(&format!("rate_limits.{}.burst", field), Err("should be greater than 0".to_string()));
+            }
+        }
+
+        if self.issuers.server.is_empty() {
+            check("issuers.server", Err("should be set".to_string()));
+        }
+
+        if self.issuers.daemon.is_empty() {
+            check("issuers.daemon", Err("should contain at least one issuer".to_string()));
+        }
+
+        if self.issuers.web.is_empty() {
+            check("issuers.web", Err("should contain at least one issuer".to_string()));
+        }
+
+        for (field, version) in [("daemons.min_version", &self.daemons.min_version), ("daemons.max_version", &self.daemons.max_version)] {
+            if !version.is_empty() && versions::parse(version).is_none() {
+                check(field, Err(format!("invalid version \"{}\", expected e.g. 0.1.0", version)));
+            }
+        }
+
+        if let (Some(min), Some(max)) = (versions::parse(&self.daemons.min_version), versions::parse(&self.daemons.max_version))
+            && min > max {
+            check("daemons.max_version", Err("should not be older than daemons.min_version".to_string()));
+        }
+
+        for (field, interval) in [("remote_config.node_status_interval", self.remote_config.node_status_interval), ("remote_config.server_status_interval", self.remote_config.server_status_interval)] {
+            if interval == Some(0) {
+                check(field, Err("should be greater than 0".to_string()));
+            }
+        }
+
+        for feature in self.remote_config.disabled_features.iter() {
+            match feature {
+                Feature::RemoteConfig => check("remote_config.disabled_features", Err("remote_config can't be disabled remotely, as it could never be enabled again".to_string())),
+                Feature::Unknown => check("remote_config.disabled_features", Err("contains an unknown feature".to_string())),
+                _ => (),
+            }
+        }
+
+        match self.debug.bind.parse::<SocketAddr>() {
+            Ok(addr) if !addr.ip().is_loopback() => check("debug.bind", Err(format!("\"{}\" should be a loopback address", self.debug.bind))),
+            Ok(_) => (),
+            Err(e) => check("debug.bind", Err(format!("invalid address \"{}\": {}", self.debug.bind, e))),
+        }
+
+        for (field, probability) in [("chaos.drop_probability", self.chaos.drop_probability), ("chaos.delay_probability", self.chaos.delay_probability)] {
+            if !(0.0..=1.0).contains(&probability) {
+                check(field, Err("should be between 0 and 1".to_string()));
+            }
+        }
+
+        if self.wasm.fuel == 0 {
+            check("wasm.fuel", Err("should be greater than 0".to_string()));
+        }
+
+        if self.wasm.max_memory == 0 {
+            check("wasm.max_memory", Err("should be greater than 0".to_string()));
+        }
+
+        if self.wasm.timeout_ms == 0 {
+            check("wasm.timeout_ms", Err("should be greater than 0".to_string()));
+        }
+
+        if self.tls.enabled() {
+            if !Path::new(&self.tls.cert).is_file() {
+                check("tls.cert", Err(format!("\"{}\" is not a file", self.tls.cert)));
+            }
+
+            if !Path::new(&self.tls.key).is_file() {
+                check("tls.key", Err(format!("\"{}\" is not a file", self.tls.key)));
+            }
+
+            if self.tls.alpn.iter().any(|protocol| protocol.is_empty() || protocol.len() > 255) {
+                check("tls.alpn", Err("protocols should be between 1 and 255 bytes long".to_string()));
+            }
+        }
+
+        if self.sync.ack_timeout == 0 {
+            check("sync.ack_timeout", Err("should be greater than 0".to_string()));
+        }
+
+        if self.outbox.ttl == 0 {
+            check("outbox.ttl", Err("should be greater than 0".to_string()));
+        }
+
+        if self.outbox.capacity == 0 {
+            check("outbox.capacity", Err("should be greater than 0".to_string()));
+        }
+
+        if self.watchdog.interval == 0 {
+            check("watchdog.interval", Err("should be greater than 0".to_string()));
+        }
+
+        if self.history.enabled && self.history.retention_minutes == 0 {
+            check("history.retention_minutes", Err("should be greater than 0".to_string()));
+        }
+
+        if self.automation.enabled && self.automation.log_retention_days == 0 {
+            check("automation.log_retention_days", Err("should be greater than 0".to_string()));
+        }
+
+        if self.database.query_timeout == 0 {
+            check("database.query_timeout", Err("should be greater than 0".to_string()));
+        }
+
+        if self.standalone.enabled {
+            if !Path::new(&self.standalone.folder).is_dir() {
+                check("standalone.folder", Err(format!("\"{}\" is not a folder", self.standalone.folder)));
+            }
+
+            if self.standalone.interval == 0 {
+                check("standalone.interval", Err("should be greater than 0".to_string()));
+            }
+
+            if self.gitops.enabled {
+                check("gitops.enabled", Err("requires a database, the spec files of standalone.folder are used instead".to_string()));
+            }
+
+            if self.auth.backend != AuthBackend::Postgres {
+                check("auth.backend", Err("is not used in standalone mode, the keys are read from standalone.folder".to_string()));
+            }
+        }
+
+        if self.database.backend == DatabaseBackend::Sqlite {
+            let unsupported = [
+                ("metrics.accounting", self.metrics.accounting),
+                ("automation.enabled", self.automation.enabled),
+                ("standalone.enabled", self.standalone.enabled),
+            ];
+
+            for (key, enabled) in unsupported {
+                if enabled {
+                    check(key, Err("is not supported by the sqlite database backend, should be false".to_string()));
+                }
+            }
+        }
+
+        match self.auth.backend {
+            _ if self.standalone.enabled => (),
+            AuthBackend::Postgres => (),
+            AuthBackend::File if !Path::new(&self.auth.file).is_file() => check("auth.file", Err(format!("\"{}\" is not a file", self.auth.file))),
+            AuthBackend::File => (),
+            AuthBackend::Http => {
+                // keys fetched over plain HTTP could be replaced to impersonate any user or daemon
+                check("auth.url", check_url(&self.auth.url, &["https"]));
+
+                if !self.auth.ca_cert.is_empty() {
+                    check("auth.ca_cert", std::fs::File::open(&self.auth.ca_cert).map(|_| ()).map_err(|e| format!("could not read \"{}\": {}", self.auth.ca_cert, e)));
+                }
+            },
+        }
+
+        problems
+    }
+}
+
+fn check_url(url: &str, schemes: &[&str]) -> Result<(), String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid URL \"{}\": {}", url, e))?;
+
+    if !schemes.contains(&parsed.scheme()) {
+        return Err(format!("URL \"{}\" should use one of the schemes {}", url, schemes.join(", ")));
+    }
+
+    Ok(())
+}
+
+fn check_origin(origin: &str) -> Result<(), String> {
+    check_url(origin, &["http", "https"])?;
+
+    let parsed = reqwest::Url::parse(origin).map_err(|e| format!("invalid URL \"{}\": {}", origin, e))?;
+
+    if parsed.path() != "/" || parsed.query().is_some() || parsed.fragment().is_some() {
+        return Err(format!("origin \"{}\" should not have a path, query or fragment", origin));
+    }
+
+    Ok(())
+}
+
+fn check_address(address: &str) -> Result<(), String> {
+    address.to_socket_addrs().map(|_| ()).map_err(|e| format!("invalid address \"{}\": {}", address, e))
+}
+
+fn save(config: &Config, file: &str) -> Result<(), String> {
+    std::fs::write(file, toml::to_string_pretty(&config).map_err(|_| "could not serialize config")?).map_err(|e| format!("could not write config file {}: {}", file, e))
+}
+
+fn parse(file: &str) -> Result<(Config, bool), String> {
+    match std::fs::read_to_string(file) {
+        Ok(contents) => {
+            let (contents, substituted) = packet::env::substitute(&contents, |name| std::env::var(name).ok()).map_err(|e| format!("could not load config file {}: {}", file, e))?;
+            Ok((toml::from_str(&contents).map_err(|e| format!("could not parse config file {}: {}", file, e))?, substituted))
+        },
+        Err(_) => Ok((Config::default(), false)),
+    }
+}
+
+/// Loads and validates the configuration from the given file, or returns the default configuration
+/// if the file does not exist. Also returns whether environment variables were substituted in it.
+pub fn load(file: &str) -> Result<(Config, bool), String> {
+    let (config, substituted) = parse(file)?;
+
+    let problems = config.validate();
+
+    if !problems.is_empty() {
+        return Err(format!("invalid values in config file {}:\n  {}", file, problems.join("\n  ")));
+    }
+
+    Ok((config, substituted))
 }
 
 /// Load the configuration from the given file, or create the file with the default configuration if
 /// it does not exist.
-pub fn load_or_create(file: &str) -> Config {
-    let config = load(file).unwrap_or_default();
-    save(&config, file);
-    config
+pub fn load_or_create(file: &str) -> Result<Config, String> {
+    let (config, substituted) = load(file)?;
+
+    // rewriting the file would replace the variables with their current values
+    if !substituted {
+        save(&config, file)?;
+    }
+
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allowed_origins_normalized() {
+        let mut config = Config::default();
+        config.sockets.allowed_origins = vec!["https://Panel.example.com/".to_string(), "http://localhost:3000".to_string(), "https://panel.example.com:443".to_string()];
+
+        assert_eq!(config.allowed_origins(), vec!["https://panel.example.com", "http://localhost:3000", "https://panel.example.com"]);
+        assert!(!config.validate().iter().any(|problem| problem.starts_with("sockets.allowed_origins")));
+
+        config.sockets.allowed_origins = vec!["https://panel.example.com/app".to_string(), "https://panel.example.com?a=b".to_string(), "ws://panel.example.com".to_string()];
+        let problems = config.validate();
+
+        for i in 0..3 {
+            assert!(problems.iter().any(|problem| problem.starts_with(&format!("sockets.allowed_origins[{}]", i))), "{:?}", problems);
+        }
+    }
 }
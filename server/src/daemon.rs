@@ -2,11 +2,11 @@ use std::{borrow::Borrow, net::SocketAddr, sync::Arc};
 
 use async_trait::async_trait;
 use josekit::jwe::alg::rsaes::RsaesJweDecrypter;
-use packet::{daemon_server::{auth::DSAuthPacket, event::DSEventPacket, handshake_response::DSHandshakeResponsePacket}, Packet, ID};
+use packet::{ack, close::CloseReason, heartbeat::PongPacket, subprotocol::Encoding, daemon_server::{auth::DSAuthPacket, command_result::DSCommandResultPacket, error::DSErrorPacket, event::DSEventPacket, fetch_build_context::DSFetchBuildContextPacket, handshake_response::DSHandshakeResponsePacket, query_logs_response::DSQueryLogsResponsePacket, query_stats_response::DSQueryStatsResponsePacket, query_tasks_response::DSQueryTasksResponsePacket, query_top_response::DSQueryTopResponsePacket, query_usage_response::DSQueryUsageResponsePacket, sync_progress::DSSyncProgressPacket, sync_result::DSSyncResultPacket, terminal_close::DSTerminalClosePacket, terminal_output::DSTerminalOutputPacket}, Packet, ID};
 use sqlx::types::Uuid;
-use tracing::{info, instrument};
+use tracing::{info, instrument, warn};
 
-use crate::{config::CONFIG, db, encryption::DECRYPTER, server::Server, state::{DaemonKeyCache, State, Tx}};
+use crate::{auth, config::CONFIG, encryption::DECRYPTER, plugins, quotas, repository, server::Server, sessions::{self, Peer, Session}, state::{DaemonKeyCache, State, Tx}, versions};
 
 /// `DaemonServer` is a WebSocket server (implemented by the `Server` trait) that listens for daemon
 /// connections.
@@ -14,10 +14,6 @@ pub struct DaemonServer {
     state: Arc<State>,
 }
 
-struct PublicKeyQuery {
-    node_public_key: String,
-}
-
 impl DaemonServer {
     /// Creates a new `DaemonServer` instance, with the given `State`.
     pub fn new(state: Arc<State>) -> Self {
@@ -26,26 +22,43 @@ impl DaemonServer {
         }
     }
 
-    async fn query_user_public_key(&self, daemon_uuid: &Uuid) -> Result<Arc<Vec<u8>>, String> {
+    async fn query_user_public_key(&self, daemon_uuid: &Uuid) -> Result<Option<Arc<Vec<u8>>>, String> {
         {
             let cache: &DaemonKeyCache = self.state.daemon_key_cache.borrow();
             if let Some(v) = cache.get(daemon_uuid) {
-                return Ok(v.clone());
+                return Ok(Some(v.clone()));
             }
         }
 
-        let res = sqlx::query_as!(PublicKeyQuery, "SELECT node_public_key FROM aesterisk.nodes WHERE node_uuid = $1", daemon_uuid).fetch_one(db::get()?).await.map_err(|_| format!("Node with UUID {} does not exist", &daemon_uuid))?;
+        let Some(key) = auth::get().daemon_public_key(daemon_uuid).await? else {
+            return Ok(None);
+        };
 
         let cache: &DaemonKeyCache = self.state.daemon_key_cache.borrow();
-        cache.insert(*daemon_uuid, Arc::new(res.node_public_key.into_bytes()));
-        Ok(cache.get(daemon_uuid).ok_or("key should be in cache")?.clone())
+        cache.insert(*daemon_uuid, Arc::new(key.into_bytes()));
+        Ok(Some(cache.get(daemon_uuid).ok_or("key should be in cache")?.clone()))
     }
 
     async fn handle_auth(&self, auth_packet: DSAuthPacket, addr: SocketAddr) -> Result<(), String> {
         let uuid = Uuid::parse_str(&auth_packet.daemon_uuid).map_err(|_| "Could not parse UUID")?;
-        let key = self.query_user_public_key(&uuid).await?;
 
-        self.state.send_daemon_handshake_request(addr, uuid, key).await
+        let Some(key) = self.query_user_public_key(&uuid).await? else {
+            // the node was removed, or never existed, so the daemon shouldn't keep reconnecting
+            self.state.disconnect_daemon(addr, CloseReason::Revoked)?;
+            return Err(format!("Node with UUID {} does not exist", uuid));
+        };
+        let mut rejection = quotas::check_daemon(uuid).await?;
+        let update = versions::check(&auth_packet.version);
+
+        if let Some(update) = update.as_ref() {
+            warn!("Daemon {} runs unsupported version \"{}\"", uuid, update.version);
+
+            if update.refused && rejection.is_none() {
+                rejection = Some(format!("Daemon version \"{}\" is not supported, update the daemon", update.version));
+            }
+        }
+
+        self.state.send_daemon_handshake_request(addr, uuid, key, auth_packet, rejection, update).await
     }
 
     async fn handle_handshake_response(&self, handshake_reponse_packet: DSHandshakeResponsePacket, addr: SocketAddr) -> Result<(), String> {
@@ -53,7 +66,15 @@ impl DaemonServer {
 
         info!("Authenticated");
 
-        self.state.send_init_data(addr).await?;
+        let uuid = self.state.daemon_uuid(&addr)?;
+        tokio::spawn(plugins::daemon_authenticated(uuid));
+
+        // converge the node to the database state after any downtime, without waiting for a sync
+        // from the web client
+        let state = self.state.clone();
+        tokio::spawn(async move {
+            state.reconcile_daemon(addr).await;
+        });
 
         Ok(())
     }
@@ -61,7 +82,79 @@ impl DaemonServer {
     async fn handle_event(&self, event_packet: DSEventPacket, addr: SocketAddr) -> Result<(), String> {
         // debug!("Event: {:#?}", event_packet);
 
-        self.state.send_event_from_daemon(&addr, event_packet.data).await
+        self.state.send_event_from_daemon(&addr, event_packet).await
+    }
+
+    async fn handle_error(&self, error_packet: DSErrorPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_daemon_error(&addr, error_packet)
+    }
+
+    async fn handle_query_logs_response(&self, query_logs_response_packet: DSQueryLogsResponsePacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_logs_response(&addr, query_logs_response_packet)
+    }
+
+    async fn handle_query_stats_response(&self, query_stats_response_packet: DSQueryStatsResponsePacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_stats_response(&addr, query_stats_response_packet)
+    }
+
+    async fn handle_query_top_response(&self, query_top_response_packet: DSQueryTopResponsePacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_top_response(&addr, query_top_response_packet)
+    }
+
+    async fn handle_query_tasks_response(&self, query_tasks_response_packet: DSQueryTasksResponsePacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_tasks_response(&addr, query_tasks_response_packet)
+    }
+
+    async fn handle_query_usage_response(&self, query_usage_response_packet: DSQueryUsageResponsePacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_usage_response(&addr, query_usage_response_packet)
+    }
+
+    async fn handle_fetch_build_context(&self, fetch_build_context_packet: DSFetchBuildContextPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_build_context(addr, fetch_build_context_packet).await
+    }
+
+    async fn handle_sync_progress(&self, sync_progress_packet: DSSyncProgressPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_sync_progress(&addr, sync_progress_packet)
+    }
+
+    async fn handle_command_result(&self, command_result_packet: DSCommandResultPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_command_result(&addr, command_result_packet)
+    }
+
+    async fn handle_terminal_output(&self, terminal_output_packet: DSTerminalOutputPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_terminal_output(&addr, terminal_output_packet)
+    }
+
+    async fn handle_terminal_close(&self, terminal_close_packet: DSTerminalClosePacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_terminal_close(&addr, terminal_close_packet)
+    }
+
+    async fn handle_ack(&self, packet: Packet, addr: SocketAddr) -> Result<(), String> {
+        let (request_id, res) = ack::outcome(packet)?;
+
+        self.state.receive_ack(&addr, request_id, res)
+    }
+
+    async fn handle_pong(&self, pong_packet: PongPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.receive_pong(&addr, pong_packet)
+    }
+
+    async fn handle_sync_result(&self, sync_result_packet: DSSyncResultPacket, addr: SocketAddr) -> Result<(), String> {
+        let uuid = self.state.authenticated_daemon_uuid(&addr).ok_or("Daemon hasn't authenticated")?;
+
+        if sync_result_packet.resync {
+            info!("Daemon could not apply delta sync, sending full sync");
+            return self.state.full_sync_daemon(uuid, sync_result_packet.request).await;
+        }
+
+        for server in sync_result_packet.servers {
+            // the cached spec must not diverge from the repository, which keeps the old ports
+            repository::get().writable().map_err(|e| format!("{}, not storing ports assigned to server {}", e, server.id))?;
+            self.state.assign_sync_ports(&uuid, server.id, &server.ports)?;
+            repository::get().assign_ports(uuid, server.id, &server.ports).await?;
+        }
+
+        Ok(())
     }
 }
 
@@ -75,16 +168,20 @@ impl Server for DaemonServer {
         &CONFIG.sockets.daemon
     }
 
+    fn uses_tls(&self) -> bool {
+        CONFIG.tls.daemon
+    }
+
     fn get_decrypter(&self) -> &'static RsaesJweDecrypter {
         &DECRYPTER
     }
 
-    fn get_issuer(&self) -> &'static str {
-        "aesterisk/daemon"
+    fn get_issuers(&self) -> &'static [String] {
+        &CONFIG.issuers.daemon
     }
 
-    async fn on_accept(&self, addr: SocketAddr, tx: Tx) -> Result<(), String> {
-        self.state.add_daemon(addr, tx);
+    async fn on_accept(&self, addr: SocketAddr, tx: Tx, encoding: Encoding) -> Result<(), String> {
+        self.state.add_daemon(addr, tx, encoding);
 
         Ok(())
     }
@@ -93,21 +190,90 @@ impl Server for DaemonServer {
         self.state.remove_daemon(addr).await
     }
 
+    async fn on_session_end(&self, addr: SocketAddr, session: Session) -> Result<(), String> {
+        // daemons that never authenticated can't be attributed to a node, as anyone can claim its
+        // UUID in the auth packet
+        match self.state.authenticated_daemon_uuid(&addr) {
+            Some(uuid) => sessions::record(Peer::Daemon(uuid), addr, session).await,
+            None => Ok(()),
+        }
+    }
+
+    fn is_authenticated(&self, addr: &SocketAddr) -> bool {
+        self.state.authenticated_daemon_uuid(addr).is_some()
+    }
+
     async fn on_decrypt_error(&self, addr: SocketAddr) -> Result<(), String> {
-        self.state.disconnect_daemon(addr)
+        self.state.disconnect_daemon(addr, CloseReason::ProtocolError)
+    }
+
+    async fn on_handled(&self, addr: SocketAddr, request_id: u64, res: &Result<(), String>) -> Result<(), String> {
+        self.state.ack_daemon(&addr, request_id, res)
+    }
+
+    async fn on_heartbeat(&self, addr: SocketAddr) -> Result<(), String> {
+        self.state.ping_daemon(&addr)
     }
 
     #[instrument("daemon", skip(self, packet))]
     async fn on_packet(&self, packet: Packet, addr: SocketAddr) -> Result<(), String> {
+        // anyone can claim the UUID of a node in the auth packet, so nothing else is handled until
+        // the daemon has answered the challenge
+        if !matches!(packet.id, ID::DSAuth | ID::DSHandshakeResponse) && self.state.authenticated_daemon_uuid(&addr).is_none() {
+            return Err(format!("Daemon hasn't authenticated, not handling {:?}", packet.id));
+        }
+
         match packet.id {
             ID::DSAuth => {
-                self.handle_auth(DSAuthPacket::parse(packet).ok_or("Could not parse DSAuthPacket")?, addr).await
+                self.handle_auth(packet.payload()?, addr).await
             },
             ID::DSHandshakeResponse => {
-                self.handle_handshake_response(DSHandshakeResponsePacket::parse(packet).ok_or("Could not parse DSHandshakeResponsePacket")?, addr).await
+                self.handle_handshake_response(packet.payload()?, addr).await
             }
             ID::DSEvent => {
-                self.handle_event(DSEventPacket::parse(packet).ok_or("Could not parse DSEventPacket")?, addr).await
+                self.handle_event(packet.payload()?, addr).await
+            },
+            ID::DSSyncResult => {
+                self.handle_sync_result(packet.payload()?, addr).await
+            },
+            ID::DSSyncProgress => {
+                self.handle_sync_progress(packet.payload()?, addr).await
+            },
+            ID::DSQueryLogsResponse => {
+                self.handle_query_logs_response(packet.payload()?, addr).await
+            },
+            ID::DSQueryStatsResponse => {
+                self.handle_query_stats_response(packet.payload()?, addr).await
+            },
+            ID::DSQueryTopResponse => {
+                self.handle_query_top_response(packet.payload()?, addr).await
+            },
+            ID::DSQueryTasksResponse => {
+                self.handle_query_tasks_response(packet.payload()?, addr).await
+            },
+            ID::DSQueryUsageResponse => {
+                self.handle_query_usage_response(packet.payload()?, addr).await
+            },
+            ID::DSFetchBuildContext => {
+                self.handle_fetch_build_context(packet.payload()?, addr).await
+            },
+            ID::DSError => {
+                self.handle_error(packet.payload()?, addr).await
+            },
+            ID::DSCommandResult => {
+                self.handle_command_result(packet.payload()?, addr).await
+            },
+            ID::DSTerminalOutput => {
+                self.handle_terminal_output(packet.payload()?, addr).await
+            },
+            ID::DSTerminalClose => {
+                self.handle_terminal_close(packet.payload()?, addr).await
+            },
+            ID::Ack | ID::Nack => {
+                self.handle_ack(packet, addr).await
+            },
+            ID::Pong => {
+                self.handle_pong(packet.payload()?, addr).await
             },
             _ => {
                 Err(format!("Should not receive [SW]* packet: {:?}", packet.id))
@@ -1,21 +1,124 @@
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use std::{str::FromStr, sync::atomic::{AtomicBool, Ordering}, time::{Duration, Instant}};
+
+use sqlx::{postgres::PgPoolOptions, sqlite::{SqliteConnectOptions, SqlitePoolOptions}, PgPool, SqlitePool};
 use tokio::sync::OnceCell;
+use tracing::warn;
+
+use crate::{config::{DatabaseBackend, CONFIG}, standalone};
 
 static DB_POOL: OnceCell<PgPool> = OnceCell::const_new();
+static SQLITE_POOL: OnceCell<SqlitePool> = OnceCell::const_new();
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
 
-/// Initialise the database connection pool.
+/// Initialise the database connection pool of the configured backend. SQLite databases are
+/// created and migrated if needed.
 pub async fn init() -> Result<(), String> {
+    let url = std::env::var("DATABASE_URL").map_err(|_| "DATABASE_URL should be set")?;
+
+    if CONFIG.database.backend == DatabaseBackend::Sqlite {
+        let options = SqliteConnectOptions::from_str(&url).map_err(|e| format!("SQLx error: {}", e))?
+            .create_if_missing(true)
+            .foreign_keys(true);
+
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .connect_with(options)
+            .await
+            .map_err(|e| format!("SQLx error: {}", e))?;
+
+        sqlx::migrate!("../migrations/sqlite").run(&pool).await.map_err(|e| format!("Could not migrate database: {}", e))?;
+
+        SQLITE_POOL.set(pool).map_err(|_| "Database pool already initialised")?;
+        return Ok(());
+    }
+
     let pool = PgPoolOptions::new()
         .min_connections(1)
         .max_connections(1)
-        .connect(&std::env::var("DATABASE_URL").map_err(|_| "DATABASE_URL should be set")?)
+        .connect(&url)
         .await
         .map_err(|e| format!("SQLx error: {}", e))?;
     DB_POOL.set(pool).map_err(|_| "Database pool already initialised")?;
     Ok(())
 }
 
+/// Returns whether the server stores its data in Postgres, rather than SQLite or the spec files of
+/// standalone mode.
+pub fn postgres() -> bool {
+    !standalone::enabled() && CONFIG.database.backend == DatabaseBackend::Postgres
+}
+
 /// Get the database connection pool.
 pub fn get() -> Result<&'static PgPool, &'static str> {
+    if CONFIG.database.backend == DatabaseBackend::Sqlite {
+        return Err("Not supported by the sqlite database backend");
+    }
+
     DB_POOL.get().ok_or("Database pool not initialised")
 }
+
+/// Get the connection pool of the SQLite database.
+pub fn sqlite() -> Result<&'static SqlitePool, &'static str> {
+    SQLITE_POOL.get().ok_or("Database pool not initialised")
+}
+
+/// Initialises the SQLite connection pool with a migrated in-memory database, for tests of the
+/// SQLite repository. The database lives as long as the single connection of the pool.
+#[cfg(test)]
+pub async fn sqlite_memory() -> &'static SqlitePool {
+    SQLITE_POOL.get_or_init(|| async {
+        let options = SqliteConnectOptions::from_str("sqlite::memory:").unwrap().foreign_keys(true);
+
+        let pool = SqlitePoolOptions::new()
+            .min_connections(1)
+            .max_connections(1)
+            .idle_timeout(None)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await
+            .unwrap();
+
+        sqlx::migrate!("../migrations/sqlite").run(&pool).await.unwrap();
+
+        pool
+    }).await
+}
+
+/// Returns whether the server is in read-only mode, in which nothing is written to the database and
+/// mutating requests are rejected.
+pub fn read_only() -> bool {
+    READ_ONLY.load(Ordering::Relaxed)
+}
+
+/// Puts the server into (or out of) read-only mode.
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::Relaxed);
+}
+
+/// Returns an error if the server is in read-only mode.
+pub fn writable() -> Result<(), String> {
+    if read_only() {
+        return Err("Server is in read-only mode".to_string());
+    }
+
+    Ok(())
+}
+
+/// Runs a query, failing it if it takes longer than the configured query timeout. Queries taking
+/// longer than the slow query threshold are logged with their name and duration.
+pub async fn timed<T>(name: &str, query: impl Future<Output = Result<T, sqlx::Error>>) -> Result<T, String> {
+    let start = Instant::now();
+
+    let res = tokio::time::timeout(Duration::from_secs(CONFIG.database.query_timeout), query).await;
+    let duration = start.elapsed();
+
+    if duration >= Duration::from_millis(CONFIG.database.slow_query_threshold) {
+        warn!("Slow query {} took {}ms", name, duration.as_millis());
+    }
+
+    match res {
+        Ok(res) => res.map_err(|e| format!("{} failed: {}", name, e)),
+        Err(_) => Err(format!("{} timed out after {}s", name, CONFIG.database.query_timeout)),
+    }
+}
@@ -0,0 +1,65 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use packet::debug::{self, Source};
+use tokio::net::TcpListener;
+use tracing::{debug, error, warn};
+
+use crate::{config::CONFIG, state::State, telemetry};
+
+/// jemalloc with sampled heap profiles, served as `/debug/pprof/heap`.
+#[global_allocator]
+static ALLOCATOR: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+#[unsafe(export_name = "_rjem_malloc_conf")]
+static MALLOC_CONF: &[u8; 45] = b"prof:true,prof_active:true,lg_prof_sample:19\0";
+
+struct Server {
+    state: Arc<State>,
+}
+
+impl Source for Server {
+    async fn state(&self) -> serde_json::Value {
+        let maps = self.state.map_sizes().into_iter().map(|(name, size)| (name.to_string(), serde_json::Value::from(size))).collect::<serde_json::Map<_, _>>();
+
+        serde_json::json!({
+            "maps": maps,
+            "listen_quota_exceeded": telemetry::LISTEN_QUOTA_EXCEEDED.load(Ordering::Relaxed),
+        })
+    }
+
+    fn config(&self) -> Result<serde_json::Value, String> {
+        serde_json::to_value(&*CONFIG).map_err(|e| format!("could not serialize config: {}", e))
+    }
+}
+
+/// Serves the debug endpoint on `debug.bind`, which dumps the sizes of the state maps, the counters
+/// and the configuration of the server, and records CPU and heap profiles, see
+/// `packet::debug::respond`.
+pub async fn run(state: Arc<State>) {
+    let listener = match TcpListener::bind(&CONFIG.debug.bind).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            error!("Could not bind debug endpoint to {}: {}", CONFIG.debug.bind, e);
+            return;
+        },
+    };
+
+    warn!("Serving debug endpoint on http://{}/debug", CONFIG.debug.bind);
+
+    let server = Arc::new(Server { state });
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let server = Arc::clone(&server);
+
+                tokio::spawn(async move {
+                    if let Err(e) = debug::respond(stream, server.as_ref()).await {
+                        debug!("Could not serve debug request: {}", e);
+                    }
+                });
+            },
+            Err(e) => warn!("Could not accept debug connection: {}", e),
+        }
+    }
+}
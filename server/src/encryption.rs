@@ -1,9 +1,9 @@
-use std::time::{Duration, SystemTime};
+use std::time::SystemTime;
 
-use josekit::{jwe::{alg::rsaes::{RsaesJweDecrypter, RsaesJweEncrypter}, JweHeader}, jwk::alg::rsa::RsaKeyPair, jwt::{self, JwtPayload, JwtPayloadValidator}, Map, Value};
+use josekit::{jwe::{self, alg::rsaes::{RsaesJweDecrypter, RsaesJweEncrypter}, JweHeader}, jwk::alg::rsa::RsaKeyPair, jwt::{self, JwtPayload, JwtPayloadValidator}, Map, Value};
 use lazy_static::lazy_static;
 
-use packet::Packet;
+use packet::{envelope::{self, Envelope}, subprotocol::Encoding, Packet};
 
 use crate::config::CONFIG;
 
@@ -18,45 +18,77 @@ fn read_key(file: &str) -> josekit::jwk::Jwk {
     key.to_jwk_private_key()
 }
 
-/// Encrypt a packet using the given encrypter
-pub fn encrypt_packet(packet: Packet, encrypter: &RsaesJweEncrypter) -> Result<String, String> {
+/// Encrypt a packet using the given encrypter, serialized in the given encoding
+pub fn encrypt_packet(packet: Packet, encrypter: &RsaesJweEncrypter, encoding: Encoding) -> Result<String, String> {
     let mut header = JweHeader::new();
-    header.set_token_type("JWT");
     header.set_algorithm("RSA-OAEP");
     header.set_content_encryption("A256GCM");
 
-    let mut payload = JwtPayload::new();
-    payload.set_claim("p", Some(serde_json::to_value(packet).map_err(|_| "Packet should be serializable")?)).map_err(|_| "Could not set payload claim")?;
-    payload.set_issuer("aesterisk/server");
-    payload.set_issued_at(&SystemTime::now());
-    payload.set_expires_at(&SystemTime::now().checked_add(Duration::from_secs(60)).ok_or("Duration overflow")?);
+    match encoding {
+        Encoding::Json => {
+            header.set_token_type("JWT");
 
-    Ok(jwt::encode_with_encrypter(&payload, &header, encrypter).map_err(|_| "Could not encrypt packet")?)
+            let mut payload = JwtPayload::new();
+            payload.set_claim("p", Some(serde_json::to_value(packet).map_err(|_| "Packet should be serializable")?)).map_err(|_| "Could not set payload claim")?;
+            payload.set_issuer(&CONFIG.issuers.server);
+            payload.set_issued_at(&SystemTime::now());
+            payload.set_expires_at(&SystemTime::now().checked_add(envelope::LIFETIME).ok_or("Duration overflow")?);
+
+            Ok(jwt::encode_with_encrypter(&payload, &header, encrypter).map_err(|_| "Could not encrypt packet")?)
+        },
+        Encoding::MessagePack => {
+            header.set_content_type(envelope::CONTENT_TYPE);
+
+            let envelope = Envelope::new(packet, &CONFIG.issuers.server)?;
+
+            Ok(jwe::serialize_compact(&envelope.to_bytes()?, &header, encrypter).map_err(|_| "Could not encrypt packet")?)
+        },
+    }
 }
 
-/// Decrypt a packet using the given decrypter
-pub async fn decrypt_packet(msg: &str, decrypter: &RsaesJweDecrypter, issuer: &str, on_err: Option<impl AsyncFnOnce() -> Result<(), String>>) -> Result<Packet, String> {
-    let (payload, _) = jwt::decode_with_decrypter(msg, decrypter).map_err(|_| "Could not decrypt message")?;
+/// Decrypt a packet using the given decrypter, accepting it only if it was issued by one of
+/// `issuers`. Packets are decoded according to the content type of their JWE header, so either
+/// encoding is accepted.
+pub async fn decrypt_packet(msg: &str, decrypter: &RsaesJweDecrypter, issuers: &[String], on_err: Option<impl AsyncFnOnce() -> Result<(), String>>) -> Result<Packet, String> {
+    let (plaintext, header) = jwe::deserialize_compact(msg, decrypter).map_err(|_| "Could not decrypt message")?;
+
+    if header.content_type() == Some(envelope::CONTENT_TYPE) {
+        let envelope = Envelope::from_bytes(&plaintext)?;
+        reject_invalid(envelope.validate(issuers), on_err).await?;
+
+        return Ok(envelope.packet);
+    }
+
+    let payload = serde_json::from_slice::<Map<String, Value>>(&plaintext).ok().and_then(|payload| JwtPayload::from_map(payload).ok()).ok_or("Could not parse message")?;
 
     let mut validator = JwtPayloadValidator::new();
-    validator.set_issuer(issuer);
     validator.set_base_time(SystemTime::now());
-    validator.set_min_issued_time(SystemTime::now() - Duration::from_secs(60));
+    validator.set_min_issued_time(SystemTime::now() - envelope::LIFETIME);
     validator.set_max_issued_time(SystemTime::now());
 
-    match validator.validate(&payload) {
-        Ok(()) => (),
-        Err(e) => {
-            if on_err.is_some() {
-                on_err.unwrap()().await?;
-            }
+    let res = validator.validate(&payload).map_err(|e| e.to_string()).and_then(|_| match payload.issuer() {
+        Some(issuer) if issuers.iter().any(|accepted| accepted == issuer) => Ok(()),
+        Some(issuer) => Err(format!("issuer {} is not accepted", issuer)),
+        None => Err("missing issuer".to_string()),
+    });
 
-            return Err(format!("Invalid token: {}", e));
-        }
-    }
+    reject_invalid(res, on_err).await?;
 
     let payload: Map<String, Value> = payload.into();
     let try_packet = Packet::from_value(payload.into_iter().find_map(|(k, v)| if k == "p" { Some(v) } else { None }).ok_or("No payload found in packet")?);
 
     try_packet.ok_or(format!("Could not parse packet: \"{}\"", msg))
 }
+
+/// Calls `on_err` if the claims of a packet are invalid.
+async fn reject_invalid(res: Result<(), String>, on_err: Option<impl AsyncFnOnce() -> Result<(), String>>) -> Result<(), String> {
+    if let Err(e) = res {
+        if let Some(on_err) = on_err {
+            on_err().await?;
+        }
+
+        return Err(format!("Invalid token: {}", e));
+    }
+
+    Ok(())
+}
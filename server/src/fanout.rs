@@ -0,0 +1,89 @@
+use std::sync::{Arc, Weak};
+
+use packet::events::EventData;
+use sqlx::types::Uuid;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, warn};
+
+use crate::{config::CONFIG, state::State};
+
+/// An event of a daemon waiting to be delivered.
+struct Job {
+    daemon: Uuid,
+    event: EventData,
+    seq: Option<u64>,
+    timestamp: Option<u64>,
+    /// Told the result once the event was delivered, if the submitter waits for it
+    delivered: Option<oneshot::Sender<Result<(), String>>>,
+}
+
+/// `Fanout` is a pool of workers delivering events to the web clients listening. Every daemon
+/// belongs to a single shard, whose worker delivers all of its events, so they reach web clients in
+/// the order they were submitted, and the workers of different shards rarely contend for the same
+/// `DashMap` shards.
+pub struct Fanout {
+    shards: Vec<mpsc::Sender<Job>>,
+}
+
+impl Fanout {
+    /// Starts a worker for each of the `fanout.shards` shards, each queueing up to `fanout.queue`
+    /// events. The workers stop once the state is dropped.
+    pub fn start(state: &Arc<State>) -> Self {
+        let shards = (0..CONFIG.fanout.shards()).map(|shard| {
+            let (tx, rx) = mpsc::channel(CONFIG.fanout.queue);
+            tokio::spawn(work(shard, Arc::downgrade(state), rx));
+            tx
+        }).collect::<Vec<_>>();
+
+        debug!("Started {} fan-out workers", shards.len());
+
+        Self {
+            shards,
+        }
+    }
+
+    /// Queues an event of a daemon on the worker of its shard, waiting while its queue is full.
+    pub async fn submit(&self, daemon: Uuid, event: EventData, seq: Option<u64>, timestamp: Option<u64>) -> Result<(), String> {
+        self.queue(daemon, event, seq, timestamp, None).await
+    }
+
+    /// Queues an event of a daemon on the worker of its shard, and waits until it was delivered.
+    pub async fn deliver(&self, daemon: Uuid, event: EventData, seq: Option<u64>, timestamp: Option<u64>) -> Result<(), String> {
+        let (tx, rx) = oneshot::channel();
+
+        self.queue(daemon, event, seq, timestamp, Some(tx)).await?;
+
+        rx.await.map_err(|_| "Fan-out worker stopped before delivering the event".to_string())?
+    }
+
+    async fn queue(&self, daemon: Uuid, event: EventData, seq: Option<u64>, timestamp: Option<u64>, delivered: Option<oneshot::Sender<Result<(), String>>>) -> Result<(), String> {
+        let shard = (daemon.as_u128() % self.shards.len() as u128) as usize;
+
+        self.shards[shard].send(Job {
+            daemon,
+            event,
+            seq,
+            timestamp,
+            delivered,
+        }).await.map_err(|_| format!("Fan-out worker {} has stopped", shard))
+    }
+}
+
+async fn work(shard: usize, state: Weak<State>, mut rx: mpsc::Receiver<Job>) {
+    while let Some(job) = rx.recv().await {
+        let Some(state) = state.upgrade() else {
+            break;
+        };
+
+        let res = state.process_event(&job.daemon, job.event, job.seq, job.timestamp).await;
+
+        match job.delivered {
+            Some(delivered) => {
+                let _ = delivered.send(res);
+            },
+            None => if let Err(e) = res {
+                warn!("Fan-out worker {} could not deliver event of daemon {}: {}", shard, job.daemon, e);
+            },
+        }
+    }
+}
@@ -0,0 +1,21 @@
+use std::{sync::Arc, time::Duration};
+
+use tracing::warn;
+
+use crate::state::State;
+
+/// How often fleet summaries are sent to web clients.
+const INTERVAL: Duration = Duration::from_secs(5);
+
+/// Periodically sends fleet summaries to all web clients listening for them.
+pub async fn run(state: Arc<State>) {
+    let mut interval = tokio::time::interval(INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if let Err(e) = state.send_fleet_summaries() {
+            warn!("Could not send fleet summaries: {}", e);
+        }
+    }
+}
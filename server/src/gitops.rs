@@ -0,0 +1,82 @@
+use std::{path::Path, sync::Arc, time::Duration};
+
+use tokio::process::Command;
+use tracing::{info, warn};
+
+use crate::{config::CONFIG, db, import, spec::{Spec, SpecFormat}, state::State};
+
+async fn git(args: &[&str]) -> Result<String, String> {
+    let output = Command::new("git").args(args).output().await.map_err(|e| format!("Could not run git: {}", e))?;
+
+    if !output.status.success() {
+        return Err(format!("git {} failed: {}", args.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Checks out the latest commit of the configured branch, cloning the repository first if needed,
+/// and returns its hash.
+async fn fetch() -> Result<String, String> {
+    let config = &CONFIG.gitops;
+
+    if Path::new(&config.folder).join(".git").exists() {
+        git(&["-C", &config.folder, "fetch", "--depth", "1", "origin", &config.branch]).await?;
+        git(&["-C", &config.folder, "reset", "--hard", "FETCH_HEAD"]).await?;
+    } else {
+        git(&["clone", "--depth", "1", "--branch", &config.branch, &config.repository, &config.folder]).await?;
+    }
+
+    git(&["-C", &config.folder, "rev-parse", "HEAD"]).await
+}
+
+/// Imports the spec of the given commit.
+async fn import(state: &State, commit: &str) -> Result<(), String> {
+    let path = Path::new(&CONFIG.gitops.folder).join(&CONFIG.gitops.path);
+    let contents = tokio::fs::read_to_string(&path).await.map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    let spec = Spec::parse(&contents, SpecFormat::from_path(&path))?;
+
+    let changes = import::import(state, CONFIG.gitops.team, &spec, CONFIG.gitops.prune, false).await?;
+
+    for change in changes.iter() {
+        info!("Applied {} from commit {}", change, commit);
+    }
+
+    Ok(())
+}
+
+/// Periodically imports the spec of the configured Git repository whenever a new commit is pushed,
+/// if enabled. Commits that fail to import are retried on the next check.
+pub async fn run(state: Arc<State>) {
+    if !CONFIG.gitops.enabled {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(CONFIG.gitops.interval));
+    let mut imported = None;
+
+    loop {
+        interval.tick().await;
+
+        if db::read_only() {
+            continue;
+        }
+
+        let commit = match fetch().await {
+            Ok(commit) => commit,
+            Err(e) => {
+                warn!("Could not fetch spec repository: {}", e);
+                continue;
+            }
+        };
+
+        if imported.as_ref() == Some(&commit) {
+            continue;
+        }
+
+        match import(&state, &commit).await {
+            Ok(()) => imported = Some(commit),
+            Err(e) => warn!("Could not import spec from commit {}: {}", commit, e),
+        }
+    }
+}
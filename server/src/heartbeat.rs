@@ -0,0 +1,27 @@
+use std::{sync::Arc, time::Duration};
+
+use tracing::info;
+
+use crate::{config::CONFIG, state::State};
+
+/// Periodically disconnects connections that left the last `sockets.missed_heartbeats` pings
+/// unanswered, see `State::reap_stale`.
+pub async fn run(state: Arc<State>) {
+    if CONFIG.sockets.heartbeat_interval == 0 {
+        return;
+    }
+
+    let interval = Duration::from_secs(CONFIG.sockets.heartbeat_interval);
+    let timeout = interval * CONFIG.sockets.missed_heartbeats;
+    let mut interval = tokio::time::interval(interval);
+
+    loop {
+        interval.tick().await;
+
+        let reaped = state.reap_stale(timeout);
+
+        if reaped > 0 {
+            info!("Disconnected {} connections that missed {} heartbeats", reaped, CONFIG.sockets.missed_heartbeats);
+        }
+    }
+}
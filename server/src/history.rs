@@ -0,0 +1,156 @@
+use std::{collections::HashMap, mem, sync::{atomic::{AtomicU64, Ordering}, Mutex}, time::Duration};
+
+use packet::events::{EventData, EventType};
+use sqlx::types::Uuid;
+use tracing::warn;
+
+use crate::{config::CONFIG, db, repository, sessions::now};
+
+/// How often events past their retention are deleted.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How often recorded events are written to the database.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+/// How many recorded events may wait to be written. Events recorded while as many are waiting are
+/// dropped, so a slow database can't make them pile up.
+const MAX_PENDING: usize = 10_000;
+/// How many events are written with a single query.
+const BATCH_SIZE: usize = 1_000;
+
+/// Events recorded since the last flush, oldest first
+static PENDING: Mutex<Vec<NewEvent>> = Mutex::new(Vec::new());
+/// Events dropped since the last flush, as too many were waiting
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+
+/// An event stored in the history, with the sequence number and time the daemon sent it with.
+pub struct StoredEvent {
+    pub event: EventData,
+    pub seq: Option<u64>,
+    pub timestamp: u64,
+}
+
+impl StoredEvent {
+    /// Parses an event as stored by `Repository::store_events`.
+    pub fn from_db(seq: Option<i64>, timestamp: i64, data: &str) -> Result<Self, String> {
+        Ok(Self {
+            event: serde_json::from_str(data).map_err(|e| format!("Could not parse stored event: {}", e))?,
+            seq: seq.map(|seq| seq as u64),
+            timestamp: timestamp as u64,
+        })
+    }
+}
+
+/// A recorded event waiting to be stored, `data` being its JSON.
+pub struct NewEvent {
+    pub uuid: Uuid,
+    pub event_type: i16,
+    pub seq: Option<u64>,
+    pub timestamp: u64,
+    pub data: String,
+}
+
+/// Returns the history ID of an event type, or `None` if events of the type aren't stored.
+fn type_to_db(event_type: EventType) -> Option<i16> {
+    match event_type {
+        EventType::NodeStatus => Some(0),
+        EventType::ServerStatus => Some(1),
+        _ => None,
+    }
+}
+
+/// Queues a status event of a daemon to be stored with the next batch, so neither the delivery to
+/// web clients nor the database is held up by one insert per event. Other events aren't stored.
+pub fn record(uuid: Uuid, event: &EventData, seq: Option<u64>, timestamp: Option<u64>) {
+    let Some(event_type) = type_to_db(event.event_type()) else {
+        return;
+    };
+
+    if !CONFIG.history.enabled || db::read_only() {
+        return;
+    }
+
+    let data = match serde_json::to_string(event) {
+        Ok(data) => data,
+        Err(e) => {
+            warn!("Could not serialize event for the history: {}", e);
+            return;
+        },
+    };
+
+    let mut pending = PENDING.lock().expect("history queue poisoned");
+
+    if pending.len() >= MAX_PENDING {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+        return;
+    }
+
+    pending.push(NewEvent {
+        uuid,
+        event_type,
+        seq,
+        timestamp: timestamp.unwrap_or_else(now),
+        data,
+    });
+}
+
+/// Writes the queued events in batches of `BATCH_SIZE`. Events of a batch that fails are lost, as
+/// retrying them would only make more pile up.
+async fn flush() {
+    let dropped = DROPPED.swap(0, Ordering::Relaxed);
+    if dropped > 0 {
+        warn!("Dropped {} events, as {} were waiting to be stored", dropped, MAX_PENDING);
+    }
+
+    let pending = mem::take(&mut *PENDING.lock().expect("history queue poisoned"));
+
+    for batch in pending.chunks(BATCH_SIZE) {
+        if let Err(e) = repository::get().store_events(batch).await {
+            warn!("Could not store {} events: {}", batch.len(), e);
+        }
+    }
+}
+
+/// Returns the latest `limit` stored events of a type of each of the given daemons, oldest first.
+/// Daemons without stored events are left out.
+pub async fn fetch(daemons: &[Uuid], event_type: EventType, limit: u32) -> Result<HashMap<Uuid, Vec<StoredEvent>>, String> {
+    let Some(event_type) = type_to_db(event_type) else {
+        return Ok(HashMap::new());
+    };
+
+    let mut events = HashMap::<Uuid, Vec<StoredEvent>>::new();
+
+    for (daemon, event) in repository::get().events(daemons, event_type, limit).await?.into_iter() {
+        events.entry(daemon).or_default().push(event);
+    }
+
+    Ok(events)
+}
+
+/// Periodically writes the recorded events and deletes events past their retention. Does nothing
+/// if the history is disabled.
+pub async fn run() {
+    if !CONFIG.history.enabled {
+        return;
+    }
+
+    let mut flush_interval = tokio::time::interval(FLUSH_INTERVAL);
+    let mut retention_interval = tokio::time::interval(RETENTION_INTERVAL);
+
+    loop {
+        tokio::select! {
+            _ = flush_interval.tick() => {
+                if !db::read_only() {
+                    flush().await;
+                }
+            },
+            _ = retention_interval.tick() => {
+                if db::read_only() {
+                    continue;
+                }
+
+                if let Err(e) = repository::get().delete_events(now().saturating_sub(CONFIG.history.retention_minutes * 60)).await {
+                    warn!("Could not delete old events: {}", e);
+                }
+            },
+        }
+    }
+}
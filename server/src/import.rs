@@ -0,0 +1,439 @@
+use std::{collections::{HashMap, HashSet}, fmt::{Display, Formatter}};
+
+use sqlx::types::Uuid;
+use tracing::{info, warn};
+
+use crate::{quotas, repository, spec::{NetworkSpec, NodeState, ServerSpec, Spec, TagSpec, TagState}, state::State};
+
+/// A change to the database needed to apply a spec.
+#[derive(Debug, Clone)]
+pub enum Change {
+    CreateTag { tag: TagSpec },
+    UpdateTag { id: i32, tag: TagSpec },
+    DeleteServer { node: Uuid, id: i32, name: String },
+    DeleteNetwork { node: Uuid, id: i32, name: String },
+    CreateNetwork { node: Uuid, node_id: i32, network: NetworkSpec },
+    UpdateNetwork { node: Uuid, id: i32, network: NetworkSpec },
+    CreateServer { node: Uuid, node_id: i32, server: ServerSpec },
+    UpdateServer { node: Uuid, id: i32, server: ServerSpec },
+}
+
+impl Change {
+    /// Returns the node whose spec is changed, or `None` for tag changes, which only affect the
+    /// nodes of servers using the tag.
+    pub fn node(&self) -> Option<Uuid> {
+        match self {
+            Change::CreateTag { .. } | Change::UpdateTag { .. } => None,
+            Change::DeleteServer { node, .. }
+            | Change::DeleteNetwork { node, .. }
+            | Change::CreateNetwork { node, .. }
+            | Change::UpdateNetwork { node, .. }
+            | Change::CreateServer { node, .. }
+            | Change::UpdateServer { node, .. } => Some(*node),
+        }
+    }
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Change::CreateTag { tag } => write!(f, "create tag {} ({}:{})", tag.name, tag.image, tag.docker_tag),
+            Change::UpdateTag { tag, .. } => write!(f, "update tag {} ({}:{})", tag.name, tag.image, tag.docker_tag),
+            Change::DeleteServer { node, name, .. } => write!(f, "delete server {} on {}", name, node),
+            Change::DeleteNetwork { node, name, .. } => write!(f, "delete network {} on {}", name, node),
+            Change::CreateNetwork { node, network, .. } => write!(f, "create network {} on {}", network.name, node),
+            Change::UpdateNetwork { node, network, .. } => write!(f, "update network {} on {}", network.name, node),
+            Change::CreateServer { node, server, .. } => write!(f, "create server {} on {}", server.name, node),
+            Change::UpdateServer { node, server, .. } => write!(f, "update server {} on {}", server.name, node),
+        }
+    }
+}
+
+/// The changes needed to apply a spec, in the order they have to be applied in, along with the
+/// team the spec is imported for and the IDs of the existing tags and networks the changes refer
+/// to by name.
+#[derive(Debug, Default)]
+pub struct Plan {
+    pub changes: Vec<Change>,
+    pub team: u32,
+    pub tags: HashMap<String, i32>,
+    pub networks: HashMap<(i32, String), i32>,
+}
+
+/// Diffs a spec against the database for a team, whose nodes the spec's nodes have to be.
+/// Networks and servers of the spec's nodes that are not in the spec are only deleted if `prune`
+/// is set, nodes that are not in the spec are never touched.
+pub async fn plan(team: u32, spec: &Spec, prune: bool) -> Result<Plan, String> {
+    let names = spec.tags.iter().map(|tag| tag.name.clone())
+        .chain(spec.nodes.iter().flat_map(|node| node.servers.iter().map(|server| server.tag.clone())))
+        .collect::<HashSet<_>>().into_iter().collect::<Vec<_>>();
+
+    let tags = repository::get().spec_tags(team, &names).await?;
+    let nodes = repository::get().spec_nodes(&spec.nodes.iter().map(|node| node.uuid).collect::<Vec<_>>()).await?;
+
+    diff(team, spec, tags, nodes, prune)
+}
+
+/// Diffs a spec against the tags the team can use and the current state of the spec's nodes.
+/// Tags of the spec only update tags the team owns, shared tags that differ are shadowed by a new
+/// tag of the team instead.
+fn diff(team: u32, spec: &Spec, tags: HashMap<String, TagState>, mut nodes: HashMap<Uuid, NodeState>, prune: bool) -> Result<Plan, String> {
+    if let Some(unsupported) = spec.standalone_only() {
+        return Err(format!("The database can't store {}, which is only supported in standalone mode", unsupported));
+    }
+
+    let mut plan = Plan {
+        team,
+        ..Plan::default()
+    };
+
+    for tag in spec.tags.iter() {
+        match tags.get(&tag.name) {
+            Some(current) if current.tag == *tag => (),
+            Some(current) if current.owned => plan.changes.push(Change::UpdateTag {
+                id: current.id,
+                tag: tag.clone(),
+            }),
+            _ => plan.changes.push(Change::CreateTag {
+                tag: tag.clone(),
+            }),
+        }
+    }
+
+    plan.tags = tags.into_iter().map(|(name, current)| (name, current.id)).collect();
+
+    for node in spec.nodes.iter() {
+        let current = nodes.remove(&node.uuid).ok_or_else(|| format!("Node {} does not exist", node.uuid))?;
+
+        for server in node.servers.iter() {
+            if !plan.tags.contains_key(&server.tag) && !spec.tags.iter().any(|tag| tag.name == server.tag) {
+                return Err(format!("Server {} uses tag {}, which does not exist", server.name, server.tag));
+            }
+        }
+
+        plan.networks.extend(current.networks.iter().map(|(id, network)| ((current.id, network.name.clone()), *id)));
+
+        if prune {
+            for (id, server) in current.servers.iter().filter(|(_, server)| !node.servers.iter().any(|s| s.name == server.name)) {
+                plan.changes.push(Change::DeleteServer {
+                    node: node.uuid,
+                    id: *id,
+                    name: server.name.clone(),
+                });
+            }
+
+            for (id, network) in current.networks.iter().filter(|(_, network)| !node.networks.iter().any(|n| n.name == network.name)) {
+                plan.changes.push(Change::DeleteNetwork {
+                    node: node.uuid,
+                    id: *id,
+                    name: network.name.clone(),
+                });
+            }
+        }
+
+        for network in node.networks.iter() {
+            match current.networks.iter().find(|(_, n)| n.name == network.name) {
+                Some((_, existing)) if existing == network => (),
+                Some((id, _)) => plan.changes.push(Change::UpdateNetwork {
+                    node: node.uuid,
+                    id: *id,
+                    network: network.clone(),
+                }),
+                None => plan.changes.push(Change::CreateNetwork {
+                    node: node.uuid,
+                    node_id: current.id,
+                    network: network.clone(),
+                }),
+            }
+        }
+
+        for server in node.servers.iter() {
+            match current.servers.iter().find(|(_, s)| s.name == server.name) {
+                Some((id, existing)) => {
+                    let mut server = server.clone();
+
+                    // secret envs without a value (as in exports) keep their current value
+                    for key in server.secrets.iter() {
+                        if !server.envs.contains_key(key) {
+                            let value = existing.envs.get(key).ok_or_else(|| format!("Secret env {} of server {} has no value", key, server.name))?;
+                            server.envs.insert(key.clone(), value.clone());
+                        }
+                    }
+
+                    // ports without a mapping keep the host port the daemon assigned
+                    for port in server.ports.iter_mut().filter(|port| port.mapped == 0) {
+                        if let Some(assigned) = existing.ports.iter().find(|p| p.port == port.port && p.protocol == port.protocol) {
+                            port.mapped = assigned.mapped;
+                        }
+                    }
+
+                    if *existing != server {
+                        plan.changes.push(Change::UpdateServer {
+                            node: node.uuid,
+                            id: *id,
+                            server,
+                        });
+                    }
+                },
+                None => {
+                    if let Some(key) = server.secrets.iter().find(|key| !server.envs.contains_key(*key)) {
+                        return Err(format!("Secret env {} of server {} has no value", key, server.name));
+                    }
+
+                    plan.changes.push(Change::CreateServer {
+                        node: node.uuid,
+                        node_id: current.id,
+                        server: server.clone(),
+                    });
+                },
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Diffs a spec against the database for a team and, unless `dry_run` is set, applies the changes
+/// and syncs the affected daemons. Returns the changes.
+pub async fn import(state: &State, team: u32, spec: &Spec, prune: bool, dry_run: bool) -> Result<Vec<Change>, String> {
+    let team_nodes = repository::get().team_nodes(team).await?;
+
+    if let Some(node) = spec.nodes.iter().find(|node| !team_nodes.contains(&node.uuid)) {
+        return Err(format!("Node {} does not belong to team {}", node.uuid, team));
+    }
+
+    let plan = plan(team, spec, prune).await?;
+    let changes = plan.changes.clone();
+
+    // imported servers don't reserve any memory or storage, so only the server quota can be hit
+    let mut added = HashMap::<Uuid, i64>::new();
+    for change in changes.iter() {
+        match change {
+            Change::CreateServer { node, .. } => *added.entry(*node).or_default() += 1,
+            Change::DeleteServer { node, .. } => *added.entry(*node).or_default() -= 1,
+            _ => (),
+        }
+    }
+
+    for (node, servers) in added.into_iter().filter(|(_, servers)| *servers > 0) {
+        if let Some(exceeded) = quotas::check_servers(node, servers, 0, 0).await? {
+            return Err(exceeded);
+        }
+    }
+
+    if dry_run || changes.is_empty() {
+        return Ok(changes);
+    }
+
+    repository::get().writable()?;
+    repository::get().apply(plan).await?;
+
+    info!("Imported spec with {} changes", changes.len());
+
+    state.invalidate_specs();
+
+    // tag changes affect the servers using the tag on any node, the other nodes pick them up on
+    // their next sync
+    let nodes = if changes.iter().any(|change| change.node().is_none()) {
+        spec.nodes.iter().map(|node| node.uuid).collect::<HashSet<_>>()
+    } else {
+        changes.iter().filter_map(Change::node).collect::<HashSet<_>>()
+    };
+
+    for node in nodes {
+        if let Err(e) = state.sync_daemon(node, None, HashMap::new()).await {
+            warn!("Could not sync daemon {} after import: {}", node, e);
+        }
+    }
+
+    Ok(changes)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use crate::spec::{HealthcheckSpec, NodeSpec, PortProtocol, PortSpec, UpdateSpec};
+
+    use super::*;
+
+    const NODE: Uuid = Uuid::from_u128(0x6d3f2b1a_0c4e_4f5a_9b7d_2e8c1a3f4b5d);
+
+    fn tag(name: &str, docker_tag: &str) -> TagSpec {
+        TagSpec {
+            name: name.to_string(),
+            image: "itzg/minecraft-server".to_string(),
+            docker_tag: docker_tag.to_string(),
+            healthcheck: HealthcheckSpec {
+                test: vec!["CMD".to_string(), "mc-health".to_string()],
+                interval: 30,
+                timeout: 5,
+                retries: 3,
+            },
+            mounts: Vec::new(),
+            env_defs: Vec::new(),
+        }
+    }
+
+    fn server(name: &str, tag: &str) -> ServerSpec {
+        ServerSpec {
+            name: name.to_string(),
+            tag: tag.to_string(),
+            envs: BTreeMap::new(),
+            secrets: BTreeSet::new(),
+            ports: Vec::new(),
+            networks: Vec::new(),
+            isolation: None,
+            depends_on: Vec::new(),
+            update: UpdateSpec::default(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    fn spec(tags: Vec<TagSpec>, servers: Vec<ServerSpec>) -> Spec {
+        Spec {
+            tags,
+            nodes: vec![NodeSpec {
+                uuid: NODE,
+                networks: Vec::new(),
+                servers,
+                users: BTreeSet::new(),
+            }],
+        }
+    }
+
+    fn existing(tags: Vec<(i32, bool, TagSpec)>) -> HashMap<String, TagState> {
+        tags.into_iter().map(|(id, owned, tag)| (tag.name.clone(), TagState { id, owned, tag })).collect()
+    }
+
+    fn node(servers: Vec<(i32, ServerSpec)>) -> HashMap<Uuid, NodeState> {
+        HashMap::from([(NODE, NodeState {
+            id: 1,
+            networks: Vec::new(),
+            servers,
+        })])
+    }
+
+    fn changes(plan: &Plan) -> Vec<String> {
+        plan.changes.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn create_tags() {
+        // the second tag isn't used by any server, but is still created for the team
+        let spec = spec(vec![tag("minecraft", "latest"), tag("unused", "latest")], vec![server("survival", "minecraft")]);
+        let plan = diff(7, &spec, HashMap::new(), node(Vec::new()), false).unwrap();
+
+        assert_eq!(plan.team, 7);
+        assert_eq!(changes(&plan), vec![
+            "create tag minecraft (itzg/minecraft-server:latest)",
+            "create tag unused (itzg/minecraft-server:latest)",
+            format!("create server survival on {}", NODE).as_str(),
+        ]);
+    }
+
+    #[test]
+    fn update_owned_tags() {
+        let spec = spec(vec![tag("minecraft", "java21")], Vec::new());
+        let plan = diff(7, &spec, existing(vec![(3, true, tag("minecraft", "latest"))]), node(Vec::new()), false).unwrap();
+
+        assert!(matches!(plan.changes.as_slice(), [Change::UpdateTag { id: 3, .. }]));
+
+        let plan = diff(7, &spec, existing(vec![(3, true, tag("minecraft", "java21"))]), node(Vec::new()), false).unwrap();
+
+        assert!(plan.changes.is_empty());
+    }
+
+    #[test]
+    fn shadow_shared_tags() {
+        let spec = spec(vec![tag("minecraft", "java21")], Vec::new());
+        let plan = diff(7, &spec, existing(vec![(3, false, tag("minecraft", "latest"))]), node(Vec::new()), false).unwrap();
+
+        assert!(matches!(plan.changes.as_slice(), [Change::CreateTag { .. }]));
+
+        let plan = diff(7, &spec, existing(vec![(3, false, tag("minecraft", "java21"))]), node(Vec::new()), false).unwrap();
+
+        assert!(plan.changes.is_empty());
+        assert_eq!(plan.tags.get("minecraft"), Some(&3));
+    }
+
+    #[test]
+    fn reject_unknown_references() {
+        let spec = spec(Vec::new(), vec![server("survival", "minecraft")]);
+
+        assert_eq!(diff(7, &spec, HashMap::new(), node(Vec::new()), false).unwrap_err(), "Server survival uses tag minecraft, which does not exist");
+        assert_eq!(diff(7, &spec, existing(vec![(3, false, tag("minecraft", "latest"))]), HashMap::new(), false).unwrap_err(), format!("Node {} does not exist", NODE));
+    }
+
+    #[test]
+    fn reject_standalone_only_fields() {
+        let mut labeled = server("survival", "minecraft");
+        labeled.labels.insert("env".to_string(), "prod".to_string());
+        let spec = spec(vec![tag("minecraft", "latest")], vec![labeled]);
+
+        assert_eq!(diff(7, &spec, HashMap::new(), node(Vec::new()), false).unwrap_err(), "The database can't store the isolation, dependencies, update strategy and labels of server survival, which is only supported in standalone mode");
+    }
+
+    #[test]
+    fn update_servers() {
+        let mut current = server("survival", "minecraft");
+        current.ports.push(PortSpec { port: 25565, protocol: PortProtocol::Tcp, mapped: 30001 });
+
+        // ports without a mapping keep the assigned one, so an unchanged server isn't updated
+        let mut unchanged = current.clone();
+        unchanged.ports[0].mapped = 0;
+
+        let tags = existing(vec![(3, false, tag("minecraft", "latest"))]);
+        let plan = diff(7, &spec(Vec::new(), vec![unchanged.clone()]), tags.clone(), node(vec![(5, current.clone())]), false).unwrap();
+
+        assert!(plan.changes.is_empty());
+
+        unchanged.envs.insert("EULA".to_string(), "TRUE".to_string());
+        let plan = diff(7, &spec(Vec::new(), vec![unchanged]), tags, node(vec![(5, current)]), false).unwrap();
+
+        match plan.changes.as_slice() {
+            [Change::UpdateServer { id: 5, server, .. }] => assert_eq!(server.ports[0].mapped, 30001),
+            changes => panic!("unexpected changes {:?}", changes),
+        }
+    }
+
+    #[test]
+    fn prune_servers() {
+        let tags = existing(vec![(3, false, tag("minecraft", "latest"))]);
+        let current = node(vec![(5, server("creative", "minecraft"))]);
+
+        let plan = diff(7, &spec(Vec::new(), Vec::new()), tags.clone(), current.clone(), false).unwrap();
+        assert!(plan.changes.is_empty());
+
+        let plan = diff(7, &spec(Vec::new(), Vec::new()), tags, current, true).unwrap();
+        assert!(matches!(plan.changes.as_slice(), [Change::DeleteServer { id: 5, .. }]));
+    }
+    #[test]
+    fn keep_secret_envs() {
+        let mut current = server("survival", "minecraft");
+        current.envs.insert("RCON_PASSWORD".to_string(), "hunter2".to_string());
+        current.secrets.insert("RCON_PASSWORD".to_string());
+
+        // an export leaves the value of secret envs out
+        let mut exported = current.clone();
+        exported.envs.clear();
+
+        let tags = existing(vec![(3, false, tag("minecraft", "latest"))]);
+        let plan = diff(7, &spec(Vec::new(), vec![exported.clone()]), tags.clone(), node(vec![(5, current.clone())]), false).unwrap();
+
+        assert!(plan.changes.is_empty());
+
+        exported.envs.insert("EULA".to_string(), "TRUE".to_string());
+        let plan = diff(7, &spec(Vec::new(), vec![exported.clone()]), tags.clone(), node(vec![(5, current)]), false).unwrap();
+
+        match plan.changes.as_slice() {
+            [Change::UpdateServer { server, .. }] => {
+                assert_eq!(server.envs.get("RCON_PASSWORD").map(String::as_str), Some("hunter2"));
+                assert!(server.secrets.contains("RCON_PASSWORD"));
+            },
+            changes => panic!("unexpected changes {:?}", changes),
+        }
+
+        assert_eq!(diff(7, &spec(Vec::new(), vec![exported]), tags, node(Vec::new()), false).unwrap_err(), "Secret env RCON_PASSWORD of server survival has no value");
+    }
+}
@@ -0,0 +1,145 @@
+use std::time::Duration;
+
+use packet::server_web::query_notifications_response::{Notification, NotificationKind};
+use sqlx::types::Uuid;
+use tracing::warn;
+
+use crate::{config::CONFIG, db, repository, sessions::now};
+
+/// Default amount of notifications returned by `query`.
+const DEFAULT_LIMIT: u32 = 50;
+
+/// Most notifications returned by `query`.
+const MAX_LIMIT: u32 = 500;
+
+/// How often notifications past their retention are deleted.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The users a notification is stored for.
+pub enum Recipients {
+    /// All users of the team the daemon belongs to
+    Team(Uuid),
+    User(u32),
+}
+
+pub fn kind_to_db(kind: NotificationKind) -> i16 {
+    match kind {
+        NotificationKind::Alert => 0,
+        NotificationKind::SyncFailed => 1,
+        NotificationKind::DaemonError => 2,
+        NotificationKind::GroupSync => 3,
+        NotificationKind::Automation => 4,
+        NotificationKind::CommandResult => 5,
+    }
+}
+
+pub fn kind_from_db(kind: i16) -> Result<NotificationKind, String> {
+    match kind {
+        0 => Ok(NotificationKind::Alert),
+        1 => Ok(NotificationKind::SyncFailed),
+        2 => Ok(NotificationKind::DaemonError),
+        3 => Ok(NotificationKind::GroupSync),
+        4 => Ok(NotificationKind::Automation),
+        5 => Ok(NotificationKind::CommandResult),
+        _ => Err(format!("Invalid notification kind {}", kind)),
+    }
+}
+
+/// Stores a notification in the inbox of its recipients in the background, so it doesn't hold up
+/// the delivery to web clients that are connected.
+pub fn notify(recipients: Recipients, kind: NotificationKind, server: Option<u32>, message: String) {
+    if !CONFIG.inbox.enabled || db::read_only() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        if let Err(e) = repository::get().store_notification(&recipients, kind, server, &message, now()).await {
+            warn!("Could not store notification: {}", e);
+        }
+    });
+}
+
+/// Periodically deletes notifications past their retention. Does nothing if the inbox is disabled.
+pub async fn run() {
+    if !CONFIG.inbox.enabled {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if db::read_only() {
+            continue;
+        }
+
+        if let Err(e) = repository::get().delete_notifications(now().saturating_sub(CONFIG.inbox.retention_days * 24 * 60 * 60)).await {
+            warn!("Could not delete old notifications: {}", e);
+        }
+    }
+}
+
+/// Returns the amount of notifications to return for a requested limit, see `query`.
+fn page_size(limit: Option<u32>) -> u32 {
+    limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+}
+
+/// Returns the latest notifications of a user, newest first, and the amount of unread
+/// notifications it has.
+pub async fn query(user_id: u32, unread_only: bool, before: Option<u64>, limit: Option<u32>) -> Result<(Vec<Notification>, u64), String> {
+    let limit = page_size(limit);
+
+    repository::get().notifications(user_id, unread_only, before, limit).await
+}
+
+/// Marks notifications of a user as read, or all of them if `all` is set. Notifications of other
+/// users are left as they are.
+pub async fn mark_read(user_id: u32, ids: &[u64], all: bool) -> Result<(), String> {
+    db::writable()?;
+
+    repository::get().mark_read(user_id, (!all).then_some(ids)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [NotificationKind; 6] = [
+        NotificationKind::Alert,
+        NotificationKind::SyncFailed,
+        NotificationKind::DaemonError,
+        NotificationKind::GroupSync,
+        NotificationKind::Automation,
+        NotificationKind::CommandResult,
+    ];
+
+    #[test]
+    fn kinds_round_trip() {
+        for kind in KINDS {
+            assert_eq!(kind_from_db(kind_to_db(kind)), Ok(kind));
+        }
+    }
+
+    #[test]
+    fn kinds_stored_as_distinct_values() {
+        let mut stored = KINDS.iter().map(|kind| kind_to_db(*kind)).collect::<Vec<_>>();
+        stored.sort();
+        stored.dedup();
+
+        assert_eq!(stored.len(), KINDS.len());
+    }
+
+    #[test]
+    fn unknown_kinds_rejected() {
+        assert!(kind_from_db(-1).is_err());
+        assert!(kind_from_db(KINDS.len() as i16).is_err());
+    }
+
+    #[test]
+    fn page_size_clamped() {
+        assert_eq!(page_size(None), DEFAULT_LIMIT);
+        assert_eq!(page_size(Some(10)), 10);
+        assert_eq!(page_size(Some(u32::MAX)), MAX_LIMIT);
+    }
+}
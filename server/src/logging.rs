@@ -1,4 +1,4 @@
-use std::{io, sync::OnceLock};
+use std::{io, sync::Mutex};
 
 use tracing::Level;
 use tracing_appender::{non_blocking::WorkerGuard, rolling::Rotation};
@@ -6,9 +6,9 @@ use tracing_subscriber::{fmt::writer::MakeWriterExt, layer::SubscriberExt, util:
 
 use crate::config::CONFIG;
 
-static FILE_GUARD: OnceLock<WorkerGuard> = OnceLock::new();
-static STDOUT_GUARD: OnceLock<WorkerGuard> = OnceLock::new();
-static STDERR_GUARD: OnceLock<WorkerGuard> = OnceLock::new();
+static FILE_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+static STDOUT_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
+static STDERR_GUARD: Mutex<Option<WorkerGuard>> = Mutex::new(None);
 
 /// Initialize the logging system.
 pub fn init() {
@@ -17,13 +17,13 @@ pub fn init() {
 
     let logs_rotation = tracing_appender::rolling::Builder::new().filename_suffix("server.aesterisk.log").rotation(Rotation::DAILY).build(&CONFIG.logging.folder).expect("could not initialize file logger");
     let (logs_file, logs_file_guard) = tracing_appender::non_blocking(logs_rotation);
-    FILE_GUARD.set(logs_file_guard).expect("logs_file_guard already set");
+    FILE_GUARD.lock().expect("file_guard poisoned").replace(logs_file_guard);
     let logs_file_layer = tracing_subscriber::fmt::layer().with_writer(logs_file.with_max_level(Level::DEBUG)).with_ansi(false);
 
     let (logs_stdout, logs_stdout_guard) = tracing_appender::non_blocking(io::stdout());
-    STDOUT_GUARD.set(logs_stdout_guard).expect("logs_stdout_guard already set");
+    STDOUT_GUARD.lock().expect("stdout_guard poisoned").replace(logs_stdout_guard);
     let (logs_stderr, logs_stderr_guard) = tracing_appender::non_blocking(io::stderr());
-    STDERR_GUARD.set(logs_stderr_guard).expect("logs_stderr_guard already set");
+    STDERR_GUARD.lock().expect("stderr_guard poisoned").replace(logs_stderr_guard);
     let logs_stdout_layer = tracing_subscriber::fmt::layer().with_writer(logs_stderr.with_max_level(Level::WARN).or_else(logs_stdout.with_max_level(Level::DEBUG))).with_ansi(true);
 
     #[cfg(feature = "tokio_debug")]
@@ -39,3 +39,10 @@ pub fn init() {
         .with(logs_stdout_layer)
         .init();
 }
+
+/// Flush the logs before the program exits.
+pub fn flush() {
+    drop(FILE_GUARD.lock().expect("file_guard poisoned").take()); // skipcq: RS-E1021
+    drop(STDERR_GUARD.lock().expect("stderr_guard poisoned").take()); // skipcq: RS-E1021
+    drop(STDOUT_GUARD.lock().expect("stdout_guard poisoned").take()); // skipcq: RS-E1021
+}
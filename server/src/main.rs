@@ -1,6 +1,6 @@
-use std::{process, sync::Arc};
+use std::{process, sync::Arc, time::{Duration, Instant}};
 
-use futures_util::join;
+use packet::close::CloseReason;
 use state::State;
 use tracing::{info, warn, error};
 
@@ -8,52 +8,326 @@ use daemon::DaemonServer;
 use web::WebServer;
 use server::Server;
 
+mod accounting;
+mod alerts;
+mod automation;
+mod auth;
+mod builds;
+mod catalog;
+#[cfg(feature = "chaos")]
+mod chaos;
 mod config;
 mod daemon;
 mod db;
+#[cfg(feature = "debug_endpoint")]
+mod debug;
 mod encryption;
+mod fanout;
+mod fleet;
+mod gitops;
+mod heartbeat;
+mod history;
+mod import;
+mod inbox;
 mod logging;
+mod metadata;
+mod metrics;
+mod notify;
+mod placement;
+mod plugins;
+mod postgres;
+mod quotas;
+mod rate_limit;
+mod repository;
 mod server;
+mod sessions;
+mod spec;
+mod sqlite;
+mod standalone;
 mod state;
+mod telemetry;
+mod tls;
+mod versions;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod watchdog;
 mod web;
 
+/// How long to wait for connections to close when shutting down.
+const SHUTDOWN_GRACE_PERIOD: Duration = Duration::from_secs(1);
+
+/// How often an internal server is restarted in a row before the server exits.
+const MAX_RESTARTS: u32 = 5;
+
+/// How long to wait before the first restart of an internal server, doubled after every restart.
+const RESTART_BACKOFF: Duration = Duration::from_secs(1);
+
+/// How long an internal server has to run before it is considered recovered, and its restarts are
+/// counted from zero again.
+const RESTART_RESET: Duration = Duration::from_secs(300);
+
+/// Exit codes of the server, so its supervisor can tell why it exited.
+#[repr(i32)]
+enum ExitCode {
+    Success = 0,
+    ConfigError = 1,
+    DatabaseError = 2,
+    /// An internal server could not bind to its address, even after restarting it
+    BindError = 3,
+    /// An internal server kept stopping or panicking, even after restarting it
+    ServerError = 4,
+    ExportError = 5,
+}
+
+impl From<ExitCode> for i32 {
+    fn from(code: ExitCode) -> i32 {
+        code as i32
+    }
+}
+
 #[dotenvy::load]
 #[tokio::main]
 async fn main() {
+    if std::env::args().any(|arg| arg == "--validate-config") {
+        match config::load("config.toml") {
+            Ok(_) => {
+                println!("Configuration is valid");
+                exit(ExitCode::Success);
+            },
+            Err(e) => {
+                eprintln!("Configuration error: {}", e);
+                exit(ExitCode::ConfigError);
+            },
+        }
+    }
+
+    // the spec is printed to stdout, so this has to happen before logging is initialized
+    if let Some(i) = std::env::args().position(|arg| arg == "--export-spec") {
+        match export_spec(std::env::args().skip(i + 1).collect()).await {
+            Ok(spec) => {
+                print!("{}", spec);
+                exit(ExitCode::Success);
+            },
+            Err(e) => {
+                eprintln!("Could not export spec: {}", e);
+                exit(ExitCode::ExportError);
+            },
+        }
+    }
+
     logging::init();
 
     info!("Starting Aesterisk Server v{}", env!("CARGO_PKG_VERSION"));
 
-    if let Err(e) = db::init().await {
+    // takes the sockets passed by socket activation, before any tasks read the environment
+    packet::activation::init();
+
+    #[cfg(feature = "chaos")]
+    warn!("Built with the chaos feature, faults are injected as configured in [chaos]");
+
+    if standalone::enabled() {
+        info!("Starting without a database, reading specs from {}", config::CONFIG.standalone.folder);
+
+        if let Err(e) = standalone::load().await {
+            error!("Failed to read {}: {}", config::CONFIG.standalone.folder, e);
+            exit(ExitCode::ConfigError);
+        }
+
+        db::set_read_only(true);
+    } else if let Err(e) = db::init().await {
         error!("Failed to initialize database connection: {}", e);
-        process::exit(1);
+        exit(ExitCode::DatabaseError);
+    }
+
+    if config::CONFIG.server.read_only {
+        warn!("Starting in read-only mode");
+        db::set_read_only(true);
+    }
+
+    #[cfg(unix)]
+    tokio::spawn(toggle_read_only());
+
+    if let Err(e) = tls::init() {
+        error!("Failed to load TLS certificate: {}", e);
+        exit(ExitCode::ConfigError);
+    }
+
+    plugins::init();
+
+    #[cfg(feature = "wasm")]
+    if config::CONFIG.wasm.enabled
+        && let Err(e) = wasm::init() {
+        error!("Failed to start WASM extensions: {}", e);
+        exit(ExitCode::ConfigError);
+    }
+
+    #[cfg(not(feature = "wasm"))]
+    if config::CONFIG.wasm.enabled {
+        warn!("WASM extensions are enabled, but the server was built without the wasm feature");
     }
 
     let state = Arc::new(State::new());
+    state.start_fanout();
+
+    tokio::spawn(metrics::run());
+    tokio::spawn(automation::run());
+    tokio::spawn(fleet::run(Arc::clone(&state)));
+    tokio::spawn(gitops::run(Arc::clone(&state)));
+    tokio::spawn(heartbeat::run(Arc::clone(&state)));
+    tokio::spawn(history::run());
+    tokio::spawn(inbox::run());
+    tokio::spawn(notify::run(Arc::clone(&state)));
+    tokio::spawn(sessions::run());
+    tokio::spawn(standalone::run(Arc::clone(&state)));
+    tokio::spawn(telemetry::run(Arc::clone(&state)));
+    tokio::spawn(watchdog::run());
+    tokio::spawn(shutdown(Arc::clone(&state)));
+
+    #[cfg(feature = "debug_endpoint")]
+    tokio::spawn(debug::run(Arc::clone(&state)));
 
     let daemon_server = Arc::new(DaemonServer::new(Arc::clone(&state)));
     let web_server = Arc::new(WebServer::new(Arc::clone(&state)));
 
-    info!("Starting Daemon Server...");
-    let daemon_server_handle = tokio::spawn(daemon_server.start());
+    // both internal servers are needed, so the server exits (to be restarted by its supervisor,
+    // e.g. systemd) once either of them can't be recovered
+    let (code, reason) = tokio::select! {
+        reason = supervise(daemon_server) => reason,
+        reason = supervise(web_server) => reason,
+    };
 
-    info!("Starting Web Server...");
-    let web_server_handle = tokio::spawn(web_server.start());
+    error!("{}, exiting...", reason);
 
-    let (web_res, daemon_res) = join!(web_server_handle, daemon_server_handle);
+    // the heartbeats stop as well, which is what operators have to rely on if this doesn't get
+    // through
+    watchdog::alert(false, &[reason]).await;
 
-    if web_res.is_err() {
-        warn!("Failed to join web server handle");
+    exit(code);
+}
+
+/// Flushes the logs and exits with `code`.
+fn exit(code: ExitCode) -> ! {
+    logging::flush();
+    process::exit(code.into())
+}
+
+/// Runs an internal server, restarting it with an exponential backoff whenever it stops, panics or
+/// can't bind to its address. Returns the exit code and why it failed once it was restarted
+/// `MAX_RESTARTS` times in a row.
+async fn supervise<S: Server>(server: Arc<S>) -> (ExitCode, String) {
+    let name = server.get_tracing_name();
+    let mut restarts = 0;
+    let mut backoff = RESTART_BACKOFF;
+
+    loop {
+        info!("Starting the {} server...", name);
+        let started = Instant::now();
+
+        let (code, reason) = match tokio::spawn(Arc::clone(&server).start()).await {
+            Ok(Ok(())) => (ExitCode::ServerError, "stopped".to_string()),
+            Ok(Err(e)) => (ExitCode::BindError, e),
+            Err(e) if e.is_panic() => (ExitCode::ServerError, "panicked".to_string()),
+            Err(e) => (ExitCode::ServerError, format!("failed: {}", e)),
+        };
+
+        watchdog::set_listening(name, false);
+
+        if started.elapsed() >= RESTART_RESET {
+            restarts = 0;
+            backoff = RESTART_BACKOFF;
+        }
+
+        if restarts >= MAX_RESTARTS {
+            return (code, format!("The {} server {} and could not be restarted after {} attempts", name, reason, MAX_RESTARTS));
+        }
+
+        restarts += 1;
+        warn!("The {} server {}, restarting in {}s (attempt {}/{})", name, reason, backoff.as_secs(), restarts, MAX_RESTARTS);
+
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    }
+}
+
+/// Exports the nodes given as arguments (`<uuid>...` or `--team <id>`) as a declarative spec.
+async fn export_spec(args: Vec<String>) -> Result<String, String> {
+    db::init().await?;
+
+    let nodes = match args.as_slice() {
+        [flag, team] if flag == "--team" => repository::get().team_nodes(team.parse().map_err(|_| format!("Invalid team id {}", team))?).await?,
+        [] => return Err("Usage: --export-spec <uuid>... | --export-spec --team <id>".to_string()),
+        uuids => uuids.iter().map(|uuid| uuid.parse().map_err(|_| format!("Invalid node UUID {}", uuid))).collect::<Result<Vec<_>, String>>()?,
+    };
+
+    spec::export(&nodes).await?.to_toml()
+}
+
+/// Waits for `Ctrl+C` (or `SIGTERM`), then tells all daemons and web clients that the server is
+/// shutting down, so they reconnect once it is back (or to `server.drain_url`), and exits.
+async fn shutdown(state: Arc<State>) {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            },
+            Err(e) => {
+                warn!("Unable to listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await;
+            },
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => if let Err(e) = res {
+            warn!("Unable to listen for Ctrl+C: {}", e);
+            return;
+        },
+        _ = terminate => (),
     }
 
-    if daemon_res.is_err() {
-        warn!("Failed to join daemon server handle");
+    warn!("Shutting down...");
+
+    if let Some(url) = config::CONFIG.server.drain_url.as_ref() {
+        info!("Redirecting daemons to {}", url);
+        state.redirect_daemons(url);
     }
 
-    warn!("Internal servers are down, exiting...");
+    state.disconnect_all(CloseReason::ServerShutdown);
 
-    // TODO: as this is the main server, and exit should probably immediately notify us, but as
-    //       this is a prototype, we'll just let it exit for now. as something might have failed,
-    //       we can't rely on the notification being sent, so we'll need to monitor the server
-    //       status from the outside as well.
+    // gives the connections a moment to send their close frames
+    tokio::time::sleep(SHUTDOWN_GRACE_PERIOD).await;
+
+    exit(ExitCode::Success);
+}
+
+/// Toggles read-only mode whenever the server receives `SIGUSR1`.
+#[cfg(unix)]
+async fn toggle_read_only() {
+    let mut signal = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1()) {
+        Ok(signal) => signal,
+        Err(e) => {
+            warn!("Unable to listen for SIGUSR1, read-only mode can't be toggled: {}", e);
+            return;
+        },
+    };
+
+    while signal.recv().await.is_some() {
+        if standalone::enabled() {
+            warn!("Read-only mode can't be left without a database");
+            continue;
+        }
+
+        let read_only = !db::read_only();
+        db::set_read_only(read_only);
+
+        if read_only {
+            warn!("Entered read-only mode");
+        } else {
+            info!("Left read-only mode");
+        }
+    }
 }
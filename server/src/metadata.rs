@@ -0,0 +1,41 @@
+use std::collections::BTreeMap;
+
+use packet::server_daemon::sync::ServerMetadata;
+use sqlx::types::Uuid;
+
+use crate::{db, repository};
+
+/// Longest friendly name of a server, in characters.
+const MAX_NAME_LENGTH: usize = 64;
+
+/// Most custom labels a server can have.
+const MAX_LABELS: usize = 32;
+
+fn validate(name: Option<&String>, labels: Option<&BTreeMap<String, String>>) -> Result<(), String> {
+    if let Some(name) = name
+        && (name.trim().is_empty() || name.chars().count() > MAX_NAME_LENGTH) {
+        return Err(format!("Server names have to be between 1 and {} characters long", MAX_NAME_LENGTH));
+    }
+
+    if let Some(labels) = labels {
+        if labels.len() > MAX_LABELS {
+            return Err(format!("Servers can't have more than {} labels", MAX_LABELS));
+        }
+
+        // keys become part of Docker label keys
+        if let Some(key) = labels.keys().find(|key| key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))) {
+            return Err(format!("Invalid label key '{}', only letters, digits, '.', '-' and '_' are allowed", key));
+        }
+    }
+
+    Ok(())
+}
+
+/// Updates the friendly name and/or the custom labels of a server of a daemon, and returns its
+/// resulting metadata.
+pub async fn update(uuid: Uuid, server: u32, name: Option<String>, labels: Option<BTreeMap<String, String>>) -> Result<ServerMetadata, String> {
+    db::writable()?;
+    validate(name.as_ref(), labels.as_ref())?;
+
+    repository::get().update_metadata(uuid, server, name, labels).await
+}
@@ -0,0 +1,136 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use packet::events::{EventData, MetricSample};
+use sqlx::types::Uuid;
+use tracing::{debug, warn};
+
+use crate::{accounting, config::CONFIG, db, repository};
+
+/// How often the rollup and retention jobs run.
+const JOB_INTERVAL: Duration = Duration::from_secs(60 * 60);
+/// Length of a rollup bucket, in seconds.
+const ROLLUP_BUCKET: i64 = 60 * 60;
+/// How far back the rollup job looks for raw samples, in seconds. Hours that are older than this
+/// when the job runs (e.g. because the server was down) are not rolled up.
+const ROLLUP_WINDOW: i64 = 24 * 60 * 60;
+
+lazy_static! {
+    /// Maps a node uuid and server id (0 for the node itself) to the time of the last stored sample.
+    static ref LAST_SAMPLES: DashMap<(Uuid, u32), u64> = DashMap::new();
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// Stores a sample of a status event at the time it happened (`timestamp`, or now), if metrics are
+/// enabled and the last sample of the same node or server is at least `sample_interval` seconds
+/// old.
+pub async fn record(uuid: Uuid, event: &EventData, timestamp: Option<u64>) -> Result<(), String> {
+    if !CONFIG.metrics.enabled || db::read_only() {
+        return Ok(());
+    }
+
+    let (server, cpu, memory_used, memory_total, storage_used, storage_total) = match event {
+        EventData::NodeStatus(status) => match &status.stats {
+            Some(stats) => (0, Some(stats.cpu), Some(stats.used_memory), Some(stats.total_memory), Some(stats.used_storage), Some(stats.total_storage)),
+            None => return Ok(()),
+        },
+        EventData::ServerStatus(status) => (
+            status.server,
+            status.cpu_per_core(),
+            status.memory.as_ref().map(|memory| memory.used),
+            status.memory.as_ref().map(|memory| memory.total),
+            status.storage.as_ref().map(|storage| storage.used),
+            status.storage.as_ref().map(|storage| storage.total),
+        ),
+        EventData::Alert(_) | EventData::FleetSummary(_) | EventData::ResourceWarning(_) | EventData::BuildOutput(_) | EventData::UpdatePhase(_) | EventData::UpdateRequired(_) | EventData::Task(_) | EventData::ServerLog(_) => return Ok(()),
+    };
+
+    // events are recorded at the time they happened, even if the daemon replayed them later
+    let now = timestamp.unwrap_or_else(now);
+
+    {
+        let mut last = LAST_SAMPLES.entry((uuid, server)).or_insert(0);
+
+        if now < *last + CONFIG.metrics.sample_interval {
+            return Ok(());
+        }
+
+        *last = now;
+    }
+
+    let sample = MetricSample {
+        timestamp: now,
+        cpu,
+        memory_used,
+        memory_total,
+        storage_used,
+        storage_total,
+    };
+
+    repository::get().store_metrics(uuid, server, &sample).await.map_err(|e| format!("Could not store metrics: {}", e))
+}
+
+/// Returns the samples of a node (or one of its servers) within the given range (inclusive),
+/// oldest first. Ranges reaching further back than the raw retention use the hourly rollups.
+pub async fn query(uuid: Uuid, server: Option<u32>, from: u64, to: u64) -> Result<Vec<MetricSample>, String> {
+    // timestamps come from web clients, so they are clamped to the range of the column
+    let (from_time, to_time) = (i64::try_from(from).unwrap_or(i64::MAX), i64::try_from(to).unwrap_or(i64::MAX));
+
+    let hourly = from.saturating_add(CONFIG.metrics.raw_retention_hours.saturating_mul(60 * 60)) < now();
+
+    repository::get().metrics(uuid, server.unwrap_or(0), from_time, to_time, hourly).await.map_err(|e| format!("Could not query metrics: {}", e))
+}
+
+async fn rollup() -> Result<(), String> {
+    let now = now() as i64;
+    let hour = now - now % ROLLUP_BUCKET;
+
+    repository::get().rollup_metrics(hour - ROLLUP_WINDOW, hour).await.map_err(|e| format!("Could not roll up metrics: {}", e))
+}
+
+async fn apply_retention() -> Result<(), String> {
+    let now = now();
+
+    let raw_before = now.saturating_sub(CONFIG.metrics.raw_retention_hours.saturating_mul(60 * 60));
+    let rollups_before = now.saturating_sub(CONFIG.metrics.rollup_retention_days.saturating_mul(24 * 60 * 60));
+
+    repository::get().delete_metrics(raw_before, rollups_before).await.map_err(|e| format!("Could not delete old metrics: {}", e))
+}
+
+/// Periodically rolls up raw samples into hourly buckets, attributes them to teams if accounting is
+/// enabled, and deletes samples past their retention.
+/// Does nothing if metrics are disabled.
+pub async fn run() {
+    if !CONFIG.metrics.enabled {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(JOB_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if db::read_only() {
+            continue;
+        }
+
+        debug!("Running metrics rollup and retention jobs");
+
+        // accounting runs before the retention job, so rollups are accounted before they are
+        // deleted, and only after a successful rollup, so no hour is accounted incompletely
+        match rollup().await {
+            Ok(()) => if CONFIG.metrics.accounting && let Err(e) = accounting::account().await {
+                warn!("{}", e);
+            },
+            Err(e) => warn!("{}", e),
+        }
+
+        if let Err(e) = apply_retention().await {
+            warn!("{}", e);
+        }
+    }
+}
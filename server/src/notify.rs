@@ -0,0 +1,48 @@
+use std::{sync::Arc, time::Duration};
+
+use sqlx::postgres::PgListener;
+use tracing::{debug, warn};
+
+use crate::{db, state::State};
+
+/// The channel the database notifies on whenever data that is part of a sync changes, see the
+/// `aesterisk.notify_sync` trigger function.
+const CHANNEL: &str = "aesterisk_sync";
+
+/// How long to wait before reconnecting after the listener failed.
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Listens for database change notifications, and invalidates the cached specs whenever data that
+/// is part of a sync changes. Only Postgres notifies the server of changes.
+pub async fn run(state: Arc<State>) {
+    if !db::postgres() {
+        return;
+    }
+
+    loop {
+        if let Err(e) = listen(&state).await {
+            warn!("Database change listener failed: {}", e);
+        }
+
+        // notifications might have been missed
+        state.invalidate_specs();
+
+        tokio::time::sleep(RETRY_INTERVAL).await;
+    }
+}
+
+async fn listen(state: &State) -> Result<(), String> {
+    // the listener holds on to its connection, so it shouldn't take one from the pool
+    let mut listener = PgListener::connect(&std::env::var("DATABASE_URL").map_err(|_| "DATABASE_URL should be set")?).await.map_err(|e| format!("SQLx error: {}", e))?;
+    listener.listen(CHANNEL).await.map_err(|e| format!("Could not listen for database changes: {}", e))?;
+
+    loop {
+        match listener.try_recv().await.map_err(|e| format!("SQLx error: {}", e))? {
+            Some(notification) => {
+                debug!("Database changed ({}), invalidating cached specs", notification.payload());
+                state.invalidate_specs();
+            },
+            None => return Err("connection to the database was lost".to_string()),
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use packet::{events::NodeStats, server_web::place_server_response::PlacementCandidate};
+use sqlx::types::Uuid;
+
+use crate::{db, repository};
+
+/// Returns the load of a daemon, the average usage of its CPU, memory and (if limited) server
+/// capacity, between 0 and 1.
+pub fn load(stats: &NodeStats, servers: u32, max_servers: Option<u32>) -> f64 {
+    let mut usages = vec![(stats.cpu / 100.0).clamp(0.0, 1.0)];
+
+    if stats.total_memory > 0.0 {
+        usages.push((stats.used_memory / stats.total_memory).clamp(0.0, 1.0));
+    }
+
+    if let Some(max_servers) = max_servers.filter(|max_servers| *max_servers > 0) {
+        usages.push((servers as f64 / max_servers as f64).clamp(0.0, 1.0));
+    }
+
+    usages.iter().sum::<f64>() / usages.len() as f64
+}
+
+/// Sorts placement candidates, least-loaded first.
+pub fn rank(candidates: &mut [PlacementCandidate]) {
+    candidates.sort_by(|a, b| a.load.total_cmp(&b.load).then(a.servers.cmp(&b.servers)));
+}
+
+/// Assigns a server to a daemon of the user's team, unless it already is assigned
+/// to one.
+pub async fn assign(user_id: u32, server: u32, daemon: Uuid) -> Result<(), String> {
+    if !repository::get().team_daemons(user_id).await?.contains(&daemon) {
+        return Err(format!("Node {} does not belong to your team", daemon));
+    }
+
+    db::writable()?;
+
+    if !repository::get().assign_server(daemon, server).await? {
+        return Err(format!("Server {} is already assigned to a daemon", server));
+    }
+
+    Ok(())
+}
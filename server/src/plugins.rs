@@ -0,0 +1,100 @@
+use std::sync::OnceLock;
+
+use async_trait::async_trait;
+use packet::{events::EventData, server_daemon::sync::SDSyncPacket, web_server::command::WSCommandPacket};
+use sqlx::types::Uuid;
+use tracing::{info, warn};
+
+static PLUGINS: OnceLock<Vec<Box<dyn Plugin>>> = OnceLock::new();
+
+/// `Plugin` is a compiled-in extension of the server, with hooks that are called as daemons and
+/// web clients are handled, e.g. to push events to a monitoring system or to enforce extra
+/// policies. Every hook does nothing by default.
+#[async_trait]
+pub trait Plugin: Send + Sync {
+    /// The name of the plugin, used in logs and errors.
+    fn name(&self) -> &'static str;
+
+    /// Called once a daemon authenticated.
+    async fn on_daemon_authenticated(&self, _daemon: Uuid) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called for every event before it is delivered to the web clients listening, including
+    /// events created by the server. Called on the hot path of event delivery, so it shouldn't
+    /// block, but spawn a task for slow work instead.
+    fn on_event(&self, _daemon: &Uuid, _event: &EventData) {}
+
+    /// Called before a sync is sent to a daemon. Returning an error cancels the sync, which is
+    /// reported to the web clients that requested it.
+    async fn on_sync(&self, _daemon: Uuid, _sync: &SDSyncPacket) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called before a command of a user is forwarded to a daemon of their team. Returning an error
+    /// rejects the command, which is reported to the web client.
+    async fn on_command(&self, _user_id: u32, _command: &WSCommandPacket) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Returns the plugins compiled into the server, in the order their hooks are called. Deployments
+/// add their plugins here.
+fn registered() -> Vec<Box<dyn Plugin>> {
+    Vec::new()
+}
+
+/// Registers the plugins compiled into the server, should be called once at startup.
+pub fn init() {
+    register(registered());
+}
+
+/// Registers plugins, which is only possible once.
+pub fn register(plugins: Vec<Box<dyn Plugin>>) {
+    for plugin in plugins.iter() {
+        info!("Registered plugin {}", plugin.name());
+    }
+
+    if PLUGINS.set(plugins).is_err() {
+        warn!("Plugins were already registered");
+    }
+}
+
+fn all() -> &'static [Box<dyn Plugin>] {
+    PLUGINS.get().map(Vec::as_slice).unwrap_or_default()
+}
+
+/// Calls the `on_daemon_authenticated` hook of every plugin. Errors are only logged, as the daemon
+/// is authenticated already.
+pub async fn daemon_authenticated(daemon: Uuid) {
+    for plugin in all() {
+        if let Err(e) = plugin.on_daemon_authenticated(daemon).await {
+            warn!("Plugin {} failed handling authentication of daemon {}: {}", plugin.name(), daemon, e);
+        }
+    }
+}
+
+/// Calls the `on_event` hook of every plugin.
+pub fn event(daemon: &Uuid, event: &EventData) {
+    for plugin in all() {
+        plugin.on_event(daemon, event);
+    }
+}
+
+/// Calls the `on_sync` hook of every plugin, failing once one of them cancels the sync.
+pub async fn sync(daemon: Uuid, sync: &SDSyncPacket) -> Result<(), String> {
+    for plugin in all() {
+        plugin.on_sync(daemon, sync).await.map_err(|e| format!("Sync was cancelled by plugin {}: {}", plugin.name(), e))?;
+    }
+
+    Ok(())
+}
+
+/// Calls the `on_command` hook of every plugin, failing once one of them rejects the command.
+pub async fn command(user_id: u32, command: &WSCommandPacket) -> Result<(), String> {
+    for plugin in all() {
+        plugin.on_command(user_id, command).await.map_err(|e| format!("Command was rejected by plugin {}: {}", plugin.name(), e))?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,1450 @@
+use std::{collections::{BTreeMap, HashMap}, net::SocketAddr};
+
+use async_trait::async_trait;
+use packet::{events::MetricSample, maintenance::MaintenanceWindow, server_daemon::{catalog::CatalogImage, sync::{Build, BuildContext, Dependency, Env, EnvDef, EnvType, Healthcheck, Isolation, IsolationPolicy, Mount, Network, Port, Protocol, SDSyncPacket, Server, ServerMetadata, ServerNetwork, Tag, UpdateStrategy}}, server_web::{query_connections_response::ConnectionSession, query_notifications_response::{Notification, NotificationKind}}};
+use sqlx::{types::Uuid, Transaction};
+use tracing::info;
+
+use crate::{alerts::AlertRule, db, history::{NewEvent, StoredEvent}, import::{Change, Plan}, inbox::{self, Recipients}, quotas::{DaemonQuota, Reservation, ServerQuota}, repository::{Repository, WasmExtension}, sessions::{Peer, Session}, spec::{HealthcheckSpec, NetworkSpec, NodeState, PortProtocol, PortSpec, ServerNetworkSpec, ServerSpec, TagSpec, TagState, UpdateSpec}};
+
+/// Stores everything in the `aesterisk` schema of a Postgres database.
+pub struct Postgres;
+
+#[async_trait]
+impl Repository for Postgres {
+    async fn team_daemons(&self, user_id: u32) -> Result<Vec<Uuid>, String> {
+        db::timed("fetch_team_daemons", sqlx::query_scalar!(r#"
+            SELECT
+                nodes.node_uuid
+            FROM aesterisk.users
+            INNER JOIN aesterisk.team_nodes
+                ON users.user_team = team_nodes.team_id
+            INNER JOIN aesterisk.nodes
+                ON team_nodes.node_id = nodes.node_id
+            WHERE users.user_id = $1;
+        "#, user_id as i32).fetch_all(db::get()?)).await.map_err(|_| "failed to fetch team daemons".to_string())
+    }
+
+    async fn spec(&self, uuid: Uuid) -> Result<SDSyncPacket, String> {
+        struct DbNetwork {
+            network_id: i32,
+            network_local_ip: i32,
+        }
+
+        let networks = db::timed("fetch_networks", sqlx::query_as!(DbNetwork, r#"
+            SELECT
+                networks.network_id,
+                networks.network_local_ip
+            FROM aesterisk.nodes
+            LEFT JOIN aesterisk.node_networks
+                ON nodes.node_id = node_networks.node_id
+            LEFT JOIN aesterisk.networks
+                ON node_networks.network_id = networks.network_id
+            WHERE nodes.node_uuid = $1
+            AND networks.network_id IS NOT NULL;
+        "#, uuid).fetch_all(db::get()?)).await.map_err(|_| "failed to fetch network data")?;
+
+        #[derive(sqlx::FromRow)]
+        struct DbServer {
+            server_id: i32,
+            tag_image: String,
+            tag_docker_tags: String,
+            tag_healthcheck_test: Vec<String>,
+            tag_healthcheck_interval: i32,
+            tag_healthcheck_timeout: i32,
+            tag_healthcheck_retries: i32,
+            mount_container_path: Option<Vec<String>>,
+            mount_host_path: Option<Vec<String>>,
+            env_def_key: Option<Vec<String>>,
+            env_def_required: Option<Vec<bool>>,
+            env_def_type: Option<Vec<i16>>,
+            env_def_default_value: Option<Vec<Option<String>>>,
+            env_def_regex: Option<Vec<Option<String>>>,
+            env_def_min: Option<Vec<Option<i32>>>,
+            env_def_max: Option<Vec<Option<i32>>>,
+            env_def_trim: Option<Vec<bool>>,
+            env_key: Option<Vec<String>>,
+            env_value: Option<Vec<String>>,
+            network_id: Option<Vec<i32>>,
+            network_local_ip: Option<Vec<i16>>,
+            port_port: Option<Vec<i32>>,
+            port_protocol: Option<Vec<i16>>,
+            port_mapped: Option<Vec<i32>>,
+            server_isolation_policy: i16,
+            server_isolation_allowlist: Vec<String>,
+            tag_build_git_url: Option<String>,
+            tag_build_context_hash: Option<String>,
+            tag_build_dockerfile: String,
+            server_update_strategy: i16,
+        }
+
+        let servers = db::timed("fetch_servers", sqlx::query_as!(DbServer, r#"
+            WITH mounts_cte AS (
+                SELECT
+                    tag_mounts.tag_id,
+                    ARRAY_AGG(mounts.mount_container_path ORDER BY mounts.mount_id) AS mount_container_path,
+                    ARRAY_AGG(mounts.mount_host_path ORDER BY mounts.mount_id) AS mount_host_path
+                FROM aesterisk.mounts
+                JOIN aesterisk.tag_mounts ON mounts.mount_id = tag_mounts.mount_id
+                GROUP BY tag_mounts.tag_id
+            ),
+            env_defs_cte AS (
+                SELECT
+                    tag_env_defs.tag_id,
+                    ARRAY_AGG(env_defs.env_def_key ORDER BY env_defs.env_def_id) AS env_def_key,
+                    ARRAY_AGG(env_defs.env_def_required ORDER BY env_defs.env_def_id) AS env_def_required,
+                    ARRAY_AGG(env_defs.env_def_type ORDER BY env_defs.env_def_id) AS env_def_type,
+                    ARRAY_AGG(env_defs.env_def_default_value ORDER BY env_defs.env_def_id) AS env_def_default_value,
+                    ARRAY_AGG(env_defs.env_def_regex ORDER BY env_defs.env_def_id) AS env_def_regex,
+                    ARRAY_AGG(env_defs.env_def_min ORDER BY env_defs.env_def_id) AS env_def_min,
+                    ARRAY_AGG(env_defs.env_def_max ORDER BY env_defs.env_def_id) AS env_def_max,
+                    ARRAY_AGG(env_defs.env_def_trim ORDER BY env_defs.env_def_id) AS env_def_trim
+                FROM aesterisk.env_defs
+                JOIN aesterisk.tag_env_defs ON env_defs.env_def_id = tag_env_defs.env_def_id
+                GROUP BY tag_env_defs.tag_id
+            ),
+            envs_cte AS (
+                SELECT
+                    server_envs.server_id,
+                    ARRAY_AGG(envs.env_key ORDER BY envs.env_id) AS env_key,
+                    ARRAY_AGG(envs.env_value ORDER BY envs.env_id) AS env_value
+                FROM aesterisk.envs
+                JOIN aesterisk.server_envs ON envs.env_id = server_envs.env_id
+                GROUP BY server_envs.server_id
+            ),
+            networks_cte AS (
+                SELECT
+                    server_networks.server_id,
+                    ARRAY_AGG(server_networks.network_id ORDER BY server_networks.network_id) AS network_id,
+                    ARRAY_AGG(server_networks.local_ip ORDER BY server_networks.network_id) AS network_local_ip
+                FROM aesterisk.server_networks
+                GROUP BY server_networks.server_id
+            ),
+            ports_cte AS (
+                SELECT
+                    server_ports.server_id,
+                    ARRAY_AGG(ports.port_port ORDER BY ports.port_id) AS port_port,
+                    ARRAY_AGG(ports.port_protocol ORDER BY ports.port_id) AS port_protocol,
+                    ARRAY_AGG(ports.port_mapped ORDER BY ports.port_id) AS port_mapped
+                FROM aesterisk.ports
+                JOIN aesterisk.server_ports ON ports.port_id = server_ports.port_id
+                GROUP BY server_ports.server_id
+            )
+            SELECT
+                servers.server_id,
+                tags.tag_image,
+                tags.tag_docker_tags,
+                tags.tag_healthcheck_test,
+                tags.tag_healthcheck_interval,
+                tags.tag_healthcheck_timeout,
+                tags.tag_healthcheck_retries,
+                mounts_cte.mount_container_path,
+                mounts_cte.mount_host_path,
+                env_defs_cte.env_def_key,
+                env_defs_cte.env_def_required,
+                env_defs_cte.env_def_type,
+                env_defs_cte.env_def_default_value AS "env_def_default_value: _",
+                env_defs_cte.env_def_regex AS "env_def_regex: _",
+                env_defs_cte.env_def_min AS "env_def_min: _",
+                env_defs_cte.env_def_max AS "env_def_max: _",
+                env_defs_cte.env_def_trim,
+                envs_cte.env_key,
+                envs_cte.env_value,
+                networks_cte.network_id,
+                networks_cte.network_local_ip,
+                ports_cte.port_port,
+                ports_cte.port_protocol,
+                ports_cte.port_mapped,
+                servers.server_isolation_policy,
+                servers.server_isolation_allowlist,
+                tags.tag_build_git_url,
+                tags.tag_build_context_hash,
+                tags.tag_build_dockerfile,
+                servers.server_update_strategy
+            FROM aesterisk.nodes
+            LEFT JOIN aesterisk.node_servers ON nodes.node_id = node_servers.node_id
+            LEFT JOIN aesterisk.servers ON node_servers.server_id = servers.server_id
+            LEFT JOIN aesterisk.tags ON servers.server_tag = tags.tag_id
+            LEFT JOIN mounts_cte ON servers.server_tag = mounts_cte.tag_id
+            LEFT JOIN env_defs_cte ON servers.server_tag = env_defs_cte.tag_id
+            LEFT JOIN envs_cte ON servers.server_id = envs_cte.server_id
+            LEFT JOIN networks_cte ON servers.server_id = networks_cte.server_id
+            LEFT JOIN ports_cte ON servers.server_id = ports_cte.server_id
+            WHERE nodes.node_uuid = $1;
+        "#, uuid).fetch_all(db::get()?)).await.map_err(|e| format!("Failed to fetch server data: {}", e))?;
+
+        struct DbMaintenanceWindow {
+            server_id: Option<i32>,
+            maintenance_window_cron: String,
+            maintenance_window_duration: i32,
+        }
+
+        let windows = db::timed("fetch_maintenance_windows", sqlx::query_as!(DbMaintenanceWindow, r#"
+            SELECT
+                maintenance_windows.server_id,
+                maintenance_windows.maintenance_window_cron,
+                maintenance_windows.maintenance_window_duration
+            FROM aesterisk.maintenance_windows
+            INNER JOIN aesterisk.nodes
+                ON maintenance_windows.node_id = nodes.node_id
+            WHERE nodes.node_uuid = $1;
+        "#, uuid).fetch_all(db::get()?)).await.map_err(|_| "failed to fetch maintenance windows")?;
+
+        let mut node_maintenance = Vec::new();
+        let mut server_maintenance = HashMap::<i32, Vec<MaintenanceWindow>>::new();
+
+        for window in windows.into_iter() {
+            let maintenance_window = MaintenanceWindow {
+                cron: window.maintenance_window_cron,
+                duration: window.maintenance_window_duration.max(0) as u32,
+            };
+
+            match window.server_id {
+                Some(server_id) => server_maintenance.entry(server_id).or_default().push(maintenance_window),
+                None => node_maintenance.push(maintenance_window),
+            }
+        }
+
+        struct DbDependency {
+            server_id: i32,
+            dependency_server_id: i32,
+            dependency_wait_healthy: bool,
+        }
+
+        let dependencies = db::timed("fetch_server_dependencies", sqlx::query_as!(DbDependency, r#"
+            SELECT
+                server_dependencies.server_id,
+                server_dependencies.dependency_server_id,
+                server_dependencies.dependency_wait_healthy
+            FROM aesterisk.server_dependencies
+            INNER JOIN aesterisk.node_servers
+                ON server_dependencies.server_id = node_servers.server_id
+            INNER JOIN aesterisk.nodes
+                ON node_servers.node_id = nodes.node_id
+            WHERE nodes.node_uuid = $1
+            ORDER BY server_dependencies.dependency_server_id;
+        "#, uuid).fetch_all(db::get()?)).await.map_err(|_| "failed to fetch server dependencies")?;
+
+        let mut server_dependencies = HashMap::<i32, Vec<Dependency>>::new();
+
+        for dependency in dependencies.into_iter() {
+            server_dependencies.entry(dependency.server_id).or_default().push(Dependency {
+                server: dependency.dependency_server_id as u32,
+                healthy: dependency.dependency_wait_healthy,
+            });
+        }
+
+        let servers = servers.into_iter().map(|s| Server {
+            id: s.server_id as u32,
+            tag: Tag {
+                image: s.tag_image,
+                docker_tag: s.tag_docker_tags,
+                healthcheck: Healthcheck {
+                    test: s.tag_healthcheck_test,
+                    interval: s.tag_healthcheck_interval as u64,
+                    timeout: s.tag_healthcheck_timeout as u64,
+                    retries: s.tag_healthcheck_retries as u64,
+                },
+                mounts: s.mount_container_path.unwrap_or_default().into_iter().zip(s.mount_host_path.unwrap_or_default()).map(|(container_path, host_path)| Mount {
+                    container_path,
+                    host_path,
+                }).collect(),
+                env_defs: s.env_def_key.unwrap_or_default().into_iter()
+                    .zip(s.env_def_required.unwrap_or_default())
+                    .zip(s.env_def_type.unwrap_or_default())
+                    .zip(s.env_def_default_value.unwrap_or_default())
+                    .zip(s.env_def_regex.unwrap_or_default())
+                    .zip(s.env_def_min.unwrap_or_default())
+                    .zip(s.env_def_max.unwrap_or_default())
+                    .zip(s.env_def_trim.unwrap_or_default())
+                    .map(|(((((((key, required), env_type), default), regex), min), max), trim)| EnvDef {
+                        key,
+                        required,
+                        env_type: EnvType::from(env_type as u8),
+                        default,
+                        regex,
+                        min: min.map(|min| min as i64),
+                        max: max.map(|max| max as i64),
+                        trim,
+                    })
+                    .collect(),
+                build: match (s.tag_build_git_url, s.tag_build_context_hash) {
+                    (Some(url), _) => Some(BuildContext::Git(url)),
+                    (None, Some(hash)) => Some(BuildContext::Archive(hash)),
+                    (None, None) => None,
+                }.map(|context| Build {
+                    context,
+                    dockerfile: s.tag_build_dockerfile,
+                }),
+            },
+            envs: s.env_key.unwrap_or_default().into_iter().zip(s.env_value.unwrap_or_default()).map(|(key, value)| Env {
+                key,
+                value,
+            }).collect(),
+            networks: s.network_id.unwrap_or_default().into_iter().zip(s.network_local_ip.unwrap_or_default()).map(|(network, ip)| ServerNetwork {
+                network: network as u32,
+                ip: ip as u8,
+            }).collect(),
+            ports: s.port_port.unwrap_or_default().into_iter().zip(s.port_mapped.unwrap_or_default()).zip(s.port_protocol.unwrap_or_default()).map(|((port, mapped), protocol)| Port {
+                port: port as u16,
+                mapped: mapped as u16,
+                protocol: Protocol::from(protocol as u8),
+            }).collect(),
+            isolation: Isolation {
+                policy: IsolationPolicy::from(s.server_isolation_policy as u8),
+                allowlist: s.server_isolation_allowlist,
+            },
+            maintenance: server_maintenance.remove(&s.server_id).unwrap_or_default(),
+            depends_on: server_dependencies.remove(&s.server_id).unwrap_or_default(),
+            update: UpdateStrategy::from(s.server_update_strategy as u8),
+        }).collect();
+
+        let mut sync = SDSyncPacket {
+            networks: networks.into_iter().map(|nw| Network {
+                id: nw.network_id as u32,
+                subnet: nw.network_local_ip as u8,
+            }).collect(),
+            servers,
+            maintenance: node_maintenance,
+            metadata: fetch_metadata(uuid).await?,
+            hash: String::new(),
+            base: String::new(),
+            removed_networks: Vec::new(),
+            removed_servers: Vec::new(),
+            request: None,
+        };
+
+        sync.hash = sync.spec_hash()?;
+
+        Ok(sync)
+    }
+
+    fn writable(&self) -> Result<(), String> {
+        db::writable()
+    }
+
+    async fn assign_ports(&self, uuid: Uuid, server: u32, ports: &[Port]) -> Result<(), String> {
+        for port in ports {
+            // only update servers that actually belong to the reporting daemon
+            db::timed("update_assigned_port", sqlx::query!(r#"
+                UPDATE aesterisk.ports
+                SET port_mapped = $1
+                FROM aesterisk.server_ports
+                JOIN aesterisk.node_servers ON server_ports.server_id = node_servers.server_id
+                JOIN aesterisk.nodes ON node_servers.node_id = nodes.node_id
+                WHERE ports.port_id = server_ports.port_id
+                AND server_ports.server_id = $2
+                AND ports.port_port = $3
+                AND ports.port_protocol = $4
+                AND nodes.node_uuid = $5;
+            "#, port.mapped as i32, server as i32, port.port as i32, port.protocol as i16, uuid).execute(db::get()?)).await.map_err(|e| format!("Failed to update assigned port: {}", e))?;
+
+            info!("Server {} was assigned host port {} for {}/{}", server, port.mapped, port.port, port.protocol);
+        }
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        db::timed("watchdog_ping", sqlx::query_scalar!(r#"SELECT 1 AS "alive!""#).fetch_one(db::get()?)).await.map(|_| ())
+    }
+
+    async fn node_team(&self, uuid: Uuid) -> Result<Option<u32>, String> {
+        let team = db::timed("fetch_node_team", sqlx::query_scalar!(r#"
+            SELECT
+                team_nodes.team_id
+            FROM aesterisk.team_nodes
+            INNER JOIN aesterisk.nodes
+                ON team_nodes.node_id = nodes.node_id
+            WHERE nodes.node_uuid = $1;
+        "#, uuid).fetch_optional(db::get()?)).await?;
+
+        Ok(team.map(|team| team as u32))
+    }
+
+    async fn team_extensions(&self, team: u32) -> Result<Vec<WasmExtension>, String> {
+        let extensions = db::timed("fetch_team_extensions", sqlx::query!(r#"
+            SELECT
+                wasm_extensions.wasm_extension_id,
+                wasm_extensions.wasm_extension_name,
+                wasm_extensions.wasm_extension_module
+            FROM aesterisk.wasm_extensions
+            WHERE wasm_extensions.team_id = $1
+            ORDER BY wasm_extensions.wasm_extension_position, wasm_extensions.wasm_extension_id;
+        "#, team as i32).fetch_all(db::get()?)).await?;
+
+        Ok(extensions.into_iter().map(|extension| WasmExtension {
+            id: extension.wasm_extension_id as u32,
+            name: extension.wasm_extension_name,
+            module: extension.wasm_extension_module,
+        }).collect())
+    }
+
+    async fn group_members(&self, group: u32) -> Result<Vec<Uuid>, String> {
+        db::timed("fetch_group_members", sqlx::query_scalar!(r#"
+            SELECT
+                nodes.node_uuid
+            FROM aesterisk.node_group_members
+            INNER JOIN aesterisk.nodes
+                ON node_group_members.node_id = nodes.node_id
+            WHERE node_group_members.node_group_id = $1;
+        "#, group as i32).fetch_all(db::get()?)).await.map_err(|_| "failed to fetch group members".to_string())
+    }
+
+    async fn assign_server(&self, daemon: Uuid, server: u32) -> Result<bool, String> {
+        let res = db::timed("assign_server", sqlx::query!(r#"
+            INSERT INTO aesterisk.node_servers (
+                node_id,
+                server_id
+            )
+            SELECT
+                nodes.node_id,
+                $2
+            FROM aesterisk.nodes
+            WHERE nodes.node_uuid = $1
+            AND NOT EXISTS (
+                SELECT 1 FROM aesterisk.node_servers WHERE node_servers.server_id = $2
+            );
+        "#, daemon, server as i32).execute(db::get()?)).await.map_err(|e| format!("Failed to assign server: {}", e))?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn update_metadata(&self, uuid: Uuid, server: u32, name: Option<String>, labels: Option<BTreeMap<String, String>>) -> Result<ServerMetadata, String> {
+        let mut tx = db::get()?.begin().await.map_err(|e| format!("Could not start transaction: {}", e))?;
+
+        let name = db::timed("update_server_name", sqlx::query_scalar!(r#"
+            UPDATE aesterisk.servers
+            SET server_name = COALESCE($3, servers.server_name)
+            FROM aesterisk.node_servers
+            INNER JOIN aesterisk.nodes
+                ON node_servers.node_id = nodes.node_id
+            WHERE servers.server_id = $2
+            AND node_servers.server_id = servers.server_id
+            AND nodes.node_uuid = $1
+            RETURNING servers.server_name;
+        "#, uuid, server as i32, name).fetch_optional(&mut *tx)).await?.ok_or_else(|| format!("Server {} is not a server of daemon {}", server, uuid))?;
+
+        if let Some(labels) = labels {
+            let (keys, values): (Vec<String>, Vec<String>) = labels.into_iter().unzip();
+
+            db::timed("clear_server_labels", sqlx::query!(r#"
+                DELETE FROM aesterisk.server_labels WHERE server_id = $1;
+            "#, server as i32).execute(&mut *tx)).await?;
+
+            db::timed("insert_server_labels", sqlx::query!(r#"
+                INSERT INTO aesterisk.server_labels (
+                    server_id,
+                    server_label_key,
+                    server_label_value
+                )
+                SELECT $1, label.key, label.value FROM UNNEST($2::TEXT[], $3::TEXT[]) AS label(key, value);
+            "#, server as i32, &keys, &values).execute(&mut *tx)).await?;
+        }
+
+        let labels = db::timed("fetch_server_labels", sqlx::query!(r#"
+            SELECT
+                server_labels.server_label_key,
+                server_labels.server_label_value
+            FROM aesterisk.server_labels
+            WHERE server_labels.server_id = $1;
+        "#, server as i32).fetch_all(&mut *tx)).await?;
+
+        tx.commit().await.map_err(|e| format!("Could not commit transaction: {}", e))?;
+
+        Ok(ServerMetadata {
+            server,
+            name,
+            labels: labels.into_iter().map(|label| (label.server_label_key, label.server_label_value)).collect(),
+        })
+    }
+
+    async fn build_context(&self, uuid: Uuid, hash: &str) -> Result<Option<Vec<u8>>, String> {
+        db::timed("fetch_build_context", sqlx::query_scalar!(r#"
+            SELECT
+                build_contexts.build_context_data
+            FROM aesterisk.build_contexts
+            WHERE build_contexts.build_context_hash = $2
+            AND EXISTS (
+                SELECT 1
+                FROM aesterisk.nodes
+                INNER JOIN aesterisk.node_servers
+                    ON nodes.node_id = node_servers.node_id
+                INNER JOIN aesterisk.servers
+                    ON node_servers.server_id = servers.server_id
+                INNER JOIN aesterisk.tags
+                    ON servers.server_tag = tags.tag_id
+                WHERE nodes.node_uuid = $1
+                AND tags.tag_build_context_hash = $2
+            );
+        "#, uuid, hash).fetch_optional(db::get()?)).await.map_err(|_| "failed to fetch build context".to_string())
+    }
+
+    async fn catalog(&self, limit: u32) -> Result<Vec<CatalogImage>, String> {
+        struct DbCatalogImage {
+            tag_image: String,
+            tag_docker_tags: String,
+        }
+
+        let images = db::timed("fetch_catalog", sqlx::query_as!(DbCatalogImage, r#"
+            SELECT
+                tags.tag_image,
+                tags.tag_docker_tags
+            FROM aesterisk.servers
+            INNER JOIN aesterisk.tags
+                ON servers.server_tag = tags.tag_id
+            WHERE tags.tag_build_git_url IS NULL
+            AND tags.tag_build_context_hash IS NULL
+            GROUP BY tags.tag_id, tags.tag_image, tags.tag_docker_tags
+            ORDER BY COUNT(*) DESC
+            LIMIT $1;
+        "#, limit as i64).fetch_all(db::get()?)).await.map_err(|_| "failed to fetch catalog")?;
+
+        Ok(images.into_iter().map(|image| CatalogImage {
+            image: image.tag_image,
+            docker_tag: image.tag_docker_tags,
+        }).collect())
+    }
+
+    async fn alert_rules(&self, uuid: Uuid) -> Result<Vec<AlertRule>, String> {
+        struct DbAlertRule {
+            alert_rule_id: i32,
+            server_id: Option<i32>,
+            alert_rule_metric: i16,
+            alert_rule_threshold: f64,
+            alert_rule_duration: i32,
+            alert_rule_webhook: Option<String>,
+        }
+
+        let rules = db::timed("fetch_alert_rules", sqlx::query_as!(DbAlertRule, r#"
+            SELECT
+                alert_rules.alert_rule_id,
+                alert_rules.server_id,
+                alert_rules.alert_rule_metric,
+                alert_rules.alert_rule_threshold,
+                alert_rules.alert_rule_duration,
+                alert_rules.alert_rule_webhook
+            FROM aesterisk.alert_rules
+            INNER JOIN aesterisk.nodes
+                ON alert_rules.node_id = nodes.node_id
+            WHERE nodes.node_uuid = $1;
+        "#, uuid).fetch_all(db::get()?)).await.map_err(|_| "failed to fetch alert rules")?;
+
+        Ok(rules.into_iter().filter_map(|rule| AlertRule::from_db(
+            rule.alert_rule_id as i64,
+            rule.server_id.map(|id| id as i64),
+            rule.alert_rule_metric as i64,
+            rule.alert_rule_threshold,
+            rule.alert_rule_duration as i64,
+            rule.alert_rule_webhook,
+        )).collect())
+    }
+
+    async fn daemon_quotas(&self, uuid: Uuid) -> Result<Vec<DaemonQuota>, String> {
+        let quotas = db::timed("fetch_daemon_quotas", sqlx::query!(r#"
+            SELECT
+                team_quotas.team_id,
+                team_quotas.quota_max_daemons AS "quota_max_daemons!",
+                (
+                    SELECT COUNT(*)
+                    FROM aesterisk.team_nodes AS others
+                    WHERE others.team_id = team_nodes.team_id
+                    AND others.node_id < team_nodes.node_id
+                ) AS "preceding!"
+            FROM aesterisk.nodes
+            INNER JOIN aesterisk.team_nodes
+                ON nodes.node_id = team_nodes.node_id
+            INNER JOIN aesterisk.team_quotas
+                ON team_nodes.team_id = team_quotas.team_id
+            WHERE nodes.node_uuid = $1
+            AND team_quotas.quota_max_daemons IS NOT NULL;
+        "#, uuid).fetch_all(db::get()?)).await?;
+
+        Ok(quotas.into_iter().map(|quota| DaemonQuota {
+            team: quota.team_id as u32,
+            max_daemons: quota.quota_max_daemons as i64,
+            preceding: quota.preceding,
+        }).collect())
+    }
+
+    async fn server_quotas(&self, daemon: Uuid) -> Result<Vec<ServerQuota>, String> {
+        let quotas = db::timed("fetch_server_quotas", sqlx::query!(r#"
+            SELECT
+                team_quotas.team_id,
+                team_quotas.quota_max_servers,
+                team_quotas.quota_max_memory,
+                team_quotas.quota_max_storage,
+                COUNT(servers.server_id) AS "servers!",
+                COALESCE(SUM(servers.server_memory_reservation), 0) AS "memory!",
+                COALESCE(SUM(servers.server_storage_reservation), 0) AS "storage!"
+            FROM aesterisk.nodes
+            INNER JOIN aesterisk.team_nodes
+                ON nodes.node_id = team_nodes.node_id
+            INNER JOIN aesterisk.team_quotas
+                ON team_nodes.team_id = team_quotas.team_id
+            LEFT JOIN aesterisk.team_nodes AS quota_nodes
+                ON team_quotas.team_id = quota_nodes.team_id
+            LEFT JOIN aesterisk.node_servers
+                ON quota_nodes.node_id = node_servers.node_id
+            LEFT JOIN aesterisk.servers
+                ON node_servers.server_id = servers.server_id
+            WHERE nodes.node_uuid = $1
+            GROUP BY team_quotas.team_id;
+        "#, daemon).fetch_all(db::get()?)).await?;
+
+        Ok(quotas.into_iter().map(|quota| ServerQuota {
+            team: quota.team_id as u32,
+            max_servers: quota.quota_max_servers.map(|max| max as i64),
+            max_memory: quota.quota_max_memory.map(|max| max as i64),
+            max_storage: quota.quota_max_storage.map(|max| max as i64),
+            servers: quota.servers,
+            memory: quota.memory,
+            storage: quota.storage,
+        }).collect())
+    }
+
+    async fn server_reservation(&self, server: u32) -> Result<Option<Reservation>, String> {
+        let reservation = db::timed("fetch_server_reservation", sqlx::query!(r#"
+            SELECT
+                servers.server_memory_reservation,
+                servers.server_storage_reservation,
+                EXISTS (
+                    SELECT 1 FROM aesterisk.node_servers
+                    WHERE node_servers.server_id = servers.server_id
+                ) AS "assigned!"
+            FROM aesterisk.servers
+            WHERE servers.server_id = $1;
+        "#, server as i32).fetch_optional(db::get()?)).await?;
+
+        Ok(reservation.map(|reservation| Reservation {
+            memory: reservation.server_memory_reservation as i64,
+            storage: reservation.server_storage_reservation as i64,
+            assigned: reservation.assigned,
+        }))
+    }
+
+    async fn store_session(&self, peer: &Peer, addr: SocketAddr, session: &Session) -> Result<(), String> {
+        let (peer_type, node_uuid, user_id) = match peer {
+            Peer::Daemon(uuid) => ("daemon", Some(*uuid), None),
+            Peer::Web(user_id) => ("web", None, Some(*user_id as i32)),
+        };
+
+        db::timed("insert_connection_session", sqlx::query!(r#"
+            INSERT INTO aesterisk.connection_sessions (
+                session_peer_type,
+                node_uuid,
+                user_id,
+                session_addr,
+                session_connected_at,
+                session_disconnected_at,
+                session_reason,
+                session_bytes_in,
+                session_bytes_out,
+                session_packets_in,
+                session_packets_out
+            ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11);
+        "#,
+            peer_type,
+            node_uuid,
+            user_id,
+            addr.to_string(),
+            session.connected_at as i64,
+            session.disconnected_at as i64,
+            session.reason,
+            session.bytes_in as i64,
+            session.bytes_out as i64,
+            session.packets_in as i64,
+            session.packets_out as i64,
+        ).execute(db::get()?)).await?;
+
+        Ok(())
+    }
+
+    async fn sessions(&self, uuid: Uuid, limit: u32) -> Result<Vec<ConnectionSession>, String> {
+        let sessions = db::timed("fetch_connection_sessions", sqlx::query!(r#"
+            SELECT
+                connection_sessions.session_addr,
+                connection_sessions.session_connected_at,
+                connection_sessions.session_disconnected_at,
+                connection_sessions.session_reason,
+                connection_sessions.session_bytes_in,
+                connection_sessions.session_bytes_out,
+                connection_sessions.session_packets_in,
+                connection_sessions.session_packets_out
+            FROM aesterisk.connection_sessions
+            WHERE connection_sessions.node_uuid = $1
+            ORDER BY connection_sessions.session_connected_at DESC
+            LIMIT $2;
+        "#, uuid, limit as i64).fetch_all(db::get()?)).await?;
+
+        Ok(sessions.into_iter().map(|session| ConnectionSession {
+            addr: session.session_addr,
+            connected_at: session.session_connected_at as u64,
+            disconnected_at: session.session_disconnected_at as u64,
+            reason: session.session_reason,
+            bytes_in: session.session_bytes_in as u64,
+            bytes_out: session.session_bytes_out as u64,
+            packets_in: session.session_packets_in as u64,
+            packets_out: session.session_packets_out as u64,
+        }).collect())
+    }
+
+    async fn delete_sessions(&self, before: u64) -> Result<(), String> {
+        db::timed("delete_old_connection_sessions", sqlx::query!(
+            "DELETE FROM aesterisk.connection_sessions WHERE session_disconnected_at < $1",
+            before as i64,
+        ).execute(db::get()?)).await.map(|_| ())
+    }
+
+    async fn store_notification(&self, recipients: &Recipients, kind: NotificationKind, server: Option<u32>, message: &str, created_at: u64) -> Result<(), String> {
+        let server = server.map(|server| server as i32);
+
+        match recipients {
+            Recipients::Team(daemon) => {
+                db::timed("insert_team_notifications", sqlx::query!(r#"
+                    INSERT INTO aesterisk.notifications (
+                        user_id,
+                        notification_kind,
+                        node_uuid,
+                        server_id,
+                        notification_message,
+                        notification_created_at
+                    )
+                    SELECT
+                        users.user_id,
+                        $2,
+                        nodes.node_uuid,
+                        $3,
+                        $4,
+                        $5
+                    FROM aesterisk.nodes
+                    INNER JOIN aesterisk.team_nodes
+                        ON nodes.node_id = team_nodes.node_id
+                    INNER JOIN aesterisk.users
+                        ON team_nodes.team_id = users.user_team
+                    WHERE nodes.node_uuid = $1;
+                "#, daemon, inbox::kind_to_db(kind), server, message, created_at as i64).execute(db::get()?)).await?;
+            },
+            Recipients::User(user_id) => {
+                db::timed("insert_user_notification", sqlx::query!(r#"
+                    INSERT INTO aesterisk.notifications (
+                        user_id,
+                        notification_kind,
+                        server_id,
+                        notification_message,
+                        notification_created_at
+                    ) VALUES ($1, $2, $3, $4, $5);
+                "#, *user_id as i32, inbox::kind_to_db(kind), server, message, created_at as i64).execute(db::get()?)).await?;
+            },
+        }
+
+        Ok(())
+    }
+
+    async fn notifications(&self, user_id: u32, unread_only: bool, before: Option<u64>, limit: u32) -> Result<(Vec<Notification>, u64), String> {
+        let notifications = db::timed("fetch_notifications", sqlx::query!(r#"
+            SELECT
+                notifications.notification_id,
+                notifications.notification_kind,
+                notifications.node_uuid,
+                notifications.server_id,
+                notifications.notification_message,
+                notifications.notification_created_at,
+                notifications.notification_read
+            FROM aesterisk.notifications
+            WHERE notifications.user_id = $1
+                AND (NOT $2 OR NOT notifications.notification_read)
+                AND ($3::BIGINT IS NULL OR notifications.notification_id < $3)
+            ORDER BY notifications.notification_id DESC
+            LIMIT $4;
+        "#, user_id as i32, unread_only, before.map(|before| before as i64), limit as i64).fetch_all(db::get()?)).await?;
+
+        let unread = db::timed("count_unread_notifications", sqlx::query_scalar!(r#"
+            SELECT COUNT(*) AS "count!"
+            FROM aesterisk.notifications
+            WHERE notifications.user_id = $1
+                AND NOT notifications.notification_read;
+        "#, user_id as i32).fetch_one(db::get()?)).await?;
+
+        let notifications = notifications.into_iter().map(|notification| Ok(Notification {
+            id: notification.notification_id as u64,
+            kind: inbox::kind_from_db(notification.notification_kind)?,
+            daemon: notification.node_uuid,
+            server: notification.server_id.map(|server| server as u32),
+            message: notification.notification_message,
+            created_at: notification.notification_created_at as u64,
+            read: notification.notification_read,
+        })).collect::<Result<Vec<_>, String>>()?;
+
+        Ok((notifications, unread as u64))
+    }
+
+    async fn mark_read(&self, user_id: u32, ids: Option<&[u64]>) -> Result<(), String> {
+        match ids {
+            None => {
+                db::timed("mark_all_notifications_read", sqlx::query!(r#"
+                    UPDATE aesterisk.notifications
+                    SET notification_read = TRUE
+                    WHERE user_id = $1 AND NOT notification_read;
+                "#, user_id as i32).execute(db::get()?)).await?;
+            },
+            Some(ids) => {
+                let ids = ids.iter().map(|id| *id as i64).collect::<Vec<_>>();
+
+                db::timed("mark_notifications_read", sqlx::query!(r#"
+                    UPDATE aesterisk.notifications
+                    SET notification_read = TRUE
+                    WHERE user_id = $1 AND notification_id = ANY($2);
+                "#, user_id as i32, &ids).execute(db::get()?)).await?;
+            },
+        }
+
+        Ok(())
+    }
+
+    async fn delete_notifications(&self, before: u64) -> Result<(), String> {
+        db::timed("delete_old_notifications", sqlx::query!(
+            "DELETE FROM aesterisk.notifications WHERE notification_created_at < $1",
+            before as i64,
+        ).execute(db::get()?)).await.map(|_| ())
+    }
+
+    async fn store_metrics(&self, uuid: Uuid, server: u32, sample: &MetricSample) -> Result<(), String> {
+        db::timed("insert_metrics", sqlx::query!(
+            "INSERT INTO aesterisk.metrics (metric_time, node_uuid, server_id, metric_cpu, metric_memory_used, metric_memory_total, metric_storage_used, metric_storage_total) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+            sample.timestamp as i64,
+            uuid,
+            server as i32,
+            sample.cpu,
+            sample.memory_used,
+            sample.memory_total,
+            sample.storage_used,
+            sample.storage_total,
+        ).execute(db::get()?)).await.map(|_| ())
+    }
+
+    async fn metrics(&self, uuid: Uuid, server: u32, from: i64, to: i64, hourly: bool) -> Result<Vec<MetricSample>, String> {
+        struct MetricRow {
+            metric_time: i64,
+            metric_cpu: Option<f64>,
+            metric_memory_used: Option<f64>,
+            metric_memory_total: Option<f64>,
+            metric_storage_used: Option<f64>,
+            metric_storage_total: Option<f64>,
+        }
+
+        let rows = if hourly {
+            db::timed("fetch_metrics_hourly", sqlx::query_as!(
+                MetricRow,
+                "SELECT metric_time, metric_cpu, metric_memory_used, metric_memory_total, metric_storage_used, metric_storage_total FROM aesterisk.metrics_hourly WHERE node_uuid = $1 AND server_id = $2 AND metric_time >= $3 AND metric_time <= $4 ORDER BY metric_time",
+                uuid,
+                server as i32,
+                from,
+                to,
+            ).fetch_all(db::get()?)).await
+        } else {
+            db::timed("fetch_metrics", sqlx::query_as!(
+                MetricRow,
+                "SELECT metric_time, metric_cpu, metric_memory_used, metric_memory_total, metric_storage_used, metric_storage_total FROM aesterisk.metrics WHERE node_uuid = $1 AND server_id = $2 AND metric_time >= $3 AND metric_time <= $4 ORDER BY metric_time",
+                uuid,
+                server as i32,
+                from,
+                to,
+            ).fetch_all(db::get()?)).await
+        }?;
+
+        Ok(rows.into_iter().map(|row| MetricSample {
+            timestamp: row.metric_time as u64,
+            cpu: row.metric_cpu,
+            memory_used: row.metric_memory_used,
+            memory_total: row.metric_memory_total,
+            storage_used: row.metric_storage_used,
+            storage_total: row.metric_storage_total,
+        }).collect())
+    }
+
+    async fn rollup_metrics(&self, from: i64, to: i64) -> Result<(), String> {
+        db::timed("rollup_metrics", sqlx::query!(
+            r#"INSERT INTO aesterisk.metrics_hourly (metric_time, node_uuid, server_id, metric_cpu, metric_memory_used, metric_memory_total, metric_storage_used, metric_storage_total)
+            SELECT metric_time - metric_time % 3600 AS bucket, node_uuid, server_id, AVG(metric_cpu), AVG(metric_memory_used), AVG(metric_memory_total), AVG(metric_storage_used), AVG(metric_storage_total)
+            FROM aesterisk.metrics
+            WHERE metric_time >= $1 AND metric_time < $2
+            GROUP BY bucket, node_uuid, server_id
+            ON CONFLICT (node_uuid, server_id, metric_time) DO NOTHING"#,
+            from,
+            to,
+        ).execute(db::get()?)).await.map(|_| ())
+    }
+
+    async fn delete_metrics(&self, raw_before: u64, rollups_before: u64) -> Result<(), String> {
+        db::timed("delete_old_metrics", sqlx::query!(
+            "DELETE FROM aesterisk.metrics WHERE metric_time < $1",
+            raw_before as i64,
+        ).execute(db::get()?)).await?;
+
+        db::timed("delete_old_metric_rollups", sqlx::query!(
+            "DELETE FROM aesterisk.metrics_hourly WHERE metric_time < $1",
+            rollups_before as i64,
+        ).execute(db::get()?)).await?;
+
+        Ok(())
+    }
+
+    async fn store_events(&self, events: &[NewEvent]) -> Result<(), String> {
+        let uuids = events.iter().map(|event| event.uuid).collect::<Vec<_>>();
+        let types = events.iter().map(|event| event.event_type).collect::<Vec<_>>();
+        // the sequence numbers are nullable, which the checked array type doesn't allow for
+        let seqs = events.iter().map(|event| event.seq.map(|seq| seq as i64)).collect::<Vec<_>>();
+        let timestamps = events.iter().map(|event| event.timestamp as i64).collect::<Vec<_>>();
+        let data = events.iter().map(|event| event.data.clone()).collect::<Vec<_>>();
+
+        db::timed("insert_events", sqlx::query!(r#"
+            INSERT INTO aesterisk.events (
+                node_uuid,
+                event_type,
+                event_seq,
+                event_time,
+                event_data
+            )
+            SELECT * FROM UNNEST($1::UUID[], $2::SMALLINT[], $3::BIGINT[], $4::BIGINT[], $5::TEXT[]);
+        "#, &uuids, &types, &seqs as _, &timestamps, &data).execute(db::get()?)).await?;
+
+        Ok(())
+    }
+
+    async fn events(&self, uuids: &[Uuid], event_type: i16, limit: u32) -> Result<Vec<(Uuid, StoredEvent)>, String> {
+        struct DbEvent {
+            node_uuid: Uuid,
+            event_seq: Option<i64>,
+            event_time: i64,
+            event_data: String,
+        }
+
+        let rows = db::timed("fetch_events", sqlx::query_as!(DbEvent, r#"
+            SELECT
+                node_uuid AS "node_uuid!",
+                event_seq,
+                event_time AS "event_time!",
+                event_data AS "event_data!"
+            FROM (
+                SELECT
+                    node_uuid,
+                    event_seq,
+                    event_time,
+                    event_data,
+                    ROW_NUMBER() OVER (PARTITION BY node_uuid ORDER BY event_id DESC) AS event_rank
+                FROM aesterisk.events
+                WHERE node_uuid = ANY($1)
+                AND event_type = $2
+            ) AS ranked
+            WHERE event_rank <= $3
+            ORDER BY node_uuid, event_rank DESC;
+        "#, uuids, event_type, limit as i64).fetch_all(db::get()?)).await?;
+
+        rows.into_iter().map(|row| Ok((row.node_uuid, StoredEvent::from_db(row.event_seq, row.event_time, &row.event_data)?))).collect()
+    }
+
+    async fn delete_events(&self, before: u64) -> Result<(), String> {
+        db::timed("delete_old_events", sqlx::query!(
+            "DELETE FROM aesterisk.events WHERE event_time < $1",
+            before as i64,
+        ).execute(db::get()?)).await.map(|_| ())
+    }
+
+    async fn user_team(&self, user_id: u32) -> Result<u32, String> {
+        let team = db::timed("fetch_user_team", sqlx::query_scalar!(r#"
+            SELECT user_team FROM aesterisk.users WHERE user_id = $1;
+        "#, user_id as i32).fetch_one(db::get()?)).await?;
+
+        Ok(team as u32)
+    }
+
+    async fn team_nodes(&self, team: u32) -> Result<Vec<Uuid>, String> {
+        db::timed("fetch_team_nodes", sqlx::query_scalar!(r#"
+            SELECT
+                nodes.node_uuid
+            FROM aesterisk.team_nodes
+            INNER JOIN aesterisk.nodes
+                ON team_nodes.node_id = nodes.node_id
+            WHERE team_nodes.team_id = $1;
+        "#, team as i32).fetch_all(db::get()?)).await
+    }
+
+    async fn spec_tags(&self, team: u32, names: &[String]) -> Result<HashMap<String, TagState>, String> {
+        let tags = db::timed("fetch_spec_tags", sqlx::query_as!(DbTag, r#"
+            SELECT DISTINCT ON (tags.tag_name)
+                tags.tag_id,
+                tags.tag_team,
+                tags.tag_name,
+                tags.tag_image,
+                tags.tag_docker_tags,
+                tags.tag_healthcheck_test,
+                tags.tag_healthcheck_interval,
+                tags.tag_healthcheck_timeout,
+                tags.tag_healthcheck_retries
+            FROM aesterisk.tags
+            WHERE tags.tag_name = ANY($1)
+            AND (tags.tag_team = $2 OR tags.tag_team IS NULL)
+            ORDER BY tags.tag_name, tags.tag_team IS NULL, tags.tag_id;
+        "#, names, team as i32).fetch_all(db::get()?)).await?;
+
+        Ok(tags.into_iter().map(|tag| (tag.tag_name.clone(), TagState::from(tag))).collect())
+    }
+
+    async fn node_tags(&self, uuids: &[Uuid]) -> Result<Vec<TagSpec>, String> {
+        let tags = db::timed("fetch_spec_node_tags", sqlx::query_as!(DbTag, r#"
+            SELECT DISTINCT ON (tags.tag_name)
+                tags.tag_id,
+                tags.tag_team,
+                tags.tag_name,
+                tags.tag_image,
+                tags.tag_docker_tags,
+                tags.tag_healthcheck_test,
+                tags.tag_healthcheck_interval,
+                tags.tag_healthcheck_timeout,
+                tags.tag_healthcheck_retries
+            FROM aesterisk.tags
+            WHERE tags.tag_id IN (
+                SELECT servers.server_tag FROM aesterisk.node_servers
+                INNER JOIN aesterisk.nodes
+                    ON node_servers.node_id = nodes.node_id
+                INNER JOIN aesterisk.servers
+                    ON node_servers.server_id = servers.server_id
+                WHERE nodes.node_uuid = ANY($1)
+            )
+            ORDER BY tags.tag_name, tags.tag_team IS NULL, tags.tag_id;
+        "#, uuids).fetch_all(db::get()?)).await?;
+
+        Ok(tags.into_iter().map(|tag| TagState::from(tag).tag).collect())
+    }
+
+    async fn spec_nodes(&self, uuids: &[Uuid]) -> Result<HashMap<Uuid, NodeState>, String> {
+        struct DbNode {
+            node_id: i32,
+            node_uuid: Uuid,
+        }
+
+        let nodes = db::timed("fetch_spec_nodes", sqlx::query_as!(DbNode, r#"
+            SELECT
+                nodes.node_id,
+                nodes.node_uuid
+            FROM aesterisk.nodes
+            WHERE nodes.node_uuid = ANY($1);
+        "#, uuids).fetch_all(db::get()?)).await?;
+
+        let ids = nodes.iter().map(|node| node.node_id).collect::<Vec<_>>();
+
+        let mut states = nodes.into_iter().map(|node| (node.node_id, (node.node_uuid, NodeState {
+            id: node.node_id,
+            networks: Vec::new(),
+            servers: Vec::new(),
+        }))).collect::<HashMap<_, _>>();
+
+        struct DbNetwork {
+            node_id: i32,
+            network_id: i32,
+            network_name: String,
+            network_local_ip: i16,
+        }
+
+        let networks = db::timed("fetch_spec_networks", sqlx::query_as!(DbNetwork, r#"
+            SELECT
+                node_networks.node_id,
+                networks.network_id,
+                networks.network_name,
+                networks.network_local_ip
+            FROM aesterisk.node_networks
+            INNER JOIN aesterisk.networks
+                ON node_networks.network_id = networks.network_id
+            WHERE node_networks.node_id = ANY($1)
+            ORDER BY networks.network_id;
+        "#, &ids).fetch_all(db::get()?)).await?;
+
+        let network_names = networks.iter().map(|network| (network.network_id, network.network_name.clone())).collect::<HashMap<_, _>>();
+
+        for network in networks.into_iter() {
+            if let Some((_, state)) = states.get_mut(&network.node_id) {
+                state.networks.push((network.network_id, NetworkSpec {
+                    name: network.network_name,
+                    local_ip: network.network_local_ip as u8,
+                }));
+            }
+        }
+
+        struct DbServer {
+            node_id: i32,
+            server_id: i32,
+            server_name: String,
+            tag_name: String,
+            env_key: Vec<String>,
+            env_value: Vec<String>,
+            env_secret: Vec<bool>,
+            port_port: Vec<i32>,
+            port_protocol: Vec<i16>,
+            port_mapped: Vec<i32>,
+            network_id: Vec<i32>,
+            network_local_ip: Vec<i16>,
+        }
+
+        let servers = db::timed("fetch_spec_servers", sqlx::query_as!(DbServer, r#"
+            SELECT
+                node_servers.node_id,
+                servers.server_id,
+                servers.server_name,
+                tags.tag_name,
+                ARRAY(
+                    SELECT envs.env_key FROM aesterisk.server_envs
+                    INNER JOIN aesterisk.envs ON server_envs.env_id = envs.env_id
+                    WHERE server_envs.server_id = servers.server_id
+                    ORDER BY envs.env_id
+                ) AS "env_key!",
+                ARRAY(
+                    SELECT envs.env_value FROM aesterisk.server_envs
+                    INNER JOIN aesterisk.envs ON server_envs.env_id = envs.env_id
+                    WHERE server_envs.server_id = servers.server_id
+                    ORDER BY envs.env_id
+                ) AS "env_value!",
+                ARRAY(
+                    SELECT envs.env_secret FROM aesterisk.server_envs
+                    INNER JOIN aesterisk.envs ON server_envs.env_id = envs.env_id
+                    WHERE server_envs.server_id = servers.server_id
+                    ORDER BY envs.env_id
+                ) AS "env_secret!",
+                ARRAY(
+                    SELECT ports.port_port FROM aesterisk.server_ports
+                    INNER JOIN aesterisk.ports ON server_ports.port_id = ports.port_id
+                    WHERE server_ports.server_id = servers.server_id
+                    ORDER BY ports.port_id
+                ) AS "port_port!",
+                ARRAY(
+                    SELECT ports.port_protocol FROM aesterisk.server_ports
+                    INNER JOIN aesterisk.ports ON server_ports.port_id = ports.port_id
+                    WHERE server_ports.server_id = servers.server_id
+                    ORDER BY ports.port_id
+                ) AS "port_protocol!",
+                ARRAY(
+                    SELECT ports.port_mapped FROM aesterisk.server_ports
+                    INNER JOIN aesterisk.ports ON server_ports.port_id = ports.port_id
+                    WHERE server_ports.server_id = servers.server_id
+                    ORDER BY ports.port_id
+                ) AS "port_mapped!",
+                ARRAY(
+                    SELECT server_networks.network_id FROM aesterisk.server_networks
+                    WHERE server_networks.server_id = servers.server_id
+                    ORDER BY server_networks.network_id
+                ) AS "network_id!",
+                ARRAY(
+                    SELECT server_networks.local_ip FROM aesterisk.server_networks
+                    WHERE server_networks.server_id = servers.server_id
+                    ORDER BY server_networks.network_id
+                ) AS "network_local_ip!"
+            FROM aesterisk.node_servers
+            INNER JOIN aesterisk.servers
+                ON node_servers.server_id = servers.server_id
+            INNER JOIN aesterisk.tags
+                ON servers.server_tag = tags.tag_id
+            WHERE node_servers.node_id = ANY($1)
+            ORDER BY servers.server_id;
+        "#, &ids).fetch_all(db::get()?)).await?;
+
+        for server in servers.into_iter() {
+            if let Some((_, state)) = states.get_mut(&server.node_id) {
+                state.servers.push((server.server_id, ServerSpec {
+                    name: server.server_name,
+                    tag: server.tag_name,
+                    secrets: server.env_key.iter().zip(server.env_secret).filter(|(_, secret)| *secret).map(|(key, _)| key.clone()).collect(),
+                    envs: server.env_key.into_iter().zip(server.env_value).collect(),
+                    ports: server.port_port.into_iter().zip(server.port_protocol).zip(server.port_mapped).map(|((port, protocol), mapped)| PortSpec {
+                        port: port as u16,
+                        protocol: PortProtocol::from(protocol),
+                        mapped: mapped as u16,
+                    }).collect(),
+                    // networks of other nodes can't be referenced by name, and are left out
+                    networks: server.network_id.into_iter().zip(server.network_local_ip).filter_map(|(network, ip)| Some(ServerNetworkSpec {
+                        network: network_names.get(&network)?.clone(),
+                        ip: ip as u8,
+                    })).collect(),
+                    isolation: None,
+                    depends_on: Vec::new(),
+                    update: UpdateSpec::default(),
+                    labels: BTreeMap::new(),
+                }));
+            }
+        }
+
+        Ok(states.into_values().collect())
+    }
+
+    async fn apply(&self, mut plan: Plan) -> Result<(), String> {
+        let mut tx = db::get()?.begin().await.map_err(|e| format!("Could not start transaction: {}", e))?;
+
+        for change in plan.changes.iter() {
+            match change {
+                Change::CreateTag { tag } => {
+                    let id = db::timed("import_create_tag", sqlx::query_scalar!(r#"
+                        INSERT INTO aesterisk.tags (
+                            tag_team,
+                            tag_name,
+                            tag_image,
+                            tag_docker_tags,
+                            tag_healthcheck_test,
+                            tag_healthcheck_interval,
+                            tag_healthcheck_timeout,
+                            tag_healthcheck_retries
+                        ) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+                        RETURNING tag_id;
+                    "#, plan.team as i32, tag.name, tag.image, tag.docker_tag, &tag.healthcheck.test, tag.healthcheck.interval as i32, tag.healthcheck.timeout as i32, tag.healthcheck.retries as i32).fetch_one(&mut *tx)).await?;
+
+                    plan.tags.insert(tag.name.clone(), id);
+                },
+                Change::UpdateTag { id, tag } => {
+                    db::timed("import_update_tag", sqlx::query!(r#"
+                        UPDATE aesterisk.tags
+                        SET
+                            tag_image = $3,
+                            tag_docker_tags = $4,
+                            tag_healthcheck_test = $5,
+                            tag_healthcheck_interval = $6,
+                            tag_healthcheck_timeout = $7,
+                            tag_healthcheck_retries = $8
+                        WHERE tag_id = $1
+                        AND tag_team = $2;
+                    "#, id, plan.team as i32, tag.image, tag.docker_tag, &tag.healthcheck.test, tag.healthcheck.interval as i32, tag.healthcheck.timeout as i32, tag.healthcheck.retries as i32).execute(&mut *tx)).await?;
+                },
+                Change::DeleteServer { id, .. } => {
+                    clear_server(&mut tx, *id).await?;
+
+                    db::timed("import_delete_server", sqlx::query!(r#"
+                        WITH node_servers AS (
+                            DELETE FROM aesterisk.node_servers WHERE server_id = $1
+                        ), maintenance_windows AS (
+                            DELETE FROM aesterisk.maintenance_windows WHERE server_id = $1
+                        ), alert_rules AS (
+                            DELETE FROM aesterisk.alert_rules WHERE server_id = $1
+                        ), automation_rules AS (
+                            DELETE FROM aesterisk.automation_rules WHERE server_id = $1
+                        ), server_dependencies AS (
+                            DELETE FROM aesterisk.server_dependencies WHERE server_id = $1 OR dependency_server_id = $1
+                        ), server_labels AS (
+                            DELETE FROM aesterisk.server_labels WHERE server_id = $1
+                        )
+                        DELETE FROM aesterisk.servers WHERE server_id = $1;
+                    "#, id).execute(&mut *tx)).await?;
+                },
+                Change::DeleteNetwork { id, .. } => {
+                    db::timed("import_delete_network", sqlx::query!(r#"
+                        WITH server_networks AS (
+                            DELETE FROM aesterisk.server_networks WHERE network_id = $1
+                        ), node_networks AS (
+                            DELETE FROM aesterisk.node_networks WHERE network_id = $1
+                        )
+                        DELETE FROM aesterisk.networks WHERE network_id = $1;
+                    "#, id).execute(&mut *tx)).await?;
+                },
+                Change::CreateNetwork { node_id, network, .. } => {
+                    let id = db::timed("import_create_network", sqlx::query_scalar!(r#"
+                        WITH network AS (
+                            INSERT INTO aesterisk.networks (
+                                network_name,
+                                network_local_ip
+                            ) VALUES ($2, $3)
+                            RETURNING network_id
+                        )
+                        INSERT INTO aesterisk.node_networks (
+                            node_id,
+                            network_id
+                        )
+                        SELECT $1, network.network_id FROM network
+                        RETURNING network_id;
+                    "#, node_id, network.name, network.local_ip as i16).fetch_one(&mut *tx)).await?;
+
+                    plan.networks.insert((*node_id, network.name.clone()), id);
+                },
+                Change::UpdateNetwork { id, network, .. } => {
+                    db::timed("import_update_network", sqlx::query!(r#"
+                        UPDATE aesterisk.networks
+                        SET network_local_ip = $2
+                        WHERE network_id = $1;
+                    "#, id, network.local_ip as i16).execute(&mut *tx)).await?;
+                },
+                Change::CreateServer { node_id, server, .. } => {
+                    let tag = *plan.tags.get(&server.tag).ok_or_else(|| format!("Tag {} does not exist", server.tag))?;
+
+                    let id = db::timed("import_create_server", sqlx::query_scalar!(r#"
+                        WITH server AS (
+                            INSERT INTO aesterisk.servers (
+                                server_name,
+                                server_tag
+                            ) VALUES ($2, $3)
+                            RETURNING server_id
+                        )
+                        INSERT INTO aesterisk.node_servers (
+                            node_id,
+                            server_id
+                        )
+                        SELECT $1, server.server_id FROM server
+                        RETURNING server_id;
+                    "#, node_id, server.name, tag).fetch_one(&mut *tx)).await?;
+
+                    insert_server(&mut tx, id, *node_id, server, &plan.networks).await?;
+                },
+                Change::UpdateServer { id, server, .. } => {
+                    let tag = *plan.tags.get(&server.tag).ok_or_else(|| format!("Tag {} does not exist", server.tag))?;
+
+                    let node_id = db::timed("import_update_server", sqlx::query_scalar!(r#"
+                        UPDATE aesterisk.servers
+                        SET server_tag = $2
+                        FROM aesterisk.node_servers
+                        WHERE servers.server_id = $1
+                        AND node_servers.server_id = servers.server_id
+                        RETURNING node_servers.node_id;
+                    "#, id, tag).fetch_one(&mut *tx)).await?;
+
+                    clear_server(&mut tx, *id).await?;
+                    insert_server(&mut tx, *id, node_id, server, &plan.networks).await?;
+                },
+            }
+        }
+
+        tx.commit().await.map_err(|e| format!("Could not commit transaction: {}", e))
+    }
+}
+
+struct DbTag {
+    tag_id: i32,
+    tag_team: Option<i32>,
+    tag_name: String,
+    tag_image: String,
+    tag_docker_tags: String,
+    tag_healthcheck_test: Vec<String>,
+    tag_healthcheck_interval: i32,
+    tag_healthcheck_timeout: i32,
+    tag_healthcheck_retries: i32,
+}
+
+impl From<DbTag> for TagState {
+    fn from(tag: DbTag) -> Self {
+        TagState {
+            id: tag.tag_id,
+            owned: tag.tag_team.is_some(),
+            tag: TagSpec {
+                name: tag.tag_name,
+                image: tag.tag_image,
+                docker_tag: tag.tag_docker_tags,
+                healthcheck: HealthcheckSpec {
+                    test: tag.tag_healthcheck_test,
+                    interval: tag.tag_healthcheck_interval.max(0) as u32,
+                    timeout: tag.tag_healthcheck_timeout.max(0) as u32,
+                    retries: tag.tag_healthcheck_retries.max(0) as u32,
+                },
+                mounts: Vec::new(),
+                env_defs: Vec::new(),
+            },
+        }
+    }
+}
+
+/// Returns the display metadata of all servers of a daemon.
+async fn fetch_metadata(uuid: Uuid) -> Result<Vec<ServerMetadata>, String> {
+    let servers = db::timed("fetch_server_metadata", sqlx::query!(r#"
+        SELECT
+            servers.server_id,
+            servers.server_name,
+            ARRAY_REMOVE(ARRAY_AGG(server_labels.server_label_key ORDER BY server_labels.server_label_key), NULL) AS "label_keys!",
+            ARRAY_REMOVE(ARRAY_AGG(server_labels.server_label_value ORDER BY server_labels.server_label_key), NULL) AS "label_values!"
+        FROM aesterisk.nodes
+        INNER JOIN aesterisk.node_servers
+            ON nodes.node_id = node_servers.node_id
+        INNER JOIN aesterisk.servers
+            ON node_servers.server_id = servers.server_id
+        LEFT JOIN aesterisk.server_labels
+            ON servers.server_id = server_labels.server_id
+        WHERE nodes.node_uuid = $1
+        GROUP BY servers.server_id
+        ORDER BY servers.server_id;
+    "#, uuid).fetch_all(db::get()?)).await?;
+
+    Ok(servers.into_iter().map(|server| ServerMetadata {
+        server: server.server_id as u32,
+        name: server.server_name,
+        labels: server.label_keys.into_iter().zip(server.label_values).collect(),
+    }).collect())
+}
+
+/// Deletes the envs, ports and network addresses of a server.
+async fn clear_server(tx: &mut Transaction<'_, sqlx::Postgres>, server: i32) -> Result<(), String> {
+    db::timed("import_clear_server", sqlx::query!(r#"
+        WITH server_envs AS (
+            DELETE FROM aesterisk.server_envs WHERE server_id = $1 RETURNING env_id
+        ), envs AS (
+            DELETE FROM aesterisk.envs WHERE env_id IN (SELECT env_id FROM server_envs)
+        ), server_ports AS (
+            DELETE FROM aesterisk.server_ports WHERE server_id = $1 RETURNING port_id
+        ), ports AS (
+            DELETE FROM aesterisk.ports WHERE port_id IN (SELECT port_id FROM server_ports)
+        )
+        DELETE FROM aesterisk.server_networks WHERE server_id = $1;
+    "#, server).execute(&mut **tx)).await?;
+
+    Ok(())
+}
+
+/// Inserts the envs, ports and network addresses of a server.
+async fn insert_server(tx: &mut Transaction<'_, sqlx::Postgres>, server: i32, node: i32, spec: &ServerSpec, networks: &HashMap<(i32, String), i32>) -> Result<(), String> {
+    let (keys, values): (Vec<_>, Vec<_>) = spec.envs.clone().into_iter().unzip();
+    let secrets = keys.iter().map(|key| spec.secrets.contains(key)).collect::<Vec<_>>();
+
+    db::timed("import_insert_envs", sqlx::query!(r#"
+        WITH envs AS (
+            INSERT INTO aesterisk.envs (
+                env_key,
+                env_value,
+                env_secret
+            )
+            SELECT env.key, env.value, env.secret FROM UNNEST($2::TEXT[], $3::TEXT[], $4::BOOLEAN[]) AS env(key, value, secret)
+            RETURNING env_id
+        )
+        INSERT INTO aesterisk.server_envs (
+            server_id,
+            env_id
+        )
+        SELECT $1, envs.env_id FROM envs;
+    "#, server, &keys, &values, &secrets).execute(&mut **tx)).await?;
+
+    let ports = spec.ports.iter().map(|port| port.port as i32).collect::<Vec<_>>();
+    let protocols = spec.ports.iter().map(|port| i16::from(port.protocol)).collect::<Vec<_>>();
+    let mapped = spec.ports.iter().map(|port| port.mapped as i32).collect::<Vec<_>>();
+
+    db::timed("import_insert_ports", sqlx::query!(r#"
+        WITH ports AS (
+            INSERT INTO aesterisk.ports (
+                port_port,
+                port_protocol,
+                port_mapped
+            )
+            SELECT port.port, port.protocol, port.mapped FROM UNNEST($2::INTEGER[], $3::SMALLINT[], $4::INTEGER[]) AS port(port, protocol, mapped)
+            RETURNING port_id
+        )
+        INSERT INTO aesterisk.server_ports (
+            server_id,
+            port_id
+        )
+        SELECT $1, ports.port_id FROM ports;
+    "#, server, &ports, &protocols, &mapped).execute(&mut **tx)).await?;
+
+    let (network_ids, ips): (Vec<_>, Vec<_>) = spec.networks.iter().map(|network| {
+        let id = networks.get(&(node, network.network.clone())).ok_or_else(|| format!("Network {} does not exist", network.network))?;
+        Ok((*id, network.ip as i16))
+    }).collect::<Result<Vec<_>, String>>()?.into_iter().unzip();
+
+    db::timed("import_insert_server_networks", sqlx::query!(r#"
+        INSERT INTO aesterisk.server_networks (
+            server_id,
+            network_id,
+            local_ip
+        )
+        SELECT $1, network.id, network.ip FROM UNNEST($2::INTEGER[], $3::SMALLINT[]) AS network(id, ip);
+    "#, server, &network_ids, &ips).execute(&mut **tx)).await?;
+
+    Ok(())
+}
@@ -0,0 +1,76 @@
+use sqlx::types::Uuid;
+
+use crate::repository;
+
+/// The daemon quota of a team owning a node, and how many of the team's nodes are older.
+pub struct DaemonQuota {
+    pub team: u32,
+    pub max_daemons: i64,
+    pub preceding: i64,
+}
+
+/// The server, memory and storage (in MB) quotas of a team owning a node, `None` meaning unlimited,
+/// and what the servers on the team's nodes use up already.
+pub struct ServerQuota {
+    pub team: u32,
+    pub max_servers: Option<i64>,
+    pub max_memory: Option<i64>,
+    pub max_storage: Option<i64>,
+    pub servers: i64,
+    pub memory: i64,
+    pub storage: i64,
+}
+
+/// The memory and storage (in MB) a server reserves, and whether it is assigned to a node.
+pub struct Reservation {
+    pub memory: i64,
+    pub storage: i64,
+    pub assigned: bool,
+}
+
+/// Returns why a daemon can't be accepted, if one of the teams owning its node has more nodes than
+/// its daemon quota allows. The oldest nodes of a team are accepted first.
+pub async fn check_daemon(uuid: Uuid) -> Result<Option<String>, String> {
+    let quotas = repository::get().daemon_quotas(uuid).await?;
+
+    Ok(quotas.into_iter()
+        .find(|quota| quota.preceding >= quota.max_daemons)
+        .map(|quota| format!("Team {} has reached its quota of {} daemons", quota.team, quota.max_daemons)))
+}
+
+/// Returns which quota would be exceeded, if `servers` servers reserving `memory` and `storage` (in
+/// MB) in total were added to a daemon.
+pub async fn check_servers(daemon: Uuid, servers: i64, memory: i64, storage: i64) -> Result<Option<String>, String> {
+    let quotas = repository::get().server_quotas(daemon).await?;
+
+    for quota in quotas.into_iter() {
+        if let Some(max) = quota.max_servers
+            && quota.servers + servers > max {
+            return Ok(Some(format!("Team {} has reached its quota of {} servers", quota.team, max)));
+        }
+
+        if let Some(max) = quota.max_memory
+            && quota.memory + memory > max {
+            return Ok(Some(format!("Team {} would exceed its memory quota of {} MB ({} MB reserved)", quota.team, max, quota.memory)));
+        }
+
+        if let Some(max) = quota.max_storage
+            && quota.storage + storage > max {
+            return Ok(Some(format!("Team {} would exceed its storage quota of {} MB ({} MB reserved)", quota.team, max, quota.storage)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// Returns which quota would be exceeded, if an existing server was assigned to a daemon. Servers
+/// that already are assigned are counted by `check_servers` already, and are never reassigned.
+pub async fn check_placement(server: u32, daemon: Uuid) -> Result<Option<String>, String> {
+    let reservation = repository::get().server_reservation(server).await?.ok_or_else(|| format!("Server {} does not exist", server))?;
+
+    if reservation.assigned {
+        return Ok(None);
+    }
+
+    check_servers(daemon, 1, reservation.memory, reservation.storage).await
+}
@@ -0,0 +1,102 @@
+use std::{fmt::{Display, Formatter}, time::Instant};
+
+use packet::ID;
+
+use crate::config::{RateLimit, CONFIG};
+
+/// A class of web client packets sharing a rate limit, see `config::RateLimits`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PacketClass {
+    Listen,
+    Query,
+    Command,
+}
+
+impl PacketClass {
+    /// Returns the class of a packet, or `None` if packets of its type aren't rate limited.
+    pub fn of(id: &ID) -> Option<Self> {
+        match id {
+            ID::WSListen | ID::WSUnlisten => Some(Self::Listen),
+            ID::WSQueryLogs
+            | ID::WSQueryStats
+            | ID::WSQuerySnapshot
+            | ID::WSQueryTop
+            | ID::WSQueryTasks
+            | ID::WSQueryUsage
+            | ID::WSQueryTeamUsage
+            | ID::WSQueryMetrics
+            | ID::WSQueryConnections
+            | ID::WSQueryNotifications
+            | ID::WSExportSpec => Some(Self::Query),
+            ID::WSSyncGroup
+            | ID::WSPlaceServer
+            | ID::WSImportSpec
+            | ID::WSServerMetadata
+            | ID::WSCancelTask
+            | ID::WSMarkNotificationsRead
+            | ID::WSCommand
+            | ID::WSTerminalOpen => Some(Self::Command),
+            _ => None,
+        }
+    }
+
+    /// Returns the configured limit of the class.
+    pub fn limit(&self) -> &'static RateLimit {
+        match self {
+            Self::Listen => &CONFIG.rate_limits.listens,
+            Self::Query => &CONFIG.rate_limits.queries,
+            Self::Command => &CONFIG.rate_limits.commands,
+        }
+    }
+}
+
+impl Display for PacketClass {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Listen => write!(f, "listen"),
+            Self::Query => write!(f, "query"),
+            Self::Command => write!(f, "command"),
+        }
+    }
+}
+
+/// A token bucket, holding up to `burst` tokens and refilling at `rate` tokens per `period`. Every
+/// packet takes a token.
+pub struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    /// Creates a full bucket.
+    pub fn new(limit: &RateLimit) -> Self {
+        Self {
+            tokens: limit.burst as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refilled(&self, limit: &RateLimit) -> f64 {
+        let refill = self.updated.elapsed().as_secs_f64() * limit.rate as f64 / limit.period.max(1) as f64;
+
+        (self.tokens + refill).min(limit.burst as f64)
+    }
+
+    /// Takes a token, returning `false` if the bucket is empty.
+    pub fn take(&mut self, limit: &RateLimit) -> bool {
+        self.tokens = self.refilled(limit);
+        self.updated = Instant::now();
+
+        if self.tokens < 1.0 {
+            return false;
+        }
+
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// Returns whether the bucket has refilled completely, in which case it can be dropped.
+    pub fn is_full(&self, limit: &RateLimit) -> bool {
+        self.refilled(limit) >= limit.burst as f64
+    }
+}
@@ -0,0 +1,130 @@
+use std::{collections::{BTreeMap, HashMap}, net::SocketAddr};
+
+use async_trait::async_trait;
+use lazy_static::lazy_static;
+use packet::{events::MetricSample, server_daemon::{catalog::CatalogImage, sync::{Port, SDSyncPacket, ServerMetadata}}, server_web::{query_connections_response::ConnectionSession, query_notifications_response::{Notification, NotificationKind}}};
+use sqlx::types::Uuid;
+
+use crate::{alerts::AlertRule, history::{NewEvent, StoredEvent}, import::Plan, inbox::Recipients, postgres::Postgres, quotas::{DaemonQuota, Reservation, ServerQuota}, sessions::{Peer, Session}, spec::{NodeState, TagSpec, TagState}, sqlite::{self, Sqlite}, standalone::{self, Standalone}};
+
+lazy_static! {
+    static ref REPOSITORY: Box<dyn Repository> = if standalone::enabled() {
+        Box::new(Standalone)
+    } else if sqlite::enabled() {
+        Box::new(Sqlite)
+    } else {
+        Box::new(Postgres)
+    };
+}
+
+/// A WASM module a team uploaded to filter and transform the events of its nodes, see `wasm`.
+#[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+pub struct WasmExtension {
+    pub id: u32,
+    pub name: String,
+    pub module: Vec<u8>,
+}
+
+/// `Repository` stores the nodes, networks and servers daemons are synced with, which users can
+/// access them, and what the server records about them: metrics, status events, connection
+/// sessions and notifications. Every backend behaves the same towards daemons and web clients.
+#[async_trait]
+pub trait Repository: Send + Sync {
+    /// Returns the daemons of the nodes a user can access, those of the user's team.
+    async fn team_daemons(&self, user_id: u32) -> Result<Vec<Uuid>, String>;
+    /// Assembles the full spec of a daemon.
+    async fn spec(&self, uuid: Uuid) -> Result<SDSyncPacket, String>;
+    /// Checks that specs can be synced and host ports stored, see `db::writable`.
+    fn writable(&self) -> Result<(), String>;
+    /// Stores the host ports a daemon assigned to one of its servers, so later syncs keep them.
+    async fn assign_ports(&self, uuid: Uuid, server: u32, ports: &[Port]) -> Result<(), String>;
+    /// Checks that the repository can be queried.
+    async fn ping(&self) -> Result<(), String>;
+
+    /// Returns the team owning the node of a daemon, if any. Only WASM extensions need it.
+    #[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+    async fn node_team(&self, uuid: Uuid) -> Result<Option<u32>, String>;
+    /// Returns the WASM extensions a team uploaded, in the order they are run.
+    #[cfg_attr(not(feature = "wasm"), allow(dead_code))]
+    async fn team_extensions(&self, team: u32) -> Result<Vec<WasmExtension>, String>;
+    /// Returns the daemons of the nodes in a node group.
+    async fn group_members(&self, group: u32) -> Result<Vec<Uuid>, String>;
+    /// Assigns a server to a daemon, unless it already is assigned to one. Returns whether it was
+    /// assigned.
+    async fn assign_server(&self, daemon: Uuid, server: u32) -> Result<bool, String>;
+    /// Updates the friendly name and/or the custom labels of a server of a daemon, and returns its
+    /// resulting metadata.
+    async fn update_metadata(&self, uuid: Uuid, server: u32, name: Option<String>, labels: Option<BTreeMap<String, String>>) -> Result<ServerMetadata, String>;
+    /// Returns an uploaded build context archive, or `None` if it doesn't exist or none of the
+    /// daemon's servers are built from it.
+    async fn build_context(&self, uuid: Uuid, hash: &str) -> Result<Option<Vec<u8>>, String>;
+    /// Returns the `limit` images used by the most servers, except for images built by daemons.
+    async fn catalog(&self, limit: u32) -> Result<Vec<CatalogImage>, String>;
+
+    /// Returns the alert rules of a daemon.
+    async fn alert_rules(&self, uuid: Uuid) -> Result<Vec<AlertRule>, String>;
+
+    /// Returns the daemon quotas of the teams owning the node of a daemon.
+    async fn daemon_quotas(&self, uuid: Uuid) -> Result<Vec<DaemonQuota>, String>;
+    /// Returns the server, memory and storage quotas of the teams owning the node of a daemon,
+    /// along with what their nodes use up already.
+    async fn server_quotas(&self, daemon: Uuid) -> Result<Vec<ServerQuota>, String>;
+    /// Returns the memory and storage reserved by a server, or `None` if it doesn't exist.
+    async fn server_reservation(&self, server: u32) -> Result<Option<Reservation>, String>;
+
+    /// Stores a connection that has ended.
+    async fn store_session(&self, peer: &Peer, addr: SocketAddr, session: &Session) -> Result<(), String>;
+    /// Returns the latest `limit` sessions of a daemon, newest first.
+    async fn sessions(&self, uuid: Uuid, limit: u32) -> Result<Vec<ConnectionSession>, String>;
+    /// Deletes the sessions that ended before `before`.
+    async fn delete_sessions(&self, before: u64) -> Result<(), String>;
+
+    /// Stores a notification in the inbox of its recipients.
+    async fn store_notification(&self, recipients: &Recipients, kind: NotificationKind, server: Option<u32>, message: &str, created_at: u64) -> Result<(), String>;
+    /// Returns the latest `limit` notifications of a user (older than `before`, if set), newest
+    /// first, and the amount of unread notifications it has.
+    async fn notifications(&self, user_id: u32, unread_only: bool, before: Option<u64>, limit: u32) -> Result<(Vec<Notification>, u64), String>;
+    /// Marks notifications of a user as read, or all of them if `ids` is `None`.
+    async fn mark_read(&self, user_id: u32, ids: Option<&[u64]>) -> Result<(), String>;
+    /// Deletes the notifications created before `before`.
+    async fn delete_notifications(&self, before: u64) -> Result<(), String>;
+
+    /// Stores a metric sample of a node, or one of its servers (`server` 0 being the node itself).
+    async fn store_metrics(&self, uuid: Uuid, server: u32, sample: &MetricSample) -> Result<(), String>;
+    /// Returns the raw or hourly samples of a node (or one of its servers) within the given range
+    /// (inclusive), oldest first.
+    async fn metrics(&self, uuid: Uuid, server: u32, from: i64, to: i64, hourly: bool) -> Result<Vec<MetricSample>, String>;
+    /// Averages the raw samples within the given range into hourly rollups, keeping rollups that
+    /// exist already.
+    async fn rollup_metrics(&self, from: i64, to: i64) -> Result<(), String>;
+    /// Deletes the raw samples older than `raw_before` and the rollups older than `rollups_before`.
+    async fn delete_metrics(&self, raw_before: u64, rollups_before: u64) -> Result<(), String>;
+
+    /// Stores a batch of events of daemons in the history.
+    async fn store_events(&self, events: &[NewEvent]) -> Result<(), String>;
+    /// Returns the latest `limit` stored events of a type of each of the given daemons, grouped by
+    /// daemon and oldest first.
+    async fn events(&self, uuids: &[Uuid], event_type: i16, limit: u32) -> Result<Vec<(Uuid, StoredEvent)>, String>;
+    /// Deletes the events that happened before `before`.
+    async fn delete_events(&self, before: u64) -> Result<(), String>;
+
+    /// Returns the team of a user.
+    async fn user_team(&self, user_id: u32) -> Result<u32, String>;
+    /// Returns the nodes of a team.
+    async fn team_nodes(&self, team: u32) -> Result<Vec<Uuid>, String>;
+    /// Fetches the tags with the given names a team can use, its own tags and the shared ones. If
+    /// multiple tags share a name, the team's own tag is used, otherwise the oldest one.
+    async fn spec_tags(&self, team: u32, names: &[String]) -> Result<HashMap<String, TagState>, String>;
+    /// Fetches the tags the servers of the given nodes use. If multiple of them share a name, the
+    /// one owned by a team is used, otherwise the oldest one.
+    async fn node_tags(&self, uuids: &[Uuid]) -> Result<Vec<TagSpec>, String>;
+    /// Fetches the networks and servers of the given nodes. Nodes that don't exist are left out.
+    async fn spec_nodes(&self, uuids: &[Uuid]) -> Result<HashMap<Uuid, NodeState>, String>;
+    /// Applies the changes of an import plan in a single transaction.
+    async fn apply(&self, plan: Plan) -> Result<(), String>;
+}
+
+/// Returns the repository selected by `database.backend`, or the spec files of standalone mode.
+pub fn get() -> &'static dyn Repository {
+    REPOSITORY.as_ref()
+}
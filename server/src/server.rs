@@ -1,16 +1,16 @@
-use std::{net::SocketAddr, sync::Arc};
+use std::{net::SocketAddr, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::Duration};
 
 use async_trait::async_trait;
 use futures_channel::mpsc::unbounded;
-use futures_util::{future, pin_mut, stream::{SplitSink, SplitStream}, StreamExt, TryStreamExt};
+use futures_util::{future::{self, Either}, pin_mut, stream::{SplitSink, SplitStream}, StreamExt, TryStreamExt};
 use josekit::jwe::alg::rsaes::RsaesJweDecrypter;
-use packet::Packet;
-use tokio::net::{TcpListener, TcpStream};
-use tokio_tungstenite::{tungstenite::{self, Message}, WebSocketStream};
-use tracing::{debug, error, info, span, Level, Span};
+use packet::{activation, chunk::Reassembler, close::CloseReason, subprotocol::{self, Encoding, Subprotocol}, Packet, ID};
+use tokio::{net::TcpStream, sync::Mutex};
+use tokio_tungstenite::{tungstenite::{self, handshake::server::{ErrorResponse, Request, Response}, http::{HeaderValue, StatusCode}, protocol::{frame::coding::CloseCode, CloseFrame}, Message}, WebSocketStream};
+use tracing::{debug, error, info, span, warn, Level, Span};
 use tracing_futures::Instrument;
 
-use crate::{encryption, state::{Rx, Tx}};
+use crate::{config::CONFIG, encryption, sessions::{self, Session}, state::{Rx, Tx}, tls::{self, Stream}, watchdog};
 
 /// The main `Server` trait, which handles WebSocket connections, decryption and parsing of
 /// packets.
@@ -23,38 +23,62 @@ pub trait Server: Send + Sync + 'static {
     fn get_bind_addr(&self) -> &'static str;
     /// Return the decrypter to use when decrypting packets
     fn get_decrypter(&self) -> &'static RsaesJweDecrypter;
-    /// Return the issuer to use when decrypting packets
-    fn get_issuer(&self) -> &'static str;
+    /// Return the issuers accepted when decrypting packets
+    fn get_issuers(&self) -> &'static [String];
+    /// Return whether connections are wrapped in TLS, see `tls::accept`
+    fn uses_tls(&self) -> bool {
+        false
+    }
 
-    /// Called when a new connection is accepted
-    async fn on_accept(&self, addr: SocketAddr, tx: Tx) -> Result<(), String>;
+    /// Called when a WebSocket upgrade is requested, before it is accepted. Returning an error
+    /// rejects the upgrade with `403 Forbidden`.
+    fn check_upgrade(&self, _request: &Request) -> Result<(), String> {
+        Ok(())
+    }
+    /// Called when a new connection is accepted, with the encoding negotiated with its subprotocol
+    async fn on_accept(&self, addr: SocketAddr, tx: Tx, encoding: Encoding) -> Result<(), String>;
     /// Called when a connection is disconnected
     async fn on_disconnect(&self, addr: SocketAddr) -> Result<(), String>;
+    /// Called when a connection ends, before `on_disconnect`, with the statistics of the session
+    async fn on_session_end(&self, _addr: SocketAddr, _session: Session) -> Result<(), String> {
+        Ok(())
+    }
+    /// Return whether a connection has answered the challenge of its handshake. Chunked packets
+    /// are only reassembled for authenticated connections.
+    fn is_authenticated(&self, addr: &SocketAddr) -> bool;
     /// Called when a packet could not be decrypted
     async fn on_decrypt_error(&self, addr: SocketAddr) -> Result<(), String>;
     /// Called when a packet is received
     async fn on_packet(&self, packet: Packet, addr: SocketAddr) -> Result<(), String>;
+    /// Called after a packet sent with a request id was handled, to acknowledge it
+    async fn on_handled(&self, addr: SocketAddr, request_id: u64, res: &Result<(), String>) -> Result<(), String>;
+    /// Called every `sockets.heartbeat_interval` seconds while a connection is open, to ping it
+    async fn on_heartbeat(&self, addr: SocketAddr) -> Result<(), String>;
 
-    /// Start the server.
-    async fn start(self: Arc<Self>) {
+    /// Start the server. Only returns if it could not bind to its address.
+    async fn start(self: Arc<Self>) -> Result<(), String> {
         let tracing_name = self.as_ref().get_tracing_name();
         async move {
-            let try_socket = TcpListener::bind(self.get_bind_addr()).await;
+            let try_socket = activation::bind(tracing_name, self.get_bind_addr()).await;
             let listener = match try_socket {
                 Ok(listener) => listener,
                 Err(e) => {
                     error!("Error binding to socket: {}", e);
-                    return;
+                    watchdog::set_listening(tracing_name, false);
+                    return Err(format!("could not bind to {}: {}", self.get_bind_addr(), e));
                 }
             };
 
-            info!("Listening on: {}", self.get_bind_addr());
+            info!("Listening on: {}{}", self.get_bind_addr(), if self.uses_tls() { " (TLS)" } else { "" });
+            watchdog::set_listening(tracing_name, true);
 
             loop {
                 let conn = listener.accept().await;
 
                 match conn {
                     Ok((stream, addr)) => {
+                        watchdog::set_listening(tracing_name, true);
+
                         let self_cloned = Arc::clone(&self);
                         tokio::spawn(async move {
                             match self_cloned.accept_connection(stream, addr).await {
@@ -67,7 +91,9 @@ pub trait Server: Send + Sync + 'static {
                         }.instrument(span!(Level::TRACE, "client", "addr" = %addr)));
                     }
                     Err(e) => {
+                        // e.g. the server ran out of file descriptors
                         error!("Error in connection: {}", e);
+                        watchdog::set_listening(tracing_name, false);
                     }
                 }
             }
@@ -78,42 +104,95 @@ pub trait Server: Send + Sync + 'static {
     async fn accept_connection(self: Arc<Self>, raw_stream: TcpStream, addr: SocketAddr) -> Result<(), String> {
         debug!("Accepted TCP connection");
 
-        let stream = tokio_tungstenite::accept_async(raw_stream).await.map_err(|e| format!("Could not accept connection: {}", self.error_to_string(e)))?;
+        let raw_stream = match self.uses_tls() {
+            true => tls::accept(raw_stream).await?,
+            false => Stream::Plain(raw_stream),
+        };
+
+        let reject = |status: StatusCode, e: String| -> ErrorResponse {
+            warn!("Rejected WebSocket upgrade: {}", e);
+
+            let mut response = ErrorResponse::new(Some(e));
+            *response.status_mut() = status;
+            response
+        };
+
+        let mut protocol = Subprotocol::LEGACY;
+
+        // tungstenite answers rejected upgrades with the error response, so it can't be boxed
+        #[allow(clippy::result_large_err)]
+        let check_upgrade = |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+            self.check_upgrade(request).map_err(|e| reject(StatusCode::FORBIDDEN, e))?;
+
+            // clients that don't offer any subprotocols use the legacy one
+            if let Some(offered) = request.headers().get(subprotocol::HEADER) {
+                let selected = offered.to_str().ok()
+                    .and_then(Subprotocol::negotiate)
+                    .ok_or_else(|| reject(StatusCode::BAD_REQUEST, format!("No supported subprotocol offered, expected one of {}", Subprotocol::offer())))?;
+
+                response.headers_mut().insert(subprotocol::HEADER, HeaderValue::from_static(selected.as_str()));
+                protocol = selected;
+            }
+
+            Ok(response)
+        };
+
+        let stream = tokio_tungstenite::accept_hdr_async(raw_stream, check_upgrade).await.map_err(|e| format!("Could not accept connection: {}", self.error_to_string(e)))?;
+        debug!("Using subprotocol {}", protocol);
         let (write, read) = stream.split();
 
         let (tx, rx) = unbounded();
 
-        self.on_accept(addr, tx).instrument(Span::current()).await?;
+        self.on_accept(addr, tx.clone(), protocol.encoding()).instrument(Span::current()).await?;
 
-        self.handle_client(write, read, addr, rx).await?;
+        self.handle_client(write, read, addr, tx, rx, protocol).await?;
 
         Ok(())
     }
 
-    /// Handle a WebSocket connection.
-    async fn handle_client(self: Arc<Self>, write: SplitSink<WebSocketStream<TcpStream>, Message>, read: SplitStream<WebSocketStream<TcpStream>>, addr: SocketAddr, rx: Rx) -> Result<(), String> {
+    /// Handle a WebSocket connection, with messages framed according to `protocol`. `tx` is only
+    /// used to close the connection once it has been idle for too long.
+    async fn handle_client(self: Arc<Self>, write: SplitSink<WebSocketStream<Stream>, Message>, read: SplitStream<WebSocketStream<Stream>>, addr: SocketAddr, tx: Tx, rx: Rx, protocol: Subprotocol) -> Result<(), String> {
         debug!("Established WebSocket connection");
 
-        let incoming = read.try_filter(|msg| future::ready(msg.is_text())).for_each(|msg| async {
+        let connected_at = sessions::now();
+        let (bytes_in, bytes_out, packets_in, packets_out) = (AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0), AtomicU64::new(0));
+        let read_error = std::sync::Mutex::new(None);
+        let last_received = AtomicU64::new(connected_at);
+
+        let reassembler = Arc::new(Mutex::new(Reassembler::new()));
+
+        // pings and pongs keep the connection from being idle as well
+        let incoming = read.inspect_ok(|_| last_received.store(sessions::now(), Ordering::Relaxed)).try_filter(|msg| future::ready(msg.is_text() || msg.is_binary())).for_each(|msg| async {
             let msg = match msg {
                 Ok(msg) => msg,
                 Err(e) => {
-                    error!("Error reading message: {}", self.error_to_string(e));
+                    let e = self.error_to_string(e);
+                    error!("Error reading message: {}", e);
+
+                    if let Ok(mut read_error) = read_error.lock() {
+                        *read_error = Some(e);
+                    }
+
                     return;
                 }
             };
 
-            let text = match msg.into_text() {
+            bytes_in.fetch_add(msg.len() as u64, Ordering::Relaxed);
+            packets_in.fetch_add(1, Ordering::Relaxed);
+
+            let text = match subprotocol::unframe(protocol, msg) {
                 Ok(text) => text,
                 Err(e) => {
-                    error!("Error converting message to text: {}", e);
+                    error!("Error reading message: {}", e);
                     return;
                 }
             };
 
             let self_cloned = Arc::clone(&self);
+            let reassembler = Arc::clone(&reassembler);
             tokio::spawn(async move {
-                match self_cloned.handle_packet(text, addr).await {
+                match self_cloned.handle_packet(text, addr, reassembler).await {
                     Ok(_) => future::ready(()),
                     Err(e) => {
                         error!("Error handling packet: {}", e);
@@ -123,10 +202,81 @@ pub trait Server: Send + Sync + 'static {
             });
         });
 
-        let outgoing = rx.map(Ok).forward(write);
+        let outgoing = rx.map(|msg| {
+            let msg = subprotocol::frame(protocol, msg);
+
+            bytes_out.fetch_add(msg.len() as u64, Ordering::Relaxed);
+            packets_out.fetch_add(1, Ordering::Relaxed);
 
-        pin_mut!(incoming, outgoing);
-        future::select(incoming, outgoing).await;
+            msg
+        }).map(Ok).forward(write);
+
+        // never completes, the connection ends once the close frame has been sent
+        let idle = async {
+            let timeout = CONFIG.sockets.idle_timeout;
+
+            if timeout > 0 {
+                loop {
+                    let idle_for = sessions::now().saturating_sub(last_received.load(Ordering::Relaxed));
+
+                    if idle_for >= timeout {
+                        debug!("Closing idle connection");
+                        close(&tx, CloseReason::IdleTimeout);
+                        break;
+                    }
+
+                    tokio::time::sleep(Duration::from_secs(timeout - idle_for)).await;
+                }
+            }
+
+            future::pending::<()>().await
+        };
+
+        // never completes either, connections that stop answering are closed by `State::reap_stale`
+        let heartbeat = async {
+            let interval = CONFIG.sockets.heartbeat_interval;
+
+            if interval > 0 {
+                let mut interval = tokio::time::interval(Duration::from_secs(interval));
+
+                // the first tick completes immediately, before the peer could have authenticated
+                interval.tick().await;
+
+                loop {
+                    interval.tick().await;
+
+                    if let Err(e) = self.on_heartbeat(addr).instrument(Span::current()).await {
+                        warn!("Could not send heartbeat: {}", e);
+                    }
+                }
+            }
+
+            future::pending::<()>().await
+        };
+
+        #[cfg(feature = "chaos")]
+        let idle = future::select(Box::pin(idle), Box::pin(crate::chaos::drop_connection(&tx)));
+
+        pin_mut!(incoming, outgoing, idle, heartbeat);
+        let reason = match future::select(future::select(incoming, future::join(idle, heartbeat)), outgoing).await {
+            Either::Left(_) => read_error.lock().ok().and_then(|mut e| e.take()).unwrap_or_else(|| "Closed by peer".to_string()),
+            Either::Right((Ok(()), _)) => "Closed by server".to_string(),
+            Either::Right((Err(e), _)) => self.error_to_string(e),
+        };
+
+        let session = Session {
+            connected_at,
+            disconnected_at: sessions::now(),
+            reason,
+            bytes_in: bytes_in.load(Ordering::Relaxed),
+            bytes_out: bytes_out.load(Ordering::Relaxed),
+            packets_in: packets_in.load(Ordering::Relaxed),
+            packets_out: packets_out.load(Ordering::Relaxed),
+        };
+
+        if let Err(e) = self.on_session_end(addr, session).instrument(Span::current()).await {
+            warn!("Could not record session: {}", e);
+        }
 
         let res = self.on_disconnect(addr).instrument(Span::current()).await;
 
@@ -135,15 +285,44 @@ pub trait Server: Send + Sync + 'static {
         res
     }
 
-    /// Handle a packet.
-    async fn handle_packet(self: Arc<Self>, msg: String, addr: SocketAddr) -> Result<(), String> {
+    /// Handle a packet. Chunks are collected in the connection's `reassembler` until the packet
+    /// they belong to is complete.
+    async fn handle_packet(self: Arc<Self>, msg: String, addr: SocketAddr, reassembler: Arc<Mutex<Reassembler>>) -> Result<(), String> {
         let on_err = async || {
             self.on_decrypt_error(addr).await
         };
 
-        let packet = encryption::decrypt_packet(&msg, self.get_decrypter(), self.get_issuer(), Some(on_err)).await?;
+        let mut packet = encryption::decrypt_packet(&msg, self.get_decrypter(), self.get_issuers(), Some(on_err)).await?;
+
+        if packet.id == ID::Chunk {
+            // chunks are buffered until the packet is complete, which nobody should be able to make
+            // the server do before authenticating
+            if !self.is_authenticated(&addr) {
+                return Err("Received chunked packet before authenticating".into());
+            }
+
+            let chunk = packet.payload()?;
+
+            packet = match reassembler.lock().await.push(chunk)? {
+                Some(packet) => packet,
+                None => return Ok(()),
+            };
+        }
+
+        #[cfg(feature = "chaos")]
+        crate::chaos::delay().await;
 
-        self.on_packet(packet, addr).instrument(Span::current()).await
+        // acknowledgements carry the request id of the packet they answer, and are not answered
+        let request_id = packet.request_id.filter(|_| !matches!(packet.id, ID::Ack | ID::Nack));
+
+        let res = self.on_packet(packet, addr).instrument(Span::current()).await;
+
+        if let Some(request_id) = request_id
+            && let Err(e) = self.on_handled(addr, request_id, &res).await {
+            warn!("Could not acknowledge packet: {}", e);
+        }
+
+        res
     }
 
     /// Convert a `tungstenite::Error` to a `String` in a pretty format.
@@ -165,3 +344,14 @@ pub trait Server: Send + Sync + 'static {
     }
 
 }
+
+/// Closes a connection, sending a close frame telling the peer why.
+pub fn close(tx: &Tx, reason: CloseReason) {
+    // the channel is already closed if the connection is going away anyway
+    let _ = tx.unbounded_send(Message::Close(Some(CloseFrame {
+        code: CloseCode::from(reason.code()),
+        reason: reason.to_string().into(),
+    })));
+
+    tx.close_channel();
+}
@@ -0,0 +1,75 @@
+use std::{net::SocketAddr, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use packet::server_web::query_connections_response::ConnectionSession;
+use sqlx::types::Uuid;
+use tracing::warn;
+
+use crate::{config::CONFIG, db, repository};
+
+/// How often sessions past their retention are deleted.
+const RETENTION_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Default amount of sessions returned by `query`.
+const DEFAULT_LIMIT: u32 = 50;
+
+/// Most sessions returned by `query`.
+const MAX_LIMIT: u32 = 500;
+
+/// Returns the current unix timestamp in seconds.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+/// The peer of a connection, as far as it is known when the connection ends.
+pub enum Peer {
+    Daemon(Uuid),
+    Web(u32),
+}
+
+/// Statistics of a connection, collected by `Server::handle_client`.
+pub struct Session {
+    pub connected_at: u64,
+    pub disconnected_at: u64,
+    pub reason: String,
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    pub packets_in: u64,
+    pub packets_out: u64,
+}
+
+/// Stores a session that has ended.
+pub async fn record(peer: Peer, addr: SocketAddr, session: Session) -> Result<(), String> {
+    if !CONFIG.sessions.enabled || db::read_only() {
+        return Ok(());
+    }
+
+    repository::get().store_session(&peer, addr, &session).await
+}
+
+/// Periodically deletes sessions past their retention.
+pub async fn run() {
+    if !CONFIG.sessions.enabled {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(RETENTION_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        if db::read_only() {
+            continue;
+        }
+
+        if let Err(e) = repository::get().delete_sessions(now().saturating_sub(CONFIG.sessions.retention_days * 24 * 60 * 60)).await {
+            warn!("Could not delete old sessions: {}", e);
+        }
+    }
+}
+
+/// Returns the latest sessions of a daemon, newest first.
+pub async fn query(uuid: Uuid, limit: Option<u32>) -> Result<Vec<ConnectionSession>, String> {
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT);
+
+    repository::get().sessions(uuid, limit).await
+}
@@ -0,0 +1,475 @@
+use std::{collections::{BTreeMap, BTreeSet, HashSet}, path::Path};
+
+use serde::{Deserialize, Serialize};
+use sqlx::types::Uuid;
+
+use crate::repository;
+
+/// The `Spec` struct represents a declarative spec of the networks and servers of a set of nodes,
+/// and the tags their servers use. Everything is identified by name (and nodes by UUID) instead of
+/// database IDs, so specs can be kept in Git and applied to other instances.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Spec {
+    /// The tags servers can use, in addition to the tags that already exist.
+    #[serde(default)]
+    pub tags: Vec<TagSpec>,
+    #[serde(default)]
+    pub nodes: Vec<NodeSpec>,
+}
+
+/// The `TagSpec` struct represents a tag, identified by its name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TagSpec {
+    pub name: String,
+    pub image: String,
+    pub docker_tag: String,
+    pub healthcheck: HealthcheckSpec,
+    /// Only supported in standalone mode, like `env_defs`, see `Spec::standalone_only`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mounts: Vec<MountSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub env_defs: Vec<EnvDefSpec>,
+}
+
+/// The `HealthcheckSpec` struct represents the healthcheck of a tag.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HealthcheckSpec {
+    pub test: Vec<String>,
+    pub interval: u32,
+    pub timeout: u32,
+    pub retries: u32,
+}
+
+/// The `NodeSpec` struct represents the networks and servers of a node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NodeSpec {
+    pub uuid: Uuid,
+    #[serde(default)]
+    pub networks: Vec<NetworkSpec>,
+    #[serde(default)]
+    pub servers: Vec<ServerSpec>,
+    /// The users that can access the node in standalone mode, which has no teams.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub users: BTreeSet<u32>,
+}
+
+/// The `NetworkSpec` struct represents a network of a node, identified by its name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkSpec {
+    pub name: String,
+    /// The third octet of the network's `10.133.0.0/24` subnet.
+    pub local_ip: u8,
+}
+
+/// The `ServerSpec` struct represents a server of a node, identified by its name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerSpec {
+    pub name: String,
+    /// The name of the tag the server uses.
+    pub tag: String,
+    #[serde(default)]
+    pub envs: BTreeMap<String, String>,
+    /// The keys of the envs that are secret. Exports leave their values out of `envs`, and
+    /// imports keep the current value of secret envs without one.
+    #[serde(default, skip_serializing_if = "BTreeSet::is_empty")]
+    pub secrets: BTreeSet<String>,
+    #[serde(default)]
+    pub ports: Vec<PortSpec>,
+    #[serde(default)]
+    pub networks: Vec<ServerNetworkSpec>,
+    /// Only supported in standalone mode, like the fields below, see `Spec::standalone_only`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub isolation: Option<IsolationSpec>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<DependencySpec>,
+    #[serde(default, skip_serializing_if = "UpdateSpec::is_recreate")]
+    pub update: UpdateSpec,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+/// The `PortSpec` struct represents a port of a server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PortSpec {
+    pub port: u16,
+    pub protocol: PortProtocol,
+    /// The host port to map to, or `0` to keep the port the daemon assigned.
+    #[serde(default)]
+    pub mapped: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PortProtocol {
+    Tcp,
+    Udp,
+}
+
+impl From<i16> for PortProtocol {
+    fn from(value: i16) -> Self {
+        match value {
+            1 => PortProtocol::Udp,
+            _ => PortProtocol::Tcp,
+        }
+    }
+}
+
+impl From<PortProtocol> for i16 {
+    fn from(value: PortProtocol) -> Self {
+        match value {
+            PortProtocol::Tcp => 0,
+            PortProtocol::Udp => 1,
+        }
+    }
+}
+
+/// The `ServerNetworkSpec` struct represents the address of a server in a network of its node.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServerNetworkSpec {
+    /// The name of the network.
+    pub network: String,
+    /// The last octet of the server's address in the network.
+    pub ip: u8,
+}
+
+/// The `MountSpec` struct represents a host path mounted into the containers of a tag.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MountSpec {
+    pub container_path: String,
+    pub host_path: String,
+}
+
+/// The `EnvDefSpec` struct represents the definition of an env servers of a tag can set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EnvDefSpec {
+    pub key: String,
+    #[serde(default)]
+    pub required: bool,
+    #[serde(rename = "type", default)]
+    pub env_type: EnvTypeSpec,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub regex: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<i64>,
+    #[serde(default)]
+    pub trim: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EnvTypeSpec {
+    Boolean,
+    Number,
+    #[default]
+    String,
+}
+
+/// The `IsolationSpec` struct represents what a server can reach outside of its networks.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IsolationSpec {
+    #[serde(default)]
+    pub policy: IsolationPolicySpec,
+    /// The CIDRs the server can reach with the `internal_only` policy.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowlist: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IsolationPolicySpec {
+    #[default]
+    FullEgress,
+    NoInternet,
+    InternalOnly,
+}
+
+/// The `DependencySpec` struct represents a server of the same node that has to be started
+/// before a server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DependencySpec {
+    /// The name of the server.
+    pub server: String,
+    /// Whether the server has to be healthy, instead of just started.
+    #[serde(default)]
+    pub healthy: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpdateSpec {
+    #[default]
+    Recreate,
+    BlueGreen,
+}
+
+impl UpdateSpec {
+    pub fn is_recreate(&self) -> bool {
+        *self == UpdateSpec::Recreate
+    }
+}
+
+/// The formats a spec can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpecFormat {
+    Toml,
+    Yaml,
+}
+
+impl SpecFormat {
+    /// Returns the format of a spec file by its extension, TOML unless it is `.yaml` or `.yml`.
+    pub fn from_path(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|extension| extension.to_str()) {
+            Some("yaml" | "yml") => SpecFormat::Yaml,
+            _ => SpecFormat::Toml,
+        }
+    }
+}
+
+impl Spec {
+    /// Parses a spec, checking that it is consistent.
+    pub fn parse(contents: &str, format: SpecFormat) -> Result<Self, String> {
+        let spec: Spec = match format {
+            SpecFormat::Toml => toml::from_str(contents).map_err(|e| format!("Could not parse spec: {}", e))?,
+            SpecFormat::Yaml => serde_yaml::from_str(contents).map_err(|e| format!("Could not parse spec: {}", e))?,
+        };
+        spec.validate()?;
+        Ok(spec)
+    }
+
+    /// Serializes the spec to TOML.
+    pub fn to_toml(&self) -> Result<String, String> {
+        toml::to_string_pretty(self).map_err(|e| format!("Could not serialize spec: {}", e))
+    }
+
+    /// Returns what the spec sets that only standalone mode supports, if anything. Imports reject
+    /// such specs, as the database would drop it.
+    pub fn standalone_only(&self) -> Option<String> {
+        if let Some(tag) = self.tags.iter().find(|tag| !tag.mounts.is_empty() || !tag.env_defs.is_empty()) {
+            return Some(format!("the mounts and env definitions of tag {}", tag.name));
+        }
+
+        if let Some(node) = self.nodes.iter().find(|node| !node.users.is_empty()) {
+            return Some(format!("the users of node {}", node.uuid));
+        }
+
+        self.nodes.iter().flat_map(|node| node.servers.iter())
+            .find(|server| server.isolation.is_some() || !server.depends_on.is_empty() || !server.update.is_recreate() || !server.labels.is_empty())
+            .map(|server| format!("the isolation, dependencies, update strategy and labels of server {}", server.name))
+    }
+
+    /// Checks that names are unique, and servers only use networks of and depend on servers of
+    /// their node.
+    pub fn validate(&self) -> Result<(), String> {
+        unique("tag", self.tags.iter().map(|tag| tag.name.as_str()))?;
+        unique("node", self.nodes.iter().map(|node| node.uuid.to_string()))?;
+
+        for node in self.nodes.iter() {
+            unique("network", node.networks.iter().map(|network| network.name.as_str()))?;
+            unique("server", node.servers.iter().map(|server| server.name.as_str()))?;
+
+            for server in node.servers.iter() {
+                for network in server.networks.iter() {
+                    if !node.networks.iter().any(|n| n.name == network.network) {
+                        return Err(format!("Server {} uses network {}, which is not a network of node {}", server.name, network.network, node.uuid));
+                    }
+                }
+
+                for dependency in server.depends_on.iter() {
+                    if !node.servers.iter().any(|s| s.name == dependency.server) {
+                        return Err(format!("Server {} depends on server {}, which is not a server of node {}", server.name, dependency.server, node.uuid));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn unique<T: ToString>(kind: &str, names: impl Iterator<Item = T>) -> Result<(), String> {
+    let mut seen = HashSet::new();
+
+    for name in names {
+        let name = name.to_string();
+
+        if !seen.insert(name.clone()) {
+            return Err(format!("Duplicate {} {}", kind, name));
+        }
+    }
+
+    Ok(())
+}
+
+/// The networks and servers of a node in the database, with their IDs.
+#[derive(Debug, Clone)]
+pub struct NodeState {
+    pub id: i32,
+    pub networks: Vec<(i32, NetworkSpec)>,
+    pub servers: Vec<(i32, ServerSpec)>,
+}
+
+/// A tag in the database a team can use, with its ID.
+#[derive(Debug, Clone)]
+pub struct TagState {
+    pub id: i32,
+    /// Whether the tag belongs to the team, tags without a team are shared by all teams and can't
+    /// be changed by imports.
+    pub owned: bool,
+    pub tag: TagSpec,
+}
+
+/// Exports the networks and servers of the given nodes, and the tags their servers use, as a
+/// spec. Everything is sorted by name, so exports of the same state are identical. The values of
+/// secret envs are left out, importing the spec again keeps them.
+pub async fn export(uuids: &[Uuid]) -> Result<Spec, String> {
+    let mut nodes = repository::get().spec_nodes(uuids).await?.into_iter().map(|(uuid, state)| {
+        let mut networks = state.networks.into_iter().map(|(_, network)| network).collect::<Vec<_>>();
+        networks.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut servers = state.servers.into_iter().map(|(_, mut server)| {
+            server.envs.retain(|key, _| !server.secrets.contains(key));
+            server.ports.sort_by_key(|port| (port.port, i16::from(port.protocol)));
+            server.networks.sort_by(|a, b| a.network.cmp(&b.network));
+            server
+        }).collect::<Vec<_>>();
+        servers.sort_by(|a, b| a.name.cmp(&b.name));
+
+        NodeSpec {
+            uuid,
+            networks,
+            servers,
+            users: BTreeSet::new(),
+        }
+    }).collect::<Vec<_>>();
+    nodes.sort_by_key(|node| node.uuid);
+
+    let mut tags = repository::get().node_tags(uuids).await?;
+    tags.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(Spec {
+        tags,
+        nodes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+        [[tags]]
+        name = "minecraft"
+        image = "itzg/minecraft-server"
+        docker_tag = "latest"
+        healthcheck = { test = ["CMD", "mc-health"], interval = 30, timeout = 5, retries = 3 }
+
+        [[nodes]]
+        uuid = "6d3f2b1a-0c4e-4f5a-9b7d-2e8c1a3f4b5d"
+        networks = [{ name = "internal", local_ip = 1 }]
+
+        [[nodes.servers]]
+        name = "survival"
+        tag = "minecraft"
+        envs = { EULA = "TRUE" }
+        ports = [{ port = 25565, protocol = "tcp" }]
+        networks = [{ network = "internal", ip = 2 }]
+    "#;
+
+    const YAML: &str = r#"
+tags:
+  - name: minecraft
+    image: itzg/minecraft-server
+    docker_tag: latest
+    healthcheck:
+      test: [CMD, mc-health]
+      interval: 30
+      timeout: 5
+      retries: 3
+nodes:
+  - uuid: 6d3f2b1a-0c4e-4f5a-9b7d-2e8c1a3f4b5d
+    networks:
+      - name: internal
+        local_ip: 1
+    servers:
+      - name: survival
+        tag: minecraft
+        envs:
+          EULA: "TRUE"
+        ports:
+          - port: 25565
+            protocol: tcp
+        networks:
+          - network: internal
+            ip: 2
+"#;
+
+    #[test]
+    fn parse_formats() {
+        let toml = Spec::parse(TOML, SpecFormat::Toml).unwrap();
+        let yaml = Spec::parse(YAML, SpecFormat::Yaml).unwrap();
+
+        assert_eq!(toml, yaml);
+        assert_eq!(toml.nodes[0].servers[0].ports[0].mapped, 0);
+        assert_eq!(Spec::parse(&toml.to_toml().unwrap(), SpecFormat::Toml).unwrap(), toml);
+    }
+
+    #[test]
+    fn format_from_path() {
+        assert_eq!(SpecFormat::from_path("specs/aesterisk.yaml"), SpecFormat::Yaml);
+        assert_eq!(SpecFormat::from_path("aesterisk.yml"), SpecFormat::Yaml);
+        assert_eq!(SpecFormat::from_path("aesterisk.toml"), SpecFormat::Toml);
+        assert_eq!(SpecFormat::from_path("aesterisk"), SpecFormat::Toml);
+    }
+
+    #[test]
+    fn reject_unknown_fields() {
+        assert!(Spec::parse("[[nodes]]\nuuid = \"6d3f2b1a-0c4e-4f5a-9b7d-2e8c1a3f4b5d\"\ncolor = \"red\"\n", SpecFormat::Toml).is_err());
+        assert!(Spec::parse("nodes:\n  - uuid: 6d3f2b1a-0c4e-4f5a-9b7d-2e8c1a3f4b5d\n    color: red\n", SpecFormat::Yaml).is_err());
+    }
+
+    #[test]
+    fn validate_duplicates() {
+        let spec = Spec::parse(TOML, SpecFormat::Toml).unwrap();
+
+        let mut tags = spec.clone();
+        tags.tags.push(tags.tags[0].clone());
+        assert_eq!(tags.validate(), Err("Duplicate tag minecraft".to_string()));
+
+        let mut nodes = spec.clone();
+        nodes.nodes.push(nodes.nodes[0].clone());
+        assert_eq!(nodes.validate(), Err("Duplicate node 6d3f2b1a-0c4e-4f5a-9b7d-2e8c1a3f4b5d".to_string()));
+
+        let mut networks = spec.clone();
+        networks.nodes[0].networks.push(NetworkSpec { name: "internal".to_string(), local_ip: 2 });
+        assert_eq!(networks.validate(), Err("Duplicate network internal".to_string()));
+
+        let mut servers = spec;
+        let server = servers.nodes[0].servers[0].clone();
+        servers.nodes[0].servers.push(server);
+        assert_eq!(servers.validate(), Err("Duplicate server survival".to_string()));
+    }
+
+    #[test]
+    fn validate_networks() {
+        let mut spec = Spec::parse(TOML, SpecFormat::Toml).unwrap();
+        spec.nodes[0].servers[0].networks[0].network = "public".to_string();
+
+        assert_eq!(spec.validate(), Err("Server survival uses network public, which is not a network of node 6d3f2b1a-0c4e-4f5a-9b7d-2e8c1a3f4b5d".to_string()));
+    }
+}
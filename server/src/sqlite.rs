@@ -0,0 +1,1603 @@
+use std::{collections::{BTreeMap, HashMap}, net::SocketAddr};
+
+use async_trait::async_trait;
+use packet::{events::MetricSample, maintenance::MaintenanceWindow, server_daemon::{catalog::CatalogImage, sync::{Build, BuildContext, Dependency, Env, EnvDef, EnvType, Healthcheck, Isolation, IsolationPolicy, Mount, Network, Port, Protocol, SDSyncPacket, Server, ServerMetadata, ServerNetwork, Tag, UpdateStrategy}}, server_web::{query_connections_response::ConnectionSession, query_notifications_response::{Notification, NotificationKind}}};
+use serde::{de::DeserializeOwned, Serialize};
+use sqlx::{types::Uuid, Sqlite as Db, Transaction};
+use tracing::info;
+
+use crate::{alerts::AlertRule, config::{DatabaseBackend, CONFIG}, db, history::{NewEvent, StoredEvent}, import::{Change, Plan}, inbox::{self, Recipients}, quotas::{DaemonQuota, Reservation, ServerQuota}, repository::{Repository, WasmExtension}, sessions::{Peer, Session}, spec::{HealthcheckSpec, NetworkSpec, NodeState, PortProtocol, PortSpec, ServerNetworkSpec, ServerSpec, TagSpec, TagState, UpdateSpec}};
+
+/// Returns whether the server stores its data in SQLite, see `config::DatabaseBackend::Sqlite`.
+pub fn enabled() -> bool {
+    CONFIG.database.backend == DatabaseBackend::Sqlite
+}
+
+fn parse_uuid(uuid: &str) -> Result<Uuid, String> {
+    Uuid::parse_str(uuid).map_err(|e| format!("Node UUID {} is invalid: {}", uuid, e))
+}
+
+/// Encodes a value stored as JSON, like arrays and the lists of IDs queries are filtered by.
+fn to_json<T: Serialize + ?Sized>(value: &T) -> Result<String, String> {
+    serde_json::to_string(value).map_err(|e| format!("Could not encode {}: {}", std::any::type_name::<T>(), e))
+}
+
+fn from_json<T: DeserializeOwned>(what: &str, json: &str) -> Result<T, String> {
+    serde_json::from_str(json).map_err(|e| format!("{} is not valid JSON: {}", what, e))
+}
+
+/// Stores everything but automation rules and usage accounting in a SQLite database, with the same
+/// tables as the Postgres schema, see `migrations/sqlite`. SQLite doesn't aggregate into arrays, so
+/// the parts of a server are fetched separately and grouped by server, and lists of IDs are passed
+/// to queries as JSON arrays.
+pub struct Sqlite;
+
+#[async_trait]
+impl Repository for Sqlite {
+    async fn team_daemons(&self, user_id: u32) -> Result<Vec<Uuid>, String> {
+        let uuids = db::timed("fetch_team_daemons", sqlx::query_scalar!(r#"
+            SELECT
+                nodes.node_uuid
+            FROM users
+            INNER JOIN team_nodes
+                ON users.user_team = team_nodes.team_id
+            INNER JOIN nodes
+                ON team_nodes.node_id = nodes.node_id
+            WHERE users.user_id = ?1;
+        "#, user_id).fetch_all(db::sqlite()?)).await.map_err(|_| "failed to fetch team daemons".to_string())?;
+
+        uuids.iter().map(|uuid| parse_uuid(uuid)).collect()
+    }
+
+    async fn spec(&self, uuid: Uuid) -> Result<SDSyncPacket, String> {
+        let pool = db::sqlite()?;
+        let node = uuid.to_string();
+
+        let networks = db::timed("fetch_networks", sqlx::query!(r#"
+            SELECT
+                networks.network_id,
+                networks.network_local_ip
+            FROM nodes
+            INNER JOIN node_networks
+                ON nodes.node_id = node_networks.node_id
+            INNER JOIN networks
+                ON node_networks.network_id = networks.network_id
+            WHERE nodes.node_uuid = ?1
+            ORDER BY networks.network_id;
+        "#, node).fetch_all(pool)).await.map_err(|_| "failed to fetch network data")?;
+
+        let servers = db::timed("fetch_servers", sqlx::query!(r#"
+            SELECT
+                servers.server_id,
+                servers.server_name,
+                servers.server_tag,
+                servers.server_isolation_policy,
+                servers.server_isolation_allowlist,
+                servers.server_update_strategy,
+                tags.tag_image,
+                tags.tag_docker_tags,
+                tags.tag_healthcheck_test,
+                tags.tag_healthcheck_interval,
+                tags.tag_healthcheck_timeout,
+                tags.tag_healthcheck_retries,
+                tags.tag_build_git_url,
+                tags.tag_build_context_hash,
+                tags.tag_build_dockerfile
+            FROM nodes
+            INNER JOIN node_servers ON nodes.node_id = node_servers.node_id
+            INNER JOIN servers ON node_servers.server_id = servers.server_id
+            INNER JOIN tags ON servers.server_tag = tags.tag_id
+            WHERE nodes.node_uuid = ?1
+            ORDER BY servers.server_id;
+        "#, node).fetch_all(pool)).await.map_err(|e| format!("Failed to fetch server data: {}", e))?;
+
+        let mounts = db::timed("fetch_mounts", sqlx::query!(r#"
+            SELECT
+                tag_mounts.tag_id,
+                mounts.mount_container_path,
+                mounts.mount_host_path
+            FROM mounts
+            INNER JOIN tag_mounts ON mounts.mount_id = tag_mounts.mount_id
+            WHERE tag_mounts.tag_id IN (
+                SELECT servers.server_tag
+                FROM servers
+                INNER JOIN node_servers ON servers.server_id = node_servers.server_id
+                INNER JOIN nodes ON node_servers.node_id = nodes.node_id
+                WHERE nodes.node_uuid = ?1
+            )
+            ORDER BY mounts.mount_id;
+        "#, node).fetch_all(pool)).await.map_err(|e| format!("Failed to fetch mounts: {}", e))?;
+
+        let env_defs = db::timed("fetch_env_defs", sqlx::query!(r#"
+            SELECT
+                tag_env_defs.tag_id,
+                env_defs.env_def_key,
+                env_defs.env_def_required,
+                env_defs.env_def_type,
+                env_defs.env_def_default_value,
+                env_defs.env_def_regex,
+                env_defs.env_def_min,
+                env_defs.env_def_max,
+                env_defs.env_def_trim
+            FROM env_defs
+            INNER JOIN tag_env_defs ON env_defs.env_def_id = tag_env_defs.env_def_id
+            WHERE tag_env_defs.tag_id IN (
+                SELECT servers.server_tag
+                FROM servers
+                INNER JOIN node_servers ON servers.server_id = node_servers.server_id
+                INNER JOIN nodes ON node_servers.node_id = nodes.node_id
+                WHERE nodes.node_uuid = ?1
+            )
+            ORDER BY env_defs.env_def_id;
+        "#, node).fetch_all(pool)).await.map_err(|e| format!("Failed to fetch env definitions: {}", e))?;
+
+        let envs = db::timed("fetch_envs", sqlx::query!(r#"
+            SELECT
+                server_envs.server_id,
+                envs.env_key,
+                envs.env_value
+            FROM envs
+            INNER JOIN server_envs ON envs.env_id = server_envs.env_id
+            INNER JOIN node_servers ON server_envs.server_id = node_servers.server_id
+            INNER JOIN nodes ON node_servers.node_id = nodes.node_id
+            WHERE nodes.node_uuid = ?1
+            ORDER BY envs.env_id;
+        "#, node).fetch_all(pool)).await.map_err(|e| format!("Failed to fetch envs: {}", e))?;
+
+        let server_networks = db::timed("fetch_server_networks", sqlx::query!(r#"
+            SELECT
+                server_networks.server_id,
+                server_networks.network_id,
+                server_networks.local_ip
+            FROM server_networks
+            INNER JOIN node_servers ON server_networks.server_id = node_servers.server_id
+            INNER JOIN nodes ON node_servers.node_id = nodes.node_id
+            WHERE nodes.node_uuid = ?1
+            ORDER BY server_networks.network_id;
+        "#, node).fetch_all(pool)).await.map_err(|e| format!("Failed to fetch server networks: {}", e))?;
+
+        let ports = db::timed("fetch_ports", sqlx::query!(r#"
+            SELECT
+                server_ports.server_id,
+                ports.port_port,
+                ports.port_protocol,
+                ports.port_mapped
+            FROM ports
+            INNER JOIN server_ports ON ports.port_id = server_ports.port_id
+            INNER JOIN node_servers ON server_ports.server_id = node_servers.server_id
+            INNER JOIN nodes ON node_servers.node_id = nodes.node_id
+            WHERE nodes.node_uuid = ?1
+            ORDER BY ports.port_id;
+        "#, node).fetch_all(pool)).await.map_err(|e| format!("Failed to fetch ports: {}", e))?;
+
+        let windows = db::timed("fetch_maintenance_windows", sqlx::query!(r#"
+            SELECT
+                maintenance_windows.server_id,
+                maintenance_windows.maintenance_window_cron,
+                maintenance_windows.maintenance_window_duration
+            FROM maintenance_windows
+            INNER JOIN nodes
+                ON maintenance_windows.node_id = nodes.node_id
+            WHERE nodes.node_uuid = ?1
+            ORDER BY maintenance_windows.maintenance_window_id;
+        "#, node).fetch_all(pool)).await.map_err(|_| "failed to fetch maintenance windows")?;
+
+        let dependencies = db::timed("fetch_server_dependencies", sqlx::query!(r#"
+            SELECT
+                server_dependencies.server_id,
+                server_dependencies.dependency_server_id,
+                server_dependencies.dependency_wait_healthy
+            FROM server_dependencies
+            INNER JOIN node_servers
+                ON server_dependencies.server_id = node_servers.server_id
+            INNER JOIN nodes
+                ON node_servers.node_id = nodes.node_id
+            WHERE nodes.node_uuid = ?1
+            ORDER BY server_dependencies.dependency_server_id;
+        "#, node).fetch_all(pool)).await.map_err(|_| "failed to fetch server dependencies")?;
+
+        let labels = db::timed("fetch_server_labels", sqlx::query!(r#"
+            SELECT
+                server_labels.server_id,
+                server_labels.server_label_key,
+                server_labels.server_label_value
+            FROM server_labels
+            INNER JOIN node_servers
+                ON server_labels.server_id = node_servers.server_id
+            INNER JOIN nodes
+                ON node_servers.node_id = nodes.node_id
+            WHERE nodes.node_uuid = ?1;
+        "#, node).fetch_all(pool)).await.map_err(|e| format!("Failed to fetch server labels: {}", e))?;
+
+        let mut tag_mounts = HashMap::<i64, Vec<Mount>>::new();
+        for mount in mounts.into_iter() {
+            tag_mounts.entry(mount.tag_id).or_default().push(Mount {
+                container_path: mount.mount_container_path,
+                host_path: mount.mount_host_path,
+            });
+        }
+
+        let mut tag_env_defs = HashMap::<i64, Vec<EnvDef>>::new();
+        for env_def in env_defs.into_iter() {
+            tag_env_defs.entry(env_def.tag_id).or_default().push(EnvDef {
+                key: env_def.env_def_key,
+                required: env_def.env_def_required,
+                env_type: EnvType::from(env_def.env_def_type as u8),
+                default: env_def.env_def_default_value,
+                regex: env_def.env_def_regex,
+                min: env_def.env_def_min,
+                max: env_def.env_def_max,
+                trim: env_def.env_def_trim,
+            });
+        }
+
+        let mut server_envs = HashMap::<i64, Vec<Env>>::new();
+        for env in envs.into_iter() {
+            server_envs.entry(env.server_id).or_default().push(Env {
+                key: env.env_key,
+                value: env.env_value,
+            });
+        }
+
+        let mut server_network_map = HashMap::<i64, Vec<ServerNetwork>>::new();
+        for network in server_networks.into_iter() {
+            server_network_map.entry(network.server_id).or_default().push(ServerNetwork {
+                network: network.network_id as u32,
+                ip: network.local_ip as u8,
+            });
+        }
+
+        let mut server_ports = HashMap::<i64, Vec<Port>>::new();
+        for port in ports.into_iter() {
+            server_ports.entry(port.server_id).or_default().push(Port {
+                port: port.port_port as u16,
+                mapped: port.port_mapped as u16,
+                protocol: Protocol::from(port.port_protocol as u8),
+            });
+        }
+
+        let mut node_maintenance = Vec::new();
+        let mut server_maintenance = HashMap::<i64, Vec<MaintenanceWindow>>::new();
+        for window in windows.into_iter() {
+            let maintenance_window = MaintenanceWindow {
+                cron: window.maintenance_window_cron,
+                duration: window.maintenance_window_duration.max(0) as u32,
+            };
+
+            match window.server_id {
+                Some(server_id) => server_maintenance.entry(server_id).or_default().push(maintenance_window),
+                None => node_maintenance.push(maintenance_window),
+            }
+        }
+
+        let mut server_dependencies = HashMap::<i64, Vec<Dependency>>::new();
+        for dependency in dependencies.into_iter() {
+            server_dependencies.entry(dependency.server_id).or_default().push(Dependency {
+                server: dependency.dependency_server_id as u32,
+                healthy: dependency.dependency_wait_healthy,
+            });
+        }
+
+        let mut server_labels = HashMap::<i64, BTreeMap<String, String>>::new();
+        for label in labels.into_iter() {
+            server_labels.entry(label.server_id).or_default().insert(label.server_label_key, label.server_label_value);
+        }
+
+        let metadata = servers.iter().map(|s| ServerMetadata {
+            server: s.server_id as u32,
+            name: s.server_name.clone(),
+            labels: server_labels.remove(&s.server_id).unwrap_or_default(),
+        }).collect();
+
+        let servers = servers.into_iter().map(|s| Ok(Server {
+            id: s.server_id as u32,
+            tag: Tag {
+                image: s.tag_image,
+                docker_tag: s.tag_docker_tags,
+                healthcheck: Healthcheck {
+                    test: from_json(&format!("Healthcheck test of server {}", s.server_id), &s.tag_healthcheck_test)?,
+                    interval: s.tag_healthcheck_interval as u64,
+                    timeout: s.tag_healthcheck_timeout as u64,
+                    retries: s.tag_healthcheck_retries as u64,
+                },
+                mounts: tag_mounts.get(&s.server_tag).cloned().unwrap_or_default(),
+                env_defs: tag_env_defs.get(&s.server_tag).cloned().unwrap_or_default(),
+                build: match (s.tag_build_git_url, s.tag_build_context_hash) {
+                    (Some(url), _) => Some(BuildContext::Git(url)),
+                    (None, Some(hash)) => Some(BuildContext::Archive(hash)),
+                    (None, None) => None,
+                }.map(|context| Build {
+                    context,
+                    dockerfile: s.tag_build_dockerfile,
+                }),
+            },
+            envs: server_envs.remove(&s.server_id).unwrap_or_default(),
+            networks: server_network_map.remove(&s.server_id).unwrap_or_default(),
+            ports: server_ports.remove(&s.server_id).unwrap_or_default(),
+            isolation: Isolation {
+                policy: IsolationPolicy::from(s.server_isolation_policy as u8),
+                allowlist: from_json(&format!("Isolation allowlist of server {}", s.server_id), &s.server_isolation_allowlist)?,
+            },
+            maintenance: server_maintenance.remove(&s.server_id).unwrap_or_default(),
+            depends_on: server_dependencies.remove(&s.server_id).unwrap_or_default(),
+            update: UpdateStrategy::from(s.server_update_strategy as u8),
+        })).collect::<Result<Vec<_>, String>>()?;
+
+        let mut sync = SDSyncPacket {
+            networks: networks.into_iter().map(|nw| Network {
+                id: nw.network_id as u32,
+                subnet: nw.network_local_ip as u8,
+            }).collect(),
+            servers,
+            maintenance: node_maintenance,
+            metadata,
+            hash: String::new(),
+            base: String::new(),
+            removed_networks: Vec::new(),
+            removed_servers: Vec::new(),
+            request: None,
+        };
+
+        sync.hash = sync.spec_hash()?;
+
+        Ok(sync)
+    }
+
+    fn writable(&self) -> Result<(), String> {
+        db::writable()
+    }
+
+    async fn assign_ports(&self, uuid: Uuid, server: u32, ports: &[Port]) -> Result<(), String> {
+        let node = uuid.to_string();
+
+        for port in ports {
+            let protocol = port.protocol as u8;
+
+            // only update servers that actually belong to the reporting daemon
+            db::timed("update_assigned_port", sqlx::query!(r#"
+                UPDATE ports
+                SET port_mapped = ?1
+                WHERE ports.port_port = ?2
+                AND ports.port_protocol = ?3
+                AND ports.port_id IN (
+                    SELECT server_ports.port_id
+                    FROM server_ports
+                    JOIN node_servers ON server_ports.server_id = node_servers.server_id
+                    JOIN nodes ON node_servers.node_id = nodes.node_id
+                    WHERE server_ports.server_id = ?4
+                    AND nodes.node_uuid = ?5
+                );
+            "#, port.mapped, port.port, protocol, server, node).execute(db::sqlite()?)).await.map_err(|e| format!("Failed to update assigned port: {}", e))?;
+
+            info!("Server {} was assigned host port {} for {}/{}", server, port.mapped, port.port, port.protocol);
+        }
+
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        db::timed("watchdog_ping", sqlx::query_scalar!("SELECT 1 AS alive;").fetch_one(db::sqlite()?)).await.map(|_| ())
+    }
+
+    async fn node_team(&self, uuid: Uuid) -> Result<Option<u32>, String> {
+        let node = uuid.to_string();
+
+        let team = db::timed("fetch_node_team", sqlx::query_scalar!(r#"
+            SELECT
+                team_nodes.team_id
+            FROM team_nodes
+            INNER JOIN nodes
+                ON team_nodes.node_id = nodes.node_id
+            WHERE nodes.node_uuid = ?1;
+        "#, node).fetch_optional(db::sqlite()?)).await?;
+
+        Ok(team.map(|team| team as u32))
+    }
+
+    async fn team_extensions(&self, team: u32) -> Result<Vec<WasmExtension>, String> {
+        let extensions = db::timed("fetch_team_extensions", sqlx::query!(r#"
+            SELECT
+                wasm_extensions.wasm_extension_id,
+                wasm_extensions.wasm_extension_name,
+                wasm_extensions.wasm_extension_module
+            FROM wasm_extensions
+            WHERE wasm_extensions.team_id = ?1
+            ORDER BY wasm_extensions.wasm_extension_position, wasm_extensions.wasm_extension_id;
+        "#, team).fetch_all(db::sqlite()?)).await?;
+
+        Ok(extensions.into_iter().map(|extension| WasmExtension {
+            id: extension.wasm_extension_id as u32,
+            name: extension.wasm_extension_name,
+            module: extension.wasm_extension_module,
+        }).collect())
+    }
+
+    async fn group_members(&self, group: u32) -> Result<Vec<Uuid>, String> {
+        let members = db::timed("fetch_group_members", sqlx::query_scalar!(r#"
+            SELECT
+                nodes.node_uuid
+            FROM node_group_members
+            INNER JOIN nodes
+                ON node_group_members.node_id = nodes.node_id
+            WHERE node_group_members.node_group_id = ?1;
+        "#, group).fetch_all(db::sqlite()?)).await.map_err(|_| "failed to fetch group members".to_string())?;
+
+        members.iter().map(|uuid| parse_uuid(uuid)).collect()
+    }
+
+    async fn assign_server(&self, daemon: Uuid, server: u32) -> Result<bool, String> {
+        let node = daemon.to_string();
+
+        let res = db::timed("assign_server", sqlx::query!(r#"
+            INSERT INTO node_servers (
+                node_id,
+                server_id
+            )
+            SELECT
+                nodes.node_id,
+                ?2
+            FROM nodes
+            WHERE nodes.node_uuid = ?1
+            AND NOT EXISTS (
+                SELECT 1 FROM node_servers WHERE node_servers.server_id = ?2
+            );
+        "#, node, server).execute(db::sqlite()?)).await.map_err(|e| format!("Failed to assign server: {}", e))?;
+
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn update_metadata(&self, uuid: Uuid, server: u32, name: Option<String>, labels: Option<BTreeMap<String, String>>) -> Result<ServerMetadata, String> {
+        let node = uuid.to_string();
+        let mut tx = db::sqlite()?.begin().await.map_err(|e| format!("Could not start transaction: {}", e))?;
+
+        let name = db::timed("update_server_name", sqlx::query_scalar!(r#"
+            UPDATE servers
+            SET server_name = COALESCE(?3, servers.server_name)
+            WHERE servers.server_id = ?2
+            AND servers.server_id IN (
+                SELECT node_servers.server_id
+                FROM node_servers
+                INNER JOIN nodes
+                    ON node_servers.node_id = nodes.node_id
+                WHERE nodes.node_uuid = ?1
+            )
+            RETURNING servers.server_name;
+        "#, node, server, name).fetch_optional(&mut *tx)).await?.ok_or_else(|| format!("Server {} is not a server of daemon {}", server, uuid))?;
+
+        if let Some(labels) = labels {
+            db::timed("clear_server_labels", sqlx::query!(r#"
+                DELETE FROM server_labels WHERE server_id = ?1;
+            "#, server).execute(&mut *tx)).await?;
+
+            for (key, value) in labels.iter() {
+                db::timed("insert_server_label", sqlx::query!(r#"
+                    INSERT INTO server_labels (
+                        server_id,
+                        server_label_key,
+                        server_label_value
+                    ) VALUES (?1, ?2, ?3);
+                "#, server, key, value).execute(&mut *tx)).await?;
+            }
+        }
+
+        let labels = db::timed("fetch_server_labels", sqlx::query!(r#"
+            SELECT
+                server_labels.server_label_key,
+                server_labels.server_label_value
+            FROM server_labels
+            WHERE server_labels.server_id = ?1;
+        "#, server).fetch_all(&mut *tx)).await?;
+
+        tx.commit().await.map_err(|e| format!("Could not commit transaction: {}", e))?;
+
+        Ok(ServerMetadata {
+            server,
+            name,
+            labels: labels.into_iter().map(|label| (label.server_label_key, label.server_label_value)).collect(),
+        })
+    }
+
+    async fn build_context(&self, uuid: Uuid, hash: &str) -> Result<Option<Vec<u8>>, String> {
+        let node = uuid.to_string();
+
+        db::timed("fetch_build_context", sqlx::query_scalar!(r#"
+            SELECT
+                build_contexts.build_context_data
+            FROM build_contexts
+            WHERE build_contexts.build_context_hash = ?2
+            AND EXISTS (
+                SELECT 1
+                FROM nodes
+                INNER JOIN node_servers
+                    ON nodes.node_id = node_servers.node_id
+                INNER JOIN servers
+                    ON node_servers.server_id = servers.server_id
+                INNER JOIN tags
+                    ON servers.server_tag = tags.tag_id
+                WHERE nodes.node_uuid = ?1
+                AND tags.tag_build_context_hash = ?2
+            );
+        "#, node, hash).fetch_optional(db::sqlite()?)).await.map_err(|_| "failed to fetch build context".to_string())
+    }
+
+    async fn catalog(&self, limit: u32) -> Result<Vec<CatalogImage>, String> {
+        let images = db::timed("fetch_catalog", sqlx::query!(r#"
+            SELECT
+                tags.tag_image,
+                tags.tag_docker_tags
+            FROM servers
+            INNER JOIN tags
+                ON servers.server_tag = tags.tag_id
+            WHERE tags.tag_build_git_url IS NULL
+            AND tags.tag_build_context_hash IS NULL
+            GROUP BY tags.tag_id, tags.tag_image, tags.tag_docker_tags
+            ORDER BY COUNT(*) DESC
+            LIMIT ?1;
+        "#, limit).fetch_all(db::sqlite()?)).await.map_err(|_| "failed to fetch catalog")?;
+
+        Ok(images.into_iter().map(|image| CatalogImage {
+            image: image.tag_image,
+            docker_tag: image.tag_docker_tags,
+        }).collect())
+    }
+
+    async fn alert_rules(&self, uuid: Uuid) -> Result<Vec<AlertRule>, String> {
+        let node = uuid.to_string();
+
+        let rules = db::timed("fetch_alert_rules", sqlx::query!(r#"
+            SELECT
+                alert_rules.alert_rule_id,
+                alert_rules.server_id,
+                alert_rules.alert_rule_metric,
+                alert_rules.alert_rule_threshold,
+                alert_rules.alert_rule_duration,
+                alert_rules.alert_rule_webhook
+            FROM alert_rules
+            INNER JOIN nodes
+                ON alert_rules.node_id = nodes.node_id
+            WHERE nodes.node_uuid = ?1;
+        "#, node).fetch_all(db::sqlite()?)).await.map_err(|_| "failed to fetch alert rules")?;
+
+        Ok(rules.into_iter().filter_map(|rule| AlertRule::from_db(
+            rule.alert_rule_id,
+            rule.server_id,
+            rule.alert_rule_metric,
+            rule.alert_rule_threshold,
+            rule.alert_rule_duration,
+            rule.alert_rule_webhook,
+        )).collect())
+    }
+
+    async fn daemon_quotas(&self, uuid: Uuid) -> Result<Vec<DaemonQuota>, String> {
+        let node = uuid.to_string();
+
+        let quotas = db::timed("fetch_daemon_quotas", sqlx::query!(r#"
+            SELECT
+                team_quotas.team_id,
+                team_quotas.quota_max_daemons AS "quota_max_daemons!",
+                (
+                    SELECT COUNT(*)
+                    FROM team_nodes AS others
+                    WHERE others.team_id = team_nodes.team_id
+                    AND others.node_id < team_nodes.node_id
+                ) AS "preceding!: i64"
+            FROM nodes
+            INNER JOIN team_nodes
+                ON nodes.node_id = team_nodes.node_id
+            INNER JOIN team_quotas
+                ON team_nodes.team_id = team_quotas.team_id
+            WHERE nodes.node_uuid = ?1
+            AND team_quotas.quota_max_daemons IS NOT NULL;
+        "#, node).fetch_all(db::sqlite()?)).await?;
+
+        Ok(quotas.into_iter().map(|quota| DaemonQuota {
+            team: quota.team_id as u32,
+            max_daemons: quota.quota_max_daemons,
+            preceding: quota.preceding,
+        }).collect())
+    }
+
+    async fn server_quotas(&self, daemon: Uuid) -> Result<Vec<ServerQuota>, String> {
+        let node = daemon.to_string();
+
+        let quotas = db::timed("fetch_server_quotas", sqlx::query!(r#"
+            SELECT
+                team_quotas.team_id,
+                team_quotas.quota_max_servers,
+                team_quotas.quota_max_memory,
+                team_quotas.quota_max_storage,
+                COUNT(servers.server_id) AS "servers!: i64",
+                COALESCE(SUM(servers.server_memory_reservation), 0) AS "memory!: i64",
+                COALESCE(SUM(servers.server_storage_reservation), 0) AS "storage!: i64"
+            FROM nodes
+            INNER JOIN team_nodes
+                ON nodes.node_id = team_nodes.node_id
+            INNER JOIN team_quotas
+                ON team_nodes.team_id = team_quotas.team_id
+            LEFT JOIN team_nodes AS quota_nodes
+                ON team_quotas.team_id = quota_nodes.team_id
+            LEFT JOIN node_servers
+                ON quota_nodes.node_id = node_servers.node_id
+            LEFT JOIN servers
+                ON node_servers.server_id = servers.server_id
+            WHERE nodes.node_uuid = ?1
+            GROUP BY team_quotas.team_id;
+        "#, node).fetch_all(db::sqlite()?)).await?;
+
+        Ok(quotas.into_iter().map(|quota| ServerQuota {
+            team: quota.team_id as u32,
+            max_servers: quota.quota_max_servers,
+            max_memory: quota.quota_max_memory,
+            max_storage: quota.quota_max_storage,
+            servers: quota.servers,
+            memory: quota.memory,
+            storage: quota.storage,
+        }).collect())
+    }
+
+    async fn server_reservation(&self, server: u32) -> Result<Option<Reservation>, String> {
+        let reservation = db::timed("fetch_server_reservation", sqlx::query!(r#"
+            SELECT
+                servers.server_memory_reservation,
+                servers.server_storage_reservation,
+                EXISTS (
+                    SELECT 1 FROM node_servers
+                    WHERE node_servers.server_id = servers.server_id
+                ) AS "assigned!: bool"
+            FROM servers
+            WHERE servers.server_id = ?1;
+        "#, server).fetch_optional(db::sqlite()?)).await?;
+
+        Ok(reservation.map(|reservation| Reservation {
+            memory: reservation.server_memory_reservation,
+            storage: reservation.server_storage_reservation,
+            assigned: reservation.assigned,
+        }))
+    }
+
+    async fn store_session(&self, peer: &Peer, addr: SocketAddr, session: &Session) -> Result<(), String> {
+        let (peer_type, node_uuid, user_id) = match peer {
+            Peer::Daemon(uuid) => ("daemon", Some(uuid.to_string()), None),
+            Peer::Web(user_id) => ("web", None, Some(*user_id)),
+        };
+
+        let addr = addr.to_string();
+        let connected_at = session.connected_at as i64;
+        let disconnected_at = session.disconnected_at as i64;
+        let bytes_in = session.bytes_in as i64;
+        let bytes_out = session.bytes_out as i64;
+        let packets_in = session.packets_in as i64;
+        let packets_out = session.packets_out as i64;
+
+        db::timed("insert_connection_session", sqlx::query!(r#"
+            INSERT INTO connection_sessions (
+                session_peer_type,
+                node_uuid,
+                user_id,
+                session_addr,
+                session_connected_at,
+                session_disconnected_at,
+                session_reason,
+                session_bytes_in,
+                session_bytes_out,
+                session_packets_in,
+                session_packets_out
+            ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11);
+        "#,
+            peer_type,
+            node_uuid,
+            user_id,
+            addr,
+            connected_at,
+            disconnected_at,
+            session.reason,
+            bytes_in,
+            bytes_out,
+            packets_in,
+            packets_out,
+        ).execute(db::sqlite()?)).await?;
+
+        Ok(())
+    }
+
+    async fn sessions(&self, uuid: Uuid, limit: u32) -> Result<Vec<ConnectionSession>, String> {
+        let node = uuid.to_string();
+
+        let sessions = db::timed("fetch_connection_sessions", sqlx::query!(r#"
+            SELECT
+                connection_sessions.session_addr,
+                connection_sessions.session_connected_at,
+                connection_sessions.session_disconnected_at,
+                connection_sessions.session_reason,
+                connection_sessions.session_bytes_in,
+                connection_sessions.session_bytes_out,
+                connection_sessions.session_packets_in,
+                connection_sessions.session_packets_out
+            FROM connection_sessions
+            WHERE connection_sessions.node_uuid = ?1
+            ORDER BY connection_sessions.session_connected_at DESC
+            LIMIT ?2;
+        "#, node, limit).fetch_all(db::sqlite()?)).await?;
+
+        Ok(sessions.into_iter().map(|session| ConnectionSession {
+            addr: session.session_addr,
+            connected_at: session.session_connected_at as u64,
+            disconnected_at: session.session_disconnected_at as u64,
+            reason: session.session_reason,
+            bytes_in: session.session_bytes_in as u64,
+            bytes_out: session.session_bytes_out as u64,
+            packets_in: session.session_packets_in as u64,
+            packets_out: session.session_packets_out as u64,
+        }).collect())
+    }
+
+    async fn delete_sessions(&self, before: u64) -> Result<(), String> {
+        let before = before as i64;
+
+        db::timed("delete_old_connection_sessions", sqlx::query!(
+            "DELETE FROM connection_sessions WHERE session_disconnected_at < ?1",
+            before,
+        ).execute(db::sqlite()?)).await.map(|_| ())
+    }
+
+    async fn store_notification(&self, recipients: &Recipients, kind: NotificationKind, server: Option<u32>, message: &str, created_at: u64) -> Result<(), String> {
+        let kind = inbox::kind_to_db(kind);
+        let created_at = created_at as i64;
+
+        match recipients {
+            Recipients::Team(daemon) => {
+                let node = daemon.to_string();
+
+                db::timed("insert_team_notifications", sqlx::query!(r#"
+                    INSERT INTO notifications (
+                        user_id,
+                        notification_kind,
+                        node_uuid,
+                        server_id,
+                        notification_message,
+                        notification_created_at
+                    )
+                    SELECT
+                        users.user_id,
+                        ?2,
+                        nodes.node_uuid,
+                        ?3,
+                        ?4,
+                        ?5
+                    FROM nodes
+                    INNER JOIN team_nodes
+                        ON nodes.node_id = team_nodes.node_id
+                    INNER JOIN users
+                        ON team_nodes.team_id = users.user_team
+                    WHERE nodes.node_uuid = ?1;
+                "#, node, kind, server, message, created_at).execute(db::sqlite()?)).await?;
+            },
+            Recipients::User(user_id) => {
+                db::timed("insert_user_notification", sqlx::query!(r#"
+                    INSERT INTO notifications (
+                        user_id,
+                        notification_kind,
+                        server_id,
+                        notification_message,
+                        notification_created_at
+                    ) VALUES (?1, ?2, ?3, ?4, ?5);
+                "#, user_id, kind, server, message, created_at).execute(db::sqlite()?)).await?;
+            },
+        }
+
+        Ok(())
+    }
+
+    async fn notifications(&self, user_id: u32, unread_only: bool, before: Option<u64>, limit: u32) -> Result<(Vec<Notification>, u64), String> {
+        let before = before.map(|before| before as i64);
+
+        let notifications = db::timed("fetch_notifications", sqlx::query!(r#"
+            SELECT
+                notifications.notification_id,
+                notifications.notification_kind,
+                notifications.node_uuid,
+                notifications.server_id,
+                notifications.notification_message,
+                notifications.notification_created_at,
+                notifications.notification_read
+            FROM notifications
+            WHERE notifications.user_id = ?1
+                AND (NOT ?2 OR NOT notifications.notification_read)
+                AND (?3 IS NULL OR notifications.notification_id < ?3)
+            ORDER BY notifications.notification_id DESC
+            LIMIT ?4;
+        "#, user_id, unread_only, before, limit).fetch_all(db::sqlite()?)).await?;
+
+        let unread = db::timed("count_unread_notifications", sqlx::query_scalar!(r#"
+            SELECT COUNT(*) AS "count!: i64"
+            FROM notifications
+            WHERE notifications.user_id = ?1
+                AND NOT notifications.notification_read;
+        "#, user_id).fetch_one(db::sqlite()?)).await?;
+
+        let notifications = notifications.into_iter().map(|notification| Ok(Notification {
+            id: notification.notification_id as u64,
+            kind: inbox::kind_from_db(notification.notification_kind as i16)?,
+            daemon: notification.node_uuid.as_deref().map(parse_uuid).transpose()?,
+            server: notification.server_id.map(|server| server as u32),
+            message: notification.notification_message,
+            created_at: notification.notification_created_at as u64,
+            read: notification.notification_read,
+        })).collect::<Result<Vec<_>, String>>()?;
+
+        Ok((notifications, unread as u64))
+    }
+
+    async fn mark_read(&self, user_id: u32, ids: Option<&[u64]>) -> Result<(), String> {
+        match ids {
+            None => {
+                db::timed("mark_all_notifications_read", sqlx::query!(r#"
+                    UPDATE notifications
+                    SET notification_read = TRUE
+                    WHERE user_id = ?1 AND NOT notification_read;
+                "#, user_id).execute(db::sqlite()?)).await?;
+            },
+            Some(ids) => {
+                let ids = to_json(ids)?;
+
+                db::timed("mark_notifications_read", sqlx::query!(r#"
+                    UPDATE notifications
+                    SET notification_read = TRUE
+                    WHERE user_id = ?1 AND notification_id IN (SELECT value FROM json_each(?2));
+                "#, user_id, ids).execute(db::sqlite()?)).await?;
+            },
+        }
+
+        Ok(())
+    }
+
+    async fn delete_notifications(&self, before: u64) -> Result<(), String> {
+        let before = before as i64;
+
+        db::timed("delete_old_notifications", sqlx::query!(
+            "DELETE FROM notifications WHERE notification_created_at < ?1",
+            before,
+        ).execute(db::sqlite()?)).await.map(|_| ())
+    }
+
+    async fn store_metrics(&self, uuid: Uuid, server: u32, sample: &MetricSample) -> Result<(), String> {
+        let node = uuid.to_string();
+        let timestamp = sample.timestamp as i64;
+
+        db::timed("insert_metrics", sqlx::query!(
+            "INSERT INTO metrics (metric_time, node_uuid, server_id, metric_cpu, metric_memory_used, metric_memory_total, metric_storage_used, metric_storage_total) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            timestamp,
+            node,
+            server,
+            sample.cpu,
+            sample.memory_used,
+            sample.memory_total,
+            sample.storage_used,
+            sample.storage_total,
+        ).execute(db::sqlite()?)).await.map(|_| ())
+    }
+
+    async fn metrics(&self, uuid: Uuid, server: u32, from: i64, to: i64, hourly: bool) -> Result<Vec<MetricSample>, String> {
+        struct MetricRow {
+            metric_time: i64,
+            metric_cpu: Option<f64>,
+            metric_memory_used: Option<f64>,
+            metric_memory_total: Option<f64>,
+            metric_storage_used: Option<f64>,
+            metric_storage_total: Option<f64>,
+        }
+
+        let node = uuid.to_string();
+
+        let rows = if hourly {
+            db::timed("fetch_metrics_hourly", sqlx::query_as!(
+                MetricRow,
+                "SELECT metric_time, metric_cpu, metric_memory_used, metric_memory_total, metric_storage_used, metric_storage_total FROM metrics_hourly WHERE node_uuid = ?1 AND server_id = ?2 AND metric_time >= ?3 AND metric_time <= ?4 ORDER BY metric_time",
+                node,
+                server,
+                from,
+                to,
+            ).fetch_all(db::sqlite()?)).await
+        } else {
+            db::timed("fetch_metrics", sqlx::query_as!(
+                MetricRow,
+                "SELECT metric_time, metric_cpu, metric_memory_used, metric_memory_total, metric_storage_used, metric_storage_total FROM metrics WHERE node_uuid = ?1 AND server_id = ?2 AND metric_time >= ?3 AND metric_time <= ?4 ORDER BY metric_time",
+                node,
+                server,
+                from,
+                to,
+            ).fetch_all(db::sqlite()?)).await
+        }?;
+
+        Ok(rows.into_iter().map(|row| MetricSample {
+            timestamp: row.metric_time as u64,
+            cpu: row.metric_cpu,
+            memory_used: row.metric_memory_used,
+            memory_total: row.metric_memory_total,
+            storage_used: row.metric_storage_used,
+            storage_total: row.metric_storage_total,
+        }).collect())
+    }
+
+    async fn rollup_metrics(&self, from: i64, to: i64) -> Result<(), String> {
+        db::timed("rollup_metrics", sqlx::query!(
+            r#"INSERT OR IGNORE INTO metrics_hourly (metric_time, node_uuid, server_id, metric_cpu, metric_memory_used, metric_memory_total, metric_storage_used, metric_storage_total)
+            SELECT metric_time - metric_time % 3600 AS bucket, node_uuid, server_id, AVG(metric_cpu), AVG(metric_memory_used), AVG(metric_memory_total), AVG(metric_storage_used), AVG(metric_storage_total)
+            FROM metrics
+            WHERE metric_time >= ?1 AND metric_time < ?2
+            GROUP BY bucket, node_uuid, server_id"#,
+            from,
+            to,
+        ).execute(db::sqlite()?)).await.map(|_| ())
+    }
+
+    async fn delete_metrics(&self, raw_before: u64, rollups_before: u64) -> Result<(), String> {
+        let raw_before = raw_before as i64;
+        let rollups_before = rollups_before as i64;
+
+        db::timed("delete_old_metrics", sqlx::query!(
+            "DELETE FROM metrics WHERE metric_time < ?1",
+            raw_before,
+        ).execute(db::sqlite()?)).await?;
+
+        db::timed("delete_old_metric_rollups", sqlx::query!(
+            "DELETE FROM metrics_hourly WHERE metric_time < ?1",
+            rollups_before,
+        ).execute(db::sqlite()?)).await?;
+
+        Ok(())
+    }
+
+    async fn store_events(&self, events: &[NewEvent]) -> Result<(), String> {
+        // SQLite has no arrays to insert from, but one transaction spares a commit per event
+        let mut tx = db::sqlite()?.begin().await.map_err(|e| format!("Could not start transaction: {}", e))?;
+
+        for event in events.iter() {
+            let node = event.uuid.to_string();
+            let seq = event.seq.map(|seq| seq as i64);
+            let timestamp = event.timestamp as i64;
+
+            db::timed("insert_event", sqlx::query!(r#"
+                INSERT INTO events (
+                    node_uuid,
+                    event_type,
+                    event_seq,
+                    event_time,
+                    event_data
+                ) VALUES (?1, ?2, ?3, ?4, ?5);
+            "#, node, event.event_type, seq, timestamp, event.data).execute(&mut *tx)).await?;
+        }
+
+        tx.commit().await.map_err(|e| format!("Could not commit transaction: {}", e))
+    }
+
+    async fn events(&self, uuids: &[Uuid], event_type: i16, limit: u32) -> Result<Vec<(Uuid, StoredEvent)>, String> {
+        let uuids = to_json(uuids)?;
+
+        let rows = db::timed("fetch_events", sqlx::query!(r#"
+            SELECT
+                node_uuid AS "node_uuid!",
+                event_seq,
+                event_time AS "event_time!",
+                event_data AS "event_data!"
+            FROM (
+                SELECT
+                    node_uuid,
+                    event_seq,
+                    event_time,
+                    event_data,
+                    ROW_NUMBER() OVER (PARTITION BY node_uuid ORDER BY event_id DESC) AS event_rank
+                FROM events
+                WHERE node_uuid IN (SELECT value FROM json_each(?1))
+                AND event_type = ?2
+            )
+            WHERE event_rank <= ?3
+            ORDER BY node_uuid, event_rank DESC;
+        "#, uuids, event_type, limit).fetch_all(db::sqlite()?)).await?;
+
+        rows.into_iter().map(|row| Ok((parse_uuid(&row.node_uuid)?, StoredEvent::from_db(row.event_seq, row.event_time, &row.event_data)?))).collect()
+    }
+
+    async fn delete_events(&self, before: u64) -> Result<(), String> {
+        let before = before as i64;
+
+        db::timed("delete_old_events", sqlx::query!(
+            "DELETE FROM events WHERE event_time < ?1",
+            before,
+        ).execute(db::sqlite()?)).await.map(|_| ())
+    }
+
+    async fn user_team(&self, user_id: u32) -> Result<u32, String> {
+        let team = db::timed("fetch_user_team", sqlx::query_scalar!(r#"
+            SELECT user_team FROM users WHERE user_id = ?1;
+        "#, user_id).fetch_one(db::sqlite()?)).await?;
+
+        Ok(team as u32)
+    }
+
+    async fn team_nodes(&self, team: u32) -> Result<Vec<Uuid>, String> {
+        let uuids = db::timed("fetch_team_nodes", sqlx::query_scalar!(r#"
+            SELECT
+                nodes.node_uuid
+            FROM team_nodes
+            INNER JOIN nodes
+                ON team_nodes.node_id = nodes.node_id
+            WHERE team_nodes.team_id = ?1;
+        "#, team).fetch_all(db::sqlite()?)).await?;
+
+        uuids.iter().map(|uuid| parse_uuid(uuid)).collect()
+    }
+
+    async fn spec_tags(&self, team: u32, names: &[String]) -> Result<HashMap<String, TagState>, String> {
+        let names = to_json(names)?;
+
+        let tags = db::timed("fetch_spec_tags", sqlx::query_as!(DbTag, r#"
+            SELECT
+                tags.tag_id,
+                tags.tag_team,
+                tags.tag_name,
+                tags.tag_image,
+                tags.tag_docker_tags,
+                tags.tag_healthcheck_test,
+                tags.tag_healthcheck_interval,
+                tags.tag_healthcheck_timeout,
+                tags.tag_healthcheck_retries
+            FROM tags
+            WHERE tags.tag_name IN (SELECT value FROM json_each(?1))
+            AND (tags.tag_team = ?2 OR tags.tag_team IS NULL)
+            ORDER BY tags.tag_name, tags.tag_team IS NULL, tags.tag_id;
+        "#, names, team).fetch_all(db::sqlite()?)).await?;
+
+        let mut states = HashMap::new();
+
+        // the rows are sorted by preference, so the first tag of each name is used
+        for tag in tags.into_iter() {
+            if !states.contains_key(&tag.tag_name) {
+                states.insert(tag.tag_name.clone(), TagState::try_from(tag)?);
+            }
+        }
+
+        Ok(states)
+    }
+
+    async fn node_tags(&self, uuids: &[Uuid]) -> Result<Vec<TagSpec>, String> {
+        let uuids = to_json(uuids)?;
+
+        let tags = db::timed("fetch_spec_node_tags", sqlx::query_as!(DbTag, r#"
+            SELECT
+                tags.tag_id,
+                tags.tag_team,
+                tags.tag_name,
+                tags.tag_image,
+                tags.tag_docker_tags,
+                tags.tag_healthcheck_test,
+                tags.tag_healthcheck_interval,
+                tags.tag_healthcheck_timeout,
+                tags.tag_healthcheck_retries
+            FROM tags
+            WHERE tags.tag_id IN (
+                SELECT servers.server_tag FROM node_servers
+                INNER JOIN nodes
+                    ON node_servers.node_id = nodes.node_id
+                INNER JOIN servers
+                    ON node_servers.server_id = servers.server_id
+                WHERE nodes.node_uuid IN (SELECT value FROM json_each(?1))
+            )
+            ORDER BY tags.tag_name, tags.tag_team IS NULL, tags.tag_id;
+        "#, uuids).fetch_all(db::sqlite()?)).await?;
+
+        let mut specs = Vec::<TagSpec>::new();
+
+        // the rows are sorted by name and preference, so the first tag of each name is used
+        for tag in tags.into_iter() {
+            if specs.last().is_none_or(|last| last.name != tag.tag_name) {
+                specs.push(TagState::try_from(tag)?.tag);
+            }
+        }
+
+        Ok(specs)
+    }
+
+    async fn spec_nodes(&self, uuids: &[Uuid]) -> Result<HashMap<Uuid, NodeState>, String> {
+        let pool = db::sqlite()?;
+        let uuids = to_json(uuids)?;
+
+        let nodes = db::timed("fetch_spec_nodes", sqlx::query!(r#"
+            SELECT
+                nodes.node_id,
+                nodes.node_uuid
+            FROM nodes
+            WHERE nodes.node_uuid IN (SELECT value FROM json_each(?1));
+        "#, uuids).fetch_all(pool)).await?;
+
+        let ids = to_json(&nodes.iter().map(|node| node.node_id).collect::<Vec<_>>())?;
+
+        let mut states = nodes.into_iter().map(|node| Ok((node.node_id, (parse_uuid(&node.node_uuid)?, NodeState {
+            id: node.node_id as i32,
+            networks: Vec::new(),
+            servers: Vec::new(),
+        })))).collect::<Result<HashMap<_, _>, String>>()?;
+
+        let networks = db::timed("fetch_spec_networks", sqlx::query!(r#"
+            SELECT
+                node_networks.node_id,
+                networks.network_id,
+                networks.network_name,
+                networks.network_local_ip
+            FROM node_networks
+            INNER JOIN networks
+                ON node_networks.network_id = networks.network_id
+            WHERE node_networks.node_id IN (SELECT value FROM json_each(?1))
+            ORDER BY networks.network_id;
+        "#, ids).fetch_all(pool)).await?;
+
+        let network_names = networks.iter().map(|network| (network.network_id, network.network_name.clone())).collect::<HashMap<_, _>>();
+
+        for network in networks.into_iter() {
+            if let Some((_, state)) = states.get_mut(&network.node_id) {
+                state.networks.push((network.network_id as i32, NetworkSpec {
+                    name: network.network_name,
+                    local_ip: network.network_local_ip as u8,
+                }));
+            }
+        }
+
+        let servers = db::timed("fetch_spec_servers", sqlx::query!(r#"
+            SELECT
+                node_servers.node_id,
+                servers.server_id,
+                servers.server_name,
+                tags.tag_name
+            FROM node_servers
+            INNER JOIN servers
+                ON node_servers.server_id = servers.server_id
+            INNER JOIN tags
+                ON servers.server_tag = tags.tag_id
+            WHERE node_servers.node_id IN (SELECT value FROM json_each(?1))
+            ORDER BY servers.server_id;
+        "#, ids).fetch_all(pool)).await?;
+
+        let envs = db::timed("fetch_spec_envs", sqlx::query!(r#"
+            SELECT
+                server_envs.server_id,
+                envs.env_key,
+                envs.env_value,
+                envs.env_secret
+            FROM envs
+            INNER JOIN server_envs ON envs.env_id = server_envs.env_id
+            INNER JOIN node_servers ON server_envs.server_id = node_servers.server_id
+            WHERE node_servers.node_id IN (SELECT value FROM json_each(?1))
+            ORDER BY envs.env_id;
+        "#, ids).fetch_all(pool)).await?;
+
+        let ports = db::timed("fetch_spec_ports", sqlx::query!(r#"
+            SELECT
+                server_ports.server_id,
+                ports.port_port,
+                ports.port_protocol,
+                ports.port_mapped
+            FROM ports
+            INNER JOIN server_ports ON ports.port_id = server_ports.port_id
+            INNER JOIN node_servers ON server_ports.server_id = node_servers.server_id
+            WHERE node_servers.node_id IN (SELECT value FROM json_each(?1))
+            ORDER BY ports.port_id;
+        "#, ids).fetch_all(pool)).await?;
+
+        let server_networks = db::timed("fetch_spec_server_networks", sqlx::query!(r#"
+            SELECT
+                server_networks.server_id,
+                server_networks.network_id,
+                server_networks.local_ip
+            FROM server_networks
+            INNER JOIN node_servers ON server_networks.server_id = node_servers.server_id
+            WHERE node_servers.node_id IN (SELECT value FROM json_each(?1))
+            ORDER BY server_networks.network_id;
+        "#, ids).fetch_all(pool)).await?;
+
+        let mut specs = servers.iter().map(|server| (server.server_id, ServerSpec {
+            name: server.server_name.clone(),
+            tag: server.tag_name.clone(),
+            envs: BTreeMap::new(),
+            secrets: Default::default(),
+            ports: Vec::new(),
+            networks: Vec::new(),
+            isolation: None,
+            depends_on: Vec::new(),
+            update: UpdateSpec::default(),
+            labels: BTreeMap::new(),
+        })).collect::<HashMap<_, _>>();
+
+        for env in envs.into_iter() {
+            if let Some(spec) = specs.get_mut(&env.server_id) {
+                if env.env_secret {
+                    spec.secrets.insert(env.env_key.clone());
+                }
+
+                spec.envs.insert(env.env_key, env.env_value);
+            }
+        }
+
+        for port in ports.into_iter() {
+            if let Some(spec) = specs.get_mut(&port.server_id) {
+                spec.ports.push(PortSpec {
+                    port: port.port_port as u16,
+                    protocol: PortProtocol::from(port.port_protocol as i16),
+                    mapped: port.port_mapped as u16,
+                });
+            }
+        }
+
+        for network in server_networks.into_iter() {
+            // networks of other nodes can't be referenced by name, and are left out
+            if let Some(spec) = specs.get_mut(&network.server_id)
+                && let Some(name) = network_names.get(&network.network_id) {
+                spec.networks.push(ServerNetworkSpec {
+                    network: name.clone(),
+                    ip: network.local_ip as u8,
+                });
+            }
+        }
+
+        for server in servers.into_iter() {
+            if let Some((_, state)) = states.get_mut(&server.node_id)
+                && let Some(spec) = specs.remove(&server.server_id) {
+                state.servers.push((server.server_id as i32, spec));
+            }
+        }
+
+        Ok(states.into_values().collect())
+    }
+
+    async fn apply(&self, mut plan: Plan) -> Result<(), String> {
+        let mut tx = db::sqlite()?.begin().await.map_err(|e| format!("Could not start transaction: {}", e))?;
+
+        for change in plan.changes.iter() {
+            match change {
+                Change::CreateTag { tag } => {
+                    let test = to_json(&tag.healthcheck.test)?;
+
+                    let id = db::timed("import_create_tag", sqlx::query_scalar!(r#"
+                        INSERT INTO tags (
+                            tag_team,
+                            tag_name,
+                            tag_image,
+                            tag_docker_tags,
+                            tag_healthcheck_test,
+                            tag_healthcheck_interval,
+                            tag_healthcheck_timeout,
+                            tag_healthcheck_retries
+                        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+                        RETURNING tag_id;
+                    "#, plan.team, tag.name, tag.image, tag.docker_tag, test, tag.healthcheck.interval, tag.healthcheck.timeout, tag.healthcheck.retries).fetch_one(&mut *tx)).await?;
+
+                    plan.tags.insert(tag.name.clone(), id as i32);
+                },
+                Change::UpdateTag { id, tag } => {
+                    let test = to_json(&tag.healthcheck.test)?;
+
+                    db::timed("import_update_tag", sqlx::query!(r#"
+                        UPDATE tags
+                        SET
+                            tag_image = ?3,
+                            tag_docker_tags = ?4,
+                            tag_healthcheck_test = ?5,
+                            tag_healthcheck_interval = ?6,
+                            tag_healthcheck_timeout = ?7,
+                            tag_healthcheck_retries = ?8
+                        WHERE tag_id = ?1
+                        AND tag_team = ?2;
+                    "#, id, plan.team, tag.image, tag.docker_tag, test, tag.healthcheck.interval, tag.healthcheck.timeout, tag.healthcheck.retries).execute(&mut *tx)).await?;
+                },
+                Change::DeleteServer { id, .. } => {
+                    clear_server(&mut tx, *id).await?;
+
+                    db::timed("import_delete_node_server", sqlx::query!("DELETE FROM node_servers WHERE server_id = ?1", id).execute(&mut *tx)).await?;
+                    db::timed("import_delete_maintenance_windows", sqlx::query!("DELETE FROM maintenance_windows WHERE server_id = ?1", id).execute(&mut *tx)).await?;
+                    db::timed("import_delete_alert_rules", sqlx::query!("DELETE FROM alert_rules WHERE server_id = ?1", id).execute(&mut *tx)).await?;
+                    db::timed("import_delete_server_dependencies", sqlx::query!("DELETE FROM server_dependencies WHERE server_id = ?1 OR dependency_server_id = ?1", id).execute(&mut *tx)).await?;
+                    db::timed("import_delete_server_labels", sqlx::query!("DELETE FROM server_labels WHERE server_id = ?1", id).execute(&mut *tx)).await?;
+                    db::timed("import_delete_server", sqlx::query!("DELETE FROM servers WHERE server_id = ?1", id).execute(&mut *tx)).await?;
+                },
+                Change::DeleteNetwork { id, .. } => {
+                    db::timed("import_delete_server_networks", sqlx::query!("DELETE FROM server_networks WHERE network_id = ?1", id).execute(&mut *tx)).await?;
+                    db::timed("import_delete_node_network", sqlx::query!("DELETE FROM node_networks WHERE network_id = ?1", id).execute(&mut *tx)).await?;
+                    db::timed("import_delete_network", sqlx::query!("DELETE FROM networks WHERE network_id = ?1", id).execute(&mut *tx)).await?;
+                },
+                Change::CreateNetwork { node_id, network, .. } => {
+                    let id = db::timed("import_create_network", sqlx::query_scalar!(r#"
+                        INSERT INTO networks (
+                            network_name,
+                            network_local_ip
+                        ) VALUES (?1, ?2)
+                        RETURNING network_id;
+                    "#, network.name, network.local_ip).fetch_one(&mut *tx)).await?;
+
+                    db::timed("import_create_node_network", sqlx::query!(r#"
+                        INSERT INTO node_networks (
+                            node_id,
+                            network_id
+                        ) VALUES (?1, ?2);
+                    "#, node_id, id).execute(&mut *tx)).await?;
+
+                    plan.networks.insert((*node_id, network.name.clone()), id as i32);
+                },
+                Change::UpdateNetwork { id, network, .. } => {
+                    db::timed("import_update_network", sqlx::query!(r#"
+                        UPDATE networks
+                        SET network_local_ip = ?2
+                        WHERE network_id = ?1;
+                    "#, id, network.local_ip).execute(&mut *tx)).await?;
+                },
+                Change::CreateServer { node_id, server, .. } => {
+                    let tag = *plan.tags.get(&server.tag).ok_or_else(|| format!("Tag {} does not exist", server.tag))?;
+
+                    let id = db::timed("import_create_server", sqlx::query_scalar!(r#"
+                        INSERT INTO servers (
+                            server_name,
+                            server_tag
+                        ) VALUES (?1, ?2)
+                        RETURNING server_id;
+                    "#, server.name, tag).fetch_one(&mut *tx)).await?;
+
+                    db::timed("import_create_node_server", sqlx::query!(r#"
+                        INSERT INTO node_servers (
+                            node_id,
+                            server_id
+                        ) VALUES (?1, ?2);
+                    "#, node_id, id).execute(&mut *tx)).await?;
+
+                    insert_server(&mut tx, id as i32, *node_id, server, &plan.networks).await?;
+                },
+                Change::UpdateServer { id, server, .. } => {
+                    let tag = *plan.tags.get(&server.tag).ok_or_else(|| format!("Tag {} does not exist", server.tag))?;
+
+                    db::timed("import_update_server", sqlx::query!(r#"
+                        UPDATE servers
+                        SET server_tag = ?2
+                        WHERE server_id = ?1;
+                    "#, id, tag).execute(&mut *tx)).await?;
+
+                    let node_id = db::timed("fetch_server_node", sqlx::query_scalar!(r#"
+                        SELECT node_id FROM node_servers WHERE server_id = ?1;
+                    "#, id).fetch_one(&mut *tx)).await?;
+
+                    clear_server(&mut tx, *id).await?;
+                    insert_server(&mut tx, *id, node_id as i32, server, &plan.networks).await?;
+                },
+            }
+        }
+
+        tx.commit().await.map_err(|e| format!("Could not commit transaction: {}", e))
+    }
+}
+
+struct DbTag {
+    tag_id: i64,
+    tag_team: Option<i64>,
+    tag_name: String,
+    tag_image: String,
+    tag_docker_tags: String,
+    tag_healthcheck_test: String,
+    tag_healthcheck_interval: i64,
+    tag_healthcheck_timeout: i64,
+    tag_healthcheck_retries: i64,
+}
+
+impl TryFrom<DbTag> for TagState {
+    type Error = String;
+
+    fn try_from(tag: DbTag) -> Result<Self, String> {
+        Ok(TagState {
+            id: tag.tag_id as i32,
+            owned: tag.tag_team.is_some(),
+            tag: TagSpec {
+                healthcheck: HealthcheckSpec {
+                    test: from_json(&format!("Healthcheck test of tag {}", tag.tag_name), &tag.tag_healthcheck_test)?,
+                    interval: tag.tag_healthcheck_interval.max(0) as u32,
+                    timeout: tag.tag_healthcheck_timeout.max(0) as u32,
+                    retries: tag.tag_healthcheck_retries.max(0) as u32,
+                },
+                name: tag.tag_name,
+                image: tag.tag_image,
+                docker_tag: tag.tag_docker_tags,
+                mounts: Vec::new(),
+                env_defs: Vec::new(),
+            },
+        })
+    }
+}
+
+/// Deletes the envs, ports and network addresses of a server. Envs and ports always belong to a
+/// single server, so the ones no server refers to anymore are deleted with the links.
+async fn clear_server(tx: &mut Transaction<'_, Db>, server: i32) -> Result<(), String> {
+    db::timed("import_clear_server_envs", sqlx::query!("DELETE FROM server_envs WHERE server_id = ?1", server).execute(&mut **tx)).await?;
+    db::timed("import_clear_envs", sqlx::query!("DELETE FROM envs WHERE env_id NOT IN (SELECT env_id FROM server_envs)").execute(&mut **tx)).await?;
+    db::timed("import_clear_server_ports", sqlx::query!("DELETE FROM server_ports WHERE server_id = ?1", server).execute(&mut **tx)).await?;
+    db::timed("import_clear_ports", sqlx::query!("DELETE FROM ports WHERE port_id NOT IN (SELECT port_id FROM server_ports)").execute(&mut **tx)).await?;
+    db::timed("import_clear_server_networks", sqlx::query!("DELETE FROM server_networks WHERE server_id = ?1", server).execute(&mut **tx)).await?;
+
+    Ok(())
+}
+
+/// Inserts the envs, ports and network addresses of a server.
+async fn insert_server(tx: &mut Transaction<'_, Db>, server: i32, node: i32, spec: &ServerSpec, networks: &HashMap<(i32, String), i32>) -> Result<(), String> {
+    for (key, value) in spec.envs.iter() {
+        let secret = spec.secrets.contains(key);
+
+        let env = db::timed("import_insert_env", sqlx::query_scalar!(r#"
+            INSERT INTO envs (
+                env_key,
+                env_value,
+                env_secret
+            ) VALUES (?1, ?2, ?3)
+            RETURNING env_id;
+        "#, key, value, secret).fetch_one(&mut **tx)).await?;
+
+        db::timed("import_insert_server_env", sqlx::query!(r#"
+            INSERT INTO server_envs (
+                server_id,
+                env_id
+            ) VALUES (?1, ?2);
+        "#, server, env).execute(&mut **tx)).await?;
+    }
+
+    for port in spec.ports.iter() {
+        let protocol = i16::from(port.protocol);
+
+        let port = db::timed("import_insert_port", sqlx::query_scalar!(r#"
+            INSERT INTO ports (
+                port_port,
+                port_protocol,
+                port_mapped
+            ) VALUES (?1, ?2, ?3)
+            RETURNING port_id;
+        "#, port.port, protocol, port.mapped).fetch_one(&mut **tx)).await?;
+
+        db::timed("import_insert_server_port", sqlx::query!(r#"
+            INSERT INTO server_ports (
+                server_id,
+                port_id
+            ) VALUES (?1, ?2);
+        "#, server, port).execute(&mut **tx)).await?;
+    }
+
+    for network in spec.networks.iter() {
+        let id = networks.get(&(node, network.network.clone())).ok_or_else(|| format!("Network {} does not exist", network.network))?;
+
+        db::timed("import_insert_server_network", sqlx::query!(r#"
+            INSERT INTO server_networks (
+                server_id,
+                network_id,
+                local_ip
+            ) VALUES (?1, ?2, ?3);
+        "#, server, id, network.ip).execute(&mut **tx)).await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use packet::events::{EventData, NodeStatusEvent};
+
+    use super::*;
+
+    const NODE: Uuid = Uuid::from_u128(0x0b7e4c2d_9a1f_4e3b_8c5d_6f2a1b3c4d5e);
+
+    async fn seed() {
+        sqlx::query(r#"
+            INSERT INTO teams (team_id, team_name) VALUES (1, 'team');
+            INSERT INTO users (user_id, user_team, user_public_key) VALUES (1, 1, 'key');
+            INSERT INTO nodes (node_id, node_name, node_public_key, node_uuid) VALUES (1, 'node', 'key', '0b7e4c2d-9a1f-4e3b-8c5d-6f2a1b3c4d5e');
+            INSERT INTO team_nodes (team_id, node_id) VALUES (1, 1);
+            INSERT INTO networks (network_id, network_name, network_local_ip) VALUES (1, 'internal', 3);
+            INSERT INTO node_networks (node_id, network_id) VALUES (1, 1);
+            INSERT INTO tags (tag_id, tag_name, tag_image, tag_docker_tags, tag_healthcheck_test, tag_healthcheck_interval, tag_healthcheck_timeout, tag_healthcheck_retries, tag_team, tag_build_git_url)
+                VALUES (1, 'app', 'app', 'latest', '["CMD", "true"]', 30, 5, 3, 1, 'https://example.com/app.git');
+            INSERT INTO mounts (mount_id, mount_container_path, mount_host_path) VALUES (1, '/data', '/srv/app');
+            INSERT INTO tag_mounts (tag_id, mount_id) VALUES (1, 1);
+            INSERT INTO servers (server_id, server_name, server_tag, server_isolation_policy, server_isolation_allowlist, server_update_strategy) VALUES (1, 'web', 1, 2, '["10.0.0.0/8"]', 1);
+            INSERT INTO servers (server_id, server_name, server_tag) VALUES (2, 'db', 1);
+            INSERT INTO node_servers (node_id, server_id) VALUES (1, 1), (1, 2);
+            INSERT INTO envs (env_id, env_key, env_value, env_secret) VALUES (1, 'TOKEN', 'secret', TRUE);
+            INSERT INTO server_envs (server_id, env_id) VALUES (1, 1);
+            INSERT INTO ports (port_id, port_port, port_protocol, port_mapped) VALUES (1, 80, 0, 8080);
+            INSERT INTO server_ports (server_id, port_id) VALUES (1, 1);
+            INSERT INTO server_networks (server_id, network_id, local_ip) VALUES (1, 1, 2);
+            INSERT INTO server_dependencies (server_id, dependency_server_id, dependency_wait_healthy) VALUES (1, 2, TRUE);
+            INSERT INTO server_labels (server_id, server_label_key, server_label_value) VALUES (1, 'env', 'prod');
+            INSERT INTO maintenance_windows (node_id, maintenance_window_cron, maintenance_window_duration) VALUES (1, '0 4 * * *', 3600);
+            INSERT INTO wasm_extensions (wasm_extension_id, team_id, wasm_extension_name, wasm_extension_position, wasm_extension_module) VALUES (1, 1, 'last', 1, X'00'), (2, 1, 'first', 0, X'01');
+        "#).execute(db::sqlite_memory().await).await.unwrap();
+    }
+
+    // a single test, as the in-memory database is shared and lives as long as its runtime
+    #[tokio::test]
+    async fn repository() {
+        seed().await;
+
+        let spec = Sqlite.spec(NODE).await.unwrap();
+
+        assert_eq!(spec.networks, vec![Network { id: 1, subnet: 3 }]);
+        assert_eq!(spec.servers.len(), 2);
+        assert_eq!(spec.maintenance, vec![MaintenanceWindow { cron: "0 4 * * *".to_string(), duration: 3600 }]);
+        assert!(!spec.hash.is_empty());
+
+        let web = &spec.servers[0];
+        assert_eq!(web.tag.healthcheck.test, vec!["CMD".to_string(), "true".to_string()]);
+        assert_eq!(web.tag.mounts, vec![Mount { container_path: "/data".to_string(), host_path: "/srv/app".to_string() }]);
+        assert_eq!(web.tag.build, Some(Build { context: BuildContext::Git("https://example.com/app.git".to_string()), dockerfile: "Dockerfile".to_string() }));
+        assert_eq!(web.envs, vec![Env { key: "TOKEN".to_string(), value: "secret".to_string() }]);
+        assert_eq!(web.ports, vec![Port { port: 80, mapped: 8080, protocol: Protocol::from(0) }]);
+        assert_eq!(web.networks, vec![ServerNetwork { network: 1, ip: 2 }]);
+        assert_eq!(web.isolation.allowlist, vec!["10.0.0.0/8".to_string()]);
+        assert_eq!(web.depends_on, vec![Dependency { server: 2, healthy: true }]);
+        assert_eq!(spec.metadata[0].labels.get("env").map(String::as_str), Some("prod"));
+
+        assert_eq!(Sqlite.team_daemons(1).await.unwrap(), vec![NODE]);
+        assert_eq!(Sqlite.node_team(NODE).await.unwrap(), Some(1));
+
+        let extensions = Sqlite.team_extensions(1).await.unwrap();
+        assert_eq!(extensions.iter().map(|extension| extension.name.as_str()).collect::<Vec<_>>(), vec!["first", "last"]);
+        assert_eq!(extensions[0].module, vec![1]);
+
+        let tags = Sqlite.spec_tags(1, &["app".to_string()]).await.unwrap();
+        assert!(tags["app"].owned);
+
+        let nodes = Sqlite.spec_nodes(&[NODE]).await.unwrap();
+        let (id, web) = &nodes[&NODE].servers[0];
+        assert_eq!(*id, 1);
+        assert!(web.secrets.contains("TOKEN"));
+        assert_eq!(web.networks[0].network, "internal");
+
+        // deleting the server others depend on also deletes the dependencies on it
+        Sqlite.apply(Plan {
+            changes: vec![Change::DeleteServer { node: NODE, id: 2, name: "db".to_string() }],
+            team: 1,
+            tags: HashMap::new(),
+            networks: HashMap::new(),
+        }).await.unwrap();
+
+        let spec = Sqlite.spec(NODE).await.unwrap();
+        assert_eq!(spec.servers.len(), 1);
+        assert!(spec.servers[0].depends_on.is_empty());
+
+        Sqlite.store_notification(&Recipients::Team(NODE), NotificationKind::SyncFailed, Some(1), "failed", 10).await.unwrap();
+
+        let (notifications, unread) = Sqlite.notifications(1, true, None, 10).await.unwrap();
+        assert_eq!(unread, 1);
+        assert_eq!(notifications[0].daemon, Some(NODE));
+
+        Sqlite.mark_read(1, Some(&[notifications[0].id])).await.unwrap();
+        let (notifications, unread) = Sqlite.notifications(1, true, None, 10).await.unwrap();
+        assert!(notifications.is_empty());
+        assert_eq!(unread, 0);
+
+        // the latest events of each daemon are returned, oldest first
+        let other = Uuid::from_u128(1);
+        let data = serde_json::to_string(&EventData::NodeStatus(NodeStatusEvent {
+            online: true,
+            stats: None,
+            in_maintenance: false,
+            servers: None,
+            max_servers: None,
+            info: None,
+        })).unwrap();
+
+        let events = [(NODE, 1), (other, 2), (NODE, 3), (NODE, 4)].map(|(uuid, seq)| NewEvent {
+            uuid,
+            event_type: 0,
+            seq: Some(seq),
+            timestamp: seq * 10,
+            data: data.clone(),
+        });
+        Sqlite.store_events(&events).await.unwrap();
+
+        let stored = Sqlite.events(&[NODE, other], 0, 2).await.unwrap();
+        let seqs = stored.iter().map(|(uuid, event)| (*uuid, event.seq.unwrap(), event.timestamp)).collect::<HashSet<_>>();
+        assert_eq!(seqs, HashSet::from([(NODE, 3, 30), (NODE, 4, 40), (other, 2, 20)]));
+        assert_eq!(stored.iter().filter(|(uuid, _)| *uuid == NODE).map(|(_, event)| event.seq.unwrap()).collect::<Vec<_>>(), vec![3, 4]);
+    }
+}
@@ -0,0 +1,568 @@
+use std::{collections::{BTreeMap, HashMap, HashSet, hash_map::DefaultHasher}, hash::{Hash, Hasher}, net::SocketAddr, path::Path, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use packet::{events::MetricSample, server_daemon::{catalog::CatalogImage, sync::{Dependency, Env, EnvDef, EnvType, Healthcheck, Isolation, IsolationPolicy, Mount, Network, Port, Protocol, SDSyncPacket, Server, ServerMetadata, ServerNetwork, Tag, UpdateStrategy}}, server_web::{query_connections_response::ConnectionSession, query_notifications_response::{Notification, NotificationKind}}};
+use sqlx::types::Uuid;
+use tokio::sync::RwLock;
+use tracing::{info, warn};
+
+use crate::{alerts::AlertRule, config::CONFIG, history::{NewEvent, StoredEvent}, import::Plan, inbox::Recipients, quotas::{DaemonQuota, Reservation, ServerQuota}, repository::{Repository, WasmExtension}, sessions::{Peer, Session}, spec::{EnvTypeSpec, IsolationPolicySpec, NodeState, Spec, SpecFormat, TagSpec, TagState, UpdateSpec}, state::State};
+
+/// The file of the folder containing the public keys, in the format of the `file` authentication
+/// backend. Every other `.toml`, `.yaml` or `.yml` file of the folder is a spec file.
+const KEYS_FILE: &str = "keys.toml";
+
+/// What everything standalone mode doesn't store fails with, being the server's own records and
+/// the features that change specs, as the spec files are only read.
+const UNSUPPORTED: &str = "Not supported in standalone mode";
+
+lazy_static! {
+    /// The spec files of the folder, merged into one
+    static ref SPEC: RwLock<Spec> = RwLock::new(Spec::default());
+    /// Hash of the contents of the folder when it was last read, to notice changes
+    static ref FINGERPRINT: RwLock<Option<u64>> = RwLock::new(None);
+    /// Host ports daemons assigned to servers, which are kept in the database otherwise
+    static ref ASSIGNED_PORTS: DashMap<u32, Vec<Port>> = DashMap::new();
+}
+
+/// Returns whether the server runs without a database, see `config::Standalone`.
+pub fn enabled() -> bool {
+    CONFIG.standalone.enabled
+}
+
+/// Returns the path of the file containing the public keys of users and daemons.
+pub fn keys_file() -> String {
+    Path::new(&CONFIG.standalone.folder).join(KEYS_FILE).to_string_lossy().to_string()
+}
+
+/// Returns the ID of a network or server, which spec files identify by name only. The ID is
+/// derived from the node and the name, so it stays the same as long as they do.
+fn id(node: &Uuid, kind: &str, name: &str) -> u32 {
+    // FNV-1a, as the IDs have to be the same across restarts and versions of the server
+    let hash = format!("{}/{}/{}", node, kind, name).bytes().fold(0x811c9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x01000193));
+
+    // IDs are stored as positive `i32`s elsewhere
+    (hash & 0x7fff_ffff).max(1)
+}
+
+/// Reads the spec files of the folder, ordered by name.
+async fn read_folder() -> Result<Vec<(String, String)>, String> {
+    let folder = &CONFIG.standalone.folder;
+    let mut entries = tokio::fs::read_dir(folder).await.map_err(|e| format!("Could not read folder {}: {}", folder, e))?;
+    let mut files = Vec::new();
+
+    while let Some(entry) = entries.next_entry().await.map_err(|e| format!("Could not read folder {}: {}", folder, e))? {
+        let path = entry.path();
+
+        if path.extension().is_some_and(|extension| extension == "toml" || extension == "yaml" || extension == "yml") {
+            let contents = tokio::fs::read_to_string(&path).await.map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+            files.push((entry.file_name().to_string_lossy().to_string(), contents));
+        }
+    }
+
+    files.sort();
+
+    Ok(files)
+}
+
+/// Merges the spec files, checking that everything the servers refer to exists and that no two
+/// networks or servers end up with the same ID.
+fn merge(files: &[(String, String)]) -> Result<Spec, String> {
+    let mut spec = Spec::default();
+
+    for (name, contents) in files.iter().filter(|(name, _)| name != KEYS_FILE) {
+        let file = Spec::parse(contents, SpecFormat::from_path(name)).map_err(|e| format!("{}: {}", name, e))?;
+        spec.tags.extend(file.tags);
+        spec.nodes.extend(file.nodes);
+    }
+
+    spec.validate()?;
+
+    let tags = spec.tags.iter().map(|tag| tag.name.as_str()).collect::<HashSet<_>>();
+    let mut ids = HashMap::new();
+
+    for node in spec.nodes.iter() {
+        for network in node.networks.iter() {
+            if let Some(other) = ids.insert(id(&node.uuid, "network", &network.name), format!("network {} of node {}", network.name, node.uuid)) {
+                return Err(format!("Network {} of node {} has the same ID as {}, rename one of them", network.name, node.uuid, other));
+            }
+        }
+
+        for server in node.servers.iter() {
+            if !tags.contains(server.tag.as_str()) {
+                return Err(format!("Server {} uses tag {}, which is not in any spec file", server.name, server.tag));
+            }
+
+            if let Some(key) = server.secrets.iter().find(|key| !server.envs.contains_key(*key)) {
+                return Err(format!("Secret env {} of server {} has no value", key, server.name));
+            }
+
+            if let Some(other) = ids.insert(id(&node.uuid, "server", &server.name), format!("server {} of node {}", server.name, node.uuid)) {
+                return Err(format!("Server {} of node {} has the same ID as {}, rename one of them", server.name, node.uuid, other));
+            }
+        }
+    }
+
+    Ok(spec)
+}
+
+/// Reads the folder again if anything in it changed. Returns whether it did.
+pub async fn load() -> Result<bool, String> {
+    let files = read_folder().await?;
+
+    let mut hasher = DefaultHasher::new();
+    files.hash(&mut hasher);
+    let fingerprint = hasher.finish();
+
+    // broken files are only reported once, the previous spec is kept until they are fixed
+    if FINGERPRINT.write().await.replace(fingerprint) == Some(fingerprint) {
+        return Ok(false);
+    }
+
+    *SPEC.write().await = merge(&files)?;
+
+    Ok(true)
+}
+
+/// Checks the folder for changes every `standalone.interval` seconds, and syncs the daemons of
+/// its nodes whenever it changed.
+pub async fn run(state: Arc<State>) {
+    if !enabled() {
+        return;
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(CONFIG.standalone.interval));
+
+    loop {
+        interval.tick().await;
+
+        match load().await {
+            Ok(true) => (),
+            Ok(false) => continue,
+            Err(e) => {
+                warn!("Could not reload {}: {}", CONFIG.standalone.folder, e);
+                continue;
+            },
+        }
+
+        info!("Reloaded {}", CONFIG.standalone.folder);
+
+        state.invalidate_specs();
+        state.web_key_cache.clear();
+        state.daemon_key_cache.clear();
+
+        for node in daemons().await {
+            if let Err(e) = state.sync_daemon(node, None, HashMap::new()).await {
+                warn!("Could not sync daemon {} after reload: {}", node, e);
+            }
+        }
+    }
+}
+
+/// Returns the daemons of the nodes in the spec files.
+pub async fn daemons() -> Vec<Uuid> {
+    SPEC.read().await.nodes.iter().map(|node| node.uuid).collect()
+}
+
+/// Assembles the full spec of a daemon from the merged spec files, with the host ports daemons
+/// assigned to servers without a fixed one.
+fn sync(spec: &Spec, uuid: Uuid) -> Result<SDSyncPacket, String> {
+    let node = spec.nodes.iter().find(|node| node.uuid == uuid).ok_or(format!("Node {} is not in any spec file", uuid))?;
+    let tags = spec.tags.iter().map(|tag| (tag.name.as_str(), tag)).collect::<HashMap<_, _>>();
+
+    let servers = node.servers.iter().map(|server| {
+        let server_id = id(&uuid, "server", &server.name);
+        let tag = tags.get(server.tag.as_str()).ok_or(format!("Tag {} does not exist", server.tag))?;
+        let assigned = ASSIGNED_PORTS.get(&server_id).map(|ports| ports.clone()).unwrap_or_default();
+        let isolation = server.isolation.clone().unwrap_or_default();
+
+        Ok(Server {
+            id: server_id,
+            tag: Tag {
+                image: tag.image.clone(),
+                docker_tag: tag.docker_tag.clone(),
+                healthcheck: Healthcheck {
+                    test: tag.healthcheck.test.clone(),
+                    interval: tag.healthcheck.interval as u64,
+                    timeout: tag.healthcheck.timeout as u64,
+                    retries: tag.healthcheck.retries as u64,
+                },
+                mounts: tag.mounts.iter().map(|mount| Mount {
+                    container_path: mount.container_path.clone(),
+                    host_path: mount.host_path.clone(),
+                }).collect(),
+                env_defs: tag.env_defs.iter().map(|env_def| EnvDef {
+                    key: env_def.key.clone(),
+                    required: env_def.required,
+                    env_type: match env_def.env_type {
+                        EnvTypeSpec::Boolean => EnvType::Boolean,
+                        EnvTypeSpec::Number => EnvType::Number,
+                        EnvTypeSpec::String => EnvType::String,
+                    },
+                    default: env_def.default.clone(),
+                    regex: env_def.regex.clone(),
+                    min: env_def.min,
+                    max: env_def.max,
+                    trim: env_def.trim,
+                }).collect(),
+                build: None,
+            },
+            envs: server.envs.iter().map(|(key, value)| Env {
+                key: key.clone(),
+                value: value.clone(),
+            }).collect(),
+            networks: server.networks.iter().map(|network| ServerNetwork {
+                network: id(&uuid, "network", &network.network),
+                ip: network.ip,
+            }).collect(),
+            ports: server.ports.iter().map(|port| {
+                let protocol = Protocol::from(i16::from(port.protocol) as u8);
+
+                // ports without a fixed host port keep the one the daemon assigned
+                let mapped = match port.mapped {
+                    0 => assigned.iter().find(|assigned| assigned.port == port.port && assigned.protocol == protocol).map(|assigned| assigned.mapped).unwrap_or(0),
+                    mapped => mapped,
+                };
+
+                Port {
+                    port: port.port,
+                    protocol,
+                    mapped,
+                }
+            }).collect(),
+            isolation: Isolation {
+                policy: match isolation.policy {
+                    IsolationPolicySpec::FullEgress => IsolationPolicy::FullEgress,
+                    IsolationPolicySpec::NoInternet => IsolationPolicy::NoInternet,
+                    IsolationPolicySpec::InternalOnly => IsolationPolicy::InternalOnly,
+                },
+                allowlist: isolation.allowlist,
+            },
+            maintenance: Vec::new(),
+            depends_on: server.depends_on.iter().map(|dependency| Dependency {
+                server: id(&uuid, "server", &dependency.server),
+                healthy: dependency.healthy,
+            }).collect(),
+            update: match server.update {
+                UpdateSpec::Recreate => UpdateStrategy::Recreate,
+                UpdateSpec::BlueGreen => UpdateStrategy::BlueGreen,
+            },
+        })
+    }).collect::<Result<Vec<_>, String>>()?;
+
+    let mut sync = SDSyncPacket {
+        networks: node.networks.iter().map(|network| Network {
+            id: id(&uuid, "network", &network.name),
+            subnet: network.local_ip,
+        }).collect(),
+        metadata: node.servers.iter().map(|server| ServerMetadata {
+            server: id(&uuid, "server", &server.name),
+            name: server.name.clone(),
+            labels: server.labels.clone(),
+        }).collect(),
+        servers,
+        maintenance: Vec::new(),
+        hash: String::new(),
+        base: String::new(),
+        removed_networks: Vec::new(),
+        removed_servers: Vec::new(),
+        request: None,
+    };
+
+    sync.hash = sync.spec_hash()?;
+
+    Ok(sync)
+}
+
+/// `Standalone` serves the nodes of the spec files in `standalone.folder`, to the users they list.
+/// Host ports daemons assign are only kept in memory.
+pub struct Standalone;
+
+#[async_trait]
+impl Repository for Standalone {
+    async fn team_daemons(&self, user_id: u32) -> Result<Vec<Uuid>, String> {
+        Ok(SPEC.read().await.nodes.iter().filter(|node| node.users.contains(&user_id)).map(|node| node.uuid).collect())
+    }
+
+    async fn spec(&self, uuid: Uuid) -> Result<SDSyncPacket, String> {
+        sync(&*SPEC.read().await, uuid)
+    }
+
+    fn writable(&self) -> Result<(), String> {
+        // standalone mode is read-only, but its specs don't come from the database
+        Ok(())
+    }
+
+    async fn assign_ports(&self, _uuid: Uuid, server: u32, ports: &[Port]) -> Result<(), String> {
+        ASSIGNED_PORTS.insert(server, ports.to_vec());
+        Ok(())
+    }
+
+    async fn ping(&self) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn node_team(&self, _uuid: Uuid) -> Result<Option<u32>, String> {
+        // teams don't exist in standalone mode
+        Ok(None)
+    }
+
+    async fn team_extensions(&self, _team: u32) -> Result<Vec<WasmExtension>, String> {
+        Ok(Vec::new())
+    }
+
+    async fn group_members(&self, _group: u32) -> Result<Vec<Uuid>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn assign_server(&self, _daemon: Uuid, _server: u32) -> Result<bool, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn update_metadata(&self, _uuid: Uuid, _server: u32, _name: Option<String>, _labels: Option<BTreeMap<String, String>>) -> Result<ServerMetadata, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn build_context(&self, _uuid: Uuid, _hash: &str) -> Result<Option<Vec<u8>>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn catalog(&self, _limit: u32) -> Result<Vec<CatalogImage>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn alert_rules(&self, _uuid: Uuid) -> Result<Vec<AlertRule>, String> {
+        Ok(Vec::new())
+    }
+
+    // quotas are per team, which don't exist in standalone mode
+    async fn daemon_quotas(&self, _uuid: Uuid) -> Result<Vec<DaemonQuota>, String> {
+        Ok(Vec::new())
+    }
+
+    async fn server_quotas(&self, _daemon: Uuid) -> Result<Vec<ServerQuota>, String> {
+        Ok(Vec::new())
+    }
+
+    async fn server_reservation(&self, _server: u32) -> Result<Option<Reservation>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn store_session(&self, _peer: &Peer, _addr: SocketAddr, _session: &Session) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn sessions(&self, _uuid: Uuid, _limit: u32) -> Result<Vec<ConnectionSession>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn delete_sessions(&self, _before: u64) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn store_notification(&self, _recipients: &Recipients, _kind: NotificationKind, _server: Option<u32>, _message: &str, _created_at: u64) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn notifications(&self, _user_id: u32, _unread_only: bool, _before: Option<u64>, _limit: u32) -> Result<(Vec<Notification>, u64), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn mark_read(&self, _user_id: u32, _ids: Option<&[u64]>) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn delete_notifications(&self, _before: u64) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn store_metrics(&self, _uuid: Uuid, _server: u32, _sample: &MetricSample) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn metrics(&self, _uuid: Uuid, _server: u32, _from: i64, _to: i64, _hourly: bool) -> Result<Vec<MetricSample>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn rollup_metrics(&self, _from: i64, _to: i64) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn delete_metrics(&self, _raw_before: u64, _rollups_before: u64) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn store_events(&self, _events: &[NewEvent]) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn events(&self, _uuids: &[Uuid], _event_type: i16, _limit: u32) -> Result<Vec<(Uuid, StoredEvent)>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn delete_events(&self, _before: u64) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn user_team(&self, _user_id: u32) -> Result<u32, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn team_nodes(&self, _team: u32) -> Result<Vec<Uuid>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn spec_tags(&self, _team: u32, _names: &[String]) -> Result<HashMap<String, TagState>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn node_tags(&self, _uuids: &[Uuid]) -> Result<Vec<TagSpec>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn spec_nodes(&self, _uuids: &[Uuid]) -> Result<HashMap<Uuid, NodeState>, String> {
+        Err(UNSUPPORTED.to_string())
+    }
+
+    async fn apply(&self, _plan: Plan) -> Result<(), String> {
+        Err(UNSUPPORTED.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NODE: Uuid = Uuid::from_u128(0x6d3f2b1a_0c4e_4f5a_9b7d_2e8c1a3f4b5d);
+
+    const TAGS: &str = r#"
+        [[tags]]
+        name = "minecraft"
+        image = "itzg/minecraft-server"
+        docker_tag = "latest"
+        healthcheck = { test = ["CMD", "mc-health"], interval = 30, timeout = 5, retries = 3 }
+        mounts = [{ container_path = "/data", host_path = "/srv/minecraft" }]
+        env_defs = [{ key = "MEMORY", type = "number", default = "2048", min = 512 }]
+    "#;
+
+    const NODES: &str = r#"
+nodes:
+  - uuid: 6d3f2b1a-0c4e-4f5a-9b7d-2e8c1a3f4b5d
+    users: [1]
+    networks:
+      - name: internal
+        local_ip: 1
+    servers:
+      - name: proxy
+        tag: minecraft
+        ports:
+          - port: 25565
+            protocol: tcp
+      - name: survival
+        tag: minecraft
+        networks:
+          - network: internal
+            ip: 2
+        isolation:
+          policy: internal_only
+          allowlist: [10.0.0.0/8]
+        depends_on:
+          - server: proxy
+            healthy: true
+        update: blue_green
+        labels:
+          env: prod
+"#;
+
+    fn files(nodes: &str) -> Vec<(String, String)> {
+        vec![
+            ("keys.toml".to_string(), "[users]\n1 = \"key\"\n".to_string()),
+            ("nodes.yaml".to_string(), nodes.to_string()),
+            ("tags.toml".to_string(), TAGS.to_string()),
+        ]
+    }
+
+    #[test]
+    fn ids_stable_and_distinct() {
+        assert_eq!(id(&NODE, "server", "survival"), 2007970835);
+        assert_eq!(id(&NODE, "network", "survival"), 1193746486);
+        assert_ne!(id(&NODE, "server", "survival"), id(&Uuid::nil(), "server", "survival"));
+    }
+
+    #[test]
+    fn files_merged() {
+        let spec = merge(&files(NODES)).expect("could not merge spec files");
+
+        assert_eq!(spec.tags.len(), 1);
+        assert_eq!(spec.nodes.len(), 1);
+        assert_eq!(spec.nodes[0].servers.len(), 2);
+    }
+
+    #[test]
+    fn broken_references_rejected() {
+        let missing_tag = NODES.replace("tag: minecraft\n        networks", "tag: paper\n        networks");
+        assert_eq!(merge(&files(&missing_tag)).unwrap_err(), "Server survival uses tag paper, which is not in any spec file");
+
+        let missing_dependency = NODES.replace("server: proxy", "server: lobby");
+        assert_eq!(merge(&files(&missing_dependency)).unwrap_err(), format!("nodes.yaml: Server survival depends on server lobby, which is not a server of node {}", NODE));
+
+        let mut duplicate = files(NODES);
+        duplicate.push(("other.yml".to_string(), NODES.to_string()));
+        assert_eq!(merge(&duplicate).unwrap_err(), format!("Duplicate node {}", NODE));
+    }
+
+    #[test]
+    fn spec_complete() {
+        let spec = merge(&files(NODES)).expect("could not merge spec files");
+        let packet = sync(&spec, NODE).expect("could not assemble spec");
+
+        let survival = packet.servers.iter().find(|server| server.id == id(&NODE, "server", "survival")).expect("survival is missing");
+        assert_eq!(survival.tag.mounts, vec![Mount {
+            container_path: "/data".to_string(),
+            host_path: "/srv/minecraft".to_string(),
+        }]);
+        assert_eq!(survival.tag.env_defs[0].env_type, EnvType::Number);
+        assert_eq!(survival.tag.env_defs[0].min, Some(512));
+        assert_eq!(survival.networks[0].network, packet.networks[0].id);
+        assert_eq!(survival.isolation.policy, IsolationPolicy::InternalOnly);
+        assert_eq!(survival.isolation.allowlist, vec!["10.0.0.0/8".to_string()]);
+        assert_eq!(survival.depends_on, vec![Dependency {
+            server: id(&NODE, "server", "proxy"),
+            healthy: true,
+        }]);
+        assert_eq!(survival.update, UpdateStrategy::BlueGreen);
+
+        let metadata = packet.metadata.iter().find(|metadata| metadata.server == survival.id).expect("metadata is missing");
+        assert_eq!(metadata.labels.get("env").map(String::as_str), Some("prod"));
+
+        assert!(sync(&spec, Uuid::nil()).is_err());
+    }
+
+    #[test]
+    fn assigned_ports_kept() {
+        let nodes = NODES.replace("6d3f2b1a", "7d3f2b1a");
+        let node = Uuid::parse_str("7d3f2b1a-0c4e-4f5a-9b7d-2e8c1a3f4b5d").expect("invalid UUID");
+        let spec = merge(&files(&nodes)).expect("could not merge spec files");
+        let proxy = id(&node, "server", "proxy");
+
+        ASSIGNED_PORTS.insert(proxy, vec![Port {
+            port: 25565,
+            protocol: Protocol::Tcp,
+            mapped: 30001,
+        }]);
+
+        let packet = sync(&spec, node).expect("could not assemble spec");
+        let ports = &packet.servers.iter().find(|server| server.id == proxy).expect("proxy is missing").ports;
+        assert_eq!(ports[0].mapped, 30001);
+    }
+
+    #[tokio::test]
+    async fn only_listed_users_reach_nodes() {
+        *SPEC.write().await = merge(&files(NODES)).expect("could not merge spec files");
+
+        assert_eq!(Standalone.team_daemons(1).await, Ok(vec![NODE]));
+        assert_eq!(Standalone.team_daemons(2).await, Ok(Vec::new()));
+    }
+}
@@ -1,15 +1,18 @@
-use std::{borrow::Borrow, collections::{HashMap, HashSet}, fmt::Write, net::SocketAddr, sync::Arc};
+use std::{borrow::Borrow, collections::{hash_map::Entry, HashMap, HashSet, VecDeque}, fmt::Write, hash::Hash, net::SocketAddr, sync::{atomic::{AtomicU64, Ordering}, Arc, OnceLock}, time::{Duration, Instant}};
 
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
 use dashmap::DashMap;
 use futures_channel::mpsc;
+use futures_util::future;
 use josekit::jwe::alg::rsaes::RsaesJweEncrypter;
 use openssl::rand::rand_bytes;
-use packet::{events::{EventData, EventType, ListenEvent, NodeStatusEvent}, server_daemon::{auth_response::SDAuthResponsePacket, handshake_request::SDHandshakeRequestPacket, listen::SDListenPacket, sync::{Env, EnvDef, EnvType, Healthcheck, Mount, Network, Port, Protocol, SDSyncPacket, Server, ServerNetwork, Tag}}, server_web::{auth_response::SWAuthResponsePacket, event::SWEventPacket, handshake_request::SWHandshakeRequestPacket}};
+use packet::{ack, chunk, close::CloseReason, features::{Feature, Features}, flow::{self, Window}, heartbeat::{PingPacket, PongPacket}, subprotocol::Encoding, terminal::Reorder, daemon_server::{auth::DSAuthPacket, command_result::DSCommandResultPacket, error::DSErrorPacket, event::DSEventPacket, fetch_build_context::DSFetchBuildContextPacket, query_logs_response::DSQueryLogsResponsePacket, query_stats_response::DSQueryStatsResponsePacket, query_tasks_response::DSQueryTasksResponsePacket, query_top_response::DSQueryTopResponsePacket, query_usage_response::DSQueryUsageResponsePacket, sync_progress::DSSyncProgressPacket, terminal_close::DSTerminalClosePacket, terminal_output::DSTerminalOutputPacket}, events::{EventData, EventType, FleetSummaryEvent, ListenEvent, NodeStats, NodeStatusEvent, ServerCounts, ServerStatusEvent, ServerStatusType, SyncStep, UpdateRequiredEvent}, server_daemon::{auth_response::SDAuthResponsePacket, build_context::SDBuildContextPacket, cancel_task::SDCancelTaskPacket, command::SDCommandPacket, handshake_request::SDHandshakeRequestPacket, listen::SDListenPacket, query_logs::SDQueryLogsPacket, query_stats::SDQueryStatsPacket, query_tasks::SDQueryTasksPacket, query_top::SDQueryTopPacket, query_usage::SDQueryUsagePacket, reconnect_to::SDReconnectToPacket, server_metadata::SDServerMetadataPacket, terminal_close::SDTerminalClosePacket, terminal_input::SDTerminalInputPacket, terminal_open::SDTerminalOpenPacket, window_update::SDWindowUpdatePacket, sync::{Port, SDSyncPacket}}, server_web::{auth_response::SWAuthResponsePacket, command_result::SWCommandResultPacket, error::{ErrorCode, SWErrorPacket}, event::SWEventPacket, export_spec_response::SWExportSpecResponsePacket, handshake_request::SWHandshakeRequestPacket, import_spec_response::SWImportSpecResponsePacket, place_server_response::{PlacementCandidate, SWPlaceServerResponsePacket}, query_connections_response::SWQueryConnectionsResponsePacket, query_logs_response::SWQueryLogsResponsePacket, query_top_response::SWQueryTopResponsePacket, query_metrics_response::SWQueryMetricsResponsePacket, query_notifications_response::{NotificationKind, SWQueryNotificationsResponsePacket}, query_stats_response::SWQueryStatsResponsePacket, query_tasks_response::SWQueryTasksResponsePacket, query_team_usage_response::{SWQueryTeamUsageResponsePacket, Usage}, query_usage_response::SWQueryUsageResponsePacket, server_metadata_response::SWServerMetadataResponsePacket, sync_group_result::{GroupSyncResult, SWSyncGroupResultPacket}, sync_progress::SWSyncProgressPacket, terminal_close::SWTerminalClosePacket, terminal_output::SWTerminalOutputPacket}, web_server::{cancel_task::WSCancelTaskPacket, command::WSCommandPacket, export_spec::WSExportSpecPacket, import_spec::WSImportSpecPacket, mark_notifications_read::WSMarkNotificationsReadPacket, place_server::WSPlaceServerPacket, query_connections::WSQueryConnectionsPacket, query_logs::WSQueryLogsPacket, query_metrics::WSQueryMetricsPacket, query_notifications::WSQueryNotificationsPacket, query_snapshot::WSQuerySnapshotPacket, query_stats::WSQueryStatsPacket, query_tasks::WSQueryTasksPacket, query_team_usage::WSQueryTeamUsagePacket, query_top::WSQueryTopPacket, query_usage::WSQueryUsagePacket, server_metadata::WSServerMetadataPacket, terminal_close::WSTerminalClosePacket, terminal_input::WSTerminalInputPacket, terminal_open::WSTerminalOpenPacket, window_update::WSWindowUpdatePacket}, Packet};
 use sqlx::types::Uuid;
+use tokio::sync::{oneshot, Mutex};
 use tokio_tungstenite::tungstenite::Message;
-use tracing::warn;
+use tracing::{debug, error, info, warn};
 
-use crate::{db, encryption};
+use crate::{accounting, alerts, automation, builds, catalog, config::CONFIG, encryption, fanout::Fanout, history::{self, StoredEvent}, import, inbox::{self, Recipients}, metadata, metrics, placement, plugins, quotas, rate_limit::{Bucket, PacketClass}, repository, server, sessions, spec::{self, Spec, SpecFormat}, telemetry};
 
 /// `Tx` is a type alias for the transmitting end of an `mpsc::unbounded` channel.
 pub type Tx = mpsc::UnboundedSender<Message>;
@@ -19,34 +22,56 @@ pub type Rx = mpsc::UnboundedReceiver<Message>;
 /// WebHandshake is a struct that contains the information required to send a handshake request to
 /// the web client.
 pub struct WebHandshake {
-    #[allow(dead_code)] // TODO: this should be used to authenticate which user can access which
-                        //       daemons
+    // TODO: this should also be used to authenticate which user can access which daemons
     user_id: u32,
+    /// Public key of the user, which all of its web clients share
+    key: Arc<Vec<u8>>,
     encrypter: RsaesJweEncrypter,
     challenge: String,
+    features: Features,
+    /// Whether the web client has answered the challenge
+    authenticated: bool,
 }
 
 /// WebSocket is a struct that contains the transmitting end of the `mpsc::unbounded` channel, to
-/// send messages to the web client, as well as an optional `WebHandshake` (if the handshake
-/// request has been sent).
+/// send messages to the web client, the encoding negotiated with its subprotocol, as well as an
+/// optional `WebHandshake` (if the handshake request has been sent).
 pub struct WebSocket {
     tx: Tx,
+    encoding: Encoding,
     handshake: Option<WebHandshake>,
 }
 
+impl WebSocket {
+    /// Returns whether a feature was negotiated with the web client.
+    fn has_feature(&self, feature: Feature) -> bool {
+        self.handshake.as_ref().is_some_and(|handshake| handshake.features.has(feature))
+    }
+}
+
 /// `DaemonHandshake` is a struct that contains the information required to send a handshake request
 /// to the daemon.
 pub struct DaemonHandshake {
     daemon_uuid: Uuid,
     encrypter: RsaesJweEncrypter,
     challenge: String,
+    sync_hash: String,
+    features: Features,
+    /// Why the daemon is rejected once it has authenticated, e.g. because its team has reached its
+    /// daemon quota
+    rejection: Option<String>,
+    /// Reported to web clients once the daemon has authenticated, if its version is unsupported
+    update: Option<UpdateRequiredEvent>,
+    /// Whether the daemon has answered the challenge and was accepted
+    authenticated: bool,
 }
 
 /// `DaemonSocket` is a struct that contains the transmitting end of the `mpsc::unbounded` channel, to
-/// send messages to the daemon, as well as an optional `DaemonHandshake` (if the handshake request
-/// has been sent).
+/// send messages to the daemon, the encoding negotiated with its subprotocol, as well as an
+/// optional `DaemonHandshake` (if the handshake request has been sent).
 pub struct DaemonSocket {
     tx: Tx,
+    encoding: Encoding,
     handshake: Option<DaemonHandshake>,
 }
 
@@ -71,6 +96,238 @@ pub type DaemonListenMap = Arc<DashMap<Uuid, HashMap<EventType, HashSet<SocketAd
 pub type WebListenMap = Arc<DashMap<SocketAddr, HashMap<EventType, HashSet<Uuid>>>>;
 /// `DaemonIDMap` is a type alias for a `DashMap` mapping a `Uuid` to a `SocketAddr`.
 pub type DaemonIDMap = Arc<DashMap<Uuid, SocketAddr>>;
+/// `PendingQueryMap` is a type alias for a `DashMap` mapping a request id to the `SocketAddr` of
+/// the web client that sent the query, the `Uuid` of the daemon it was forwarded to, and when it
+/// was sent.
+pub type PendingQueryMap = Arc<DashMap<u64, (SocketAddr, Uuid, Instant)>>;
+/// `PendingCommandMap` is a type alias for a `DashMap` mapping a request id to the `SocketAddr` of
+/// the web client that ran the command, its user, the `Uuid` of the daemon it was forwarded to,
+/// and when it was sent. Unlike queries, commands outlive their web client, as their result is
+/// stored in the inbox of the user.
+pub type PendingCommandMap = Arc<DashMap<u64, (SocketAddr, u32, Uuid, Instant)>>;
+/// `PendingAckMap` is a type alias for a `DashMap` mapping a request id to the `SocketAddr` of the
+/// daemon the packet was sent to, and the channel waiting for it to be acknowledged.
+pub type PendingAckMap = Arc<DashMap<u64, (SocketAddr, oneshot::Sender<Result<(), String>>)>>;
+/// `Heartbeat` is when a connection that supports heartbeats last answered a ping, or was first
+/// pinged, and the nonce of the ping it hasn't answered yet, if any.
+pub struct Heartbeat {
+    last_pong: Instant,
+    pending: Option<u64>,
+}
+
+/// `HeartbeatMap` is a type alias for a `DashMap` mapping the `SocketAddr` of a connection to its
+/// `Heartbeat`.
+pub type HeartbeatMap = Arc<DashMap<SocketAddr, Heartbeat>>;
+
+/// `Terminal` is a terminal a web client opened into a server of a daemon. Its packets are put back
+/// into order in both directions, and numbered again when they are forwarded, see
+/// `terminal::Reorder`.
+pub struct Terminal {
+    web: SocketAddr,
+    user_id: u32,
+    daemon: Uuid,
+    /// Output packets forwarded since credits were last granted to the daemon, if the server grants
+    /// them on behalf of a web client without `Feature::TerminalFlowControl`
+    forwarded: u32,
+    input: Reorder<WSTerminalInputPacket>,
+    output: Reorder<TerminalOutput>,
+    /// Sequence number of the next input packet sent to the daemon
+    input_seq: u64,
+    /// Sequence number of the next output or close packet sent to the web client
+    output_seq: u64,
+}
+
+impl Terminal {
+    fn new(web: SocketAddr, user_id: u32, daemon: Uuid) -> Self {
+        Self {
+            web,
+            user_id,
+            daemon,
+            forwarded: 0,
+            input: Reorder::default(),
+            output: Reorder::default(),
+            input_seq: 0,
+            output_seq: 0,
+        }
+    }
+
+    fn next_input_seq(&mut self) -> u64 {
+        self.input_seq += 1;
+        self.input_seq - 1
+    }
+
+    fn next_output_seq(&mut self) -> u64 {
+        self.output_seq += 1;
+        self.output_seq - 1
+    }
+}
+
+/// What a daemon sends for a terminal, in the order it has to reach the web client.
+enum TerminalOutput {
+    /// Base64 encoded bytes read from the TTY
+    Data(String),
+    Close(DSTerminalClosePacket),
+}
+
+/// A packet sent to a daemon with a request id, waiting for the daemon to acknowledge it, see
+/// `State::send_to_daemon_acked`.
+struct PendingAck {
+    request_id: u64,
+    rx: oneshot::Receiver<Result<(), String>>,
+}
+
+/// A sync sent to a daemon, which is finished once the daemon has applied it, see
+/// `State::finish_sync`.
+struct SentSync {
+    addr: SocketAddr,
+    ack: Option<PendingAck>,
+}
+
+/// `TerminalMap` is a type alias for a `DashMap` mapping the session id of a terminal to the
+/// `Terminal`, routing its input to the daemon and its output to the web client.
+pub type TerminalMap = Arc<DashMap<Uuid, Terminal>>;
+
+/// `LogListenMap` is a type alias for a `DashMap` mapping a `Uuid` of a daemon and the id of one of
+/// its servers to the `SocketAddr`s of the web clients following the logs of that server.
+pub type LogListenMap = Arc<DashMap<(Uuid, u32), HashSet<SocketAddr>>>;
+
+/// `StatusListenMap` is a type alias for a `DashMap` mapping the `SocketAddr` of a web client and
+/// the `Uuid` of a daemon to the ids of the servers whose `ServerStatus` events the web client
+/// listens to. Web clients without an entry listen to the status of all servers of the daemon.
+pub type StatusListenMap = Arc<DashMap<(SocketAddr, Uuid), HashSet<u32>>>;
+
+/// `GroupListenMap` is a type alias for a `DashMap` mapping a `SocketAddr` to a `HashMap` of
+/// `EventType` to a `HashSet` of daemon group ids. Basically, it maps a web client to the groups it
+/// listens to per event, so membership changes can be applied to its subscriptions.
+pub type GroupListenMap = Arc<DashMap<SocketAddr, HashMap<EventType, HashSet<u32>>>>;
+/// `GroupMemberCache` is a type alias for a `DashMap` mapping a daemon group id to the `Uuid`s of
+/// its members.
+pub type GroupMemberCache = Arc<DashMap<u32, HashSet<Uuid>>>;
+/// `TeamCache` is a type alias for a `DashMap` mapping a user id to the `Uuid`s of the daemons of
+/// its team, and when they were fetched.
+pub type TeamCache = Arc<DashMap<u32, (Instant, HashSet<Uuid>)>>;
+
+/// `SyncCache` is a type alias for a `DashMap` mapping a `Uuid` to the last full `SDSyncPacket` sent
+/// to that daemon, which the next sync is sent as a delta of.
+pub type SyncCache = Arc<DashMap<Uuid, SDSyncPacket>>;
+/// `SyncLockMap` is a type alias for a `DashMap` mapping a `Uuid` to a lock, which is held while a
+/// sync is sent to that daemon, so syncs of the same daemon never interleave.
+pub type SyncLockMap = Arc<DashMap<Uuid, Arc<Mutex<()>>>>;
+/// `SpecCache` is a type alias for a `DashMap` mapping a `Uuid` to the full spec of that daemon as
+/// assembled from the database, and when it was assembled.
+pub type SpecCache = Arc<DashMap<Uuid, (Instant, SDSyncPacket)>>;
+/// `Requesters` is a type alias for a `HashMap` mapping the `SocketAddr`s of the web clients
+/// waiting for a sync to the request id of the packet they requested it with, if any.
+pub type Requesters = HashMap<SocketAddr, Option<u64>>;
+/// `SyncRequestMap` is a type alias for a `DashMap` mapping a sync request id to the web clients
+/// waiting for the progress of that sync, and the `Uuid` of the daemon it was sent to.
+pub type SyncRequestMap = Arc<DashMap<u64, (Requesters, Uuid)>>;
+/// `FinishedSyncMap` is a type alias for a `DashMap` mapping the request id of a sync that finished
+/// to its web clients, the `Uuid` of its daemon, and when it finished. They are kept for
+/// `QUERY_TTL`, as the daemon's error can arrive after its final step.
+pub type FinishedSyncMap = Arc<DashMap<u64, (Requesters, Uuid, Instant)>>;
+/// `SyncOrphanMap` is a type alias for a `DashMap` mapping a sync request id to the users whose web
+/// clients requested the sync, but disconnected before it finished.
+pub type SyncOrphanMap = Arc<DashMap<u64, HashSet<u32>>>;
+
+/// `Critical` is a packet whose loss would leave the user in the dark, e.g. the result of a sync it
+/// requested, waiting to be acknowledged by one of the user's web clients.
+pub struct Critical {
+    request_id: u64,
+    packet: Packet,
+    queued_at: Instant,
+    /// Web client the packet was last sent to, which is expected to acknowledge it
+    sent_to: Option<SocketAddr>,
+}
+
+/// `OutboxMap` is a type alias for a `DashMap` mapping a user id to the `Critical` packets none of
+/// its web clients acknowledged yet, oldest first.
+pub type OutboxMap = Arc<DashMap<u32, VecDeque<Critical>>>;
+
+/// `SyncDebounce` is a struct containing when a daemon was last synced on request of a web client,
+/// and the web clients waiting for a deferred sync of that daemon.
+#[derive(Default)]
+pub struct SyncDebounce {
+    last: Option<Instant>,
+    waiting: Requesters,
+    scheduled: bool,
+}
+
+/// `SyncDebounceMap` is a type alias for a `DashMap` mapping a `Uuid` to the `SyncDebounce` of that
+/// daemon.
+pub type SyncDebounceMap = Arc<DashMap<Uuid, SyncDebounce>>;
+/// `SyncRateLimitMap` is a type alias for a `DashMap` mapping a `SocketAddr` to the times the web
+/// client requested a sync in the last minute.
+pub type SyncRateLimitMap = Arc<DashMap<SocketAddr, VecDeque<Instant>>>;
+/// `PacketRateLimitMap` is a type alias for a `DashMap` mapping a `SocketAddr` and a `PacketClass`
+/// to the token bucket of that web client for packets of that class.
+pub type PacketRateLimitMap = Arc<DashMap<(SocketAddr, PacketClass), Bucket>>;
+/// `StreamWindowMap` is a type alias for a `DashMap` mapping a web client, a daemon and a streamed
+/// `EventType` to the flow control window of the web client for those events.
+pub type StreamWindowMap = Arc<DashMap<(SocketAddr, Uuid, EventType), Window>>;
+/// `DaemonWindowMap` is a type alias for a `DashMap` mapping a daemon and a streamed `EventType` to
+/// the credits the daemon has left for those events, as far as the server knows.
+pub type DaemonWindowMap = Arc<DashMap<(Uuid, EventType), Window>>;
+/// `EventSequenceMap` is a type alias for a `DashMap` mapping a daemon, a snapshot `EventType` and
+/// (for `ServerStatus`) a server to the sequence number of the latest snapshot handled, see
+/// `DSEventPacket::seq`.
+pub type EventSequenceMap = Arc<DashMap<(Uuid, EventType, Option<u32>), u64>>;
+/// `EventReorderMap` is a type alias for a `DashMap` mapping the `SocketAddr` of a daemon to the
+/// events it sent that are held back until the events sent before them arrived. Snapshots are
+/// only held as placeholders, as they aren't held back.
+pub type EventReorderMap = Arc<DashMap<SocketAddr, Arc<Mutex<Reorder<Option<DSEventPacket>>>>>>;
+
+/// The window in which the sync requests of a web client are counted against its rate limit.
+const SYNC_RATE_LIMIT_WINDOW: Duration = Duration::from_secs(60);
+/// How long a daemon may take to answer a query before the web client stops waiting for it.
+const QUERY_TTL: Duration = Duration::from_secs(60);
+/// How long the daemons of a team are cached for checking access to server logs, see `TeamCache`.
+const TEAM_CACHE_TTL: Duration = Duration::from_secs(30);
+
+/// Removes empty event sets and maps from a listen map, returning the amount of removed entries.
+fn compact_listen_map<K: Eq + Hash, V>(map: &DashMap<K, HashMap<EventType, HashSet<V>>>) -> usize {
+    let mut removed = 0;
+
+    map.retain(|_, events| {
+        let before = events.len();
+        events.retain(|_, set| !set.is_empty());
+        removed += before - events.len();
+
+        if events.is_empty() {
+            removed += 1;
+        }
+
+        !events.is_empty()
+    });
+
+    removed
+}
+
+/// `DaemonStatus` is a struct containing the latest reported status of a daemon and its servers.
+#[derive(Default)]
+pub struct DaemonStatus {
+    node: Option<NodeStats>,
+    servers: HashMap<u32, ServerStatusType>,
+    server_counts: Option<ServerCounts>,
+    max_servers: Option<u32>,
+    in_maintenance: bool,
+    /// The latest node status and server status events with their sequence numbers and timestamps,
+    /// sent to web clients asking for a snapshot or starting to listen
+    node_event: Option<(NodeStatusEvent, Option<u64>, Option<u64>)>,
+    server_events: HashMap<u32, (ServerStatusEvent, Option<u64>, Option<u64>)>,
+}
+
+impl DaemonStatus {
+    /// Forgets the status of servers that were removed from the daemon.
+    fn retain_servers(&mut self, servers: &HashSet<u32>) {
+        self.servers.retain(|id, _| servers.contains(id));
+        self.server_events.retain(|id, _| servers.contains(id));
+    }
+}
+
+/// `StatusCache` is a type alias for a `DashMap` mapping a `Uuid` to the latest `DaemonStatus` of
+/// that daemon.
+pub type StatusCache = Arc<DashMap<Uuid, DaemonStatus>>;
 
 /// `State` is a struct containing all data that is required by `daemon` and `web` servers.
 pub struct State {
@@ -85,6 +342,39 @@ pub struct State {
     daemon_listen_map: DaemonListenMap,
     web_listen_map: WebListenMap,
     daemon_id_map: DaemonIDMap,
+    log_listens: LogListenMap,
+    status_listens: StatusListenMap,
+    terminals: TerminalMap,
+
+    pending_queries: PendingQueryMap,
+    pending_commands: PendingCommandMap,
+    pending_acks: PendingAckMap,
+    next_request: AtomicU64,
+    heartbeats: HeartbeatMap,
+
+    status_cache: StatusCache,
+
+    group_listen_map: GroupListenMap,
+    group_member_cache: GroupMemberCache,
+    team_cache: TeamCache,
+
+    sync_cache: SyncCache,
+    sync_locks: SyncLockMap,
+    spec_cache: SpecCache,
+    spec_generation: AtomicU64,
+    sync_requests: SyncRequestMap,
+    finished_syncs: FinishedSyncMap,
+    sync_orphans: SyncOrphanMap,
+    outbox: OutboxMap,
+    sync_debounce: SyncDebounceMap,
+    sync_rate_limits: SyncRateLimitMap,
+    packet_rate_limits: PacketRateLimitMap,
+    stream_windows: StreamWindowMap,
+    daemon_windows: DaemonWindowMap,
+    event_sequences: EventSequenceMap,
+    event_reorders: EventReorderMap,
+
+    fanout: OnceLock<Fanout>,
 }
 
 impl State {
@@ -98,779 +388,4008 @@ impl State {
             daemon_listen_map: Arc::new(DashMap::new()),
             web_listen_map: Arc::new(DashMap::new()),
             daemon_id_map: Arc::new(DashMap::new()),
+            log_listens: Arc::new(DashMap::new()),
+            status_listens: Arc::new(DashMap::new()),
+            terminals: Arc::new(DashMap::new()),
+            pending_queries: Arc::new(DashMap::new()),
+            pending_commands: Arc::new(DashMap::new()),
+            pending_acks: Arc::new(DashMap::new()),
+            next_request: AtomicU64::new(0),
+            heartbeats: Arc::new(DashMap::new()),
+            status_cache: Arc::new(DashMap::new()),
+            group_listen_map: Arc::new(DashMap::new()),
+            group_member_cache: Arc::new(DashMap::new()),
+            team_cache: Arc::new(DashMap::new()),
+            sync_cache: Arc::new(DashMap::new()),
+            sync_locks: Arc::new(DashMap::new()),
+            spec_cache: Arc::new(DashMap::new()),
+            spec_generation: AtomicU64::new(0),
+            sync_requests: Arc::new(DashMap::new()),
+            finished_syncs: Arc::new(DashMap::new()),
+            sync_orphans: Arc::new(DashMap::new()),
+            outbox: Arc::new(DashMap::new()),
+            sync_debounce: Arc::new(DashMap::new()),
+            sync_rate_limits: Arc::new(DashMap::new()),
+            packet_rate_limits: Arc::new(DashMap::new()),
+            stream_windows: Arc::new(DashMap::new()),
+            daemon_windows: Arc::new(DashMap::new()),
+            event_sequences: Arc::new(DashMap::new()),
+            event_reorders: Arc::new(DashMap::new()),
+            fanout: OnceLock::new(),
         }
     }
 
-    /// Sends an event from the server to the web clients listening.
-    pub async fn send_event_from_server(&self, uuid: &Uuid, event: EventData) -> Result<(), String> {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_LISTEN_MAP", file!(), line!());
-        let map: &DaemonListenMap = self.daemon_listen_map.borrow();
-
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_LISTEN_MAP", file!(), line!());
-        let daemon = map.get(uuid).ok_or("Daemon not found in DaemonListenMap")?;
-
-        let clients = daemon.get(&event.event_type());
-
-        if let Some(clients) = clients {
-            for client in clients.iter() {
-                #[cfg(feature = "lock_debug")]
-                debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
-                let map: &WebChannelMap = self.web_channel_map.borrow();
+    /// Starts the worker pool delivering events, see `Fanout`. Until it is started, events are
+    /// delivered by the task sending them.
+    pub fn start_fanout(self: &Arc<Self>) {
+        if self.fanout.set(Fanout::start(self)).is_err() {
+            warn!("Fan-out worker pool was already started");
+        }
+    }
 
-                #[cfg(feature = "lock_debug")]
-                debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
-                let socket = map.get(client).ok_or("Disconnected client still in WebChannelMap")?;
+    fn send_to_daemon(&self, addr: &SocketAddr, packet: Packet) -> Result<(), String> {
+        let socket = self.daemon_channel_map.get(addr).ok_or("Daemon not found in DaemonChannelMap")?;
+        let handshake = socket.handshake.as_ref().ok_or("Daemon hasn't requested authentication")?;
+        let encrypter = &handshake.encrypter;
 
-                socket.tx.unbounded_send(
-                    Message::Text(
-                        encryption::encrypt_packet(
-                            SWEventPacket {
-                                event: event.clone(),
-                                daemon: *uuid,
-                            }.to_packet()?,
-                            &socket.handshake.as_ref().ok_or("Client hasn't requested authentication")?.encrypter
-                        )?
-                    )
-                ).map_err(|_| "Could not send packet to client")?;
+        // large packets (e.g. syncs of big nodes) are split up to stay below message size limits
+        let packets = if handshake.features.has(Feature::Chunking) {
+            chunk::split(packet)?
+        } else {
+            vec![packet]
+        };
 
-                #[cfg(feature = "lock_debug")]
-                debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
-            }
+        for packet in packets {
+            socket.tx.unbounded_send(
+                Message::Text(
+                    encryption::encrypt_packet(packet, encrypter, socket.encoding)?
+                )
+            ).map_err(|_| "Failed to send packet")?;
         }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_LISTEN_MAP", file!(), line!());
-
         Ok(())
     }
 
-    /// Sends an event from the daemon to the server.
-    pub async fn send_event_from_daemon(&self, addr: &SocketAddr, event: EventData) -> Result<(), String> {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
-        let uuid = self.daemon_channel_map.get(addr).ok_or("Daemon not found in DaemonChannelMap")?.handshake.as_ref().ok_or("Client hasn't requested authentication")?.daemon_uuid;
+    fn send_to_web(&self, addr: &SocketAddr, packet: Packet) -> Result<(), String> {
+        let socket = self.web_channel_map.get(addr).ok_or("Client not found in WebChannelMap")?;
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
+        socket.tx.unbounded_send(
+            Message::Text(
+                encryption::encrypt_packet(
+                    packet,
+                    &socket.handshake.as_ref().ok_or("Client hasn't requested authentication")?.encrypter,
+                    socket.encoding
+                )?
+            )
+        ).map_err(|_| "Failed to send packet")?;
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+        Ok(())
+    }
 
-        self.send_event_from_server(&uuid, event).await
+    /// Registers a query from a web client to a daemon, and returns the request id that the
+    /// daemon will answer with.
+    fn register_query(&self, addr: SocketAddr, daemon: Uuid) -> u64 {
+        let request = self.next_request.fetch_add(1, Ordering::Relaxed);
+        self.pending_queries.insert(request, (addr, daemon, Instant::now()));
+        request
     }
 
-    /// Sends a handshake request to a daemon.
-    pub async fn send_daemon_handshake_request(&self, addr: SocketAddr, uuid: Uuid, key: Arc<Vec<u8>>) -> Result<(), String> {
-        let mut challenge_bytes = [0; 256];
-        rand_bytes(&mut challenge_bytes).map_err(|_| "Could not generate challenge")?;
+    /// Takes the web client waiting for the answer to a query, making sure the answer comes from
+    /// the daemon the query was sent to and in time.
+    fn take_query(&self, request: u64, daemon: Uuid) -> Result<SocketAddr, String> {
+        // answers for queries sent to other daemons leave them pending
+        let (_, (addr, _, sent)) = self.pending_queries.remove_if(&request, |_, (_, expected, _)| *expected == daemon).ok_or("Unknown request id, or query was not sent to this daemon")?;
 
-        let challenge = challenge_bytes.iter().try_fold::<_, _, Result<String, String>>(String::default(), |mut s, byte| {
-            write!(s, "{:02X}", byte).map_err(|_| "could not write byte")?;
-            Ok(s)
-        })?;
+        if sent.elapsed() >= QUERY_TTL {
+            return Err(format!("Daemon answered query {} after it expired", request));
+        }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
-        let clients: &DaemonChannelMap = self.daemon_channel_map.borrow();
+        Ok(addr)
+    }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
-        let mut client = clients.get_mut(&addr).ok_or("Client not found in channel_map")?;
+    /// Sends a packet to a daemon with a request id, returning the acknowledgement to wait for with
+    /// `wait_for_ack`. Daemons that don't support acknowledgements are only sent the packet.
+    fn send_to_daemon_acked(&self, addr: &SocketAddr, packet: Packet) -> Result<Option<PendingAck>, String> {
+        if !self.daemon_features(addr).has(Feature::Acks) {
+            return self.send_to_daemon(addr, packet).map(|_| None);
+        }
 
-        client.handshake = Some(DaemonHandshake {
-            daemon_uuid: uuid,
-            encrypter: josekit::jwe::RSA_OAEP.encrypter_from_pem(key.as_ref()).map_err(|_| "key should be valid")?,
-            challenge: challenge.clone(),
-        });
+        let request_id = self.next_request.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = oneshot::channel();
+        self.pending_acks.insert(request_id, (*addr, tx));
 
-        client.tx.unbounded_send(
-            Message::text(
-                encryption::encrypt_packet(
-                    SDHandshakeRequestPacket {
-                        challenge
-                    }.to_packet(),
-                    &client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.encrypter,
-                )?
-            )
-        ).map_err(|_| "Failed to send packet")?;
+        // the daemon logs the handling of the packet with the same request id
+        debug!("Sending {:?} with request id {}", packet.id, request_id);
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+        if let Err(e) = self.send_to_daemon(addr, packet.with_request_id(request_id)) {
+            self.pending_acks.remove(&request_id);
+            return Err(e);
+        }
 
-        Ok(())
+        Ok(Some(PendingAck {
+            request_id,
+            rx,
+        }))
     }
 
-    /// Authenticates a daemon with the given challenge.
-    pub fn authenticate_daemon(&self, addr: SocketAddr, challenge: String) -> Result<(), String> {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
-        let clients: &DaemonChannelMap = self.daemon_channel_map.borrow();
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
-        let client = clients.get(&addr).ok_or("Client not found in channel_map")?;
+    /// Waits until a daemon acknowledges a packet sent with `send_to_daemon_acked`, returning the
+    /// reason if the daemon could not handle it.
+    async fn wait_for_ack(&self, ack: Option<PendingAck>, timeout: Duration) -> Result<(), String> {
+        let Some(ack) = ack else {
+            return Ok(());
+        };
 
-        if challenge != client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.challenge {
-            warn!("Failed authentication");
-            client.tx.close_channel();
-            return Err("Challenge does not match".to_string());
+        match tokio::time::timeout(timeout, ack.rx).await {
+            Ok(Ok(res)) => res.map_err(|e| format!("Daemon could not handle packet: {}", e)),
+            Ok(Err(_)) => Err("Daemon disconnected before acknowledging packet".to_string()),
+            Err(_) => {
+                self.pending_acks.remove(&ack.request_id);
+                Err(format!("Daemon did not acknowledge packet within {}s", timeout.as_secs()))
+            },
         }
+    }
 
-        let uuid = client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.daemon_uuid;
-        let encrypter = &client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.encrypter;
+    /// Passes the acknowledgement of a daemon on to the packet waiting for it, see
+    /// `send_to_daemon_acked`.
+    pub fn receive_ack(&self, addr: &SocketAddr, request_id: u64, res: Result<(), String>) -> Result<(), String> {
+        let (_, (_, tx)) = self.pending_acks.remove_if(&request_id, |_, (daemon, _)| daemon == addr).ok_or("Unknown request id")?;
 
-        client.tx.unbounded_send(
-            Message::text(
-                encryption::encrypt_packet(
-                    SDAuthResponsePacket {
-                        success: true,
-                    }.to_packet()?,
-                    encrypter,
-                )?
-            )
-        ).map_err(|_| "Failed to send packet")?;
+        // the packet might have stopped waiting for the acknowledgement just now
+        let _ = tx.send(res);
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_LISTEN_MAP", file!(), line!());
-        let daemon_listen_map: &DaemonListenMap = self.daemon_listen_map.borrow();
+        Ok(())
+    }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_LISTEN_MAP", file!(), line!());
-        if let Some(listen_map) = daemon_listen_map.get(&uuid) {
-            let events = listen_map.keys().copied().collect::<Vec<_>>();
+    /// Answers a packet of a daemon that was sent with a request id, see `ack`.
+    pub fn ack_daemon(&self, addr: &SocketAddr, request_id: u64, res: &Result<(), String>) -> Result<(), String> {
+        self.send_to_daemon(addr, ack::reply(request_id, res)?)
+    }
 
-            client.tx.unbounded_send(
-                Message::Text(
-                    encryption::encrypt_packet(
-                        SDListenPacket {
-                            events
-                        }.to_packet()?,
-                        encrypter
-                    )?
-                )
-            ).map_err(|_| "Failed to send packet")?;
+    /// Answers a packet of a web client that was sent with a request id, see `ack`.
+    pub fn ack_web(&self, addr: &SocketAddr, request_id: u64, res: &Result<(), String>) -> Result<(), String> {
+        self.send_to_web(addr, ack::reply(request_id, res)?)
+    }
+
+    /// Queues a critical packet for a user until one of its web clients acknowledges it, returning
+    /// the request id to send it with. Once `outbox.capacity` is reached, the oldest packet is
+    /// dropped.
+    fn queue_critical(&self, user_id: u32, packet: &Packet, sent_to: Option<SocketAddr>) -> u64 {
+        let request_id = self.next_request.fetch_add(1, Ordering::Relaxed);
+        let mut outbox = self.outbox.entry(user_id).or_default();
+
+        if outbox.len() >= CONFIG.outbox.capacity
+            && let Some(dropped) = outbox.pop_front() {
+            warn!("Dropping unacknowledged {:?}Packet of user {}, as its outbox is full", dropped.packet.id, user_id);
         }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_ID_MAP", file!(), line!());
-        self.daemon_id_map.insert(uuid, addr);
+        outbox.push_back(Critical {
+            request_id,
+            packet: packet.clone(),
+            queued_at: Instant::now(),
+            sent_to,
+        });
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_ID_MAP", file!(), line!());
+        request_id
+    }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_ID_MAP", file!(), line!());
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_LISTEN_MAP", file!(), line!());
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+    /// Sends a critical packet, e.g. the result of a command, to a web client. Clients that
+    /// negotiated `Feature::Acks` have to acknowledge it, otherwise it is redelivered to the next
+    /// web client of the user that authenticates, e.g. after the page was reloaded.
+    fn send_critical(&self, addr: &SocketAddr, packet: Packet) -> Result<(), String> {
+        let user_id = match self.web_channel_map.get(addr) {
+            Some(socket) if socket.has_feature(Feature::Acks) => socket.handshake.as_ref().map(|handshake| handshake.user_id),
+            _ => None,
+        };
 
-        Ok(())
+        let Some(user_id) = user_id else {
+            return self.send_to_web(addr, packet);
+        };
+
+        let request_id = self.queue_critical(user_id, &packet, Some(*addr));
+        self.send_to_web(addr, packet.with_request_id(request_id))
     }
 
-    /// Sends initial data to a daemon.
-    pub async fn send_init_data(&self, addr: SocketAddr) -> Result<(), String> {
-        let uuid = self.daemon_channel_map.get(&addr).ok_or("Client not found in channel_map")?.handshake.as_ref().ok_or("Client hasn't requested authentication")?.daemon_uuid;
-        
-        self.sync_daemon(uuid, Some(addr)).await
+    /// Returns a web client of a user that negotiated `Feature::Acks`, if any is connected.
+    fn acking_client(&self, user_id: u32) -> Option<SocketAddr> {
+        self.web_channel_map.iter()
+            .find(|socket| socket.has_feature(Feature::Acks) && socket.handshake.as_ref().is_some_and(|handshake| handshake.user_id == user_id))
+            .map(|socket| *socket.key())
     }
 
-    // Sends data to a daemon for synchronization with the database.
-    pub async fn sync_daemon(&self, uuid: Uuid, addr: Option<SocketAddr>) -> Result<(), String> {
-        let addr = addr.or_else(|| self.daemon_id_map.get(&uuid).map(|a| *a));
+    /// Sends a critical packet to one of the web clients of a user that negotiated `Feature::Acks`,
+    /// for packets whose web client disconnected in the meantime. If none are connected, the packet
+    /// is delivered once the user reconnects.
+    fn send_critical_to_user(&self, user_id: u32, packet: Packet) -> Result<(), String> {
+        let client = self.acking_client(user_id);
+        let request_id = self.queue_critical(user_id, &packet, client);
 
-        if addr.is_none() {
-            return Ok(());
+        if let Some(client) = client
+            && let Err(e) = self.send_to_web(&client, packet.with_request_id(request_id)) {
+            warn!("Could not send critical packet to {}: {}", client, e);
         }
 
-        let addr = addr.expect("addr should always exist");
+        Ok(())
+    }
 
-        struct DbNetwork {
-            network_id: i32,
-            network_local_ip: i32,
-        }
-
-        let networks = sqlx::query_as!(DbNetwork, r#"
-            SELECT
-                networks.network_id,
-                networks.network_local_ip
-            FROM aesterisk.nodes
-            LEFT JOIN aesterisk.node_networks
-                ON nodes.node_id = node_networks.node_id
-            LEFT JOIN aesterisk.networks
-                ON node_networks.network_id = networks.network_id
-            WHERE nodes.node_uuid = $1
-            AND networks.network_id IS NOT NULL;
-        "#, uuid).fetch_all(db::get()?).await.map_err(|_| "failed to fetch network data")?;
-
-        #[derive(sqlx::FromRow)]
-        struct DbServer {
-            server_id: i32,
-            tag_image: String,
-            tag_docker_tags: String,
-            tag_healthcheck_test: Vec<String>,
-            tag_healthcheck_interval: i32,
-            tag_healthcheck_timeout: i32,
-            tag_healthcheck_retries: i32,
-            mount_container_path: Option<Vec<String>>,
-            mount_host_path: Option<Vec<String>>,
-            env_def_key: Option<Vec<String>>,
-            env_def_required: Option<Vec<bool>>,
-            env_def_type: Option<Vec<i16>>,
-            env_def_default_value: Option<Vec<Option<String>>>,
-            env_def_regex: Option<Vec<Option<String>>>,
-            env_def_min: Option<Vec<Option<i32>>>,
-            env_def_max: Option<Vec<Option<i32>>>,
-            env_def_trim: Option<Vec<bool>>,
-            env_key: Option<Vec<String>>,
-            env_value: Option<Vec<String>>,
-            network_id: Option<Vec<i32>>,
-            network_local_ip: Option<Vec<i16>>,
-            port_port: Option<Vec<i32>>,
-            port_protocol: Option<Vec<i16>>,
-            port_mapped: Option<Vec<i32>>,
-        }
-
-        let servers = sqlx::query_as!(DbServer, r#"
-            WITH mounts_cte AS (
-                SELECT
-                    tag_mounts.tag_id,
-                    ARRAY_AGG(mounts.mount_container_path ORDER BY mounts.mount_id) AS mount_container_path,
-                    ARRAY_AGG(mounts.mount_host_path ORDER BY mounts.mount_id) AS mount_host_path
-                FROM aesterisk.mounts
-                JOIN aesterisk.tag_mounts ON mounts.mount_id = tag_mounts.mount_id
-                GROUP BY tag_mounts.tag_id
-            ),
-            env_defs_cte AS (
-                SELECT
-                    tag_env_defs.tag_id,
-                    ARRAY_AGG(env_defs.env_def_key ORDER BY env_defs.env_def_id) AS env_def_key,
-                    ARRAY_AGG(env_defs.env_def_required ORDER BY env_defs.env_def_id) AS env_def_required,
-                    ARRAY_AGG(env_defs.env_def_type ORDER BY env_defs.env_def_id) AS env_def_type,
-                    ARRAY_AGG(env_defs.env_def_default_value ORDER BY env_defs.env_def_id) AS env_def_default_value,
-                    ARRAY_AGG(env_defs.env_def_regex ORDER BY env_defs.env_def_id) AS env_def_regex,
-                    ARRAY_AGG(env_defs.env_def_min ORDER BY env_defs.env_def_id) AS env_def_min,
-                    ARRAY_AGG(env_defs.env_def_max ORDER BY env_defs.env_def_id) AS env_def_max,
-                    ARRAY_AGG(env_defs.env_def_trim ORDER BY env_defs.env_def_id) AS env_def_trim
-                FROM aesterisk.env_defs
-                JOIN aesterisk.tag_env_defs ON env_defs.env_def_id = tag_env_defs.env_def_id
-                GROUP BY tag_env_defs.tag_id
-            ),
-            envs_cte AS (
-                SELECT
-                    server_envs.server_id,
-                    ARRAY_AGG(envs.env_key ORDER BY envs.env_id) AS env_key,
-                    ARRAY_AGG(envs.env_value ORDER BY envs.env_id) AS env_value
-                FROM aesterisk.envs
-                JOIN aesterisk.server_envs ON envs.env_id = server_envs.env_id
-                GROUP BY server_envs.server_id
-            ),
-            networks_cte AS (
-                SELECT
-                    server_networks.server_id,
-                    ARRAY_AGG(server_networks.network_id ORDER BY server_networks.network_id) AS network_id,
-                    ARRAY_AGG(server_networks.local_ip ORDER BY server_networks.network_id) AS network_local_ip
-                FROM aesterisk.server_networks
-                GROUP BY server_networks.server_id
-            ),
-            ports_cte AS (
-                SELECT
-                    server_ports.server_id,
-                    ARRAY_AGG(ports.port_port ORDER BY ports.port_id) AS port_port,
-                    ARRAY_AGG(ports.port_protocol ORDER BY ports.port_id) AS port_protocol,
-                    ARRAY_AGG(ports.port_mapped ORDER BY ports.port_id) AS port_mapped
-                FROM aesterisk.ports
-                JOIN aesterisk.server_ports ON ports.port_id = server_ports.port_id
-                GROUP BY server_ports.server_id
-            )
-            SELECT
-                servers.server_id,
-                tags.tag_image,
-                tags.tag_docker_tags,
-                tags.tag_healthcheck_test,
-                tags.tag_healthcheck_interval,
-                tags.tag_healthcheck_timeout,
-                tags.tag_healthcheck_retries,
-                mounts_cte.mount_container_path,
-                mounts_cte.mount_host_path,
-                env_defs_cte.env_def_key,
-                env_defs_cte.env_def_required,
-                env_defs_cte.env_def_type,
-                env_defs_cte.env_def_default_value AS "env_def_default_value: _",
-                env_defs_cte.env_def_regex AS "env_def_regex: _",
-                env_defs_cte.env_def_min AS "env_def_min: _",
-                env_defs_cte.env_def_max AS "env_def_max: _",
-                env_defs_cte.env_def_trim,
-                envs_cte.env_key,
-                envs_cte.env_value,
-                networks_cte.network_id,
-                networks_cte.network_local_ip,
-                ports_cte.port_port,
-                ports_cte.port_protocol,
-                ports_cte.port_mapped
-            FROM aesterisk.nodes
-            LEFT JOIN aesterisk.node_servers ON nodes.node_id = node_servers.node_id
-            LEFT JOIN aesterisk.servers ON node_servers.server_id = servers.server_id
-            LEFT JOIN aesterisk.tags ON servers.server_tag = tags.tag_id
-            LEFT JOIN mounts_cte ON servers.server_tag = mounts_cte.tag_id
-            LEFT JOIN env_defs_cte ON servers.server_tag = env_defs_cte.tag_id
-            LEFT JOIN envs_cte ON servers.server_id = envs_cte.server_id
-            LEFT JOIN networks_cte ON servers.server_id = networks_cte.server_id
-            LEFT JOIN ports_cte ON servers.server_id = ports_cte.server_id
-            WHERE nodes.node_uuid = $1;
-        "#, uuid).fetch_all(db::get()?).await.map_err(|e| format!("Failed to fetch server data: {}", e))?;
-
-        let servers = servers.into_iter().map(|s| Server {
-            id: s.server_id as u32,
-            tag: Tag {
-                image: s.tag_image,
-                docker_tag: s.tag_docker_tags,
-                healthcheck: Healthcheck {
-                    test: s.tag_healthcheck_test,
-                    interval: s.tag_healthcheck_interval as u64,
-                    timeout: s.tag_healthcheck_timeout as u64,
-                    retries: s.tag_healthcheck_retries as u64,
-                },
-                mounts: s.mount_container_path.unwrap_or_default().into_iter().zip(s.mount_host_path.unwrap_or_default()).map(|(container_path, host_path)| Mount {
-                    container_path,
-                    host_path,
-                }).collect(),
-                env_defs: s.env_def_key.unwrap_or_default().into_iter()
-                    .zip(s.env_def_required.unwrap_or_default())
-                    .zip(s.env_def_type.unwrap_or_default())
-                    .zip(s.env_def_default_value.unwrap_or_default())
-                    .zip(s.env_def_regex.unwrap_or_default())
-                    .zip(s.env_def_min.unwrap_or_default())
-                    .zip(s.env_def_max.unwrap_or_default())
-                    .zip(s.env_def_trim.unwrap_or_default())
-                    .map(|(((((((key, required), env_type), default), regex), min), max), trim)| EnvDef {
-                        key,
-                        required,
-                        env_type: EnvType::from(env_type as u8),
-                        default,
-                        regex,
-                        min: min.map(|min| min as i64),
-                        max: max.map(|max| max as i64),
-                        trim,
-                    })
-                    .collect(),
-            },
-            envs: s.env_key.unwrap_or_default().into_iter().zip(s.env_value.unwrap_or_default()).map(|(key, value)| Env {
-                key,
-                value,
-            }).collect(),
-            networks: s.network_id.unwrap_or_default().into_iter().zip(s.network_local_ip.unwrap_or_default()).map(|(network, ip)| ServerNetwork {
-                network: network as u32,
-                ip: ip as u8,
-            }).collect(),
-            ports: s.port_port.unwrap_or_default().into_iter().zip(s.port_mapped.unwrap_or_default()).zip(s.port_protocol.unwrap_or_default()).map(|((port, mapped), protocol)| Port {
-                port: port as u16,
-                mapped: mapped as u16,
-                protocol: Protocol::from(protocol as u8),
-            }).collect(),
-        }).collect();
-
-        let sync = SDSyncPacket {
-            networks: networks.into_iter().map(|nw| Network {
-                id: nw.network_id as u32,
-                subnet: nw.network_local_ip as u8,
-            }).collect(),
-            servers,
+    /// Redelivers the critical packets of a user that none of its web clients acknowledged yet to
+    /// a web client, e.g. one that just authenticated. Packets are only redelivered if the web
+    /// client they were sent to has disconnected, so every packet is handled by a single client.
+    fn redeliver_critical(&self, addr: &SocketAddr, user_id: u32) -> Result<(), String> {
+        let ttl = Duration::from_secs(CONFIG.outbox.ttl);
+
+        let packets = match self.outbox.get_mut(&user_id) {
+            Some(mut outbox) => outbox.iter_mut()
+                .filter(|critical| critical.queued_at.elapsed() < ttl)
+                .filter(|critical| critical.sent_to.is_none_or(|sent_to| !self.web_channel_map.contains_key(&sent_to)))
+                .map(|critical| {
+                    critical.sent_to = Some(*addr);
+                    critical.packet.clone().with_request_id(critical.request_id)
+                })
+                .collect::<Vec<_>>(),
+            None => return Ok(()),
         };
 
-        let client = self.daemon_channel_map.get(&addr).ok_or("Client not found in channel_map")?;
-        let encrypter = &client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.encrypter;
-        client.tx.unbounded_send(Message::Text(encryption::encrypt_packet(sync.to_packet()?, encrypter)?)).map_err(|e| format!("Couldn't send packet: {}", e))?;
+        if !packets.is_empty() {
+            info!("Redelivering {} unacknowledged packets", packets.len());
+        }
+
+        for packet in packets {
+            self.send_to_web(addr, packet)?;
+        }
 
         Ok(())
     }
 
-    /// Adds a daemon to the server.
-    pub fn add_daemon(&self, addr: SocketAddr, tx: Tx) {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
-        self.daemon_channel_map.insert(addr, DaemonSocket {
-            tx,
-            handshake: None,
-        });
+    /// Removes a critical packet acknowledged by a web client from the outbox of its user. Web
+    /// clients acknowledge every packet sent with a request id, so acknowledgements of packets that
+    /// aren't in the outbox are ignored.
+    pub fn receive_web_ack(&self, addr: &SocketAddr, request_id: u64, res: Result<(), String>) -> Result<(), String> {
+        let user_id = self.web_user(addr)?;
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
-    }
+        // the client received the packet, so it isn't redelivered even if it couldn't handle it
+        if let Err(e) = res {
+            warn!("Web client could not handle packet {}: {}", request_id, e);
+        }
 
-    /// Removes a daemon from the server. Should only be used in the `on_disconnect` method, see
-    /// `disconnect_daemon` for a more general use case.
-    pub async fn remove_daemon(&self, addr: SocketAddr) -> Result<(), String> {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
-        let uuid = self.daemon_channel_map.get(&addr).ok_or("Daemon not found in DaemonChannelMap")?.handshake.as_ref().ok_or("Daemon hasn't authenticated")?.daemon_uuid;
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+        {
+            let Some(mut outbox) = self.outbox.get_mut(&user_id) else {
+                return Ok(());
+            };
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
-        self.daemon_channel_map.remove(&addr);
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+            if let Some(position) = outbox.iter().position(|critical| critical.request_id == request_id) {
+                outbox.remove(position);
+            }
+        }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_ID_MAP", file!(), line!());
-        self.daemon_id_map.remove(&uuid);
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_ID_MAP", file!(), line!());
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_ID_MAP", file!(), line!());
+        self.outbox.remove_if(&user_id, |_, outbox| outbox.is_empty());
 
-        self.send_event_from_server(&uuid, EventData::NodeStatus(NodeStatusEvent {
-            online: false,
-            stats: None,
-        })).await
+        Ok(())
     }
 
-    /// Disconnects a daemon from the server.
-    pub fn disconnect_daemon(&self, addr: SocketAddr) -> Result<(), String> {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
-        self.daemon_channel_map.get(&addr).ok_or("Client not found in channel_map")?.tx.close_channel();
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+    /// Returns a ping for a connection, or `None` if it hasn't answered its last ping yet, which
+    /// leaves the connection to be reaped if it never does.
+    fn ping(&self, addr: &SocketAddr) -> Result<Option<Packet>, String> {
+        let mut heartbeat = self.heartbeats.entry(*addr).or_insert_with(|| Heartbeat {
+            last_pong: Instant::now(),
+            pending: None,
+        });
 
-        Ok(())
+        if heartbeat.pending.is_some() {
+            return Ok(None);
+        }
+
+        let nonce = self.next_request.fetch_add(1, Ordering::Relaxed);
+        heartbeat.pending = Some(nonce);
+
+        PingPacket {
+            nonce,
+        }.to_packet().map(Some)
     }
 
-    /// Called when a daemon connects to the server to immediately send it all events that has been
-    /// listened to.
-    pub async fn update_listens_for_daemon(&self, addr: &SocketAddr, uuid: &Uuid) -> Result<(), String> {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
-        let daemon_channel_map: &DaemonChannelMap = self.daemon_channel_map.borrow();
+    /// Pings a daemon, if it has authenticated and negotiated `Feature::Heartbeats`, see
+    /// `reap_stale`. Pongs of daemons that haven't authenticated are rejected.
+    pub fn ping_daemon(&self, addr: &SocketAddr) -> Result<(), String> {
+        if self.authenticated_daemon_uuid(addr).is_none() || !self.daemon_features(addr).has(Feature::Heartbeats) {
+            return Ok(());
+        }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
-        let socket = daemon_channel_map.get(addr).ok_or("Daemon not found in DaemonChannelMap")?;
+        let Some(ping) = self.ping(addr)? else {
+            return Ok(());
+        };
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_LISTEN_MAP", file!(), line!());
-        let daemon_listen_map: &DaemonListenMap = self.daemon_listen_map.borrow();
+        // pings fail once the connection is closed, which then has nothing left to reap
+        self.send_to_daemon(addr, ping).inspect_err(|_| {
+            self.heartbeats.remove(addr);
+        })
+    }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_LISTEN_MAP", file!(), line!());
-        let events = daemon_listen_map.get(uuid).ok_or("Daemon not found in DaemonListenMap")?.keys().copied().collect::<Vec<_>>();
+    /// Pings a web client, if it negotiated `Feature::Heartbeats`, see `reap_stale`.
+    pub fn ping_web(&self, addr: &SocketAddr) -> Result<(), String> {
+        if !self.web_channel_map.get(addr).is_some_and(|socket| socket.has_feature(Feature::Heartbeats)) {
+            return Ok(());
+        }
 
-        socket.tx.unbounded_send(
-            Message::Text(
-                encryption::encrypt_packet(
-                    SDListenPacket {
-                        events
-                    }.to_packet()?,
-                    &socket.handshake.as_ref().ok_or("Daemon hasn't requested authentication!")?.encrypter
-                )?
-            )
-        ).map_err(|_| "Failed to send packet")?;
+        let Some(ping) = self.ping(addr)? else {
+            return Ok(());
+        };
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_LISTEN_MAP", file!(), line!());
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+        // pings fail once the connection is closed, which then has nothing left to reap
+        self.send_to_web(addr, ping).inspect_err(|_| {
+            self.heartbeats.remove(addr);
+        })
+    }
+
+    /// Records that a connection answered its outstanding ping.
+    pub fn receive_pong(&self, addr: &SocketAddr, pong: PongPacket) -> Result<(), String> {
+        let mut heartbeat = self.heartbeats.get_mut(addr)
+            .filter(|heartbeat| heartbeat.pending == Some(pong.nonce))
+            .ok_or_else(|| format!("Unexpected pong {}", pong.nonce))?;
+
+        heartbeat.last_pong = Instant::now();
+        heartbeat.pending = None;
 
         Ok(())
     }
 
-    /// Sends a handshake request to a web client.
-    pub fn send_web_handshake_request(&self, addr: &SocketAddr, user_id: u32, key: Arc<Vec<u8>>) -> Result<(), String> {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
-        let clients: &WebChannelMap = self.web_channel_map.borrow();
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
-        let mut client = clients.get_mut(addr).ok_or("Client not found in channel_map")?;
+    /// Disconnects all connections that haven't answered a ping for `timeout`. Daemons are removed
+    /// once their connection has ended, which tells the web clients listening that they went
+    /// offline. Returns the amount of disconnected connections.
+    pub fn reap_stale(&self, timeout: Duration) -> usize {
+        let stale = self.heartbeats.iter().filter(|heartbeat| heartbeat.last_pong.elapsed() >= timeout).map(|heartbeat| *heartbeat.key()).collect::<Vec<_>>();
 
-        let mut challenge_bytes = [0; 256];
-        rand_bytes(&mut challenge_bytes).map_err(|_| "Could not generate challenge")?;
-        let challenge = challenge_bytes.iter().try_fold::<_, _, Result<String, String>>(String::default(), |mut s, byte| {
-            write!(s, "{:02X}", byte).map_err(|_| "could not write byte")?;
-            Ok(s)
-        })?;
+        for addr in stale.iter() {
+            // pings fail once the connection is closed, so it isn't reaped again while it's closing
+            self.heartbeats.remove(addr);
 
-        client.handshake = Some(WebHandshake {
-            user_id,
-            encrypter: josekit::jwe::RSA_OAEP.encrypter_from_pem(key.as_ref()).map_err(|_| "key should be valid")?,
-            challenge: challenge.clone(),
-        });
+            let res = if self.daemon_channel_map.contains_key(addr) {
+                self.disconnect_daemon(*addr, CloseReason::HeartbeatTimeout)
+            } else {
+                self.disconnect_web(*addr, CloseReason::HeartbeatTimeout)
+            };
 
-        client.tx.unbounded_send(
-            Message::text(
-                encryption::encrypt_packet(
-                    SWHandshakeRequestPacket {
-                        challenge
-                    }.to_packet()?,
-                    &client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.encrypter,
-                )?
-            )
-        ).map_err(|_| "Failed to send packet")?;
+            if let Err(e) = res {
+                warn!("Could not disconnect stale connection {}: {}", addr, e);
+            }
+        }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
+        stale.len()
+    }
 
-        Ok(())
+    /// Forwards a logs query from a web client to the daemon.
+    pub async fn query_logs(&self, addr: SocketAddr, query: WSQueryLogsPacket) -> Result<(), String> {
+        let user_id = self.web_user(&addr)?;
+
+        if !self.team_daemons(user_id).await?.contains(&query.daemon) {
+            return Err(format!("Node {} does not belong to your team", query.daemon));
+        }
+
+        let daemon_addr = *self.daemon_id_map.get(&query.daemon).ok_or("Daemon is not connected")?;
+        let request = self.register_query(addr, query.daemon);
+
+        self.send_to_daemon(&daemon_addr, SDQueryLogsPacket {
+            request,
+            server: query.server,
+            lines: query.lines,
+        }.to_packet()?)
     }
 
-    /// Authenticates a web client with the given challenge.
-    pub fn authenticate_web(&self, addr: SocketAddr, challenge: String) -> Result<(), String> {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
-        let clients: &WebChannelMap = self.web_channel_map.borrow();
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
-        let client = clients.get_mut(&addr).ok_or("Client not found in channel_map")?;
+    /// Sends the answer to a logs query from a daemon to the web client that requested it.
+    pub fn send_logs_response(&self, addr: &SocketAddr, response: DSQueryLogsResponsePacket) -> Result<(), String> {
+        let uuid = self.daemon_uuid(addr)?;
+        let web_addr = self.take_query(response.request, uuid)?;
 
-        if challenge != client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.challenge {
-            warn!("Failed authentication");
-            client.tx.close_channel();
-            return Err("Challenge does not match".to_string());
+        self.send_to_web(&web_addr, SWQueryLogsResponsePacket {
+            daemon: uuid,
+            server: response.server,
+            lines: response.lines,
+        }.to_packet()?)
+    }
+
+    /// Forwards a command from a web client to the daemon, if it belongs to the team of the user.
+    /// If it can't be forwarded, the web client is sent an `SWCommandResultPacket` with the reason.
+    pub async fn send_command(&self, addr: SocketAddr, command: WSCommandPacket) -> Result<(), String> {
+        // anyone can claim a user id in the auth packet, only answering the challenge proves it
+        let user_id = self.authenticated_web_user(&addr).ok_or("Web client hasn't authenticated")?;
+
+        let res = async {
+            if !self.team_daemons(user_id).await?.contains(&command.daemon) {
+                return Err(format!("Node {} does not belong to your team", command.daemon));
+            }
+
+            plugins::command(user_id, &command).await?;
+
+            let daemon_addr = *self.daemon_id_map.get(&command.daemon).ok_or("Daemon is not connected")?;
+            let request = self.next_request.fetch_add(1, Ordering::Relaxed);
+            self.pending_commands.insert(request, (addr, user_id, command.daemon, Instant::now()));
+
+            self.send_to_daemon(&daemon_addr, SDCommandPacket {
+                request,
+                server: command.server,
+                command: command.command,
+            }.to_packet()?).inspect_err(|_| {
+                self.pending_commands.remove(&request);
+            })
+        }.await;
+
+        match res {
+            Ok(()) => Ok(()),
+            Err(e) => self.send_to_web(&addr, SWCommandResultPacket {
+                daemon: command.daemon,
+                server: command.server,
+                command: command.command,
+                error: Some(e),
+            }.to_packet()?),
         }
+    }
 
-        client.tx.unbounded_send(
-            Message::text(
-                encryption::encrypt_packet(
-                    SWAuthResponsePacket {
-                        success: true,
-                    }.to_packet()?,
-                    &client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.encrypter,
-                )?
-            )
-        ).map_err(|_| "Failed to send packet")?;
+    /// Sends the result of a command from a daemon to the web client that ran it, or another web
+    /// client of its user if it disconnected, and stores it in the inbox of the user. The result is
+    /// critical, as the web client can't tell what state the server is in otherwise.
+    pub fn send_command_result(&self, addr: &SocketAddr, result: DSCommandResultPacket) -> Result<(), String> {
+        let uuid = self.daemon_uuid(addr)?;
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
+        // commands of automation rules have no web client waiting for their result
+        if automation::command_result(result.request, uuid, result.server, result.error.as_deref()) {
+            return Ok(());
+        }
+
+        let (_, (web_addr, user_id, _, sent)) = self.pending_commands.remove_if(&result.request, |_, (_, _, daemon, _)| *daemon == uuid).ok_or("Unknown request id")?;
+
+        if sent.elapsed() >= QUERY_TTL {
+            return Err(format!("Daemon answered command {} after it expired", result.request));
+        }
+
+        let message = match result.error.as_ref() {
+            Some(e) => format!("{:?} of server {} failed: {}", result.command, result.server, e),
+            None => format!("{:?} of server {} succeeded", result.command, result.server),
+        };
+
+        inbox::notify(Recipients::User(user_id), NotificationKind::CommandResult, Some(result.server), message);
+
+        let packet = SWCommandResultPacket {
+            daemon: uuid,
+            server: result.server,
+            command: result.command,
+            error: result.error,
+        }.to_packet()?;
+
+        match self.web_channel_map.contains_key(&web_addr) {
+            true => self.send_critical(&web_addr, packet),
+            false => self.send_critical_to_user(user_id, packet),
+        }
+    }
+
+    /// Opens a terminal of a web client into a server of a daemon of its team. If it can't be
+    /// opened, the web client is sent an `SWTerminalClosePacket` with the reason.
+    pub async fn open_terminal(&self, addr: SocketAddr, open: WSTerminalOpenPacket) -> Result<(), String> {
+        let res = async {
+            if !CONFIG.terminals.enabled {
+                return Err("Terminals are disabled".to_string());
+            }
+
+            // a shell in a container must not be opened before the client answered the challenge
+            let user_id = self.authenticated_web_user(&addr).ok_or("Web client hasn't authenticated")?;
+
+            if !repository::get().team_daemons(user_id).await?.contains(&open.daemon) {
+                return Err(format!("Node {} does not belong to your team", open.daemon));
+            }
+
+            let daemon_addr = *self.daemon_id_map.get(&open.daemon).ok_or("Daemon is not connected")?;
+
+            if !self.daemon_features(&daemon_addr).has(Feature::Terminals) {
+                return Err(format!("Daemon {} does not support terminals", open.daemon));
+            }
+
+            let max = CONFIG.terminals.max_per_user;
+            if max > 0 && self.terminals.iter().filter(|terminal| terminal.user_id == user_id).count() >= max {
+                return Err(format!("Opening another terminal would exceed the limit of {} per user", max));
+            }
+
+            if self.terminals.contains_key(&open.session) {
+                return Err(format!("Terminal session {} already exists", open.session));
+            }
+
+            self.terminals.insert(open.session, Terminal::new(addr, user_id, open.daemon));
+
+            self.send_to_daemon(&daemon_addr, SDTerminalOpenPacket {
+                session: open.session,
+                server: open.server,
+                size: open.size,
+                command: open.command,
+            }.to_packet()?).inspect_err(|_| {
+                self.terminals.remove(&open.session);
+            })
+        }.await;
+
+        match res {
+            Ok(()) => Ok(()),
+            Err(e) => self.send_to_web(&addr, SWTerminalClosePacket {
+                session: open.session,
+                exit_code: None,
+                error: Some(e),
+                seq: None,
+            }.to_packet()?),
+        }
+    }
+
+    /// Forwards input for a terminal from the web client that opened it to its daemon, in the order
+    /// the web client sent it.
+    pub fn send_terminal_input(&self, addr: SocketAddr, input: WSTerminalInputPacket) -> Result<(), String> {
+        self.authenticated_web_user(&addr).ok_or("Web client hasn't authenticated")?;
+
+        let session = input.session;
+
+        // held while forwarding, so the input is sent in the order it is numbered
+        let mut terminal = self.terminals.get_mut(&session).filter(|terminal| terminal.web == addr).ok_or("Unknown terminal session")?;
+        let daemon_addr = *self.daemon_id_map.get(&terminal.daemon).ok_or("Daemon is not connected")?;
+
+        for input in terminal.input.push(input.seq, input)? {
+            let seq = terminal.next_input_seq();
+
+            self.send_to_daemon(&daemon_addr, SDTerminalInputPacket {
+                session,
+                data: input.data,
+                resize: input.resize,
+                credits: input.credits,
+                seq: Some(seq),
+            }.to_packet()?)?;
+        }
 
         Ok(())
     }
 
-    /// Forwards a listen event to all daemons required from a web client.
-    pub async fn send_listen(&self, addr: SocketAddr, events: Vec<ListenEvent>) -> Result<(), String> {
-        let mut update_daemons = HashSet::new();
-        let mut offline_daemons = HashSet::new();
+    /// Closes a terminal on request of the web client that opened it.
+    pub fn close_terminal(&self, addr: SocketAddr, close: WSTerminalClosePacket) -> Result<(), String> {
+        let (_, terminal) = self.terminals.remove_if(&close.session, |_, terminal| terminal.web == addr).ok_or("Unknown terminal session")?;
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting DAEMON_ID_MAP", file!(), line!());
-        let daemon_id_map: &DaemonIDMap = self.daemon_id_map.borrow();
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got DAEMON_ID_MAP", file!(), line!());
+        self.close_daemon_terminal(close.session, terminal.daemon)
+    }
 
-        {
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] awaiting WEB_LISTEN_MAP", file!(), line!());
-            let web_listen_map: &WebListenMap = self.web_listen_map.borrow();
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] got WEB_LISTEN_MAP", file!(), line!());
+    /// Tells a daemon to close a terminal, if it is still connected.
+    fn close_daemon_terminal(&self, session: Uuid, daemon: Uuid) -> Result<(), String> {
+        let Some(daemon_addr) = self.daemon_id_map.get(&daemon).map(|daemon_addr| *daemon_addr) else {
+            return Ok(());
+        };
 
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] awaiting DAEMON_LISTEN_MAP", file!(), line!());
-            let daemon_listen_map: &DaemonListenMap = self.daemon_listen_map.borrow();
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] got DAEMON_LISTEN_MAP", file!(), line!());
+        self.send_to_daemon(&daemon_addr, SDTerminalClosePacket {
+            session,
+        }.to_packet()?)
+    }
 
-            for event in events.into_iter() {
-                for daemon in event.daemons.iter() {
-                    update_daemons.insert(*daemon);
+    /// Forwards the output of a terminal from the daemon to the web client that opened it.
+    pub fn send_terminal_output(&self, addr: &SocketAddr, output: DSTerminalOutputPacket) -> Result<(), String> {
+        self.forward_terminal_output(addr, output.session, output.seq, TerminalOutput::Data(output.data))
+    }
 
-                    if let Some(mut listen_map) = daemon_listen_map.get_mut(daemon) {
-                        if let Some(client_set) = listen_map.get_mut(&event.event) {
-                            client_set.insert(addr);
-                        } else {
-                            listen_map.insert(event.event, HashSet::from([addr]));
-                        }
-                    } else {
-                        let mut set = HashSet::new();
-                        set.insert(addr);
-                        let mut listen_map = HashMap::new();
-                        listen_map.insert(event.event, set);
-                        daemon_listen_map.insert(*daemon, listen_map);
-                    }
+    /// Tells the web client of a terminal that the daemon closed it, once the output before it was
+    /// forwarded.
+    pub fn send_terminal_close(&self, addr: &SocketAddr, close: DSTerminalClosePacket) -> Result<(), String> {
+        self.forward_terminal_output(addr, close.session, close.seq, TerminalOutput::Close(close))
+    }
 
-                    if event.event == EventType::NodeStatus && daemon_id_map.get(daemon).is_none() {
-                        offline_daemons.insert(*daemon);
-                    }
+    /// Forwards output and close packets of a terminal to its web client, in the order the daemon
+    /// sent them.
+    fn forward_terminal_output(&self, addr: &SocketAddr, session: Uuid, seq: Option<u64>, output: TerminalOutput) -> Result<(), String> {
+        let uuid = self.daemon_uuid(addr)?;
+        let mut forwarded = 0;
+        let mut close = None;
+
+        let web_addr = {
+            // output that was already on its way when the web client closed the terminal is dropped
+            let Some(mut terminal) = self.terminals.get_mut(&session).filter(|terminal| terminal.daemon == uuid) else {
+                return Ok(());
+            };
+
+            for output in terminal.output.push(seq, output)? {
+                let seq = terminal.next_output_seq();
+
+                match output {
+                    TerminalOutput::Data(data) => {
+                        self.send_to_web(&terminal.web, SWTerminalOutputPacket {
+                            session,
+                            data,
+                            seq: Some(seq),
+                        }.to_packet()?)?;
+
+                        forwarded += 1;
+                    },
+                    TerminalOutput::Close(packet) => {
+                        close = Some(SWTerminalClosePacket {
+                            session,
+                            exit_code: packet.exit_code,
+                            error: packet.error,
+                            seq: Some(seq),
+                        });
+
+                        break;
+                    },
                 }
+            }
 
-                if let Some(mut listen_map) = web_listen_map.get_mut(&addr) {
-                    if let Some(daemon_set) = listen_map.get_mut(&event.event) {
-                        for daemon in event.daemons.iter() {
-                            daemon_set.insert(*daemon);
-                        }
-                    } else {
-                        listen_map.insert(event.event, HashSet::from_iter(event.daemons.into_iter()));
-                    }
-                } else {
-                    web_listen_map.insert(addr, HashMap::from([(event.event, HashSet::from_iter(event.daemons.into_iter()))]));
+            terminal.web
+        };
+
+        if let Some(close) = close {
+            self.terminals.remove(&session);
+            return self.send_to_web(&web_addr, close.to_packet()?);
+        }
+
+        self.replenish_terminal_window(addr, &web_addr, session, forwarded)
+    }
+
+    /// Grants a daemon more credits for the output of a terminal on behalf of a web client without
+    /// flow control, which can always take more output, once half of its window has been used.
+    fn replenish_terminal_window(&self, addr: &SocketAddr, web_addr: &SocketAddr, session: Uuid, forwarded: u32) -> Result<(), String> {
+        if forwarded == 0 || !self.daemon_features(addr).has(Feature::TerminalFlowControl) || self.web_channel_map.get(web_addr).is_some_and(|socket| socket.has_feature(Feature::TerminalFlowControl)) {
+            return Ok(());
+        }
+
+        let Some(mut terminal) = self.terminals.get_mut(&session) else {
+            return Ok(());
+        };
+
+        terminal.forwarded += forwarded;
+
+        // granted in batches, rather than with an input packet for every output packet
+        if terminal.forwarded < flow::INITIAL_WINDOW / 2 {
+            return Ok(());
+        }
+
+        let credits = std::mem::take(&mut terminal.forwarded);
+        let seq = terminal.next_input_seq();
+
+        // held while sending, so the input is sent in the order it is numbered
+        self.send_to_daemon(addr, SDTerminalInputPacket {
+            session,
+            data: String::new(),
+            resize: None,
+            credits,
+            seq: Some(seq),
+        }.to_packet()?)
+    }
+
+    /// Forwards a process list query from a web client to the daemon, if it belongs to the team of
+    /// the user.
+    pub async fn query_top(&self, addr: SocketAddr, query: WSQueryTopPacket) -> Result<(), String> {
+        let user_id = self.web_user(&addr)?;
+
+        if !self.team_daemons(user_id).await?.contains(&query.daemon) {
+            return Err(format!("Node {} does not belong to your team", query.daemon));
+        }
+
+        let daemon_addr = *self.daemon_id_map.get(&query.daemon).ok_or("Daemon is not connected")?;
+        let request = self.register_query(addr, query.daemon);
+
+        self.send_to_daemon(&daemon_addr, SDQueryTopPacket {
+            request,
+            server: query.server,
+        }.to_packet()?)
+    }
+
+    /// Sends the answer to a process list query from a daemon to the web client that requested it.
+    pub fn send_top_response(&self, addr: &SocketAddr, response: DSQueryTopResponsePacket) -> Result<(), String> {
+        let uuid = self.daemon_uuid(addr)?;
+        let web_addr = self.take_query(response.request, uuid)?;
+
+        self.send_to_web(&web_addr, SWQueryTopResponsePacket {
+            daemon: uuid,
+            server: response.server,
+            table: response.table,
+            error: response.error,
+        }.to_packet()?)
+    }
+
+    /// Forwards a task list query from a web client to the daemon, if it belongs to the team of the
+    /// user.
+    pub async fn query_tasks(&self, addr: SocketAddr, query: WSQueryTasksPacket) -> Result<(), String> {
+        let user_id = self.web_user(&addr)?;
+
+        let res = async {
+            if !repository::get().team_daemons(user_id).await?.contains(&query.daemon) {
+                return Err(format!("Node {} does not belong to your team", query.daemon));
+            }
+
+            let daemon_addr = *self.daemon_id_map.get(&query.daemon).ok_or("Daemon is not connected")?;
+            let request = self.register_query(addr, query.daemon);
+
+            self.send_to_daemon(&daemon_addr, SDQueryTasksPacket {
+                request,
+            }.to_packet()?)
+        }.await;
+
+        match res {
+            Ok(()) => Ok(()),
+            Err(e) => self.send_to_web(&addr, SWQueryTasksResponsePacket {
+                daemon: query.daemon,
+                tasks: Vec::new(),
+                error: Some(e),
+            }.to_packet()?),
+        }
+    }
+
+    /// Sends the answer to a task list query from a daemon to the web client that requested it.
+    pub fn send_tasks_response(&self, addr: &SocketAddr, response: DSQueryTasksResponsePacket) -> Result<(), String> {
+        let uuid = self.daemon_uuid(addr)?;
+        let web_addr = self.take_query(response.request, uuid)?;
+
+        self.send_to_web(&web_addr, SWQueryTasksResponsePacket {
+            daemon: uuid,
+            tasks: response.tasks,
+            error: None,
+        }.to_packet()?)
+    }
+
+    /// Forwards the cancellation of a task from a web client to the daemon, if it belongs to the
+    /// team of the user. The daemon reports the cancellation as a `Task` event.
+    pub async fn cancel_task(&self, addr: SocketAddr, packet: WSCancelTaskPacket) -> Result<(), String> {
+        let user_id = self.web_user(&addr)?;
+
+        if !repository::get().team_daemons(user_id).await?.contains(&packet.daemon) {
+            return Err(format!("Node {} does not belong to your team", packet.daemon));
+        }
+
+        let daemon_addr = *self.daemon_id_map.get(&packet.daemon).ok_or("Daemon is not connected")?;
+
+        self.send_to_daemon(&daemon_addr, SDCancelTaskPacket {
+            task: packet.task,
+        }.to_packet()?)
+    }
+
+    /// Forwards a one-shot stats query from a web client to the daemon, if it belongs to the team
+    /// of the user.
+    pub async fn query_stats(&self, addr: SocketAddr, query: WSQueryStatsPacket) -> Result<(), String> {
+        let user_id = self.web_user(&addr)?;
+
+        if !self.team_daemons(user_id).await?.contains(&query.daemon) {
+            return Err(format!("Node {} does not belong to your team", query.daemon));
+        }
+
+        let daemon_addr = *self.daemon_id_map.get(&query.daemon).ok_or("Daemon is not connected")?;
+        let request = self.register_query(addr, query.daemon);
+
+        self.send_to_daemon(&daemon_addr, SDQueryStatsPacket {
+            request,
+            server: query.server,
+        }.to_packet()?)
+    }
+
+    /// Sends the answer to a one-shot stats query from a daemon to the web client that requested it.
+    pub fn send_stats_response(&self, addr: &SocketAddr, response: DSQueryStatsResponsePacket) -> Result<(), String> {
+        let uuid = self.daemon_uuid(addr)?;
+        let web_addr = self.take_query(response.request, uuid)?;
+
+        self.send_to_web(&web_addr, SWQueryStatsResponsePacket {
+            daemon: uuid,
+            server: response.server,
+            stats: response.stats,
+            error: response.error,
+        }.to_packet()?)
+    }
+
+    /// Forwards a usage history query from a web client to the daemon, if it belongs to the team
+    /// of the user.
+    pub async fn query_usage(&self, addr: SocketAddr, query: WSQueryUsagePacket) -> Result<(), String> {
+        let user_id = self.web_user(&addr)?;
+
+        if !self.team_daemons(user_id).await?.contains(&query.daemon) {
+            return Err(format!("Node {} does not belong to your team", query.daemon));
+        }
+
+        let daemon_addr = *self.daemon_id_map.get(&query.daemon).ok_or("Daemon is not connected")?;
+        let request = self.register_query(addr, query.daemon);
+
+        self.send_to_daemon(&daemon_addr, SDQueryUsagePacket {
+            request,
+            server: query.server,
+            from: query.from,
+            to: query.to,
+        }.to_packet()?)
+    }
+
+    /// Sends the answer to a usage history query from a daemon to the web client that requested it.
+    pub fn send_usage_response(&self, addr: &SocketAddr, response: DSQueryUsageResponsePacket) -> Result<(), String> {
+        let uuid = self.daemon_uuid(addr)?;
+        let web_addr = self.take_query(response.request, uuid)?;
+
+        self.send_to_web(&web_addr, SWQueryUsageResponsePacket {
+            daemon: uuid,
+            server: response.server,
+            samples: response.samples,
+        }.to_packet()?)
+    }
+
+    fn fleet_summary(&self, daemons: &HashSet<Uuid>) -> FleetSummaryEvent {
+        let mut summary = FleetSummaryEvent::default();
+        let mut cpu_samples = 0;
+
+        for daemon in daemons.iter() {
+            if !self.daemon_id_map.contains_key(daemon) {
+                summary.offline_daemons += 1;
+                continue;
+            }
+
+            summary.online_daemons += 1;
+
+            let status = match self.status_cache.get(daemon) {
+                Some(status) => status,
+                None => continue,
+            };
+
+            if let Some(stats) = &status.node {
+                summary.cpu += stats.cpu;
+                summary.used_memory += stats.used_memory;
+                summary.total_memory += stats.total_memory;
+                cpu_samples += 1;
+            }
+
+            for server in status.servers.values() {
+                match server {
+                    ServerStatusType::Healthy => summary.healthy_servers += 1,
+                    ServerStatusType::Stopped => summary.stopped_servers += 1,
+                    _ => summary.unhealthy_servers += 1,
                 }
             }
+        }
+
+        if cpu_samples > 0 {
+            summary.cpu /= cpu_samples as f64;
+        }
+
+        summary
+    }
+
+    /// Returns the latest cached events of a status event type of a daemon that a web client wants,
+    /// with their sequence numbers and timestamps.
+    fn latest_status_events(&self, addr: &SocketAddr, daemon: &Uuid, event_type: EventType) -> Vec<(EventData, Option<u64>, Option<u64>)> {
+        let Some(status) = self.status_cache.get(daemon) else {
+            return Vec::new();
+        };
+
+        match event_type {
+            EventType::NodeStatus => status.node_event.clone().map(|(event, seq, timestamp)| (EventData::NodeStatus(event), seq, timestamp)).into_iter().collect(),
+            EventType::ServerStatus => status.server_events.values()
+                .filter(|(event, _, _)| self.wants_status(addr, daemon, event.server))
+                .map(|(event, seq, timestamp)| (EventData::ServerStatus(event.clone()), *seq, *timestamp))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Sends the latest node status and server status events of a daemon to a web client, for the
+    /// event types it listens to, e.g. after it noticed a gap in their sequence numbers.
+    pub fn send_snapshot(&self, addr: SocketAddr, query: WSQuerySnapshotPacket) -> Result<(), String> {
+        let listens = |event: EventType| self.web_listen_map.get(&addr).is_some_and(|listen_map| listen_map.get(&event).is_some_and(|daemons| daemons.contains(&query.daemon)));
+
+        let events = [EventType::NodeStatus, EventType::ServerStatus].into_iter()
+            .filter(|event_type| listens(*event_type))
+            .flat_map(|event_type| self.latest_status_events(&addr, &query.daemon, event_type))
+            .collect::<Vec<_>>();
+
+        for (event, seq, timestamp) in events {
+            self.send_to_web(&addr, SWEventPacket {
+                event,
+                daemon: query.daemon,
+                seq,
+                timestamp,
+            }.to_packet()?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends the stored history (see `history::fetch`) and the latest cached events of a status
+    /// event type of a daemon to a web client that started listening to it, so it doesn't have to
+    /// wait for the next event. The history is sent oldest first, before the latest events.
+    fn send_status_history(&self, addr: SocketAddr, daemon: Uuid, event_type: EventType, stored: Vec<StoredEvent>) -> Result<(), String> {
+        let latest = self.latest_status_events(&addr, &daemon, event_type);
+
+        // the latest events are stored as well, but are sent from the cache
+        let latest_seqs = latest.iter().filter_map(|(_, seq, _)| *seq).collect::<HashSet<_>>();
+
+        for stored in stored.into_iter() {
+            if stored.seq.is_some_and(|seq| latest_seqs.contains(&seq)) {
+                continue;
+            }
+
+            if let EventData::ServerStatus(status) = &stored.event && !self.wants_status(&addr, &daemon, status.server) {
+                continue;
+            }
+
+            self.send_to_web(&addr, SWEventPacket {
+                event: stored.event,
+                daemon,
+                seq: stored.seq,
+                timestamp: Some(stored.timestamp),
+            }.to_packet()?)?;
+        }
+
+        for (event, seq, timestamp) in latest {
+            self.send_to_web(&addr, SWEventPacket {
+                event,
+                daemon,
+                seq,
+                timestamp,
+            }.to_packet()?)?;
+        }
+
+        Ok(())
+    }
+
+    /// Fetches the stored history of the status events web clients started listening to, with one
+    /// query per event type rather than per daemon.
+    async fn fetch_status_history(&self, statuses: &[(Uuid, EventType)]) -> HashMap<(Uuid, EventType), Vec<StoredEvent>> {
+        let mut histories = HashMap::new();
+
+        if !CONFIG.history.enabled || CONFIG.history.backfill == 0 {
+            return histories;
+        }
+
+        for event_type in [EventType::NodeStatus, EventType::ServerStatus] {
+            let daemons = statuses.iter().filter(|(_, status)| *status == event_type).map(|(daemon, _)| *daemon).collect::<Vec<_>>();
+
+            if daemons.is_empty() {
+                continue;
+            }
+
+            match history::fetch(&daemons, event_type, CONFIG.history.backfill).await {
+                Ok(events) => histories.extend(events.into_iter().map(|(daemon, events)| ((daemon, event_type), events))),
+                Err(e) => warn!("Could not fetch history of {} daemons: {}", daemons.len(), e),
+            }
+        }
+
+        histories
+    }
+
+    /// Sends a fleet summary of the daemons they listen to, to every web client listening for
+    /// `FleetSummary` events.
+    pub fn send_fleet_summaries(&self) -> Result<(), String> {
+        for listen_map in self.web_listen_map.iter() {
+            if let Some(daemons) = listen_map.get(&EventType::FleetSummary) {
+                self.send_to_web(listen_map.key(), SWEventPacket {
+                    event: EventData::FleetSummary(self.fleet_summary(daemons)),
+                    daemon: Uuid::nil(),
+                    seq: None,
+                    timestamp: None,
+                }.to_packet()?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the daemons a new server could be placed on, least-loaded first. Only daemons that
+    /// are online, not in maintenance and not full are eligible.
+    fn placement_candidates(&self, daemons: &[Uuid]) -> Vec<PlacementCandidate> {
+        let mut candidates = daemons.iter().filter(|daemon| self.daemon_id_map.contains_key(daemon)).filter_map(|daemon| {
+            let status = self.status_cache.get(daemon)?;
+            let stats = status.node.as_ref()?;
+
+            if status.in_maintenance {
+                return None;
+            }
+
+            let servers = status.server_counts.as_ref().map(ServerCounts::total).unwrap_or(status.servers.len() as u32);
+
+            if status.max_servers.is_some_and(|max_servers| servers >= max_servers) {
+                return None;
+            }
+
+            Some(PlacementCandidate {
+                daemon: *daemon,
+                load: placement::load(stats, servers, status.max_servers),
+                servers,
+                max_servers: status.max_servers,
+            })
+        }).collect::<Vec<_>>();
+
+        placement::rank(&mut candidates);
+
+        candidates
+    }
+
+    /// Suggests daemons of the web client's team for a new server, and if a server is given,
+    /// assigns it to the least-loaded one and syncs that daemon.
+    pub async fn place_server(&self, addr: SocketAddr, packet: WSPlaceServerPacket) -> Result<(), String> {
+        let user_id = self.web_channel_map.get(&addr).and_then(|socket| socket.handshake.as_ref().map(|handshake| handshake.user_id)).ok_or("Web client is not authenticated")?;
+
+        let candidates = self.placement_candidates(&repository::get().team_daemons(user_id).await?);
+
+        let assigned = match (packet.server, candidates.first()) {
+            (Some(server), Some(candidate)) => match quotas::check_placement(server, candidate.daemon).await {
+                Ok(Some(exceeded)) => Err(exceeded),
+                Ok(None) => placement::assign(user_id, server, candidate.daemon).await.map(|_| Some(candidate.daemon)),
+                Err(e) => Err(e),
+            },
+            (Some(_), None) => Err("No daemon is available to place the server on".to_string()),
+            (None, _) => Ok(None),
+        };
+
+        let (assigned, error) = match assigned {
+            Ok(assigned) => (assigned, None),
+            Err(e) => (None, Some(e)),
+        };
+
+        self.send_to_web(&addr, SWPlaceServerResponsePacket {
+            server: packet.server,
+            candidates,
+            assigned,
+            error,
+        }.to_packet()?)?;
+
+        if let (Some(server), Some(daemon)) = (packet.server, assigned) {
+            info!("Placed server {} on daemon {}", server, daemon);
+
+            self.invalidate_specs();
+            self.sync_daemon(daemon, None, HashMap::from([(addr, None)])).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Imports a declarative spec uploaded by a web client, if all of its nodes belong to the
+    /// client's team.
+    pub async fn import_spec(&self, addr: SocketAddr, packet: WSImportSpecPacket) -> Result<(), String> {
+        let user_id = self.web_channel_map.get(&addr).and_then(|socket| socket.handshake.as_ref().map(|handshake| handshake.user_id)).ok_or("Web client is not authenticated")?;
+
+        let res = async {
+            let spec = Spec::parse(&packet.spec, if packet.yaml { SpecFormat::Yaml } else { SpecFormat::Toml })?;
+            let team = repository::get().user_team(user_id).await?;
+
+            import::import(self, team, &spec, packet.prune, packet.dry_run).await
+        }.await;
+
+        let (changes, error) = match res {
+            Ok(changes) => (changes.iter().map(ToString::to_string).collect(), None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
+
+        self.send_to_web(&addr, SWImportSpecResponsePacket {
+            applied: error.is_none() && !packet.dry_run && !changes.is_empty(),
+            changes,
+            error,
+        }.to_packet()?)
+    }
+
+    /// Exports nodes of the web client's team as a declarative spec.
+    pub async fn export_spec(&self, addr: SocketAddr, packet: WSExportSpecPacket) -> Result<(), String> {
+        let user_id = self.web_channel_map.get(&addr).and_then(|socket| socket.handshake.as_ref().map(|handshake| handshake.user_id)).ok_or("Web client is not authenticated")?;
+
+        let res = async {
+            let daemons = repository::get().team_daemons(user_id).await?;
+
+            if let Some(daemon) = packet.daemons.iter().find(|daemon| !daemons.contains(daemon)) {
+                return Err(format!("Node {} does not belong to your team", daemon));
+            }
+
+            let daemons = if packet.daemons.is_empty() { daemons } else { packet.daemons.clone() };
+
+            spec::export(&daemons).await?.to_toml()
+        }.await;
+
+        let (spec, error) = match res {
+            Ok(spec) => (Some(spec), None),
+            Err(e) => (None, Some(e)),
+        };
+
+        self.send_to_web(&addr, SWExportSpecResponsePacket {
+            spec,
+            error,
+        }.to_packet()?)
+    }
+
+    /// Updates the display metadata of a server of the web client's team, and passes it on to its
+    /// daemon if connected, which replaces the container of the server to apply it.
+    pub async fn server_metadata(&self, addr: SocketAddr, packet: WSServerMetadataPacket) -> Result<(), String> {
+        let user_id = self.web_channel_map.get(&addr).and_then(|socket| socket.handshake.as_ref().map(|handshake| handshake.user_id)).ok_or("Web client is not authenticated")?;
+
+        let res = async {
+            if !repository::get().team_daemons(user_id).await?.contains(&packet.daemon) {
+                return Err(format!("Node {} does not belong to your team", packet.daemon));
+            }
+
+            let metadata = metadata::update(packet.daemon, packet.server, packet.name.clone(), packet.labels.clone()).await?;
+            self.invalidate_specs();
+
+            if let Some(daemon_addr) = self.daemon_id_map.get(&packet.daemon).map(|addr| *addr) {
+                self.send_to_daemon(&daemon_addr, SDServerMetadataPacket {
+                    metadata,
+                }.to_packet()?)?;
+            }
+
+            Ok(())
+        }.await;
+
+        self.send_to_web(&addr, SWServerMetadataResponsePacket {
+            daemon: packet.daemon,
+            server: packet.server,
+            error: res.err(),
+        }.to_packet()?)
+    }
+
+    /// Answers a usage query of a web client with the monthly usage records of its team.
+    pub async fn query_team_usage(&self, addr: SocketAddr, query: WSQueryTeamUsagePacket) -> Result<(), String> {
+        let user_id = self.web_channel_map.get(&addr).and_then(|socket| socket.handshake.as_ref().map(|handshake| handshake.user_id)).ok_or("Web client is not authenticated")?;
+
+        let (team, months, error) = match accounting::query(user_id, &query.from, &query.to).await {
+            Ok((team, months)) => (team, months, None),
+            Err(e) => (0, Vec::new(), Some(e)),
+        };
+
+        let mut total = Usage::default();
+        for month in months.iter() {
+            total.add(&month.usage);
+        }
+
+        self.send_to_web(&addr, SWQueryTeamUsageResponsePacket {
+            team,
+            months,
+            total,
+            error,
+        }.to_packet()?)
+    }
+
+    /// Answers a connection history query of a web client for a daemon of its team.
+    pub async fn query_connections(&self, addr: SocketAddr, query: WSQueryConnectionsPacket) -> Result<(), String> {
+        let user_id = self.web_channel_map.get(&addr).and_then(|socket| socket.handshake.as_ref().map(|handshake| handshake.user_id)).ok_or("Web client is not authenticated")?;
+
+        let res = async {
+            if !repository::get().team_daemons(user_id).await?.contains(&query.daemon) {
+                return Err(format!("Node {} does not belong to your team", query.daemon));
+            }
+
+            sessions::query(query.daemon, query.limit).await
+        }.await;
+
+        let (sessions, error) = match res {
+            Ok(sessions) => (sessions, None),
+            Err(e) => (Vec::new(), Some(e)),
+        };
+
+        self.send_to_web(&addr, SWQueryConnectionsResponsePacket {
+            daemon: query.daemon,
+            sessions,
+            error,
+        }.to_packet()?)
+    }
+
+    /// Answers a query of a web client for the notification inbox of its user.
+    pub async fn query_notifications(&self, addr: SocketAddr, query: WSQueryNotificationsPacket) -> Result<(), String> {
+        let user_id = self.web_user(&addr)?;
+
+        let (notifications, unread, error) = match inbox::query(user_id, query.unread_only, query.before, query.limit).await {
+            Ok((notifications, unread)) => (notifications, unread, None),
+            Err(e) => (Vec::new(), 0, Some(e)),
+        };
+
+        self.send_to_web(&addr, SWQueryNotificationsResponsePacket {
+            notifications,
+            unread,
+            error,
+        }.to_packet()?)
+    }
+
+    /// Marks notifications in the inbox of the user of a web client as read.
+    pub async fn mark_notifications_read(&self, addr: SocketAddr, packet: WSMarkNotificationsReadPacket) -> Result<(), String> {
+        let user_id = self.web_user(&addr)?;
+
+        inbox::mark_read(user_id, &packet.ids, packet.all).await
+    }
+
+    /// Answers a metrics history query from a web client from the database.
+    pub async fn query_metrics(&self, addr: SocketAddr, query: WSQueryMetricsPacket) -> Result<(), String> {
+        let samples = metrics::query(query.daemon, query.server, query.from, query.to).await?;
+
+        self.send_to_web(&addr, SWQueryMetricsResponsePacket {
+            daemon: query.daemon,
+            server: query.server,
+            samples,
+        }.to_packet()?)
+    }
+
+    /// Sends an event from the server to the web clients listening, returning once it was
+    /// delivered.
+    pub async fn send_event_from_server(&self, uuid: &Uuid, event: EventData) -> Result<(), String> {
+        match self.fanout.get() {
+            Some(fanout) => fanout.deliver(*uuid, event, None, None).await,
+            None => self.process_event(uuid, event, None, None).await,
+        }
+    }
+
+    /// Hands an event to the fan-out worker of the daemon, so events of a daemon are delivered in
+    /// the order they are sent. Waits while the worker is behind, so a daemon can't queue events
+    /// faster than they are delivered.
+    async fn submit_event(&self, uuid: &Uuid, event: EventData, seq: Option<u64>, timestamp: Option<u64>) -> Result<(), String> {
+        match self.fanout.get() {
+            Some(fanout) => fanout.submit(*uuid, event, seq, timestamp).await,
+            None => self.process_event(uuid, event, seq, timestamp).await,
+        }
+    }
+
+    /// Runs the WASM extensions of the team of the daemon on an event and hands it to the plugins,
+    /// updates the status cache and evaluates the alert and automation rules of the daemon against
+    /// it, then delivers it to the web clients listening.
+    pub async fn process_event(&self, uuid: &Uuid, event: EventData, seq: Option<u64>, timestamp: Option<u64>) -> Result<(), String> {
+        #[cfg(feature = "wasm")]
+        let Some(event) = crate::wasm::filter(uuid, event).await? else {
+            return Ok(());
+        };
+
+        plugins::event(uuid, &event);
+        self.cache_status(uuid, &event, seq, timestamp);
+
+        if let EventData::ResourceWarning(warning) = &event {
+            if warning.firing {
+                warn!("Daemon {} is running low on {:?} ({:.1}% used, threshold {}%)", uuid, warning.resource, warning.value, warning.threshold);
+            } else {
+                info!("Daemon {} recovered from low {:?} ({:.1}% used)", uuid, warning.resource, warning.value);
+            }
+
+            alerts::notify_resource_warning(uuid, warning);
+        }
+
+        for alert in alerts::evaluate(uuid, &event) {
+            let message = match alert.firing {
+                true => format!("{:?} usage is at {:.1}%, above the threshold of {}%", alert.metric, alert.value, alert.threshold),
+                false => format!("{:?} usage recovered to {:.1}%", alert.metric, alert.value),
+            };
+            inbox::notify(Recipients::Team(*uuid), NotificationKind::Alert, alert.server, message);
+
+            if let Err(e) = self.deliver_event(uuid, EventData::Alert(alert), None, None) {
+                warn!("Could not deliver alert: {}", e);
+            }
+        }
+
+        for firing in automation::evaluate(uuid, &event, timestamp) {
+            self.run_automation(uuid, firing);
+        }
+
+        self.deliver_event(uuid, event, seq, timestamp)
+    }
+
+    /// Updates the status cache with a status event of a daemon.
+    fn cache_status(&self, uuid: &Uuid, event: &EventData, seq: Option<u64>, timestamp: Option<u64>) {
+        match event {
+            EventData::NodeStatus(status) => {
+                let mut cached = self.status_cache.entry(*uuid).or_default();
+                cached.node = status.stats.clone();
+                cached.server_counts = status.servers.clone();
+                cached.max_servers = status.max_servers;
+                cached.in_maintenance = status.in_maintenance;
+                cached.node_event = Some((status.clone(), seq, timestamp));
+            },
+            EventData::ServerStatus(status) => {
+                let mut cached = self.status_cache.entry(*uuid).or_default();
+                cached.servers.insert(status.server, status.status.clone());
+                cached.server_events.insert(status.server, (status.clone(), seq, timestamp));
+            },
+            _ => (),
+        }
+    }
+
+    /// Runs the actions of an automation rule that fired: sends its command to the daemon, whose
+    /// result is logged once the daemon answers, and notifies the team of the daemon.
+    fn run_automation(&self, uuid: &Uuid, firing: automation::Firing) {
+        if let Some(command) = firing.command {
+            let res = (|| {
+                let daemon_addr = *self.daemon_id_map.get(uuid).ok_or("Daemon is not connected")?;
+                let request = self.next_request.fetch_add(1, Ordering::Relaxed);
+                automation::command_sent(request, *uuid, firing.rule);
+
+                self.send_to_daemon(&daemon_addr, SDCommandPacket {
+                    request,
+                    server: firing.server,
+                    command,
+                }.to_packet()?).inspect_err(|_| {
+                    automation::forget_command(request);
+                })
+            })();
+
+            if let Err(e) = res {
+                automation::log(firing.rule, *uuid, firing.server, automation::Outcome::CommandFailed, format!("Could not send {:?}: {}", command, e));
+            }
+        }
+
+        if let Some(message) = firing.notification {
+            inbox::notify(Recipients::Team(*uuid), NotificationKind::Automation, Some(firing.server), message);
+        }
+    }
+
+    fn deliver_event(&self, uuid: &Uuid, event: EventData, seq: Option<u64>, timestamp: Option<u64>) -> Result<(), String> {
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_LISTEN_MAP", file!(), line!());
+        let map: &DaemonListenMap = self.daemon_listen_map.borrow();
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_LISTEN_MAP", file!(), line!());
+        // daemons without listeners are removed from the map, see `compact`
+        let Some(daemon) = map.get(uuid) else {
+            return Ok(());
+        };
+
+        let event_type = event.event_type();
+        let clients = daemon.get(&event_type);
+
+        // all web clients of a user share its key, so the event only has to be encrypted once per
+        // user (and encoding) rather than for every client
+        let mut encrypted = HashMap::<(Arc<Vec<u8>>, Encoding), String>::new();
+
+        if let Some(clients) = clients {
+            for client in clients.iter() {
+                #[cfg(feature = "lock_debug")]
+                debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
+                let map: &WebChannelMap = self.web_channel_map.borrow();
+
+                #[cfg(feature = "lock_debug")]
+                debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
+                let socket = map.get(client).ok_or("Disconnected client still in WebChannelMap")?;
+
+                // logs are only sent to the web clients following the server
+                if let EventData::ServerLog(log) = &event
+                    && !self.log_listens.get(&(*uuid, log.server)).is_some_and(|clients| clients.contains(client)) {
+                    continue;
+                }
+
+                if let EventData::ServerStatus(status) = &event
+                    && !self.wants_status(client, uuid, status.server) {
+                    continue;
+                }
+
+                // streamed events are left out while the web client has no credits left
+                if event_type.is_streamed() && socket.has_feature(Feature::FlowControl) && !self.stream_windows.entry((*client, *uuid, event_type)).or_default().take() {
+                    continue;
+                }
+
+                let handshake = socket.handshake.as_ref().ok_or("Client hasn't requested authentication")?;
+
+                let msg = match encrypted.entry((Arc::clone(&handshake.key), socket.encoding)) {
+                    Entry::Occupied(entry) => entry.get().clone(),
+                    Entry::Vacant(entry) => entry.insert(
+                        encryption::encrypt_packet(
+                            SWEventPacket {
+                                event: event.clone(),
+                                daemon: *uuid,
+                                seq,
+                                timestamp,
+                            }.to_packet()?,
+                            &handshake.encrypter,
+                            socket.encoding
+                        )?
+                    ).clone(),
+                };
+
+                socket.tx.unbounded_send(Message::Text(msg)).map_err(|_| "Could not send packet to client")?;
+
+                #[cfg(feature = "lock_debug")]
+                debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
+            }
+        }
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_LISTEN_MAP", file!(), line!());
+
+        Ok(())
+    }
+
+    /// Sends an event from the daemon to the web clients listening, see `process_event`. The
+    /// packets of a connection are handled concurrently, so events are put back into the order the
+    /// daemon sent them, except for snapshots, which are handled right away and dropped if a newer
+    /// one was handled first.
+    pub async fn send_event_from_daemon(&self, addr: &SocketAddr, packet: DSEventPacket) -> Result<(), String> {
+        let seq = packet.seq;
+        let (snapshot, held) = match packet.data.event_type().is_snapshot() {
+            true => (Some(packet), None),
+            false => (None, Some(packet)),
+        };
+
+        let reorder = self.event_reorders.entry(*addr).or_insert_with(|| Arc::new(Mutex::new(Reorder::starting_at(1)))).clone();
+        let mut reorder = reorder.lock().await;
+
+        // events can get lost if they can't be parsed, so the ones after them are passed on
+        // eventually
+        let ready = match reorder.push_lossy(seq, held) {
+            Ok(ready) => ready,
+            Err(e) => {
+                debug!("Dropping event of daemon at {}: {}", addr, e);
+                return Ok(());
+            },
+        };
+
+        // handled while the lock is held, so they stay in order
+        let mut res = Ok(());
+        for packet in ready.into_iter().flatten() {
+            res = res.and(self.handle_event_from_daemon(addr, packet).await);
+        }
+
+        drop(reorder);
+
+        match snapshot {
+            Some(packet) => res.and(self.handle_event_from_daemon(addr, packet).await),
+            None => res,
+        }
+    }
+
+    /// Records an event from the daemon that is next in order, and submits it unless it is
+    /// replayed or a stale snapshot.
+    async fn handle_event_from_daemon(&self, addr: &SocketAddr, packet: DSEventPacket) -> Result<(), String> {
+        let DSEventPacket { data: event, seq, timestamp, replayed } = packet;
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
+        let uuid = self.daemon_channel_map.get(addr).ok_or("Daemon not found in DaemonChannelMap")?.handshake.as_ref().ok_or("Client hasn't requested authentication")?.daemon_uuid;
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+
+        let event_type = event.event_type();
+
+        if self.is_stale(&uuid, &event, seq) {
+            debug!("Dropping stale {:?} event {:?} of daemon {}", event_type, seq, uuid);
+            return Ok(());
+        }
+
+        history::record(uuid, &event, seq, timestamp);
+
+        let sample = event.clone();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::record(uuid, &sample, timestamp).await {
+                warn!("{}", e);
+            }
+        });
+
+        // only status events are buffered by the daemon, which are out of date by the time they
+        // are replayed, so they only catch up the history, the status cache and the statuses
+        // automation rules compare against
+        if replayed {
+            self.cache_status(&uuid, &event, seq, timestamp);
+            automation::replayed(&uuid, &event, timestamp);
+            return Ok(());
+        }
+
+        if !event_type.is_streamed() {
+            return self.submit_event(&uuid, event, seq, timestamp).await;
+        }
+
+        // the daemon took a credit for sending the event
+        self.daemon_windows.entry((uuid, event_type)).or_default().take();
+        self.submit_event(&uuid, event, seq, timestamp).await?;
+        self.replenish_daemon_window(&uuid, event_type)
+    }
+
+    /// Returns whether a snapshot of a daemon is a duplicate of, or older than, the latest snapshot
+    /// of its type (and server), in which case it is dropped. Otherwise, it becomes the latest
+    /// snapshot. Other events, and events of older daemons without sequence numbers, are never
+    /// stale.
+    fn is_stale(&self, uuid: &Uuid, event: &EventData, seq: Option<u64>) -> bool {
+        let Some(seq) = seq.filter(|_| event.event_type().is_snapshot()) else {
+            return false;
+        };
+
+        let server = match event {
+            EventData::ServerStatus(status) => Some(status.server),
+            _ => None,
+        };
+
+        let mut latest = self.event_sequences.entry((*uuid, event.event_type(), server)).or_default();
+
+        if seq <= *latest {
+            return true;
+        }
+
+        *latest = seq;
+        false
+    }
+
+    /// Grants a daemon more credits for a streamed event type once a web client listening for it
+    /// has a lot more credits left than the daemon, so the daemon sends as many events as the
+    /// fastest web client can take. Web clients without flow control can always take more events.
+    fn replenish_daemon_window(&self, uuid: &Uuid, event: EventType) -> Result<(), String> {
+        let Some(addr) = self.daemon_id_map.get(uuid).map(|addr| *addr) else {
+            return Ok(());
+        };
+
+        if !self.daemon_features(&addr).has(Feature::FlowControl) {
+            return Ok(());
+        }
+
+        let clients = self.daemon_listen_map.get(uuid).and_then(|listen_map| listen_map.get(&event).cloned()).unwrap_or_default();
+        let target = clients.iter().map(|client| {
+            match self.web_channel_map.get(client) {
+                Some(socket) if socket.has_feature(Feature::FlowControl) => self.stream_windows.get(&(*client, *uuid, event)).map(|window| window.credits()).unwrap_or(flow::INITIAL_WINDOW),
+                _ => flow::INITIAL_WINDOW,
+            }
+        }).max().unwrap_or_default();
+
+        let credits = {
+            let mut window = self.daemon_windows.entry((*uuid, event)).or_default();
+
+            // granted in batches, rather than with a window update for every event
+            if window.credits() * 2 >= target {
+                return Ok(());
+            }
+
+            let credits = target - window.credits();
+            window.grant(credits);
+            credits
+        };
+
+        self.send_to_daemon(&addr, SDWindowUpdatePacket {
+            event,
+            credits,
+        }.to_packet()?)
+    }
+
+    /// Adds credits granted by a web client to its window for a streamed event type of a daemon it
+    /// listens to.
+    pub fn grant_web_window(&self, addr: SocketAddr, window_update: WSWindowUpdatePacket) -> Result<(), String> {
+        if !window_update.event.is_streamed() {
+            return Err(format!("{:?} events are not subject to flow control", window_update.event));
+        }
+
+        let listening = self.web_listen_map.get(&addr).is_some_and(|listen_map| listen_map.get(&window_update.event).is_some_and(|daemons| daemons.contains(&window_update.daemon)));
+
+        if !listening {
+            return Err(format!("Web client doesn't listen for {:?} events of {}", window_update.event, window_update.daemon));
+        }
+
+        self.stream_windows.entry((addr, window_update.daemon, window_update.event)).or_default().grant(window_update.credits);
+        self.replenish_daemon_window(&window_update.daemon, window_update.event)
+    }
+
+    /// Returns the UUID of an authenticated daemon.
+    pub fn daemon_uuid(&self, addr: &SocketAddr) -> Result<Uuid, String> {
+        Ok(self.daemon_channel_map.get(addr).ok_or("Daemon not found in DaemonChannelMap")?.handshake.as_ref().ok_or("Daemon hasn't authenticated")?.daemon_uuid)
+    }
+
+    /// Returns the user ID a web client requested authentication for.
+    pub fn web_user(&self, addr: &SocketAddr) -> Result<u32, String> {
+        Ok(self.web_channel_map.get(addr).ok_or("Client not found in channel_map")?.handshake.as_ref().ok_or("Web client hasn't requested authentication")?.user_id)
+    }
+
+    /// Returns the UUID of a daemon, if it has answered the challenge and was accepted.
+    pub fn authenticated_daemon_uuid(&self, addr: &SocketAddr) -> Option<Uuid> {
+        self.daemon_channel_map.get(addr)?.handshake.as_ref().filter(|handshake| handshake.authenticated).map(|handshake| handshake.daemon_uuid)
+    }
+
+    /// Returns the user ID of a web client, if it has answered the challenge.
+    pub fn authenticated_web_user(&self, addr: &SocketAddr) -> Option<u32> {
+        self.web_channel_map.get(addr)?.handshake.as_ref().filter(|handshake| handshake.authenticated).map(|handshake| handshake.user_id)
+    }
+
+    /// Returns the features negotiated with a daemon, or no features if it hasn't authenticated.
+    fn daemon_features(&self, addr: &SocketAddr) -> Features {
+        self.daemon_channel_map.get(addr).and_then(|socket| socket.handshake.as_ref().map(|handshake| handshake.features.clone())).unwrap_or_default()
+    }
+
+    /// Sends a handshake request to a daemon, in response to its auth packet.
+    pub async fn send_daemon_handshake_request(&self, addr: SocketAddr, uuid: Uuid, key: Arc<Vec<u8>>, auth: DSAuthPacket, rejection: Option<String>, update: Option<UpdateRequiredEvent>) -> Result<(), String> {
+        let mut challenge_bytes = [0; 256];
+        rand_bytes(&mut challenge_bytes).map_err(|_| "Could not generate challenge")?;
+
+        let challenge = challenge_bytes.iter().try_fold::<_, _, Result<String, String>>(String::default(), |mut s, byte| {
+            write!(s, "{:02X}", byte).map_err(|_| "could not write byte")?;
+            Ok(s)
+        })?;
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
+        let clients: &DaemonChannelMap = self.daemon_channel_map.borrow();
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
+        let mut client = clients.get_mut(&addr).ok_or("Client not found in channel_map")?;
+
+        client.handshake = Some(DaemonHandshake {
+            daemon_uuid: uuid,
+            encrypter: josekit::jwe::RSA_OAEP.encrypter_from_pem(key.as_ref()).map_err(|_| "key should be valid")?,
+            challenge: challenge.clone(),
+            sync_hash: auth.sync_hash,
+            features: Features::supported().negotiate(&auth.features),
+            rejection,
+            update,
+            authenticated: false,
+        });
+
+        client.tx.unbounded_send(
+            Message::text(
+                encryption::encrypt_packet(
+                    SDHandshakeRequestPacket {
+                        challenge
+                    }.to_packet()?,
+                    &client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.encrypter,
+                    client.encoding,
+                )?
+            )
+        ).map_err(|_| "Failed to send packet")?;
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+
+        Ok(())
+    }
+
+    /// Authenticates a daemon with the given challenge.
+    pub fn authenticate_daemon(&self, addr: SocketAddr, challenge: String) -> Result<(), String> {
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
+        let clients: &DaemonChannelMap = self.daemon_channel_map.borrow();
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
+        let mut client = clients.get_mut(&addr).ok_or("Client not found in channel_map")?;
+
+        if challenge != client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.challenge {
+            warn!("Failed authentication");
+            // the key may have been rotated since it was cached
+            if let Some(handshake) = client.handshake.as_ref() {
+                self.daemon_key_cache.remove(&handshake.daemon_uuid);
+            }
+            server::close(&client.tx, CloseReason::AuthFailed);
+            return Err("Challenge does not match".to_string());
+        }
+
+        let handshake = client.handshake.as_ref().ok_or("Client hasn't requested authentication")?;
+        let uuid = handshake.daemon_uuid;
+        let encrypter = &handshake.encrypter;
+
+        if let Some(update) = handshake.update.as_ref()
+            && let Err(e) = self.deliver_event(&uuid, EventData::UpdateRequired(update.clone()), None, None) {
+            warn!("Could not deliver update required event: {}", e);
+        }
+
+        if let Some(rejection) = handshake.rejection.as_ref() {
+            warn!("Rejected daemon {}: {}", uuid, rejection);
+
+            client.tx.unbounded_send(
+                Message::text(
+                    encryption::encrypt_packet(
+                        SDAuthResponsePacket {
+                            success: false,
+                            features: Features::default(),
+                            error: Some(rejection.clone()),
+                        }.to_packet()?,
+                        encrypter,
+                        client.encoding,
+                    )?
+                )
+            ).map_err(|_| "Failed to send packet")?;
+            server::close(&client.tx, CloseReason::Rejected);
+
+            return Err(rejection.clone());
+        }
+
+        client.tx.unbounded_send(
+            Message::text(
+                encryption::encrypt_packet(
+                    SDAuthResponsePacket {
+                        success: true,
+                        features: handshake.features.clone(),
+                        error: None,
+                    }.to_packet()?,
+                    encrypter,
+                    client.encoding,
+                )?
+            )
+        ).map_err(|_| "Failed to send packet")?;
+
+        if handshake.features.has(Feature::RemoteConfig) {
+            client.tx.unbounded_send(
+                Message::text(
+                    encryption::encrypt_packet(
+                        CONFIG.remote_config.packet().to_packet()?,
+                        encrypter,
+                        client.encoding,
+                    )?
+                )
+            ).map_err(|_| "Failed to send packet")?;
+        }
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_LISTEN_MAP", file!(), line!());
+        let daemon_listen_map: &DaemonListenMap = self.daemon_listen_map.borrow();
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_LISTEN_MAP", file!(), line!());
+        // looked up first, as it reads the listens of the daemon as well
+        let status_servers = self.status_servers(&uuid);
+
+        if let Some(listen_map) = daemon_listen_map.get(&uuid) {
+            let events = listen_map.keys().copied().collect::<Vec<_>>();
+
+            client.tx.unbounded_send(
+                Message::Text(
+                    encryption::encrypt_packet(
+                        SDListenPacket {
+                            events,
+                            log_servers: self.log_servers(&uuid),
+                            status_servers,
+                        }.to_packet()?,
+                        encrypter,
+                        client.encoding
+                    )?
+                )
+            ).map_err(|_| "Failed to send packet")?;
+        }
+
+        if let Some(handshake) = client.handshake.as_mut() {
+            handshake.authenticated = true;
+        }
+
+        // sequence numbers start over on every connection
+        self.event_sequences.retain(|(daemon, _, _), _| *daemon != uuid);
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_ID_MAP", file!(), line!());
+        self.daemon_id_map.insert(uuid, addr);
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_ID_MAP", file!(), line!());
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_ID_MAP", file!(), line!());
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_LISTEN_MAP", file!(), line!());
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+
+        Ok(())
+    }
+
+    fn sync_lock(&self, uuid: Uuid) -> Arc<Mutex<()>> {
+        self.sync_locks.entry(uuid).or_default().clone()
+    }
+
+    /// Sends initial data to a daemon.
+    pub async fn send_init_data(&self, addr: SocketAddr) -> Result<(), String> {
+        let (uuid, sync_hash) = {
+            let client = self.daemon_channel_map.get(&addr).ok_or("Client not found in channel_map")?;
+            let handshake = client.handshake.as_ref().ok_or("Client hasn't requested authentication")?;
+            (handshake.daemon_uuid, handshake.sync_hash.clone())
+        };
+
+        let sent = {
+            let lock = self.sync_lock(uuid);
+            let _guard = lock.lock().await;
+
+            // a delta sync can only be sent if the daemon has applied the spec it would be based on
+            self.sync_cache.remove_if(&uuid, |_, spec| spec.hash != sync_hash);
+
+            self.send_sync(uuid, Some(addr), None).await?
+        };
+
+        self.finish_sync(uuid, sent).await
+    }
+
+    /// Reconciles a daemon that just authenticated with the database, by sending it its initial
+    /// data. Failed attempts are retried, until the daemon disconnects or the configured amount of
+    /// attempts is reached.
+    pub async fn reconcile_daemon(&self, addr: SocketAddr) {
+        for attempt in 1..=CONFIG.sync.reconcile_attempts {
+            match self.send_init_data(addr).await {
+                Ok(()) => return,
+                Err(e) => warn!("Reconciliation sync failed (attempt {}/{}): {}", attempt, CONFIG.sync.reconcile_attempts, e),
+            }
+
+            tokio::time::sleep(Duration::from_secs(CONFIG.sync.reconcile_retry_delay * attempt as u64)).await;
+
+            if self.daemon_uuid(&addr).is_err() {
+                return;
+            }
+        }
+
+        error!("Giving up on reconciliation sync after {} attempts", CONFIG.sync.reconcile_attempts);
+    }
+
+    /// Sends a sync progress step to web clients. Clients that disconnected in the meantime are
+    /// skipped, but the final step of the sync `request` is still delivered to their users, see
+    /// `send_critical`.
+    fn send_sync_step(&self, requesters: &Requesters, uuid: Uuid, step: SyncStep, request: Option<u64>) -> Result<(), String> {
+        let is_final = step.is_final();
+
+        if let SyncStep::Failed { error } = &step {
+            inbox::notify(Recipients::Team(uuid), NotificationKind::SyncFailed, None, format!("Sync failed: {}", error));
+        }
+
+        let packet = SWSyncProgressPacket {
+            daemon: uuid,
+            step,
+        }.to_packet()?;
+
+        for requester in requesters.keys() {
+            let supported = self.web_channel_map.get(requester).is_some_and(|socket| socket.handshake.as_ref().is_some_and(|handshake| handshake.features.has(Feature::SyncProgress)));
+
+            if !supported {
+                continue;
+            }
+
+            let res = match is_final {
+                true => self.send_critical(requester, packet.clone()),
+                false => self.send_to_web(requester, packet.clone()),
+            };
+
+            if let Err(e) = res {
+                warn!("Could not send sync progress to {}: {}", requester, e);
+            }
+        }
+
+        if is_final
+            && let Some(request) = request
+            && let Some((_, users)) = self.sync_orphans.remove(&request) {
+            for user_id in users {
+                self.send_critical_to_user(user_id, packet.clone())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Records a sync request of a web client. If the client exceeded its rate limit, it is sent a
+    /// `Throttled` step instead, and `true` is returned.
+    pub fn throttle_sync(&self, addr: SocketAddr, uuid: Uuid) -> Result<bool, String> {
+        let window = SYNC_RATE_LIMIT_WINDOW;
+
+        let retry_after = {
+            let mut requests = self.sync_rate_limits.entry(addr).or_default();
+
+            while requests.front().is_some_and(|request| request.elapsed() >= window) {
+                requests.pop_front();
+            }
+
+            if requests.len() < CONFIG.sync.rate_limit as usize {
+                requests.push_back(Instant::now());
+                return Ok(false);
+            }
+
+            requests.front().map(|request| window.saturating_sub(request.elapsed())).unwrap_or(window)
+        };
+
+        warn!("Web client {} exceeded its sync rate limit", addr);
+
+        self.send_sync_step(&HashMap::from([(addr, None)]), uuid, SyncStep::Throttled {
+            retry_after: retry_after.as_secs().max(1),
+        }, None)?;
+
+        Ok(true)
+    }
+
+    /// Counts a packet of a web client against the rate limit of its class. Returns whether the
+    /// packet should be dropped, in which case the web client is told which limit it exceeded.
+    pub fn throttle_packet(&self, addr: SocketAddr, class: PacketClass, request: Option<u64>) -> Result<bool, String> {
+        let limit = class.limit();
+
+        if limit.rate == 0 || self.packet_rate_limits.entry((addr, class)).or_insert_with(|| Bucket::new(limit)).take(limit) {
+            return Ok(false);
+        }
+
+        warn!("Web client {} exceeded its {} rate limit", addr, class);
+
+        self.send_to_web(&addr, SWErrorPacket {
+            code: ErrorCode::RateLimited,
+            message: format!("Exceeded the {} rate limit of {} packets per {} seconds (burst of {})", class, limit.rate, limit.period, limit.burst),
+            daemon: None,
+            request,
+        }.to_packet()?)?;
+
+        Ok(true)
+    }
+
+    /// Adds a web client to the clients waiting for a sync of a daemon. Returns how long to wait
+    /// before flushing the sync with `flush_sync`, or `None` if a flush is already scheduled, which
+    /// the request is coalesced into.
+    pub fn queue_sync(&self, uuid: Uuid, requester: SocketAddr, request: Option<u64>) -> Option<Duration> {
+        let mut debounce = self.sync_debounce.entry(uuid).or_default();
+        debounce.waiting.insert(requester, request);
+
+        if debounce.scheduled {
+            return None;
+        }
+
+        debounce.scheduled = true;
+
+        let window = Duration::from_secs(CONFIG.sync.debounce);
+        Some(debounce.last.map(|last| window.saturating_sub(last.elapsed())).unwrap_or_default())
+    }
+
+    /// Syncs a daemon for all web clients waiting for it, see `queue_sync`.
+    pub async fn flush_sync(&self, uuid: Uuid) -> Result<(), String> {
+        let requesters = match self.sync_debounce.get_mut(&uuid) {
+            Some(mut debounce) => {
+                debounce.scheduled = false;
+                debounce.last = Some(Instant::now());
+                std::mem::take(&mut debounce.waiting)
+            },
+            None => return Ok(()),
+        };
+
+        self.sync_daemon(uuid, None, requesters).await
+    }
+
+    /// Sends data to a daemon for synchronization with the database. The progress of applying the
+    /// sync is reported to the `requesters`.
+    pub async fn sync_daemon(&self, uuid: Uuid, addr: Option<SocketAddr>, requesters: Requesters) -> Result<(), String> {
+        if let Err(e) = repository::get().writable() {
+            self.send_sync_step(&requesters, uuid, SyncStep::Failed {
+                error: e.clone(),
+            }, None)?;
+
+            return Err(e);
+        }
+
+        // the lock only orders sending syncs, the acknowledgement can take until `sync.ack_timeout`
+        let (sent, request) = {
+            let lock = self.sync_lock(uuid);
+            let _guard = lock.lock().await;
+
+            // progress can only be reported by daemons that support it
+            let progress = self.daemon_id_map.get(&uuid).is_some_and(|addr| self.daemon_features(&addr).has(Feature::SyncProgress));
+
+            let request = (!requesters.is_empty() && progress).then(|| {
+                let request = self.next_request.fetch_add(1, Ordering::Relaxed);
+                self.sync_requests.insert(request, (requesters.clone(), uuid));
+                request
+            });
+
+            (self.send_sync(uuid, addr, request).await, request)
+        };
+
+        let res = match sent {
+            Ok(sent) => self.finish_sync(uuid, sent).await,
+            Err(e) => Err(e),
+        };
+
+        // daemons report their own failures, which already removed the request
+        if let (Err(e), Some(request)) = (&res, request)
+            && let Some((_, (requesters, _))) = self.sync_requests.remove(&request) {
+            self.finished_syncs.insert(request, (requesters.clone(), uuid, Instant::now()));
+
+            self.send_sync_step(&requesters, uuid, SyncStep::Failed {
+                error: e.clone(),
+            }, Some(request))?;
+        }
+
+        res
+    }
+
+    /// Sends the spec of a daemon, as a delta to the last one sent if possible. Should be called
+    /// with the sync lock of the daemon held, the sync is finished with `finish_sync` once it is
+    /// released.
+    async fn send_sync(&self, uuid: Uuid, addr: Option<SocketAddr>, request: Option<u64>) -> Result<Option<SentSync>, String> {
+        let addr = addr.or_else(|| self.daemon_id_map.get(&uuid).map(|a| *a));
+
+        if addr.is_none() {
+            return Ok(None);
+        }
+
+        let addr = addr.expect("addr should always exist");
+
+        let mut sync = self.spec(uuid).await?;
+        sync.request = request;
+
+        if let Some(mut status) = self.status_cache.get_mut(&uuid) {
+            status.retain_servers(&sync.servers.iter().map(|server| server.id).collect());
+        }
+
+        plugins::sync(uuid, &sync).await?;
+
+        let packet = match self.sync_cache.get(&uuid) {
+            Some(previous) if self.daemon_features(&addr).has(Feature::DeltaSync) => sync.diff(&previous).to_packet()?,
+            _ => sync.to_packet()?,
+        };
+
+        self.sync_cache.insert(uuid, sync);
+
+        match self.send_to_daemon_acked(&addr, packet) {
+            Ok(ack) => Ok(Some(SentSync {
+                addr,
+                ack,
+            })),
+            Err(e) => {
+                self.sync_cache.remove(&uuid);
+                Err(e)
+            },
+        }
+    }
+
+    /// Waits until a daemon has applied a sync sent with `send_sync`, then loads everything that
+    /// depends on its spec and updates its listens.
+    async fn finish_sync(&self, uuid: Uuid, sent: Option<SentSync>) -> Result<(), String> {
+        let Some(SentSync { addr, ack }) = sent else {
+            return Ok(());
+        };
+
+        if let Err(e) = self.wait_for_ack(ack, Duration::from_secs(CONFIG.sync.ack_timeout)).await {
+            // the daemon might not have applied the sync, so the next one can't be a delta. A
+            // delta sent meanwhile is refused by the daemon, as it is based on this sync
+            self.sync_cache.remove(&uuid);
+            return Err(e);
+        }
+
+        if CONFIG.catalog.enabled && self.daemon_features(&addr).has(Feature::Catalog) {
+            self.send_to_daemon(&addr, catalog::fetch().await?.to_packet()?)?;
+        }
+
+        alerts::load(uuid).await?;
+        automation::load(uuid).await?;
+
+        #[cfg(feature = "wasm")]
+        crate::wasm::load(uuid).await?;
+
+        self.update_listens_for_daemon(&addr, &uuid).await
+    }
+
+    /// Returns the full spec of a daemon, from the `SpecCache` if it's still valid, or from the
+    /// database otherwise.
+    async fn spec(&self, uuid: Uuid) -> Result<SDSyncPacket, String> {
+        if let Some(cached) = self.spec_cache.get(&uuid)
+            && cached.0.elapsed() < Duration::from_secs(CONFIG.sync.spec_cache_ttl) {
+            return Ok(cached.1.clone());
+        }
+
+        let generation = self.spec_generation.load(Ordering::Acquire);
+        let spec = repository::get().spec(uuid).await?;
+
+        // the spec might already be outdated if the database changed while it was being fetched
+        if CONFIG.sync.spec_cache_ttl > 0 && generation == self.spec_generation.load(Ordering::Acquire) {
+            self.spec_cache.insert(uuid, (Instant::now(), spec.clone()));
+        }
+
+        Ok(spec)
+    }
+
+    /// Drops all cached specs and teams, should be called whenever the database changes.
+    pub fn invalidate_specs(&self) {
+        self.spec_generation.fetch_add(1, Ordering::AcqRel);
+        self.spec_cache.clear();
+        self.team_cache.clear();
+    }
+
+    /// Returns the daemons of the team of a user, from the `TeamCache` if they were fetched less
+    /// than `TEAM_CACHE_TTL` ago, see `Repository::team_daemons`.
+    async fn team_daemons(&self, user_id: u32) -> Result<HashSet<Uuid>, String> {
+        if let Some(cached) = self.team_cache.get(&user_id)
+            && cached.0.elapsed() < TEAM_CACHE_TTL {
+            return Ok(cached.1.clone());
+        }
+
+        let daemons = repository::get().team_daemons(user_id).await?.into_iter().collect::<HashSet<_>>();
+        self.team_cache.insert(user_id, (Instant::now(), daemons.clone()));
+
+        Ok(daemons)
+    }
+
+    /// Records the host ports a daemon assigned to a server, so the next delta sync is based on the
+    /// same spec as the one the daemon has applied.
+    pub fn assign_sync_ports(&self, uuid: &Uuid, id: u32, ports: &[Port]) -> Result<(), String> {
+        match self.sync_cache.get_mut(uuid) {
+            Some(mut spec) => spec.assign_ports(id, ports),
+            None => Ok(()),
+        }
+    }
+
+    /// Forgets the last spec sent to a daemon and sends it a full sync, reporting its progress with
+    /// the request id of the sync it replaces.
+    pub async fn full_sync_daemon(&self, uuid: Uuid, request: Option<u64>) -> Result<(), String> {
+        let sent = {
+            let lock = self.sync_lock(uuid);
+            let _guard = lock.lock().await;
+
+            self.sync_cache.remove(&uuid);
+            self.send_sync(uuid, None, request).await?
+        };
+
+        self.finish_sync(uuid, sent).await
+    }
+
+    /// Sends a sync progress step from a daemon to the web clients that requested the sync.
+    pub fn send_sync_progress(&self, addr: &SocketAddr, progress: DSSyncProgressPacket) -> Result<(), String> {
+        let uuid = self.daemon_uuid(addr)?;
+
+        let is_final = progress.step.is_final();
+
+        let (requesters, daemon) = match is_final {
+            true => self.sync_requests.remove_if(&progress.request, |_, (_, daemon)| *daemon == uuid),
+            false => self.sync_requests.get(&progress.request).filter(|entry| entry.1 == uuid).map(|entry| (progress.request, entry.clone())),
+        }.ok_or("Unknown request id")?.1;
+
+        // kept for an error of the daemon arriving after the final step, see `send_daemon_error`
+        if is_final {
+            self.finished_syncs.insert(progress.request, (requesters.clone(), daemon, Instant::now()));
+        }
+
+        self.send_sync_step(&requesters, uuid, progress.step, Some(progress.request))
+    }
+
+    /// Passes an error reported by a daemon on to the web clients waiting for the request that
+    /// failed, or to all web clients listening to the daemon if it didn't fail for a request.
+    pub fn send_daemon_error(&self, addr: &SocketAddr, error: DSErrorPacket) -> Result<(), String> {
+        let uuid = self.daemon_uuid(addr)?;
+
+        warn!("Daemon {} reported an error: {}", uuid, error.message);
+
+        // errors of a sync are stored once its failed step arrives
+        if error.request.is_none() {
+            inbox::notify(Recipients::Team(uuid), NotificationKind::DaemonError, None, error.message.clone());
+        }
+
+        let clients: Requesters = match error.request {
+            // the error and the failed step are handled concurrently, so either can arrive first
+            Some(request) => self.sync_requests.get(&request).filter(|entry| entry.1 == uuid).map(|entry| entry.0.clone())
+                .or_else(|| self.finished_syncs.get(&request).filter(|entry| entry.1 == uuid).map(|entry| entry.0.clone()))
+                .unwrap_or_default(),
+            None => self.web_listen_map.iter()
+                .filter(|listen_map| listen_map.values().any(|daemons| daemons.contains(&uuid)))
+                .map(|listen_map| (*listen_map.key(), None))
+                .collect(),
+        };
+
+        for (client, request) in clients.iter() {
+            let packet = SWErrorPacket {
+                code: error.code,
+                message: error.message.clone(),
+                daemon: Some(uuid),
+                request: *request,
+            }.to_packet()?;
+
+            if let Err(e) = self.send_critical(client, packet) {
+                warn!("Could not send daemon error to {}: {}", client, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Sends an uploaded build context to the daemon that requested it, if one of its servers is
+    /// built from it.
+    pub async fn send_build_context(&self, addr: SocketAddr, request: DSFetchBuildContextPacket) -> Result<(), String> {
+        let uuid = self.daemon_uuid(&addr)?;
+
+        let data = builds::context(uuid, &request.hash).await?;
+
+        if data.is_none() {
+            warn!("Daemon {} requested unknown build context {}", uuid, request.hash);
+        }
+
+        self.send_to_daemon(&addr, SDBuildContextPacket {
+            hash: request.hash,
+            data: data.map(|data| BASE64.encode(data)),
+        }.to_packet()?)
+    }
+
+    /// Adds a daemon to the server.
+    pub fn add_daemon(&self, addr: SocketAddr, tx: Tx, encoding: Encoding) {
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
+        self.daemon_channel_map.insert(addr, DaemonSocket {
+            tx,
+            encoding,
+            handshake: None,
+        });
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+    }
+
+    /// Removes a daemon from the server. Should only be used in the `on_disconnect` method, see
+    /// `disconnect_daemon` for a more general use case.
+    pub async fn remove_daemon(&self, addr: SocketAddr) -> Result<(), String> {
+        // events that never arrived on the connection can't hold back anything anymore
+        self.event_reorders.remove(&addr);
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
+        let uuid = self.daemon_channel_map.get(&addr).ok_or("Daemon not found in DaemonChannelMap")?.handshake.as_ref().ok_or("Daemon hasn't authenticated")?.daemon_uuid;
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
+        self.daemon_channel_map.remove(&addr);
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+
+        // packets waiting for an acknowledgement fail once their channel is dropped
+        self.pending_acks.retain(|_, (daemon, _)| *daemon != addr);
+        self.heartbeats.remove(&addr);
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_ID_MAP", file!(), line!());
+        self.daemon_id_map.remove(&uuid);
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_ID_MAP", file!(), line!());
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_ID_MAP", file!(), line!());
+
+        self.status_cache.remove(&uuid);
+        self.daemon_windows.retain(|(daemon, _), _| *daemon != uuid);
+        self.event_sequences.retain(|(daemon, _, _), _| *daemon != uuid);
+
+        let terminals = self.terminals.iter().filter(|terminal| terminal.daemon == uuid).map(|terminal| *terminal.key()).collect::<Vec<_>>();
+        for session in terminals {
+            if let Some((_, mut terminal)) = self.terminals.remove(&session) {
+                let res = SWTerminalClosePacket {
+                    session,
+                    exit_code: None,
+                    error: Some("Daemon disconnected".to_string()),
+                    seq: Some(terminal.next_output_seq()),
+                }.to_packet().and_then(|packet| self.send_to_web(&terminal.web, packet));
+
+                if let Err(e) = res {
+                    warn!("Could not close terminal {}: {}", session, e);
+                }
+            }
+        }
+
+        self.send_event_from_server(&uuid, EventData::NodeStatus(NodeStatusEvent {
+            online: false,
+            stats: None,
+            in_maintenance: false,
+            servers: None,
+            max_servers: None,
+            info: None,
+        })).await
+    }
+
+    /// Disconnects a daemon from the server, telling it why.
+    pub fn disconnect_daemon(&self, addr: SocketAddr, reason: CloseReason) -> Result<(), String> {
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
+        server::close(&self.daemon_channel_map.get(&addr).ok_or("Client not found in channel_map")?.tx, reason);
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+
+        Ok(())
+    }
+
+    /// Returns the events the server itself needs from a daemon, regardless of web clients
+    /// listening, to store metrics and the history and to evaluate alert and automation rules.
+    fn internal_listens(&self, uuid: &Uuid) -> Vec<EventType> {
+        let fleet_summary = self.daemon_listen_map.get(uuid).is_some_and(|listen_map| listen_map.contains_key(&EventType::FleetSummary));
+
+        // the history has to be recorded for daemons nobody listens to as well, as that's what it
+        // is backfilled for
+        if CONFIG.metrics.enabled || CONFIG.history.enabled || fleet_summary {
+            return vec![EventType::NodeStatus, EventType::ServerStatus];
+        }
+
+        let mut events = alerts::required_events(uuid);
+
+        if automation::has_rules(uuid) && !events.contains(&EventType::ServerStatus) {
+            events.push(EventType::ServerStatus);
+        }
+
+        events
+    }
+
+    /// Returns whether a web client listening to `ServerStatus` events of a daemon listens to the
+    /// status of a server, see `ListenEvent::servers`.
+    fn wants_status(&self, addr: &SocketAddr, uuid: &Uuid, server: u32) -> bool {
+        self.status_listens.get(&(*addr, *uuid)).is_none_or(|servers| servers.contains(&server))
+    }
+
+    /// Returns the servers of a daemon whose status web clients listen to, or `None` if the status
+    /// of all of them is needed, e.g. to evaluate alert or automation rules.
+    fn status_servers(&self, uuid: &Uuid) -> Option<Vec<u32>> {
+        if self.internal_listens(uuid).contains(&EventType::ServerStatus) {
+            return None;
+        }
+
+        let clients = self.daemon_listen_map.get(uuid)?.get(&EventType::ServerStatus)?.clone();
+
+        let mut servers = HashSet::new();
+        for client in clients.iter() {
+            servers.extend(self.status_listens.get(&(*client, *uuid))?.iter().copied());
+        }
+
+        Some(servers.into_iter().collect())
+    }
+
+    /// Returns the servers of a daemon whose logs are followed by web clients.
+    fn log_servers(&self, uuid: &Uuid) -> Vec<u32> {
+        self.log_listens.iter().filter(|listens| listens.key().0 == *uuid).map(|listens| listens.key().1).collect()
+    }
+
+    /// Called when a daemon connects to the server to immediately send it all events that has been
+    /// listened to.
+    pub async fn update_listens_for_daemon(&self, addr: &SocketAddr, uuid: &Uuid) -> Result<(), String> {
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_CHANNEL_MAP", file!(), line!());
+        let daemon_channel_map: &DaemonChannelMap = self.daemon_channel_map.borrow();
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_CHANNEL_MAP", file!(), line!());
+        let socket = daemon_channel_map.get(addr).ok_or("Daemon not found in DaemonChannelMap")?;
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_LISTEN_MAP", file!(), line!());
+        let daemon_listen_map: &DaemonListenMap = self.daemon_listen_map.borrow();
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_LISTEN_MAP", file!(), line!());
+        let mut events = daemon_listen_map.get(uuid).map(|listen_map| listen_map.keys().copied().collect::<Vec<_>>()).unwrap_or_default();
+
+        for event in self.internal_listens(uuid) {
+            if !events.contains(&event) {
+                events.push(event);
+            }
+        }
+
+        socket.tx.unbounded_send(
+            Message::Text(
+                encryption::encrypt_packet(
+                    SDListenPacket {
+                        events,
+                        log_servers: self.log_servers(uuid),
+                        status_servers: self.status_servers(uuid),
+                    }.to_packet()?,
+                    &socket.handshake.as_ref().ok_or("Daemon hasn't requested authentication!")?.encrypter,
+                    socket.encoding
+                )?
+            )
+        ).map_err(|_| "Failed to send packet")?;
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_LISTEN_MAP", file!(), line!());
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_CHANNEL_MAP", file!(), line!());
+
+        Ok(())
+    }
+
+    /// Sends a handshake request to a web client.
+    pub fn send_web_handshake_request(&self, addr: &SocketAddr, user_id: u32, key: Arc<Vec<u8>>, features: Features) -> Result<(), String> {
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
+        let clients: &WebChannelMap = self.web_channel_map.borrow();
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
+        let mut client = clients.get_mut(addr).ok_or("Client not found in channel_map")?;
+
+        let mut challenge_bytes = [0; 256];
+        rand_bytes(&mut challenge_bytes).map_err(|_| "Could not generate challenge")?;
+        let challenge = challenge_bytes.iter().try_fold::<_, _, Result<String, String>>(String::default(), |mut s, byte| {
+            write!(s, "{:02X}", byte).map_err(|_| "could not write byte")?;
+            Ok(s)
+        })?;
+
+        client.handshake = Some(WebHandshake {
+            user_id,
+            encrypter: josekit::jwe::RSA_OAEP.encrypter_from_pem(key.as_ref()).map_err(|_| "key should be valid")?,
+            key,
+            challenge: challenge.clone(),
+            features: Features::supported().negotiate(&features),
+            authenticated: false,
+        });
+
+        client.tx.unbounded_send(
+            Message::text(
+                encryption::encrypt_packet(
+                    SWHandshakeRequestPacket {
+                        challenge
+                    }.to_packet()?,
+                    &client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.encrypter,
+                    client.encoding,
+                )?
+            )
+        ).map_err(|_| "Failed to send packet")?;
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
+
+        Ok(())
+    }
+
+    /// Authenticates a web client with the given challenge.
+    pub fn authenticate_web(&self, addr: SocketAddr, challenge: String) -> Result<(), String> {
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
+        let clients: &WebChannelMap = self.web_channel_map.borrow();
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
+        let mut client = clients.get_mut(&addr).ok_or("Client not found in channel_map")?;
+
+        if challenge != client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.challenge {
+            warn!("Failed authentication");
+            // the key may have been rotated since it was cached
+            if let Some(handshake) = client.handshake.as_ref() {
+                self.web_key_cache.remove(&handshake.user_id);
+            }
+            server::close(&client.tx, CloseReason::AuthFailed);
+            return Err("Challenge does not match".to_string());
+        }
+
+        if let Some(handshake) = client.handshake.as_mut() {
+            handshake.authenticated = true;
+        }
+
+        client.tx.unbounded_send(
+            Message::text(
+                encryption::encrypt_packet(
+                    SWAuthResponsePacket {
+                        success: true,
+                        features: client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.features.clone(),
+                    }.to_packet()?,
+                    &client.handshake.as_ref().ok_or("Client hasn't requested authentication")?.encrypter,
+                    client.encoding,
+                )?
+            )
+        ).map_err(|_| "Failed to send packet")?;
+
+        let redeliver = client.has_feature(Feature::Acks).then(|| client.handshake.as_ref().map(|handshake| handshake.user_id)).flatten();
+        drop(client);
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
+
+        if let Some(user_id) = redeliver {
+            self.redeliver_critical(&addr, user_id)?;
+        }
+
+        Ok(())
+    }
+
+    async fn fetch_group_members(group: u32) -> Result<HashSet<Uuid>, String> {
+        Ok(repository::get().group_members(group).await?.into_iter().collect())
+    }
+
+    /// Returns the members of a daemon group, from the cache if possible.
+    async fn group_members(&self, group: u32) -> Result<HashSet<Uuid>, String> {
+        if let Some(members) = self.group_member_cache.get(&group) {
+            return Ok(members.clone());
+        }
+
+        let members = Self::fetch_group_members(group).await?;
+        self.group_member_cache.insert(group, members.clone());
+
+        Ok(members)
+    }
+
+    /// Removes a daemon from the subscriptions of a web client for an event, unless another group
+    /// the client listens to for that event still contains the daemon.
+    fn remove_group_listen(&self, addr: SocketAddr, event: EventType, group: u32, daemon: Uuid) {
+        let still_listened = self.group_listen_map.get(&addr).is_some_and(|listen_map| {
+            listen_map.get(&event).is_some_and(|groups| {
+                groups.iter().any(|other| *other != group && self.group_member_cache.get(other).is_some_and(|members| members.contains(&daemon)))
+            })
+        });
+
+        if !still_listened {
+            self.remove_daemon_listen(addr, event, daemon);
+        }
+    }
+
+    /// Removes a daemon from the subscriptions of a web client for an event, along with the
+    /// servers it listens to and the window of the stream.
+    fn remove_daemon_listen(&self, addr: SocketAddr, event: EventType, daemon: Uuid) {
+        match event {
+            EventType::ServerStatus => {
+                self.status_listens.remove(&(addr, daemon));
+            },
+            EventType::ServerLog => self.log_listens.retain(|(log_daemon, _), clients| {
+                if *log_daemon == daemon {
+                    clients.remove(&addr);
+                }
+
+                !clients.is_empty()
+            }),
+            _ => (),
+        }
+
+        if let Some(mut listen_map) = self.web_listen_map.get_mut(&addr)
+            && let Some(daemons) = listen_map.get_mut(&event) {
+            daemons.remove(&daemon);
+        }
+
+        if let Some(mut listen_map) = self.daemon_listen_map.get_mut(&daemon)
+            && let Some(clients) = listen_map.get_mut(&event) {
+            clients.remove(&addr);
+
+            if clients.is_empty() {
+                listen_map.remove(&event);
+            }
+        }
+
+        self.daemon_listen_map.remove_if(&daemon, |_, listen_map| listen_map.is_empty());
+        self.stream_windows.remove(&(addr, daemon, event));
+    }
+
+    /// Reloads the members of a daemon group, applies membership changes to all web clients
+    /// listening to the group, and syncs all connected members. The result of every member is sent
+    /// back to the web client.
+    pub async fn sync_group(&self, addr: SocketAddr, group: u32) -> Result<(), String> {
+        let members = Self::fetch_group_members(group).await?;
+        let old_members = self.group_member_cache.insert(group, members.clone()).unwrap_or_default();
+
+        let added = members.difference(&old_members).copied().collect::<Vec<_>>();
+        let removed = old_members.difference(&members).copied().collect::<Vec<_>>();
+
+        let listeners = self.group_listen_map.iter().filter_map(|listen_map| {
+            let events = listen_map.iter().filter(|(_, groups)| groups.contains(&group)).map(|(event, _)| *event).collect::<Vec<_>>();
+            (!events.is_empty()).then(|| (*listen_map.key(), events))
+        }).collect::<Vec<_>>();
+
+        for (listener, events) in listeners.into_iter() {
+            if !added.is_empty() {
+                self.send_listen(listener, events.iter().map(|event| ListenEvent {
+                    event: *event,
+                    daemons: added.clone(),
+                    groups: Vec::new(),
+                    servers: Vec::new(),
+                }).collect()).await?;
+            }
+
+            for event in events.iter() {
+                for daemon in removed.iter() {
+                    self.remove_group_listen(listener, *event, group, *daemon);
+                }
+            }
+        }
+
+        for daemon in removed.iter() {
+            if let Some(daemon_addr) = self.daemon_id_map.get(daemon).map(|daemon_addr| *daemon_addr) {
+                self.update_listens_for_daemon(&daemon_addr, daemon).await?;
+            }
+        }
+
+        // daemons are synced at the same time, as each sync waits until the daemon applied it
+        let results = future::join_all(members.into_iter().map(|daemon| async move {
+            let online = self.daemon_id_map.contains_key(&daemon);
+
+            GroupSyncResult {
+                daemon,
+                online,
+                error: match online {
+                    true => self.sync_daemon(daemon, None, HashMap::from([(addr, None)])).await.err(),
+                    false => None,
+                },
+            }
+        })).await;
+
+        if let Ok(user_id) = self.web_user(&addr) {
+            let failed = results.iter().filter(|result| !result.online || result.error.is_some()).count();
+            inbox::notify(Recipients::User(user_id), NotificationKind::GroupSync, None, format!("Synced group {}: {} of {} daemons failed or were offline", group, failed, results.len()));
+        }
+
+        self.send_critical(&addr, SWSyncGroupResultPacket {
+            group,
+            results,
+        }.to_packet()?)
+    }
+
+    /// Counts the listens of all web clients matching `filter`, see `config::Listens`.
+    fn listen_count(&self, filter: impl Fn(&SocketAddr) -> bool) -> usize {
+        self.web_listen_map.iter()
+            .filter(|entry| filter(entry.key()))
+            .map(|entry| entry.value().values().map(HashSet::len).sum::<usize>())
+            .sum()
+    }
+
+    /// Checks whether adding `events` to the listens of a web client stays within its listen
+    /// quotas, returning a description of the exceeded quota otherwise.
+    fn check_listen_quota(&self, addr: SocketAddr, events: &[ListenEvent]) -> Result<(), String> {
+        let added = {
+            let existing = self.web_listen_map.get(&addr);
+
+            events.iter()
+                .flat_map(|event| event.daemons.iter().map(|daemon| (event.event, *daemon)))
+                .filter(|(event, daemon)| !existing.as_ref().and_then(|map| map.get(event)).is_some_and(|daemons| daemons.contains(daemon)))
+                .collect::<HashSet<_>>()
+                .len()
+        };
+
+        if added == 0 {
+            return Ok(());
+        }
+
+        let max = CONFIG.listens.max_per_socket;
+        if max > 0 {
+            let count = self.listen_count(|a| *a == addr) + added;
+
+            if count > max {
+                return Err(format!("Listening to {} events would exceed the limit of {} per connection", count, max));
+            }
+        }
+
+        let max = CONFIG.listens.max_per_user;
+        let user_id = self.web_channel_map.get(&addr).and_then(|socket| socket.handshake.as_ref().map(|handshake| handshake.user_id));
+
+        if max > 0 && let Some(user_id) = user_id {
+            let sockets = self.web_channel_map.iter()
+                .filter(|socket| socket.handshake.as_ref().is_some_and(|handshake| handshake.user_id == user_id))
+                .map(|socket| *socket.key())
+                .collect::<HashSet<_>>();
+
+            let count = self.listen_count(|a| sockets.contains(a)) + added;
+
+            if count > max {
+                return Err(format!("Listening to {} events would exceed the limit of {} per user", count, max));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Forwards a listen event to all daemons required from a web client. Listens exceeding the
+    /// listen quotas are rejected as a whole.
+    pub async fn send_listen(&self, addr: SocketAddr, mut events: Vec<ListenEvent>) -> Result<(), String> {
+        let mut update_daemons = HashSet::new();
+        let mut offline_daemons = HashSet::new();
+        let mut streams = HashSet::new();
+        let mut statuses = Vec::new();
+
+        for event in events.iter_mut() {
+            for group in event.groups.iter() {
+                let members = self.group_members(*group).await?;
+                for member in members.into_iter() {
+                    if !event.daemons.contains(&member) {
+                        event.daemons.push(member);
+                    }
+                }
+            }
+        }
+
+        if let Err(e) = self.check_listen_quota(addr, &events) {
+            warn!("Rejected listen from {}: {}", addr, e);
+            telemetry::LISTEN_QUOTA_EXCEEDED.fetch_add(1, Ordering::Relaxed);
+
+            return self.send_to_web(&addr, SWErrorPacket {
+                code: ErrorCode::ListenQuotaExceeded,
+                message: e,
+                daemon: None,
+                request: None,
+            }.to_packet()?);
+        }
+
+        for event in events.iter() {
+            for group in event.groups.iter() {
+                self.group_listen_map.entry(addr).or_default().entry(event.event).or_default().insert(*group);
+            }
+        }
+
+        for event in events.iter().filter(|event| event.event == EventType::ServerStatus) {
+            for daemon in event.daemons.iter() {
+                let listening = self.web_listen_map.get(&addr).is_some_and(|listen_map| listen_map.get(&EventType::ServerStatus).is_some_and(|daemons| daemons.contains(daemon)));
+
+                if event.servers.is_empty() {
+                    self.status_listens.remove(&(addr, *daemon));
+                } else if !listening || self.status_listens.contains_key(&(addr, *daemon)) {
+                    // listening to some servers doesn't narrow down a listen to all of them
+                    self.status_listens.entry((addr, *daemon)).or_default().extend(event.servers.iter().copied());
+                }
+            }
+        }
+
+        // the logs of servers are only followed on the daemons of the user's team
+        let team = match events.iter().any(|event| event.event == EventType::ServerLog) {
+            true => self.team_daemons(self.web_user(&addr)?).await?,
+            false => HashSet::new(),
+        };
+
+        let mut followed = Vec::new();
+        for event in events.iter_mut().filter(|event| event.event == EventType::ServerLog) {
+            event.daemons.retain(|daemon| {
+                let allowed = team.contains(daemon);
+                if !allowed {
+                    warn!("Web client {} may not follow the logs of node {}", addr, daemon);
+                }
+                allowed
+            });
+
+            for daemon in event.daemons.iter() {
+                for server in event.servers.iter() {
+                    if self.log_listens.entry((*daemon, *server)).or_default().insert(addr) {
+                        followed.push((*daemon, *server));
+                    }
+                }
+            }
+        }
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting DAEMON_ID_MAP", file!(), line!());
+        let daemon_id_map: &DaemonIDMap = self.daemon_id_map.borrow();
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got DAEMON_ID_MAP", file!(), line!());
+
+        {
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] awaiting WEB_LISTEN_MAP", file!(), line!());
+            let web_listen_map: &WebListenMap = self.web_listen_map.borrow();
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] got WEB_LISTEN_MAP", file!(), line!());
+
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] awaiting DAEMON_LISTEN_MAP", file!(), line!());
+            let daemon_listen_map: &DaemonListenMap = self.daemon_listen_map.borrow();
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] got DAEMON_LISTEN_MAP", file!(), line!());
+
+            for event in events.into_iter() {
+                for daemon in event.daemons.iter() {
+                    update_daemons.insert(*daemon);
+
+                    if event.event.is_streamed() {
+                        streams.insert((*daemon, event.event));
+                    }
+
+                    if matches!(event.event, EventType::NodeStatus | EventType::ServerStatus) {
+                        statuses.push((*daemon, event.event));
+                    }
+
+                    if let Some(mut listen_map) = daemon_listen_map.get_mut(daemon) {
+                        if let Some(client_set) = listen_map.get_mut(&event.event) {
+                            client_set.insert(addr);
+                        } else {
+                            listen_map.insert(event.event, HashSet::from([addr]));
+                        }
+                    } else {
+                        let mut set = HashSet::new();
+                        set.insert(addr);
+                        let mut listen_map = HashMap::new();
+                        listen_map.insert(event.event, set);
+                        daemon_listen_map.insert(*daemon, listen_map);
+                    }
+
+                    if event.event == EventType::NodeStatus && daemon_id_map.get(daemon).is_none() {
+                        offline_daemons.insert(*daemon);
+                    }
+                }
+
+                if let Some(mut listen_map) = web_listen_map.get_mut(&addr) {
+                    if let Some(daemon_set) = listen_map.get_mut(&event.event) {
+                        for daemon in event.daemons.iter() {
+                            daemon_set.insert(*daemon);
+                        }
+                    } else {
+                        listen_map.insert(event.event, HashSet::from_iter(event.daemons.into_iter()));
+                    }
+                } else {
+                    web_listen_map.insert(addr, HashMap::from([(event.event, HashSet::from_iter(event.daemons.into_iter()))]));
+                }
+            }
+
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] dropped DAEMON_LISTEN_MAP", file!(), line!());
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] dropped WEB_LISTEN_MAP", file!(), line!());
+        }
+
+        let mut histories = self.fetch_status_history(&statuses).await;
+
+        // sent before the status of offline daemons, which is newer than their history
+        for (daemon, event_type) in statuses.into_iter() {
+            self.send_status_history(addr, daemon, event_type, histories.remove(&(daemon, event_type)).unwrap_or_default())?;
+        }
+
+        for daemon in offline_daemons.into_iter() {
+            self.send_event_from_server(&daemon, EventData::NodeStatus(NodeStatusEvent {
+                online: false,
+                stats: None,
+                in_maintenance: false,
+                servers: None,
+                max_servers: None,
+                info: None,
+            })).await?;
+        }
+
+        for daemon in update_daemons.into_iter() {
+            if let Some(daemon_addr) = daemon_id_map.get(&daemon) {
+                self.update_listens_for_daemon(&daemon_addr, &daemon).await?;
+            }
+        }
+
+        // the daemon may have run out of credits for a slower web client
+        for (daemon, event) in streams.into_iter() {
+            self.replenish_daemon_window(&daemon, event)?;
+        }
+
+        // lines written before the server was followed are sent like the answer to a logs query
+        for (daemon, server) in followed.into_iter() {
+            if CONFIG.listens.log_backfill > 0 && daemon_id_map.contains_key(&daemon) {
+                self.query_logs(addr, WSQueryLogsPacket {
+                    daemon,
+                    server,
+                    lines: CONFIG.listens.log_backfill,
+                }).await?;
+            }
+        }
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped DAEMON_ID_MAP", file!(), line!());
+
+        Ok(())
+    }
+
+    /// Removes subscriptions of a web client, and updates the listens of the daemons affected, see
+    /// `WSUnlistenPacket`.
+    pub async fn remove_listen(&self, addr: SocketAddr, mut events: Vec<ListenEvent>) -> Result<(), String> {
+        let mut update_daemons = HashSet::new();
+
+        for event in events.iter_mut() {
+            if let Some(mut listen_map) = self.group_listen_map.get_mut(&addr)
+                && let Some(groups) = listen_map.get_mut(&event.event) {
+                for group in event.groups.iter() {
+                    groups.remove(group);
+                }
+            }
+
+            // members of the remaining groups stay subscribed to
+            let still_listened = |daemon: &Uuid| self.group_listen_map.get(&addr).is_some_and(|listen_map| {
+                listen_map.get(&event.event).is_some_and(|groups| {
+                    groups.iter().any(|group| self.group_member_cache.get(group).is_some_and(|members| members.contains(daemon)))
+                })
+            });
+
+            let mut members = Vec::new();
+            for group in event.groups.iter() {
+                members.extend(self.group_members(*group).await?.into_iter().filter(|member| !still_listened(member)));
+            }
+
+            for member in members.into_iter() {
+                if !event.daemons.contains(&member) {
+                    event.daemons.push(member);
+                }
+            }
+        }
+
+        for event in events.iter() {
+            for daemon in event.daemons.iter() {
+                let listening = self.web_listen_map.get(&addr).is_some_and(|listen_map| listen_map.get(&event.event).is_some_and(|daemons| daemons.contains(daemon)));
+
+                if !listening {
+                    continue;
+                }
+
+                let servers_left = match event.event {
+                    EventType::ServerStatus if !event.servers.is_empty() => match self.status_listens.get_mut(&(addr, *daemon)) {
+                        Some(mut servers) => {
+                            servers.retain(|server| !event.servers.contains(server));
+                            !servers.is_empty()
+                        },
+                        // the status of all servers is only unsubscribed from as a whole
+                        None => continue,
+                    },
+                    EventType::ServerLog => {
+                        self.log_listens.retain(|(log_daemon, server), clients| {
+                            if log_daemon == daemon && (event.servers.is_empty() || event.servers.contains(server)) {
+                                clients.remove(&addr);
+                            }
+
+                            !clients.is_empty()
+                        });
+
+                        self.log_listens.iter().any(|listens| listens.key().0 == *daemon && listens.value().contains(&addr))
+                    },
+                    _ => false,
+                };
+
+                update_daemons.insert(*daemon);
+
+                if !servers_left {
+                    self.remove_daemon_listen(addr, event.event, *daemon);
+                }
+            }
+        }
+
+        for daemon in update_daemons.into_iter() {
+            let daemon_addr = self.daemon_id_map.get(&daemon).map(|daemon_addr| *daemon_addr);
+
+            if let Some(daemon_addr) = daemon_addr {
+                self.update_listens_for_daemon(&daemon_addr, &daemon).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Adds a web client to the server.
+    pub fn add_web(&self, addr: SocketAddr, tx: Tx, encoding: Encoding) {
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
+
+        self.web_channel_map.insert(addr, WebSocket {
+            tx,
+            encoding,
+            handshake: None,
+        });
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
+    }
+
+    /// Removes a web client from the server. Should only be used in the `on_disconnect` method,
+    /// see `disconnect_web` for a more general use case.
+    pub async fn remove_web(&self, addr: SocketAddr) -> Result<(), String> {
+        let mut update_daemons = HashSet::new();
+
+        let orphaned_by = {
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] awaiting WEB_LISTEN_MAP", file!(), line!());
+            let web_listen_map: &WebListenMap = self.web_listen_map.borrow();
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] got WEB_LISTEN_MAP", file!(), line!());
+
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] awaiting DAEMON_LISTEN_MAP", file!(), line!());
+            let daemon_listen_map: &DaemonListenMap = self.daemon_listen_map.borrow();
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] got DAEMON_LISTEN_MAP", file!(), line!());
+
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
+            let web_channel_map: &WebChannelMap = self.web_channel_map.borrow();
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
+
+            // the results of syncs the client was waiting for are still delivered to its user
+            let orphaned_by = web_channel_map.remove(&addr)
+                .and_then(|(_, socket)| socket.has_feature(Feature::Acks).then(|| socket.handshake.map(|handshake| handshake.user_id)).flatten());
+
+            self.pending_queries.retain(|_, (web_addr, _, _)| *web_addr != addr);
+            self.heartbeats.remove(&addr);
+            self.sync_requests.iter_mut().for_each(|mut request| {
+                if request.0.remove(&addr).is_some()
+                    && let Some(user_id) = orphaned_by {
+                    self.sync_orphans.entry(*request.key()).or_default().insert(user_id);
+                }
+            });
+            self.finished_syncs.iter_mut().for_each(|mut finished| {
+                finished.0.remove(&addr);
+            });
+            self.sync_debounce.iter_mut().for_each(|mut debounce| {
+                debounce.waiting.remove(&addr);
+            });
+            self.sync_rate_limits.remove(&addr);
+            self.packet_rate_limits.retain(|(web_addr, _), _| *web_addr != addr);
+            self.stream_windows.retain(|(web_addr, _, _), _| *web_addr != addr);
+            self.group_listen_map.remove(&addr);
+            self.log_listens.retain(|_, clients| {
+                clients.remove(&addr);
+                !clients.is_empty()
+            });
+            self.status_listens.retain(|(web_addr, _), _| *web_addr != addr);
+            if let Some((_, listen_map)) = web_listen_map.remove(&addr) {
+                for (event, daemons) in listen_map.iter() {
+                    for daemon in daemons.iter() {
+                        update_daemons.insert(*daemon);
+
+                        {
+                            let mut listen_map = daemon_listen_map.get_mut(daemon).ok_or("daemon not found in DaemonListenMap")?;
+                            let event_map = listen_map.get_mut(event).ok_or("event not found in DaemonListenMap")?;
+
+                            event_map.remove(&addr);
+
+                            if event_map.is_empty() {
+                                listen_map.remove(event);
+                            }
+                        }
+
+                        daemon_listen_map.remove_if(daemon, |_, listen_map| listen_map.is_empty());
+                    }
+                }
+            }
+
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] dropped DAEMON_LISTEN_MAP", file!(), line!());
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] dropped WEB_LISTEN_MAP", file!(), line!());
+
+            orphaned_by
+        };
+
+        // the packets the client didn't acknowledge are handled by another client of its user
+        if let Some(user_id) = orphaned_by
+            && let Some(client) = self.acking_client(user_id) {
+            self.redeliver_critical(&client, user_id)?;
+        }
+
+        // the processes of terminals are killed, instead of running on without anyone attached
+        let terminals = self.terminals.iter().filter(|terminal| terminal.web == addr).map(|terminal| *terminal.key()).collect::<Vec<_>>();
+        for session in terminals {
+            if let Some((_, terminal)) = self.terminals.remove(&session)
+                && let Err(e) = self.close_daemon_terminal(session, terminal.daemon) {
+                warn!("Could not close terminal {}: {}", session, e);
+            }
+        }
+
+        for daemon in update_daemons {
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] awaiting DAEMON_ID_MAP", file!(), line!());
+            if let Some(daemon_addr) = self.daemon_id_map.get(&daemon) {
+                self.update_listens_for_daemon(&daemon_addr, &daemon).await?;
+            }
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] got DAEMON_ID_MAP", file!(), line!());
+            #[cfg(feature = "lock_debug")]
+            debug!("[{}:{}] dropped DAEMON_ID_MAP", file!(), line!());
+        }
+
+        Ok(())
+    }
+
+    /// Removes empty entries from the listen maps, and entries of the query and sync maps that are
+    /// no longer needed, which are left behind as web clients and daemons come and go. Returns the
+    /// amount of removed entries.
+    pub fn compact(&self) -> usize {
+        let mut removed = compact_listen_map(&self.web_listen_map) + compact_listen_map(&self.daemon_listen_map) + compact_listen_map(&self.group_listen_map);
+
+        let mut count = |before: usize, after: usize| removed += before - after;
+
+        let before = self.pending_queries.len();
+        self.pending_queries.retain(|_, (_, daemon, sent)| self.daemon_id_map.contains_key(daemon) && sent.elapsed() < QUERY_TTL);
+        count(before, self.pending_queries.len());
+
+        let before = self.pending_commands.len();
+        self.pending_commands.retain(|_, (_, _, daemon, sent)| self.daemon_id_map.contains_key(daemon) && sent.elapsed() < QUERY_TTL);
+        count(before, self.pending_commands.len());
+
+        count(automation::forget_commands(|daemon| self.daemon_id_map.contains_key(daemon)), 0);
+
+        let before = self.sync_requests.len();
+        self.sync_requests.retain(|request, (requesters, daemon)| (!requesters.is_empty() || self.sync_orphans.contains_key(request)) && self.daemon_id_map.contains_key(daemon));
+        count(before, self.sync_requests.len());
+
+        let before = self.team_cache.len();
+        self.team_cache.retain(|_, (fetched, _)| fetched.elapsed() < TEAM_CACHE_TTL);
+        count(before, self.team_cache.len());
+
+        let before = self.finished_syncs.len();
+        self.finished_syncs.retain(|_, (requesters, _, finished)| !requesters.is_empty() && finished.elapsed() < QUERY_TTL);
+        count(before, self.finished_syncs.len());
+
+        // collected first, as `remove_web` locks `sync_requests` before `sync_orphans`
+        let orphaned = self.sync_orphans.iter().map(|orphans| *orphans.key()).collect::<Vec<_>>();
+        for request in orphaned.iter().filter(|request| !self.sync_requests.contains_key(request)) {
+            count(usize::from(self.sync_orphans.remove(request).is_some()), 0);
+        }
+
+        let ttl = Duration::from_secs(CONFIG.outbox.ttl);
+        for mut outbox in self.outbox.iter_mut() {
+            let before = outbox.len();
+            outbox.retain(|critical| critical.queued_at.elapsed() < ttl);
+            count(before, outbox.len());
+        }
+        self.outbox.retain(|_, outbox| !outbox.is_empty());
+
+        let debounce = Duration::from_secs(CONFIG.sync.debounce);
+        let before = self.sync_debounce.len();
+        self.sync_debounce.retain(|_, state| state.scheduled || !state.waiting.is_empty() || state.last.is_some_and(|last| last.elapsed() < debounce));
+        count(before, self.sync_debounce.len());
+
+        let before = self.sync_rate_limits.len();
+        self.sync_rate_limits.retain(|_, requests| requests.back().is_some_and(|request| request.elapsed() < SYNC_RATE_LIMIT_WINDOW));
+        count(before, self.sync_rate_limits.len());
+
+        let before = self.packet_rate_limits.len();
+        self.packet_rate_limits.retain(|(_, class), bucket| !bucket.is_full(class.limit()));
+        count(before, self.packet_rate_limits.len());
+
+        // locks that are not held or waited for are only referenced by the map itself
+        let before = self.sync_locks.len();
+        self.sync_locks.retain(|_, lock| Arc::strong_count(lock) > 1);
+        count(before, self.sync_locks.len());
+
+        removed
+    }
+
+    /// Returns the amount of entries in each map of the state, to make leaks visible.
+    pub fn map_sizes(&self) -> Vec<(&'static str, usize)> {
+        vec![
+            ("web_channels", self.web_channel_map.len()),
+            ("daemon_channels", self.daemon_channel_map.len()),
+            ("web_listens", self.web_listen_map.len()),
+            ("daemon_listens", self.daemon_listen_map.len()),
+            ("group_listens", self.group_listen_map.len()),
+            ("log_listens", self.log_listens.len()),
+            ("status_listens", self.status_listens.len()),
+            ("terminals", self.terminals.len()),
+            ("pending_queries", self.pending_queries.len()),
+            ("pending_commands", self.pending_commands.len()),
+            ("pending_acks", self.pending_acks.len()),
+            ("heartbeats", self.heartbeats.len()),
+            ("status_cache", self.status_cache.len()),
+            ("sync_cache", self.sync_cache.len()),
+            ("sync_locks", self.sync_locks.len()),
+            ("spec_cache", self.spec_cache.len()),
+            ("team_cache", self.team_cache.len()),
+            ("sync_requests", self.sync_requests.len()),
+            ("finished_syncs", self.finished_syncs.len()),
+            ("sync_orphans", self.sync_orphans.len()),
+            ("outbox", self.outbox.len()),
+            ("sync_debounce", self.sync_debounce.len()),
+            ("sync_rate_limits", self.sync_rate_limits.len()),
+            ("packet_rate_limits", self.packet_rate_limits.len()),
+            ("stream_windows", self.stream_windows.len()),
+            ("daemon_windows", self.daemon_windows.len()),
+            ("event_sequences", self.event_sequences.len()),
+            ("event_reorders", self.event_reorders.len()),
+        ]
+    }
+
+    /// Disconnects a web client from the server, telling it why.
+    pub fn disconnect_web(&self, addr: SocketAddr, reason: CloseReason) -> Result<(), String> {
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
+        server::close(&self.web_channel_map.get(&addr).ok_or("Client not found in channel_map")?.tx, reason);
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
+
+        #[cfg(feature = "lock_debug")]
+        debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
+
+        Ok(())
+    }
+
+    /// Tells all authenticated daemons to reconnect to another server.
+    pub fn redirect_daemons(&self, url: &str) {
+        for daemon in self.daemon_id_map.iter() {
+            let res = SDReconnectToPacket {
+                url: url.to_string(),
+            }.to_packet().and_then(|packet| self.send_to_daemon(daemon.value(), packet));
+
+            if let Err(e) = res {
+                warn!("Could not redirect daemon {}: {}", daemon.key(), e);
+            }
+        }
+    }
+
+    /// Disconnects all daemons and web clients from the server, telling them why.
+    pub fn disconnect_all(&self, reason: CloseReason) {
+        for client in self.daemon_channel_map.iter() {
+            server::close(&client.tx, reason);
+        }
+
+        for client in self.web_channel_map.iter() {
+            server::close(&client.tx, reason);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{pin::Pin, str::FromStr};
+
+    use futures_util::StreamExt;
+    use josekit::jwe::alg::rsaes::RsaesJweDecrypter;
+    use josekit::jwk;
+    use mpsc::unbounded;
+    use packet::{command::ServerCommand, events::{CpuConvention, ServerLogEvent, UpdatePhase, UpdatePhaseEvent}, terminal::TerminalSize, ID};
+
+    use super::*;
+
+    /// The keys of a test client: the public key the server encrypts its packets with, and the
+    /// decrypter for reading them.
+    struct Keys {
+        public: Arc<Vec<u8>>,
+        decrypter: RsaesJweDecrypter,
+    }
+
+    fn keygen() -> Keys {
+        let keys = jwk::alg::rsa::RsaKeyPair::generate(2048).expect("could not create keys");
+
+        Keys {
+            public: Arc::new(keys.to_pem_public_key()),
+            decrypter: josekit::jwe::RSA_OAEP.decrypter_from_pem(keys.to_pem_private_key()).expect("could not create decrypter"),
+        }
+    }
+
+    /// Decrypts a message sent by the server.
+    async fn decrypt(msg: Message, keys: &Keys) -> Packet {
+        let msg = msg.into_text().expect("message is not text");
+        encryption::decrypt_packet(&msg, &keys.decrypter, std::slice::from_ref(&CONFIG.issuers.server), None::<fn() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>>>).await.expect("could not decrypt packet")
+    }
+
+    /// Receives and decrypts the next packet sent to a test client.
+    async fn receive(rx: &mut Rx, keys: &Keys) -> Packet {
+        decrypt(rx.next().await.expect("could not get message"), keys).await
+    }
+
+    /// Connects a web client of user 1 on a port, and returns the handshake request it was sent.
+    async fn handshake(state: &State, port: u16, keys: &Keys, features: Features) -> (SocketAddr, Rx, SWHandshakeRequestPacket) {
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let (tx, mut rx) = unbounded();
+
+        state.add_web(addr, tx, Encoding::Json);
+        state.send_web_handshake_request(&addr, 1, Arc::clone(&keys.public), features).expect("could not send web handshake request");
+
+        let handshake_request = SWHandshakeRequestPacket::parse(receive(&mut rx, keys).await).expect("could not parse packet");
+
+        (addr, rx, handshake_request)
+    }
+
+    /// Connects a web client of user 1 on a port, which has requested authentication.
+    async fn add_web(state: &State, port: u16, keys: &Keys, features: Features) -> (SocketAddr, Rx) {
+        let (addr, rx, _) = handshake(state, port, keys, features).await;
+        (addr, rx)
+    }
+
+    /// Connects a web client of user 1 on a port, which has answered the challenge, leaving nothing
+    /// in its channel.
+    async fn add_authenticated_web(state: &State, port: u16, keys: &Keys, features: Features) -> (SocketAddr, Rx) {
+        let (addr, mut rx, handshake_request) = handshake(state, port, keys, features).await;
+        state.authenticate_web(addr, handshake_request.challenge).expect("could not authenticate");
+
+        while let Ok(Some(_)) = rx.try_next() {}
+
+        (addr, rx)
+    }
+
+    /// The auth packet of a daemon without a previous sync.
+    fn auth(uuid: Uuid, features: Features) -> DSAuthPacket {
+        DSAuthPacket {
+            daemon_uuid: uuid.to_string(),
+            sync_hash: String::new(),
+            features,
+            version: String::new(),
+        }
+    }
+
+    /// Connects and authenticates a daemon on a port, leaving nothing in its channel.
+    async fn add_daemon(state: &State, port: u16, uuid: Uuid, keys: &Keys, features: Features) -> (SocketAddr, Rx) {
+        let addr = SocketAddr::from(([127, 0, 0, 1], port));
+        let (tx, mut rx) = unbounded();
+
+        state.add_daemon(addr, tx, Encoding::Json);
+        state.send_daemon_handshake_request(addr, uuid, Arc::clone(&keys.public), auth(uuid, features), None, None).await.expect("could not send daemon handshake request");
+
+        let handshake_request = SDHandshakeRequestPacket::parse(receive(&mut rx, keys).await).expect("could not parse packet");
+        state.authenticate_daemon(addr, handshake_request.challenge).expect("could not authenticate");
+
+        while let Ok(Some(_)) = rx.try_next() {}
+
+        (addr, rx)
+    }
+
+    /// Sets the daemons of the team of user 1, as if they were fetched from the database.
+    fn join_team(state: &State, daemons: &[Uuid]) {
+        state.team_cache.insert(1, (Instant::now(), daemons.iter().copied().collect()));
+    }
+
+    /// Listens for an event type of a daemon, for all of its servers if `servers` is empty.
+    fn listen(event: EventType, daemon: Uuid, servers: Vec<u32>) -> ListenEvent {
+        ListenEvent {
+            event,
+            daemons: vec![daemon],
+            groups: Vec::new(),
+            servers,
+        }
+    }
+
+    /// The status of a healthy server.
+    fn server_status(server: u32) -> ServerStatusEvent {
+        ServerStatusEvent {
+            server,
+            status: ServerStatusType::Healthy,
+            memory: None,
+            cpu: None,
+            storage: None,
+            in_maintenance: false,
+            reason: None,
+            started_at: None,
+            restart_count: 0,
+            cpu_convention: CpuConvention::default(),
+            cores: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn encryption_decryption() {
+        let state = Arc::new(State::new());
+
+        let web_addr_1 = SocketAddr::from(([127, 0, 0, 1], 30001));
+        let (web_tx_1, mut web_rx_1) = unbounded();
+
+        let web_keys_1 = jwk::alg::rsa::RsaKeyPair::generate(2048).expect("could not create keys");
+        let web_public_1 = Arc::new(web_keys_1.to_pem_public_key());
+
+        let web_private_1 = Arc::new(web_keys_1.to_pem_private_key());
+        let decrypter = josekit::jwe::RSA_OAEP.decrypter_from_pem(web_private_1.as_ref()).expect("could not create decrypter");
+
+        state.add_web(web_addr_1, web_tx_1, Encoding::Json);
+        state.send_web_handshake_request(&web_addr_1, 1, web_public_1, Features::default()).expect("could not send web handshake request");
+
+        let handshake_request = web_rx_1.next().await.expect("could not get message");
+        let message = handshake_request.into_text().expect("message is not text");
+
+        let packet = encryption::decrypt_packet(&message, &decrypter, &["aesterisk/server".to_string()], None::<fn() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>>>).await.expect("could not decrypt packet");
+
+        assert_eq!(packet.id, ID::SWHandshakeRequest);
+    }
+
+    #[tokio::test]
+    async fn encryption_decryption_msgpack() {
+        let state = State::new();
+        let keys = keygen();
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 30001));
+        let (tx, mut rx) = unbounded();
+
+        state.add_web(addr, tx, Encoding::MessagePack);
+        state.send_web_handshake_request(&addr, 1, Arc::clone(&keys.public), Features::default()).expect("could not send web handshake request");
+
+        assert_eq!(receive(&mut rx, &keys).await.id, ID::SWHandshakeRequest);
+    }
+
+    #[tokio::test]
+    async fn event_encrypted_once_per_user() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let mut receivers = Vec::new();
+
+        for port in [33001, 33002] {
+            let (addr, rx) = add_web(&state, port, &keys, Features::default()).await;
+            state.send_listen(addr, vec![listen(EventType::UpdateRequired, daemon, Vec::new())]).await.expect("could not listen");
+
+            receivers.push(rx);
+        }
+
+        state.deliver_event(&daemon, EventData::UpdateRequired(UpdateRequiredEvent {
+            version: String::new(),
+            min_version: String::new(),
+            max_version: String::new(),
+            refused: false,
+        }), None, None).expect("could not deliver event");
+
+        let mut messages = Vec::new();
+        for rx in receivers.iter_mut() {
+            messages.push(rx.next().await.expect("could not get message"));
+        }
+
+        assert_eq!(messages[0], messages[1]);
+        assert_eq!(decrypt(messages.remove(0), &keys).await.id, ID::SWEvent);
+    }
+
+    #[tokio::test]
+    async fn events_of_daemon_delivered_in_order() {
+        let state = Arc::new(State::new());
+        state.start_fanout();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (addr, mut rx) = add_web(&state, 33003, &keys, Features::default()).await;
+
+        state.send_listen(addr, vec![listen(EventType::UpdateRequired, daemon, Vec::new())]).await.expect("could not listen");
+
+        let versions = (0..10).map(|i| format!("0.{}.0", i)).collect::<Vec<_>>();
+
+        for version in versions.iter() {
+            state.send_event_from_server(&daemon, EventData::UpdateRequired(UpdateRequiredEvent {
+                version: version.clone(),
+                min_version: String::new(),
+                max_version: String::new(),
+                refused: false,
+            })).await.expect("could not send event");
+        }
+
+        for version in versions.iter() {
+            match SWEventPacket::parse(receive(&mut rx, &keys).await).expect("could not parse event packet").event {
+                EventData::UpdateRequired(event) => assert_eq!(&event.version, version),
+                _ => panic!("unexpected event"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn event_from_server_delivered_before_returning() {
+        let state = Arc::new(State::new());
+        state.start_fanout();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (addr, mut rx) = add_web(&state, 33008, &keys, Features::default()).await;
+
+        state.send_listen(addr, vec![listen(EventType::UpdateRequired, daemon, Vec::new())]).await.expect("could not listen");
+
+        state.send_event_from_server(&daemon, EventData::UpdateRequired(UpdateRequiredEvent {
+            version: String::new(),
+            min_version: String::new(),
+            max_version: String::new(),
+            refused: false,
+        })).await.expect("could not send event");
+
+        let msg = rx.try_next().expect("event was not delivered yet").expect("could not get message");
+        assert_eq!(decrypt(msg, &keys).await.id, ID::SWEvent);
+    }
+
+    #[tokio::test]
+    async fn snapshot_keeps_sequence_numbers() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (addr, mut rx) = add_web(&state, 33004, &keys, Features::default()).await;
+
+        state.send_listen(addr, vec![listen(EventType::ServerStatus, daemon, Vec::new())]).await.expect("could not listen");
+
+        state.process_event(&daemon, EventData::ServerStatus(server_status(1)), Some(5), None).await.expect("could not process event");
+        state.send_snapshot(addr, WSQuerySnapshotPacket {
+            daemon,
+        }).expect("could not send snapshot");
+
+        for _ in 0..2 {
+            let event = SWEventPacket::parse(receive(&mut rx, &keys).await).expect("could not parse event packet");
+
+            assert_eq!(event.seq, Some(5));
+            assert!(matches!(event.event, EventData::ServerStatus(ServerStatusEvent { server: 1, .. })));
+        }
+    }
+
+    #[tokio::test]
+    async fn latest_status_sent_on_listen() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (addr, mut rx) = add_web(&state, 33009, &keys, Features::default()).await;
+
+        state.process_event(&daemon, EventData::ServerStatus(server_status(1)), Some(7), Some(1000)).await.expect("could not process event");
+        state.send_listen(addr, vec![listen(EventType::ServerStatus, daemon, Vec::new())]).await.expect("could not listen");
+
+        let event = SWEventPacket::parse(receive(&mut rx, &keys).await).expect("could not parse event packet");
+
+        assert_eq!(event.seq, Some(7));
+        assert_eq!(event.timestamp, Some(1000));
+        assert!(matches!(event.event, EventData::ServerStatus(ServerStatusEvent { server: 1, .. })));
+    }
+
+    #[test]
+    fn status_of_removed_servers_pruned() {
+        let mut status = DaemonStatus::default();
+
+        for server in 1..=3 {
+            status.servers.insert(server, ServerStatusType::Healthy);
+            status.server_events.insert(server, (server_status(server), None, None));
+        }
+
+        status.retain_servers(&HashSet::from([1, 3]));
+
+        assert_eq!(status.servers.keys().copied().collect::<HashSet<_>>(), HashSet::from([1, 3]));
+        assert_eq!(status.server_events.keys().copied().collect::<HashSet<_>>(), HashSet::from([1, 3]));
+    }
+
+    #[test]
+    fn stale_snapshots_dropped() {
+        let state = State::new();
+        let daemon = Uuid::from_u128(1);
+        let status = |server| EventData::ServerStatus(server_status(server));
+
+        assert!(!state.is_stale(&daemon, &status(1), Some(2)));
+        assert!(state.is_stale(&daemon, &status(1), Some(2)));
+        assert!(state.is_stale(&daemon, &status(1), Some(1)));
+        assert!(!state.is_stale(&daemon, &status(1), Some(3)));
+
+        // sequence numbers are counted per server, and events without one are always delivered
+        assert!(!state.is_stale(&daemon, &status(2), Some(1)));
+        assert!(!state.is_stale(&daemon, &status(1), None));
+
+        // other events are reordered rather than dropped
+        let log = EventData::ServerLog(ServerLogEvent {
+            server: 1,
+            lines: Vec::new(),
+            skipped: 0,
+        });
+        assert!(!state.is_stale(&daemon, &log, Some(1)));
+    }
+
+    #[tokio::test]
+    async fn stale_connections_reaped() {
+        let state = State::new();
+        let keys = keygen();
+
+        let (addr, mut rx) = add_web(&state, 33005, &keys, Features::from([Feature::Heartbeats])).await;
+
+        state.ping_web(&addr).expect("could not ping");
+        let ping = PingPacket::parse(receive(&mut rx, &keys).await).expect("could not parse ping packet");
+
+        // only one ping is outstanding at a time
+        state.ping_web(&addr).expect("could not ping");
+        assert!(rx.try_next().is_err());
+
+        assert!(state.receive_pong(&addr, PongPacket {
+            nonce: ping.nonce + 1,
+        }).is_err());
+        state.receive_pong(&addr, PongPacket {
+            nonce: ping.nonce,
+        }).expect("could not receive pong");
+        assert!(state.receive_pong(&addr, PongPacket {
+            nonce: ping.nonce,
+        }).is_err());
+        assert_eq!(state.reap_stale(Duration::from_secs(60)), 0);
+
+        assert_eq!(state.reap_stale(Duration::ZERO), 1);
+        assert!(matches!(rx.next().await, Some(Message::Close(_))));
+        assert!(state.ping_web(&addr).is_err());
+        assert_eq!(state.heartbeats.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn critical_packets_redelivered_until_acknowledged() {
+        let state = State::new();
+        let keys = keygen();
+
+        let (addr, mut rx) = add_web(&state, 33006, &keys, Features::from([Feature::Acks])).await;
+
+        state.send_critical(&addr, SWErrorPacket {
+            code: ErrorCode::SyncFailed,
+            message: "could not pull image".to_string(),
+            daemon: None,
+            request: None,
+        }.to_packet().expect("could not build packet")).expect("could not send critical packet");
+
+        let sent = receive(&mut rx, &keys).await;
+        assert_eq!(sent.id, ID::SWError);
+
+        // the page is reloaded before the packet was acknowledged
+        state.remove_web(addr).await.expect("could not remove web client");
+
+        let (addr, mut rx, handshake_request) = handshake(&state, 33007, &keys, Features::from([Feature::Acks])).await;
+        state.authenticate_web(addr, handshake_request.challenge).expect("could not authenticate");
+        assert_eq!(receive(&mut rx, &keys).await.id, ID::SWAuthResponse);
+
+        let redelivered = receive(&mut rx, &keys).await;
+        assert_eq!(redelivered.id, ID::SWError);
+        assert_eq!(redelivered.request_id, sent.request_id);
+
+        state.receive_web_ack(&addr, redelivered.request_id.expect("critical packet has no request id"), Ok(())).expect("could not receive ack");
+        assert_eq!(state.outbox.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn critical_packets_redelivered_only_to_one_client() {
+        let state = State::new();
+        let keys = keygen();
+
+        let connect = async |port| {
+            let (addr, mut rx, handshake_request) = handshake(&state, port, &keys, Features::from([Feature::Acks])).await;
+            state.authenticate_web(addr, handshake_request.challenge).expect("could not authenticate");
+            assert_eq!(receive(&mut rx, &keys).await.id, ID::SWAuthResponse);
+            (addr, rx)
+        };
+
+        let (first, mut first_rx) = connect(33034).await;
+
+        state.send_critical(&first, SWErrorPacket {
+            code: ErrorCode::SyncFailed,
+            message: "could not pull image".to_string(),
+            daemon: None,
+            request: None,
+        }.to_packet().expect("could not build packet")).expect("could not send critical packet");
+        let sent = receive(&mut first_rx, &keys).await;
+
+        // the first tab is still connected to acknowledge the packet
+        let (second, mut second_rx) = connect(33035).await;
+        assert!(second_rx.try_next().is_err());
+
+        // acknowledgements of packets that aren't critical are ignored
+        state.receive_web_ack(&second, u64::MAX, Ok(())).expect("could not receive ack");
+
+        state.remove_web(first).await.expect("could not remove web client");
+
+        let redelivered = receive(&mut second_rx, &keys).await;
+        assert_eq!(redelivered.request_id, sent.request_id);
+
+        state.receive_web_ack(&second, redelivered.request_id.expect("critical packet has no request id"), Ok(())).expect("could not receive ack");
+        assert_eq!(state.outbox.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn command_results_outlive_web_client() {
+        let state = State::new();
+        let keys = keygen();
+
+        let connect = async |port| {
+            let (addr, mut rx, handshake_request) = handshake(&state, port, &keys, Features::from([Feature::Acks])).await;
+            state.authenticate_web(addr, handshake_request.challenge).expect("could not authenticate");
+            assert_eq!(receive(&mut rx, &keys).await.id, ID::SWAuthResponse);
+            (addr, rx)
+        };
+
+        let uuid = Uuid::from_u128(1);
+        let (daemon, mut daemon_rx) = add_daemon(&state, 33039, uuid, &keys, Features::default()).await;
+        join_team(&state, &[uuid]);
 
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] dropped DAEMON_LISTEN_MAP", file!(), line!());
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] dropped WEB_LISTEN_MAP", file!(), line!());
-        }
+        let (first, _first_rx) = connect(33040).await;
 
-        for daemon in offline_daemons.into_iter() {
-            self.send_event_from_server(&daemon, EventData::NodeStatus(NodeStatusEvent {
-                online: false,
-                stats: None,
-            })).await?;
+        state.send_command(first, WSCommandPacket {
+            daemon: uuid,
+            server: 3,
+            command: ServerCommand::Restart,
+        }).await.expect("could not send command");
+        let command = SDCommandPacket::parse(receive(&mut daemon_rx, &keys).await).expect("could not parse packet");
+
+        // the tab that ran the command is closed before it finished
+        state.remove_web(first).await.expect("could not remove web client");
+        let (_second, mut second_rx) = connect(33041).await;
+
+        let result = |request| DSCommandResultPacket {
+            request,
+            server: 3,
+            command: ServerCommand::Restart,
+            error: Some("Could not restart server 3: container is paused".to_string()),
+        };
+
+        state.send_command_result(&daemon, result(command.request)).expect("could not send command result");
+
+        let result_packet = SWCommandResultPacket::parse(receive(&mut second_rx, &keys).await).expect("could not parse packet");
+        assert_eq!(result_packet.error.as_deref(), Some("Could not restart server 3: container is paused"));
+
+        // answered only once
+        assert!(state.send_command_result(&daemon, result(command.request)).is_err());
+        assert_eq!(state.pending_commands.len(), 0);
+    }
+
+    /// A daemon whose commands and syncs are refused by the `Gatekeeper` plugin.
+    const QUARANTINED: Uuid = Uuid::from_u128(0x4515);
+
+    /// A plugin rejecting commands and cancelling syncs of the `QUARANTINED` daemon, leaving every
+    /// other daemon alone, as plugins are registered for all tests.
+    struct Gatekeeper;
+
+    #[async_trait::async_trait]
+    impl plugins::Plugin for Gatekeeper {
+        fn name(&self) -> &'static str {
+            "gatekeeper"
         }
 
-        for daemon in update_daemons.into_iter() {
-            if let Some(daemon_addr) = daemon_id_map.get(&daemon) {
-                self.update_listens_for_daemon(&daemon_addr, &daemon).await?;
+        async fn on_sync(&self, daemon: Uuid, _sync: &SDSyncPacket) -> Result<(), String> {
+            match daemon {
+                QUARANTINED => Err("daemon is quarantined".to_string()),
+                _ => Ok(()),
             }
         }
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped DAEMON_ID_MAP", file!(), line!());
+        async fn on_command(&self, _user_id: u32, command: &WSCommandPacket) -> Result<(), String> {
+            match command.daemon {
+                QUARANTINED => Err("daemon is quarantined".to_string()),
+                _ => Ok(()),
+            }
+        }
+    }
 
-        Ok(())
+    #[tokio::test]
+    async fn plugins_reject_commands_and_cancel_syncs() {
+        plugins::register(vec![Box::new(Gatekeeper)]);
+
+        let state = State::new();
+        let keys = keygen();
+
+        let (_daemon, mut daemon_rx) = add_daemon(&state, 33047, QUARANTINED, &keys, Features::from([Feature::SyncProgress])).await;
+        join_team(&state, &[QUARANTINED]);
+
+        let (web, mut rx, handshake_request) = handshake(&state, 33048, &keys, Features::from([Feature::SyncProgress])).await;
+        state.authenticate_web(web, handshake_request.challenge).expect("could not authenticate");
+        assert_eq!(receive(&mut rx, &keys).await.id, ID::SWAuthResponse);
+
+        state.send_command(web, WSCommandPacket {
+            daemon: QUARANTINED,
+            server: 1,
+            command: ServerCommand::Restart,
+        }).await.expect("could not send command");
+
+        let result = SWCommandResultPacket::parse(receive(&mut rx, &keys).await).expect("could not parse packet");
+        assert_eq!(result.error.as_deref(), Some("Command was rejected by plugin gatekeeper: daemon is quarantined"));
+
+        // the spec is cached, so it isn't fetched from the database
+        state.spec_cache.insert(QUARANTINED, (Instant::now(), SDSyncPacket {
+            networks: Vec::new(),
+            servers: Vec::new(),
+            maintenance: Vec::new(),
+            metadata: Vec::new(),
+            hash: String::new(),
+            base: String::new(),
+            removed_networks: Vec::new(),
+            removed_servers: Vec::new(),
+            request: None,
+        }));
+
+        assert!(state.sync_daemon(QUARANTINED, None, HashMap::from([(web, Some(9))])).await.is_err());
+
+        let progress = SWSyncProgressPacket::parse(receive(&mut rx, &keys).await).expect("could not parse packet");
+        assert!(matches!(progress.step, SyncStep::Failed { error } if error == "Sync was cancelled by plugin gatekeeper: daemon is quarantined"));
+
+        // neither reached the daemon
+        assert!(daemon_rx.try_next().is_err());
     }
 
-    /// Adds a web client to the server.
-    pub fn add_web(&self, addr: SocketAddr, tx: Tx) {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
+    #[tokio::test]
+    async fn sync_errors_reach_requesters_in_either_order() {
+        let state = State::new();
+        let keys = keygen();
+
+        let uuid = Uuid::from_u128(1);
+        let (daemon, _daemon_rx) = add_daemon(&state, 33018, uuid, &keys, Features::from([Feature::SyncProgress, Feature::DaemonErrors])).await;
+        let (web, mut rx) = add_web(&state, 33019, &keys, Features::from([Feature::SyncProgress])).await;
+
+        let error = |request| DSErrorPacket {
+            code: ErrorCode::SyncFailed,
+            message: "could not pull image".to_string(),
+            request: Some(request),
+        };
+        let failed = |request| DSSyncProgressPacket {
+            request,
+            step: SyncStep::Failed {
+                error: "could not pull image".to_string(),
+            },
+        };
 
-        self.web_channel_map.insert(addr, WebSocket {
-            tx,
-            handshake: None,
-        });
+        // the error arrives before the failed step
+        state.sync_requests.insert(1, (HashMap::from([(web, Some(7))]), uuid));
+        state.send_daemon_error(&daemon, error(1)).expect("could not send daemon error");
+        state.send_sync_progress(&daemon, failed(1)).expect("could not send sync progress");
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
+        let sent = SWErrorPacket::parse(receive(&mut rx, &keys).await).expect("could not parse error packet");
+        assert_eq!((sent.daemon, sent.request), (Some(uuid), Some(7)));
+        assert_eq!(receive(&mut rx, &keys).await.id, ID::SWSyncProgress);
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
+        // the error arrives after the failed step
+        state.sync_requests.insert(2, (HashMap::from([(web, Some(8))]), uuid));
+        state.send_sync_progress(&daemon, failed(2)).expect("could not send sync progress");
+        state.send_daemon_error(&daemon, error(2)).expect("could not send daemon error");
+
+        assert_eq!(receive(&mut rx, &keys).await.id, ID::SWSyncProgress);
+        let sent = SWErrorPacket::parse(receive(&mut rx, &keys).await).expect("could not parse error packet");
+        assert_eq!((sent.daemon, sent.request), (Some(uuid), Some(8)));
+
+        // errors of unknown syncs reach no one
+        state.send_daemon_error(&daemon, error(3)).expect("could not send daemon error");
+        assert!(rx.try_next().is_err());
     }
 
-    /// Removes a web client from the server. Should only be used in the `on_disconnect` method,
-    /// see `disconnect_web` for a more general use case.
-    pub async fn remove_web(&self, addr: SocketAddr) -> Result<(), String> {
-        let mut update_daemons = HashSet::new();
+    #[tokio::test]
+    async fn daemon_acknowledgements_resolve_packets() {
+        let state = State::new();
+        let keys = keygen();
 
-        {
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] awaiting WEB_LISTEN_MAP", file!(), line!());
-            let web_listen_map: &WebListenMap = self.web_listen_map.borrow();
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] got WEB_LISTEN_MAP", file!(), line!());
+        let (addr, mut rx) = add_daemon(&state, 33015, Uuid::from_u128(1), &keys, Features::from([Feature::Acks])).await;
+        let packet = || SDCancelTaskPacket {
+            task: 1,
+        }.to_packet().expect("could not build packet");
 
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] awaiting DAEMON_LISTEN_MAP", file!(), line!());
-            let daemon_listen_map: &DaemonListenMap = self.daemon_listen_map.borrow();
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] got DAEMON_LISTEN_MAP", file!(), line!());
+        let ack = state.send_to_daemon_acked(&addr, packet()).expect("could not send packet").expect("packet should wait for an acknowledgement");
+        let request_id = receive(&mut rx, &keys).await.request_id.expect("packet has no request id");
 
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
-            let web_channel_map: &WebChannelMap = self.web_channel_map.borrow();
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
+        // only the daemon the packet was sent to can acknowledge it
+        assert!(state.receive_ack(&SocketAddr::from(([127, 0, 0, 1], 33016)), request_id, Ok(())).is_err());
 
-            web_channel_map.remove(&addr);
-            if let Some(listen_map) = web_listen_map.get(&addr) {
-                for (event, daemons) in listen_map.iter() {
-                    for daemon in daemons.iter() {
-                        update_daemons.insert(*daemon);
+        state.receive_ack(&addr, request_id, Ok(())).expect("could not receive ack");
+        state.wait_for_ack(Some(ack), Duration::from_secs(1)).await.expect("packet was not acknowledged");
+        assert!(state.receive_ack(&addr, request_id, Ok(())).is_err());
 
-                        let mut listen_map = daemon_listen_map.get_mut(daemon).ok_or("daemon not found in DaemonListenMap")?;
-                        let event_map = listen_map.get_mut(event).ok_or("event not found in DaemonListenMap")?;
+        let ack = state.send_to_daemon_acked(&addr, packet()).expect("could not send packet");
+        let request_id = receive(&mut rx, &keys).await.request_id.expect("packet has no request id");
 
-                        event_map.remove(&addr);
+        state.receive_ack(&addr, request_id, Err("no such task".to_string())).expect("could not receive nack");
+        let e = state.wait_for_ack(ack, Duration::from_secs(1)).await.expect_err("packet was acknowledged");
+        assert!(e.contains("no such task"));
 
-                        if event_map.is_empty() {
-                            listen_map.remove(event);
-                        }
-                    }
-                }
-            }
+        let ack = state.send_to_daemon_acked(&addr, packet()).expect("could not send packet");
+        assert!(state.wait_for_ack(ack, Duration::ZERO).await.is_err());
+        assert_eq!(state.pending_acks.len(), 0);
+    }
 
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] dropped DAEMON_LISTEN_MAP", file!(), line!());
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] dropped WEB_LISTEN_MAP", file!(), line!());
+    #[tokio::test]
+    async fn packets_to_daemons_without_acks_not_awaited() {
+        let state = State::new();
+        let keys = keygen();
+
+        let (addr, mut rx) = add_daemon(&state, 33017, Uuid::from_u128(1), &keys, Features::default()).await;
+
+        let ack = state.send_to_daemon_acked(&addr, SDCancelTaskPacket {
+            task: 1,
+        }.to_packet().expect("could not build packet")).expect("could not send packet");
+
+        assert!(ack.is_none());
+        assert_eq!(receive(&mut rx, &keys).await.request_id, None);
+        assert_eq!(state.pending_acks.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn server_logs_only_sent_to_followers() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let mut clients = Vec::new();
+        join_team(&state, &[daemon]);
+
+        for server in 1..=2 {
+            let (addr, rx) = add_web(&state, 33010 + server as u16, &keys, Features::default()).await;
+            state.send_listen(addr, vec![listen(EventType::ServerLog, daemon, vec![server])]).await.expect("could not listen");
+
+            clients.push(rx);
         }
 
-        for daemon in update_daemons {
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] awaiting DAEMON_ID_MAP", file!(), line!());
-            if let Some(daemon_addr) = self.daemon_id_map.get(&daemon) {
-                self.update_listens_for_daemon(&daemon_addr, &daemon).await?;
-            }
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] got DAEMON_ID_MAP", file!(), line!());
-            #[cfg(feature = "lock_debug")]
-            debug!("[{}:{}] dropped DAEMON_ID_MAP", file!(), line!());
+        state.process_event(&daemon, EventData::ServerLog(ServerLogEvent {
+            server: 1,
+            lines: Vec::new(),
+            skipped: 0,
+        }), None, None).await.expect("could not process event");
+
+        let event = SWEventPacket::parse(receive(&mut clients[0], &keys).await).expect("could not parse event packet");
+        assert!(matches!(event.event, EventData::ServerLog(ServerLogEvent { server: 1, .. })));
+
+        assert!(clients[1].try_next().is_err());
+    }
+
+    #[tokio::test]
+    async fn server_logs_only_accessible_on_team_daemons() {
+        let state = State::new();
+        let keys = keygen();
+
+        let (team, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let (addr, _rx) = add_web(&state, 33036, &keys, Features::default()).await;
+        join_team(&state, &[team]);
+
+        state.send_listen(addr, vec![listen(EventType::ServerLog, team, vec![1]), listen(EventType::ServerLog, other, vec![1])]).await.expect("could not listen");
+        assert_eq!(state.log_servers(&team), vec![1]);
+        assert!(state.log_servers(&other).is_empty());
+
+        let e = state.query_logs(addr, WSQueryLogsPacket {
+            daemon: other,
+            server: 1,
+            lines: 10,
+        }).await.expect_err("logs of another team were queried");
+        assert!(e.contains("does not belong to your team"));
+    }
+
+    #[tokio::test]
+    async fn unlistening_clears_logs_and_windows() {
+        let state = State::new();
+        let keys = keygen();
+
+        let (grouped, direct) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let (addr, _rx) = add_web(&state, 33042, &keys, Features::from([Feature::FlowControl])).await;
+        join_team(&state, &[grouped, direct]);
+
+        state.send_listen(addr, vec![listen(EventType::ServerLog, grouped, vec![1]), listen(EventType::ServerLog, direct, vec![1])]).await.expect("could not listen");
+        for daemon in [grouped, direct] {
+            state.stream_windows.entry((addr, daemon, EventType::ServerLog)).or_default().take();
         }
 
-        Ok(())
+        // the daemon left the only group the web client listened to it through
+        state.group_member_cache.insert(5, HashSet::from([grouped]));
+        state.group_listen_map.entry(addr).or_default().insert(EventType::ServerLog, HashSet::from([5]));
+        state.remove_group_listen(addr, EventType::ServerLog, 5, grouped);
+
+        assert!(state.log_servers(&grouped).is_empty());
+        assert!(!state.stream_windows.contains_key(&(addr, grouped, EventType::ServerLog)));
+        assert!(!state.daemon_listen_map.contains_key(&grouped));
+
+        state.remove_listen(addr, vec![listen(EventType::ServerLog, direct, Vec::new())]).await.expect("could not unlisten");
+
+        assert!(state.log_servers(&direct).is_empty());
+        assert!(state.stream_windows.is_empty());
+        assert!(state.daemon_listen_map.is_empty());
     }
 
-    /// Disconnects a web client from the server.
-    pub fn disconnect_web(&self, addr: SocketAddr) -> Result<(), String> {
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] awaiting WEB_CHANNEL_MAP", file!(), line!());
-        self.web_channel_map.get(&addr).ok_or("Client not found in channel_map")?.tx.close_channel();
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] got WEB_CHANNEL_MAP", file!(), line!());
+    #[tokio::test]
+    async fn replayed_events_only_recorded() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (daemon_addr, _daemon_rx) = add_daemon(&state, 33043, daemon, &keys, Features::default()).await;
+        let (addr, mut rx) = add_web(&state, 33044, &keys, Features::default()).await;
+        state.send_listen(addr, vec![listen(EventType::ServerStatus, daemon, Vec::new())]).await.expect("could not listen");
+
+        let event = |seq, replayed| DSEventPacket {
+            data: EventData::ServerStatus(server_status(seq as u32)),
+            seq: Some(seq),
+            timestamp: Some(100 + seq),
+            replayed,
+        };
 
-        #[cfg(feature = "lock_debug")]
-        debug!("[{}:{}] dropped WEB_CHANNEL_MAP", file!(), line!());
+        state.send_event_from_daemon(&daemon_addr, event(1, true)).await.expect("could not send event");
+        assert!(rx.try_next().is_err());
+        assert!(state.status_cache.get(&daemon).is_some_and(|cached| cached.servers.contains_key(&1)));
 
-        Ok(())
+        state.send_event_from_daemon(&daemon_addr, event(2, false)).await.expect("could not send event");
+        let event = SWEventPacket::parse(receive(&mut rx, &keys).await).expect("could not parse event packet");
+        assert!(matches!(event.event, EventData::ServerStatus(ServerStatusEvent { server: 2, .. })));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::{pin::Pin, str::FromStr};
+    #[tokio::test]
+    async fn events_handled_in_order() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (daemon_addr, _daemon_rx) = add_daemon(&state, 33045, daemon, &keys, Features::default()).await;
+        let (addr, mut rx) = add_web(&state, 33046, &keys, Features::default()).await;
+        state.send_listen(addr, vec![listen(EventType::UpdatePhase, daemon, Vec::new()), listen(EventType::ServerStatus, daemon, Vec::new())]).await.expect("could not listen");
+
+        let event = |seq, data| DSEventPacket {
+            data,
+            seq: Some(seq),
+            timestamp: None,
+            replayed: false,
+        };
+        let phase = |phase| EventData::UpdatePhase(UpdatePhaseEvent {
+            server: 1,
+            phase,
+        });
 
-    use futures_util::StreamExt;
-    use josekit::jwk;
-    use mpsc::unbounded;
-    use packet::ID;
+        state.send_event_from_daemon(&daemon_addr, event(3, phase(UpdatePhase::Done))).await.expect("could not send event");
+        state.send_event_from_daemon(&daemon_addr, event(2, phase(UpdatePhase::Swapping))).await.expect("could not send event");
+        assert!(rx.try_next().is_err());
 
-    use super::*;
+        state.send_event_from_daemon(&daemon_addr, event(1, phase(UpdatePhase::StartingCandidate))).await.expect("could not send event");
+
+        for expected in [1, 2, 3] {
+            let event = SWEventPacket::parse(receive(&mut rx, &keys).await).expect("could not parse event packet");
+            assert_eq!(event.seq, Some(expected));
+        }
+
+        // snapshots of other servers aren't stale, even with a lower sequence number
+        state.send_event_from_daemon(&daemon_addr, event(5, EventData::ServerStatus(server_status(1)))).await.expect("could not send event");
+        state.send_event_from_daemon(&daemon_addr, event(4, EventData::ServerStatus(server_status(2)))).await.expect("could not send event");
+
+        for expected in [1, 2] {
+            let event = SWEventPacket::parse(receive(&mut rx, &keys).await).expect("could not parse event packet");
+            assert!(matches!(event.event, EventData::ServerStatus(ServerStatusEvent { server, .. }) if server == expected));
+        }
+    }
 
     #[tokio::test]
-    async fn encryption_decryption() {
-        let state = Arc::new(State::new());
+    async fn server_status_only_sent_for_listened_servers() {
+        let state = State::new();
+        let keys = keygen();
 
-        let web_addr_1 = SocketAddr::from(([127, 0, 0, 1], 30001));
-        let (web_tx_1, mut web_rx_1) = unbounded();
+        let daemon = Uuid::from_u128(1);
+        let (addr, mut rx) = add_web(&state, 33013, &keys, Features::default()).await;
 
-        let web_keys_1 = jwk::alg::rsa::RsaKeyPair::generate(2048).expect("could not create keys");
-        let web_public_1 = Arc::new(web_keys_1.to_pem_public_key());
+        state.send_listen(addr, vec![listen(EventType::ServerStatus, daemon, vec![2])]).await.expect("could not listen");
+        assert_eq!(state.status_servers(&daemon), Some(vec![2]));
 
-        let web_private_1 = Arc::new(web_keys_1.to_pem_private_key());
-        let decrypter = josekit::jwe::RSA_OAEP.decrypter_from_pem(web_private_1.as_ref()).expect("could not create decrypter");
+        for server in 1..=2 {
+            state.process_event(&daemon, EventData::ServerStatus(server_status(server)), None, None).await.expect("could not process event");
+        }
 
-        state.add_web(web_addr_1, web_tx_1);
-        state.send_web_handshake_request(&web_addr_1, 1, web_public_1).expect("could not send web handshake request");
+        let event = SWEventPacket::parse(receive(&mut rx, &keys).await).expect("could not parse event packet");
+        assert!(matches!(event.event, EventData::ServerStatus(ServerStatusEvent { server: 2, .. })));
 
-        let handshake_request = web_rx_1.next().await.expect("could not get message");
-        let message = handshake_request.into_text().expect("message is not text");
+        assert!(rx.try_next().is_err());
 
-        let packet = encryption::decrypt_packet(&message, &decrypter, "aesterisk/server", None::<fn() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>>>).await.expect("could not decrypt packet");
+        // listening to all servers again lifts the filter
+        state.send_listen(addr, vec![listen(EventType::ServerStatus, daemon, Vec::new())]).await.expect("could not listen");
+        assert_eq!(state.status_servers(&daemon), None);
+    }
 
-        assert_eq!(packet.id, ID::SWHandshakeRequest);
+    #[tokio::test]
+    async fn unlisten_removes_subscriptions() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (addr, _rx) = add_web(&state, 33014, &keys, Features::default()).await;
+        join_team(&state, &[daemon]);
+
+        state.send_listen(addr, vec![listen(EventType::ServerStatus, daemon, vec![1, 2]), listen(EventType::ServerLog, daemon, vec![1, 2])]).await.expect("could not listen");
+
+        // some servers are left, so the daemon is still listened to
+        state.remove_listen(addr, vec![listen(EventType::ServerStatus, daemon, vec![1]), listen(EventType::ServerLog, daemon, vec![1])]).await.expect("could not unlisten");
+        assert_eq!(state.status_servers(&daemon), Some(vec![2]));
+        assert_eq!(state.log_servers(&daemon), vec![2]);
+
+        state.remove_listen(addr, vec![listen(EventType::ServerStatus, daemon, vec![2]), listen(EventType::ServerLog, daemon, Vec::new())]).await.expect("could not unlisten");
+        assert!(state.log_listens.is_empty());
+        assert!(state.status_listens.is_empty());
+        assert!(state.daemon_listen_map.is_empty());
+        assert!(state.web_listen_map.get(&addr).is_some_and(|listen_map| listen_map.values().all(HashSet::is_empty)));
     }
 
     #[tokio::test]
@@ -888,13 +4407,13 @@ mod tests {
 
         let web_user_id_1 = 1234;
 
-        state.add_web(web_addr_1, web_tx_1);
-        state.send_web_handshake_request(&web_addr_1, web_user_id_1, web_public_1).expect("could not send web handshake request");
+        state.add_web(web_addr_1, web_tx_1, Encoding::Json);
+        state.send_web_handshake_request(&web_addr_1, web_user_id_1, web_public_1, Features::default()).expect("could not send web handshake request");
 
         let handshake_request = web_rx_1.next().await.expect("could not get message");
         let message = handshake_request.into_text().expect("message is not text");
 
-        let packet = encryption::decrypt_packet(&message, &decrypter, "aesterisk/server", None::<fn() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>>>).await.expect("could not decrypt packet");
+        let packet = encryption::decrypt_packet(&message, &decrypter, &["aesterisk/server".to_string()], None::<fn() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>>>).await.expect("could not decrypt packet");
 
         assert_eq!(packet.id, ID::SWHandshakeRequest);
 
@@ -923,13 +4442,18 @@ mod tests {
 
         let daemon_uuid_1 = Uuid::from_str("DAE11071-0000-4000-0000-000000000000").expect("could not create uuid");
 
-        state.add_daemon(daemon_addr_1, daemon_tx_1);
-        state.send_daemon_handshake_request(daemon_addr_1, daemon_uuid_1, daemon_public_1).await.expect("could not send daemon handshake request");
+        state.add_daemon(daemon_addr_1, daemon_tx_1, Encoding::Json);
+        state.send_daemon_handshake_request(daemon_addr_1, daemon_uuid_1, daemon_public_1, DSAuthPacket {
+            daemon_uuid: daemon_uuid_1.to_string(),
+            sync_hash: String::new(),
+            features: Features::default(),
+            version: String::new(),
+        }, None, None).await.expect("could not send daemon handshake request");
 
         let handshake_request = daemon_rx_1.next().await.expect("could not get message");
         let message = handshake_request.into_text().expect("message is not text");
 
-        let packet = encryption::decrypt_packet(&message, &decrypter, "aesterisk/server", None::<fn() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>>>).await.expect("could not decrypt packet");
+        let packet = encryption::decrypt_packet(&message, &decrypter, &["aesterisk/server".to_string()], None::<fn() -> Pin<Box<dyn Future<Output = Result<(), String>> + Send>>>).await.expect("could not decrypt packet");
 
         assert_eq!(packet.id, ID::SDHandshakeRequest);
 
@@ -942,4 +4466,435 @@ mod tests {
         assert!(client.as_ref().unwrap().handshake.is_some());
         assert!(client.unwrap().handshake.as_ref().unwrap().daemon_uuid == daemon_uuid_1);
     }
+
+    /// Asserts that the web and daemon listen maps mirror each other and contain no empty entries.
+    fn assert_listen_maps_consistent(state: &State) {
+        for web in state.web_listen_map.iter() {
+            assert!(!web.is_empty());
+
+            for (event, daemons) in web.iter() {
+                assert!(!daemons.is_empty());
+
+                for daemon in daemons.iter() {
+                    assert!(state.daemon_listen_map.get(daemon).is_some_and(|listen_map| listen_map.get(event).is_some_and(|clients| clients.contains(web.key()))));
+                }
+            }
+        }
+
+        for daemon in state.daemon_listen_map.iter() {
+            assert!(!daemon.is_empty());
+
+            for (event, clients) in daemon.iter() {
+                assert!(!clients.is_empty());
+
+                for client in clients.iter() {
+                    assert!(state.web_listen_map.get(client).is_some_and(|listen_map| listen_map.get(event).is_some_and(|daemons| daemons.contains(daemon.key()))));
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn listen_maps_under_churn() {
+        let state = State::new();
+
+        let daemons = (0..4).map(Uuid::from_u128).collect::<Vec<_>>();
+        let mut connected = VecDeque::new();
+
+        for round in 0..24u16 {
+            let addr = SocketAddr::from(([127, 0, 0, 1], 31000 + round));
+            let (tx, _rx) = unbounded();
+
+            state.add_web(addr, tx, Encoding::Json);
+            state.send_listen(addr, vec![ListenEvent {
+                event: EventType::ServerStatus,
+                daemons: daemons.iter().skip(round as usize % daemons.len()).copied().collect(),
+                groups: Vec::new(),
+                servers: Vec::new(),
+            }]).await.expect("could not listen");
+
+            connected.push_back(addr);
+
+            if round % 3 == 2 {
+                let addr = connected.pop_front().expect("a client should be connected");
+                state.remove_web(addr).await.expect("could not remove web client");
+            }
+
+            assert_listen_maps_consistent(&state);
+        }
+
+        while let Some(addr) = connected.pop_front() {
+            state.remove_web(addr).await.expect("could not remove web client");
+            assert_listen_maps_consistent(&state);
+        }
+
+        assert!(state.web_listen_map.is_empty());
+        assert!(state.daemon_listen_map.is_empty());
+        assert!(state.web_channel_map.is_empty());
+
+        // entries emptied by other paths (e.g. group membership changes) are left for `compact`
+        let addr = SocketAddr::from(([127, 0, 0, 1], 32000));
+        state.web_listen_map.insert(addr, HashMap::from([(EventType::ServerStatus, HashSet::new())]));
+        state.daemon_listen_map.insert(daemons[0], HashMap::new());
+
+        assert_eq!(state.compact(), 3);
+        assert!(state.web_listen_map.is_empty());
+        assert!(state.daemon_listen_map.is_empty());
+    }
+
+    #[tokio::test]
+    async fn daemon_window_replenished_by_web_grants() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (_, mut daemon_rx) = add_daemon(&state, 33030, daemon, &keys, Features::from([Feature::FlowControl])).await;
+        let (web_addr, _web_rx) = add_web(&state, 33031, &keys, Features::from([Feature::FlowControl])).await;
+
+        state.send_listen(web_addr, vec![listen(EventType::BuildOutput, daemon, Vec::new())]).await.expect("could not listen");
+
+        while let Ok(Some(_)) = daemon_rx.try_next() {}
+
+        // the daemon sent 156 events, of which the web client has not processed 200 yet
+        for _ in 0..156 {
+            state.daemon_windows.entry((daemon, EventType::BuildOutput)).or_default().take();
+        }
+        for _ in 0..200 {
+            state.stream_windows.entry((web_addr, daemon, EventType::BuildOutput)).or_default().take();
+        }
+
+        // the daemon has more credits left than the web client
+        state.replenish_daemon_window(&daemon, EventType::BuildOutput).expect("could not replenish window");
+        assert!(daemon_rx.try_next().is_err());
+
+        state.grant_web_window(web_addr, WSWindowUpdatePacket {
+            daemon,
+            event: EventType::BuildOutput,
+            credits: 200,
+        }).expect("could not grant window");
+
+        let window_update = SDWindowUpdatePacket::parse(receive(&mut daemon_rx, &keys).await).expect("could not parse packet");
+        assert_eq!(window_update.event, EventType::BuildOutput);
+        assert_eq!(window_update.credits, 156);
+        assert_eq!(state.daemon_windows.get(&(daemon, EventType::BuildOutput)).map(|window| window.credits()), Some(flow::INITIAL_WINDOW));
+
+        // granted in batches
+        state.grant_web_window(web_addr, WSWindowUpdatePacket {
+            daemon,
+            event: EventType::BuildOutput,
+            credits: 1,
+        }).expect("could not grant window");
+        assert!(daemon_rx.try_next().is_err());
+
+        assert!(state.grant_web_window(web_addr, WSWindowUpdatePacket {
+            daemon,
+            event: EventType::NodeStatus,
+            credits: 1,
+        }).is_err());
+
+        assert!(state.grant_web_window(web_addr, WSWindowUpdatePacket {
+            daemon: Uuid::from_u128(2),
+            event: EventType::BuildOutput,
+            credits: 1,
+        }).is_err());
+    }
+
+    #[tokio::test]
+    async fn terminal_window_replenished_for_web_without_flow_control() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (daemon_addr, mut daemon_rx) = add_daemon(&state, 33032, daemon, &keys, Features::from([Feature::Terminals, Feature::TerminalFlowControl])).await;
+        let (web_addr, mut web_rx) = add_authenticated_web(&state, 33033, &keys, Features::default()).await;
+
+        let session = Uuid::from_u128(3);
+        state.terminals.insert(session, Terminal::new(web_addr, 1, daemon));
+
+        for _ in 0..flow::INITIAL_WINDOW / 2 {
+            state.send_terminal_output(&daemon_addr, DSTerminalOutputPacket {
+                session,
+                data: String::new(),
+                seq: None,
+            }).expect("could not send terminal output");
+
+            assert_eq!(receive(&mut web_rx, &keys).await.id, ID::SWTerminalOutput);
+        }
+
+        // granted once half of the window was used
+        let input = SDTerminalInputPacket::parse(receive(&mut daemon_rx, &keys).await).expect("could not parse packet");
+        assert_eq!(input.session, session);
+        assert_eq!(input.credits, flow::INITIAL_WINDOW / 2);
+        assert!(input.data.is_empty());
+        assert!(daemon_rx.try_next().is_err());
+
+        // credits of web clients with flow control are forwarded
+        state.send_terminal_input(web_addr, WSTerminalInputPacket {
+            session,
+            data: String::new(),
+            resize: None,
+            credits: 10,
+            seq: None,
+        }).expect("could not send terminal input");
+
+        let input = SDTerminalInputPacket::parse(receive(&mut daemon_rx, &keys).await).expect("could not parse packet");
+        assert_eq!(input.credits, 10);
+    }
+
+    #[tokio::test]
+    async fn terminal_packets_forwarded_in_order() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (daemon_addr, mut daemon_rx) = add_daemon(&state, 33037, daemon, &keys, Features::from([Feature::Terminals])).await;
+        let (web_addr, mut web_rx) = add_authenticated_web(&state, 33038, &keys, Features::default()).await;
+
+        let session = Uuid::from_u128(3);
+        state.terminals.insert(session, Terminal::new(web_addr, 1, daemon));
+
+        let input = |data: &str, seq| WSTerminalInputPacket {
+            session,
+            data: data.to_string(),
+            resize: None,
+            credits: 0,
+            seq: Some(seq),
+        };
+
+        state.send_terminal_input(web_addr, input("b", 1)).expect("could not send terminal input");
+        assert!(daemon_rx.try_next().is_err());
+        state.send_terminal_input(web_addr, input("a", 0)).expect("could not send terminal input");
+
+        for (data, seq) in [("a", 0), ("b", 1)] {
+            let input = SDTerminalInputPacket::parse(receive(&mut daemon_rx, &keys).await).expect("could not parse packet");
+            assert_eq!((input.data.as_str(), input.seq), (data, Some(seq)));
+        }
+
+        state.send_terminal_close(&daemon_addr, DSTerminalClosePacket {
+            session,
+            exit_code: Some(0),
+            error: None,
+            seq: Some(2),
+        }).expect("could not send terminal close");
+        state.send_terminal_output(&daemon_addr, DSTerminalOutputPacket {
+            session,
+            data: "d".to_string(),
+            seq: Some(1),
+        }).expect("could not send terminal output");
+        assert!(web_rx.try_next().is_err());
+
+        state.send_terminal_output(&daemon_addr, DSTerminalOutputPacket {
+            session,
+            data: "c".to_string(),
+            seq: Some(0),
+        }).expect("could not send terminal output");
+
+        for (data, seq) in [("c", 0), ("d", 1)] {
+            let output = SWTerminalOutputPacket::parse(receive(&mut web_rx, &keys).await).expect("could not parse packet");
+            assert_eq!((output.data.as_str(), output.seq), (data, Some(seq)));
+        }
+
+        // closed only after the output before it
+        let close = SWTerminalClosePacket::parse(receive(&mut web_rx, &keys).await).expect("could not parse packet");
+        assert_eq!((close.exit_code, close.seq), (Some(0), Some(2)));
+        assert!(!state.terminals.contains_key(&session));
+    }
+
+    #[tokio::test]
+    async fn unauthenticated_daemon_packets_rejected() {
+        use crate::{daemon::DaemonServer, server::Server};
+        use packet::daemon_server::sync_result::DSSyncResultPacket;
+
+        let state = Arc::new(State::new());
+        let server = DaemonServer::new(Arc::clone(&state));
+        let keys = keygen();
+
+        let addr = SocketAddr::from(([127, 0, 0, 1], 33049));
+        let (tx, mut rx) = unbounded();
+        let uuid = Uuid::from_u128(1);
+
+        state.add_daemon(addr, tx, Encoding::Json);
+        state.send_daemon_handshake_request(addr, uuid, Arc::clone(&keys.public), auth(uuid, Features::from([Feature::Heartbeats])), None, None).await.expect("could not send daemon handshake request");
+        assert_eq!(receive(&mut rx, &keys).await.id, ID::SDHandshakeRequest);
+
+        let sync_result = DSSyncResultPacket {
+            servers: Vec::new(),
+            resync: true,
+            request: None,
+        }.to_packet().expect("could not create packet");
+
+        let e = server.on_packet(sync_result, addr).await.expect_err("unauthenticated daemon could request a full sync");
+        assert!(e.contains("hasn't authenticated"));
+
+        // its pongs would be rejected as well
+        state.ping_daemon(&addr).expect("could not ping");
+        assert!(rx.try_next().is_err());
+    }
+
+    #[test]
+    fn queries_only_answered_by_their_daemon() {
+        let state = State::new();
+
+        let web = SocketAddr::from(([127, 0, 0, 1], 33050));
+        let request = state.register_query(web, Uuid::from_u128(1));
+
+        assert!(state.take_query(request, Uuid::from_u128(2)).is_err());
+        assert_eq!(state.take_query(request, Uuid::from_u128(1)), Ok(web));
+        assert!(state.take_query(request, Uuid::from_u128(1)).is_err());
+    }
+
+    #[tokio::test]
+    async fn processes_only_queried_on_team_daemons() {
+        let state = State::new();
+        let keys = keygen();
+
+        let (team, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let (_other_addr, mut other_rx) = add_daemon(&state, 33051, other, &keys, Features::default()).await;
+        let (addr, _rx) = add_web(&state, 33052, &keys, Features::default()).await;
+        join_team(&state, &[team]);
+
+        let e = state.query_top(addr, WSQueryTopPacket {
+            daemon: other,
+            server: 1,
+        }).await.expect_err("processes of another team were queried");
+        assert!(e.contains("does not belong to your team"));
+
+        assert!(other_rx.try_next().is_err());
+        assert!(state.pending_queries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn usage_only_queried_on_team_daemons() {
+        let state = State::new();
+        let keys = keygen();
+
+        let (team, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let (_other_addr, mut other_rx) = add_daemon(&state, 33053, other, &keys, Features::default()).await;
+        let (addr, _rx) = add_web(&state, 33054, &keys, Features::default()).await;
+        join_team(&state, &[team]);
+
+        let e = state.query_usage(addr, WSQueryUsagePacket {
+            daemon: other,
+            server: 1,
+            from: 0,
+            to: 86400,
+        }).await.expect_err("usage of another team was queried");
+        assert!(e.contains("does not belong to your team"));
+
+        assert!(other_rx.try_next().is_err());
+        assert!(state.pending_queries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn stats_only_queried_on_team_daemons() {
+        let state = State::new();
+        let keys = keygen();
+
+        let (team, other) = (Uuid::from_u128(1), Uuid::from_u128(2));
+        let (_other_addr, mut other_rx) = add_daemon(&state, 33055, other, &keys, Features::default()).await;
+        let (addr, _rx) = add_web(&state, 33056, &keys, Features::default()).await;
+        join_team(&state, &[team]);
+
+        for server in [None, Some(1)] {
+            let e = state.query_stats(addr, WSQueryStatsPacket {
+                daemon: other,
+                server,
+            }).await.expect_err("stats of another team were queried");
+            assert!(e.contains("does not belong to your team"));
+        }
+
+        assert!(other_rx.try_next().is_err());
+        assert!(state.pending_queries.is_empty());
+    }
+
+    #[tokio::test]
+    async fn authenticated_after_answering_challenge() {
+        let state = State::new();
+        let keys = keygen();
+
+        let (web, _web_rx, handshake_request) = handshake(&state, 33057, &keys, Features::default()).await;
+
+        // clients that only requested authentication aren't authenticated yet
+        assert_eq!(state.web_user(&web), Ok(1));
+        assert_eq!(state.authenticated_web_user(&web), None);
+
+        state.authenticate_web(web, handshake_request.challenge).expect("could not authenticate");
+        assert_eq!(state.authenticated_web_user(&web), Some(1));
+
+        let uuid = Uuid::from_u128(1);
+        let daemon = SocketAddr::from(([127, 0, 0, 1], 33058));
+        let (tx, mut rx) = unbounded();
+
+        state.add_daemon(daemon, tx, Encoding::Json);
+        state.send_daemon_handshake_request(daemon, uuid, Arc::clone(&keys.public), auth(uuid, Features::default()), None, None).await.expect("could not send daemon handshake request");
+        let handshake_request = SDHandshakeRequestPacket::parse(receive(&mut rx, &keys).await).expect("could not parse packet");
+
+        assert_eq!(state.authenticated_daemon_uuid(&daemon), None);
+
+        state.authenticate_daemon(daemon, handshake_request.challenge).expect("could not authenticate");
+        assert_eq!(state.authenticated_daemon_uuid(&daemon), Some(uuid));
+    }
+
+    #[tokio::test]
+    async fn terminals_only_used_after_authenticating() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (_daemon_addr, mut daemon_rx) = add_daemon(&state, 33059, daemon, &keys, Features::from([Feature::Terminals])).await;
+        let (web_addr, mut web_rx) = add_web(&state, 33060, &keys, Features::default()).await;
+        join_team(&state, &[daemon]);
+
+        let session = Uuid::from_u128(3);
+        state.open_terminal(web_addr, WSTerminalOpenPacket {
+            session,
+            daemon,
+            server: 1,
+            size: TerminalSize {
+                cols: 80,
+                rows: 24,
+            },
+            command: Vec::new(),
+        }).await.expect("could not refuse terminal");
+
+        let close = SWTerminalClosePacket::parse(receive(&mut web_rx, &keys).await).expect("could not parse packet");
+        assert!(close.error.is_some_and(|e| e.contains("hasn't authenticated")));
+        assert!(!state.terminals.contains_key(&session));
+
+        state.terminals.insert(session, Terminal::new(web_addr, 1, daemon));
+        let e = state.send_terminal_input(web_addr, WSTerminalInputPacket {
+            session,
+            data: "id\n".to_string(),
+            resize: None,
+            credits: 0,
+            seq: None,
+        }).expect_err("unauthenticated client typed into a terminal");
+        assert!(e.contains("hasn't authenticated"));
+
+        assert!(daemon_rx.try_next().is_err());
+    }
+
+    #[tokio::test]
+    async fn commands_only_sent_after_authenticating() {
+        let state = State::new();
+        let keys = keygen();
+
+        let daemon = Uuid::from_u128(1);
+        let (_daemon_addr, mut daemon_rx) = add_daemon(&state, 33061, daemon, &keys, Features::default()).await;
+        join_team(&state, &[daemon]);
+
+        // the client claimed user 1 in its auth packet, but never answered the challenge
+        let (web_addr, mut web_rx) = add_web(&state, 33062, &keys, Features::default()).await;
+
+        let e = state.send_command(web_addr, WSCommandPacket {
+            daemon,
+            server: 1,
+            command: ServerCommand::Kill,
+        }).await.expect_err("unauthenticated client sent a command");
+        assert!(e.contains("hasn't authenticated"));
+
+        assert!(daemon_rx.try_next().is_err());
+        assert!(web_rx.try_next().is_err());
+        assert!(state.pending_commands.is_empty());
+    }
 }
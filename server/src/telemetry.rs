@@ -0,0 +1,28 @@
+use std::{sync::{atomic::{AtomicU64, Ordering}, Arc}, time::Duration};
+
+use tracing::{debug, info};
+
+use crate::state::State;
+
+/// The amount of listens rejected, because they would have exceeded a listen quota.
+pub static LISTEN_QUOTA_EXCEEDED: AtomicU64 = AtomicU64::new(0);
+
+/// Periodically compacts the state, and logs the counters and the sizes of the state maps.
+pub async fn run(state: Arc<State>) {
+    let mut interval = tokio::time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        let removed = state.compact();
+
+        if removed > 0 {
+            debug!("Removed {} unused state entries", removed);
+        }
+
+        let sizes = state.map_sizes().into_iter().map(|(name, size)| format!("{}={}", name, size)).collect::<Vec<_>>();
+
+        info!("State sizes: {}", sizes.join(" "));
+        info!("Listens rejected by quota: {}", LISTEN_QUOTA_EXCEEDED.load(Ordering::Relaxed));
+    }
+}
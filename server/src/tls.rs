@@ -0,0 +1,183 @@
+use std::{fs::File, io::{self, BufReader}, pin::Pin, sync::{Arc, OnceLock}, task::{Context, Poll}, time::Duration};
+
+use tokio::{io::{AsyncRead, AsyncWrite, ReadBuf}, net::TcpStream};
+use tokio_rustls::{rustls::{crypto::ring, ServerConfig}, server::TlsStream, TlsAcceptor};
+use tracing::info;
+
+use crate::config::CONFIG;
+
+/// How long a client may take to complete the TLS handshake.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+static ACCEPTOR: OnceLock<TlsAcceptor> = OnceLock::new();
+
+/// A connection accepted by one of the WebSocket servers, see `Server::accept_connection`.
+pub enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            Stream::Tls(stream) => Pin::new(stream).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Plain(stream) => Pin::new(stream).poll_shutdown(cx),
+            Stream::Tls(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+/// Loads the certificate and private key configured in `tls`, should be called once at startup.
+/// Does nothing if neither server accepts TLS connections.
+pub fn init() -> Result<(), String> {
+    if !CONFIG.tls.enabled() {
+        return Ok(());
+    }
+
+    let acceptor = acceptor(&CONFIG.tls.cert, &CONFIG.tls.key, &CONFIG.tls.alpn)?;
+
+    info!("Loaded TLS certificate from {}", CONFIG.tls.cert);
+
+    let _ = ACCEPTOR.set(acceptor);
+
+    Ok(())
+}
+
+/// Builds an acceptor presenting the certificate chain and private key in the given PEM files, and
+/// offering the given ALPN protocols.
+fn acceptor(cert: &str, key: &str, alpn: &[String]) -> Result<TlsAcceptor, String> {
+    let certs = rustls_pemfile::certs(&mut BufReader::new(File::open(cert).map_err(|e| format!("Could not open {}: {}", cert, e))?))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("Could not read certificates from {}: {}", cert, e))?;
+
+    if certs.is_empty() {
+        return Err(format!("{} does not contain any certificates", cert));
+    }
+
+    let key = rustls_pemfile::private_key(&mut BufReader::new(File::open(key).map_err(|e| format!("Could not open {}: {}", key, e))?))
+        .map_err(|e| format!("Could not read private key from {}: {}", key, e))?
+        .ok_or_else(|| format!("{} does not contain a private key", key))?;
+
+    let mut config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Could not configure TLS: {}", e))?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|e| format!("Invalid certificate or private key: {}", e))?;
+
+    config.alpn_protocols = alpn.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+
+    Ok(TlsAcceptor::from(Arc::new(config)))
+}
+
+/// Performs the TLS handshake of a connection.
+pub async fn accept(stream: TcpStream) -> Result<Stream, String> {
+    let acceptor = ACCEPTOR.get().ok_or("TLS is not initialized")?;
+
+    let stream = tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await
+        .map_err(|_| "TLS handshake timed out".to_string())?
+        .map_err(|e| format!("TLS handshake failed: {}", e))?;
+
+    Ok(Stream::Tls(Box::new(stream)))
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+    use tokio_rustls::{rustls::{pki_types::ServerName, ClientConfig, RootCertStore}, TlsConnector};
+
+    use super::*;
+
+    const CA: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/tls/ca.pem");
+    const CERT: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/tls/cert.pem");
+    const KEY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/tls/key.pem");
+
+    fn connector(alpn: &[&str]) -> TlsConnector {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut BufReader::new(File::open(CA).expect("could not open CA"))) {
+            roots.add(cert.expect("could not read CA")).expect("could not trust CA");
+        }
+
+        let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .expect("could not configure TLS")
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        config.alpn_protocols = alpn.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+
+        TlsConnector::from(Arc::new(config))
+    }
+
+    #[tokio::test]
+    async fn handshake() {
+        let acceptor = acceptor(CERT, KEY, &["http/1.1".to_string()]).expect("could not load certificate");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("could not bind");
+        let addr = listener.local_addr().expect("could not get address");
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("could not accept");
+            let mut stream = acceptor.accept(stream).await.expect("TLS handshake failed");
+            stream.write_all(b"hello").await.expect("could not write");
+            stream.shutdown().await.expect("could not shut down");
+        });
+
+        let stream = TcpStream::connect(addr).await.expect("could not connect");
+        let mut stream = connector(&["http/1.1"]).connect(ServerName::try_from("localhost").unwrap(), stream).await.expect("TLS handshake failed");
+
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+        let mut received = String::new();
+        stream.read_to_string(&mut received).await.expect("could not read");
+        assert_eq!(received, "hello");
+
+        server.await.expect("server failed");
+    }
+
+    #[tokio::test]
+    async fn wrong_hostname_is_rejected() {
+        let acceptor = acceptor(CERT, KEY, &[]).expect("could not load certificate");
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("could not bind");
+        let addr = listener.local_addr().expect("could not get address");
+
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("could not accept");
+            let _ = acceptor.accept(stream).await;
+        });
+
+        let stream = TcpStream::connect(addr).await.expect("could not connect");
+        assert!(connector(&[]).connect(ServerName::try_from("example.com").unwrap(), stream).await.is_err());
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(acceptor("/nonexistent.pem", KEY, &[]).err().is_some_and(|e| e.contains("Could not open /nonexistent.pem")));
+        // a certificate isn't a key
+        assert!(acceptor(CERT, CERT, &[]).err().is_some_and(|e| e.contains("does not contain a private key")));
+        assert!(acceptor(KEY, KEY, &[]).err().is_some_and(|e| e.contains("does not contain any certificates")));
+    }
+}
@@ -0,0 +1,38 @@
+use packet::events::UpdateRequiredEvent;
+
+use crate::config::CONFIG;
+
+/// Parses a version like `1.2.3`, where missing parts count as 0 and pre-release or build suffixes
+/// (`1.2.3-beta.1`, `1.2.3+abc`) are ignored.
+pub fn parse(version: &str) -> Option<(u64, u64, u64)> {
+    let version = version.split(['-', '+']).next()?;
+    let mut parts = version.split('.').map(str::parse::<u64>);
+
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().transpose().ok()?.unwrap_or(0);
+    let patch = parts.next().transpose().ok()?.unwrap_or(0);
+
+    if parts.next().is_some() {
+        return None;
+    }
+
+    Some((major, minor, patch))
+}
+
+/// Returns the event reporting a daemon version outside of the range configured in `daemons`, or
+/// `None` if it is supported. Daemons that don't report a valid version are older than every
+/// supported version.
+pub fn check(version: &str) -> Option<UpdateRequiredEvent> {
+    let config = &CONFIG.daemons;
+    let parsed = parse(version);
+
+    let too_old = parse(&config.min_version).is_some_and(|min| parsed.is_none_or(|version| version < min));
+    let too_new = parse(&config.max_version).is_some_and(|max| parsed.is_some_and(|version| version > max));
+
+    (too_old || too_new).then(|| UpdateRequiredEvent {
+        version: version.to_string(),
+        min_version: config.min_version.clone(),
+        max_version: config.max_version.clone(),
+        refused: config.refuse_unsupported,
+    })
+}
@@ -0,0 +1,462 @@
+use std::{collections::HashMap, hash::{DefaultHasher, Hash, Hasher}, sync::{Arc, OnceLock}, thread, time::Duration};
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use packet::events::EventData;
+use sqlx::types::Uuid;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+use wasmtime::{Engine, Linker, Memory, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+use crate::{config::CONFIG, repository::{self, WasmExtension}};
+
+/// The largest event an extension may return, in bytes.
+const MAX_OUTPUT: usize = 1024 * 1024;
+/// How often the epoch of the engine advances, which is how precise `wasm.timeout_ms` is.
+const EPOCH_TICK: Duration = Duration::from_millis(10);
+/// The most events waiting for the worker, before the fan-out workers wait for it.
+const QUEUE: usize = 1024;
+
+lazy_static! {
+    static ref ENGINE: Result<Engine, String> = {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        config.epoch_interruption(true);
+
+        Engine::new(&config).map_err(|e| format!("Could not create WASM engine: {}", e))
+    };
+
+    /// The compiled extensions of every team with any, in the order they are run
+    static ref EXTENSIONS: DashMap<u32, Arc<[Extension]>> = DashMap::new();
+    /// The team of every daemon, loaded whenever it is synced
+    static ref TEAMS: DashMap<Uuid, u32> = DashMap::new();
+}
+
+/// The worker running the extensions, see `work`.
+static WORKER: OnceLock<mpsc::Sender<Request>> = OnceLock::new();
+
+/// A compiled extension of a team.
+#[derive(Clone)]
+struct Extension {
+    id: u32,
+    name: String,
+    /// The hash of the uploaded module, so it is only compiled again once it changed
+    hash: u64,
+    module: Module,
+}
+
+enum Request {
+    /// Runs extensions on an event of a daemon, see `apply`.
+    Filter {
+        daemon: Uuid,
+        extensions: Arc<[Extension]>,
+        event: Box<EventData>,
+        filtered: oneshot::Sender<Option<EventData>>,
+    },
+    /// Drops the instances of extensions that were deleted.
+    Unload(Vec<u32>),
+}
+
+/// Starts the worker running the extensions, should be called once at startup if `wasm.enabled`
+/// is set.
+pub fn init() -> Result<(), String> {
+    if WORKER.get().is_some() {
+        return Ok(());
+    }
+
+    let engine = ENGINE.as_ref()?.clone();
+    let (tx, rx) = mpsc::channel(QUEUE);
+
+    let ticker = engine.clone();
+    thread::Builder::new().name("wasm-epoch".to_string()).spawn(move || loop {
+        thread::sleep(EPOCH_TICK);
+        ticker.increment_epoch();
+    }).map_err(|e| format!("Could not start WASM epoch ticker: {}", e))?;
+
+    thread::Builder::new().name("wasm".to_string()).spawn(move || work(engine, rx)).map_err(|e| format!("Could not start WASM worker: {}", e))?;
+
+    let _ = WORKER.set(tx);
+
+    Ok(())
+}
+
+/// (Re)loads the team of a daemon and the extensions of the team from the database. Modules that
+/// changed are compiled again, modules that can't be compiled are skipped.
+pub async fn load(uuid: Uuid) -> Result<(), String> {
+    if !CONFIG.wasm.enabled {
+        return Ok(());
+    }
+
+    let Some(team) = repository::get().node_team(uuid).await? else {
+        TEAMS.remove(&uuid);
+        return Ok(());
+    };
+
+    TEAMS.insert(uuid, team);
+
+    let uploaded = repository::get().team_extensions(team).await?;
+    let compiled = EXTENSIONS.get(&team).map(|extensions| Arc::clone(&extensions));
+
+    let previous = compiled.clone();
+    let extensions = tokio::task::spawn_blocking(move || compile_all(team, uploaded, previous.as_deref().unwrap_or_default()))
+        .await
+        .map_err(|e| format!("Could not compile WASM extensions of team {}: {}", team, e))??;
+
+    let unloaded = compiled.iter()
+        .flat_map(|compiled| compiled.iter())
+        .filter(|old| !extensions.iter().any(|extension| extension.id == old.id && extension.hash == old.hash))
+        .map(|old| old.id)
+        .collect::<Vec<_>>();
+
+    if extensions.is_empty() {
+        EXTENSIONS.remove(&team);
+    } else {
+        EXTENSIONS.insert(team, extensions.into());
+    }
+
+    if !unloaded.is_empty()
+        && let Some(worker) = WORKER.get() {
+        worker.send(Request::Unload(unloaded)).await.map_err(|_| "WASM worker has stopped".to_string())?;
+    }
+
+    Ok(())
+}
+
+/// Compiles the uploaded extensions of a team, reusing the ones compiled already.
+fn compile_all(team: u32, uploaded: Vec<WasmExtension>, compiled: &[Extension]) -> Result<Vec<Extension>, String> {
+    let engine = ENGINE.as_ref()?;
+    let mut extensions = Vec::with_capacity(uploaded.len());
+
+    for extension in uploaded.into_iter() {
+        let mut hasher = DefaultHasher::new();
+        extension.module.hash(&mut hasher);
+        let hash = hasher.finish();
+
+        if let Some(compiled) = compiled.iter().find(|compiled| compiled.id == extension.id && compiled.hash == hash) {
+            extensions.push(compiled.clone());
+            continue;
+        }
+
+        match compile(engine, &extension.module) {
+            Ok(module) => {
+                info!("Loaded WASM extension {} of team {}", extension.name, team);
+                extensions.push(Extension {
+                    id: extension.id,
+                    name: extension.name,
+                    hash,
+                    module,
+                });
+            },
+            Err(e) => warn!("Could not load WASM extension {} of team {}: {}", extension.name, team, e),
+        }
+    }
+
+    Ok(extensions)
+}
+
+fn compile(engine: &Engine, module: &[u8]) -> Result<Module, String> {
+    let module = Module::new(engine, module).map_err(|e| format!("Could not compile: {}", e))?;
+
+    // extensions can't reach anything outside of their sandbox
+    if let Some(import) = module.imports().next() {
+        return Err(format!("Imports {}::{}, but extensions can't import anything", import.module(), import.name()));
+    }
+
+    Ok(module)
+}
+
+/// Runs the extensions of the team of a daemon on one of its events, in order. Returns the
+/// transformed event, or `None` if an extension dropped it. Extensions that fail, e.g. because they
+/// ran out of fuel or time, are skipped, so a broken extension doesn't hide events.
+pub async fn filter(uuid: &Uuid, event: EventData) -> Result<Option<EventData>, String> {
+    let Some(extensions) = TEAMS.get(uuid).and_then(|team| EXTENSIONS.get(&*team).map(|extensions| Arc::clone(&extensions))) else {
+        return Ok(Some(event));
+    };
+
+    run(*uuid, extensions, event).await
+}
+
+/// Hands an event to the worker, so extensions never block the tokio runtime, and waits until it
+/// was filtered.
+async fn run(daemon: Uuid, extensions: Arc<[Extension]>, event: EventData) -> Result<Option<EventData>, String> {
+    let worker = WORKER.get().ok_or("WASM extensions were not initialized")?;
+    let (tx, rx) = oneshot::channel();
+
+    worker.send(Request::Filter {
+        daemon,
+        extensions,
+        event: Box::new(event),
+        filtered: tx,
+    }).await.map_err(|_| "WASM worker has stopped".to_string())?;
+
+    rx.await.map_err(|_| "WASM worker stopped before filtering the event".to_string())
+}
+
+/// Runs the extensions on the events it receives, on a thread of its own. Each extension keeps its
+/// instance between events, until it fails or changes.
+fn work(engine: Engine, mut rx: mpsc::Receiver<Request>) {
+    let mut slots = HashMap::<u32, Slot>::new();
+
+    while let Some(request) = rx.blocking_recv() {
+        match request {
+            Request::Filter { daemon, extensions, event, filtered } => {
+                let _ = filtered.send(apply(&engine, &mut slots, daemon, &extensions, *event));
+            },
+            Request::Unload(ids) => for id in ids.iter() {
+                slots.remove(id);
+            },
+        }
+    }
+}
+
+fn apply(engine: &Engine, slots: &mut HashMap<u32, Slot>, daemon: Uuid, extensions: &[Extension], event: EventData) -> Option<EventData> {
+    let mut event = event;
+
+    for extension in extensions.iter() {
+        let slot = slots.entry(extension.id).or_insert_with(|| Slot::new(extension.hash));
+
+        if slot.hash != extension.hash {
+            *slot = Slot::new(extension.hash);
+        }
+
+        match slot.run(engine, &extension.module, &event) {
+            Ok(filtered) => {
+                if slot.failing {
+                    info!("WASM extension {} recovered", extension.name);
+                    slot.failing = false;
+                }
+
+                match filtered {
+                    Some(transformed) => event = transformed,
+                    None => {
+                        debug!("WASM extension {} dropped {:?} event of daemon {}", extension.name, event.event_type(), daemon);
+                        return None;
+                    },
+                }
+            },
+            Err(e) => match slot.failing {
+                true => debug!("WASM extension {} failed on event of daemon {}: {}", extension.name, daemon, e),
+                false => {
+                    warn!("WASM extension {} failed on event of daemon {}, skipping it until it recovers: {}", extension.name, daemon, e);
+                    slot.failing = true;
+                },
+            },
+        }
+    }
+
+    Some(event)
+}
+
+/// The state of an extension on the worker.
+struct Slot {
+    hash: u64,
+    /// The instance of the extension, created on the first event and dropped once it fails
+    sandbox: Option<Sandbox>,
+    /// Whether the extension failed on the last event, so failures are only warned about once
+    failing: bool,
+}
+
+impl Slot {
+    fn new(hash: u64) -> Self {
+        Self {
+            hash,
+            sandbox: None,
+            failing: false,
+        }
+    }
+
+    fn run(&mut self, engine: &Engine, module: &Module, event: &EventData) -> Result<Option<EventData>, String> {
+        let input = serde_json::to_vec(event).map_err(|e| format!("Could not serialize event: {}", e))?;
+
+        // the instance is in an unknown state after a trap, so the next event gets a fresh one
+        let mut sandbox = match self.sandbox.take() {
+            Some(sandbox) => sandbox,
+            None => Sandbox::new(engine, module)?,
+        };
+
+        let output = sandbox.call(&input)?;
+        self.sandbox = Some(sandbox);
+
+        output.map(|output| serde_json::from_slice(&output).map_err(|e| format!("Returned an invalid event: {}", e))).transpose()
+    }
+}
+
+/// An instance of an extension. The module exports its `memory`, an `alloc(len: u32) -> u32`
+/// function returning a buffer for the JSON of the event, and a `filter(ptr: u32, len: u32) -> u64`
+/// function returning the pointer (upper 32 bits) and length (lower 32 bits) of the JSON of the
+/// transformed event, or a length of `0` to drop the event. The instance is reused for later
+/// events, so buffers it doesn't free count against `wasm.max_memory` until it fails.
+struct Sandbox {
+    store: Store<StoreLimits>,
+    memory: Memory,
+    alloc: TypedFunc<u32, u32>,
+    filter: TypedFunc<(u32, u32), u64>,
+}
+
+impl Sandbox {
+    fn new(engine: &Engine, module: &Module) -> Result<Self, String> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(CONFIG.wasm.max_memory)
+            .instances(1)
+            .build();
+
+        let mut store = Store::new(engine, limits);
+        store.limiter(|limits: &mut StoreLimits| limits);
+        Self::limit(&mut store)?;
+
+        let instance = Linker::new(engine).instantiate(&mut store, module).map_err(|e| format!("Could not instantiate: {}", e))?;
+        let memory = instance.get_memory(&mut store, "memory").ok_or("Module does not export its memory")?;
+        let alloc = instance.get_typed_func::<u32, u32>(&mut store, "alloc").map_err(|e| e.to_string())?;
+        let filter = instance.get_typed_func::<(u32, u32), u64>(&mut store, "filter").map_err(|e| e.to_string())?;
+
+        Ok(Self {
+            store,
+            memory,
+            alloc,
+            filter,
+        })
+    }
+
+    /// Gives the extension `wasm.fuel` fuel and `wasm.timeout_ms` milliseconds for the next event.
+    fn limit(store: &mut Store<StoreLimits>) -> Result<(), String> {
+        store.set_fuel(CONFIG.wasm.fuel).map_err(|e| e.to_string())?;
+        store.set_epoch_deadline(CONFIG.wasm.timeout_ms.div_ceil(EPOCH_TICK.as_millis() as u64));
+
+        Ok(())
+    }
+
+    /// Passes the JSON of an event to the extension, and returns the JSON it returned, if any.
+    fn call(&mut self, input: &[u8]) -> Result<Option<Vec<u8>>, String> {
+        Self::limit(&mut self.store)?;
+
+        let ptr = self.alloc.call(&mut self.store, input.len() as u32).map_err(|e| format!("alloc failed: {}", e))?;
+        self.memory.write(&mut self.store, ptr as usize, input).map_err(|e| format!("Could not write event: {}", e))?;
+
+        let res = self.filter.call(&mut self.store, (ptr, input.len() as u32)).map_err(|e| format!("filter failed: {}", e))?;
+        let (ptr, len) = ((res >> 32) as usize, (res & 0xffff_ffff) as usize);
+
+        if len == 0 {
+            return Ok(None);
+        }
+
+        if len > MAX_OUTPUT {
+            return Err(format!("Returned {} bytes, more than the {} allowed", len, MAX_OUTPUT));
+        }
+
+        let mut output = vec![0; len];
+        self.memory.read(&self.store, ptr, &mut output).map_err(|e| format!("Could not read event: {}", e))?;
+
+        Ok(Some(output))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use packet::events::ServerLogEvent;
+
+    use super::*;
+
+    /// Hands the event back unchanged, or drops every second event if `drop` is set, to show that
+    /// the instance is kept between events.
+    fn passthrough(drop: bool) -> String {
+        format!(r#"
+            (module
+                (memory (export "memory") 1)
+                (global $calls (mut i32) (i32.const 0))
+                (func (export "alloc") (param i32) (result i32)
+                    i32.const 1024)
+                (func (export "filter") (param $ptr i32) (param $len i32) (result i64)
+                    global.get $calls
+                    i32.const 1
+                    i32.add
+                    global.set $calls
+                    (if (i32.and (i32.const {}) (i32.eqz (i32.rem_u (global.get $calls) (i32.const 2))))
+                        (then (return (i64.const 0))))
+                    (i64.or
+                        (i64.shl (i64.extend_i32_u (local.get $ptr)) (i64.const 32))
+                        (i64.extend_i32_u (local.get $len)))))
+        "#, drop as u8)
+    }
+
+    /// Replaces every event with the logs of server 2.
+    const REPLACE: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (data (i32.const 0) "{\"ServerLog\":{\"server\":2,\"lines\":[]}}")
+            (func (export "alloc") (param i32) (result i32)
+                i32.const 1024)
+            (func (export "filter") (param i32 i32) (result i64)
+                i64.const 37))
+    "#;
+
+    /// Never returns, until it runs out of fuel.
+    const SPIN: &str = r#"
+        (module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32)
+                i32.const 1024)
+            (func (export "filter") (param i32 i32) (result i64)
+                (loop $spin
+                    br $spin)
+                i64.const 0))
+    "#;
+
+    fn extensions(modules: &[&str]) -> Arc<[Extension]> {
+        let uploaded = modules.iter().enumerate().map(|(i, module)| WasmExtension {
+            id: i as u32,
+            name: format!("test-{}", i),
+            module: module.as_bytes().to_vec(),
+        }).collect();
+
+        let compiled = compile_all(1, uploaded, &[]).expect("could not compile extensions");
+        assert_eq!(compiled.len(), modules.len());
+
+        compiled.into()
+    }
+
+    fn server_log(server: u32) -> EventData {
+        EventData::ServerLog(ServerLogEvent {
+            server,
+            lines: Vec::new(),
+            skipped: 0,
+        })
+    }
+
+    #[tokio::test]
+    async fn extensions_transform_and_drop_events() {
+        init().expect("could not start worker");
+
+        let daemon = Uuid::from_u128(1);
+        let extensions = extensions(&[&passthrough(false), REPLACE, &passthrough(true)]);
+
+        let first = run(daemon, Arc::clone(&extensions), server_log(1)).await.expect("could not filter event");
+        assert!(matches!(first, Some(EventData::ServerLog(ServerLogEvent { server: 2, .. }))));
+
+        let second = run(daemon, Arc::clone(&extensions), server_log(1)).await.expect("could not filter event");
+        assert!(second.is_none());
+
+        let third = run(daemon, extensions, server_log(1)).await.expect("could not filter event");
+        assert!(matches!(third, Some(EventData::ServerLog(ServerLogEvent { server: 2, .. }))));
+    }
+
+    #[tokio::test]
+    async fn failing_extensions_are_skipped() {
+        init().expect("could not start worker");
+
+        let daemon = Uuid::from_u128(2);
+        let extensions = extensions(&[SPIN, &passthrough(false)]);
+
+        for _ in 0..2 {
+            let event = run(daemon, Arc::clone(&extensions), server_log(1)).await.expect("could not filter event");
+            assert!(matches!(event, Some(EventData::ServerLog(ServerLogEvent { server: 1, .. }))));
+        }
+    }
+
+    #[test]
+    fn imports_are_rejected() {
+        let engine = ENGINE.as_ref().expect("could not create engine");
+
+        let err = compile(engine, br#"(module (import "env" "exit" (func)))"#).expect_err("module with imports compiled");
+        assert!(err.contains("env::exit"));
+    }
+}
@@ -0,0 +1,157 @@
+use std::time::Duration;
+
+use dashmap::DashMap;
+use lazy_static::lazy_static;
+use tokio::time::{Instant, MissedTickBehavior};
+use tracing::{error, info, warn};
+
+use crate::{config::CONFIG, repository};
+
+/// How long requests to the heartbeat and alert URLs may take.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long the probe for event loop lag sleeps, see `measure_lag`.
+const LAG_PROBE: Duration = Duration::from_millis(100);
+
+lazy_static! {
+    /// Whether the listener of each `Server`, by tracing name, is accepting connections.
+    static ref LISTENERS: DashMap<&'static str, bool> = DashMap::new();
+    static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build().unwrap_or_default();
+}
+
+/// Records whether a listener is accepting connections, see `Server::start`.
+pub fn set_listening(name: &'static str, listening: bool) {
+    LISTENERS.insert(name, listening);
+}
+
+/// Checks the health of the server every `watchdog.interval` seconds. While it is healthy,
+/// `watchdog.heartbeat_url` is requested after every check, so an external dead man's switch
+/// notices once the server (or its host) goes down. Whenever the server becomes unhealthy or
+/// recovers, the problems are posted to `watchdog.alert_url`, which doesn't depend on the
+/// database.
+pub async fn run() {
+    let config = &CONFIG.watchdog;
+
+    if config.heartbeat_url.is_none() && config.alert_url.is_none() {
+        info!("Watchdog has nowhere to report to, only logging problems");
+    }
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval));
+    // a slow check delays the next one, rather than making up for it with a burst of checks
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+    let mut healthy = true;
+
+    loop {
+        interval.tick().await;
+
+        let lag = measure_lag().await;
+        let problems = check(lag).await;
+
+        if problems.is_empty() {
+            if let Some(url) = config.heartbeat_url.as_ref()
+                && let Err(e) = HTTP_CLIENT.get(url).send().await.and_then(|res| res.error_for_status()) {
+                warn!("Could not send watchdog heartbeat: {}", e);
+            }
+        } else {
+            warn!("Watchdog found problems: {}", problems.join(", "));
+        }
+
+        if problems.is_empty() != healthy {
+            healthy = problems.is_empty();
+
+            if healthy {
+                info!("Server recovered");
+            }
+
+            alert(healthy, &problems).await;
+        }
+    }
+}
+
+/// Measures how much later than requested a short sleep wakes up, which is how long the runtime is
+/// too busy to poll a task that is ready. Unlike the ticks of the interval, this doesn't include the
+/// time the previous check took.
+async fn measure_lag() -> Duration {
+    let start = Instant::now();
+    tokio::time::sleep(LAG_PROBE).await;
+
+    start.elapsed().saturating_sub(LAG_PROBE)
+}
+
+/// Returns a problem if the event loop lags more than `max` behind.
+fn lag_problem(lag: Duration, max: Duration) -> Option<String> {
+    (lag > max).then(|| format!("event loop lags {}ms behind", lag.as_millis()))
+}
+
+/// Returns the problems of the server, or none if it is healthy.
+async fn check(lag: Duration) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    for listener in LISTENERS.iter() {
+        if !*listener.value() {
+            problems.push(format!("{} listener is not accepting connections", listener.key()));
+        }
+    }
+
+    if let Err(e) = repository::get().ping().await {
+        problems.push(format!("database is unreachable: {}", e));
+    }
+
+    problems.extend(lag_problem(lag, Duration::from_millis(CONFIG.watchdog.max_lag_ms)));
+
+    problems
+}
+
+/// Posts the health of the server to `watchdog.alert_url`, if configured.
+pub async fn alert(healthy: bool, problems: &[String]) {
+    let Some(url) = CONFIG.watchdog.alert_url.as_ref() else {
+        return;
+    };
+
+    let body = serde_json::json!({
+        "healthy": healthy,
+        "problems": problems,
+        "version": env!("CARGO_PKG_VERSION"),
+    });
+
+    match HTTP_CLIENT.post(url).header("Content-Type", "application/json").body(body.to_string()).send().await {
+        Ok(res) if !res.status().is_success() => error!("Watchdog alert URL responded with {}", res.status()),
+        Ok(_) => (),
+        Err(e) => error!("Could not send watchdog alert: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lag_measured_while_runtime_blocked() {
+        let probe = tokio::spawn(measure_lag());
+
+        // lets the probe start sleeping, then keeps the runtime from waking it up
+        tokio::task::yield_now().await;
+        std::thread::sleep(LAG_PROBE * 2);
+
+        let lag = probe.await.expect("probe panicked");
+        assert!(lag >= LAG_PROBE - Duration::from_millis(10), "lag of {:?} not measured", lag);
+    }
+
+    #[tokio::test]
+    async fn slow_work_before_probe_not_counted() {
+        // e.g. a slow database check, which doesn't block the runtime
+        tokio::time::sleep(LAG_PROBE * 2).await;
+
+        let lag = measure_lag().await;
+        assert!(lag < LAG_PROBE, "lag of {:?} measured on an idle runtime", lag);
+    }
+
+    #[test]
+    fn lag_over_max_is_problem() {
+        let max = Duration::from_millis(500);
+
+        assert_eq!(lag_problem(Duration::from_millis(500), max), None);
+        assert_eq!(lag_problem(Duration::from_millis(501), max), Some("event loop lags 501ms behind".to_string()));
+    }
+}
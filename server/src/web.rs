@@ -1,10 +1,11 @@
 use std::{borrow::Borrow, net::SocketAddr, sync::Arc};
 
 use async_trait::async_trait;
-use packet::{web_server::{auth::WSAuthPacket, handshake_response::WSHandshakeResponsePacket, listen::WSListenPacket, sync::WSSyncPacket}, Packet, ID};
-use tracing::{debug, info, instrument};
+use packet::{ack, close::CloseReason, heartbeat::PongPacket, subprotocol::Encoding, web_server::{auth::WSAuthPacket, cancel_task::WSCancelTaskPacket, command::WSCommandPacket, export_spec::WSExportSpecPacket, handshake_response::WSHandshakeResponsePacket, import_spec::WSImportSpecPacket, listen::WSListenPacket, mark_notifications_read::WSMarkNotificationsReadPacket, place_server::WSPlaceServerPacket, query_connections::WSQueryConnectionsPacket, query_logs::WSQueryLogsPacket, query_metrics::WSQueryMetricsPacket, query_notifications::WSQueryNotificationsPacket, query_snapshot::WSQuerySnapshotPacket, query_stats::WSQueryStatsPacket, query_tasks::WSQueryTasksPacket, query_team_usage::WSQueryTeamUsagePacket, query_top::WSQueryTopPacket, query_usage::WSQueryUsagePacket, server_metadata::WSServerMetadataPacket, sync::WSSyncPacket, sync_group::WSSyncGroupPacket, terminal_close::WSTerminalClosePacket, terminal_input::WSTerminalInputPacket, terminal_open::WSTerminalOpenPacket, unlisten::WSUnlistenPacket, window_update::WSWindowUpdatePacket}, Packet, ID};
+use tokio_tungstenite::tungstenite::handshake::server::Request;
+use tracing::{debug, info, instrument, warn};
 
-use crate::{config::CONFIG, db, encryption::DECRYPTER, server::Server, state::{State, Tx, WebKeyCache}};
+use crate::{auth, config::CONFIG, encryption::DECRYPTER, rate_limit::PacketClass, server::Server, sessions::{self, Peer, Session}, state::{State, Tx, WebKeyCache}};
 
 /// WebServer is a WebSocket server (implemented by the `Server` trait) that listens for web
 /// (frontend) connections.
@@ -12,10 +13,6 @@ pub struct WebServer {
     state: Arc<State>,
 }
 
-struct PublicKeyQuery {
-    user_public_key: String,
-}
-
 impl WebServer {
     /// Creates a new `WebServer` instance, with the given `State`.
     pub fn new(state: Arc<State>) -> Self {
@@ -24,25 +21,30 @@ impl WebServer {
         }
     }
 
-    async fn query_user_public_key(&self, user_id: u32) -> Result<Arc<Vec<u8>>, String> {
+    async fn query_user_public_key(&self, user_id: u32) -> Result<Option<Arc<Vec<u8>>>, String> {
         {
             let cache: &WebKeyCache = self.state.web_key_cache.borrow();
             if let Some(v) = cache.get(&user_id) {
-                return Ok(v.clone());
+                return Ok(Some(v.clone()));
             }
         }
 
-        let res = sqlx::query_as!(PublicKeyQuery, "SELECT user_public_key FROM aesterisk.users WHERE user_id = $1", user_id as i32).fetch_one(db::get()?).await.map_err(|_| format!("User with ID {} does not exist", user_id))?;
+        let Some(key) = auth::get().user_public_key(user_id).await? else {
+            return Ok(None);
+        };
 
         let cache: &WebKeyCache = self.state.web_key_cache.borrow();
-        cache.insert(user_id, Arc::new(res.user_public_key.into_bytes()));
-        Ok(cache.get(&user_id).ok_or("key should be in cache")?.clone())
+        cache.insert(user_id, Arc::new(key.into_bytes()));
+        Ok(Some(cache.get(&user_id).ok_or("key should be in cache")?.clone()))
     }
 
     async fn handle_auth(&self, auth_packet: WSAuthPacket, addr: SocketAddr) -> Result<(), String> {
-        let key = self.query_user_public_key(auth_packet.user_id).await?;
+        let Some(key) = self.query_user_public_key(auth_packet.user_id).await? else {
+            self.state.disconnect_web(addr, CloseReason::Revoked)?;
+            return Err(format!("User with ID {} does not exist", auth_packet.user_id));
+        };
 
-        self.state.send_web_handshake_request(&addr, auth_packet.user_id, key)
+        self.state.send_web_handshake_request(&addr, auth_packet.user_id, key, auth_packet.features)
     }
 
     async fn handle_handshake_response(&self, handshake_reponse_packet: WSHandshakeResponsePacket, addr: SocketAddr) -> Result<(), String> {
@@ -59,10 +61,153 @@ impl WebServer {
         self.state.send_listen(addr, listen_packet.events).await
     }
 
-    async fn handle_sync(&self, sync_packet: WSSyncPacket) -> Result<(), String> {
+    async fn handle_unlisten(&self, unlisten_packet: WSUnlistenPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.remove_listen(addr, unlisten_packet.events).await
+    }
+
+    async fn handle_sync(&self, sync_packet: WSSyncPacket, addr: SocketAddr, request: Option<u64>) -> Result<(), String> {
         debug!("Handling sync packet: {:#?}", sync_packet);
 
-        self.state.sync_daemon(sync_packet.daemon, None).await
+        let daemon = sync_packet.daemon;
+
+        if self.state.throttle_sync(addr, daemon)? {
+            return Ok(());
+        }
+
+        match self.state.queue_sync(daemon, addr, request) {
+            Some(delay) if delay.is_zero() => self.state.flush_sync(daemon).await,
+            Some(delay) => {
+                debug!("Deferring sync of {} by {:?}", daemon, delay);
+
+                let state = self.state.clone();
+                tokio::spawn(async move {
+                    tokio::time::sleep(delay).await;
+
+                    if let Err(e) = state.flush_sync(daemon).await {
+                        warn!("Deferred sync of {} failed: {}", daemon, e);
+                    }
+                });
+
+                Ok(())
+            },
+            None => Ok(()),
+        }
+    }
+
+    async fn handle_sync_group(&self, sync_group_packet: WSSyncGroupPacket, addr: SocketAddr) -> Result<(), String> {
+        debug!("Handling sync group packet: {:#?}", sync_group_packet);
+
+        self.state.sync_group(addr, sync_group_packet.group).await
+    }
+
+    async fn handle_export_spec(&self, export_spec_packet: WSExportSpecPacket, addr: SocketAddr) -> Result<(), String> {
+        debug!("Handling export spec packet: {:#?}", export_spec_packet);
+
+        self.state.export_spec(addr, export_spec_packet).await
+    }
+
+    async fn handle_server_metadata(&self, server_metadata_packet: WSServerMetadataPacket, addr: SocketAddr) -> Result<(), String> {
+        debug!("Handling server metadata packet: {:#?}", server_metadata_packet);
+
+        self.state.server_metadata(addr, server_metadata_packet).await
+    }
+
+    async fn handle_import_spec(&self, import_spec_packet: WSImportSpecPacket, addr: SocketAddr) -> Result<(), String> {
+        debug!("Handling import spec packet");
+
+        self.state.import_spec(addr, import_spec_packet).await
+    }
+
+    async fn handle_place_server(&self, place_server_packet: WSPlaceServerPacket, addr: SocketAddr) -> Result<(), String> {
+        debug!("Handling place server packet: {:#?}", place_server_packet);
+
+        self.state.place_server(addr, place_server_packet).await
+    }
+
+    async fn handle_query_logs(&self, query_logs_packet: WSQueryLogsPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.query_logs(addr, query_logs_packet).await
+    }
+
+    async fn handle_query_stats(&self, query_stats_packet: WSQueryStatsPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.query_stats(addr, query_stats_packet).await
+    }
+
+    async fn handle_query_snapshot(&self, query_snapshot_packet: WSQuerySnapshotPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_snapshot(addr, query_snapshot_packet)
+    }
+
+    async fn handle_query_team_usage(&self, query_team_usage_packet: WSQueryTeamUsagePacket, addr: SocketAddr) -> Result<(), String> {
+        debug!("Handling query team usage packet: {:#?}", query_team_usage_packet);
+
+        self.state.query_team_usage(addr, query_team_usage_packet).await
+    }
+
+    async fn handle_query_tasks(&self, query_tasks_packet: WSQueryTasksPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.query_tasks(addr, query_tasks_packet).await
+    }
+
+    async fn handle_cancel_task(&self, cancel_task_packet: WSCancelTaskPacket, addr: SocketAddr) -> Result<(), String> {
+        debug!("Handling cancel task packet: {:#?}", cancel_task_packet);
+
+        self.state.cancel_task(addr, cancel_task_packet).await
+    }
+
+    async fn handle_query_top(&self, query_top_packet: WSQueryTopPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.query_top(addr, query_top_packet).await
+    }
+
+    async fn handle_query_usage(&self, query_usage_packet: WSQueryUsagePacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.query_usage(addr, query_usage_packet).await
+    }
+
+    async fn handle_query_connections(&self, query_connections_packet: WSQueryConnectionsPacket, addr: SocketAddr) -> Result<(), String> {
+        debug!("Handling query connections packet: {:#?}", query_connections_packet);
+
+        self.state.query_connections(addr, query_connections_packet).await
+    }
+
+    async fn handle_query_notifications(&self, query_notifications_packet: WSQueryNotificationsPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.query_notifications(addr, query_notifications_packet).await
+    }
+
+    async fn handle_mark_notifications_read(&self, mark_notifications_read_packet: WSMarkNotificationsReadPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.mark_notifications_read(addr, mark_notifications_read_packet).await
+    }
+
+    async fn handle_command(&self, command_packet: WSCommandPacket, addr: SocketAddr) -> Result<(), String> {
+        debug!("Handling command packet: {:#?}", command_packet);
+
+        self.state.send_command(addr, command_packet).await
+    }
+
+    async fn handle_terminal_open(&self, terminal_open_packet: WSTerminalOpenPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.open_terminal(addr, terminal_open_packet).await
+    }
+
+    async fn handle_terminal_input(&self, terminal_input_packet: WSTerminalInputPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.send_terminal_input(addr, terminal_input_packet)
+    }
+
+    async fn handle_terminal_close(&self, terminal_close_packet: WSTerminalClosePacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.close_terminal(addr, terminal_close_packet)
+    }
+
+    async fn handle_window_update(&self, window_update_packet: WSWindowUpdatePacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.grant_web_window(addr, window_update_packet)
+    }
+
+    async fn handle_pong(&self, pong_packet: PongPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.receive_pong(&addr, pong_packet)
+    }
+
+    async fn handle_ack(&self, packet: Packet, addr: SocketAddr) -> Result<(), String> {
+        let (request_id, res) = ack::outcome(packet)?;
+
+        self.state.receive_web_ack(&addr, request_id, res)
+    }
+
+    async fn handle_query_metrics(&self, query_metrics_packet: WSQueryMetricsPacket, addr: SocketAddr) -> Result<(), String> {
+        self.state.query_metrics(addr, query_metrics_packet).await
     }
 }
 
@@ -72,20 +217,36 @@ impl Server for WebServer {
         &CONFIG.sockets.web
     }
 
+    fn uses_tls(&self) -> bool {
+        CONFIG.tls.web
+    }
+
     fn get_tracing_name(&self) -> &'static str {
         "web"
     }
 
-    fn get_issuer(&self) ->  &'static str {
-        "aesterisk/web"
+    fn get_issuers(&self) -> &'static [String] {
+        &CONFIG.issuers.web
     }
 
     fn get_decrypter(&self) ->  &'static josekit::jwe::alg::rsaes::RsaesJweDecrypter {
         &DECRYPTER
     }
 
-    async fn on_accept(&self, addr: SocketAddr, tx: Tx) -> Result<(), String> {
-        self.state.add_web(addr, tx);
+    fn check_upgrade(&self, request: &Request) -> Result<(), String> {
+        // browsers always send the origin of the page opening the connection, which prevents
+        // other sites from connecting on behalf of a user
+        let origin = request.headers().get("origin").ok_or("Missing Origin header")?.to_str().map_err(|_| "Invalid Origin header")?;
+
+        if !CONFIG.allowed_origins().iter().any(|allowed| allowed == origin) {
+            return Err(format!("Origin {} is not allowed", origin));
+        }
+
+        Ok(())
+    }
+
+    async fn on_accept(&self, addr: SocketAddr, tx: Tx, encoding: Encoding) -> Result<(), String> {
+        self.state.add_web(addr, tx, encoding);
 
         Ok(())
     }
@@ -94,24 +255,124 @@ impl Server for WebServer {
         self.state.remove_web(addr).await
     }
 
+    async fn on_session_end(&self, addr: SocketAddr, session: Session) -> Result<(), String> {
+        match self.state.authenticated_web_user(&addr) {
+            Some(user_id) => sessions::record(Peer::Web(user_id), addr, session).await,
+            None => Ok(()),
+        }
+    }
+
+    fn is_authenticated(&self, addr: &SocketAddr) -> bool {
+        self.state.authenticated_web_user(addr).is_some()
+    }
+
     async fn on_decrypt_error(&self, addr: SocketAddr) -> Result<(), String> {
-        self.state.disconnect_web(addr)
+        self.state.disconnect_web(addr, CloseReason::ProtocolError)
+    }
+
+    async fn on_handled(&self, addr: SocketAddr, request_id: u64, res: &Result<(), String>) -> Result<(), String> {
+        self.state.ack_web(&addr, request_id, res)
+    }
+
+    async fn on_heartbeat(&self, addr: SocketAddr) -> Result<(), String> {
+        self.state.ping_web(&addr)
     }
 
     #[instrument("web", skip(self, packet))]
     async fn on_packet(&self, packet: Packet, addr: SocketAddr) -> Result<(), String> {
+        if let Some(class) = PacketClass::of(&packet.id)
+            && self.state.throttle_packet(addr, class, packet.request_id)? {
+            return Ok(());
+        }
+
         match packet.id {
             ID::WSAuth => {
-                self.handle_auth(WSAuthPacket::parse(packet).ok_or("Could not parse WSAuthPacket")?, addr).await
+                self.handle_auth(packet.payload()?, addr).await
             },
             ID::WSHandshakeResponse => {
-                self.handle_handshake_response(WSHandshakeResponsePacket::parse(packet).ok_or("Could not parse WSHandshakeResponsePacket")?, addr).await
+                self.handle_handshake_response(packet.payload()?, addr).await
             }
             ID::WSListen => {
-                self.handle_listen(WSListenPacket::parse(packet).ok_or("Could not parse WSListenPacket")?, addr).await
+                self.handle_listen(packet.payload()?, addr).await
+            },
+            ID::WSUnlisten => {
+                self.handle_unlisten(packet.payload()?, addr).await
             },
             ID::WSSync => {
-                self.handle_sync(WSSyncPacket::parse(packet).ok_or("Could not parse WSSyncPacket")?).await
+                let request = packet.request_id;
+                self.handle_sync(packet.payload()?, addr, request).await
+            }
+            ID::WSSyncGroup => {
+                self.handle_sync_group(packet.payload()?, addr).await
+            }
+            ID::WSPlaceServer => {
+                self.handle_place_server(packet.payload()?, addr).await
+            }
+            ID::WSExportSpec => {
+                self.handle_export_spec(packet.payload()?, addr).await
+            }
+            ID::WSImportSpec => {
+                self.handle_import_spec(packet.payload()?, addr).await
+            }
+            ID::WSServerMetadata => {
+                self.handle_server_metadata(packet.payload()?, addr).await
+            }
+            ID::WSQueryLogs => {
+                self.handle_query_logs(packet.payload()?, addr).await
+            }
+            ID::WSQueryStats => {
+                self.handle_query_stats(packet.payload()?, addr).await
+            },
+            ID::WSQuerySnapshot => {
+                self.handle_query_snapshot(packet.payload()?, addr).await
+            },
+            ID::WSQueryTop => {
+                self.handle_query_top(packet.payload()?, addr).await
+            }
+            ID::WSQueryTasks => {
+                self.handle_query_tasks(packet.payload()?, addr).await
+            }
+            ID::WSCancelTask => {
+                self.handle_cancel_task(packet.payload()?, addr).await
+            }
+            ID::WSQueryUsage => {
+                self.handle_query_usage(packet.payload()?, addr).await
+            }
+            ID::WSQueryTeamUsage => {
+                self.handle_query_team_usage(packet.payload()?, addr).await
+            }
+            ID::WSQueryMetrics => {
+                self.handle_query_metrics(packet.payload()?, addr).await
+            }
+            ID::WSQueryConnections => {
+                self.handle_query_connections(packet.payload()?, addr).await
+            }
+            ID::WSQueryNotifications => {
+                self.handle_query_notifications(packet.payload()?, addr).await
+            }
+            ID::WSMarkNotificationsRead => {
+                self.handle_mark_notifications_read(packet.payload()?, addr).await
+            }
+            ID::WSCommand => {
+                self.handle_command(packet.payload()?, addr).await
+            }
+            ID::WSTerminalOpen => {
+                self.handle_terminal_open(packet.payload()?, addr).await
+            }
+            ID::WSTerminalInput => {
+                self.handle_terminal_input(packet.payload()?, addr).await
+            }
+            ID::WSTerminalClose => {
+                self.handle_terminal_close(packet.payload()?, addr).await
+            }
+            ID::WSWindowUpdate => {
+                self.handle_window_update(packet.payload()?, addr).await
+            }
+            ID::Pong => {
+                self.handle_pong(packet.payload()?, addr).await
+            }
+            ID::Ack | ID::Nack => {
+                self.handle_ack(packet, addr).await
             }
             _ => {
                 Err(format!("Should not receive [SD]* packet: {:?}", packet.id))
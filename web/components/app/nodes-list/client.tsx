@@ -6,7 +6,7 @@ import { columns } from "./columns";
 import { NodeData } from ".";
 import { useCallback, useMemo, useRef, useState } from "react";
 import useEvent from "@/hooks/event";
-import { EventOf, EventType } from "@/packets/events";
+import { EventOf, EventType, ServerCounts } from "@/packets/events";
 import { Plus } from "lucide-react";
 import { DialogClose, DialogDescription, DialogHeader, DialogTitle } from "@/components/ui/dialog";
 import { StepAccordion, StepAccordionContent, StepAccordionHeader, StepAccordionItem } from "@/components/ui/accordion-steps";
@@ -20,6 +20,19 @@ import { Form, FormControl, FormField, FormItem } from "@/components/ui/form";
 import { insertNode } from "./actions";
 import { Textarea } from "@/components/ui/textarea";
 
+function countServers(counts?: ServerCounts | null): NodeData["servers"] {
+	if(!counts) {
+		// eslint-disable-next-line no-undefined
+		return undefined;
+	}
+
+	return {
+		online: counts.healthy + counts.starting,
+		failed: counts.unhealthy + counts.restarting,
+		offline: counts.stopped + counts.stopping,
+	};
+}
+
 export default function Client({ nodes, teamID }: {
 	nodes: Node[];
 	teamID: number;
@@ -63,6 +76,11 @@ export default function Client({ nodes, teamID }: {
 					total: event.event.NodeStatus.stats?.total_memory,
 				}
 				: node.memory,
+			servers: node.uuid === event.daemon ? countServers(event.event.NodeStatus.servers) : node.servers,
+			// eslint-disable-next-line no-undefined
+			maxServers: node.uuid === event.daemon ? event.event.NodeStatus.max_servers ?? undefined : node.maxServers,
+			// eslint-disable-next-line no-undefined
+			info: node.uuid === event.daemon ? event.event.NodeStatus.info ?? undefined : node.info,
 			cpu: node.uuid === event.daemon ? event.event.NodeStatus.stats?.cpu : node.cpu,
 			storage: node.uuid === event.daemon
 				? {
@@ -82,6 +100,15 @@ export default function Client({ nodes, teamID }: {
 
 	useEvent(EventType.NodeStatus, updateStatus, nodeUuids);
 
+	const updateRequired = useCallback((event: EventOf<EventType.UpdateRequired>) => {
+		setNodeData((data) => data.map((node) => ({
+			...node,
+			updateRequired: node.uuid === event.daemon ? event.event.UpdateRequired : node.updateRequired,
+		})));
+	}, []);
+
+	useEvent(EventType.UpdateRequired, updateRequired, nodeUuids);
+
 	const hasCreatedUuid = useRef(false);
 
 	const FormSchema = z.object({
@@ -8,6 +8,24 @@ import { Button } from "@/components/ui/button";
 import { Progress } from "@/components/ui/progress";
 import { Skeleton } from "@/components/ui/skeleton";
 import { NodeData } from ".";
+import { DaemonInfo, UpdateRequiredEvent } from "@/packets/events";
+
+function updateWarning(update: UpdateRequiredEvent) {
+	const version = update.version || "an unknown version";
+	const range = [update.min_version && `at least ${update.min_version}`, update.max_version && `at most ${update.max_version}`].filter(Boolean).join(" and ");
+
+	return `This node runs ${version} of the daemon, but ${range} is required${update.refused ? ", so it was refused" : ""}`;
+}
+
+function rootlessWarning(info: DaemonInfo) {
+	const unsupported = [`host ports below ${info.min_host_port}`];
+
+	if(!info.server_stats) {
+		unsupported.push("CPU and memory stats of servers");
+	}
+
+	return `This node runs rootless Docker, which doesn't support ${unsupported.join(" or ")}`;
+}
 
 export const columns: ColumnDef<NodeData>[] = [
 	{
@@ -52,9 +70,26 @@ export const columns: ColumnDef<NodeData>[] = [
 			}
 
 			const { online, failed, offline } = row.original.servers;
+			// eslint-disable-next-line no-undefined
+			const full = row.original.maxServers !== undefined && online + failed + offline >= row.original.maxServers;
 
 			return (
 				<div className="flex flex-row items-center gap-4">
+					{
+						full && (
+							<span className="text-sm text-amber-500" title={`This node has reached its capacity of ${row.original.maxServers} servers`}>{ "Full" }</span>
+						)
+					}
+					{
+						row.original.updateRequired && (
+							<span className="text-sm text-rose-600" title={updateWarning(row.original.updateRequired)}>{ "Update required" }</span>
+						)
+					}
+					{
+						row.original.info?.rootless && (
+							<span className="text-sm text-amber-500" title={rootlessWarning(row.original.info)}>{ "Rootless" }</span>
+						)
+					}
 					{
 						online > 0 && (
 							<div className="flex flex-row items-center gap-2">
@@ -1,4 +1,5 @@
 import { Suspense } from "react";
+import { DaemonInfo, UpdateRequiredEvent } from "@/packets/events";
 import Loader from "./loader";
 
 export type NodeData = {
@@ -11,6 +12,9 @@ export type NodeData = {
 		failed: number;
 		offline: number;
 	};
+	maxServers?: number;
+	info?: DaemonInfo;
+	updateRequired?: UpdateRequiredEvent;
 	memory?: {
 		used?: number;
 		total?: number;
@@ -6,13 +6,17 @@ import { socketBus } from "@/buses/socket";
 import { dev } from "@/lib/dev";
 import { importPKCS8 } from "jose";
 import { decryptPacket, encryptPacket } from "@/lib/signing";
-import { ID, Version } from "@/packets/packet";
+import { ID, SUBPROTOCOLS, Version } from "@/packets/packet";
 import { SWHandshakeRequestData, WSHandshakeResponsePacket } from "@/packets/handshake";
 import { WSListenPacket } from "@/packets/listen";
-import { SWAuthResponseData, WSAuthPacket } from "@/packets/auth";
+import { SUPPORTED_FEATURES, SWAuthResponseData, WSAuthPacket } from "@/packets/auth";
 import { Event } from "@/packets/events";
 import { eventsBus } from "@/buses/event";
 import { WSSyncPacket } from "@/packets/sync";
+import { SWErrorData } from "@/packets/error";
+import { CloseReason } from "@/packets/close";
+import { PingData, PongPacket } from "@/packets/heartbeat";
+import { AckPacket } from "@/packets/ack";
 
 enum SocketState {
 	NotConnected,
@@ -39,6 +43,10 @@ export const SocketProvider = ({ children, userID, publicKey, privateKey }: Para
 	const [state, setState] = useState(SocketState.NotConnected); // 0 = not connected, 1 = connecting, 2 = connected, 3 = retrying
 	const connecting = useRef(false);
 	const sendConnectedToast = useRef(false);
+	// last sequence number seen per daemon, status event type and server, numbered across all events of the daemon
+	const lastSeq = useRef(new Map<string, number>());
+	// request ids of critical packets already handled, as the server redelivers them until acknowledged
+	const handledCritical = useRef(new Set<number>());
 
 	useEffect(() => {
 		const unsubHandshakeRequest = socketBus.on(ID.SWHandshakeRequest, async({ challenge }) => {
@@ -79,7 +87,33 @@ export const SocketProvider = ({ children, userID, publicKey, privateKey }: Para
 		});
 
 		const unsubEvent = socketBus.on(ID.SWEvent, (event) => {
-			eventsBus.emit(Object.keys(event.event)[0], event);
+			const type = Object.keys(event.event)[0];
+
+			// the sequence numbers of a daemon start over once it reconnects
+			if("NodeStatus" in event.event && !event.event.NodeStatus.online) {
+				for(const key of lastSeq.current.keys()) {
+					if(key.startsWith(`${event.daemon}:`)) lastSeq.current.delete(key);
+				}
+			}
+
+			// the server sends other events in order, only status snapshots can be outdated
+			const snapshot = "NodeStatus" in event.event || "ServerStatus" in event.event;
+
+			if(snapshot && event.seq !== undefined && event.seq !== null) {
+				const server = "ServerStatus" in event.event ? event.event.ServerStatus.server : "";
+				const key = `${event.daemon}:${type}:${server}`;
+				const last = lastSeq.current.get(key);
+
+				// a lower sequence number is a reordered event, which is outdated
+				if(last !== undefined && event.seq <= last) {
+					if(dev()) console.warn("[Socket] Dropping reordered event", key, event.seq, last);
+					return;
+				}
+
+				lastSeq.current.set(key, event.seq);
+			}
+
+			eventsBus.emit(type, event);
 		});
 
 		if(!socket && socketConnectionTries.current < MAX_SOCKET_CONNECTION_TRIES
@@ -98,11 +132,12 @@ export const SocketProvider = ({ children, userID, publicKey, privateKey }: Para
 				throw new Error("NEXT_PUBLIC_SERVER_WEBSOCKET_URL is not defined");
 			}
 
-			const ws = new WebSocket(process.env.NEXT_PUBLIC_SERVER_WEBSOCKET_URL);
+			const ws = new WebSocket(process.env.NEXT_PUBLIC_SERVER_WEBSOCKET_URL, SUBPROTOCOLS);
 
 			ws.onopen = async() => {
 				ws.send(await encryptPacket(WSAuthPacket({
 					user_id: userID,
+					features: SUPPORTED_FEATURES,
 				})));
 			};
 
@@ -110,11 +145,12 @@ export const SocketProvider = ({ children, userID, publicKey, privateKey }: Para
 				if(dev()) console.warn("[Socket] Error:", error);
 			};
 
-			ws.onclose = () => {
+			ws.onclose = (event) => {
+				lastSeq.current.clear();
 				setSocket(null);
 				setState(SocketState.NotConnected);
 				connecting.current = false;
-				if(dev()) console.warn("[Socket] Disconnected");
+				if(dev()) console.warn("[Socket] Disconnected:", CloseReason[event.code] ?? event.code);
 			};
 
 			ws.onmessage = async(event) => {
@@ -123,6 +159,11 @@ export const SocketProvider = ({ children, userID, publicKey, privateKey }: Para
 				if(packet) {
 					if(dev()) console.log("[Socket] Packet", packet);
 
+					if(packet.request_id !== undefined && handledCritical.current.has(packet.request_id)) {
+						ws.send(await encryptPacket(AckPacket(packet.request_id)));
+						return;
+					}
+
 					if(packet.version === Version.V0_1_0) {
 						switch(packet.id) {
 							case ID.SWAuthResponse: {
@@ -137,10 +178,25 @@ export const SocketProvider = ({ children, userID, publicKey, privateKey }: Para
 								socketBus.emit(ID.SWEvent, packet.data as Event);
 								break;
 							}
+							case ID.SWError: {
+								const error = packet.data as SWErrorData;
+								if(dev()) console.warn("[Socket] Error:", error.code, error.message);
+								toast.error(error.message);
+								break;
+							}
+							case ID.Ping: {
+								ws.send(await encryptPacket(PongPacket((packet.data as PingData).nonce)));
+								break;
+							}
 							default: {
 								console.error("UNKNOWN PACKET ID");
 							}
 						}
+
+						if(packet.request_id !== undefined) {
+							handledCritical.current.add(packet.request_id);
+							ws.send(await encryptPacket(AckPacket(packet.request_id)));
+						}
 					} else {
 						console.error("WRONG PACKET PROTOCOL VERSION");
 					}
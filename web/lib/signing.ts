@@ -11,14 +11,15 @@ export async function encryptPacket(packet: object): Promise<string> {
 			enc: "A256GCM",
 		})
 		.setIssuedAt()
-		.setIssuer("aesterisk/web")
+		.setIssuer(process.env.NEXT_PUBLIC_WEB_ISSUER ?? "aesterisk/web")
 		.setExpirationTime("1 minute")
 		.encrypt(await getServerPublicKey());
 }
 
 export async function decryptPacket(packet: string, key: KeyLike): Promise<Packet> {
 	const jwe = await jwtDecrypt(packet, key, {
-		issuer: "aesterisk/server",
+		// comma-separated, to accept multiple issuers while the server's issuer is renamed
+		issuer: process.env.NEXT_PUBLIC_SERVER_ISSUERS?.split(",") ?? "aesterisk/server",
 		keyManagementAlgorithms: ["RSA-OAEP"],
 		contentEncryptionAlgorithms: ["A256GCM"],
 	});
@@ -0,0 +1,30 @@
+import { ID, Packet, Version } from "./packet";
+
+/** `from` and `to` (inclusive) are formatted as YYYY-MM */
+export function WSQueryTeamUsagePacket(from: string, to: string): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSQueryTeamUsage,
+		data: {
+			from,
+			to,
+		},
+	} satisfies Packet;
+}
+
+export type Usage = {
+	cpu_core_hours: number;
+	memory_gb_hours: number;
+	storage_gb_hours: number;
+};
+
+export type MonthlyUsage = Usage & {
+	month: string;
+};
+
+export type SWQueryTeamUsageResponseData = {
+	team: number;
+	months: MonthlyUsage[];
+	total: Usage;
+	error: string | null;
+};
@@ -0,0 +1,16 @@
+import { ID, Packet, Version } from "./packet";
+
+/** Answers a packet sent with a `request_id`, carrying the same request id. `Ack` packets have no data */
+export type NackData = {
+	reason: string;
+};
+
+/** Acknowledges a critical packet, e.g. the result of a sync, so that the server doesn't redeliver it */
+export function AckPacket(requestId: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.Ack,
+		data: {},
+		request_id: requestId,
+	} satisfies Packet;
+}
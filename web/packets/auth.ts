@@ -1,11 +1,17 @@
 import { ID, Packet, Version } from "./packet";
 
+export type Feature = "chunking" | "delta_sync" | "sync_progress" | "resource_warnings" | "catalog" | "remote_config" | "flow_control" | "acks" | "daemon_errors" | "heartbeats" | "terminals" | "terminal_flow_control";
+
+export const SUPPORTED_FEATURES: Feature[] = ["sync_progress", "heartbeats", "acks"];
+
 export type WSAuthData = {
 	user_id: number;
+	features?: Feature[];
 };
 
 export type SWAuthResponseData = {
 	success: boolean;
+	features?: Feature[];
 };
 
 export function WSAuthPacket(data: WSAuthData): Packet {
@@ -0,0 +1,10 @@
+/** Codes of the close frames the server closes connections with */
+export enum CloseReason {
+	AuthFailed = 4001,
+	Revoked = 4002,
+	ServerShutdown = 4003,
+	IdleTimeout = 4004,
+	ProtocolError = 4005,
+	Rejected = 4006,
+	HeartbeatTimeout = 4007,
+}
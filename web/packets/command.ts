@@ -0,0 +1,24 @@
+import { ID, Packet, Version } from "./packet";
+
+/** `stop` keeps the container of the server, `restart` also restarts the servers depending on it */
+export type ServerCommand = "start" | "stop" | "restart" | "kill";
+
+export function WSCommandPacket(daemon: string, server: number, command: ServerCommand): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSCommand,
+		data: {
+			daemon,
+			server,
+			command,
+		},
+	} satisfies Packet;
+}
+
+/** `error` is null if the command succeeded */
+export type SWCommandResultData = {
+	daemon: string;
+	server: number;
+	command: ServerCommand;
+	error: string | null;
+};
@@ -0,0 +1,31 @@
+import { ID, Packet, Version } from "./packet";
+
+/** `limit` defaults to 50 sessions, at most 500 are returned */
+export function WSQueryConnectionsPacket(daemon: string, limit?: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSQueryConnections,
+		data: {
+			daemon,
+			limit,
+		},
+	} satisfies Packet;
+}
+
+/** Times are unix timestamps in seconds */
+export type ConnectionSession = {
+	addr: string;
+	connected_at: number;
+	disconnected_at: number;
+	reason: string;
+	bytes_in: number;
+	bytes_out: number;
+	packets_in: number;
+	packets_out: number;
+};
+
+export type SWQueryConnectionsResponseData = {
+	daemon: string;
+	sessions: ConnectionSession[];
+	error: string | null;
+};
@@ -0,0 +1,10 @@
+export type ErrorCode = "listen_quota_exceeded" | "quota_exceeded" | "rate_limited" | "sync_failed";
+
+export type SWErrorData = {
+	code: ErrorCode;
+	message: string;
+	/** Daemon that reported the error, if it didn't happen on the server */
+	daemon?: string | null;
+	/** Request id of the packet that failed, if it was sent with one */
+	request?: number | null;
+};
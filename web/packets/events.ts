@@ -1,6 +1,14 @@
 export enum EventType {
 	NodeStatus = "NodeStatus",
 	ServerStatus = "ServerStatus",
+	Alert = "Alert",
+	FleetSummary = "FleetSummary",
+	ResourceWarning = "ResourceWarning",
+	BuildOutput = "BuildOutput",
+	UpdatePhase = "UpdatePhase",
+	UpdateRequired = "UpdateRequired",
+	Task = "Task",
+	ServerLog = "ServerLog",
 }
 
 export type NodeStatusEvent = {
@@ -12,6 +20,25 @@ export type NodeStatusEvent = {
 		used_storage: number;
 		total_storage: number;
 	};
+	in_maintenance?: boolean;
+	servers?: ServerCounts | null;
+	max_servers?: number | null;
+	info?: DaemonInfo | null;
+};
+
+export type DaemonInfo = {
+	rootless: boolean;
+	min_host_port: number;
+	server_stats: boolean;
+};
+
+export type ServerCounts = {
+	healthy: number;
+	starting: number;
+	restarting: number;
+	stopping: number;
+	stopped: number;
+	unhealthy: number;
 };
 
 export type ServerStatusEvent = {
@@ -29,16 +56,150 @@ export type ServerStatusEvent = {
 		used: number;
 		total: number;
 	};
+	in_maintenance?: boolean;
+	reason?: StatusReason;
+	started_at?: string;
+	restart_count?: number;
+	cpu_convention?: CpuConvention;
+	cores?: number | null;
+};
+
+export type CpuConvention = "per_core" | "all_cores";
+
+export type StatusReason = {
+	exit_code?: number;
+	oom_killed: boolean;
+	error?: string;
+	health_output?: string;
+};
+
+export type LogLine = {
+	timestamp: string;
+	stream: "stdout" | "stderr";
+	line: string;
+};
+
+export type ProcessTable = {
+	titles: string[];
+	processes: string[][];
+};
+
+export type UsageSample = {
+	timestamp: number;
+	cpu: number;
+	memory: number;
+};
+
+export type MetricSample = {
+	timestamp: number;
+	cpu: number | null;
+	memory_used: number | null;
+	memory_total: number | null;
+	storage_used: number | null;
+	storage_total: number | null;
+};
+
+export type AlertEvent = {
+	rule: number;
+	server: number | null;
+	metric: "cpu" | "memory" | "storage";
+	value: number;
+	threshold: number;
+	firing: boolean;
+};
+
+export type FleetSummaryEvent = {
+	online_daemons: number;
+	offline_daemons: number;
+	healthy_servers: number;
+	unhealthy_servers: number;
+	stopped_servers: number;
+	cpu: number;
+	used_memory: number;
+	total_memory: number;
+};
+
+export type ResourceWarningEvent = {
+	resource: "disk" | "memory" | "inodes";
+	value: number;
+	threshold: number;
+	firing: boolean;
+};
+
+export type BuildOutputEvent = {
+	server: number;
+	image: string;
+	line: string;
+	error: boolean;
+	/** Lines left out before this one, because the client had no credits left */
+	skipped?: number;
+};
+
+export type ServerLogEvent = {
+	server: number;
+	lines: LogLine[];
+	/** Lines left out before these, because the client had no credits left */
+	skipped?: number;
+};
+
+export type UpdatePhase =
+	| { phase: "starting_candidate" }
+	| { phase: "waiting_for_health" }
+	| { phase: "swapping" }
+	| { phase: "done" }
+	| { phase: "rolled_back"; error: string };
+
+export type UpdatePhaseEvent = {
+	server: number;
+	phase: UpdatePhase;
+};
+
+export type UpdateRequiredEvent = {
+	version: string;
+	min_version: string;
+	max_version: string;
+	refused: boolean;
+};
+
+export type TaskKind =
+	| { type: "pull_image"; image: string }
+	| { type: "build_image"; server: number; image: string };
+
+export type TaskState =
+	| { state: "queued" }
+	| { state: "running" }
+	| { state: "done" }
+	| { state: "failed"; error: string }
+	| { state: "cancelled" };
+
+export type TaskEvent = {
+	id: number;
+	kind: TaskKind;
+	state: TaskState;
+	progress?: number;
+	checkpoint?: string;
+	attempts: number;
 };
 
 export type ListenEvent = {
 	event: EventType;
 	daemons: string[];
+	groups?: number[];
+	/** Servers to receive `ServerStatus` events of (all if empty), or to follow the logs of with `ServerLog` */
+	servers?: number[];
 };
 
 interface EventDataPayloads {
 	NodeStatus: NodeStatusEvent;
 	ServerStatus: ServerStatusEvent;
+	Alert: AlertEvent;
+	FleetSummary: FleetSummaryEvent;
+	ResourceWarning: ResourceWarningEvent;
+	BuildOutput: BuildOutputEvent;
+	UpdatePhase: UpdatePhaseEvent;
+	UpdateRequired: UpdateRequiredEvent;
+	Task: TaskEvent;
+	ServerLog: ServerLogEvent;
 }
 
 export type EventDataOf<K extends keyof EventDataPayloads> = {
@@ -52,9 +213,15 @@ export type EventData = {
 export type Event = {
 	event: EventData;
 	daemon: string;
+	/** Sequence number among all events sent by the daemon, `null` for events created by the server */
+	seq?: number | null;
+	/** Unix timestamp (in seconds) the event happened at, as reported by the daemon, `null` for events created by the server */
+	timestamp?: number | null;
 };
 
 export type EventOf<K extends keyof EventDataPayloads> = {
 	event: EventDataOf<K>;
 	daemon: string;
+	seq?: number | null;
+	timestamp?: number | null;
 };
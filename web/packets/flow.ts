@@ -0,0 +1,15 @@
+import { EventType } from "./events";
+import { ID, Packet, Version } from "./packet";
+
+/** Grants the window of a streamed event type of a daemon more credits, if `flow_control` was negotiated */
+export function WSWindowUpdatePacket(daemonUuid: string, event: EventType, credits: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSWindowUpdate,
+		data: {
+			daemon: daemonUuid,
+			event,
+			credits,
+		},
+	} satisfies Packet;
+}
@@ -0,0 +1,16 @@
+import { ID, Packet, Version } from "./packet";
+
+/** Sent periodically by the server, to be answered with a `Pong` packet carrying the same nonce */
+export type PingData = {
+	nonce: number;
+};
+
+export function PongPacket(nonce: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.Pong,
+		data: {
+			nonce,
+		},
+	} satisfies Packet;
+}
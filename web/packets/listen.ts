@@ -10,3 +10,14 @@ export function WSListenPacket(events: ListenEvent[]): Packet {
 		},
 	} satisfies Packet;
 }
+
+/** With `ServerStatus` and `ServerLog`, only the given `servers` are unsubscribed from, if any */
+export function WSUnlistenPacket(events: ListenEvent[]): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSUnlisten,
+		data: {
+			events,
+		},
+	} satisfies Packet;
+}
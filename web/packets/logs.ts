@@ -0,0 +1,13 @@
+import { ID, Packet, Version } from "./packet";
+
+export function WSQueryLogsPacket(daemonUuid: string, server: number, lines: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSQueryLogs,
+		data: {
+			daemon: daemonUuid,
+			server,
+			lines,
+		},
+	} satisfies Packet;
+}
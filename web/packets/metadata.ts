@@ -0,0 +1,21 @@
+import { ID, Packet, Version } from "./packet";
+
+/** `null` keeps the current name or labels, `labels` replaces all current labels */
+export function WSServerMetadataPacket(daemon: string, server: number, name: string | null, labels: Record<string, string> | null): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSServerMetadata,
+		data: {
+			daemon,
+			server,
+			name,
+			labels,
+		},
+	} satisfies Packet;
+}
+
+export type SWServerMetadataResponseData = {
+	daemon: string;
+	server: number;
+	error: string | null;
+};
@@ -0,0 +1,14 @@
+import { ID, Packet, Version } from "./packet";
+
+export function WSQueryMetricsPacket(daemonUuid: string, server: number | null, from: number, to: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSQueryMetrics,
+		data: {
+			daemon: daemonUuid,
+			server,
+			from,
+			to,
+		},
+	} satisfies Packet;
+}
@@ -0,0 +1,45 @@
+import { ID, Packet, Version } from "./packet";
+
+/** `limit` defaults to 50 notifications, at most 500 are returned. `before` is a notification id to page from */
+export function WSQueryNotificationsPacket(unreadOnly: boolean, before?: number, limit?: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSQueryNotifications,
+		data: {
+			unread_only: unreadOnly,
+			before,
+			limit,
+		},
+	} satisfies Packet;
+}
+
+/** Marks the notifications with `ids` as read, or all of them if `all` is set */
+export function WSMarkNotificationsReadPacket(ids: number[], all = false): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSMarkNotificationsRead,
+		data: {
+			ids,
+			all,
+		},
+	} satisfies Packet;
+}
+
+export type NotificationKind = "alert" | "sync_failed" | "daemon_error" | "group_sync" | "automation" | "command_result";
+
+/** `created_at` is a unix timestamp in seconds */
+export type Notification = {
+	id: number;
+	kind: NotificationKind;
+	daemon: string | null;
+	server: number | null;
+	message: string;
+	created_at: number;
+	read: boolean;
+};
+
+export type SWQueryNotificationsResponseData = {
+	notifications: Notification[];
+	unread: number;
+	error: string | null;
+};
@@ -17,10 +17,90 @@ export enum ID {
 	SWEvent = 11,
 	WSSync = 12,
 	SDSync = 13,
+	DSSyncResult = 14,
+	WSQueryLogs = 15,
+	SDQueryLogs = 16,
+	DSQueryLogsResponse = 17,
+	SWQueryLogsResponse = 18,
+	WSQueryTop = 19,
+	SDQueryTop = 20,
+	DSQueryTopResponse = 21,
+	SWQueryTopResponse = 22,
+	WSQueryUsage = 23,
+	SDQueryUsage = 24,
+	DSQueryUsageResponse = 25,
+	SWQueryUsageResponse = 26,
+	WSQueryMetrics = 27,
+	SWQueryMetricsResponse = 28,
+	WSSyncGroup = 29,
+	SWSyncGroupResult = 30,
+	Chunk = 31,
+	DSSyncProgress = 32,
+	SWSyncProgress = 33,
+	WSQueryStats = 34,
+	SDQueryStats = 35,
+	DSQueryStatsResponse = 36,
+	SWQueryStatsResponse = 37,
+	SWError = 38,
+	WSPlaceServer = 39,
+	SWPlaceServerResponse = 40,
+	SDCatalog = 41,
+	DSFetchBuildContext = 42,
+	SDBuildContext = 43,
+	WSImportSpec = 44,
+	SWImportSpecResponse = 45,
+	WSExportSpec = 46,
+	SWExportSpecResponse = 47,
+	WSServerMetadata = 48,
+	SWServerMetadataResponse = 49,
+	SDServerMetadata = 50,
+	WSQueryTeamUsage = 51,
+	SWQueryTeamUsageResponse = 52,
+	WSQueryConnections = 53,
+	SWQueryConnectionsResponse = 54,
+	SDReconnectTo = 55,
+	SDConfig = 56,
+	WSQueryTasks = 57,
+	SDQueryTasks = 58,
+	DSQueryTasksResponse = 59,
+	SWQueryTasksResponse = 60,
+	WSCancelTask = 61,
+	SDCancelTask = 62,
+	WSWindowUpdate = 63,
+	SDWindowUpdate = 64,
+	Ack = 65,
+	Nack = 66,
+	WSQuerySnapshot = 67,
+	DSError = 68,
+	Ping = 69,
+	Pong = 70,
+	WSQueryNotifications = 71,
+	SWQueryNotificationsResponse = 72,
+	WSMarkNotificationsRead = 73,
+	WSTerminalOpen = 74,
+	SDTerminalOpen = 75,
+	WSTerminalInput = 76,
+	SDTerminalInput = 77,
+	DSTerminalOutput = 78,
+	SWTerminalOutput = 79,
+	WSTerminalClose = 80,
+	SDTerminalClose = 81,
+	DSTerminalClose = 82,
+	SWTerminalClose = 83,
+	WSCommand = 84,
+	SDCommand = 85,
+	DSCommandResult = 86,
+	SWCommandResult = 87,
+	WSUnlisten = 88,
 }
 
+/** WebSocket subprotocols supported by the web client, in order of preference */
+export const SUBPROTOCOLS = ["aesterisk.v0+jwe-json"];
+
 export type Packet = {
 	version: Version;
 	id: ID;
 	data: unknown;
+	/** Set to be answered with an `Ack` or `Nack` packet carrying the same request id */
+	request_id?: number;
 };
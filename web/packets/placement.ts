@@ -0,0 +1,25 @@
+import { ID, Packet, Version } from "./packet";
+
+export function WSPlaceServerPacket(server: number | null): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSPlaceServer,
+		data: {
+			server,
+		},
+	} satisfies Packet;
+}
+
+export type PlacementCandidate = {
+	daemon: string;
+	load: number;
+	servers: number;
+	max_servers: number | null;
+};
+
+export type SWPlaceServerResponseData = {
+	server: number | null;
+	candidates: PlacementCandidate[];
+	assigned: string | null;
+	error: string | null;
+};
@@ -0,0 +1,12 @@
+import { ID, Packet, Version } from "./packet";
+
+/** Asks for the latest `NodeStatus` and `ServerStatus` events of a daemon, e.g. after missing some */
+export function WSQuerySnapshotPacket(daemonUuid: string): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSQuerySnapshot,
+		data: {
+			daemon: daemonUuid,
+		},
+	} satisfies Packet;
+}
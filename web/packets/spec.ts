@@ -0,0 +1,34 @@
+import { ID, Packet, Version } from "./packet";
+
+export function WSImportSpecPacket(spec: string, prune: boolean, dryRun: boolean): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSImportSpec,
+		data: {
+			spec,
+			prune,
+			dry_run: dryRun,
+		},
+	} satisfies Packet;
+}
+
+export type SWImportSpecResponseData = {
+	changes: string[];
+	applied: boolean;
+	error: string | null;
+};
+
+export function WSExportSpecPacket(daemons: string[]): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSExportSpec,
+		data: {
+			daemons,
+		},
+	} satisfies Packet;
+}
+
+export type SWExportSpecResponseData = {
+	spec: string | null;
+	error: string | null;
+};
@@ -0,0 +1,12 @@
+import { ID, Packet, Version } from "./packet";
+
+export function WSQueryStatsPacket(daemonUuid: string, server: number | null): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSQueryStats,
+		data: {
+			daemon: daemonUuid,
+			server,
+		},
+	} satisfies Packet;
+}
@@ -9,3 +9,30 @@ export function WSSyncPacket(daemonUuid: string): Packet {
 		},
 	} satisfies Packet;
 }
+
+export function WSSyncGroupPacket(group: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSSyncGroup,
+		data: {
+			group,
+		},
+	} satisfies Packet;
+}
+
+export type SyncStep =
+	| { step: "removing_server"; server: number }
+	| { step: "removing_network"; network: number }
+	| { step: "creating_network"; network: number }
+	| { step: "pulling_image"; server: number; image: string }
+	| { step: "building_image"; server: number; image: string }
+	| { step: "creating_server"; server: number }
+	| { step: "updating_server"; server: number }
+	| { step: "done" }
+	| { step: "failed"; error: string }
+	| { step: "throttled"; retry_after: number };
+
+export type SWSyncProgressPacket = {
+	daemon: string;
+	step: SyncStep;
+};
@@ -0,0 +1,30 @@
+import { TaskEvent } from "./events";
+import { ID, Packet, Version } from "./packet";
+
+export function WSQueryTasksPacket(daemonUuid: string): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSQueryTasks,
+		data: {
+			daemon: daemonUuid,
+		},
+	} satisfies Packet;
+}
+
+/** The result is reported as a `Task` event */
+export function WSCancelTaskPacket(daemonUuid: string, task: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSCancelTask,
+		data: {
+			daemon: daemonUuid,
+			task,
+		},
+	} satisfies Packet;
+}
+
+export type SWQueryTasksResponseData = {
+	daemon: string;
+	tasks: TaskEvent[];
+	error: string | null;
+};
@@ -0,0 +1,63 @@
+import { ID, Packet, Version } from "./packet";
+
+export type TerminalSize = {
+	cols: number;
+	rows: number;
+};
+
+/** `session` should be a random UUID, e.g. from `crypto.randomUUID()`. `command` defaults to `/bin/sh` */
+export function WSTerminalOpenPacket(session: string, daemon: string, server: number, size: TerminalSize, command?: string[]): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSTerminalOpen,
+		data: {
+			session,
+			daemon,
+			server,
+			size,
+			command,
+		},
+	} satisfies Packet;
+}
+
+/**
+ * `data` is base64 encoded, `credits` are only used if `terminal_flow_control` was negotiated. `seq`
+ * numbers the input of a session from 0, so the server can put it back into order
+ */
+export function WSTerminalInputPacket(session: string, data: string, resize?: TerminalSize, credits?: number, seq?: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSTerminalInput,
+		data: {
+			session,
+			data,
+			resize,
+			credits,
+			seq,
+		},
+	} satisfies Packet;
+}
+
+export function WSTerminalClosePacket(session: string): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSTerminalClose,
+		data: {
+			session,
+		},
+	} satisfies Packet;
+}
+
+/** `data` is base64 encoded. Output and close packets of a session are numbered by `seq` from 0 */
+export type SWTerminalOutputData = {
+	session: string;
+	data: string;
+	seq?: number;
+};
+
+export type SWTerminalCloseData = {
+	session: string;
+	exit_code: number | null;
+	error: string | null;
+	seq?: number;
+};
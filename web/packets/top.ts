@@ -0,0 +1,12 @@
+import { ID, Packet, Version } from "./packet";
+
+export function WSQueryTopPacket(daemonUuid: string, server: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSQueryTop,
+		data: {
+			daemon: daemonUuid,
+			server,
+		},
+	} satisfies Packet;
+}
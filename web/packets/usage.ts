@@ -0,0 +1,14 @@
+import { ID, Packet, Version } from "./packet";
+
+export function WSQueryUsagePacket(daemonUuid: string, server: number, from: number, to: number): Packet {
+	return {
+		version: Version.V0_1_0,
+		id: ID.WSQueryUsage,
+		data: {
+			daemon: daemonUuid,
+			server,
+			from,
+			to,
+		},
+	} satisfies Packet;
+}